@@ -0,0 +1,307 @@
+/// bench: time the search kernels on synthetic sketches.
+use anyhow::{bail, Context, Result};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::sync::mpsc::SyncSender;
+use std::time::Instant;
+
+use sourmash::encodings::HashFunctions;
+use sourmash::sketch::minhash::{max_hash_for_scaled, KmerMinHash};
+
+use crate::fastgather::prefetch_sketches;
+use crate::multisearch::multisearch_obj;
+use crate::pairwise::pairwise_obj;
+use crate::utils::{
+    consume_query_by_gather, finish_csv, open_stdout_or_file, GatherOptions, RunContext,
+    SearchControl, SmallSignature,
+};
+
+const KSIZE: u32 = 31;
+const KERNELS: [&str; 3] = ["multisearch", "pairwise", "gather"];
+
+/// The shape of a synthetic collection. Sketches are split into
+/// `n_groups` groups; within a group, each sketch takes `overlap` of its
+/// hashes from a pool shared by the group, and the rest are its own.
+pub struct SyntheticParams {
+    pub n_sketches: usize,
+    pub n_hashes: usize,
+    pub scaled: u32,
+    pub overlap: f64,
+    pub n_groups: usize,
+    pub seed: u64,
+}
+
+impl SyntheticParams {
+    fn check(&self) -> Result<()> {
+        if self.n_sketches < 2 {
+            bail!("bench needs at least 2 sketches");
+        }
+        if self.n_hashes == 0 {
+            bail!("bench needs at least 1 hash per sketch");
+        }
+        if self.scaled == 0 {
+            bail!("scaled must be at least 1");
+        }
+        if !(0.0..=1.0).contains(&self.overlap) {
+            bail!("overlap must be between 0 and 1, not {}", self.overlap);
+        }
+        if self.n_groups == 0 || self.n_groups > self.n_sketches {
+            bail!(
+                "the number of groups must be between 1 and the number of sketches ({})",
+                self.n_sketches
+            );
+        }
+        Ok(())
+    }
+}
+
+/// splitmix64: small, fast, and reproducible from a seed, which is all
+/// that synthetic hashes need.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+/// Build the synthetic sketches described by `params`.
+pub fn synthetic_sketches(params: &SyntheticParams) -> Vec<SmallSignature> {
+    let max_hash = max_hash_for_scaled(params.scaled);
+    let n_shared = (params.overlap * params.n_hashes as f64).round() as usize;
+
+    let pools: Vec<Vec<u64>> = (0..params.n_groups)
+        .map(|group| {
+            let mut rng = SplitMix64(params.seed ^ (group as u64).wrapping_mul(0x5851_F42D));
+            (0..params.n_hashes).map(|_| rng.below(max_hash)).collect()
+        })
+        .collect();
+
+    (0..params.n_sketches)
+        .into_par_iter()
+        .map(|i| {
+            let mut rng = SplitMix64(params.seed.wrapping_add(1 + i as u64));
+
+            // a random sample of the group's pool, without replacement.
+            let mut pool = pools[i % params.n_groups].clone();
+            for j in 0..n_shared {
+                let k = j + rng.below((pool.len() - j) as u64) as usize;
+                pool.swap(j, k);
+            }
+            let mut hashes = pool;
+            hashes.truncate(n_shared);
+            hashes.extend((n_shared..params.n_hashes).map(|_| rng.below(max_hash)));
+            hashes.sort_unstable();
+
+            let mut mh = KmerMinHash::new(
+                params.scaled,
+                KSIZE,
+                HashFunctions::Murmur64Dna,
+                42,
+                false,
+                0,
+            );
+            mh.add_many(&hashes).expect("cannot add hashes");
+
+            SmallSignature {
+                location: format!("synthetic-{}", i),
+                name: format!("synthetic-{}", i).into(),
+                md5sum: mh.md5sum().into(),
+                minhash: mh,
+            }
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+struct BenchRow {
+    kernel: String,
+    threads: usize,
+    n_sketches: usize,
+    n_hashes: usize,
+    scaled: u32,
+    overlap: f64,
+    n_groups: usize,
+    n_comparisons: usize,
+    n_results: usize,
+    seconds: f64,
+}
+
+/// Run a kernel that sends its results over a channel, counting (and
+/// dropping) them. Returns the kernel's own return value and the count.
+fn count_results<T: Send + 'static, R>(
+    kernel: impl FnOnce(SyncSender<T>) -> Result<R>,
+) -> Result<(R, usize)> {
+    let (send, recv) = std::sync::mpsc::sync_channel::<T>(rayon::current_num_threads());
+    let thrd = std::thread::spawn(move || recv.iter().count());
+
+    let ret = kernel(send);
+    let n_results = thrd.join().expect("Unable to join internal thread");
+    Ok((ret?, n_results))
+}
+
+/// Run one kernel over `sketches`. Returns (comparisons, results).
+fn run_kernel(
+    kernel: &str,
+    sketches: &Vec<SmallSignature>,
+    threshold: f64,
+) -> Result<(usize, usize)> {
+    let scaled = sketches[0].minhash.scaled();
+    match kernel {
+        "multisearch" => count_results(|send| {
+            multisearch_obj(
+                sketches,
+                sketches,
+                threshold,
+                false,
+                false,
+                false,
+                send,
+                scaled,
+                KSIZE as f64,
+                &SearchControl::default(),
+                None,
+                false,
+                false,
+                None,
+                false,
+                &RunContext::default(),
+            )
+        }),
+        "pairwise" => count_results(|send| {
+            pairwise_obj(
+                sketches,
+                false,
+                false,
+                false,
+                send,
+                threshold,
+                KSIZE as f64,
+                None,
+                false,
+                None,
+                false,
+            )
+        }),
+        "gather" => {
+            // a "metagenome" made up of every tenth sketch.
+            let mut query_mh = sketches[0].minhash.clone();
+            for sketch in sketches.iter().step_by(10).skip(1) {
+                query_mh.merge(&sketch.minhash)?;
+            }
+            let gather_options = GatherOptions::new(None, None, false)?;
+
+            count_results(|send| {
+                let matchlist = prefetch_sketches(&query_mh, sketches, 1);
+                consume_query_by_gather(
+                    "synthetic-metagenome".to_string(),
+                    "synthetic-metagenome".to_string(),
+                    query_mh,
+                    scaled,
+                    matchlist,
+                    1,
+                    &gather_options,
+                    Some(send),
+                )?;
+                Ok(sketches.len())
+            })
+        }
+        _ => unreachable!("unknown kernel {}", kernel),
+    }
+}
+
+/// Time each of `kernels` on a synthetic collection, once for each of
+/// `threads` (or once with the global thread pool, if empty), writing
+/// one CSV row per run.
+pub fn bench(
+    params: SyntheticParams,
+    kernels: Vec<String>,
+    threads: Vec<usize>,
+    threshold: f64,
+    output: Option<String>,
+) -> Result<()> {
+    params.check()?;
+    if let Some(k) = kernels.iter().find(|k| !KERNELS.contains(&k.as_str())) {
+        bail!(
+            "unknown kernel '{}'; valid kernels are: {}",
+            k,
+            KERNELS.join(", ")
+        );
+    }
+    if threads.contains(&0) {
+        bail!("thread counts must be at least 1");
+    }
+
+    eprintln!(
+        "Building {} synthetic sketches with {} hashes each (scaled={}, overlap={}, groups={})",
+        params.n_sketches, params.n_hashes, params.scaled, params.overlap, params.n_groups
+    );
+    let start = Instant::now();
+    let sketches = synthetic_sketches(&params);
+    eprintln!("...built in {:.2}s", start.elapsed().as_secs_f64());
+
+    let mut writer = csv::Writer::from_writer(open_stdout_or_file(output));
+
+    let pools: Vec<Option<rayon::ThreadPool>> = if threads.is_empty() {
+        vec![None]
+    } else {
+        threads
+            .iter()
+            .map(|&n| {
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(n)
+                    .build()
+                    .map(Some)
+                    .with_context(|| format!("cannot start a pool of {} threads", n))
+            })
+            .collect::<Result<_>>()?
+    };
+
+    for pool in pools.iter() {
+        for kernel in kernels.iter() {
+            let run = || -> Result<(usize, usize, usize, f64)> {
+                let start = Instant::now();
+                let (n_comparisons, n_results) = run_kernel(kernel, &sketches, threshold)?;
+                let seconds = start.elapsed().as_secs_f64();
+                Ok((
+                    rayon::current_num_threads(),
+                    n_comparisons,
+                    n_results,
+                    seconds,
+                ))
+            };
+            let (n_threads, n_comparisons, n_results, seconds) = match pool {
+                Some(pool) => pool.install(run)?,
+                None => run()?,
+            };
+            eprintln!(
+                "{}: {} threads, {} comparisons, {} results in {:.3}s",
+                kernel, n_threads, n_comparisons, n_results, seconds
+            );
+
+            writer.serialize(BenchRow {
+                kernel: kernel.clone(),
+                threads: n_threads,
+                n_sketches: params.n_sketches,
+                n_hashes: params.n_hashes,
+                scaled: params.scaled,
+                overlap: params.overlap,
+                n_groups: params.n_groups,
+                n_comparisons,
+                n_results,
+                seconds,
+            })?;
+            writer.flush()?;
+        }
+    }
+
+    finish_csv(writer)?.commit()
+}
@@ -1,27 +1,200 @@
+use crate::errors::BranchwaterError;
+use crate::utils::buildutils::BuildCollection;
 use crate::utils::is_revindex_database;
 use anyhow::Result;
+use rayon::prelude::*;
+use std::collections::HashMap;
 
 use sourmash::index::revindex::{RevIndex, RevIndexOps};
+use sourmash::signature::{Signature, SigsTrait};
+use sourmash::sketch::minhash::KmerMinHash;
+use sourmash::sketch::Sketch;
 
-pub fn check(index: camino::Utf8PathBuf, quick: bool, rw: bool) -> Result<()> {
+use crate::utils::{finish_csv, open_stdout_or_file};
+
+/// Options for reporting how many datasets each hash is found in.
+pub struct HashStatsOptions {
+    /// Write the number of hashes found in each number of datasets here.
+    pub output: Option<String>,
+    /// Flag hashes found in at least this fraction of datasets.
+    pub ubiquitous_fraction: f64,
+    /// Write the flagged hashes to this signature file, as a stoplist.
+    pub stoplist: Option<String>,
+}
+
+impl HashStatsOptions {
+    pub fn new(
+        output: Option<String>,
+        ubiquitous_fraction: f64,
+        stoplist: Option<String>,
+    ) -> Result<Self> {
+        if !(ubiquitous_fraction > 0.0 && ubiquitous_fraction <= 1.0) {
+            bail!("--ubiquitous-fraction must be above 0 and at most 1");
+        }
+        Ok(HashStatsOptions {
+            output,
+            ubiquitous_fraction,
+            stoplist,
+        })
+    }
+}
+
+pub fn check(
+    index: camino::Utf8PathBuf,
+    quick: bool,
+    rw: bool,
+    hash_stats: Option<HashStatsOptions>,
+) -> Result<()> {
     if !is_revindex_database(&index) {
-        bail!("'{}' is not a valid RevIndex database", index);
+        bail!(BranchwaterError::InvalidRocksDB(format!(
+            "'{}' is not a valid RevIndex database",
+            index
+        )));
     }
 
     println!("Opening DB (rw mode? {})", rw);
-    let db = match RevIndex::open(index, !rw, None) {
+    let db = match RevIndex::open(&index, !rw, None) {
         Ok(db) => db,
         Err(e) => {
-            return Err(anyhow::anyhow!(
+            bail!(BranchwaterError::InvalidRocksDB(format!(
                 "cannot open RocksDB database. Error is: {}",
                 e
-            ))
+            )))
         }
     };
 
     println!("Starting check");
     db.check(quick);
 
+    if let Some(options) = hash_stats {
+        report_hash_stats(&db, index.as_str(), &options)?;
+    }
+
     println!("Finished check");
     Ok(())
 }
+
+/// Count the datasets each hash in the index is found in, from the
+/// sketches in its collection.
+fn count_hash_datasets(db: &RevIndex) -> Result<HashMap<u64, u32>> {
+    let collection = db.collection();
+    (0..collection.len() as u32)
+        .into_par_iter()
+        .map(|dataset_id| -> Result<Vec<u64>> {
+            let mh: KmerMinHash = collection.sig_for_dataset(dataset_id)?.try_into()?;
+            Ok(mh.mins())
+        })
+        .try_fold(HashMap::new, |mut counts: HashMap<u64, u32>, hashes| {
+            for hash in hashes? {
+                *counts.entry(hash).or_default() += 1;
+            }
+            Ok(counts)
+        })
+        .try_reduce(HashMap::new, |mut a, mut b| {
+            if a.len() < b.len() {
+                std::mem::swap(&mut a, &mut b);
+            }
+            for (hash, n) in b {
+                *a.entry(hash).or_default() += n;
+            }
+            Ok(a)
+        })
+}
+
+/// Report the distribution of hash frequencies across datasets, and flag
+/// the hashes found in nearly all of them: every query containing one of
+/// these adds an entry to its counter for nearly every dataset.
+fn report_hash_stats(db: &RevIndex, index: &str, options: &HashStatsOptions) -> Result<()> {
+    let n_datasets = db.collection().len();
+    if n_datasets == 0 {
+        bail!("no datasets in '{}'", index);
+    }
+
+    println!("Counting hash frequencies across {} datasets", n_datasets);
+    let counts = count_hash_datasets(db)?;
+
+    // frequencies[n] is the number of hashes found in n datasets.
+    let mut frequencies = vec![0u64; n_datasets + 1];
+    for n in counts.values() {
+        frequencies[*n as usize] += 1;
+    }
+    let n_entries: u64 = frequencies
+        .iter()
+        .enumerate()
+        .map(|(n, n_hashes)| n as u64 * n_hashes)
+        .sum();
+    let percent = |part: u64, total: u64| 100.0 * part as f64 / total.max(1) as f64;
+
+    println!(
+        "{} distinct hashes, with {} (hash, dataset) entries",
+        counts.len(),
+        n_entries
+    );
+    println!(
+        "{} hashes ({:.1}%) are found in only one dataset",
+        frequencies[1],
+        percent(frequencies[1], counts.len() as u64)
+    );
+
+    let cutoff = ((options.ubiquitous_fraction * n_datasets as f64).ceil() as usize).max(2);
+    let mut ubiquitous: Vec<(u64, u32)> = counts
+        .iter()
+        .filter(|(_, n)| **n as usize >= cutoff)
+        .map(|(hash, n)| (*hash, *n))
+        .collect();
+    ubiquitous.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    let ubiquitous_entries: u64 = ubiquitous.iter().map(|(_, n)| *n as u64).sum();
+    println!(
+        "{} hashes are found in at least {} datasets, making up {:.1}% of entries",
+        ubiquitous.len(),
+        cutoff,
+        percent(ubiquitous_entries, n_entries)
+    );
+    for (hash, n) in ubiquitous.iter().take(10) {
+        println!("  hash {} is found in {} datasets", hash, n);
+    }
+    if ubiquitous.len() > 10 {
+        println!("  ... and {} more", ubiquitous.len() - 10);
+    }
+
+    if let Some(output) = &options.output {
+        let mut writer = csv::Writer::from_writer(open_stdout_or_file(Some(output.clone())));
+        writer.write_record(["n_datasets", "n_hashes"])?;
+        for (n, n_hashes) in frequencies.iter().enumerate().skip(1) {
+            if *n_hashes > 0 {
+                writer.write_record([n.to_string(), n_hashes.to_string()])?;
+            }
+        }
+        finish_csv(writer)?.commit()?;
+        println!("Wrote hash frequencies to '{}'", output);
+    }
+
+    if let Some(output) = &options.stoplist {
+        let template: KmerMinHash = db.collection().sig_for_dataset(0)?.try_into()?;
+        let mut mh = KmerMinHash::new(
+            template.scaled(),
+            template.ksize() as u32,
+            template.hash_function(),
+            template.seed(),
+            false,
+            template.num(),
+        );
+        let hashes: Vec<u64> = ubiquitous.iter().map(|(hash, _)| *hash).collect();
+        mh.add_many(&hashes)?;
+
+        let mut sig = Signature::default();
+        sig.push(Sketch::MinHash(mh));
+        sig.set_name(&format!(
+            "hashes in at least {} of {} datasets in {}",
+            cutoff, n_datasets, index
+        ));
+        sig.set_filename(index);
+
+        let mut collection = BuildCollection::new();
+        collection.add_sig(sig)?;
+        collection.write_sigs(output)?;
+        println!("Wrote {} hashes to stoplist '{}'", hashes.len(), output);
+    }
+
+    Ok(())
+}
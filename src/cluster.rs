@@ -2,10 +2,9 @@ use anyhow::{Context, Result};
 use rustworkx_core::connectivity::connected_components;
 use rustworkx_core::petgraph::graph::{NodeIndex, UnGraph};
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::Write;
 
-use crate::utils::MultiSearchResult;
+use crate::utils::atomicfile::AtomicFile;
+use crate::utils::{finish_csv, MultiSearchResult};
 
 // potential todo:
 // - eval DiGraph for directed similarity info (e.g. input containment_A, containment_B independently)
@@ -17,65 +16,53 @@ fn build_graph(
     similarity_threshold: f64,
 ) -> Result<(UnGraph<String, f64>, HashMap<String, NodeIndex>)> {
     let mut reader = csv::Reader::from_path(file_path).context("Failed to open CSV file")?;
+    let records = reader
+        .deserialize::<MultiSearchResult>()
+        .map(|result| result.map_err(|e| anyhow::anyhow!("Error deserializing record: {}", e)));
+    let (graph, name_to_node) =
+        build_graph_from(records, similarity_measure, similarity_threshold)?;
+
+    if graph.node_count() == 0 {
+        bail!("No nodes added to graph.")
+    }
+
+    if graph.edge_count() == 0 {
+        bail!("Graph has nodes but no edges were added.");
+    }
+
+    Ok((graph, name_to_node))
+}
+
+fn build_graph_from(
+    records: impl IntoIterator<Item = Result<MultiSearchResult>>,
+    similarity_measure: &str,
+    similarity_threshold: f64,
+) -> Result<(UnGraph<String, f64>, HashMap<String, NodeIndex>)> {
     let mut name_to_node: HashMap<String, NodeIndex> = HashMap::new();
     let mut graph = UnGraph::<String, f64>::new_undirected();
 
-    for result in reader.deserialize::<MultiSearchResult>() {
-        let record = result.map_err(|e| anyhow::anyhow!("Error deserializing record: {}", e))?;
+    for record in records {
+        let record = record?;
 
         // ignore self-matches reported via multisearch
         if record.query_name == record.match_name {
             continue;
         }
 
-        let similarity = match similarity_measure {
-            "containment" => record.containment,
-            "max_containment" => record.max_containment,
-            "jaccard" => record.jaccard,
-            "average_containment_ani" => match record.average_containment_ani {
-                Some(value) => value,
-                None => {
-                    return Err(anyhow::anyhow!(
-                        "average_containment_ani is None. Did you estimate ANI?"
-                    ))
-                }
-            },
-            "max_containment_ani" => match record.max_containment_ani {
-                Some(value) => value,
-                None => {
-                    return Err(anyhow::anyhow!(
-                        "max_containment_ani is None. Did you estimate ANI?"
-                    ))
-                }
-            },
-            _ => {
-                return Err(anyhow::anyhow!(
-                    "Invalid similarity measure: {}",
-                    similarity_measure
-                ))
-            } // should not happen
-        };
+        let similarity = record.similarity(similarity_measure)?;
 
         let node1 = *name_to_node
-            .entry(record.query_name.clone())
-            .or_insert_with(|| graph.add_node(record.query_name.clone()));
+            .entry(record.query_name.to_string())
+            .or_insert_with(|| graph.add_node(record.query_name.to_string()));
         let node2 = *name_to_node
-            .entry(record.match_name.clone())
-            .or_insert_with(|| graph.add_node(record.match_name.clone()));
+            .entry(record.match_name.to_string())
+            .or_insert_with(|| graph.add_node(record.match_name.to_string()));
 
         if similarity >= similarity_threshold {
             graph.add_edge(node1, node2, similarity);
         }
     }
 
-    if graph.node_count() == 0 {
-        bail!("No nodes added to graph.")
-    }
-
-    if graph.edge_count() == 0 {
-        bail!("Graph has nodes but no edges were added.");
-    }
-
     Ok((graph, name_to_node))
 }
 
@@ -100,10 +87,12 @@ pub fn cluster(
     let mut size_counts: HashMap<usize, usize> = HashMap::new();
 
     // Open file for components + names
-    let mut file = File::create(output_clusters).context("Failed to create output file")?;
+    let mut writer = csv::Writer::from_writer(AtomicFile::create(&output_clusters)?);
 
     // write header
-    writeln!(file, "cluster,nodes").context("Failed to write header to output file")?;
+    writer
+        .write_record(["cluster", "nodes"])
+        .context("Failed to write header to output file")?;
     // for each component, find corresponding node names + write to file
     for (i, component) in components.iter().enumerate() {
         let component_name = format!("Component_{}", i + 1);
@@ -123,27 +112,70 @@ pub fn cluster(
 
         let node_names_str = node_names.join(";");
 
-        writeln!(file, "{},{}", component_name, node_names_str).context(format!(
-            "Failed to write component {} to output file",
-            i + 1
-        ))?;
+        writer
+            .write_record([component_name.as_str(), node_names_str.as_str()])
+            .context(format!(
+                "Failed to write component {} to output file",
+                i + 1
+            ))?;
 
         // add cluster to aggregated counts
         let count = size_counts.entry(component.len()).or_insert(0);
         *count += 1;
     }
 
+    finish_csv(writer)
+        .and_then(AtomicFile::commit)
+        .context("Failed to flush output file")?;
+
     // write the sizes and counts
     if let Some(sizes_file) = cluster_sizes {
-        let mut cluster_size_file =
-            File::create(sizes_file).context("Failed to create cluster size file")?;
-        writeln!(cluster_size_file, "cluster_size,count")
+        let mut cluster_size_file = csv::Writer::from_writer(AtomicFile::create(&sizes_file)?);
+        cluster_size_file
+            .write_record(["cluster_size", "count"])
             .context("Failed to write header to cluster size file")?;
         for (size, count) in size_counts {
-            writeln!(cluster_size_file, "{},{}", size, count)
+            cluster_size_file
+                .write_record([size.to_string(), count.to_string()])
                 .context("Failed to write size count to cluster size file")?;
         }
+        finish_csv(cluster_size_file)
+            .and_then(AtomicFile::commit)
+            .context("Failed to flush cluster size file")?;
     }
 
     Ok(())
 }
+
+/// Cluster in-memory comparison results, as `cluster` does for a CSV;
+/// returns the full names of the sketches in each cluster. Sketches in
+/// `names` that are not in any result are returned as clusters of one.
+pub(crate) fn cluster_results(
+    results: Vec<MultiSearchResult>,
+    names: &[&str],
+    similarity_column: &str,
+    similarity_threshold: f64,
+) -> Result<Vec<Vec<String>>> {
+    let (mut graph, name_to_node) = build_graph_from(
+        results.into_iter().map(Ok),
+        similarity_column,
+        similarity_threshold,
+    )?;
+    for name in names {
+        if !name_to_node.contains_key(*name) {
+            graph.add_node(name.to_string());
+        }
+    }
+
+    Ok(connected_components(&graph)
+        .into_iter()
+        .map(|component| {
+            let mut names: Vec<String> = component
+                .into_iter()
+                .map(|node| graph[node].clone())
+                .collect();
+            names.sort();
+            names
+        })
+        .collect())
+}
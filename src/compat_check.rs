@@ -0,0 +1,179 @@
+/// compat_check: check that query and against inputs have compatible
+/// sketches, and estimate the size of a search, without running it.
+use anyhow::Result;
+use camino::Utf8PathBuf as PathBuf;
+use std::collections::BTreeMap;
+
+use sourmash::selection::{Select, Selection};
+
+use crate::utils::querysketch::is_sequence_input;
+use crate::utils::{load_collection, MultiCollection, ReportType, RunContext};
+
+/// Manifest-level summary of one side of a search.
+struct SideSummary {
+    n_sketches: usize,
+    n_with_abundance: usize,
+    /// estimated number of hashes once downsampled to the common scaled.
+    n_hashes: u64,
+}
+
+impl SideSummary {
+    fn new(collection: &MultiCollection, common_scaled: u32) -> Self {
+        let mut n_with_abundance = 0;
+        let mut n_hashes = 0;
+        for (_, _, record) in collection.item_iter() {
+            if record.with_abundance() {
+                n_with_abundance += 1;
+            }
+            n_hashes += *record.n_hashes() as u64 * *record.scaled() as u64 / common_scaled as u64;
+        }
+        SideSummary {
+            n_sketches: collection.len(),
+            n_with_abundance,
+            n_hashes,
+        }
+    }
+
+    /// Rough in-memory size: one u64 per hash, plus one per abundance.
+    fn est_bytes(&self) -> u64 {
+        let per_hash = if self.n_with_abundance > 0 { 16 } else { 8 };
+        self.n_hashes * per_hash
+    }
+
+    fn report(&self, report_type: ReportType) {
+        println!(
+            "{}: {} compatible sketches ({} with abundance), ~{} hashes, ~{} in memory",
+            report_type,
+            self.n_sketches,
+            self.n_with_abundance,
+            self.n_hashes,
+            format_bytes(self.est_bytes())
+        );
+    }
+}
+
+fn format_bytes(n: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = n as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, units[unit])
+}
+
+/// Print the sketch parameters present in a collection, one line each.
+fn report_available(collection: &MultiCollection, report_type: ReportType) {
+    let mut params: BTreeMap<(String, u32, u32), usize> = BTreeMap::new();
+    for (_, _, record) in collection.item_iter() {
+        let key = (
+            record.moltype().to_string(),
+            record.ksize(),
+            *record.scaled(),
+        );
+        *params.entry(key).or_default() += 1;
+    }
+
+    eprintln!("available {} sketch parameters:", report_type);
+    for ((moltype, ksize, scaled), n) in params {
+        eprintln!(
+            "  {},k={},scaled={}: {} sketches",
+            moltype, ksize, scaled, n
+        );
+    }
+}
+
+/// Load one side without any selection, so that the available sketch
+/// parameters can be reported if none match; then select.
+fn load_side(
+    path: &String,
+    selection: &Selection,
+    report_type: ReportType,
+    allow_failed_sigpaths: bool,
+) -> Result<MultiCollection> {
+    // sequence files are sketched with the requested parameters.
+    if is_sequence_input(&PathBuf::from(path)) {
+        return load_collection(
+            path,
+            selection,
+            report_type,
+            allow_failed_sigpaths,
+            &RunContext::default(),
+        );
+    }
+
+    let all = load_collection(
+        path,
+        &Selection::default(),
+        report_type,
+        allow_failed_sigpaths,
+        &RunContext::default(),
+    )?;
+    let selected = all.clone().select(selection)?;
+    if selected.is_empty() {
+        report_available(&all, report_type);
+        bail!(
+            "no {} sketches are compatible with k={}, moltype={}{}",
+            report_type,
+            selection.ksize().unwrap_or_default(),
+            selection
+                .moltype()
+                .map(|m| m.to_string())
+                .unwrap_or_default(),
+            selection
+                .scaled()
+                .map(|s| format!(", scaled={}", s))
+                .unwrap_or_default()
+        );
+    }
+    Ok(selected)
+}
+
+pub fn compat_check(
+    query_path: String,
+    against_path: String,
+    selection: Selection,
+    allow_failed_sigpaths: bool,
+) -> Result<()> {
+    let queries = load_side(
+        &query_path,
+        &selection,
+        ReportType::Query,
+        allow_failed_sigpaths,
+    )?;
+    let againsts = load_side(
+        &against_path,
+        &selection,
+        ReportType::Against,
+        allow_failed_sigpaths,
+    )?;
+
+    // searches compare sketches at the largest scaled on either side.
+    let common_scaled = match selection.scaled() {
+        Some(s) => s,
+        None => *[queries.max_scaled(), againsts.max_scaled()]
+            .into_iter()
+            .flatten()
+            .max()
+            .expect("no records!?"),
+    };
+
+    let query_summary = SideSummary::new(&queries, common_scaled);
+    let against_summary = SideSummary::new(&againsts, common_scaled);
+
+    println!(
+        "compatible! comparisons would be done at scaled={}",
+        common_scaled
+    );
+    query_summary.report(ReportType::Query);
+    against_summary.report(ReportType::Against);
+    println!(
+        "{} comparisons ({} x {})",
+        query_summary.n_sketches as u64 * against_summary.n_sketches as u64,
+        query_summary.n_sketches,
+        against_summary.n_sketches
+    );
+
+    Ok(())
+}
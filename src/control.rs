@@ -0,0 +1,52 @@
+//! Python-facing progress callbacks and cancellation tokens.
+use pyo3::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::utils::SearchControl;
+
+/// A flag Python can set to stop a running search.
+#[pyclass]
+#[derive(Default)]
+pub struct CancelToken {
+    flag: Arc<AtomicBool>,
+}
+
+#[pymethods]
+impl CancelToken {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn cancel(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+    }
+
+    #[getter]
+    fn cancelled(&self) -> bool {
+        self.flag.load(Ordering::Relaxed)
+    }
+}
+
+/// Build a SearchControl from the optional Python arguments. The progress
+/// callback is called as `progress(n_done, n_total)` with the GIL held, so
+/// the search itself must be run with the GIL released.
+pub fn search_control(
+    progress: Option<PyObject>,
+    cancel: Option<PyRef<'_, CancelToken>>,
+    progress_interval: usize,
+) -> SearchControl {
+    let cancel = cancel.map(|c| Arc::clone(&c.flag));
+    let progress = progress.map(|cb| {
+        Box::new(move |n_done: usize, n_total: usize| {
+            Python::with_gil(|py| {
+                if let Err(e) = cb.call1(py, (n_done, n_total)) {
+                    eprintln!("WARNING: progress callback failed: {e}");
+                }
+            })
+        }) as crate::utils::ProgressFn
+    });
+
+    SearchControl::new(cancel, progress, progress_interval)
+}
@@ -0,0 +1,287 @@
+/// convert: copy a collection of sketches into another storage format.
+use anyhow::{anyhow, Result};
+use camino::Utf8Path as Path;
+use camino::Utf8PathBuf as PathBuf;
+use rayon::prelude::*;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::create_dir_all;
+use std::sync::atomic;
+use std::sync::atomic::AtomicUsize;
+
+use sourmash::collection::Collection;
+use sourmash::manifest::Record;
+use sourmash::selection::{Select, Selection};
+use sourmash::signature::Signature;
+
+use crate::index::{check_output, index_obj};
+use crate::utils::atomicfile::AtomicFile;
+use crate::utils::buildutils::{BuildCollection, BuildManifest, BuildRecord};
+use crate::utils::verify;
+use crate::utils::{
+    is_revindex_database, load_collection, report_on_collection_loading, zipwriter_handle,
+    MultiCollection, ReportType, RunContext,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConvertFormat {
+    Zip,
+    Directory,
+    Manifest,
+    RocksDB,
+}
+
+impl std::str::FromStr for ConvertFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "zip" => Ok(ConvertFormat::Zip),
+            "dir" => Ok(ConvertFormat::Directory),
+            "manifest" => Ok(ConvertFormat::Manifest),
+            "rocksdb" => Ok(ConvertFormat::RocksDB),
+            _ => bail!(
+                "unknown output format '{}'; use zip, dir, manifest, or rocksdb",
+                s
+            ),
+        }
+    }
+}
+
+impl ConvertFormat {
+    /// The format implied by the output name: `.zip`, `.csv` for a
+    /// standalone manifest, `.rocksdb`, and otherwise a directory.
+    pub fn from_output(output: &str) -> Self {
+        match Path::new(output).extension() {
+            Some("zip") => ConvertFormat::Zip,
+            Some("csv") => ConvertFormat::Manifest,
+            Some("rocksdb") => ConvertFormat::RocksDB,
+            _ => ConvertFormat::Directory,
+        }
+    }
+}
+
+/// Load `input` as `load_collection` does, but also take a directory of
+/// signature files.
+fn load_input(
+    input: &String,
+    selection: &Selection,
+    allow_failed: bool,
+    ctx: &RunContext,
+) -> Result<MultiCollection> {
+    let path = PathBuf::from(input);
+    if !path.is_dir() || is_revindex_database(&path) {
+        return load_collection(input, selection, ReportType::General, allow_failed, ctx);
+    }
+
+    eprintln!("Reading signatures from directory: '{}'", input);
+    let (multi, n_failed) = MultiCollection::from_directory(&path, ctx)?;
+    let n_total = multi.len();
+    let selected = multi.select(selection)?;
+    verify::apply(&selected, ctx)?;
+    report_on_collection_loading(
+        &selected,
+        n_total - selected.len(),
+        n_failed,
+        ReportType::General,
+        allow_failed,
+        ctx,
+    )?;
+    Ok(selected)
+}
+
+/// Load one sketch, or warn and count it as failed.
+fn load_sig(coll: &Collection, record: &Record, failed_sigs: &AtomicUsize) -> Option<Signature> {
+    match coll.sig_from_record(record) {
+        Ok(sig) => Some(Signature::from(sig)),
+        Err(e) => {
+            eprintln!(
+                "WARNING: could not load sketch from '{}': {}",
+                record.internal_location(),
+                e
+            );
+            failed_sigs.fetch_add(1, atomic::Ordering::SeqCst);
+            None
+        }
+    }
+}
+
+fn write_zip(multi: &MultiCollection, output: &str, failed_sigs: &AtomicUsize) -> Result<usize> {
+    let (send, recv) =
+        std::sync::mpsc::sync_channel::<Option<BuildCollection>>(rayon::current_num_threads());
+    let thrd = zipwriter_handle(recv, output.to_string());
+
+    let processed_sigs = AtomicUsize::new(0);
+    let send_result =
+        multi
+            .par_iter()
+            .try_for_each_with(send.clone(), |s, (coll, _idx, record)| -> Result<()> {
+                if let Some(sig) = load_sig(coll, record, failed_sigs) {
+                    let mut sigs = BuildCollection::new();
+                    sigs.add_sig(sig)?;
+                    s.send(Some(sigs))
+                        .map_err(|e| anyhow!("Unable to send internal data: {:?}", e))?;
+                    processed_sigs.fetch_add(1, atomic::Ordering::SeqCst);
+                }
+                Ok(())
+            });
+
+    // Send None to sigwriter to signal completion + write manifest
+    if let Err(e) = send.send(None) {
+        eprintln!("Unable to send completion signal: {:?}", e);
+    }
+    thrd.join()
+        .unwrap_or_else(|e| Err(anyhow!("Thread panicked: {:?}", e)))?;
+    send_result?;
+
+    Ok(processed_sigs.load(atomic::Ordering::SeqCst))
+}
+
+/// Write each sketch to `<dir>/<md5>.sig.gz`, adding `_2`, `_3` etc. for
+/// repeated md5s as in zip files. Returns the records of the sketches
+/// written, with their new locations.
+fn write_directory(
+    multi: &MultiCollection,
+    dir: &Path,
+    failed_sigs: &AtomicUsize,
+) -> Result<Vec<BuildRecord>> {
+    create_dir_all(dir)?;
+
+    // choose file names up front, so that sketches can be written in parallel.
+    let mut md5_counts: HashMap<&str, usize> = HashMap::new();
+    let items: Vec<(&Collection, &Record, String)> = multi
+        .item_iter()
+        .map(|(coll, _idx, record)| {
+            let count = md5_counts.entry(record.md5().as_str()).or_insert(0);
+            *count += 1;
+            let filename = if *count > 1 {
+                format!("{}_{}.sig.gz", record.md5(), count)
+            } else {
+                format!("{}.sig.gz", record.md5())
+            };
+            (coll, record, dir.join(filename).to_string())
+        })
+        .collect();
+
+    let written: Result<Vec<Option<BuildRecord>>> = items
+        .par_iter()
+        .map(|(coll, record, location)| {
+            let sig = match load_sig(coll, record, failed_sigs) {
+                Some(sig) => sig,
+                None => return Ok(None),
+            };
+            let mut sigs = BuildCollection::new();
+            sigs.add_sig(sig)?;
+            sigs.write_sigs(location)?;
+
+            let mut built = sigs.manifest.iter().next().cloned();
+            if let Some(built) = built.as_mut() {
+                built.set_internal_location(Some(location.clone().into()));
+            }
+            Ok(built)
+        })
+        .collect();
+
+    Ok(written?.into_iter().flatten().collect())
+}
+
+/// RocksDB indexes hold sketches of a single ksize, moltype, and scaled.
+fn check_indexable(multi: &MultiCollection) -> Result<()> {
+    let params: BTreeSet<_> = multi
+        .item_iter()
+        .map(|(_, _, record)| {
+            (
+                record.ksize(),
+                record.moltype().to_string(),
+                *record.scaled(),
+            )
+        })
+        .collect();
+    if params.len() > 1 {
+        bail!(
+            "a RocksDB index holds sketches with one ksize, moltype, and scaled, but found {}; select one with -k, -m, and -s",
+            params
+                .iter()
+                .map(|(k, m, s)| format!("k={} {} scaled={}", k, m, s))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Convert the collection at `input` (a zip file, directory of signature
+/// files, standalone manifest, RocksDB index, or anything else
+/// `load_collection` takes) into `format` at `output`.
+pub fn convert(
+    input: String,
+    output: String,
+    format: ConvertFormat,
+    selection: Selection,
+    allow_failed_sigpaths: bool,
+    force: bool,
+    ctx: &RunContext,
+) -> Result<()> {
+    if format == ConvertFormat::Zip && Path::new(&output).extension() != Some("zip") {
+        bail!("Output must be a zip file.");
+    }
+    if format == ConvertFormat::Manifest && Path::new(&output).extension() != Some("csv") {
+        bail!("Manifest output must be a CSV file.");
+    }
+
+    let multi = load_input(&input, &selection, allow_failed_sigpaths, ctx)?;
+    if multi.is_empty() {
+        bail!("No sketches to convert.");
+    }
+    eprintln!("Converting {} sketches.", multi.len());
+
+    let failed_sigs = AtomicUsize::new(0);
+    match format {
+        ConvertFormat::Zip => {
+            let n_written = write_zip(&multi, &output, &failed_sigs)?;
+            eprintln!("DONE. Wrote {} sketches to '{}'", n_written, output);
+        }
+        ConvertFormat::Directory => {
+            let written = write_directory(&multi, Path::new(&output), &failed_sigs)?;
+            eprintln!(
+                "DONE. Wrote {} sketches to directory '{}'",
+                written.len(),
+                output
+            );
+        }
+        ConvertFormat::Manifest => {
+            // sketches go next to the manifest: 'db.csv' -> 'db/'.
+            let sig_dir = PathBuf::from(&output).with_extension("");
+            let written = write_directory(&multi, &sig_dir, &failed_sigs)?;
+
+            let mut manifest = BuildManifest::new();
+            manifest.extend_records(written);
+            let mut file = AtomicFile::create(&output)?;
+            manifest.to_writer(&mut file)?;
+            file.commit()?;
+
+            eprintln!(
+                "DONE. Wrote {} sketches to '{}', with manifest '{}'",
+                manifest.size(),
+                sig_dir,
+                output
+            );
+        }
+        ConvertFormat::RocksDB => {
+            check_indexable(&multi)?;
+            check_output(std::path::Path::new(&output), force)?;
+            // a relocatable index, holding its own copies of the sketches.
+            index_obj(multi, &output, false, true, None)?;
+            eprintln!("DONE. Wrote RocksDB index '{}'", output);
+        }
+    }
+
+    let failed_sigs = failed_sigs.load(atomic::Ordering::SeqCst);
+    if failed_sigs > 0 {
+        eprintln!(
+            "WARNING: {} sketches failed to load. See error messages above.",
+            failed_sigs
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,94 @@
+/// downsample: rewrite a collection at a higher scaled and/or a subset of ksizes.
+use anyhow::{anyhow, Result};
+use camino::Utf8Path as Path;
+use rayon::prelude::*;
+use std::sync::atomic;
+use std::sync::atomic::AtomicUsize;
+
+use sourmash::signature::Signature;
+
+use crate::utils::buildutils::BuildCollection;
+use crate::utils::{load_collection_ksizes, zipwriter_handle, ReportType};
+
+pub fn downsample(
+    siglist: String,
+    ksizes: Vec<u8>,
+    scaled: Option<u32>,
+    moltype: String,
+    output: String,
+    allow_failed_sigpaths: bool,
+) -> Result<()> {
+    // if output doesn't end in zip, bail
+    if Path::new(&output).extension() != Some("zip") {
+        bail!("Output must be a zip file.");
+    }
+
+    // selecting with scaled downsamples each sketch as it is loaded, and
+    // skips sketches that are already at a higher scaled.
+    let collection = load_collection_ksizes(
+        &siglist,
+        &ksizes,
+        scaled,
+        &moltype,
+        ReportType::General,
+        allow_failed_sigpaths,
+    )?;
+
+    let (send, recv) =
+        std::sync::mpsc::sync_channel::<Option<BuildCollection>>(rayon::current_num_threads());
+    let thrd = zipwriter_handle(recv, output.clone());
+
+    let processed_sigs = AtomicUsize::new(0);
+    let failed_sigs = AtomicUsize::new(0);
+
+    let send_result = collection.par_iter().try_for_each_with(
+        send.clone(),
+        |s, (coll, _idx, record)| -> Result<()> {
+            let sig = match coll.sig_from_record(record) {
+                Ok(sig) => sig,
+                Err(e) => {
+                    eprintln!(
+                        "WARNING: could not load sketch from '{}': {}",
+                        record.internal_location(),
+                        e
+                    );
+                    failed_sigs.fetch_add(1, atomic::Ordering::SeqCst);
+                    return Ok(());
+                }
+            };
+
+            let mut sigs = BuildCollection::new();
+            sigs.add_sig(Signature::from(sig))?;
+            s.send(Some(sigs))
+                .map_err(|e| anyhow!("Unable to send internal data: {:?}", e))?;
+
+            processed_sigs.fetch_add(1, atomic::Ordering::SeqCst);
+            Ok(())
+        },
+    );
+
+    // Send None to sigwriter to signal completion + write manifest
+    if let Err(e) = send.send(None) {
+        eprintln!("Unable to send completion signal: {:?}", e);
+    }
+
+    thrd.join()
+        .unwrap_or_else(|e| Err(anyhow!("Thread panicked: {:?}", e)))?;
+    send_result?;
+
+    eprintln!(
+        "DONE. Wrote {} sketches to '{}'",
+        processed_sigs.load(atomic::Ordering::SeqCst),
+        output
+    );
+
+    let failed_sigs = failed_sigs.load(atomic::Ordering::SeqCst);
+    if failed_sigs > 0 {
+        eprintln!(
+            "WARNING: {} sketches failed to load. See error messages above.",
+            failed_sigs
+        );
+    }
+
+    Ok(())
+}
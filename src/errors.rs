@@ -0,0 +1,143 @@
+//! Error kinds that callers may want to handle specifically.
+//!
+//! Internally we use `anyhow` everywhere; these are raised with `bail!`
+//! at the relevant sites. Rust callers can `downcast_ref` them; at the
+//! pyo3 boundary, `to_pyerr` maps them onto Python exception classes.
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+#[cfg(feature = "python")]
+use sourmash::errors::SourmashError;
+
+#[derive(Debug)]
+pub enum BranchwaterError {
+    /// An input path does not exist.
+    MissingFile(String),
+    /// Sketches are incompatible with the requested ksize/moltype/etc.
+    IncompatibleSelection(String),
+    /// No signatures were loaded from a collection.
+    EmptyCollection(String),
+    /// Sketches cannot be compared at the requested scaled.
+    ScaledMismatch(String),
+    /// A path is not a usable RocksDB (RevIndex) database.
+    InvalidRocksDB(String),
+}
+
+impl std::fmt::Display for BranchwaterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let msg = match self {
+            BranchwaterError::MissingFile(msg)
+            | BranchwaterError::IncompatibleSelection(msg)
+            | BranchwaterError::EmptyCollection(msg)
+            | BranchwaterError::ScaledMismatch(msg)
+            | BranchwaterError::InvalidRocksDB(msg) => msg,
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+impl std::error::Error for BranchwaterError {}
+
+#[cfg(feature = "python")]
+pub mod exceptions {
+    use pyo3::create_exception;
+    use pyo3::exceptions::PyException;
+
+    create_exception!(sourmash_plugin_branchwater, BranchwaterError, PyException);
+    create_exception!(
+        sourmash_plugin_branchwater,
+        MissingFileError,
+        BranchwaterError
+    );
+    create_exception!(
+        sourmash_plugin_branchwater,
+        IncompatibleSelectionError,
+        BranchwaterError
+    );
+    create_exception!(
+        sourmash_plugin_branchwater,
+        EmptyCollectionError,
+        BranchwaterError
+    );
+    create_exception!(
+        sourmash_plugin_branchwater,
+        ScaledMismatchError,
+        BranchwaterError
+    );
+    create_exception!(
+        sourmash_plugin_branchwater,
+        InvalidRocksDBError,
+        BranchwaterError
+    );
+}
+
+/// Convert an error into the most specific Python exception we have.
+#[cfg(feature = "python")]
+pub fn to_pyerr(e: anyhow::Error) -> PyErr {
+    use exceptions as exc;
+
+    let msg = format!("{e:#}");
+    for cause in e.chain() {
+        if let Some(err) = cause.downcast_ref::<BranchwaterError>() {
+            return match err {
+                BranchwaterError::MissingFile(_) => exc::MissingFileError::new_err(msg),
+                BranchwaterError::IncompatibleSelection(_) => {
+                    exc::IncompatibleSelectionError::new_err(msg)
+                }
+                BranchwaterError::EmptyCollection(_) => exc::EmptyCollectionError::new_err(msg),
+                BranchwaterError::ScaledMismatch(_) => exc::ScaledMismatchError::new_err(msg),
+                BranchwaterError::InvalidRocksDB(_) => exc::InvalidRocksDBError::new_err(msg),
+            };
+        }
+        if let Some(err) = cause.downcast_ref::<SourmashError>() {
+            match err {
+                SourmashError::MismatchScaled | SourmashError::CannotUpsampleScaled => {
+                    return exc::ScaledMismatchError::new_err(msg)
+                }
+                SourmashError::MismatchKSizes
+                | SourmashError::MismatchDNAProt
+                | SourmashError::MismatchNum { .. }
+                | SourmashError::MismatchSeed
+                | SourmashError::MismatchSignatureType => {
+                    return exc::IncompatibleSelectionError::new_err(msg)
+                }
+                SourmashError::RocksDBError(_) => return exc::InvalidRocksDBError::new_err(msg),
+                _ => (),
+            }
+        }
+        if let Some(err) = cause.downcast_ref::<std::io::Error>() {
+            if err.kind() == std::io::ErrorKind::NotFound {
+                return exc::MissingFileError::new_err(msg);
+            }
+        }
+    }
+
+    // errors that started out as Python exceptions are passed through.
+    match e.downcast::<PyErr>() {
+        Ok(pyerr) => pyerr,
+        Err(_) => exc::BranchwaterError::new_err(msg),
+    }
+}
+
+/// Register the exception classes on the Python module.
+#[cfg(feature = "python")]
+pub fn add_exceptions(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    use exceptions::{
+        BranchwaterError, EmptyCollectionError, IncompatibleSelectionError, InvalidRocksDBError,
+        MissingFileError, ScaledMismatchError,
+    };
+
+    let py = m.py();
+    m.add("BranchwaterError", py.get_type::<BranchwaterError>())?;
+    m.add("MissingFileError", py.get_type::<MissingFileError>())?;
+    m.add(
+        "IncompatibleSelectionError",
+        py.get_type::<IncompatibleSelectionError>(),
+    )?;
+    m.add(
+        "EmptyCollectionError",
+        py.get_type::<EmptyCollectionError>(),
+    )?;
+    m.add("ScaledMismatchError", py.get_type::<ScaledMismatchError>())?;
+    m.add("InvalidRocksDBError", py.get_type::<InvalidRocksDBError>())?;
+    Ok(())
+}
@@ -0,0 +1,153 @@
+/// extract: write a subset of a collection, selected by name, md5, or picklist.
+use anyhow::{anyhow, Result};
+use camino::Utf8Path as Path;
+use rayon::prelude::*;
+use regex::Regex;
+use std::sync::atomic;
+use std::sync::atomic::AtomicUsize;
+
+use sourmash::manifest::Record;
+use sourmash::signature::Signature;
+
+use crate::utils::buildutils::BuildCollection;
+use crate::utils::picklist::Picklist;
+use crate::utils::{load_collection_ksizes, zipwriter_handle, ReportType};
+
+/// Filters on signature records; all given filters must match.
+pub struct ExtractFilters {
+    pub name_regex: Option<Regex>,
+    pub md5s: Vec<String>,
+    pub picklist: Option<Picklist>,
+}
+
+impl ExtractFilters {
+    pub fn new(
+        name_pattern: Option<String>,
+        md5s: Vec<String>,
+        picklist: Option<String>,
+    ) -> Result<Self> {
+        let name_regex = name_pattern
+            .map(|p| Regex::new(&p).map_err(|e| anyhow!("invalid name pattern '{}': {}", p, e)))
+            .transpose()?;
+        let picklist = picklist.map(|p| Picklist::load(&p)).transpose()?;
+
+        Ok(ExtractFilters {
+            name_regex,
+            md5s,
+            picklist,
+        })
+    }
+
+    /// md5s match on the full md5 or a prefix, e.g. md5short.
+    pub fn matches(&self, record: &Record) -> bool {
+        if let Some(regex) = &self.name_regex {
+            if !regex.is_match(record.name()) {
+                return false;
+            }
+        }
+        if !self.md5s.is_empty() && !self.md5s.iter().any(|m| record.md5().starts_with(m)) {
+            return false;
+        }
+        if let Some(picklist) = &self.picklist {
+            if !picklist.matches(record) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+pub fn extract(
+    siglist: String,
+    ksizes: Vec<u8>,
+    moltype: String,
+    filters: ExtractFilters,
+    output: String,
+    allow_failed_sigpaths: bool,
+) -> Result<()> {
+    // if output doesn't end in zip, bail
+    if Path::new(&output).extension() != Some("zip") {
+        bail!("Output must be a zip file.");
+    }
+
+    let collection = load_collection_ksizes(
+        &siglist,
+        &ksizes,
+        None,
+        &moltype,
+        ReportType::General,
+        allow_failed_sigpaths,
+    )?;
+
+    // select on the manifest first, so only matching sketches are loaded.
+    let selected: Vec<_> = collection
+        .par_iter()
+        .filter(|(_, _, record)| filters.matches(record))
+        .collect();
+    if selected.is_empty() {
+        bail!("No signatures matched the given filters.");
+    }
+    eprintln!(
+        "Selected {} of {} signatures",
+        selected.len(),
+        collection.len()
+    );
+
+    let (send, recv) =
+        std::sync::mpsc::sync_channel::<Option<BuildCollection>>(rayon::current_num_threads());
+    let thrd = zipwriter_handle(recv, output.clone());
+
+    let processed_sigs = AtomicUsize::new(0);
+    let failed_sigs = AtomicUsize::new(0);
+
+    let send_result = selected.par_iter().try_for_each_with(
+        send.clone(),
+        |s, (coll, _idx, record)| -> Result<()> {
+            let sig = match coll.sig_from_record(record) {
+                Ok(sig) => sig,
+                Err(e) => {
+                    eprintln!(
+                        "WARNING: could not load sketch from '{}': {}",
+                        record.internal_location(),
+                        e
+                    );
+                    failed_sigs.fetch_add(1, atomic::Ordering::SeqCst);
+                    return Ok(());
+                }
+            };
+
+            let mut sigs = BuildCollection::new();
+            sigs.add_sig(Signature::from(sig))?;
+            s.send(Some(sigs))
+                .map_err(|e| anyhow!("Unable to send internal data: {:?}", e))?;
+
+            processed_sigs.fetch_add(1, atomic::Ordering::SeqCst);
+            Ok(())
+        },
+    );
+
+    // Send None to sigwriter to signal completion + write manifest
+    if let Err(e) = send.send(None) {
+        eprintln!("Unable to send completion signal: {:?}", e);
+    }
+
+    thrd.join()
+        .unwrap_or_else(|e| Err(anyhow!("Thread panicked: {:?}", e)))?;
+    send_result?;
+
+    eprintln!(
+        "DONE. Wrote {} sketches to '{}'",
+        processed_sigs.load(atomic::Ordering::SeqCst),
+        output
+    );
+
+    let failed_sigs = failed_sigs.load(atomic::Ordering::SeqCst);
+    if failed_sigs > 0 {
+        eprintln!(
+            "WARNING: {} sketches failed to load. See error messages above.",
+            failed_sigs
+        );
+    }
+
+    Ok(())
+}
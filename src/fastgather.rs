@@ -1,38 +1,90 @@
 /// fastgather: Run gather with a query against a list of files.
 use anyhow::Result;
+use rayon::prelude::*;
+use std::borrow::Cow;
+use std::collections::BinaryHeap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::SyncSender;
 
-use sourmash::prelude::Select;
+use sourmash::collection::Collection;
+use sourmash::manifest::Record;
 use sourmash::selection::Selection;
+use sourmash::signature::SigsTrait;
 use sourmash::sketch::minhash::KmerMinHash;
 
+use crate::utils::stoplist::{self, Stoplist};
 use crate::utils::{
-    consume_query_by_gather, csvwriter_thread, load_collection, load_sketches_above_threshold,
-    write_prefetch, BranchwaterGatherResult, ReportType,
+    collect_results, consume_query_by_gather, without_abundance_requirement,
+    BranchwaterGatherResult, CollectionSource, GatherOptions, GatherThreshold, MultiCollection,
+    PrefetchResult, ReportType, RunContext, SmallSignature,
 };
 
+#[cfg(feature = "python")]
+use crate::errors::BranchwaterError;
+#[cfg(feature = "python")]
+use crate::utils::columns::ColumnSelection;
+#[cfg(feature = "python")]
+use crate::utils::profile::Stage;
+#[cfg(feature = "python")]
+use crate::utils::taxonomy::{gather_csvwriter_thread, TaxSummarizer, TaxonomyOptions};
+#[cfg(feature = "python")]
+use crate::utils::{
+    load_sketches_above_threshold, prefetch_writer, write_prefetch, write_prefetch_header,
+    write_prefetch_rows, Flusher, PrefetchQuery,
+};
+#[cfg(feature = "python")]
+use sourmash::prelude::Select;
+#[cfg(feature = "python")]
+use std::sync::Mutex;
+
+#[cfg(feature = "python")]
 #[allow(clippy::too_many_arguments)]
 pub fn fastgather(
-    query_filepath: String,
-    against_filepath: String,
-    threshold_bp: u64,
+    query_source: CollectionSource,
+    against_source: CollectionSource,
+    threshold: GatherThreshold,
     selection: Selection,
     gather_output: Option<String>,
     prefetch_output: Option<String>,
     allow_failed_sigpaths: bool,
+    taxonomy: Option<TaxonomyOptions>,
+    gather_options: GatherOptions,
+    columns: ColumnSelection,
+    stoplist: Option<&Stoplist>,
+    ctx: &RunContext,
 ) -> Result<()> {
-    let query_collection = load_collection(
-        &query_filepath,
-        &selection,
+    // load lineages first, so that bad taxonomy files fail fast
+    let summarizer = taxonomy.map(TaxSummarizer::load).transpose()?;
+
+    let (query_collection, selection) = query_source.load_and_complete_selection(
+        selection,
         ReportType::Query,
         allow_failed_sigpaths,
+        ctx,
     )?;
 
-    if query_collection.len() != 1 {
+    if query_collection.is_empty() {
         bail!(
-            "Fastgather requires a single query sketch. Check input: '{:?}'",
-            &query_filepath
+            "Fastgather requires at least one query sketch. Check input: '{}'",
+            &query_source
         )
     }
+    if query_collection.len() > 1 {
+        return fastgather_many(
+            query_collection,
+            against_source,
+            threshold,
+            selection,
+            gather_output,
+            prefetch_output,
+            allow_failed_sigpaths,
+            summarizer,
+            gather_options,
+            columns,
+            stoplist,
+            ctx,
+        );
+    }
     // get single query sig and minhash
     let query_sig = query_collection.get_first_sig().expect("no queries!?");
 
@@ -41,43 +93,41 @@ pub fn fastgather(
     let query_md5 = query_sig.md5sum();
 
     // clone here is necessary b/c we use full query_sig in consume_query_by_gather
-    let query_sig_ds = query_sig.select(&selection)?; // downsample as needed.
-    let query_mh: KmerMinHash = match query_sig_ds.try_into() {
+    // downsample as needed.
+    let query_sig_ds = ctx.time(Stage::Selection, || query_sig.select(&selection))?;
+    let mut query_mh: KmerMinHash = match query_sig_ds.try_into() {
         Ok(query_mh) => query_mh,
         Err(_) => {
-            bail!("No query sketch matching selection parameters.");
+            bail!(BranchwaterError::IncompatibleSelection(
+                "No query sketch matching selection parameters.".to_string()
+            ));
         }
     };
+    stoplist::apply(stoplist, &mut query_mh);
 
-    let mut against_selection = selection;
+    let mut against_selection = without_abundance_requirement(&selection);
     let scaled = query_mh.scaled();
     against_selection.set_scaled(scaled);
 
     // load collection to match against.
-    let against_collection = load_collection(
-        &against_filepath,
+    let against_collection = against_source.load(
         &against_selection,
         ReportType::Against,
         allow_failed_sigpaths,
+        ctx,
     )?;
 
     // calculate the minimum number of hashes based on desired threshold
-    let threshold_hashes = {
-        let x = threshold_bp / scaled as u64;
-        if x > 0 {
-            x
-        } else {
-            1
-        }
-    };
+    let threshold_hashes = threshold.hashes(scaled, query_mh.size());
 
     eprintln!(
-        "using threshold overlap: {} {}",
-        threshold_hashes, threshold_bp
+        "using threshold overlap: {} hashes ({})",
+        threshold_hashes, threshold
     );
 
     // load a set of sketches, filtering for those with overlaps > threshold
-    let result = load_sketches_above_threshold(against_collection, &query_mh, threshold_hashes)?;
+    let result =
+        load_sketches_above_threshold(against_collection, &query_mh, threshold_hashes, ctx)?;
     let matchlist = result.0;
     let skipped_paths = result.1;
     let failed_paths = result.2;
@@ -94,41 +144,371 @@ pub fn fastgather(
         );
     }
 
+    // with no matches, carry on anyway to write outputs with just headers.
     if matchlist.is_empty() {
-        eprintln!("No search signatures loaded, exiting.");
-        return Ok(());
+        eprintln!("No matches above threshold.");
     }
 
     if prefetch_output.is_some() {
-        write_prefetch(
-            query_filename.clone(),
-            query_name.clone(),
-            query_md5,
-            prefetch_output,
-            &matchlist,
-        )
+        ctx.time(Stage::Writing, || {
+            let query = PrefetchQuery {
+                filename: &query_filename,
+                name: &query_name,
+                md5: &query_md5,
+                minhash: &query_mh,
+            };
+            write_prefetch(
+                &query,
+                prefetch_output,
+                &matchlist,
+                columns.sourmash_compat(),
+            )
+        })
         .ok();
     }
 
     let (send, recv) =
         std::sync::mpsc::sync_channel::<BranchwaterGatherResult>(rayon::current_num_threads());
-    let gather_out_thrd = csvwriter_thread(recv, gather_output);
+    let gather_out_thrd = gather_csvwriter_thread(recv, gather_output, columns, summarizer, ctx)?;
 
     // run the gather!
-    consume_query_by_gather(
-        query_name,
-        query_filename,
-        query_mh,
-        scaled as u32,
-        matchlist,
-        threshold_hashes,
-        Some(send),
-    )
+    ctx.time(Stage::Comparison, || {
+        consume_query_by_gather(
+            query_name,
+            query_filename,
+            query_mh,
+            scaled,
+            matchlist,
+            threshold_hashes,
+            &gather_options,
+            Some(send),
+        )
+    })
     .ok();
 
-    gather_out_thrd
+    let summarizer = gather_out_thrd
         .join()
         .expect("Unable to join internal thread");
+    if let Some(summarizer) = summarizer {
+        ctx.time(Stage::Writing, || summarizer.write_outputs())?;
+    }
+    ctx.record_processed(1);
+    stoplist::report(stoplist);
 
     Ok(())
 }
+
+/// Find the sketches in `against` that overlap `query_mh` by at least
+/// `threshold_hashes`.
+pub(crate) fn prefetch_sketches<'a>(
+    query_mh: &KmerMinHash,
+    against: &'a [SmallSignature],
+    threshold_hashes: u64,
+) -> BinaryHeap<PrefetchResult<'a>> {
+    against
+        .iter()
+        .filter_map(|against| {
+            let overlap = against.minhash.count_common(query_mh, false).ok()?;
+            if overlap > 0 && overlap >= threshold_hashes {
+                Some(PrefetchResult {
+                    name: against.name.clone(),
+                    md5sum: against.md5sum.clone(),
+                    minhash: Cow::Borrowed(&against.minhash),
+                    location: against.location.clone(),
+                    overlap,
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Load a query sketch at `scaled`, warning about (and recording) the
+/// path if it has no compatible sketch.
+pub(crate) fn query_sketch(
+    coll: &Collection,
+    record: &Record,
+    scaled: u32,
+    stoplist: Option<&Stoplist>,
+    ctx: &RunContext,
+) -> Option<KmerMinHash> {
+    let mut query_mh: Option<KmerMinHash> = coll
+        .sig_from_record(record)
+        .ok()
+        .and_then(|sig| sig.try_into().ok())
+        .and_then(|mh: KmerMinHash| mh.downsample_scaled(scaled).ok());
+    if let Some(query_mh) = query_mh.as_mut() {
+        stoplist::apply(stoplist, query_mh);
+    } else {
+        eprintln!(
+            "WARNING: no compatible sketches in path '{}'",
+            record.internal_location()
+        );
+        ctx.record_skipped(
+            record.internal_location().as_str(),
+            "no compatible sketches",
+        );
+    }
+    query_mh
+}
+
+/// Gather each of several queries against an against collection that is
+/// loaded into memory once. Prefetch and gather results for all queries
+/// go to the same output files.
+#[cfg(feature = "python")]
+#[allow(clippy::too_many_arguments)]
+fn fastgather_many(
+    query_collection: MultiCollection,
+    against_source: CollectionSource,
+    threshold: GatherThreshold,
+    selection: Selection,
+    gather_output: Option<String>,
+    prefetch_output: Option<String>,
+    allow_failed_sigpaths: bool,
+    summarizer: Option<TaxSummarizer>,
+    gather_options: GatherOptions,
+    columns: ColumnSelection,
+    stoplist: Option<&Stoplist>,
+    ctx: &RunContext,
+) -> Result<()> {
+    // all queries are compared at the same scaled.
+    let scaled = match selection.scaled() {
+        Some(s) => s,
+        None => {
+            let s = *query_collection.max_scaled().expect("no records!?");
+            eprintln!(
+                "Setting scaled={} based on max scaled in query collection",
+                s
+            );
+            s
+        }
+    };
+
+    let mut against_selection = without_abundance_requirement(&selection);
+    against_selection.set_scaled(scaled);
+
+    let against_collection = against_source.load(
+        &against_selection,
+        ReportType::Against,
+        allow_failed_sigpaths,
+        ctx,
+    )?;
+    let against = ctx.time(Stage::Loading, || against_collection.load_sketches())?;
+
+    if threshold.is_per_query() {
+        eprintln!("using threshold overlap: {}, per query", threshold);
+    } else {
+        eprintln!(
+            "using threshold overlap: {} hashes ({})",
+            threshold.hashes(scaled, 0),
+            threshold
+        );
+    }
+    eprintln!(
+        "gathering {} queries against {} sketches",
+        query_collection.len(),
+        against.len()
+    );
+
+    let prefetch_out = match prefetch_output {
+        Some(ref path) => {
+            let flusher = Flusher::new(ctx.flush_policy(Some(path)));
+            Some(Mutex::new((
+                prefetch_writer(prefetch_output, columns.sourmash_compat())?,
+                flusher,
+            )))
+        }
+        None => None,
+    };
+    let prefetch_rows = AtomicUsize::new(0);
+    let no_matches = AtomicUsize::new(0);
+    let skipped_paths = AtomicUsize::new(0);
+
+    let (send, recv) =
+        std::sync::mpsc::sync_channel::<BranchwaterGatherResult>(rayon::current_num_threads());
+    let gather_out_thrd = gather_csvwriter_thread(recv, gather_output, columns, summarizer, ctx)?;
+
+    ctx.time(Stage::Comparison, || {
+        query_collection
+            .par_iter()
+            .for_each_with(send, |send, (coll, _idx, record)| {
+                let Some(query_mh) = query_sketch(coll, record, scaled, stoplist, ctx) else {
+                    skipped_paths.fetch_add(1, Ordering::SeqCst);
+                    return;
+                };
+
+                let threshold_hashes = threshold.hashes(scaled, query_mh.size());
+                let matchlist = prefetch_sketches(&query_mh, &against, threshold_hashes);
+                if matchlist.is_empty() {
+                    no_matches.fetch_add(1, Ordering::SeqCst);
+                    return;
+                }
+
+                if let Some(prefetch_out) = &prefetch_out {
+                    let (writer, flusher) = &mut *prefetch_out.lock().unwrap();
+                    let query = PrefetchQuery {
+                        filename: record.filename(),
+                        name: record.name(),
+                        md5: record.md5(),
+                        minhash: &query_mh,
+                    };
+                    match write_prefetch_rows(writer, &query, &matchlist) {
+                        Ok(n) => {
+                            prefetch_rows.fetch_add(n, Ordering::SeqCst);
+                            if flusher.wrote(n) {
+                                if let Err(e) = writer.flush() {
+                                    eprintln!("Error flushing prefetch output: {}", e);
+                                }
+                            }
+                        }
+                        Err(e) => eprintln!("Error writing prefetch output: {}", e),
+                    }
+                }
+
+                consume_query_by_gather(
+                    record.name().clone(),
+                    record.filename().clone(),
+                    query_mh,
+                    scaled,
+                    matchlist,
+                    threshold_hashes,
+                    &gather_options,
+                    Some(send.clone()),
+                )
+                .ok();
+            });
+    });
+
+    if let Some(prefetch_out) = prefetch_out {
+        let (mut writer, _) = prefetch_out.into_inner().unwrap();
+        // make sure the header gets written even if there are no matches.
+        if prefetch_rows.into_inner() == 0 {
+            write_prefetch_header(&mut writer)?;
+        }
+        writer.finish()?;
+    }
+
+    let summarizer = gather_out_thrd
+        .join()
+        .expect("Unable to join internal thread");
+    if let Some(summarizer) = summarizer {
+        ctx.time(Stage::Writing, || summarizer.write_outputs())?;
+    }
+
+    let skipped_paths = skipped_paths.into_inner();
+    ctx.record_processed(query_collection.len() - skipped_paths);
+    stoplist::report(stoplist);
+    eprintln!(
+        "DONE. Gathered {} queries.",
+        query_collection.len() - skipped_paths
+    );
+    let no_matches = no_matches.into_inner();
+    if no_matches > 0 {
+        eprintln!("{} queries had no matches above threshold.", no_matches);
+    }
+    if skipped_paths > 0 {
+        eprintln!(
+            "WARNING: skipped {} query paths - no compatible signatures.",
+            skipped_paths
+        );
+    }
+
+    Ok(())
+}
+
+/// Gather each query against a collection, returning the gather results
+/// instead of writing them to a CSV file. Queries and against sketches are
+/// compared at the largest query scaled, unless `selection` sets one.
+pub fn fastgather_collect(
+    query_source: CollectionSource,
+    against_source: CollectionSource,
+    threshold: GatherThreshold,
+    selection: Selection,
+    allow_failed_sigpaths: bool,
+    gather_options: GatherOptions,
+) -> Result<Vec<BranchwaterGatherResult>> {
+    let ctx = RunContext::default();
+    let (query_collection, selection) = query_source.load_and_complete_selection(
+        selection,
+        ReportType::Query,
+        allow_failed_sigpaths,
+        &ctx,
+    )?;
+
+    let scaled = match selection.scaled() {
+        Some(s) => s,
+        None => *query_collection.max_scaled().expect("no records!?"),
+    };
+
+    let mut against_selection = without_abundance_requirement(&selection);
+    against_selection.set_scaled(scaled);
+
+    let against_collection = against_source.load(
+        &against_selection,
+        ReportType::Against,
+        allow_failed_sigpaths,
+        &ctx,
+    )?;
+    let against = against_collection.load_sketches()?;
+
+    let (n_gathered, results) = collect_results(|send| {
+        Ok(fastgather_obj(
+            &query_collection,
+            &against,
+            scaled,
+            threshold,
+            &gather_options,
+            send,
+            &ctx,
+        ))
+    })?;
+
+    eprintln!("DONE. Gathered {} queries.", n_gathered);
+
+    Ok(results)
+}
+
+/// Prefetch and gather each query against in-memory sketches, sending
+/// the gather results over `send`. Returns the number of queries gathered.
+pub(crate) fn fastgather_obj(
+    query_collection: &MultiCollection,
+    against: &[SmallSignature],
+    scaled: u32,
+    threshold: GatherThreshold,
+    gather_options: &GatherOptions,
+    send: SyncSender<BranchwaterGatherResult>,
+    ctx: &RunContext,
+) -> usize {
+    let n_gathered = AtomicUsize::new(0);
+
+    query_collection
+        .par_iter()
+        .for_each_with(send, |send, (coll, _idx, record)| {
+            let Some(query_mh) = query_sketch(coll, record, scaled, None, ctx) else {
+                return;
+            };
+            n_gathered.fetch_add(1, Ordering::SeqCst);
+
+            let threshold_hashes = threshold.hashes(scaled, query_mh.size());
+            let matchlist = prefetch_sketches(&query_mh, against, threshold_hashes);
+            if matchlist.is_empty() {
+                return;
+            }
+
+            if let Err(e) = consume_query_by_gather(
+                record.name().clone(),
+                record.filename().clone(),
+                query_mh,
+                scaled,
+                matchlist,
+                threshold_hashes,
+                gather_options,
+                Some(send.clone()),
+            ) {
+                eprintln!("Error gathering '{}': {}", record.name(), e);
+            }
+        });
+
+    n_gathered.into_inner()
+}
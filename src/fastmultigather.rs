@@ -1,5 +1,5 @@
 /// fastmultigather: Run gather for multiple queries against a list of files.
-use anyhow::Result;
+use anyhow::{bail, Result};
 use rayon::iter::ParallelIterator;
 
 use sourmash::prelude::{Storage, ToWriter};
@@ -8,12 +8,13 @@ use sourmash::{selection::Selection, signature::SigsTrait};
 use std::sync::atomic;
 use std::sync::atomic::AtomicUsize;
 
+use std::borrow::Cow;
 use std::collections::BinaryHeap;
 
 use camino::Utf8Path as PathBuf;
 
-use std::collections::HashSet;
-use std::fs::File;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::create_dir_all;
 
 use log::trace;
 
@@ -21,79 +22,369 @@ use sourmash::signature::Signature;
 use sourmash::sketch::minhash::KmerMinHash;
 use sourmash::sketch::Sketch;
 
+use crate::utils::atomicfile::AtomicFile;
+use crate::utils::columns::ColumnSelection;
+use crate::utils::profile::Stage;
+use crate::utils::stoplist::{self, Stoplist};
+use crate::utils::taxonomy::{gather_csvwriter_thread, TaxSummarizer, TaxonomyOptions};
 use crate::utils::{
-    consume_query_by_gather, csvwriter_thread, load_collection, write_prefetch,
-    BranchwaterGatherResult, MultiCollection, PrefetchResult, ReportType, SmallSignature,
+    consume_query_by_gather, downsample_query, load_collection, remove_hashes,
+    select_at_max_scaled, without_abundance_requirement, write_prefetch, BranchwaterGatherResult,
+    CollectionSource, GatherOptions, GatherThreshold, MultiCollection, PrefetchQuery,
+    PrefetchResult, ReportType, RunContext, SmallSignature,
 };
 
+/// Where to put per-query prefetch and matches outputs, and what to call them.
+#[derive(Clone, Debug, Default)]
+pub struct QueryOutputNames {
+    pub output_dir: Option<String>,
+    pub template: Option<String>,
+    /// overwrite existing per-query outputs.
+    pub force: bool,
+}
+
+impl QueryOutputNames {
+    const FIELDS: [&'static str; 4] = ["{name}", "{ident}", "{md5}", "{md5short}"];
+
+    pub fn new(output_dir: Option<String>, template: Option<String>, force: bool) -> Result<Self> {
+        if let Some(template) = &template {
+            let mut rest = template.clone();
+            for field in Self::FIELDS {
+                rest = rest.replace(field, "");
+            }
+            if rest.contains('{') || rest.contains('}') {
+                bail!(
+                    "invalid output template '{}'; use {}",
+                    template,
+                    Self::FIELDS.join(", ")
+                );
+            }
+            if rest.contains('/') {
+                bail!("output template '{}' may not contain '/'", template);
+            }
+        }
+        Ok(QueryOutputNames {
+            output_dir,
+            template,
+            force,
+        })
+    }
+
+    /// Check that none of the per-query outputs exist yet, unless `force`
+    /// is set, so that an earlier run's results aren't silently replaced.
+    pub(crate) fn check_existing(
+        &self,
+        query_collection: &MultiCollection,
+        prefetch: bool,
+        save_matches: bool,
+        save_unassigned: bool,
+    ) -> Result<()> {
+        if self.force {
+            return Ok(());
+        }
+        for (_, _, record) in query_collection.item_iter() {
+            let name = record.name();
+            let prefix = name.split(' ').next().unwrap_or_default();
+            let location = PathBuf::new(prefix).file_name().unwrap_or_default();
+
+            let mut paths = vec![];
+            if prefetch {
+                paths.push(self.path(name, record.md5(), location, ".prefetch.csv")?);
+            }
+            if save_matches {
+                paths.push(self.path(name, record.md5(), name, ".matches.sig")?);
+            }
+            if save_unassigned {
+                paths.push(self.path(name, record.md5(), name, ".unassigned.sig")?);
+            }
+            if let Some(path) = paths.into_iter().find(|p| PathBuf::new(p).exists()) {
+                bail!("output '{}' already exists; use --force to overwrite", path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Build the output path for one query. Without a template, `default`
+    /// is used as the base name, as in earlier versions.
+    pub(crate) fn path(
+        &self,
+        name: &str,
+        md5: &str,
+        default: &str,
+        suffix: &str,
+    ) -> Result<String> {
+        let basename = match &self.template {
+            Some(template) => {
+                let ident = name.split(' ').next().unwrap_or_default();
+                // query names may contain '/', which would escape output_dir.
+                template
+                    .replace("{name}", name)
+                    .replace("{ident}", ident)
+                    .replace("{md5short}", &md5[..8.min(md5.len())])
+                    .replace("{md5}", md5)
+                    .replace('/', "_")
+            }
+            None => default.to_string(),
+        };
+        let filename = format!("{}{}", basename, suffix);
+        match &self.output_dir {
+            Some(dir) => {
+                create_dir_all(dir)?;
+                Ok(PathBuf::new(dir).join(filename).to_string())
+            }
+            None => Ok(filename),
+        }
+    }
+}
+
+/// Write the hashes of `query_mh` in `matched` to `{name}.matches.sig`,
+/// and/or the rest of them, with their abundances, to
+/// `{name}.unassigned.sig`.
+pub(crate) fn save_query_hashes(
+    output_names: &QueryOutputNames,
+    query_name: &str,
+    query_md5: &str,
+    query_mh: &KmerMinHash,
+    mut matched: Vec<u64>,
+    save_matches: bool,
+    save_unassigned: bool,
+) {
+    matched.sort_unstable();
+    matched.dedup();
+
+    let write_sig = |mh: KmerMinHash, suffix: &str| {
+        let sig_filename = match output_names.path(query_name, query_md5, query_name, suffix) {
+            Ok(path) => path,
+            Err(e) => {
+                eprintln!("Error creating output directory: {}", e);
+                return;
+            }
+        };
+        if let Ok(mut file) = AtomicFile::create(&sig_filename) {
+            let mut signature = Signature::default();
+            signature.push(Sketch::MinHash(mh));
+            signature.set_filename(query_name);
+            if let Err(e) = signature.to_writer(&mut file) {
+                eprintln!("Error writing signature file: {}", e);
+            } else if let Err(e) = file.commit() {
+                eprintln!("Error writing signature file: {}", e);
+            }
+        } else {
+            eprintln!("Error creating signature file: {}", sig_filename);
+        }
+    };
+
+    if save_matches {
+        let mut new_mh = KmerMinHash::new(
+            query_mh.scaled(),
+            query_mh.ksize() as u32,
+            query_mh.hash_function(),
+            query_mh.seed(),
+            false,
+            query_mh.num(),
+        );
+        new_mh.add_many(&matched).ok();
+        write_sig(new_mh, ".matches.sig");
+    }
+    if save_unassigned {
+        match remove_hashes(query_mh, &matched) {
+            Ok(unassigned_mh) => write_sig(unassigned_mh, ".unassigned.sig"),
+            Err(e) => eprintln!("Error removing matched hashes: {}", e),
+        }
+    }
+}
+
+/// An inverted index from hash to the against sketches containing it. It is
+/// built once, so that each query's prefetch is a set of hash lookups
+/// rather than an intersection with every against sketch.
+struct SharedPrefetch {
+    scaled: u32,
+    hash_to_against: HashMap<u64, Vec<u32>>,
+}
+
+impl SharedPrefetch {
+    fn new(against: &[SmallSignature], scaled: u32) -> Self {
+        let mut hash_to_against: HashMap<u64, Vec<u32>> = HashMap::new();
+        for (idx, sig) in against.iter().enumerate() {
+            for hash in sig.minhash.iter_mins() {
+                hash_to_against.entry(*hash).or_default().push(idx as u32);
+            }
+        }
+        eprintln!(
+            "Built shared prefetch index of {} hashes from {} sketches.",
+            hash_to_against.len(),
+            against.len()
+        );
+        SharedPrefetch {
+            scaled,
+            hash_to_against,
+        }
+    }
+
+    /// Find the against sketches sharing at least `threshold_hashes` with
+    /// `query_mh`, adding the shared hashes to `matching_hashes` if given.
+    fn prefetch<'a>(
+        &self,
+        query_mh: &KmerMinHash,
+        against: &'a [SmallSignature],
+        threshold_hashes: u64,
+        matching_hashes: Option<&mut Vec<u64>>,
+    ) -> BinaryHeap<PrefetchResult<'a>> {
+        // a query at a higher scaled holds a subset of the hashes it would
+        // have at the index scaled, so its overlaps are counted correctly;
+        // like count_common, a query at a lower scaled is not compared.
+        if query_mh.scaled() < self.scaled {
+            return BinaryHeap::new();
+        }
+
+        // keep against order, so gather breaks ties as it does without sharing.
+        let mut counts: BTreeMap<u32, u64> = BTreeMap::new();
+        for hash in query_mh.iter_mins() {
+            if let Some(idxs) = self.hash_to_against.get(hash) {
+                for idx in idxs {
+                    *counts.entry(*idx).or_default() += 1;
+                }
+            }
+        }
+        counts.retain(|_, overlap| *overlap >= threshold_hashes);
+
+        if let Some(matching_hashes) = matching_hashes {
+            matching_hashes.extend(query_mh.iter_mins().filter(|hash| {
+                self.hash_to_against
+                    .get(hash)
+                    .is_some_and(|idxs| idxs.iter().any(|idx| counts.contains_key(idx)))
+            }));
+        }
+
+        counts
+            .into_iter()
+            .map(|(idx, overlap)| {
+                let against = &against[idx as usize];
+                PrefetchResult {
+                    name: against.name.clone(),
+                    md5sum: against.md5sum.clone(),
+                    minhash: Cow::Borrowed(&against.minhash),
+                    location: against.location.clone(),
+                    overlap,
+                }
+            })
+            .collect()
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn fastmultigather(
     query_filepath: String,
     against_filepath: String,
-    threshold_bp: u32,
+    threshold: GatherThreshold,
     scaled: Option<u32>,
     selection: Selection,
     allow_failed_sigpaths: bool,
     save_matches: bool,
+    save_unassigned: bool,
     output_path: Option<String>,
     create_empty_results: bool,
+    taxonomy: Option<TaxonomyOptions>,
+    output_names: QueryOutputNames,
+    shared_prefetch: bool,
+    gather_options: GatherOptions,
+    columns: ColumnSelection,
+    per_query_scaled: bool,
+    stoplist: Option<&Stoplist>,
+    ctx: &RunContext,
 ) -> Result<()> {
     let _ = env_logger::try_init();
 
-    // load query collection
-    let query_collection = load_collection(
-        &query_filepath,
-        &selection,
-        ReportType::Query,
-        allow_failed_sigpaths,
-    )?;
-
-    let common_scaled = match scaled {
-        Some(s) => s,
-        None => {
-            let s = *query_collection.max_scaled().expect("no records!?");
-            eprintln!(
-                "Setting scaled={} based on max scaled in query collection",
-                s
-            );
-            s
-        }
-    };
+    if per_query_scaled && scaled.is_some() {
+        bail!("--per-query-scaled cannot be combined with --scaled");
+    }
 
-    let mut against_selection = selection;
-    against_selection.set_scaled(common_scaled);
+    // load lineages first, so that bad taxonomy files fail fast
+    let summarizer = taxonomy.map(TaxSummarizer::load).transpose()?;
 
-    let threshold_hashes: u64 = {
-        let x = threshold_bp as u64 / common_scaled as u64;
-        if x > 0 {
-            x as u64
-        } else {
-            1
-        }
+    // load query collection
+    let (query_collection, selection) = CollectionSource::Path(query_filepath.clone())
+        .load_and_complete_selection(selection, ReportType::Query, allow_failed_sigpaths, ctx)?;
+
+    output_names.check_existing(&query_collection, true, save_matches, save_unassigned)?;
+
+    // load against collection; with per-query scaled, at its own max
+    // scaled rather than the queries'.
+    let (against_collection, common_scaled) = if per_query_scaled {
+        let against_selection = without_abundance_requirement(&selection);
+        let against_collection = load_collection(
+            &against_filepath,
+            &against_selection,
+            ReportType::Against,
+            allow_failed_sigpaths,
+            ctx,
+        )?;
+        let (against_collection, against_scaled) =
+            select_at_max_scaled(against_collection, &against_selection)?;
+        eprintln!(
+            "Gathering each query at scaled={} (the max scaled in the search collection), or its own scaled if higher",
+            against_scaled
+        );
+        (against_collection, against_scaled)
+    } else {
+        let common_scaled = match scaled {
+            Some(s) => s,
+            None => {
+                let s = *query_collection.max_scaled().expect("no records!?");
+                eprintln!(
+                    "Setting scaled={} based on max scaled in query collection",
+                    s
+                );
+                s
+            }
+        };
+
+        let mut against_selection = without_abundance_requirement(&selection);
+        against_selection.set_scaled(common_scaled);
+        let against_collection = load_collection(
+            &against_filepath,
+            &against_selection,
+            ReportType::Against,
+            allow_failed_sigpaths,
+            ctx,
+        )?;
+        (against_collection, common_scaled)
     };
 
-    println!("threshold overlap: {} {}", threshold_hashes, threshold_bp);
-
-    // load against collection
-    let against_collection = load_collection(
-        &against_filepath,
-        &against_selection,
-        ReportType::Against,
-        allow_failed_sigpaths,
-    )?;
-
-    let against_sketches = against_collection.load_sketches()?;
-
-    let (n_processed, skipped_paths, failed_paths) = fastmultigather_obj(
-        &query_collection,
-        &against_sketches,
-        save_matches,
-        output_path,
-        threshold_hashes,
-        common_scaled,
-        create_empty_results,
-    )?;
+    if threshold.is_per_query() {
+        println!("threshold overlap: {}, per query", threshold);
+    } else {
+        println!(
+            "threshold overlap: {} hashes ({})",
+            threshold.hashes(common_scaled, 0),
+            threshold
+        );
+    }
 
+    let against_sketches = ctx.time(Stage::Loading, || against_collection.load_sketches())?;
+
+    let (n_processed, skipped_paths, failed_paths) = ctx.time(Stage::Comparison, || {
+        fastmultigather_obj(
+            &query_collection,
+            &against_sketches,
+            save_matches,
+            save_unassigned,
+            output_path,
+            threshold,
+            common_scaled,
+            create_empty_results,
+            summarizer,
+            &output_names,
+            shared_prefetch,
+            &gather_options,
+            columns,
+            stoplist,
+            ctx,
+        )
+    })?;
+
+    ctx.record_processed(n_processed);
+    stoplist::report(stoplist);
     println!("DONE. Processed {} queries total.", n_processed);
 
     if skipped_paths > 0 {
@@ -112,21 +403,33 @@ pub fn fastmultigather(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn fastmultigather_obj(
     query_collection: &MultiCollection,
-    against: &Vec<SmallSignature>,
+    against: &[SmallSignature],
     save_matches: bool,
+    save_unassigned: bool,
     output_path: Option<String>,
-    threshold_hashes: u64,
+    threshold: GatherThreshold,
     common_scaled: u32,
     create_empty_results: bool,
+    summarizer: Option<TaxSummarizer>,
+    output_names: &QueryOutputNames,
+    shared_prefetch: bool,
+    gather_options: &GatherOptions,
+    columns: ColumnSelection,
+    stoplist: Option<&Stoplist>,
+    ctx: &RunContext,
 ) -> Result<(usize, usize, usize)> {
+    let shared_prefetch = shared_prefetch.then(|| SharedPrefetch::new(against, common_scaled));
+
     // set up a multi-producer, single-consumer channel.
     let (send, recv) =
         std::sync::mpsc::sync_channel::<BranchwaterGatherResult>(rayon::current_num_threads());
 
     // spawn a thread that is dedicated to printing to a buffered output
-    let gather_out_thrd = csvwriter_thread(recv, output_path);
+    let sourmash_compat = columns.sourmash_compat();
+    let gather_out_thrd = gather_csvwriter_thread(recv, output_path, columns, summarizer, ctx)?;
 
     // Iterate over all queries => do prefetch and gather!
     let processed_queries = AtomicUsize::new(0);
@@ -152,96 +455,129 @@ pub(crate) fn fastmultigather_obj(
                 let query_filename = query_sig.filename();
                 let query_name = query_sig.name();
                 let query_md5 = record.md5().clone();
+                let output_path = |default: &str, suffix: &str| {
+                    output_names
+                        .path(&query_name, &query_md5, default, suffix)
+                        .map_err(|e| {
+                            eprintln!("Error creating output directory: {}", e);
+                        })
+                        .ok()
+                };
 
                 let query_mh: KmerMinHash = query_sig.try_into().expect("cannot get sketch");
-
-                // CTB refactor
+                // compare at the against scaled, or the query's own if higher.
+                let mut query_mh = downsample_query(query_mh, common_scaled)
+                    .expect("cannot downsample query sketch");
+                stoplist::apply(stoplist, &mut query_mh);
                 let query_scaled = query_mh.scaled();
-                let query_ksize = query_mh.ksize().try_into().unwrap();
-                let query_hash_function = query_mh.hash_function().clone();
-                let query_seed = query_mh.seed();
-                let query_num = query_mh.num();
-
-                let mut matching_hashes = if save_matches { Some(Vec::new()) } else { None };
-                let matchlist: BinaryHeap<PrefetchResult> = against
-                    .iter()
-                    .filter_map(|against| {
-                        let mut mm: Option<PrefetchResult> = None;
-                        if let Ok(overlap) = against.minhash.count_common(&query_mh, false) {
-                            if overlap >= threshold_hashes {
-                                if save_matches {
-                                    if let Ok(intersection) =
-                                        against.minhash.intersection(&query_mh)
-                                    {
-                                        matching_hashes.as_mut().unwrap().extend(intersection.0);
+                let threshold_hashes = threshold.hashes(query_scaled, query_mh.size());
+
+                let save_hashes = save_matches || save_unassigned;
+                let orig_query_mh = save_hashes.then(|| query_mh.clone());
+                let mut matching_hashes = if save_hashes { Some(Vec::new()) } else { None };
+                let matchlist: BinaryHeap<PrefetchResult> = if let Some(shared) = &shared_prefetch {
+                    // query hashes at a higher scaled are still found in
+                    // the index, but the matches must be downsampled.
+                    shared
+                        .prefetch(
+                            &query_mh,
+                            against,
+                            threshold_hashes,
+                            matching_hashes.as_mut(),
+                        )
+                        .into_iter()
+                        .map(|mut result| {
+                            if result.minhash.scaled() != query_scaled {
+                                result.minhash = Cow::Owned(
+                                    result
+                                        .minhash
+                                        .into_owned()
+                                        .downsample_scaled(query_scaled)
+                                        .expect("cannot downsample against sketch"),
+                                );
+                            }
+                            result
+                        })
+                        .collect()
+                } else {
+                    against
+                        .iter()
+                        .filter_map(|against| {
+                            let against_mh = if against.minhash.scaled() == query_scaled {
+                                Cow::Borrowed(&against.minhash)
+                            } else {
+                                Cow::Owned(
+                                    against
+                                        .minhash
+                                        .clone()
+                                        .downsample_scaled(query_scaled)
+                                        .ok()?,
+                                )
+                            };
+                            let mut mm: Option<PrefetchResult> = None;
+                            if let Ok(overlap) = against_mh.count_common(&query_mh, false) {
+                                if overlap >= threshold_hashes {
+                                    if save_hashes {
+                                        if let Ok(intersection) = against_mh.intersection(&query_mh)
+                                        {
+                                            matching_hashes
+                                                .as_mut()
+                                                .unwrap()
+                                                .extend(intersection.0);
+                                        }
                                     }
+                                    let result = PrefetchResult {
+                                        name: against.name.clone(),
+                                        md5sum: against.md5sum.clone(),
+                                        minhash: against_mh,
+                                        location: against.location.clone(),
+                                        overlap,
+                                    };
+                                    mm = Some(result);
                                 }
-                                let result = PrefetchResult {
-                                    name: against.name.clone(),
-                                    md5sum: against.md5sum.clone(),
-                                    minhash: against.minhash.clone(),
-                                    location: against.location.clone(),
-                                    overlap,
-                                };
-                                mm = Some(result);
                             }
-                        }
-                        mm
-                    })
-                    .collect();
+                            mm
+                        })
+                        .collect()
+                };
 
                 if !matchlist.is_empty() || create_empty_results {
-                    let prefetch_output = format!("{}.prefetch.csv", location);
-
                     // Save initial list of matches to prefetch output
-                    write_prefetch(
-                        query_filename.clone(),
-                        query_name.clone(),
-                        query_md5,
-                        Some(prefetch_output),
-                        &matchlist,
-                    )
-                    .ok();
+                    if let Some(prefetch_output) = output_path(location, ".prefetch.csv") {
+                        let query = PrefetchQuery {
+                            filename: &query_filename,
+                            name: &query_name,
+                            md5: &query_md5,
+                            minhash: &query_mh,
+                        };
+                        write_prefetch(&query, Some(prefetch_output), &matchlist, sourmash_compat)
+                            .ok();
+                    }
 
                     // Now, do the gather!
                     consume_query_by_gather(
-                        query_name,
+                        query_name.clone(),
                         query_filename,
                         query_mh,
-                        common_scaled,
+                        query_scaled,
                         matchlist,
                         threshold_hashes,
+                        gather_options,
                         Some(send.clone()),
                     )
                     .ok();
 
-                    // Save matching hashes to .sig file if save_matches is true
-                    if save_matches {
-                        if let Some(hashes) = matching_hashes {
-                            let sig_filename = format!("{}.matches.sig", name);
-                            if let Ok(mut file) = File::create(&sig_filename) {
-                                let unique_hashes: HashSet<u64> = hashes.into_iter().collect();
-                                let mut new_mh = KmerMinHash::new(
-                                    query_scaled,
-                                    query_ksize,
-                                    query_hash_function,
-                                    query_seed,
-                                    false,
-                                    query_num,
-                                );
-                                new_mh
-                                    .add_many(&unique_hashes.into_iter().collect::<Vec<_>>())
-                                    .ok();
-                                let mut signature = Signature::default();
-                                signature.push(Sketch::MinHash(new_mh));
-                                signature.set_filename(&name);
-                                if let Err(e) = signature.to_writer(&mut file) {
-                                    eprintln!("Error writing signature file: {}", e);
-                                }
-                            } else {
-                                eprintln!("Error creating signature file: {}", sig_filename);
-                            }
-                        }
+                    // Save matched and/or unassigned hashes to .sig files
+                    if let (Some(orig_query_mh), Some(hashes)) = (orig_query_mh, matching_hashes) {
+                        save_query_hashes(
+                            output_names,
+                            &query_name,
+                            &query_md5,
+                            &orig_query_mh,
+                            hashes,
+                            save_matches,
+                            save_unassigned,
+                        );
                     }
                 } else {
                     println!("No matches to '{}'", location);
@@ -253,15 +589,22 @@ pub(crate) fn fastmultigather_obj(
                     "WARNING: no compatible sketches in path '{}'",
                     record.internal_location()
                 );
+                ctx.record_skipped(
+                    record.internal_location().as_str(),
+                    "no compatible sketches",
+                );
                 let _ = skipped_paths.fetch_add(1, atomic::Ordering::SeqCst);
             }
         }
     });
 
     drop(send);
-    gather_out_thrd
+    let summarizer = gather_out_thrd
         .join()
         .expect("unable to join CSV writing thread!?");
+    if let Some(summarizer) = summarizer {
+        ctx.time(Stage::Writing, || summarizer.write_outputs())?;
+    }
 
     Ok((
         processed_queries.into_inner(),
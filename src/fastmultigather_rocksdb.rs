@@ -5,74 +5,152 @@ use rayon::prelude::*;
 use sourmash::index::revindex::{RevIndex, RevIndexOps};
 use sourmash::prelude::*;
 use sourmash::signature::SigsTrait;
+use std::borrow::Cow;
+use std::collections::{BinaryHeap, HashMap};
 use std::sync::atomic;
 use std::sync::atomic::AtomicUsize;
 
+use sourmash::collection::Collection;
+use sourmash::manifest::Record;
 use sourmash::sketch::minhash::KmerMinHash;
 use sourmash::storage::SigStore;
 
+use crate::errors::BranchwaterError;
+use crate::fastmultigather::{save_query_hashes, QueryOutputNames};
+use crate::utils::aliases::NameAliases;
+use crate::utils::columns::ColumnSelection;
+use crate::utils::profile::Stage;
+use crate::utils::stoplist::{self, Stoplist};
+use crate::utils::taxonomy::{gather_csvwriter_thread, TaxSummarizer, TaxonomyOptions};
 use crate::utils::{
-    csvwriter_thread, is_revindex_database, load_collection, BranchwaterGatherResult,
-    MultiCollection, ReportType,
+    consume_query_by_gather, is_revindex_database, load_collection, revindex_selection,
+    BranchwaterGatherResult, GatherOptions, GatherThreshold, MultiCollection, PrefetchResult,
+    ReportType, RunContext,
 };
 
+/// The sketches of a companion collection, by md5, for gathering in
+/// memory against the candidates found with a RocksDB index.
+pub(crate) struct RefineSketches<'a> {
+    sketches: HashMap<&'a str, (&'a Collection, &'a Record)>,
+}
+
+impl<'a> RefineSketches<'a> {
+    pub(crate) fn new(collection: &'a MultiCollection) -> Self {
+        let sketches: HashMap<_, _> = collection
+            .item_iter()
+            .map(|(coll, _idx, record)| (record.md5().as_str(), (coll, record)))
+            .collect();
+        eprintln!(
+            "Refining gather results in memory, with {} sketches from the companion collection.",
+            sketches.len()
+        );
+        RefineSketches { sketches }
+    }
+
+    /// Load the sketch with `md5`, downsampled to `scaled`.
+    fn load(&self, md5: &str, scaled: u32) -> Result<(KmerMinHash, &'a Record)> {
+        let Some((coll, record)) = self.sketches.get(md5) else {
+            bail!(
+                "match with md5 {} is not in the --refine-from collection",
+                md5
+            );
+        };
+        let sig = coll.sig_from_record(record)?;
+        let mh: KmerMinHash = sig.try_into()?;
+        Ok((mh.downsample_scaled(scaled)?, record))
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn fastmultigather_rocksdb(
     queries_file: String,
     index: PathBuf,
     selection: Selection,
-    threshold_bp: u32,
+    threshold: GatherThreshold,
     output: Option<String>,
     allow_failed_sigpaths: bool,
+    taxonomy: Option<TaxonomyOptions>,
+    gather_options: GatherOptions,
+    columns: ColumnSelection,
+    output_names: QueryOutputNames,
+    save_matches: bool,
+    save_unassigned: bool,
+    refine_from: Option<String>,
+    stoplist: Option<&Stoplist>,
+    ctx: &RunContext,
 ) -> Result<()> {
+    // load lineages first, so that bad taxonomy files fail fast
+    let summarizer = taxonomy.map(TaxSummarizer::load).transpose()?;
+
     if !is_revindex_database(&index) {
-        bail!("'{}' is not a valid RevIndex database", index);
+        bail!(BranchwaterError::InvalidRocksDB(format!(
+            "'{}' is not a valid RevIndex database",
+            index
+        )));
     }
+    let aliases = NameAliases::open(&index)?;
+
     // Open database once
-    let db = match RevIndex::open(index, true, None) {
+    let db = match ctx.time(Stage::Loading, || RevIndex::open(index, true, None)) {
         Ok(db) => db,
         Err(e) => {
-            return Err(anyhow::anyhow!(
+            bail!(BranchwaterError::InvalidRocksDB(format!(
                 "cannot open RocksDB database. Error is: {}",
                 e
-            ))
+            )))
         }
     };
     println!("Loaded DB");
 
-    // grab scaled from the database.
-    let (_, max_db_scaled) = db
-        .collection()
-        .min_max_scaled()
-        .expect("no records in db?!");
-
-    let selection_scaled: u32 = match selection.scaled() {
-        Some(scaled) => {
-            if *max_db_scaled > scaled {
-                return Err(anyhow::anyhow!(
-                    "Error: database scaled is higher than requested scaled"
-                ));
-            }
-            scaled
-        }
-        None => {
-            eprintln!("Setting scaled={} from the database", *max_db_scaled);
-            *max_db_scaled
-        }
-    };
-
-    let mut set_selection = selection;
-    set_selection.set_scaled(selection_scaled);
+    // ksize, moltype, and scaled must match the database.
+    let set_selection = revindex_selection(&db, selection)?;
 
     let query_collection = load_collection(
         &queries_file,
         &set_selection,
         ReportType::Query,
         allow_failed_sigpaths,
+        ctx,
     )?;
 
-    let (n_processed, skipped_paths, failed_paths) =
-        fastmultigather_rocksdb_obj(&query_collection, &db, &set_selection, threshold_bp, output)?;
+    output_names.check_existing(&query_collection, false, save_matches, save_unassigned)?;
+
+    // the companion collection only needs to hold sketches in the index.
+    let refine_collection = refine_from
+        .map(|path| {
+            load_collection(
+                &path,
+                &set_selection,
+                ReportType::Against,
+                allow_failed_sigpaths,
+                ctx,
+            )
+        })
+        .transpose()?;
+    let refine = refine_collection.as_ref().map(RefineSketches::new);
+
+    let (n_processed, skipped_paths, failed_paths) = ctx.time(Stage::Comparison, || {
+        fastmultigather_rocksdb_obj(
+            &query_collection,
+            &db,
+            &set_selection,
+            threshold,
+            output,
+            summarizer,
+            &gather_options,
+            columns,
+            &output_names,
+            save_matches,
+            save_unassigned,
+            refine.as_ref(),
+            aliases.as_ref(),
+            stoplist,
+            ctx,
+        )
+    })?;
 
+    ctx.record_processed(n_processed);
+    stoplist::report(stoplist);
     println!("DONE. Processed {} queries total.", n_processed);
 
     if skipped_paths > 0 {
@@ -91,19 +169,182 @@ pub fn fastmultigather_rocksdb(
     Ok(())
 }
 
+/// Gather one query sketch against the database, returning the matches
+/// that pass `threshold` and the gather stopping criteria. If
+/// `matched_hashes` is given, the query hashes in those matches are added
+/// to it, from the matching sketches in the index.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn revindex_gather(
+    db: &RevIndex,
+    query_mh: &KmerMinHash,
+    query_filename: &str,
+    query_name: &str,
+    query_md5: &str,
+    selection: &Selection,
+    threshold: GatherThreshold,
+    gather_options: &GatherOptions,
+    matched_hashes: Option<&mut Vec<u64>>,
+) -> Result<Vec<BranchwaterGatherResult>> {
+    let scaled = selection.scaled().expect("scaled is not set!?");
+    let ksize = selection.ksize().expect("ksize not set!?");
+
+    let threshold = threshold.raw_hashes(scaled, query_mh.size());
+    // Gather!
+    let (counter, query_colors, hash_to_color) = db.prepare_gather_counters(query_mh);
+
+    let matches = db.gather(
+        counter,
+        query_colors,
+        hash_to_color,
+        threshold as usize,
+        query_mh,
+        Some(selection.clone()),
+    )?;
+
+    // sourmash runs the whole gather; apply the stopping criteria after.
+    let n_kept = matches
+        .iter()
+        .enumerate()
+        .take_while(|(rank, m)| gather_options.keep(*rank, m.max_containment_ani()))
+        .count();
+    let matches = &matches[..n_kept];
+
+    if let Some(matched_hashes) = matched_hashes {
+        for match_ in matches {
+            let match_sig = match_.get_match();
+            let Some(match_mh) = match_sig.minhash() else {
+                bail!("no sketch for match '{}' in database", match_.name());
+            };
+            let match_mh = match_mh.clone().downsample_scaled(query_mh.scaled())?;
+            matched_hashes.extend(match_mh.intersection(query_mh)?.0);
+        }
+    }
+
+    let results = matches
+        .iter()
+        .map(|match_| BranchwaterGatherResult {
+            intersect_bp: match_.intersect_bp(),
+            intersect_hashes: match_.intersect_bp() / query_mh.scaled() as u64,
+            f_orig_query: match_.f_orig_query(),
+            f_match: match_.f_match(),
+            f_unique_to_query: match_.f_unique_to_query(),
+            f_unique_weighted: match_.f_unique_weighted(),
+            average_abund: match_.average_abund(),
+            median_abund: match_.median_abund(),
+            std_abund: match_.std_abund(),
+            match_filename: match_.filename().clone(),
+            match_name: match_.name().clone(),
+            match_md5: match_.md5().clone(),
+            f_match_orig: match_.f_match_orig(),
+            unique_intersect_bp: match_.unique_intersect_bp(),
+            gather_result_rank: match_.gather_result_rank(),
+            remaining_bp: match_.remaining_bp(),
+            query_filename: query_filename.to_string(),
+            query_name: query_name.to_string(),
+            query_md5: query_md5.to_string(),
+            query_bp: query_mh.n_unique_kmers(),
+            ksize: ksize as u16,
+            moltype: query_mh.hash_function().to_string(),
+            scaled: query_mh.scaled(),
+            query_n_hashes: query_mh.size() as u64,
+            query_abundance: query_mh.track_abundance(),
+            query_containment_ani: match_.query_containment_ani(),
+            match_containment_ani: match_.match_containment_ani(),
+            average_containment_ani: match_.average_containment_ani(),
+            max_containment_ani: match_.max_containment_ani(),
+            n_unique_weighted_found: match_.n_unique_weighted_found(),
+            sum_weighted_found: match_.sum_weighted_found(),
+            total_weighted_hashes: match_.total_weighted_hashes(),
+
+            query_containment_ani_ci_low: match_.query_containment_ani_ci_low(),
+            query_containment_ani_ci_high: match_.query_containment_ani_ci_high(),
+            match_containment_ani_ci_low: match_.match_containment_ani_ci_low(),
+            match_containment_ani_ci_high: match_.match_containment_ani_ci_high(),
+        })
+        .collect();
+
+    Ok(results)
+}
+
+/// Gather one query sketch in two stages: find the candidate matches with
+/// the index, then run the in-memory gather against only those, loaded
+/// from `refine`. This gives the same results as `revindex_gather`, but
+/// supports everything the in-memory gather does, such as
+/// `--abundance-weighted`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn refine_gather(
+    db: &RevIndex,
+    refine: &RefineSketches,
+    query_mh: &KmerMinHash,
+    query_filename: &str,
+    query_name: &str,
+    threshold: GatherThreshold,
+    gather_options: &GatherOptions,
+    mut matched_hashes: Option<&mut Vec<u64>>,
+) -> Result<Vec<BranchwaterGatherResult>> {
+    let scaled = query_mh.scaled();
+    let threshold_hashes = threshold.hashes(scaled, query_mh.size());
+
+    let mut matchlist = BinaryHeap::new();
+    // most_common is sorted by overlap, largest first.
+    for (dataset_id, overlap) in db.counter_for_query(query_mh).most_common() {
+        let overlap = overlap as u64;
+        if overlap < threshold_hashes {
+            break;
+        }
+        let md5 = db.collection().record_for_dataset(dataset_id)?.md5();
+        let (match_mh, record) = refine.load(md5, scaled)?;
+        if let Some(matched_hashes) = matched_hashes.as_deref_mut() {
+            matched_hashes.extend(match_mh.intersection(query_mh)?.0);
+        }
+        matchlist.push(PrefetchResult {
+            name: record.name().as_str().into(),
+            md5sum: record.md5().as_str().into(),
+            location: record.internal_location().to_string(),
+            minhash: Cow::Owned(match_mh),
+            overlap,
+        });
+    }
+
+    // each candidate is reported at most once, so this never blocks.
+    let (send, recv) = std::sync::mpsc::sync_channel(matchlist.len());
+    consume_query_by_gather(
+        query_name.to_string(),
+        query_filename.to_string(),
+        query_mh.clone(),
+        scaled,
+        matchlist,
+        threshold_hashes,
+        gather_options,
+        Some(send),
+    )?;
+    Ok(recv.into_iter().collect())
+}
+
+#[allow(clippy::too_many_arguments, clippy::result_large_err)]
 pub(crate) fn fastmultigather_rocksdb_obj(
     query_collection: &MultiCollection,
     db: &RevIndex,
     selection: &Selection,
-    threshold_bp: u32,
+    threshold: GatherThreshold,
     output: Option<String>,
+    summarizer: Option<TaxSummarizer>,
+    gather_options: &GatherOptions,
+    columns: ColumnSelection,
+    output_names: &QueryOutputNames,
+    save_matches: bool,
+    save_unassigned: bool,
+    refine: Option<&RefineSketches>,
+    aliases: Option<&NameAliases>,
+    stoplist: Option<&Stoplist>,
+    ctx: &RunContext,
 ) -> Result<(usize, usize, usize)> {
     // set up a multi-producer, single-consumer channel.
     let (send, recv) =
         std::sync::mpsc::sync_channel::<BranchwaterGatherResult>(rayon::current_num_threads());
 
     // & spawn a thread that is dedicated to printing to a buffered output
-    let thrd = csvwriter_thread(recv, output);
+    let thrd = gather_csvwriter_thread(recv, output, columns, summarizer, ctx)?;
 
     //
     // Main loop: iterate (in parallel) over all search signature paths,
@@ -119,9 +360,6 @@ pub(crate) fn fastmultigather_rocksdb_obj(
     let send = query_collection
         .par_iter()
         .filter_map(|(coll, _idx, record)| {
-            let threshold = threshold_bp / selection.scaled().expect("scaled is not set!?");
-            let ksize = selection.ksize().expect("ksize not set!?");
-
             // query downsampling happens here
             match coll.sig_from_record(record) {
                 Ok(query_sig) => {
@@ -130,74 +368,67 @@ pub(crate) fn fastmultigather_rocksdb_obj(
                     let query_md5 = query_sig.md5sum();
 
                     let mut results = vec![];
-                    if let Ok(query_mh) = <SigStore as TryInto<KmerMinHash>>::try_into(query_sig) {
+                    if let Ok(mut query_mh) =
+                        <SigStore as TryInto<KmerMinHash>>::try_into(query_sig)
+                    {
+                        stoplist::apply(stoplist, &mut query_mh);
                         let _ = processed_sigs.fetch_add(1, atomic::Ordering::SeqCst);
-                        // Gather!
-                        let (counter, query_colors, hash_to_color) =
-                            db.prepare_gather_counters(&query_mh);
-
-                        let matches = db.gather(
-                            counter,
-                            query_colors,
-                            hash_to_color,
-                            threshold as usize,
-                            &query_mh,
-                            Some(selection.clone()),
-                        );
-                        if let Ok(matches) = matches {
-                            for match_ in &matches {
-                                results.push(BranchwaterGatherResult {
-                                    intersect_bp: match_.intersect_bp(),
-                                    f_orig_query: match_.f_orig_query(),
-                                    f_match: match_.f_match(),
-                                    f_unique_to_query: match_.f_unique_to_query(),
-                                    f_unique_weighted: match_.f_unique_weighted(),
-                                    average_abund: match_.average_abund(),
-                                    median_abund: match_.median_abund(),
-                                    std_abund: match_.std_abund(),
-                                    match_filename: match_.filename().clone(),
-                                    match_name: match_.name().clone(),
-                                    match_md5: match_.md5().clone(),
-                                    f_match_orig: match_.f_match_orig(),
-                                    unique_intersect_bp: match_.unique_intersect_bp(),
-                                    gather_result_rank: match_.gather_result_rank(),
-                                    remaining_bp: match_.remaining_bp(),
-                                    query_filename: query_filename.clone(),
-                                    query_name: query_name.clone(),
-                                    query_md5: query_md5.clone(),
-                                    query_bp: query_mh.n_unique_kmers(),
-                                    ksize: ksize as u16,
-                                    moltype: query_mh.hash_function().to_string(),
-                                    scaled: query_mh.scaled(),
-                                    query_n_hashes: query_mh.size() as u64,
-                                    query_abundance: query_mh.track_abundance(),
-                                    query_containment_ani: match_.query_containment_ani(),
-                                    match_containment_ani: match_.match_containment_ani(),
-                                    average_containment_ani: match_.average_containment_ani(),
-                                    max_containment_ani: match_.max_containment_ani(),
-                                    n_unique_weighted_found: match_.n_unique_weighted_found(),
-                                    sum_weighted_found: match_.sum_weighted_found(),
-                                    total_weighted_hashes: match_.total_weighted_hashes(),
-
-                                    query_containment_ani_ci_low: match_
-                                        .query_containment_ani_ci_low(),
-                                    query_containment_ani_ci_high: match_
-                                        .query_containment_ani_ci_high(),
-                                    match_containment_ani_ci_low: match_
-                                        .match_containment_ani_ci_low(),
-                                    match_containment_ani_ci_high: match_
-                                        .match_containment_ani_ci_high(),
-                                });
+                        let save_hashes = save_matches || save_unassigned;
+                        let mut matched_hashes = if save_hashes { Some(Vec::new()) } else { None };
+                        let gathered = match refine {
+                            Some(refine) => refine_gather(
+                                db,
+                                refine,
+                                &query_mh,
+                                &query_filename,
+                                &query_name,
+                                threshold,
+                                gather_options,
+                                matched_hashes.as_mut(),
+                            ),
+                            None => revindex_gather(
+                                db,
+                                &query_mh,
+                                &query_filename,
+                                &query_name,
+                                &query_md5,
+                                selection,
+                                threshold,
+                                gather_options,
+                                matched_hashes.as_mut(),
+                            ),
+                        };
+                        match gathered {
+                            Ok(matches) => {
+                                results = matches;
+                                if let Some(aliases) = aliases {
+                                    for r in results.iter_mut() {
+                                        r.match_name = aliases.get(&r.match_name).to_string();
+                                    }
+                                }
+                                if let Some(hashes) = matched_hashes {
+                                    save_query_hashes(
+                                        output_names,
+                                        &query_name,
+                                        record.md5(),
+                                        &query_mh,
+                                        hashes,
+                                        save_matches,
+                                        save_unassigned,
+                                    );
+                                }
+                            }
+                            Err(e) => {
+                                eprintln!("Error gathering matches: {:?}", e);
+                                let _ = failed_gathers.fetch_add(1, atomic::Ordering::SeqCst);
                             }
-                        } else {
-                            eprintln!("Error gathering matches: {:?}", matches.err());
-                            let _ = failed_gathers.fetch_add(1, atomic::Ordering::SeqCst);
                         }
                     } else {
                         eprintln!(
                             "WARNING: no compatible sketches in path '{}'",
                             query_filename
                         );
+                        ctx.record_skipped(&query_filename, "no compatible sketches");
                         let _ = skipped_paths.fetch_add(1, atomic::Ordering::SeqCst);
                     }
 
@@ -209,6 +440,7 @@ pub(crate) fn fastmultigather_rocksdb_obj(
                 }
                 Err(err) => {
                     eprintln!("Error loading sketch: {}", err);
+                    ctx.record_failed(record.internal_location().as_str(), &err);
                     let _ = failed_paths.fetch_add(1, atomic::Ordering::SeqCst);
                     None
                 }
@@ -219,7 +451,10 @@ pub(crate) fn fastmultigather_rocksdb_obj(
 
     // do some cleanup and error handling -
     send.expect("Unable to send internal data");
-    thrd.join().expect("Unable to join CSV writing thread.");
+    let summarizer = thrd.join().expect("Unable to join CSV writing thread.");
+    if let Some(summarizer) = summarizer {
+        ctx.time(Stage::Writing, || summarizer.write_outputs())?;
+    }
 
     // done!
     let n_processed: usize = processed_sigs.fetch_max(0, atomic::Ordering::SeqCst);
@@ -0,0 +1,304 @@
+/// fastprefetch: find all sketches that overlap each query by at least a
+/// threshold, without running gather.
+use anyhow::Result;
+use camino::Utf8PathBuf as PathBuf;
+use rayon::prelude::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use sourmash::index::revindex::{RevIndex, RevIndexOps};
+use sourmash::selection::Selection;
+use sourmash::signature::SigsTrait;
+
+use crate::errors::BranchwaterError;
+use crate::fastgather::{prefetch_sketches, query_sketch};
+use crate::utils::aliases::NameAliases;
+use crate::utils::profile::Stage;
+use crate::utils::stoplist::{self, Stoplist};
+use crate::utils::{
+    is_revindex_database, load_collection, prefetch_writer, revindex_selection,
+    write_prefetch_header, write_prefetch_row, write_prefetch_rows, CollectionSource, Flusher,
+    GatherThreshold, MultiCollection, PrefetchMatch, PrefetchQuery, PrefetchWriter, ReportType,
+    RunContext,
+};
+
+/// The prefetch CSV, shared by the threads searching each query.
+struct PrefetchOutput {
+    writer: Mutex<(PrefetchWriter, Flusher)>,
+    rows: AtomicUsize,
+}
+
+impl PrefetchOutput {
+    fn open(output: Option<String>, sourmash_compat: bool, ctx: &RunContext) -> Result<Self> {
+        let flusher = Flusher::new(ctx.flush_policy(output.as_deref()));
+        Ok(PrefetchOutput {
+            writer: Mutex::new((prefetch_writer(output, sourmash_compat)?, flusher)),
+            rows: AtomicUsize::new(0),
+        })
+    }
+
+    /// Write rows with `write`, which returns how many it wrote.
+    fn write(&self, write: impl FnOnce(&mut PrefetchWriter) -> Result<usize>) {
+        let (writer, flusher) = &mut *self.writer.lock().unwrap();
+        match write(writer) {
+            Ok(n) => {
+                self.rows.fetch_add(n, Ordering::SeqCst);
+                if flusher.wrote(n) {
+                    if let Err(e) = writer.flush() {
+                        eprintln!("Error flushing prefetch output: {}", e);
+                    }
+                }
+            }
+            Err(e) => eprintln!("Error writing prefetch output: {}", e),
+        }
+    }
+
+    /// Commit the output, writing a header if there were no matches, and
+    /// return the number of rows written.
+    fn finish(self, ctx: &RunContext) -> Result<usize> {
+        let (mut writer, _) = self.writer.into_inner().unwrap();
+        let rows = self.rows.into_inner();
+        if rows == 0 {
+            write_prefetch_header(&mut writer)?;
+        }
+        writer.finish()?;
+        ctx.record_rows(rows);
+        Ok(rows)
+    }
+}
+
+fn report_prefetch(n_processed: usize, n_rows: usize, skipped_paths: usize, ctx: &RunContext) {
+    ctx.record_processed(n_processed);
+    eprintln!(
+        "DONE. Prefetched {} queries; found {} matches.",
+        n_processed, n_rows
+    );
+    if skipped_paths > 0 {
+        eprintln!(
+            "WARNING: skipped {} query paths - no compatible signatures.",
+            skipped_paths
+        );
+    }
+}
+
+/// Compare each query in `query_source` against every sketch in
+/// `against_source`, loaded into memory once, and write the matches that
+/// share at least `threshold` hashes to `output` as prefetch CSV, with the
+/// columns of `sourmash prefetch` if `sourmash_compat`.
+#[allow(clippy::too_many_arguments)]
+pub fn fastprefetch(
+    query_source: CollectionSource,
+    against_source: CollectionSource,
+    threshold: GatherThreshold,
+    selection: Selection,
+    output: Option<String>,
+    sourmash_compat: bool,
+    allow_failed_sigpaths: bool,
+    stoplist: Option<&Stoplist>,
+    ctx: &RunContext,
+) -> Result<()> {
+    let (query_collection, selection) = query_source.load_and_complete_selection(
+        selection,
+        ReportType::Query,
+        allow_failed_sigpaths,
+        ctx,
+    )?;
+
+    // all queries are compared at the same scaled.
+    let scaled = match selection.scaled() {
+        Some(s) => s,
+        None => {
+            let s = *query_collection.max_scaled().expect("no records!?");
+            eprintln!(
+                "Setting scaled={} based on max scaled in query collection",
+                s
+            );
+            s
+        }
+    };
+
+    let mut against_selection = selection;
+    against_selection.set_scaled(scaled);
+    let against_collection = against_source.load(
+        &against_selection,
+        ReportType::Against,
+        allow_failed_sigpaths,
+        ctx,
+    )?;
+    let against = ctx.time(Stage::Loading, || against_collection.load_sketches())?;
+
+    eprintln!("using threshold overlap: {}", threshold);
+    eprintln!(
+        "prefetching {} queries against {} sketches",
+        query_collection.len(),
+        against.len()
+    );
+
+    let out = PrefetchOutput::open(output, sourmash_compat, ctx)?;
+    let processed = AtomicUsize::new(0);
+    let skipped_paths = AtomicUsize::new(0);
+
+    ctx.time(Stage::Comparison, || {
+        query_collection
+            .par_iter()
+            .for_each(|(coll, _idx, record)| {
+                let Some(query_mh) = query_sketch(coll, record, scaled, stoplist, ctx) else {
+                    skipped_paths.fetch_add(1, Ordering::SeqCst);
+                    return;
+                };
+                processed.fetch_add(1, Ordering::SeqCst);
+
+                let threshold_hashes = threshold.hashes(scaled, query_mh.size());
+                let matchlist = prefetch_sketches(&query_mh, &against, threshold_hashes);
+                if !matchlist.is_empty() {
+                    let query = PrefetchQuery {
+                        filename: record.filename(),
+                        name: record.name(),
+                        md5: record.md5(),
+                        minhash: &query_mh,
+                    };
+                    out.write(|writer| write_prefetch_rows(writer, &query, &matchlist));
+                }
+            });
+    });
+
+    let n_rows = out.finish(ctx)?;
+    stoplist::report(stoplist);
+    report_prefetch(
+        processed.into_inner(),
+        n_rows,
+        skipped_paths.into_inner(),
+        ctx,
+    );
+    Ok(())
+}
+
+/// Like `fastprefetch`, but find matches with the inverted index in a
+/// RocksDB database rather than loading its sketches.
+#[allow(clippy::too_many_arguments)]
+pub fn fastprefetch_rocksdb(
+    queries_file: String,
+    index: PathBuf,
+    threshold: GatherThreshold,
+    selection: Selection,
+    output: Option<String>,
+    sourmash_compat: bool,
+    allow_failed_sigpaths: bool,
+    stoplist: Option<&Stoplist>,
+    ctx: &RunContext,
+) -> Result<()> {
+    if !is_revindex_database(&index) {
+        bail!(BranchwaterError::InvalidRocksDB(format!(
+            "'{}' is not a valid RevIndex database",
+            index
+        )));
+    }
+    let aliases = NameAliases::open(&index)?;
+    let db = match ctx.time(Stage::Loading, || RevIndex::open(index, true, None)) {
+        Ok(db) => db,
+        Err(e) => {
+            bail!(BranchwaterError::InvalidRocksDB(format!(
+                "cannot open RocksDB database. Error is: {}",
+                e
+            )))
+        }
+    };
+
+    // ksize, moltype, and scaled must match the database.
+    let selection = revindex_selection(&db, selection)?;
+    let scaled = selection.scaled().expect("scaled is not set!?");
+
+    let query_collection = load_collection(
+        &queries_file,
+        &selection,
+        ReportType::Query,
+        allow_failed_sigpaths,
+        ctx,
+    )?;
+    eprintln!("using threshold overlap: {}", threshold);
+
+    let out = PrefetchOutput::open(output, sourmash_compat, ctx)?;
+    let (n_processed, skipped_paths) = ctx.time(Stage::Comparison, || {
+        fastprefetch_rocksdb_obj(
+            &query_collection,
+            &db,
+            aliases.as_ref(),
+            scaled,
+            threshold,
+            &out,
+            stoplist,
+            ctx,
+        )
+    });
+
+    let n_rows = out.finish(ctx)?;
+    stoplist::report(stoplist);
+    report_prefetch(n_processed, n_rows, skipped_paths, ctx);
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn fastprefetch_rocksdb_obj(
+    query_collection: &MultiCollection,
+    db: &RevIndex,
+    aliases: Option<&NameAliases>,
+    scaled: u32,
+    threshold: GatherThreshold,
+    out: &PrefetchOutput,
+    stoplist: Option<&Stoplist>,
+    ctx: &RunContext,
+) -> (usize, usize) {
+    let processed = AtomicUsize::new(0);
+    let skipped_paths = AtomicUsize::new(0);
+
+    query_collection
+        .par_iter()
+        .for_each(|(coll, _idx, record)| {
+            let Some(query_mh) = query_sketch(coll, record, scaled, stoplist, ctx) else {
+                skipped_paths.fetch_add(1, Ordering::SeqCst);
+                return;
+            };
+            processed.fetch_add(1, Ordering::SeqCst);
+
+            let threshold_hashes = threshold.hashes(scaled, query_mh.size());
+            // most_common is sorted by overlap, largest first.
+            let matches: Vec<(u32, u64)> = db
+                .counter_for_query(&query_mh)
+                .most_common()
+                .into_iter()
+                .map(|(dataset_id, overlap)| (dataset_id, overlap as u64))
+                .take_while(|(_, overlap)| *overlap > 0 && *overlap >= threshold_hashes)
+                .collect();
+            if matches.is_empty() {
+                return;
+            }
+
+            let query = PrefetchQuery {
+                filename: record.filename(),
+                name: record.name(),
+                md5: record.md5(),
+                minhash: &query_mh,
+            };
+            out.write(|writer| {
+                for (dataset_id, overlap) in matches.iter() {
+                    let match_record = db.collection().record_for_dataset(*dataset_id)?;
+                    let match_name = match aliases {
+                        Some(aliases) => aliases.get(match_record.name()),
+                        None => match_record.name().as_str(),
+                    };
+                    let prefetch_match = PrefetchMatch {
+                        filename: match_record.internal_location().as_str(),
+                        name: match_name,
+                        md5: match_record.md5(),
+                        n_hashes: *match_record.n_hashes() as u64,
+                        overlap: *overlap,
+                        scaled,
+                    };
+                    write_prefetch_row(writer, &query, &prefetch_match)?;
+                }
+                Ok(matches.len())
+            });
+        });
+
+    (processed.into_inner(), skipped_paths.into_inner())
+}
@@ -0,0 +1,185 @@
+/// hash_lookup: report which datasets in a RocksDB index contain each hash.
+use anyhow::{anyhow, Context, Result};
+use camino::Utf8PathBuf as PathBuf;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use sourmash::encodings::HashFunctions;
+use sourmash::index::revindex::{RevIndex, RevIndexOps};
+use sourmash::selection::Selection;
+use sourmash::sketch::minhash::{max_hash_for_scaled, KmerMinHash};
+use sourmash::storage::SigStore;
+
+use crate::errors::BranchwaterError;
+use crate::utils::{
+    finish_csv, is_revindex_database, load_collection, open_stdout_or_file, ReportType, RunContext,
+};
+
+#[derive(Serialize)]
+struct HashLookupRow {
+    query_name: String,
+    hash: u64,
+    match_name: String,
+    match_md5: String,
+}
+
+/// Read hash values from a text file, one per line; blank lines are ignored.
+fn read_hashes(path: &str) -> Result<Vec<u64>> {
+    let file = File::open(path).with_context(|| format!("cannot open hashes file '{}'", path))?;
+
+    let mut hashes = vec![];
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let hash = line
+            .parse::<u64>()
+            .map_err(|_| anyhow!("invalid hash '{}' on line {} of '{}'", line, i + 1, path))?;
+        hashes.push(hash);
+    }
+    Ok(hashes)
+}
+
+/// Look up each hash in the database, returning one row per matching dataset.
+fn lookup_hashes(db: &RevIndex, query_name: &str, hashes: &[u64]) -> Vec<HashLookupRow> {
+    hashes
+        .par_iter()
+        .map(|hash| {
+            // a scaled=1 sketch holding just this hash; only the hash
+            // itself is used for the lookup.
+            let mut mh = KmerMinHash::new(1, 1, HashFunctions::Murmur64Dna, 42, false, 0);
+            mh.add_hash(*hash);
+
+            let mut dataset_ids: Vec<u32> = db
+                .counter_for_query(&mh)
+                .into_iter()
+                .map(|(dataset_id, _)| dataset_id)
+                .collect();
+            dataset_ids.sort_unstable();
+
+            dataset_ids
+                .into_iter()
+                .map(|dataset_id| {
+                    let record = db
+                        .collection()
+                        .record_for_dataset(dataset_id)
+                        .expect("dataset not found");
+                    HashLookupRow {
+                        query_name: query_name.to_string(),
+                        hash: *hash,
+                        match_name: record.name().clone(),
+                        match_md5: record.md5().clone(),
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+        .flatten()
+        .collect()
+}
+
+pub fn hash_lookup(
+    index: PathBuf,
+    hashes_path: Option<String>,
+    query_path: Option<String>,
+    selection: Selection,
+    output: Option<String>,
+    allow_failed_sigpaths: bool,
+) -> Result<()> {
+    if !is_revindex_database(&index) {
+        bail!(BranchwaterError::InvalidRocksDB(format!(
+            "'{}' is not a valid RevIndex database",
+            index
+        )));
+    }
+
+    let db = match RevIndex::open(index, true, None) {
+        Ok(db) => db,
+        Err(e) => {
+            bail!(BranchwaterError::InvalidRocksDB(format!(
+                "cannot open RocksDB database. Error is: {}",
+                e
+            )))
+        }
+    };
+
+    eprintln!("Loaded DB");
+
+    let (min_db_scaled, max_db_scaled) = db
+        .collection()
+        .min_max_scaled()
+        .expect("no records in db?!");
+
+    // collect (query name, hashes) pairs to look up.
+    let queries: Vec<(String, Vec<u64>)> = match (hashes_path, query_path) {
+        (Some(hashes_path), None) => {
+            let hashes = read_hashes(&hashes_path)?;
+            let max_hash = max_hash_for_scaled(*min_db_scaled);
+            let n_unreachable = hashes.iter().filter(|h| **h > max_hash).count();
+            if n_unreachable > 0 {
+                eprintln!(
+                    "WARNING: {} hashes are above the database max_hash (scaled={}) and cannot match.",
+                    n_unreachable, min_db_scaled
+                );
+            }
+            vec![(hashes_path, hashes)]
+        }
+        (None, Some(query_path)) => {
+            // hashes above the database max_hash can never match, so
+            // downsample the queries to the database scaled.
+            let mut selection = selection;
+            selection.set_scaled(*max_db_scaled);
+
+            let query_collection = load_collection(
+                &query_path,
+                &selection,
+                ReportType::Query,
+                allow_failed_sigpaths,
+                &RunContext::default(),
+            )?;
+
+            let mut queries = vec![];
+            for (coll, _idx, record) in query_collection.item_iter() {
+                let query_sig = coll.sig_from_record(record)?;
+                let query_name = query_sig.name();
+                let mut query_mh = <SigStore as TryInto<KmerMinHash>>::try_into(query_sig)?;
+                if query_mh.scaled() < *max_db_scaled {
+                    query_mh = query_mh.downsample_scaled(*max_db_scaled)?;
+                }
+                queries.push((query_name, query_mh.mins()));
+            }
+            queries
+        }
+        _ => bail!("please provide exactly one of a hashes file or a query"),
+    };
+
+    let mut writer = csv::Writer::from_writer(open_stdout_or_file(output));
+    let mut n_hashes = 0;
+    let mut n_found = 0;
+    for (query_name, hashes) in &queries {
+        let rows = lookup_hashes(&db, query_name, hashes);
+
+        n_hashes += hashes.len();
+        n_found += rows
+            .iter()
+            .map(|row| row.hash)
+            .collect::<HashSet<_>>()
+            .len();
+
+        for row in rows {
+            writer.serialize(row)?;
+        }
+    }
+    finish_csv(writer)?.commit()?;
+
+    eprintln!(
+        "DONE. Found {} of {} hashes in the database.",
+        n_found, n_hashes
+    );
+
+    Ok(())
+}
@@ -1,14 +1,109 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 
+use camino::{Utf8Path, Utf8PathBuf};
 use sourmash::index::revindex::RevIndex;
 use sourmash::index::revindex::RevIndexOps;
 use sourmash::prelude::*;
+use std::collections::HashMap;
+use std::fs::remove_dir_all;
 use std::path::Path;
 
+use sourmash::manifest::Record;
+
+use crate::rename::{load_mapping, Annotation, MappingKey};
+use crate::utils::abundances::write_abundances;
+use crate::utils::aliases::{normalize_name, ALIASES_FILE};
+use crate::utils::atomicfile::AtomicFile;
 use crate::utils::MultiCollection;
-use crate::utils::{load_collection, ReportType};
+use crate::utils::{finish_csv, is_revindex_database, load_collection, ReportType, RunContext};
 use sourmash::collection::{Collection, CollectionSet};
 
+/// How to give the sketches in an index clean names, reported by searches
+/// in place of their own: from a rename CSV, as for `rename`, and/or by
+/// stripping paths and file extensions.
+pub struct IndexNames {
+    normalize: bool,
+    mapping: Option<(MappingKey, HashMap<String, Annotation>)>,
+}
+
+impl IndexNames {
+    /// None if names are to be left as they are.
+    pub fn new(normalize: bool, rename_from: Option<String>) -> Result<Option<Self>> {
+        let mapping = rename_from.map(|path| load_mapping(&path)).transpose()?;
+        if !normalize && mapping.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(IndexNames { normalize, mapping }))
+    }
+
+    /// The name searches report for `record`, if it is not `name`; a new
+    /// name from the rename CSV takes precedence over normalization.
+    fn alias(&self, record: &Record, name: &str) -> Option<String> {
+        let renamed = self.mapping.as_ref().and_then(|(key, mapping)| {
+            let annotation = match key {
+                MappingKey::Md5 => mapping.get(record.md5()),
+                MappingKey::Name => mapping.get(record.name()),
+            };
+            annotation.and_then(|a| a.new_name.clone())
+        });
+        let alias = match renamed {
+            Some(renamed) => renamed,
+            None if self.normalize => normalize_name(name).to_string(),
+            None => return None,
+        };
+        (alias != name).then_some(alias)
+    }
+}
+
+/// Write the alias table for the sketches in `collection` to the index
+/// directory `index`, returning the number of sketches renamed.
+fn write_aliases(
+    index: &Utf8Path,
+    collection: &CollectionSet,
+    names: &IndexNames,
+) -> Result<usize> {
+    let mut aliases: HashMap<&str, (String, &str)> = HashMap::new();
+    let mut n_conflicts = 0;
+    for (_, record) in collection.iter() {
+        // searches report the name, or else the filename or md5.
+        let name = [record.name(), record.filename(), record.md5()]
+            .into_iter()
+            .find(|v| !v.is_empty())
+            .unwrap_or(record.md5())
+            .as_str();
+        let Some(alias) = names.alias(record, name) else {
+            continue;
+        };
+        match aliases.get(name) {
+            Some((existing, _)) if *existing != alias => n_conflicts += 1,
+            Some(_) => {}
+            None => {
+                aliases.insert(name, (alias, record.md5().as_str()));
+            }
+        }
+    }
+    if n_conflicts > 0 {
+        eprintln!(
+            "WARNING: {} sketches share a name with another sketch but not its new name; using the first new name.",
+            n_conflicts
+        );
+    }
+
+    let mut rows: Vec<_> = aliases.into_iter().collect();
+    rows.sort();
+    let path = index.join(ALIASES_FILE);
+    let mut writer = csv::Writer::from_writer(AtomicFile::create(path.as_str())?);
+    writer.write_record(["md5", "name", "alias"])?;
+    for (name, (alias, md5)) in rows.iter() {
+        writer.write_record([*md5, *name, alias.as_str()])?;
+    }
+    finish_csv(writer)
+        .and_then(AtomicFile::commit)
+        .with_context(|| format!("Failed to write alias table '{}'", path))?;
+    Ok(rows.len())
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn index<P: AsRef<Path>>(
     siglist: String,
     selection: Selection,
@@ -16,21 +111,57 @@ pub fn index<P: AsRef<Path>>(
     use_colors: bool,
     allow_failed_sigpaths: bool,
     use_internal_storage: bool,
+    force: bool,
+    names: Option<IndexNames>,
+    ctx: &RunContext,
 ) -> Result<()> {
+    check_output(output.as_ref(), force)?;
+
     eprintln!("Loading sketches from {}", siglist);
 
-    let multi = match load_collection(
+    let multi = load_collection(
         &siglist,
         &selection,
         ReportType::General,
         allow_failed_sigpaths,
-    ) {
-        Ok(multi) => multi,
-        Err(err) => return Err(err.into()),
-    };
+        ctx,
+    )?;
     eprintln!("Found {} sketches total.", multi.len());
 
-    index_obj(multi, output, use_colors, use_internal_storage)
+    index_obj(
+        multi,
+        output,
+        use_colors,
+        use_internal_storage,
+        names.as_ref(),
+    )
+}
+
+/// Refuse to overwrite an existing output unless `force` is set; even
+/// then, only remove directories that are RocksDB indexes.
+pub(crate) fn check_output(output: &Path, force: bool) -> Result<()> {
+    if !output.exists() {
+        return Ok(());
+    }
+    if !force {
+        bail!(
+            "output '{}' already exists; use --force to overwrite",
+            output.display()
+        );
+    }
+
+    let is_rocksdb = Utf8PathBuf::from_path_buf(output.to_path_buf())
+        .map(|p| is_revindex_database(&p))
+        .unwrap_or(false);
+    if !is_rocksdb {
+        bail!(
+            "refusing to overwrite '{}', which is not a RocksDB index",
+            output.display()
+        );
+    }
+    eprintln!("Removing existing index '{}'", output.display());
+    remove_dir_all(output)?;
+    Ok(())
 }
 
 pub(crate) fn index_obj<P: AsRef<Path>>(
@@ -38,6 +169,7 @@ pub(crate) fn index_obj<P: AsRef<Path>>(
     output: P,
     use_colors: bool,
     use_internal_storage: bool,
+    names: Option<&IndexNames>,
 ) -> Result<()> {
     // Try to convert it into a Collection and then CollectionSet.
     let collection = match Collection::try_from(multi.clone()) {
@@ -55,10 +187,9 @@ pub(crate) fn index_obj<P: AsRef<Path>>(
                 let cs: CollectionSet = c.try_into()?;
                 Ok(cs)
             } else {
-                Err(
-                    anyhow::anyhow!("cannot index this type of collection with external storage")
-                        .into(),
-                )
+                Err(anyhow::anyhow!(
+                    "cannot index this type of collection with external storage"
+                ))
             }
         }
     };
@@ -66,8 +197,28 @@ pub(crate) fn index_obj<P: AsRef<Path>>(
     match collection {
         Ok(collection) => {
             eprintln!("Indexing {} sketches.", collection.len());
+            // searches check queries against the ksize, moltype, and
+            // scaled in the manifest stored with the index.
+            if let Some((_, record)) = collection.iter().next() {
+                eprintln!(
+                    "Sketches have ksize={}, moltype={}, scaled={}.",
+                    record.ksize(),
+                    record.moltype(),
+                    record.scaled()
+                );
+            }
             let mut index = RevIndex::create(output.as_ref(), collection, use_colors)?;
 
+            let index_path = Utf8Path::from_path(output.as_ref())
+                .with_context(|| format!("invalid path '{}'", output.as_ref().display()))?;
+            if write_abundances(index_path, index.collection())? {
+                eprintln!("Stored abundances for abundance-weighted searches.");
+            }
+            if let Some(names) = names {
+                let n_aliases = write_aliases(index_path, index.collection(), names)?;
+                eprintln!("Stored new names for {} sketches.", n_aliases);
+            }
+
             if use_internal_storage {
                 eprintln!("Internalizing storage.");
                 index.internalize_storage()?;
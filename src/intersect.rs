@@ -0,0 +1,108 @@
+/// intersect: find the hashes shared by many sketches, optionally by group.
+use anyhow::Result;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+
+use sourmash::selection::Selection;
+use sourmash::signature::{Signature, SigsTrait};
+use sourmash::sketch::minhash::KmerMinHash;
+use sourmash::sketch::Sketch;
+
+use crate::utils::buildutils::BuildCollection;
+use crate::utils::{group_sketches, load_groups, load_sketches_at_common_scaled};
+
+/// Intersect all sketches in a collection, or all sketches in each group
+/// given by `groups_csv`, and write one signature per group to `output`.
+pub fn intersect(
+    siglist: String,
+    selection: Selection,
+    groups_csv: Option<String>,
+    name: Option<String>,
+    output: String,
+    allow_failed_sigpaths: bool,
+) -> Result<()> {
+    let (sketches, _scaled) =
+        load_sketches_at_common_scaled(&siglist, &selection, allow_failed_sigpaths)?;
+
+    let groups = match &groups_csv {
+        Some(path) => {
+            let groups = load_groups(path)?;
+            let (grouped, n_ungrouped) = group_sketches(&sketches, &groups);
+            if n_ungrouped > 0 {
+                eprintln!(
+                    "WARNING: {} sketches are not in any group, and will be ignored.",
+                    n_ungrouped
+                );
+            }
+            if grouped.is_empty() {
+                bail!("No sketches matched the names in '{}'", path);
+            }
+            grouped
+        }
+        None => {
+            let name =
+                name.unwrap_or_else(|| format!("intersection of {} sketches", sketches.len()));
+            BTreeMap::from([(name, (0..sketches.len()).collect())])
+        }
+    };
+
+    let sigs = groups
+        .par_iter()
+        .map(|(group, idxs)| {
+            let minhashes: Vec<&KmerMinHash> =
+                idxs.iter().map(|idx| &sketches[*idx].minhash).collect();
+            let mh = intersect_minhashes(&minhashes)?;
+            eprintln!(
+                "'{}': {} hashes in common across {} sketches",
+                group,
+                mh.size(),
+                minhashes.len()
+            );
+
+            let mut sig = Signature::default();
+            sig.push(Sketch::MinHash(mh));
+            sig.set_name(group);
+            sig.set_filename(&siglist);
+            Ok(sig)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut collection = BuildCollection::new();
+    for sig in sigs {
+        collection.add_sig(sig)?;
+    }
+    collection.write_sigs(&output)?;
+
+    eprintln!(
+        "DONE. Wrote {} intersected signatures to '{}'",
+        collection.size(),
+        output
+    );
+
+    Ok(())
+}
+
+/// Return a flat sketch containing the hashes present in all sketches.
+pub(crate) fn intersect_minhashes(minhashes: &[&KmerMinHash]) -> Result<KmerMinHash> {
+    let Some((first, rest)) = minhashes.split_first() else {
+        bail!("No sketches to intersect.");
+    };
+
+    // mins are kept sorted, so we can binary search.
+    let mut common = first.mins();
+    for mh in rest {
+        let mins = mh.mins();
+        common.retain(|hash| mins.binary_search(hash).is_ok());
+    }
+
+    let mut mh = KmerMinHash::new(
+        first.scaled(),
+        first.ksize() as u32,
+        first.hash_function(),
+        first.seed(),
+        false,
+        first.num(),
+    );
+    mh.add_many(&common)?;
+    Ok(mh)
+}
@@ -1,385 +1,127 @@
-//! Rust-to-Python interface code for sourmash_plugin_branchwater, using pyo3.
+//! sourmash_plugin_branchwater: fast, multithreaded sketch search and
+//! comparison, built on sourmash.
 //!
-//! If you're using Rust, you're probably most interested in
-//! [utils](utils/index.html)
-
-use pyo3::prelude::*;
+//! This crate is primarily a Python extension module, providing the
+//! `sourmash scripts` commands; the pyo3 bindings are built with the
+//! `python` feature, which is on by default. Other Rust tools can depend
+//! on it with `default-features = false` and use the search functions
+//! directly, which return typed results rather than writing CSV files:
+//!
+//! ```no_run
+//! use sourmash_plugin_branchwater::{build_selection, multisearch_collect, CollectionSource};
+//!
+//! let selection = build_selection(31, Some(1000), "DNA")?;
+//! let results = multisearch_collect(
+//!     CollectionSource::Path("queries.zip".into()),
+//!     CollectionSource::Path("database.zip".into()),
+//!     0.01,      // containment threshold
+//!     selection,
+//!     true,      // allow_failed_sigpaths
+//!     true,      // estimate_ani
+//!     false,     // estimate_prob_overlap
+//!     false,     // output_all_comparisons
+//! )?;
+//! for r in results {
+//!     println!("{} {} {}", r.query_name, r.match_name, r.containment);
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! Collections are loaded with [`load_collection`], which accepts the same
+//! inputs as the command line (zip files, RocksDB indexes, manifests,
+//! pathlists, and signature files) and takes the [`RunContext`] of the
+//! command loading them (`RunContext::default()` for library use), or with
+//! the [`MultiCollection`] constructors for a specific input type. Sketches
+//! can be built from sequences in memory with [`SketchParams`].
 
 #[macro_use]
 extern crate simple_error;
 
-mod utils;
-use crate::utils::build_selection;
-use crate::utils::is_revindex_database;
+#[cfg(feature = "python")]
+mod bench;
+#[cfg(feature = "python")]
 mod check;
+#[cfg(feature = "python")]
 mod cluster;
+#[cfg(feature = "python")]
+mod compat_check;
+#[cfg(feature = "python")]
+mod control;
+#[cfg(feature = "python")]
+mod convert;
+#[cfg(feature = "python")]
+mod downsample;
+mod errors;
+#[cfg(feature = "python")]
+mod extract;
 mod fastgather;
+#[cfg(feature = "python")]
 mod fastmultigather;
+#[cfg(feature = "python")]
 mod fastmultigather_rocksdb;
+#[cfg(feature = "python")]
+mod fastprefetch;
+#[cfg(feature = "python")]
+mod hash_lookup;
+#[cfg(feature = "python")]
 mod index;
+#[cfg(feature = "python")]
+mod intersect;
+#[cfg(feature = "python")]
+mod manydescribe;
 mod manysearch;
+#[cfg(feature = "python")]
 mod manysearch_rocksdb;
+#[cfg(feature = "python")]
 mod manysketch;
+#[cfg(feature = "python")]
+mod merge;
 mod multisearch;
+#[cfg(feature = "python")]
+mod overlap;
+#[cfg(feature = "python")]
+mod overlap_summary;
 mod pairwise;
+#[cfg(feature = "python")]
+mod pybindings;
+#[cfg(feature = "python")]
+mod pycollection;
+#[cfg(feature = "python")]
+mod pyindex;
+#[cfg(feature = "python")]
+mod pysketches;
+#[cfg(feature = "python")]
+mod rename;
+#[cfg(feature = "python")]
+mod resultstream;
 mod search_significance;
+#[cfg(feature = "python")]
+mod serve;
+#[cfg(feature = "python")]
+mod shard;
+#[cfg(feature = "python")]
 mod singlesketch;
-
-use camino::Utf8PathBuf as PathBuf;
-
-#[pyfunction]
-#[pyo3(signature = (querylist_path, siglist_path, threshold, ksize, scaled, moltype, output_path=None, ignore_abundance=false, output_all_comparisons=false))]
-#[allow(clippy::too_many_arguments)]
-fn do_manysearch(
-    querylist_path: String,
-    siglist_path: String,
-    threshold: f64,
-    ksize: u8,
-    scaled: Option<u32>,
-    moltype: String,
-    output_path: Option<String>,
-    ignore_abundance: Option<bool>,
-    output_all_comparisons: Option<bool>,
-) -> anyhow::Result<u8> {
-    let againstfile_path: PathBuf = siglist_path.clone().into();
-    let selection = build_selection(ksize, scaled, &moltype);
-    eprintln!("selection scaled: {:?}", selection.scaled());
-    let allow_failed_sigpaths = true;
-
-    let ignore_abundance = ignore_abundance.unwrap_or(false);
-    let output_all_comparisons = output_all_comparisons.unwrap_or(false);
-
-    // if siglist_path is revindex, run rocksdb manysearch; otherwise run manysearch
-    if is_revindex_database(&againstfile_path) {
-        // note: manysearch_rocksdb ignores abundance automatically.
-        match manysearch_rocksdb::manysearch_rocksdb(
-            querylist_path,
-            againstfile_path,
-            selection,
-            threshold,
-            output_path,
-            allow_failed_sigpaths,
-            output_all_comparisons,
-        ) {
-            Ok(_) => Ok(0),
-            Err(e) => {
-                eprintln!("Error: {e}");
-                Ok(1)
-            }
-        }
-    } else {
-        match manysearch::manysearch(
-            querylist_path,
-            siglist_path,
-            selection,
-            threshold,
-            output_path,
-            allow_failed_sigpaths,
-            ignore_abundance,
-            output_all_comparisons,
-        ) {
-            Ok(_) => Ok(0),
-            Err(e) => {
-                eprintln!("Error: {e}");
-                Ok(1)
-            }
-        }
-    }
-}
-
-#[pyfunction]
-#[allow(clippy::too_many_arguments)]
-#[pyo3(signature = (query_filename, siglist_path, threshold_bp, ksize, scaled, moltype, output_path_prefetch=None, output_path_gather=None))]
-fn do_fastgather(
-    query_filename: String,
-    siglist_path: String,
-    threshold_bp: u64,
-    ksize: u8,
-    scaled: Option<u32>,
-    moltype: String,
-    output_path_prefetch: Option<String>,
-    output_path_gather: Option<String>,
-) -> anyhow::Result<u8> {
-    let selection = build_selection(ksize, scaled, &moltype);
-    let allow_failed_sigpaths = true;
-
-    match fastgather::fastgather(
-        query_filename,
-        siglist_path,
-        threshold_bp,
-        selection,
-        output_path_prefetch,
-        output_path_gather,
-        allow_failed_sigpaths,
-    ) {
-        Ok(_) => Ok(0),
-        Err(e) => {
-            eprintln!("Error: {e}");
-            Ok(1)
-        }
-    }
-}
-
-#[pyfunction]
-#[allow(clippy::too_many_arguments)]
-#[pyo3(signature = (query_filenames, siglist_path, threshold_bp, ksize, scaled, moltype, output_path=None, save_matches=false, create_empty_results=false))]
-fn do_fastmultigather(
-    query_filenames: String,
-    siglist_path: String,
-    threshold_bp: u64,
-    ksize: u8,
-    scaled: Option<u32>,
-    moltype: String,
-    output_path: Option<String>,
-    save_matches: bool,
-    create_empty_results: bool,
-) -> anyhow::Result<u8> {
-    let againstfile_path: camino::Utf8PathBuf = siglist_path.clone().into();
-    let selection = build_selection(ksize, scaled, &moltype);
-    let allow_failed_sigpaths = true;
-
-    // if a siglist path is a revindex, run rocksdb fastmultigather. If not, run multigather
-    if is_revindex_database(&againstfile_path) {
-        match fastmultigather_rocksdb::fastmultigather_rocksdb(
-            query_filenames,
-            againstfile_path,
-            selection.clone(),
-            threshold_bp as u32,
-            output_path,
-            allow_failed_sigpaths,
-        ) {
-            Ok(_) => Ok(0),
-            Err(e) => {
-                eprintln!("Error: {e}");
-                Ok(1)
-            }
-        }
-    } else {
-        match fastmultigather::fastmultigather(
-            query_filenames,
-            siglist_path,
-            threshold_bp as u32,
-            scaled,
-            selection,
-            allow_failed_sigpaths,
-            save_matches,
-            output_path,
-            create_empty_results,
-        ) {
-            Ok(_) => Ok(0),
-            Err(e) => {
-                eprintln!("Error: {e}");
-                Ok(1)
-            }
-        }
-    }
-}
-
-#[pyfunction]
-fn set_global_thread_pool(num_threads: usize) -> PyResult<usize> {
-    if std::panic::catch_unwind(|| {
-        rayon::ThreadPoolBuilder::new()
-            .num_threads(num_threads)
-            .build_global()
-    })
-    .is_ok()
-    {
-        Ok(rayon::current_num_threads())
-    } else {
-        Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
-            "Could not set the number of threads. Global thread pool might already be initialized.",
-        ))
-    }
-}
-
-#[pyfunction]
-#[pyo3(signature = (siglist, ksize, scaled, moltype, output, colors, use_internal_storage))]
-fn do_index(
-    siglist: String,
-    ksize: u8,
-    scaled: Option<u32>,
-    moltype: String,
-    output: String,
-    colors: bool,
-    use_internal_storage: bool,
-) -> anyhow::Result<u8> {
-    let selection = build_selection(ksize, scaled, &moltype);
-    let allow_failed_sigpaths = false;
-    match index::index(
-        siglist,
-        selection,
-        output,
-        colors,
-        allow_failed_sigpaths,
-        use_internal_storage,
-    ) {
-        Ok(_) => Ok(0),
-        Err(e) => {
-            eprintln!("Error: {e}");
-            Ok(1)
-        }
-    }
-}
-
-#[pyfunction]
-fn do_check(index: String, quick: bool, rw: bool) -> anyhow::Result<u8> {
-    let idx: PathBuf = index.into();
-    match check::check(idx, quick, rw) {
-        Ok(_) => Ok(0),
-        Err(e) => {
-            eprintln!("Error: {e}");
-            Ok(1)
-        }
-    }
-}
-
-#[pyfunction]
-#[pyo3(signature = (querylist_path, siglist_path, threshold, ksize, scaled, moltype, estimate_ani, estimate_prob_overlap, output_all_comparisons, output_path=None))]
-#[allow(clippy::too_many_arguments)]
-fn do_multisearch(
-    querylist_path: String,
-    siglist_path: String,
-    threshold: f64,
-    ksize: u8,
-    scaled: Option<u32>,
-    moltype: String,
-    estimate_ani: bool,
-    estimate_prob_overlap: bool,
-    output_all_comparisons: bool,
-    output_path: Option<String>,
-) -> anyhow::Result<u8> {
-    let _ = env_logger::try_init();
-
-    let selection = build_selection(ksize, scaled, &moltype);
-    let allow_failed_sigpaths = true;
-
-    match multisearch::multisearch(
-        querylist_path,
-        siglist_path,
-        threshold,
-        selection,
-        allow_failed_sigpaths,
-        estimate_ani,
-        estimate_prob_overlap,
-        output_all_comparisons,
-        output_path,
-    ) {
-        Ok(_) => Ok(0),
-        Err(e) => {
-            eprintln!("Error: {e}");
-            Ok(1)
-        }
-    }
-}
-
-#[pyfunction]
-#[allow(clippy::too_many_arguments)]
-#[pyo3(signature = (siglist_path, threshold, ksize, scaled, moltype, estimate_ani, write_all, output_all_comparisons, output_path=None))]
-fn do_pairwise(
-    siglist_path: String,
-    threshold: f64,
-    ksize: u8,
-    scaled: Option<u32>,
-    moltype: String,
-    estimate_ani: bool,
-    write_all: bool,
-    output_all_comparisons: bool,
-    output_path: Option<String>,
-) -> anyhow::Result<u8> {
-    let selection = build_selection(ksize, scaled, &moltype);
-    let allow_failed_sigpaths = true;
-    match pairwise::pairwise(
-        siglist_path,
-        threshold,
-        selection,
-        allow_failed_sigpaths,
-        estimate_ani,
-        write_all,
-        output_all_comparisons,
-        output_path,
-    ) {
-        Ok(_) => Ok(0),
-        Err(e) => {
-            eprintln!("Error: {e}");
-            Ok(1)
-        }
-    }
-}
-
-#[pyfunction]
-fn do_manysketch(
-    filelist: String,
-    param_str: String,
-    output: String,
-    singleton: bool,
-    force: bool,
-) -> anyhow::Result<u8> {
-    match manysketch::manysketch(filelist, param_str, output, singleton, force) {
-        Ok(_) => Ok(0),
-        Err(e) => {
-            eprintln!("Error: {e}");
-            Ok(1)
-        }
-    }
-}
-
-#[pyfunction]
-#[pyo3(signature = (input_filenames, input_moltype, param_str, output, name))]
-fn do_singlesketch(
-    input_filenames: Vec<String>,
-    input_moltype: String,
-    param_str: String,
-    output: String,
-    name: String,
-) -> anyhow::Result<u8> {
-    match singlesketch::singlesketch(input_filenames, input_moltype, param_str, output, name) {
-        Ok(_) => Ok(0),
-        Err(e) => {
-            eprintln!("Error: {e}");
-            Ok(1)
-        }
-    }
-}
-
-#[pyfunction]
-#[pyo3(signature = (pairwise_csv, output_clusters, similarity_column, similarity_threshold, cluster_sizes=None))]
-fn do_cluster(
-    pairwise_csv: String,
-    output_clusters: String,
-    similarity_column: String,
-    similarity_threshold: f64,
-    cluster_sizes: Option<String>,
-) -> anyhow::Result<u8> {
-    match cluster::cluster(
-        pairwise_csv,
-        output_clusters,
-        similarity_column,
-        similarity_threshold,
-        cluster_sizes,
-    ) {
-        Ok(_) => Ok(0),
-        Err(e) => {
-            eprintln!("Error: {e}");
-            Ok(1)
-        }
-    }
-}
-
-/// Module interface for the `sourmash_plugin_branchwater` extension module.
-
-#[pymodule]
-fn sourmash_plugin_branchwater(_py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
-    m.add_function(wrap_pyfunction!(do_manysearch, m)?)?;
-    m.add_function(wrap_pyfunction!(do_fastgather, m)?)?;
-    m.add_function(wrap_pyfunction!(do_fastmultigather, m)?)?;
-    m.add_function(wrap_pyfunction!(do_index, m)?)?;
-    m.add_function(wrap_pyfunction!(do_check, m)?)?;
-    m.add_function(wrap_pyfunction!(do_manysketch, m)?)?;
-    m.add_function(wrap_pyfunction!(set_global_thread_pool, m)?)?;
-    m.add_function(wrap_pyfunction!(do_multisearch, m)?)?;
-    m.add_function(wrap_pyfunction!(do_pairwise, m)?)?;
-    m.add_function(wrap_pyfunction!(do_cluster, m)?)?;
-    m.add_function(wrap_pyfunction!(do_singlesketch, m)?)?;
-
-    Ok(())
-}
+mod sketch;
+#[cfg(feature = "python")]
+mod subtract;
+#[cfg(feature = "python")]
+mod summarize;
+mod utils;
+#[cfg(feature = "python")]
+mod validate_zip;
+#[cfg(feature = "python")]
+mod zip_cat;
+
+pub use errors::BranchwaterError;
+pub use fastgather::fastgather_collect;
+pub use manysearch::manysearch_collect;
+pub use multisearch::multisearch_collect;
+pub use pairwise::pairwise_collect;
+pub use sketch::{write_sketches, SketchParams};
+pub use sourmash::selection::Selection;
+pub use sourmash::signature::Signature;
+pub use utils::{
+    build_partial_selection, build_selection, load_collection, BranchwaterGatherResult,
+    CollectionSource, GatherOptions, GatherThreshold, ManySearchResult, MultiCollection,
+    MultiSearchResult, ReportType, RunContext, SmallSignature,
+};
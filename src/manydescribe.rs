@@ -0,0 +1,67 @@
+/// manydescribe: write one CSV row per sketch in a collection.
+use anyhow::{anyhow, Result};
+use rayon::prelude::*;
+use serde::Serialize;
+
+use sourmash::selection::Selection;
+
+use crate::utils::{csvwriter_thread, load_collection, ReportType, RunContext};
+
+#[derive(Serialize)]
+struct DescribeRow {
+    name: String,
+    md5: String,
+    ksize: u32,
+    moltype: String,
+    scaled: u32,
+    num: u32,
+    n_hashes: usize,
+    with_abundance: bool,
+    filename: String,
+    internal_location: String,
+}
+
+pub fn manydescribe(
+    siglist: String,
+    output: Option<String>,
+    allow_failed_sigpaths: bool,
+) -> Result<()> {
+    // no selection: describe every sketch in the collection.
+    let collection = load_collection(
+        &siglist,
+        &Selection::default(),
+        ReportType::General,
+        allow_failed_sigpaths,
+        &RunContext::default(),
+    )?;
+
+    let (send, recv) = std::sync::mpsc::sync_channel::<DescribeRow>(rayon::current_num_threads());
+    let thrd = csvwriter_thread(recv, output);
+
+    // everything we need is in the manifest, so no sketches are loaded.
+    let send_result =
+        collection
+            .par_iter()
+            .try_for_each_with(send, |s, (_coll, _idx, record)| -> Result<()> {
+                s.send(DescribeRow {
+                    name: record.name().clone(),
+                    md5: record.md5().clone(),
+                    ksize: record.ksize(),
+                    moltype: record.moltype().to_string(),
+                    scaled: *record.scaled(),
+                    num: *record.num(),
+                    n_hashes: *record.n_hashes(),
+                    with_abundance: record.with_abundance(),
+                    filename: record.filename().clone(),
+                    internal_location: record.internal_location().to_string(),
+                })
+                .map_err(|e| anyhow!("Unable to send internal data: {:?}", e))
+            });
+
+    thrd.join().expect("Unable to join internal thread.");
+    send_result?;
+
+    eprintln!("DONE. Described {} sketches.", collection.len());
+
+    Ok(())
+}
@@ -3,19 +3,30 @@
 ///
 /// Note: this function loads all _queries_ into memory, and iterates over
 /// database once.
-use anyhow::Result;
+use anyhow::{bail, Result};
+use log::debug;
 use rayon::prelude::*;
 use stats::{median, stddev};
+use std::collections::HashMap;
 use std::sync::atomic;
 use std::sync::atomic::AtomicUsize;
-
+use std::sync::mpsc::SyncSender;
+use std::sync::Arc;
+
+#[cfg(feature = "python")]
+use crate::utils::columns::{result_csvwriter_thread, ColumnSelection};
+use crate::utils::coverage::CoverageReport;
+use crate::utils::dedup;
+use crate::utils::matchedhashes::MatchedHashesWriter;
+use crate::utils::profile::Stage;
+use crate::utils::stoplist::{self, Stoplist};
 use crate::utils::{
-    csvwriter_thread, load_collection, ManySearchResult, MultiCollection, ReportType,
-    SmallSignature,
+    collect_results, downsample_query, select_at_max_scaled, CollectionSource, ManySearchResult,
+    MinOverlap, MultiCollection, ReportType, RunContext, SearchControl, SmallSignature,
 };
 use sourmash::ani_utils::ani_from_containment;
 use sourmash::errors::SourmashError;
-use sourmash::selection::Selection;
+use sourmash::selection::{Select, Selection};
 use sourmash::signature::SigsTrait;
 use sourmash::sketch::minhash::KmerMinHash;
 use sourmash::storage::SigStore;
@@ -28,60 +39,139 @@ type AbundanceStats = (
     Option<f64>,
 );
 
+#[cfg(feature = "python")]
+#[allow(clippy::too_many_arguments)]
 pub fn manysearch(
-    query_filepath: String,
-    against_filepath: String,
+    query_source: CollectionSource,
+    against_source: CollectionSource,
     selection: Selection,
     threshold: f64,
     output: Option<String>,
     allow_failed_sigpaths: bool,
     ignore_abundance: bool,
     output_all_comparisons: bool,
+    control: SearchControl,
+    coverage_report: Option<String>,
+    exclude_self_matches: bool,
+    columns: ColumnSelection,
+    matched_hashes: Option<String>,
+    per_query_scaled: bool,
+    min_overlap: Option<MinOverlap>,
+    stoplist: Option<&Stoplist>,
+    ctx: &RunContext,
 ) -> Result<()> {
-    // Load query collection
-    let query_collection = load_collection(
-        &query_filepath,
-        &selection,
-        ReportType::Query,
+    // both need every query at the same scaled.
+    if per_query_scaled && (coverage_report.is_some() || matched_hashes.is_some()) {
+        bail!(
+            "--per-query-scaled cannot be combined with --coverage-report or --output-matched-hashes"
+        );
+    }
+
+    let (query_sketchlist, against_collection, common_scaled) = load_manysearch_inputs(
+        &query_source,
+        &against_source,
+        selection,
         allow_failed_sigpaths,
+        per_query_scaled,
+        stoplist,
+        ctx,
     )?;
 
-    // Figure out what scaled to use - either from selection, or from query.
-    let common_scaled: u32 = if let Some(set_scaled) = selection.scaled() {
-        set_scaled
-    } else {
-        let s = *query_collection.max_scaled().expect("no records!?");
-        eprintln!(
-            "Setting scaled={} based on max scaled in query collection",
-            s
-        );
-        s
-    };
+    // set up a multi-producer, single-consumer channel.
+    let (send, recv) =
+        std::sync::mpsc::sync_channel::<ManySearchResult>(rayon::current_num_threads());
 
-    let mut selection = selection;
-    selection.set_scaled(common_scaled);
+    // & spawn a thread that is dedicated to printing to a buffered output
+    let thrd = result_csvwriter_thread(recv, output, columns, ctx)?;
+
+    let coverage = coverage_report
+        .as_ref()
+        .map(|_| CoverageReport::new(&query_sketchlist));
+    let matched = matched_hashes
+        .map(|path| MatchedHashesWriter::create(&path, common_scaled))
+        .transpose()?;
+
+    let (n_processed, skipped_paths, failed_paths) = ctx.time(Stage::Comparison, || {
+        manysearch_obj(
+            &query_sketchlist,
+            &against_collection,
+            threshold,
+            common_scaled,
+            send,
+            ignore_abundance,
+            output_all_comparisons,
+            &control,
+            coverage.as_ref(),
+            matched.as_ref(),
+            exclude_self_matches,
+            min_overlap,
+            ctx,
+        )
+    })?;
 
-    // load all query sketches into memory, downsampling on the way
-    let query_sketchlist = query_collection.load_sketches()?;
+    thrd.join().expect("Unable to join internal thread.");
 
-    // Against: Load collection, potentially off disk & not into memory.
-    let against_collection = load_collection(
-        &against_filepath,
-        &selection,
-        ReportType::Against,
+    if let Some(matched) = matched {
+        ctx.time(Stage::Writing, || matched.finish())?;
+    }
+
+    if let (Some(coverage), Some(path)) = (coverage, coverage_report) {
+        ctx.time(Stage::Writing, || coverage.write(&query_sketchlist, &path))?;
+    }
+
+    ctx.record_processed(n_processed);
+    stoplist::report(stoplist);
+    report_search(n_processed, skipped_paths, failed_paths);
+
+    Ok(())
+}
+
+/// Search many queries against a collection, returning the results
+/// instead of writing them to a CSV file. RocksDB indexes are loaded
+/// into memory and searched like any other collection.
+pub fn manysearch_collect(
+    query_source: CollectionSource,
+    against_source: CollectionSource,
+    selection: Selection,
+    threshold: f64,
+    allow_failed_sigpaths: bool,
+    ignore_abundance: bool,
+    output_all_comparisons: bool,
+) -> Result<Vec<ManySearchResult>> {
+    let (query_sketchlist, against_collection, common_scaled) = load_manysearch_inputs(
+        &query_source,
+        &against_source,
+        selection,
         allow_failed_sigpaths,
+        false,
+        None,
+        &RunContext::default(),
     )?;
 
-    let (n_processed, skipped_paths, failed_paths) = manysearch_obj(
-        &query_sketchlist,
-        &against_collection,
-        threshold,
-        common_scaled,
-        output,
-        ignore_abundance,
-        output_all_comparisons,
-    )?;
+    let ((n_processed, skipped_paths, failed_paths), results) = collect_results(|send| {
+        manysearch_obj(
+            &query_sketchlist,
+            &against_collection,
+            threshold,
+            common_scaled,
+            send,
+            ignore_abundance,
+            output_all_comparisons,
+            &SearchControl::default(),
+            None,
+            None,
+            false,
+            None,
+            &RunContext::default(),
+        )
+    })?;
+
+    report_search(n_processed, skipped_paths, failed_paths);
+
+    Ok(results)
+}
 
+fn report_search(n_processed: usize, skipped_paths: usize, failed_paths: usize) {
     eprintln!("DONE. Processed {} search sigs", n_processed);
 
     if skipped_paths > 0 {
@@ -96,41 +186,126 @@ pub fn manysearch(
             failed_paths
         );
     }
+}
 
-    Ok(())
+/// Load all query sketches into memory, and the against collection
+/// (potentially off disk & not into memory), at a common scaled.
+///
+/// With `per_query_scaled`, the against collection is instead loaded at
+/// its own largest scaled, and each query is downsampled to that, or kept
+/// at its own scaled if higher; see `downsample_query`.
+///
+/// Returns (query sketches, against collection, against scaled).
+pub(crate) fn load_manysearch_inputs(
+    query_source: &CollectionSource,
+    against_source: &CollectionSource,
+    selection: Selection,
+    allow_failed_sigpaths: bool,
+    per_query_scaled: bool,
+    stoplist: Option<&Stoplist>,
+    ctx: &RunContext,
+) -> Result<(Vec<SmallSignature>, MultiCollection, u32)> {
+    if per_query_scaled && selection.scaled().is_some() {
+        bail!("--per-query-scaled cannot be combined with --scaled");
+    }
+
+    // Load query collection
+    let (query_collection, selection) = query_source.load_and_complete_selection(
+        selection,
+        ReportType::Query,
+        allow_failed_sigpaths,
+        ctx,
+    )?;
+
+    if per_query_scaled {
+        let against_collection =
+            against_source.load(&selection, ReportType::Against, allow_failed_sigpaths, ctx)?;
+        let (against_collection, against_scaled) =
+            select_at_max_scaled(against_collection, &selection)?;
+        eprintln!(
+            "Comparing each query at scaled={} (the max scaled in the search collection), or its own scaled if higher",
+            against_scaled
+        );
+
+        let mut query_sketchlist = ctx
+            .time(Stage::Loading, || query_collection.load_sketches())?
+            .into_iter()
+            .map(|mut query| {
+                query.minhash = downsample_query(query.minhash, against_scaled)?;
+                Ok(query)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        stoplist::apply_sketches(stoplist, &mut query_sketchlist);
+
+        return Ok((query_sketchlist, against_collection, against_scaled));
+    }
+
+    // Figure out what scaled to use - either from selection, or from query.
+    let common_scaled: u32 = if let Some(set_scaled) = selection.scaled() {
+        set_scaled
+    } else {
+        let s = *query_collection.max_scaled().expect("no records!?");
+        eprintln!(
+            "Setting scaled={} based on max scaled in query collection",
+            s
+        );
+        s
+    };
+
+    let mut selection = selection;
+    selection.set_scaled(common_scaled);
+
+    // load all query sketches into memory, downsampling on the way
+    let query_collection = ctx.time(Stage::Selection, || query_collection.select(&selection))?;
+    let mut query_sketchlist = ctx.time(Stage::Loading, || query_collection.load_sketches())?;
+    stoplist::apply_sketches(stoplist, &mut query_sketchlist);
+
+    // Against: Load collection, potentially off disk & not into memory.
+    let against_collection =
+        against_source.load(&selection, ReportType::Against, allow_failed_sigpaths, ctx)?;
+
+    Ok((query_sketchlist, against_collection, common_scaled))
 }
 
+#[allow(clippy::too_many_arguments, clippy::result_large_err)]
 pub(crate) fn manysearch_obj(
-    query_sketchlist: &Vec<SmallSignature>,
+    query_sketchlist: &[SmallSignature],
     against_collection: &MultiCollection,
     threshold: f64,
     common_scaled: u32,
-    output: Option<String>,
+    send: SyncSender<ManySearchResult>,
     ignore_abundance: bool,
     output_all_comparisons: bool,
+    control: &SearchControl,
+    coverage: Option<&CoverageReport>,
+    matched_hashes: Option<&MatchedHashesWriter>,
+    exclude_self_matches: bool,
+    min_overlap: Option<MinOverlap>,
+    ctx: &RunContext,
 ) -> Result<(usize, usize, usize)> {
-    // set up a multi-producer, single-consumer channel.
-    let (send, recv) =
-        std::sync::mpsc::sync_channel::<ManySearchResult>(rayon::current_num_threads());
-
-    // & spawn a thread that is dedicated to printing to a buffered output
-    let thrd = csvwriter_thread(recv, output);
-
     //
     // Main loop: iterate (in parallel) over all search signature paths,
     // loading them individually and searching them. Stuff results into
-    // the writer thread above.
+    // the channel.
     //
 
     let processed_sigs = AtomicUsize::new(0);
     let skipped_paths = AtomicUsize::new(0);
     let failed_paths = AtomicUsize::new(0);
+    let n_comparisons = against_collection.len() * query_sketchlist.len();
+    let aliases = ctx.aliases();
+    // like the containment threshold, this doesn't apply to all comparisons.
+    let min_overlap = min_overlap.filter(|_| !output_all_comparisons);
 
     let send = against_collection
         .par_iter()
         .filter_map(|(coll, _idx, record)| {
+            if control.is_cancelled() {
+                return None;
+            }
+
             let i = processed_sigs.fetch_add(1, atomic::Ordering::SeqCst);
-            if i % 1000 == 0 && i > 0 {
+            if i.is_multiple_of(1000) && i > 0 {
                 eprintln!("Processed {} search sigs", i);
             }
 
@@ -138,24 +313,52 @@ pub(crate) fn manysearch_obj(
 
             match coll.sig_from_record(record) {
                 Ok(against_sig) => {
-                    let against_name = against_sig.name();
-                    let against_md5 = against_sig.md5sum();
+                    let against_name: Arc<str> = against_sig.name().into();
+                    let against_md5: Arc<str> = against_sig.md5sum().into();
 
                     if let Ok(against_mh) =
                         <SigStore as TryInto<KmerMinHash>>::try_into(against_sig)
                     {
+                        if let Some(coverage) = coverage {
+                            coverage.add_against(&against_name, &against_md5, &against_mh);
+                        }
+                        let against_mins = matched_hashes.map(|_| against_mh.mins());
+                        // queries with a higher scaled than the against
+                        // sketches are compared at their own scaled.
+                        let mut downsampled: HashMap<u32, KmerMinHash> = HashMap::new();
                         for query in query_sketchlist.iter() {
+                            if exclude_self_matches && query.md5sum == against_md5 {
+                                continue;
+                            }
+                            let query_scaled = query.minhash.scaled();
+                            let compared_mh = if query_scaled == common_scaled {
+                                &against_mh
+                            } else {
+                                &*downsampled.entry(query_scaled).or_insert_with(|| {
+                                    against_mh
+                                        .clone()
+                                        .downsample_scaled(query_scaled)
+                                        .expect("cannot downsample against sketch")
+                                })
+                            };
                             let sr = calculate_manysearch_result(
                                 query,
-                                &against_mh,
+                                compared_mh,
                                 &against_name,
                                 &against_md5,
                                 threshold,
-                                common_scaled,
+                                query_scaled,
                                 ignore_abundance,
                                 output_all_comparisons,
                             );
-                            if let Some(sr) = sr {
+                            let sr = sr.filter(|sr| min_overlap.is_none_or(|m| m.passes(sr)));
+                            if let Some(mut sr) = sr {
+                                if let (Some(matched), Some(mins)) = (matched_hashes, &against_mins)
+                                {
+                                    matched.add(query, &against_md5, mins);
+                                }
+                                sr.match_aliases =
+                                    dedup::match_aliases(aliases.as_ref(), &against_md5);
                                 results.push(sr);
                             }
                         }
@@ -164,6 +367,10 @@ pub(crate) fn manysearch_obj(
                             "WARNING: no compatible sketches in path '{}'",
                             record.internal_location()
                         );
+                        ctx.record_skipped(
+                            record.internal_location().as_str(),
+                            "no compatible sketches",
+                        );
                         let _ = skipped_paths.fetch_add(1, atomic::Ordering::SeqCst);
                     }
                 }
@@ -173,17 +380,26 @@ pub(crate) fn manysearch_obj(
                         "WARNING: no compatible sketches in path '{}'",
                         record.internal_location()
                     );
+                    ctx.record_skipped(record.internal_location().as_str(), &err);
                     let _ = skipped_paths.fetch_add(1, atomic::Ordering::SeqCst);
                 }
             }
+            control.add_progress(query_sketchlist.len(), n_comparisons);
 
             Some(results)
         })
         .flatten()
         .try_for_each_with(send, |s, m| s.send(m));
 
-    send.expect("Unable to send internal data");
-    thrd.join().expect("Unable to join internal thread.");
+    // a send error means the receiver hung up, e.g. a Python consumer
+    // of a result stream stopped early. Nothing more to do.
+    if send.is_err() {
+        debug!("result receiver closed; stopped searching early");
+    }
+
+    if control.is_cancelled() {
+        bail!("search cancelled");
+    }
 
     // done!
     let i: usize = processed_sigs.fetch_max(0, atomic::Ordering::SeqCst);
@@ -222,11 +438,12 @@ fn inflate_abundances(
 
 // calculate_manysearch_result: calculate all the things
 
-fn calculate_manysearch_result(
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn calculate_manysearch_result(
     query: &SmallSignature,
     against_mh: &KmerMinHash,
-    against_name: &str,
-    against_md5: &str,
+    against_name: &Arc<str>,
+    against_md5: &Arc<str>,
     threshold: f64,
     common_scaled: u32,
     ignore_abundance: bool,
@@ -274,13 +491,18 @@ fn calculate_manysearch_result(
         let sr = ManySearchResult {
             query_name: query.name.clone(),
             query_md5: query.md5sum.clone(),
-            match_name: against_name.to_string(),
+            match_name: against_name.clone(),
             containment: containment_query_in_target,
             intersect_hashes: overlap as u64,
+            intersect_bp: overlap as u64 * query.minhash.scaled() as u64,
+            query_n_hashes: query_size as u64,
+            match_n_hashes: Some(target_size as u64),
+            query_bp: query_size as u64 * query.minhash.scaled() as u64,
+            match_bp: Some(target_size as u64 * query.minhash.scaled() as u64),
             ksize: query.minhash.ksize() as u16,
             scaled: query.minhash.scaled(),
             moltype: query.minhash.hash_function().to_string(),
-            match_md5: Some(against_md5.to_string()),
+            match_md5: Some(against_md5.clone()),
             jaccard: Some(jaccard),
             max_containment: Some(max_containment),
             average_abund,
@@ -292,6 +514,7 @@ fn calculate_manysearch_result(
             max_containment_ani,
             n_weighted_found,
             total_weighted_hashes,
+            match_aliases: None,
         };
         return Some(sr);
     }
@@ -2,8 +2,10 @@
 use anyhow::Result;
 use camino::Utf8PathBuf as PathBuf;
 use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::atomic;
 use std::sync::atomic::AtomicUsize;
+use std::sync::{Arc, Mutex};
 
 use sourmash::ani_utils::ani_from_containment;
 use sourmash::index::revindex::{RevIndex, RevIndexOps};
@@ -12,11 +14,223 @@ use sourmash::signature::SigsTrait;
 use sourmash::sketch::minhash::KmerMinHash;
 use sourmash::storage::SigStore;
 
+use crate::errors::BranchwaterError;
+use crate::utils::abundances::AbundanceTable;
+use crate::utils::aliases::NameAliases;
+use crate::utils::columns::{result_csvwriter_thread, ColumnSelection};
+use crate::utils::profile::Stage;
+use crate::utils::stoplist::{self, Stoplist};
 use crate::utils::{
-    csvwriter_thread, is_revindex_database, load_collection, ManySearchResult, MultiCollection,
-    ReportType,
+    is_revindex_database, load_collection, revindex_selection, Interner, ManySearchResult,
+    MinOverlap, MultiCollection, ReportType, RunContext,
 };
 
+/// A small LRU cache of match sizes at the query scaled, for matches
+/// that must be loaded from the database and downsampled.
+///
+/// Entries are stamped with a use counter; `order` maps each stamp back
+/// to its entry, so both lookups and evictions are O(log n).
+pub(crate) struct MatchSizeCache {
+    capacity: usize,
+    inner: Mutex<LruState>,
+}
+
+#[derive(Default)]
+struct LruState {
+    sizes: HashMap<(u32, u32), (usize, u64)>,
+    order: BTreeMap<u64, (u32, u32)>,
+    tick: u64,
+}
+
+impl LruState {
+    fn touch(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+impl MatchSizeCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        MatchSizeCache {
+            capacity,
+            inner: Mutex::new(LruState::default()),
+        }
+    }
+
+    fn get(&self, dataset_id: u32, scaled: u32) -> Option<usize> {
+        let mut guard = self.inner.lock().unwrap();
+        let tick = guard.touch();
+        let state = &mut *guard;
+        let (size, last_used) = state.sizes.get_mut(&(dataset_id, scaled))?;
+        state.order.remove(last_used);
+        state.order.insert(tick, (dataset_id, scaled));
+        *last_used = tick;
+        Some(*size)
+    }
+
+    fn insert(&self, dataset_id: u32, scaled: u32, size: usize) {
+        let mut guard = self.inner.lock().unwrap();
+        let tick = guard.touch();
+        let state = &mut *guard;
+        if let Some((_, last_used)) = state.sizes.insert((dataset_id, scaled), (size, tick)) {
+            state.order.remove(&last_used);
+        }
+        state.order.insert(tick, (dataset_id, scaled));
+        if state.order.len() > self.capacity {
+            if let Some((_, evicted)) = state.order.pop_first() {
+                state.sizes.remove(&evicted);
+            }
+        }
+    }
+}
+
+/// Number of hashes in a database sketch at `scaled`. The manifest gives
+/// this directly unless the sketch must be downsampled.
+fn match_size(
+    db: &RevIndex,
+    dataset_id: u32,
+    scaled: u32,
+    cache: &MatchSizeCache,
+) -> Result<usize> {
+    let record = db.collection().record_for_dataset(dataset_id)?;
+    if *record.scaled() == scaled {
+        return Ok(*record.n_hashes());
+    }
+    if let Some(size) = cache.get(dataset_id, scaled) {
+        return Ok(size);
+    }
+
+    let sig = db.collection().sig_for_dataset(dataset_id)?;
+    let mh: KmerMinHash = sig.try_into()?;
+    let size = mh.downsample_scaled(scaled)?.size();
+    cache.insert(dataset_id, scaled, size);
+    Ok(size)
+}
+
+/// Search one query sketch against the database, returning the matches
+/// at or above `minimum_containment`. With an abundance table, the
+/// abundance-weighted columns are filled in for matches that track
+/// abundance.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn revindex_search(
+    db: &RevIndex,
+    query_mh: &KmerMinHash,
+    query_name: &Arc<str>,
+    query_md5: &Arc<str>,
+    minimum_containment: f64,
+    output_all_comparisons: bool,
+    full_results: bool,
+    exclude_self_matches: bool,
+    size_cache: &MatchSizeCache,
+    names: &Interner,
+    abundances: Option<&AbundanceTable>,
+) -> Vec<ManySearchResult> {
+    let mut results = vec![];
+    let query_size = query_mh.size();
+    let counter = db.counter_for_query(query_mh);
+    let ksize = query_mh.ksize() as f64;
+
+    // filter the matches for containment
+    for (dataset_id, overlap) in counter.most_common() {
+        if overlap < minimum_containment as usize {
+            continue;
+        }
+        let containment = overlap as f64 / query_size as f64;
+        if containment >= minimum_containment || output_all_comparisons {
+            let qani = ani_from_containment(containment, ksize);
+            let record = db
+                .collection()
+                .record_for_dataset(dataset_id)
+                .expect("dataset not found");
+            if exclude_self_matches && record.md5().as_str() == &**query_md5 {
+                continue;
+            }
+            let match_name = [record.name(), record.filename(), record.md5()]
+                .into_iter()
+                .find(|v| !v.is_empty())
+                .unwrap_or(record.md5());
+
+            let mut result = ManySearchResult {
+                query_name: query_name.clone(),
+                query_md5: query_md5.clone(),
+                match_name: names.intern(match_name),
+                containment,
+                intersect_hashes: overlap as u64,
+                intersect_bp: overlap as u64 * query_mh.scaled() as u64,
+                query_n_hashes: query_size as u64,
+                // filled in from the database manifest, below
+                match_n_hashes: None,
+                query_bp: query_size as u64 * query_mh.scaled() as u64,
+                match_bp: None,
+                ksize: query_mh.ksize() as u16,
+                scaled: query_mh.scaled(),
+                moltype: query_mh.hash_function().to_string(),
+                match_md5: None,
+                jaccard: None,
+                max_containment: None,
+                // filled in from the abundance table, below
+                average_abund: None,
+                median_abund: None,
+                std_abund: None,
+                query_containment_ani: Some(qani),
+                match_containment_ani: None,
+                average_containment_ani: None,
+                max_containment_ani: None,
+                n_weighted_found: None,
+                total_weighted_hashes: None,
+                match_aliases: None,
+            };
+
+            // match sizes come from the database manifest.
+            if full_results {
+                match match_size(db, dataset_id, query_mh.scaled(), size_cache) {
+                    Ok(size) => {
+                        let overlap = overlap as f64;
+                        let query_size = query_size as f64;
+                        let match_size = size as f64;
+                        let match_containment = overlap / match_size;
+                        let mani = ani_from_containment(match_containment, ksize);
+
+                        result.match_md5 = Some(names.intern(record.md5()));
+                        result.match_n_hashes = Some(size as u64);
+                        result.match_bp = Some(size as u64 * query_mh.scaled() as u64);
+                        result.jaccard = Some(overlap / (match_size + query_size - overlap));
+                        result.max_containment = Some(containment.max(match_containment));
+                        result.match_containment_ani = Some(mani);
+                        result.average_containment_ani = Some((qani + mani) / 2.);
+                        result.max_containment_ani = Some(f64::max(qani, mani));
+                    }
+                    Err(e) => eprintln!(
+                        "WARNING: could not get size of match '{}': {}",
+                        result.match_name, e
+                    ),
+                }
+            }
+
+            if let Some(abundances) = abundances {
+                match abundances.weighted_stats(dataset_id, query_mh) {
+                    Ok(Some((total, found, average, median, std))) => {
+                        result.total_weighted_hashes = Some(total);
+                        result.n_weighted_found = Some(found);
+                        result.average_abund = Some(average);
+                        result.median_abund = Some(median);
+                        result.std_abund = Some(std);
+                    }
+                    Ok(None) => {}
+                    Err(e) => eprintln!(
+                        "WARNING: could not get abundances of match '{}': {}",
+                        result.match_name, e
+                    ),
+                }
+            }
+
+            results.push(result);
+        }
+    }
+    results
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn manysearch_rocksdb(
     queries_path: String,
     index: PathBuf,
@@ -25,47 +239,43 @@ pub fn manysearch_rocksdb(
     output: Option<String>,
     allow_failed_sigpaths: bool,
     output_all_comparisons: bool,
+    full_results: bool,
+    exclude_self_matches: bool,
+    ignore_abundance: bool,
+    columns: ColumnSelection,
+    min_overlap: Option<MinOverlap>,
+    stoplist: Option<&Stoplist>,
+    ctx: &RunContext,
 ) -> Result<()> {
     if !is_revindex_database(&index) {
-        bail!("'{}' is not a valid RevIndex database", index);
+        bail!(BranchwaterError::InvalidRocksDB(format!(
+            "'{}' is not a valid RevIndex database",
+            index
+        )));
     }
 
+    let abundances = if ignore_abundance {
+        None
+    } else {
+        ctx.time(Stage::Loading, || AbundanceTable::open(&index))?
+    };
+    let aliases = NameAliases::open(&index)?;
+
     // Open database once
-    let db = match RevIndex::open(index, true, None) {
+    let db = match ctx.time(Stage::Loading, || RevIndex::open(index, true, None)) {
         Ok(db) => db,
         Err(e) => {
-            return Err(anyhow::anyhow!(
+            bail!(BranchwaterError::InvalidRocksDB(format!(
                 "cannot open RocksDB database. Error is: {}",
                 e
-            ))
+            )))
         }
     };
 
     println!("Loaded DB");
 
-    // grab scaled from the database.
-    let (_, max_db_scaled) = db
-        .collection()
-        .min_max_scaled()
-        .expect("no records in db?!");
-
-    let selection_scaled: u32 = match selection.scaled() {
-        Some(scaled) => {
-            if *max_db_scaled > scaled {
-                return Err(anyhow::anyhow!(
-                    "Error: database scaled is higher than requested scaled"
-                ));
-            }
-            scaled
-        }
-        None => {
-            eprintln!("Setting scaled={} from the database", *max_db_scaled);
-            *max_db_scaled
-        }
-    };
-
-    let mut set_selection = selection;
-    set_selection.set_scaled(selection_scaled);
+    // ksize, moltype, and scaled must match the database.
+    let set_selection = revindex_selection(&db, selection)?;
 
     // Load query paths
     let query_collection = load_collection(
@@ -73,17 +283,30 @@ pub fn manysearch_rocksdb(
         &set_selection,
         ReportType::Query,
         allow_failed_sigpaths,
+        ctx,
     )?;
 
-    let (n_processed, skipped_paths, failed_paths) = manysearch_rocksdb_obj(
-        &query_collection,
-        &db,
-        minimum_containment,
-        output,
-        output_all_comparisons,
-    )?;
+    let (n_processed, skipped_paths, failed_paths) = ctx.time(Stage::Comparison, || {
+        manysearch_rocksdb_obj(
+            &query_collection,
+            &db,
+            minimum_containment,
+            output,
+            output_all_comparisons,
+            full_results,
+            exclude_self_matches,
+            abundances.as_ref(),
+            aliases.as_ref(),
+            columns,
+            min_overlap,
+            stoplist,
+            ctx,
+        )
+    })?;
 
     // done!
+    ctx.record_processed(n_processed);
+    stoplist::report(stoplist);
     eprintln!("DONE. Processed {} search sigs", n_processed);
 
     if skipped_paths > 0 {
@@ -102,19 +325,28 @@ pub fn manysearch_rocksdb(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn manysearch_rocksdb_obj(
     query_collection: &MultiCollection,
     db: &RevIndex,
     minimum_containment: f64,
     output: Option<String>,
     output_all_comparisons: bool,
+    full_results: bool,
+    exclude_self_matches: bool,
+    abundances: Option<&AbundanceTable>,
+    aliases: Option<&NameAliases>,
+    columns: ColumnSelection,
+    min_overlap: Option<MinOverlap>,
+    stoplist: Option<&Stoplist>,
+    ctx: &RunContext,
 ) -> Result<(usize, usize, usize)> {
     // set up a multi-producer, single-consumer channel.
     let (send, recv) =
         std::sync::mpsc::sync_channel::<ManySearchResult>(rayon::current_num_threads());
 
     // & spawn a thread that is dedicated to printing to a buffered output
-    let thrd = csvwriter_thread(recv, output);
+    let thrd = result_csvwriter_thread(recv, output, columns, ctx)?;
 
     //
     // Main loop: iterate (in parallel) over all search signature paths,
@@ -125,64 +357,54 @@ pub(crate) fn manysearch_rocksdb_obj(
     let processed_sigs = AtomicUsize::new(0);
     let skipped_paths = AtomicUsize::new(0);
     let failed_paths = AtomicUsize::new(0);
+    let size_cache = MatchSizeCache::new(1000);
+    let names = Interner::default();
+    // like the containment threshold, this doesn't apply to all comparisons.
+    let min_overlap = min_overlap.filter(|_| !output_all_comparisons);
 
     let send_result = query_collection
         .par_iter()
         .filter_map(|(coll, _idx, record)| {
             let i = processed_sigs.fetch_add(1, atomic::Ordering::SeqCst);
-            if i % 1000 == 0 && i > 0 {
+            if i.is_multiple_of(1000) && i > 0 {
                 eprintln!("Processed {} search sigs", i);
             }
 
             let mut results = vec![];
             match coll.sig_from_record(record) {
                 Ok(query_sig) => {
-                    let query_name = query_sig.name().clone();
-                    let query_md5 = query_sig.md5sum().clone();
+                    let query_name: Arc<str> = query_sig.name().into();
+                    let query_md5: Arc<str> = query_sig.md5sum().into();
                     let query_file = query_sig.filename().clone();
 
-                    if let Ok(query_mh) = <SigStore as TryInto<KmerMinHash>>::try_into(query_sig) {
-                        let query_size = query_mh.size();
-                        let counter = db.counter_for_query(&query_mh);
-                        let matches =
-                            db.matches_from_counter(counter, minimum_containment as usize);
-
-                        // filter the matches for containment
-                        for (path, overlap) in matches {
-                            let containment = overlap as f64 / query_size as f64;
-                            if containment >= minimum_containment || output_all_comparisons {
-                                let query_containment_ani = Some(ani_from_containment(
-                                    containment,
-                                    query_mh.ksize() as f64,
-                                ));
-
-                                results.push(ManySearchResult {
-                                    query_name: query_name.clone(),
-                                    query_md5: query_md5.clone(),
-                                    match_name: path.clone(),
-                                    containment,
-                                    intersect_hashes: overlap as u64,
-                                    ksize: query_mh.ksize() as u16,
-                                    scaled: query_mh.scaled(),
-                                    moltype: query_mh.hash_function().to_string(),
-                                    match_md5: None,
-                                    jaccard: None,
-                                    max_containment: None,
-                                    // can't calculate from here -- need to get these from w/in sourmash
-                                    average_abund: None,
-                                    median_abund: None,
-                                    std_abund: None,
-                                    query_containment_ani,
-                                    match_containment_ani: None,
-                                    average_containment_ani: None,
-                                    max_containment_ani: None,
-                                    n_weighted_found: None,
-                                    total_weighted_hashes: None,
-                                });
+                    if let Ok(mut query_mh) =
+                        <SigStore as TryInto<KmerMinHash>>::try_into(query_sig)
+                    {
+                        stoplist::apply(stoplist, &mut query_mh);
+                        results = revindex_search(
+                            db,
+                            &query_mh,
+                            &query_name,
+                            &query_md5,
+                            minimum_containment,
+                            output_all_comparisons,
+                            full_results,
+                            exclude_self_matches,
+                            &size_cache,
+                            &names,
+                            abundances,
+                        );
+                        if let Some(min_overlap) = min_overlap {
+                            results.retain(|r| min_overlap.passes(r));
+                        }
+                        if let Some(aliases) = aliases {
+                            for r in results.iter_mut() {
+                                r.match_name = names.intern(aliases.get(&r.match_name));
                             }
                         }
                     } else {
                         eprintln!("WARNING: no compatible sketches in path '{}'", query_file);
+                        ctx.record_skipped(&query_file, "no compatible sketches");
                         let _ = skipped_paths.fetch_add(1, atomic::Ordering::SeqCst);
                     }
                     if results.is_empty() {
@@ -198,6 +420,7 @@ pub(crate) fn manysearch_rocksdb_obj(
                         "WARNING: could not load sketches from path '{}'",
                         record.internal_location()
                     );
+                    ctx.record_failed(record.internal_location().as_str(), &err);
                     None
                 }
             }
@@ -221,3 +444,23 @@ pub(crate) fn manysearch_rocksdb_obj(
 
     Ok((i, skipped_paths, failed_paths))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_size_cache_evicts_least_recently_used() {
+        let cache = MatchSizeCache::new(2);
+        cache.insert(1, 1000, 10);
+        cache.insert(2, 1000, 20);
+        // reading 1 makes 2 the least recently used entry.
+        assert_eq!(cache.get(1, 1000), Some(10));
+        cache.insert(3, 1000, 30);
+        assert_eq!(cache.get(2, 1000), None);
+        assert_eq!(cache.get(1, 1000), Some(10));
+        assert_eq!(cache.get(3, 1000), Some(30));
+        // sizes depend on the scaled they were downsampled to.
+        assert_eq!(cache.get(1, 2000), None);
+    }
+}
@@ -3,20 +3,74 @@ use anyhow::{anyhow, Result};
 use rayon::prelude::*;
 
 use camino::Utf8Path as Path;
-use needletail::parse_fastx_file;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt::Display;
 use std::sync::atomic;
 use std::sync::atomic::AtomicUsize;
+use std::sync::Mutex;
 
-use crate::utils::buildutils::{BuildCollection, MultiSelect, MultiSelection};
-use crate::utils::{load_fasta_fromfile, zipwriter_handle};
+use crate::sketch::SketchParams;
+use crate::utils::buildutils::{open_fastx, BuildCollection, DuplicatePolicy};
+use crate::utils::{
+    finish_csv, load_fasta_fromfile, open_stdout_or_file, zipwriter_handle_on_duplicate,
+};
 
+#[derive(Serialize, PartialEq, Eq, PartialOrd, Ord)]
+struct SketchFailure {
+    name: String,
+    filename: String,
+    error: String,
+}
+
+/// Inputs that could not be read or sketched, written to `failures_out`.
+#[derive(Default)]
+struct SketchFailures(Mutex<Vec<SketchFailure>>);
+
+impl SketchFailures {
+    fn record(&self, name: &str, filename: impl Display, error: impl Display) {
+        self.0.lock().unwrap().push(SketchFailure {
+            name: name.to_string(),
+            filename: filename.to_string(),
+            error: error.to_string(),
+        });
+    }
+
+    fn write(self, output: &str) -> Result<()> {
+        // failures are recorded from a parallel loop; sort for stable output.
+        let mut failures = self.0.into_inner().unwrap();
+        failures.sort();
+
+        let mut writer = csv::Writer::from_writer(open_stdout_or_file(Some(output.into())));
+        for failure in failures.iter() {
+            writer.serialize(failure)?;
+        }
+        finish_csv(writer)?.commit()?;
+
+        eprintln!(
+            "Wrote {} sketching failures to '{}'",
+            failures.len(),
+            output
+        );
+        Ok(())
+    }
+}
+
+/// Sketch every sample in `filelist`. An input that can't be read or
+/// sketched doesn't stop the run: its sample is left out of `output`
+/// (or, with `singleton`, the records after the error are), and it is
+/// written to `failures_out` as CSV with `name`, `filename`, and `error`
+/// columns. Sketches with the same name and parameters are handled
+/// according to `on_duplicate`. Returns the number of inputs that failed.
 pub fn manysketch(
     filelist: String,
     param_str: String,
     output: String,
     singleton: bool,
     force: bool,
-) -> Result<()> {
+    failures_out: Option<String>,
+    on_duplicate: DuplicatePolicy,
+) -> Result<usize> {
     let (fileinfo, n_fastas) = match load_fasta_fromfile(filelist, force) {
         Ok((file_info, n_fastas)) => (file_info, n_fastas),
         Err(e) => bail!("Could not load fromfile csv. Underlying error: {}", e),
@@ -27,11 +81,23 @@ pub fn manysketch(
         bail!("No files to load, exiting.");
     }
 
+    // without --singleton, sketch names come from the CSV, so duplicates
+    // can be caught before sketching anything.
+    if !singleton && on_duplicate == DuplicatePolicy::Error {
+        let mut seen = HashSet::new();
+        for fastadata in fileinfo.iter() {
+            if !seen.insert((&fastadata.name, fastadata.input_type.to_lowercase())) {
+                bail!(
+                    "more than one row has name '{}' and moltype {}; use --on-duplicate merge or suffix to allow this",
+                    fastadata.name,
+                    fastadata.input_type
+                );
+            }
+        }
+    }
+
     // if output doesn't end in zip, bail
-    if Path::new(&output)
-        .extension()
-        .map_or(true, |ext| ext != "zip")
-    {
+    if Path::new(&output).extension() != Some("zip") {
         bail!("Output must be a zip file.");
     }
 
@@ -40,24 +106,19 @@ pub fn manysketch(
         std::sync::mpsc::sync_channel::<Option<BuildCollection>>(rayon::current_num_threads());
 
     // & spawn a thread that is dedicated to printing to a buffered output
-    let thrd = zipwriter_handle(recv, output);
-
-    // params --> buildcollection
-    let sig_template_result = BuildCollection::from_param_str(param_str.as_str());
-    let sig_templates = match sig_template_result {
-        Ok(sig_templates) => sig_templates,
-        Err(e) => {
-            bail!("Failed to parse params string: {}", e);
-        }
-    };
+    let thrd = zipwriter_handle_on_duplicate(recv, output, Some(on_duplicate));
+
+    // params --> signature templates
+    let params = SketchParams::parse(&param_str)?;
 
     // print sig templates to build
-    let _params = sig_templates.summarize_params();
+    params.summarize();
 
     // iterate over filelist_paths
     let processed_fastas = AtomicUsize::new(0);
     let failed_paths = AtomicUsize::new(0);
     let skipped_paths: AtomicUsize = AtomicUsize::new(0);
+    let failures = SketchFailures::default();
 
     // set reporting threshold at every 5% or every 1 fasta, whichever is larger)
     let reporting_threshold = std::cmp::max(n_fastas / 20, 1);
@@ -68,14 +129,13 @@ pub fn manysketch(
             let name = &fastadata.name;
             let filenames = &fastadata.paths;
             let input_moltype = &fastadata.input_type;
-            let mut sigs = sig_templates.clone();
+            let mut sample_failed = false;
             // filter sig templates for this fasta by moltype
             // atm, we only do DNA->DNA, prot->prot Future -- figure out if we need to modify to allow translate/skip
-            let multiselection = MultiSelection::from_input_moltype(input_moltype.as_str())
-                .expect("could not build selection from input moltype");
-
-            sigs.select(&multiselection)
-                .expect("could not select on sig_templates");
+            let sig_templates = params
+                .templates(input_moltype)
+                .expect("could not select sig templates for input moltype");
+            let mut sigs = sig_templates.clone();
 
             // if no sigs to build, skip this iteration
             if sigs.is_empty() {
@@ -88,7 +148,7 @@ pub fn manysketch(
                 // increment processed_fastas counter; make 1-based for % reporting
                 let i = processed_fastas.fetch_add(1, atomic::Ordering::SeqCst);
                 // progress report at threshold
-                if (i + 1) % reporting_threshold == 0 {
+                if (i + 1).is_multiple_of(reporting_threshold) {
                     let percent_processed = (((i + 1) as f64 / n_fastas as f64) * 100.0).round();
                     eprintln!(
                         "Starting file {}/{} ({}%)",
@@ -99,12 +159,13 @@ pub fn manysketch(
                 }
                 if singleton {
                     // Open fasta file reader
-                    let mut reader = match parse_fastx_file(filename) {
+                    let mut reader = match open_fastx(filename.as_str()) {
                         Ok(r) => r,
                         Err(err) => {
                             eprintln!("Error opening file {}: {:?}", filename, err);
                             failed_paths.fetch_add(1, atomic::Ordering::SeqCst);
-                            return None;
+                            failures.record(name, filename, format!("{:#}", err));
+                            continue;
                         }
                     };
 
@@ -120,7 +181,9 @@ pub fn manysketch(
                                         "Error building signatures from file: {}, {:?}",
                                         filename, err
                                     );
-                                    // do we want to keep track of singleton sigs that fail? if so, how?
+                                    failed_paths.fetch_add(1, atomic::Ordering::SeqCst);
+                                    failures.record(name, filename, format!("{:#}", err));
+                                    break;
                                 }
                                 // send singleton sigs for writing
                                 if let Err(e) = send.send(Some(sigs)) {
@@ -129,7 +192,13 @@ pub fn manysketch(
                                 }
                                 sigs = sig_templates.clone();
                             }
-                            Err(err) => eprintln!("Error while processing record: {:?}", err),
+                            Err(err) => {
+                                // the rest of a malformed file can't be trusted.
+                                eprintln!("Error while processing record: {:?}", err);
+                                failed_paths.fetch_add(1, atomic::Ordering::SeqCst);
+                                failures.record(name, filename, err);
+                                break;
+                            }
                         }
                     }
                 } else {
@@ -145,12 +214,15 @@ pub fn manysketch(
                                 filename, err
                             );
                             failed_paths.fetch_add(1, atomic::Ordering::SeqCst);
+                            failures.record(name, filename, format!("{:#}", err));
+                            sample_failed = true;
                         }
                     }
                 }
             }
-            // if singleton sketches, they have already been written; only send aggregated sketches to be written
-            if singleton {
+            // if singleton sketches, they have already been written; only send aggregated sketches to be written.
+            // don't write partial sketches for samples with a failed input.
+            if singleton || sample_failed {
                 None
             } else {
                 Some(sigs)
@@ -177,10 +249,10 @@ pub fn manysketch(
     }
 
     // join the writer thread
-    if let Err(e) = thrd
+    let write_result = thrd
         .join()
-        .unwrap_or_else(|e| Err(anyhow!("Thread panicked: {:?}", e)))
-    {
+        .unwrap_or_else(|e| Err(anyhow!("Thread panicked: {:?}", e)));
+    if let Err(e) = &write_result {
         eprintln!("Error in sigwriter thread: {:?}", e);
     }
 
@@ -190,6 +262,17 @@ pub fn manysketch(
 
     let failed_paths = failed_paths.load(atomic::Ordering::SeqCst);
 
+    if failed_paths > 0 {
+        if let Some(failures_out) = failures_out {
+            failures.write(&failures_out)?;
+        }
+    }
+
+    // e.g. a duplicate name with --on-duplicate error; no zip was written.
+    if let Err(e) = write_result {
+        bail!("Could not write signatures: {}", e);
+    }
+
     if failed_paths == i {
         bail!("Could not load fasta files: no signatures created.");
     }
@@ -211,5 +294,5 @@ pub fn manysketch(
         );
     }
 
-    Ok(())
+    Ok(failed_paths)
 }
@@ -0,0 +1,88 @@
+/// merge: union sketches by group, summing abundances.
+use anyhow::Result;
+use rayon::prelude::*;
+
+use sourmash::selection::Selection;
+use sourmash::signature::{Signature, SigsTrait};
+use sourmash::sketch::minhash::KmerMinHash;
+use sourmash::sketch::Sketch;
+
+use crate::utils::buildutils::BuildCollection;
+use crate::utils::{group_sketches, load_groups, load_sketches_at_common_scaled};
+
+/// Merge the sketches in each group given by `groups_csv`, and write one
+/// signature per group to `output`.
+pub fn merge(
+    siglist: String,
+    selection: Selection,
+    groups_csv: String,
+    output: String,
+    allow_failed_sigpaths: bool,
+) -> Result<()> {
+    let (sketches, _scaled) =
+        load_sketches_at_common_scaled(&siglist, &selection, allow_failed_sigpaths)?;
+
+    let groups = load_groups(&groups_csv)?;
+    let (groups, n_ungrouped) = group_sketches(&sketches, &groups);
+    if n_ungrouped > 0 {
+        eprintln!(
+            "WARNING: {} sketches are not in any group, and will be ignored.",
+            n_ungrouped
+        );
+    }
+    if groups.is_empty() {
+        bail!("No sketches matched the names in '{}'", groups_csv);
+    }
+
+    let sigs = groups
+        .par_iter()
+        .map(|(group, idxs)| {
+            let minhashes: Vec<&KmerMinHash> =
+                idxs.iter().map(|idx| &sketches[*idx].minhash).collect();
+            let mh = merge_minhashes(&minhashes)?;
+            eprintln!(
+                "'{}': {} hashes from {} sketches",
+                group,
+                mh.size(),
+                minhashes.len()
+            );
+
+            let mut sig = Signature::default();
+            sig.push(Sketch::MinHash(mh));
+            sig.set_name(group);
+            sig.set_filename(&siglist);
+            Ok(sig)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut collection = BuildCollection::new();
+    for sig in sigs {
+        collection.add_sig(sig)?;
+    }
+    collection.write_sigs(&output)?;
+
+    eprintln!(
+        "DONE. Wrote {} merged signatures to '{}'",
+        collection.size(),
+        output
+    );
+
+    Ok(())
+}
+
+/// Return the union of all sketches. Abundances are summed if all sketches
+/// track abundance; otherwise the result is flat.
+pub(crate) fn merge_minhashes(minhashes: &[&KmerMinHash]) -> Result<KmerMinHash> {
+    let Some((first, rest)) = minhashes.split_first() else {
+        bail!("No sketches to merge.");
+    };
+
+    let mut mh = (*first).clone();
+    if rest.iter().any(|other| !other.track_abundance()) {
+        mh.disable_abundance();
+    }
+    for other in rest {
+        mh.merge(other)?;
+    }
+    Ok(mh)
+}
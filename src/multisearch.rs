@@ -1,27 +1,45 @@
 /// multisearch: massively parallel in-memory sketch search.
-use anyhow::Result;
+use anyhow::{bail, Result};
 use rayon::prelude::*;
 use sourmash::prelude::Select;
 use sourmash::selection::Selection;
 use sourmash::signature::SigsTrait;
 use sourmash::sketch::minhash::KmerMinHash;
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
 use std::sync::atomic;
 use std::sync::atomic::AtomicUsize;
+use std::sync::mpsc::SyncSender;
+use std::sync::{Arc, Mutex};
 
 use crate::search_significance::{
     compute_inverse_document_frequency, get_hash_frequencies, get_prob_overlap,
     get_term_frequency_inverse_document_frequency, merge_all_minhashes, Normalization,
 };
+#[cfg(feature = "python")]
+use crate::utils::columns::ColumnSelection;
+#[cfg(feature = "python")]
+use crate::utils::coverage::CoverageReport;
+use crate::utils::dedup;
+use crate::utils::fdr;
+#[cfg(feature = "python")]
+use crate::utils::graph::{graph_csvwriter_thread, GraphOptions, SimilarityGraph};
 use crate::utils::multicollection::SmallSignature;
-use crate::utils::{csvwriter_thread, load_collection, MultiSearchResult, ReportType};
+use crate::utils::profile::Stage;
+use crate::utils::querygroups::QueryGroups;
+#[cfg(feature = "python")]
+use crate::utils::require_abundance;
+use crate::utils::stoplist::{self, Stoplist};
+use crate::utils::{
+    collect_results, CollectionSource, MultiSearchResult, ReportType, RunContext, SearchControl,
+};
 use sourmash::ani_utils::ani_from_containment;
 
 type OverlapStatsReturn = (
     f64,
     HashMap<u64, f64>,
     HashMap<u64, f64>,
-    HashMap<String, HashMap<u64, f64>>,
+    HashMap<Arc<str>, HashMap<u64, f64>>,
     HashMap<u64, f64>,
 );
 
@@ -34,14 +52,86 @@ struct ProbOverlapStats {
     tf_idf_score: f64,
 }
 
+/// A match kept by `--knn`. Ordered so that the least similar match (and,
+/// among equals, the latest against sketch) is the top of the max-heap,
+/// to be evicted first.
+struct KnnEntry {
+    against_idx: usize,
+    result: MultiSearchResult,
+}
+
+impl PartialEq for KnnEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for KnnEntry {}
+
+impl PartialOrd for KnnEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for KnnEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .result
+            .max_containment
+            .total_cmp(&self.result.max_containment)
+            .then(self.against_idx.cmp(&other.against_idx))
+    }
+}
+
+/// Keep the `k` most similar matches for each query.
+struct KnnHeaps {
+    k: usize,
+    heaps: Vec<Mutex<BinaryHeap<KnnEntry>>>,
+}
+
+impl KnnHeaps {
+    fn new(k: usize, n_queries: usize) -> Self {
+        KnnHeaps {
+            k,
+            heaps: (0..n_queries)
+                .map(|_| Mutex::new(BinaryHeap::with_capacity(k + 1)))
+                .collect(),
+        }
+    }
+
+    fn push(&self, query_idx: usize, against_idx: usize, result: MultiSearchResult) {
+        let mut heap = self.heaps[query_idx].lock().unwrap();
+        heap.push(KnnEntry {
+            against_idx,
+            result,
+        });
+        if heap.len() > self.k {
+            heap.pop();
+        }
+    }
+
+    /// Matches in query order, most similar first within each query.
+    fn into_results(self) -> impl Iterator<Item = MultiSearchResult> {
+        self.heaps.into_iter().flat_map(|heap| {
+            heap.into_inner()
+                .unwrap()
+                .into_sorted_vec()
+                .into_iter()
+                .map(|entry| entry.result)
+        })
+    }
+}
+
 /// Computes probability overlap statistics for a single pair of signatures
+#[allow(clippy::too_many_arguments)]
 fn compute_single_prob_overlap(
     query: &SmallSignature,
     against: &SmallSignature,
     n_comparisons: f64,
     query_merged_frequencies: &HashMap<u64, f64>,
     against_merged_frequencies: &HashMap<u64, f64>,
-    query_term_frequencies: &HashMap<String, HashMap<u64, f64>>,
+    query_term_frequencies: &HashMap<Arc<str>, HashMap<u64, f64>>,
     inverse_document_frequency: &HashMap<u64, f64>,
     containment_query_in_target: f64,
 ) -> ProbOverlapStats {
@@ -120,7 +210,7 @@ fn compute_prob_overlap_stats(
                 get_hash_frequencies(&query.minhash, Some(Normalization::L2)),
             )
         })
-        .collect::<HashMap<String, HashMap<u64, f64>>>();
+        .collect::<HashMap<Arc<str>, HashMap<u64, f64>>>();
     eprintln!("\tDone.\n");
 
     (
@@ -136,10 +226,11 @@ fn compute_prob_overlap_stats(
 ///
 /// Note: this function loads all _queries_ into memory, and iterates over
 /// database once.
-
+#[cfg(feature = "python")]
+#[allow(clippy::too_many_arguments)]
 pub fn multisearch(
-    query_filepath: String,
-    against_filepath: String,
+    query_source: CollectionSource,
+    against_source: CollectionSource,
     threshold: f64,
     selection: Selection,
     allow_failed_sigpaths: bool,
@@ -147,19 +238,179 @@ pub fn multisearch(
     estimate_prob_overlap: bool,
     output_all_comparisons: bool,
     output: Option<String>,
+    control: SearchControl,
+    graph: Option<GraphOptions>,
+    coverage_report: Option<String>,
+    knn: Option<usize>,
+    angular_similarity: bool,
+    exclude_self_matches: bool,
+    columns: ColumnSelection,
+    query_groups: Option<(String, String)>,
+    estimate_fdr: bool,
+    stoplist: Option<&Stoplist>,
+    ctx: &RunContext,
 ) -> Result<()> {
+    if let Some(g) = &graph {
+        g.check_columns(estimate_ani, angular_similarity)?;
+    }
+    if knn == Some(0) {
+        bail!("--knn must be at least 1");
+    }
+    if estimate_fdr {
+        fdr::check_output(output.as_deref(), ctx.output_mode(output.as_deref()))?;
+    }
+
+    let (queries, againsts, expected_scaled, ksize) = load_multisearch_sketches(
+        &query_source,
+        &against_source,
+        selection,
+        allow_failed_sigpaths,
+        stoplist,
+        ctx,
+    )?;
+    if angular_similarity {
+        require_abundance(&queries, "query")?;
+        require_abundance(&againsts, "against")?;
+    }
+    let groups = match &query_groups {
+        Some((groups_csv, _)) => Some(QueryGroups::load(groups_csv, &queries)?),
+        None => None,
+    };
+
+    // set up a multi-producer, single-consumer channel.
+    let (send, recv) =
+        std::sync::mpsc::sync_channel::<MultiSearchResult>(rayon::current_num_threads());
+
+    // & spawn a thread that is dedicated to printing to a buffered output
+    let thrd = graph_csvwriter_thread(
+        recv,
+        output.clone(),
+        columns,
+        graph.map(SimilarityGraph::new),
+        ctx,
+    )?;
+
+    let n_processed = ctx.time(Stage::Comparison, || {
+        multisearch_obj(
+            &queries,
+            &againsts,
+            threshold,
+            estimate_ani,
+            estimate_prob_overlap,
+            output_all_comparisons,
+            send,
+            expected_scaled,
+            ksize,
+            &control,
+            knn,
+            angular_similarity,
+            exclude_self_matches,
+            groups.as_ref(),
+            estimate_fdr,
+            ctx,
+        )
+    })?;
+
+    let graph = thrd.join().expect("Unable to join internal thread");
+    if let Some(graph) = graph {
+        ctx.time(Stage::Writing, || graph.write())?;
+    }
+
+    // q-values need all of the p-values, so are added in a second pass.
+    if let Some(path) = output.as_deref().filter(|_| estimate_fdr) {
+        ctx.time(Stage::Writing, || fdr::add_qvalues(path, n_processed))?;
+    }
+
+    if let (Some(groups), Some((_, path))) = (groups, query_groups) {
+        ctx.time(Stage::Writing, || groups.write(&path))?;
+    }
+
+    if let Some(path) = coverage_report {
+        let coverage = CoverageReport::new(&queries);
+        againsts
+            .par_iter()
+            .for_each(|a| coverage.add_against(&a.name, &a.md5sum, &a.minhash));
+        ctx.time(Stage::Writing, || coverage.write(&queries, &path))?;
+    }
+
+    ctx.record_processed(n_processed);
+    stoplist::report(stoplist);
+    eprintln!("DONE. Processed {} comparisons", n_processed);
+
+    Ok(())
+}
+
+/// Search many queries against a list of signatures, returning the
+/// results instead of writing them to a CSV file.
+#[allow(clippy::too_many_arguments)]
+pub fn multisearch_collect(
+    query_source: CollectionSource,
+    against_source: CollectionSource,
+    threshold: f64,
+    selection: Selection,
+    allow_failed_sigpaths: bool,
+    estimate_ani: bool,
+    estimate_prob_overlap: bool,
+    output_all_comparisons: bool,
+) -> Result<Vec<MultiSearchResult>> {
+    let (queries, againsts, expected_scaled, ksize) = load_multisearch_sketches(
+        &query_source,
+        &against_source,
+        selection,
+        allow_failed_sigpaths,
+        None,
+        &RunContext::default(),
+    )?;
+
+    let (n_processed, results) = collect_results(|send| {
+        multisearch_obj(
+            &queries,
+            &againsts,
+            threshold,
+            estimate_ani,
+            estimate_prob_overlap,
+            output_all_comparisons,
+            send,
+            expected_scaled,
+            ksize,
+            &SearchControl::default(),
+            None,
+            false,
+            false,
+            None,
+            false,
+            &RunContext::default(),
+        )
+    })?;
+
+    eprintln!("DONE. Processed {} comparisons", n_processed);
+
+    Ok(results)
+}
+
+/// Load query and against sketches into memory, at a common scaled.
+///
+/// Returns (queries, againsts, scaled, ksize).
+fn load_multisearch_sketches(
+    query_source: &CollectionSource,
+    against_source: &CollectionSource,
+    selection: Selection,
+    allow_failed_sigpaths: bool,
+    stoplist: Option<&Stoplist>,
+    ctx: &RunContext,
+) -> Result<(Vec<SmallSignature>, Vec<SmallSignature>, u32, f64)> {
     // Load all queries into memory at once.
-    let query_collection = load_collection(
-        &query_filepath,
-        &selection,
+    let (query_collection, selection) = query_source.load_and_complete_selection(
+        selection,
         ReportType::Query,
         allow_failed_sigpaths,
+        ctx,
     )?;
 
     let expected_scaled = match selection.scaled() {
         Some(s) => s,
         None => {
-            let s = *query_collection.max_scaled().expect("no records!?") as u32;
+            let s = *query_collection.max_scaled().expect("no records!?");
             eprintln!(
                 "Setting scaled={} based on max scaled in query collection",
                 s
@@ -174,37 +425,28 @@ pub fn multisearch(
     new_selection.set_scaled(expected_scaled);
 
     // update selection with new scaled.
-    let query_collection = query_collection.select(&new_selection)?;
+    let query_collection =
+        ctx.time(Stage::Selection, || query_collection.select(&new_selection))?;
 
-    let queries: Vec<SmallSignature> = query_collection.load_sketches()?;
+    let mut queries: Vec<SmallSignature> =
+        ctx.time(Stage::Loading, || query_collection.load_sketches())?;
+    stoplist::apply_sketches(stoplist, &mut queries);
 
     // Load all against sketches into memory at once.
-    let against_collection = load_collection(
-        &against_filepath,
+    let against_collection = against_source.load(
         &new_selection,
         ReportType::Against,
         allow_failed_sigpaths,
+        ctx,
     )?;
 
-    let againsts: Vec<SmallSignature> = against_collection.load_sketches()?;
-
-    let n_processed = multisearch_obj(
-        &queries,
-        &againsts,
-        threshold,
-        estimate_ani,
-        estimate_prob_overlap,
-        output_all_comparisons,
-        output,
-        expected_scaled,
-        ksize,
-    )?;
-
-    eprintln!("DONE. Processed {} comparisons", n_processed);
+    let againsts: Vec<SmallSignature> =
+        ctx.time(Stage::Loading, || against_collection.load_sketches())?;
 
-    Ok(())
+    Ok((queries, againsts, expected_scaled, ksize))
 }
 
+#[allow(clippy::too_many_arguments, clippy::result_large_err)]
 pub(crate) fn multisearch_obj(
     queries: &Vec<SmallSignature>,
     againsts: &Vec<SmallSignature>,
@@ -212,9 +454,16 @@ pub(crate) fn multisearch_obj(
     estimate_ani: bool,
     estimate_prob_overlap: bool,
     output_all_comparisons: bool,
-    output: Option<String>,
+    send: SyncSender<MultiSearchResult>,
     expected_scaled: u32,
     ksize: f64,
+    control: &SearchControl,
+    knn: Option<usize>,
+    angular_similarity: bool,
+    exclude_self_matches: bool,
+    query_groups: Option<&QueryGroups>,
+    estimate_fdr: bool,
+    ctx: &RunContext,
 ) -> Result<usize> {
     let (
         n_comparisons,
@@ -223,7 +472,7 @@ pub(crate) fn multisearch_obj(
         query_term_frequencies,
         inverse_document_frequency,
     ) = if estimate_prob_overlap {
-        compute_prob_overlap_stats(&queries, &againsts)
+        compute_prob_overlap_stats(queries, againsts)
     } else {
         (
             0.0,
@@ -234,29 +483,39 @@ pub(crate) fn multisearch_obj(
         )
     };
 
-    // set up a multi-producer, single-consumer channel.
-    let (send, recv) =
-        std::sync::mpsc::sync_channel::<MultiSearchResult>(rayon::current_num_threads());
-
-    // // & spawn a thread that is dedicated to printing to a buffered output
-    let thrd = csvwriter_thread(recv, output);
-
     //
     // Main loop: iterate (in parallel) over all search signature paths,
     // loading them individually and searching them. Stuff results into
-    // the writer thread above.
+    // the channel.
     //
 
     let processed_cmp = AtomicUsize::new(0);
+    let n_total = queries.len() * againsts.len();
+    if estimate_fdr {
+        eprintln!(
+            "Estimating chance overlaps over {} comparisons at scaled={}",
+            n_total, expected_scaled
+        );
+    }
+    let aliases = ctx.aliases();
 
-    let send = againsts
+    // with --knn, matches are held back and only the best are sent.
+    let knn_heaps = knn.map(|k| KnnHeaps::new(k, queries.len()));
+
+    let send_result = againsts
         .par_iter()
-        .filter_map(|against| {
+        .enumerate()
+        .filter_map(|(against_idx, against)| {
+            if control.is_cancelled() {
+                return None;
+            }
+
             let mut results = vec![];
+            let mut group_acc = query_groups.map(|g| g.accumulator());
             // search for matches & save containment.
-            for query in queries.iter() {
+            for (query_idx, query) in queries.iter().enumerate() {
                 let i = processed_cmp.fetch_add(1, atomic::Ordering::SeqCst);
-                if i % 100000 == 0 && i > 0 {
+                if i.is_multiple_of(100000) && i > 0 {
                     eprintln!("Processed {} comparisons", i);
                 }
 
@@ -280,6 +539,18 @@ pub(crate) fn multisearch_obj(
 
                 let containment_query_in_target = overlap / query_size;
 
+                if exclude_self_matches && query.md5sum == against.md5sum {
+                    continue;
+                }
+
+                if let Some(acc) = group_acc.as_mut() {
+                    acc.add(
+                        query_idx,
+                        containment_query_in_target,
+                        containment_query_in_target > threshold,
+                    );
+                }
+
                 if containment_query_in_target > threshold || output_all_comparisons {
                     let containment_target_in_query = overlap / target_size;
                     let max_containment =
@@ -324,7 +595,24 @@ pub(crate) fn multisearch_obj(
                         max_containment_ani = Some(f64::max(qani, mani));
                     }
 
-                    results.push(MultiSearchResult {
+                    let angular_similarity = if angular_similarity {
+                        query.minhash.angular_similarity(&against.minhash).ok()
+                    } else {
+                        None
+                    };
+
+                    let overlap_pvalue = estimate_fdr.then(|| {
+                        fdr::overlap_pvalue(
+                            overlap as u64,
+                            query_size as u64,
+                            target_size as u64,
+                            expected_scaled,
+                            query.minhash.ksize() as u32,
+                            &query.minhash.hash_function(),
+                        )
+                    });
+
+                    let result = MultiSearchResult {
                         query_name: query.name.clone(),
                         query_md5: query.md5sum.clone(),
                         match_name: against.name.clone(),
@@ -336,18 +624,37 @@ pub(crate) fn multisearch_obj(
                         max_containment,
                         jaccard,
                         intersect_hashes: overlap,
+                        intersect_bp: overlap as u64 * query.minhash.scaled() as u64,
+                        query_n_hashes: query_size as u64,
+                        match_n_hashes: target_size as u64,
+                        query_bp: query_size as u64 * query.minhash.scaled() as u64,
+                        match_bp: target_size as u64 * query.minhash.scaled() as u64,
                         query_containment_ani,
                         match_containment_ani,
                         average_containment_ani,
                         max_containment_ani,
+                        angular_similarity,
                         prob_overlap,
                         prob_overlap_adjusted,
                         containment_adjusted,
                         containment_adjusted_log10,
                         tf_idf_score,
-                    })
+                        overlap_pvalue,
+                        expected_false_positives: overlap_pvalue.map(|p| p * n_total as f64),
+                        bh_qvalue: None,
+                        match_aliases: dedup::match_aliases(aliases.as_ref(), &against.md5sum),
+                    };
+                    match &knn_heaps {
+                        Some(heaps) => heaps.push(query_idx, against_idx, result),
+                        None => results.push(result),
+                    }
                 }
             }
+            if let Some(acc) = group_acc {
+                acc.finish(queries, against, output_all_comparisons);
+            }
+            control.add_progress(queries.len(), n_total);
+
             if results.is_empty() {
                 None
             } else {
@@ -355,11 +662,20 @@ pub(crate) fn multisearch_obj(
             }
         })
         .flatten()
-        .try_for_each_with(send, |s, m| s.send(m));
+        .try_for_each_with(send.clone(), |s, m| s.send(m));
 
     // do some cleanup and error handling -
-    send.expect("Unable to send internal data");
-    thrd.join().expect("Unable to join internal thread");
+    send_result.expect("Unable to send internal data");
+
+    if control.is_cancelled() {
+        bail!("search cancelled");
+    }
+
+    if let Some(heaps) = knn_heaps {
+        for result in heaps.into_results() {
+            send.send(result).expect("Unable to send internal data");
+        }
+    }
 
     // done!
     let i: usize = processed_cmp.fetch_max(0, atomic::Ordering::SeqCst);
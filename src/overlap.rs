@@ -0,0 +1,99 @@
+/// overlap: count the hashes in each combination of sketches, for UpSet plots.
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+
+use sourmash::selection::Selection;
+
+use crate::utils::{finish_csv, load_sketches_at_common_scaled, open_stdout_or_file};
+
+/// Membership patterns are kept as bitmasks, one bit per sketch.
+pub const MAX_SKETCHES: usize = 64;
+
+/// For every hash in any sketch, find exactly which sketches contain it,
+/// and write the number of hashes with each membership pattern to
+/// `output` as CSV: one True/False column per sketch (named after the
+/// sketch, in collection order), then `degree` (the number of sketches in
+/// the pattern), `n_hashes`, and `bp`. Rows are sorted by decreasing
+/// `n_hashes`.
+pub fn overlap(
+    siglist: String,
+    selection: Selection,
+    output: Option<String>,
+    allow_failed_sigpaths: bool,
+) -> Result<()> {
+    let (sketches, scaled) =
+        load_sketches_at_common_scaled(&siglist, &selection, allow_failed_sigpaths)?;
+
+    if sketches.is_empty() {
+        bail!("No sketches loaded from '{}'", siglist);
+    }
+    if sketches.len() > MAX_SKETCHES {
+        bail!(
+            "overlap supports at most {} sketches, but '{}' has {}; use 'intersect' or 'pairwise' for larger collections",
+            MAX_SKETCHES,
+            siglist,
+            sketches.len()
+        );
+    }
+
+    // names become column headers, so they must be distinct.
+    let mut seen = HashSet::new();
+    for sketch in sketches.iter() {
+        if !seen.insert(sketch.name.as_ref()) {
+            bail!(
+                "more than one sketch is named '{}'; overlap columns are named after sketches, so names must be unique",
+                sketch.name
+            );
+        }
+    }
+
+    let mut membership: HashMap<u64, u64> = HashMap::new();
+    for (idx, sketch) in sketches.iter().enumerate() {
+        for hash in sketch.minhash.iter_mins() {
+            *membership.entry(*hash).or_default() |= 1 << idx;
+        }
+    }
+
+    let mut counts: HashMap<u64, u64> = HashMap::new();
+    for pattern in membership.values() {
+        *counts.entry(*pattern).or_default() += 1;
+    }
+
+    let mut rows: Vec<(u64, u64)> = counts.into_iter().collect();
+    rows.sort_by_key(|&(pattern, n_hashes)| (std::cmp::Reverse(n_hashes), pattern));
+
+    let mut writer = csv::Writer::from_writer(open_stdout_or_file(output.clone()));
+    let mut header: Vec<&str> = sketches.iter().map(|s| s.name.as_ref()).collect();
+    header.extend(["degree", "n_hashes", "bp"]);
+    writer.write_record(&header)?;
+
+    for (pattern, n_hashes) in rows.iter() {
+        let mut record: Vec<String> = (0..sketches.len())
+            .map(|idx| {
+                if pattern & (1 << idx) != 0 {
+                    "True"
+                } else {
+                    "False"
+                }
+                .to_string()
+            })
+            .collect();
+        record.push(pattern.count_ones().to_string());
+        record.push(n_hashes.to_string());
+        record.push((n_hashes * scaled as u64).to_string());
+        writer.write_record(&record)?;
+    }
+    finish_csv(writer)?.commit()?;
+
+    eprintln!(
+        "DONE. Found {} distinct hashes in {} combinations of {} sketches.",
+        membership.len(),
+        rows.len(),
+        sketches.len()
+    );
+    if let Some(output) = output {
+        eprintln!("Wrote overlap counts to '{}'", output);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,240 @@
+/// overlap_summary: count the distinct hashes in, and shared by, two collections.
+use anyhow::Result;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::sync::atomic;
+use std::sync::atomic::AtomicUsize;
+
+use sourmash::sketch::minhash::KmerMinHash;
+
+use crate::utils::{
+    build_selection, finish_csv, load_collection, open_stdout_or_file, MultiCollection, ReportType,
+    RunContext,
+};
+
+/// The hashes of the two collections at one ksize.
+#[derive(Serialize)]
+struct KsizeSummary {
+    ksize: u8,
+    moltype: String,
+    scaled: u32,
+    query_sketches: usize,
+    against_sketches: usize,
+    query_hashes: usize,
+    against_hashes: usize,
+    intersect_hashes: usize,
+    union_hashes: usize,
+    intersect_bp: u64,
+    union_bp: u64,
+    jaccard: f64,
+    query_containment: f64,
+    against_containment: f64,
+}
+
+/// For each ksize, find the distinct hashes in all sketches of `query`
+/// and of `against`, and write their sizes, union, and intersection to
+/// `output` as CSV, one row per ksize. Only hash counts are kept - no
+/// merged sketches or per-sketch results are built.
+pub fn overlap_summary(
+    query: String,
+    against: String,
+    ksizes: Vec<u8>,
+    scaled: Option<u32>,
+    moltype: String,
+    output: Option<String>,
+    allow_failed_sigpaths: bool,
+) -> Result<()> {
+    if ksizes.is_empty() {
+        bail!("Please specify at least one ksize.");
+    }
+
+    let mut writer = csv::Writer::from_writer(open_stdout_or_file(output.clone()));
+    let (mut total_query, mut total_against, mut total_intersect, mut total_union) = (0, 0, 0, 0);
+
+    for ksize in ksizes {
+        let selection = build_selection(ksize, scaled, &moltype)?;
+        let query_coll = load_collection(
+            &query,
+            &selection,
+            ReportType::Query,
+            allow_failed_sigpaths,
+            &RunContext::default(),
+        )?;
+        let against_coll = load_collection(
+            &against,
+            &selection,
+            ReportType::Against,
+            allow_failed_sigpaths,
+            &RunContext::default(),
+        )?;
+
+        // compare at the coarsest scaled in either collection.
+        let common_scaled = match scaled {
+            Some(s) => s,
+            None => {
+                let s = *query_coll
+                    .max_scaled()
+                    .into_iter()
+                    .chain(against_coll.max_scaled())
+                    .max()
+                    .expect("no records!?");
+                eprintln!("k={}: setting scaled={} based on max scaled", ksize, s);
+                s
+            }
+        };
+
+        let query_hashes = distinct_hashes(&query_coll, common_scaled)?;
+        let against_hashes = distinct_hashes(&against_coll, common_scaled)?;
+        let (intersect_hashes, union_hashes) = count_overlap(&query_hashes, &against_hashes);
+
+        let fraction = |n: usize, d: usize| if d == 0 { 0.0 } else { n as f64 / d as f64 };
+        let summary = KsizeSummary {
+            ksize,
+            moltype: moltype.clone(),
+            scaled: common_scaled,
+            query_sketches: query_coll.len(),
+            against_sketches: against_coll.len(),
+            query_hashes: query_hashes.len(),
+            against_hashes: against_hashes.len(),
+            intersect_hashes,
+            union_hashes,
+            intersect_bp: intersect_hashes as u64 * common_scaled as u64,
+            union_bp: union_hashes as u64 * common_scaled as u64,
+            jaccard: fraction(intersect_hashes, union_hashes),
+            query_containment: fraction(intersect_hashes, query_hashes.len()),
+            against_containment: fraction(intersect_hashes, against_hashes.len()),
+        };
+        eprintln!(
+            "k={}: {} query hashes, {} against hashes; {} shared, {} in total",
+            ksize, summary.query_hashes, summary.against_hashes, intersect_hashes, union_hashes
+        );
+        writer.serialize(&summary)?;
+        writer.flush()?;
+
+        total_query += summary.query_hashes;
+        total_against += summary.against_hashes;
+        total_intersect += intersect_hashes;
+        total_union += union_hashes;
+    }
+    finish_csv(writer)?.commit()?;
+
+    eprintln!(
+        "DONE. Across all ksizes: {} query hashes, {} against hashes; {} shared, {} in total.",
+        total_query, total_against, total_intersect, total_union
+    );
+    if let Some(output) = output {
+        eprintln!("Wrote overlap summary to '{}'", output);
+    }
+
+    Ok(())
+}
+
+/// The sorted, distinct hashes of all sketches in `collection`, at
+/// `scaled`. Each thread concatenates the hashes of the sketches it
+/// loads, sorts and dedups them, and the sorted lists are then merged.
+fn distinct_hashes(collection: &MultiCollection, scaled: u32) -> Result<Vec<u64>> {
+    let failed_sigs = AtomicUsize::new(0);
+
+    let hashes = collection
+        .par_iter()
+        .filter_map(|(coll, _idx, record)| match coll.sig_from_record(record) {
+            Ok(sig) => Some(sig),
+            Err(e) => {
+                eprintln!(
+                    "WARNING: could not load sketch from '{}': {}",
+                    record.internal_location(),
+                    e
+                );
+                failed_sigs.fetch_add(1, atomic::Ordering::SeqCst);
+                None
+            }
+        })
+        .map(|sig| -> Result<Vec<u64>> {
+            let mut minhash: KmerMinHash = sig.try_into()?;
+            if minhash.scaled() != scaled {
+                minhash = minhash.downsample_scaled(scaled)?;
+            }
+            Ok(minhash.mins())
+        })
+        .try_fold(Vec::new, |mut acc, mins| -> Result<Vec<u64>> {
+            acc.extend(mins?);
+            Ok(acc)
+        })
+        .map(|acc| {
+            acc.map(|mut acc| {
+                acc.sort_unstable();
+                acc.dedup();
+                acc
+            })
+        })
+        .try_reduce(Vec::new, |a, b| Ok(merge_distinct(&a, &b)))?;
+
+    let failed_sigs = failed_sigs.load(atomic::Ordering::SeqCst);
+    if failed_sigs > 0 {
+        eprintln!(
+            "WARNING: {} sketches failed to load. See error messages above.",
+            failed_sigs
+        );
+    }
+
+    Ok(hashes)
+}
+
+/// Merge two sorted lists of distinct hashes into one.
+fn merge_distinct(a: &[u64], b: &[u64]) -> Vec<u64> {
+    let mut merged = Vec::with_capacity(a.len() + b.len());
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            Ordering::Less => {
+                merged.push(a[i]);
+                i += 1;
+            }
+            Ordering::Greater => {
+                merged.push(b[j]);
+                j += 1;
+            }
+            Ordering::Equal => {
+                merged.push(a[i]);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    merged.extend_from_slice(&a[i..]);
+    merged.extend_from_slice(&b[j..]);
+    merged
+}
+
+/// The sizes of the intersection and union of two sorted lists of
+/// distinct hashes, found in a single pass over both.
+fn count_overlap(a: &[u64], b: &[u64]) -> (usize, usize) {
+    let (mut i, mut j, mut common) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            Ordering::Less => i += 1,
+            Ordering::Greater => j += 1,
+            Ordering::Equal => {
+                common += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    (common, a.len() + b.len() - common)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_and_count() {
+        let a = vec![1, 3, 5, 7];
+        let b = vec![2, 3, 7, 9, 11];
+        assert_eq!(merge_distinct(&a, &b), vec![1, 2, 3, 5, 7, 9, 11]);
+        assert_eq!(count_overlap(&a, &b), (2, 7));
+        assert_eq!(count_overlap(&a, &[]), (0, 4));
+    }
+}
@@ -1,20 +1,44 @@
 /// pairwise: massively parallel in-memory pairwise comparisons.
 use anyhow::Result;
 use rayon::prelude::*;
+use std::collections::HashMap;
 use std::sync::atomic;
 use std::sync::atomic::AtomicUsize;
+use std::sync::mpsc::SyncSender;
 
+use crate::utils::distmatrix::DistanceMatrix;
+use crate::utils::profile::Stage;
 use crate::utils::{
-    csvwriter_thread, load_collection, MultiSearchResult, ReportType, SmallSignature,
+    collect_results, CollectionSource, MultiSearchResult, ReportType, RunContext, SmallSignature,
 };
 use sourmash::ani_utils::ani_from_containment;
 use sourmash::selection::Selection;
 use sourmash::signature::SigsTrait;
 
+#[cfg(feature = "python")]
+use crate::utils::columns::ColumnSelection;
+#[cfg(feature = "python")]
+use crate::utils::distmatrix::DistanceOptions;
+#[cfg(feature = "python")]
+use crate::utils::graph::{graph_csvwriter_thread, GraphOptions, SimilarityGraph};
+#[cfg(feature = "python")]
+use crate::utils::require_abundance;
+#[cfg(feature = "python")]
+use anyhow::Context;
+#[cfg(feature = "python")]
+use std::collections::BTreeSet;
+
+/// For each sketch index, the higher-indexed sketches to compare it to.
+type CandidatePairs = HashMap<usize, Vec<usize>>;
+
+/// The number of comparisons in each unit of parallel work.
+const PAIRWISE_CHUNK_SIZE: usize = 256;
+
 /// Perform pairwise comparisons of all signatures in a list.
 ///
 /// Note: this function loads all _signatures_ into memory.
-
+#[cfg(feature = "python")]
+#[allow(clippy::too_many_arguments)]
 pub fn pairwise(
     siglist: String,
     threshold: f64,
@@ -24,19 +48,120 @@ pub fn pairwise(
     write_all: bool,
     output_all_comparisons: bool,
     output: Option<String>,
+    graph: Option<GraphOptions>,
+    candidates: Option<String>,
+    angular_similarity: bool,
+    columns: ColumnSelection,
+    distances: Option<DistanceOptions>,
+    both_directions: bool,
+    ctx: &RunContext,
 ) -> Result<()> {
-    // Load all sigs into memory at once.
-    let collection = load_collection(
+    if let Some(g) = &graph {
+        g.check_columns(estimate_ani, angular_similarity)?;
+    }
+
+    let (sketches, ksize) =
+        load_pairwise_sketches(&siglist, selection, allow_failed_sigpaths, ctx)?;
+    if angular_similarity {
+        require_abundance(&sketches, "input")?;
+    }
+
+    let candidates = candidates
+        .map(|path| load_candidate_pairs(&path, &sketches))
+        .transpose()?;
+    let distances = distances.map(|d| DistanceMatrix::new(d, sketches.len(), ksize));
+
+    // set up a multi-producer, single-consumer channel.
+    let (send, recv) =
+        std::sync::mpsc::sync_channel::<MultiSearchResult>(rayon::current_num_threads());
+
+    // // & spawn a thread that is dedicated to printing to a buffered output
+    let thrd = graph_csvwriter_thread(recv, output, columns, graph.map(SimilarityGraph::new), ctx)?;
+
+    let n_processed = ctx.time(Stage::Comparison, || {
+        pairwise_obj(
+            &sketches,
+            estimate_ani,
+            write_all,
+            output_all_comparisons,
+            send,
+            threshold,
+            ksize,
+            candidates.as_ref(),
+            angular_similarity,
+            distances.as_ref(),
+            both_directions,
+        )
+    })?;
+
+    let graph = thrd.join().expect("Unable to join internal thread");
+    if let Some(graph) = graph {
+        ctx.time(Stage::Writing, || graph.write())?;
+    }
+    if let Some(distances) = distances {
+        ctx.time(Stage::Writing, || distances.write(&sketches))?;
+    }
+
+    ctx.record_processed(n_processed);
+    eprintln!("DONE. Processed {} comparisons", n_processed);
+
+    Ok(())
+}
+
+/// Perform pairwise comparisons of all signatures in a list, returning
+/// the results instead of writing them to a CSV file.
+pub fn pairwise_collect(
+    siglist: String,
+    threshold: f64,
+    selection: Selection,
+    allow_failed_sigpaths: bool,
+    estimate_ani: bool,
+    write_all: bool,
+    output_all_comparisons: bool,
+) -> Result<Vec<MultiSearchResult>> {
+    let (sketches, ksize) = load_pairwise_sketches(
         &siglist,
-        &selection,
-        ReportType::General,
+        selection,
         allow_failed_sigpaths,
+        &RunContext::default(),
     )?;
 
+    let (n_processed, results) = collect_results(|send| {
+        pairwise_obj(
+            &sketches,
+            estimate_ani,
+            write_all,
+            output_all_comparisons,
+            send,
+            threshold,
+            ksize,
+            None,
+            false,
+            None,
+            false,
+        )
+    })?;
+
+    eprintln!("DONE. Processed {} comparisons", n_processed);
+
+    Ok(results)
+}
+
+/// Load all sketches into memory at a common scaled; returns (sketches, ksize).
+fn load_pairwise_sketches(
+    siglist: &String,
+    selection: Selection,
+    allow_failed_sigpaths: bool,
+    ctx: &RunContext,
+) -> Result<(Vec<SmallSignature>, f64)> {
+    // Load all sigs into memory at once.
+    let (collection, selection) = CollectionSource::Path(siglist.clone())
+        .load_and_complete_selection(selection, ReportType::General, allow_failed_sigpaths, ctx)?;
+
     if collection.len() <= 1 {
         bail!(
             "Pairwise requires two or more sketches. Check input: '{:?}'",
-            &siglist
+            siglist
         )
     }
 
@@ -45,7 +170,7 @@ pub fn pairwise(
     let common_scaled = match selection.scaled() {
         Some(s) => s,
         None => {
-            let s = *collection.max_scaled().expect("no records!?") as u32;
+            let s = *collection.max_scaled().expect("no records!?");
             eprintln!("Setting scaled={} based on max scaled in collection", s);
             s
         }
@@ -54,47 +179,138 @@ pub fn pairwise(
     let mut selection = selection;
     selection.set_scaled(common_scaled);
 
-    let sketches = collection.load_sketches()?;
+    let sketches = ctx.time(Stage::Loading, || collection.load_sketches())?;
     let ksize = selection.ksize().unwrap() as f64;
 
-    let n_processed = pairwise_obj(
-        &sketches,
-        estimate_ani,
-        write_all,
-        output_all_comparisons,
-        output,
-        threshold,
-        ksize,
-    )?;
-    eprintln!("DONE. Processed {} comparisons", n_processed);
+    Ok((sketches, ksize))
+}
 
-    Ok(())
+/// Load pairs of sketches to compare from a CSV, e.g. the output of a
+/// previous `multisearch` or `pairwise` run. Sketches are matched by the
+/// `query_md5` and `match_md5` columns if present, and otherwise by
+/// `query_name` and `match_name`.
+#[cfg(feature = "python")]
+fn load_candidate_pairs(path: &str, sketches: &[SmallSignature]) -> Result<CandidatePairs> {
+    let mut rdr = csv::Reader::from_path(path)
+        .with_context(|| format!("cannot open candidate pairs file '{}'", path))?;
+    let headers = rdr.headers()?.clone();
+    let column = |name: &str| headers.iter().position(|h| h == name);
+
+    let (query_col, match_col, by_md5) = match (column("query_md5"), column("match_md5")) {
+        (Some(q), Some(m)) => (q, m, true),
+        _ => match (column("query_name"), column("match_name")) {
+            (Some(q), Some(m)) => (q, m, false),
+            _ => bail!(
+                "candidate pairs file '{}' needs 'query_md5' and 'match_md5' (or 'query_name' and 'match_name') columns",
+                path
+            ),
+        },
+    };
+
+    // sketches by md5 or name; the first sketch wins for duplicate names.
+    let mut index: HashMap<&str, usize> = HashMap::new();
+    for (idx, sketch) in sketches.iter().enumerate() {
+        let key = if by_md5 { &sketch.md5sum } else { &sketch.name };
+        index.entry(key).or_insert(idx);
+    }
+
+    let mut pairs = BTreeSet::new();
+    let mut n_missing = 0;
+    for record in rdr.records() {
+        let record = record?;
+        let query = index.get(record.get(query_col).unwrap_or_default());
+        let against = index.get(record.get(match_col).unwrap_or_default());
+        match (query, against) {
+            // self-comparisons are controlled by --write-all.
+            (Some(q), Some(a)) if q != a => {
+                pairs.insert((*q.min(a), *q.max(a)));
+            }
+            (Some(_), Some(_)) => (),
+            _ => n_missing += 1,
+        }
+    }
+
+    if n_missing > 0 {
+        eprintln!(
+            "WARNING: skipped {} candidate pairs with sketches not found in the input.",
+            n_missing
+        );
+    }
+    eprintln!("Comparing {} candidate pairs from '{}'", pairs.len(), path);
+
+    let mut candidates = CandidatePairs::new();
+    for (q, a) in pairs {
+        candidates.entry(q).or_default().push(a);
+    }
+    Ok(candidates)
 }
 
+/// The comparison `result` from the match's point of view, for
+/// `--both-directions`.
+fn reversed(result: &MultiSearchResult) -> MultiSearchResult {
+    MultiSearchResult {
+        query_name: result.match_name.clone(),
+        query_md5: result.match_md5.clone(),
+        match_name: result.query_name.clone(),
+        match_md5: result.query_md5.clone(),
+        containment: result.intersect_hashes / result.match_n_hashes as f64,
+        query_n_hashes: result.match_n_hashes,
+        match_n_hashes: result.query_n_hashes,
+        query_bp: result.match_bp,
+        match_bp: result.query_bp,
+        query_containment_ani: result.match_containment_ani,
+        match_containment_ani: result.query_containment_ani,
+        moltype: result.moltype.clone(),
+        match_aliases: result.match_aliases.clone(),
+        ..*result
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn pairwise_obj(
-    sketches: &Vec<SmallSignature>,
+    sketches: &[SmallSignature],
     estimate_ani: bool,
     write_all: bool,
     output_all_comparisons: bool,
-    output: Option<String>,
+    send: SyncSender<MultiSearchResult>,
     threshold: f64,
     ksize: f64,
+    candidates: Option<&CandidatePairs>,
+    angular_similarity: bool,
+    distances: Option<&DistanceMatrix>,
+    both_directions: bool,
 ) -> Result<usize> {
-    // set up a multi-producer, single-consumer channel.
-    let (send, recv) =
-        std::sync::mpsc::sync_channel::<MultiSearchResult>(rayon::current_num_threads());
-
-    // // & spawn a thread that is dedicated to printing to a buffered output
-    let thrd = csvwriter_thread(recv, output);
-
     //
     // Main loop: iterate (in parallel) over all signature,
-    // Results written to the writer thread above.
+    // Results sent down the channel.
 
     let processed_cmp = AtomicUsize::new(0);
 
-    sketches.par_iter().enumerate().for_each(|(idx, query)| {
-        for against in sketches.iter().skip(idx + 1) {
+    // compare each sketch against all later sketches, or just the candidates.
+    let n = sketches.len();
+    let row_len = |idx: usize| match candidates {
+        Some(candidates) => candidates.get(&idx).map_or(0, Vec::len),
+        None => n - idx - 1,
+    };
+    let against_at = |idx: usize, k: usize| match candidates {
+        Some(candidates) => candidates[&idx][k],
+        None => idx + 1 + k,
+    };
+
+    // split the rows of the triangle into chunks, so that the short rows
+    // at the end don't leave threads idle.
+    let chunks = (0..n).into_par_iter().flat_map(|idx| {
+        let n_chunks = row_len(idx).div_ceil(PAIRWISE_CHUNK_SIZE).max(1);
+        (0..n_chunks).into_par_iter().map(move |chunk| (idx, chunk))
+    });
+
+    chunks.for_each(|(idx, chunk)| {
+        let query = &sketches[idx];
+        let start = chunk * PAIRWISE_CHUNK_SIZE;
+        let end = (start + PAIRWISE_CHUNK_SIZE).min(row_len(idx));
+        for k in start..end {
+            let against_idx = against_at(idx, k);
+            let against = &sketches[against_idx];
             let overlap = query.minhash.count_common(&against.minhash, false).unwrap() as f64;
             let query1_size = query.minhash.size() as f64;
             let query2_size = against.minhash.size() as f64;
@@ -105,6 +321,17 @@ pub(crate) fn pairwise_obj(
 
             let containment_q1_in_q2 = overlap / query1_size;
             let containment_q2_in_q1 = overlap / query2_size;
+            let jaccard = overlap / (query1_size + query2_size - overlap);
+
+            if let Some(distances) = distances {
+                distances.set(
+                    idx,
+                    against_idx,
+                    containment_q1_in_q2,
+                    containment_q2_in_q1,
+                    jaccard,
+                );
+            }
 
             let prob_overlap = None;
             let prob_overlap_adjusted = None;
@@ -117,7 +344,6 @@ pub(crate) fn pairwise_obj(
                 || output_all_comparisons
             {
                 let max_containment = containment_q1_in_q2.max(containment_q2_in_q1);
-                let jaccard = overlap / (query1_size + query2_size - overlap);
                 let mut query_containment_ani = None;
                 let mut match_containment_ani = None;
                 let mut average_containment_ani = None;
@@ -132,7 +358,12 @@ pub(crate) fn pairwise_obj(
                     average_containment_ani = Some((qani + mani) / 2.);
                     max_containment_ani = Some(f64::max(qani, mani));
                 }
-                send.send(MultiSearchResult {
+                let angular_similarity = if angular_similarity {
+                    query.minhash.angular_similarity(&against.minhash).ok()
+                } else {
+                    None
+                };
+                let result = MultiSearchResult {
                     query_name: query.name.clone(),
                     query_md5: query.md5sum.clone(),
                     match_name: against.name.clone(),
@@ -144,25 +375,40 @@ pub(crate) fn pairwise_obj(
                     max_containment,
                     jaccard,
                     intersect_hashes: overlap,
+                    intersect_bp: overlap as u64 * query.minhash.scaled() as u64,
+                    query_n_hashes: query1_size as u64,
+                    match_n_hashes: query2_size as u64,
+                    query_bp: query1_size as u64 * query.minhash.scaled() as u64,
+                    match_bp: query2_size as u64 * query.minhash.scaled() as u64,
                     query_containment_ani,
                     match_containment_ani,
                     average_containment_ani,
                     max_containment_ani,
+                    angular_similarity,
                     prob_overlap,
                     prob_overlap_adjusted,
                     containment_adjusted,
                     containment_adjusted_log10,
                     tf_idf_score,
-                })
-                .unwrap();
+                    overlap_pvalue: None,
+                    expected_false_positives: None,
+                    bh_qvalue: None,
+                    match_aliases: None,
+                };
+                let reverse = both_directions.then(|| reversed(&result));
+                send.send(result).unwrap();
+                if let Some(reverse) = reverse {
+                    send.send(reverse).unwrap();
+                }
             }
 
             let i = processed_cmp.fetch_add(1, atomic::Ordering::SeqCst);
-            if i % 100000 == 0 && i > 0 {
+            if i.is_multiple_of(100000) && i > 0 {
                 eprintln!("Processed {} comparisons", i);
             }
         }
-        if write_all || output_all_comparisons {
+        // each self comparison is written once, with its row's first chunk.
+        if chunk == 0 && (write_all || output_all_comparisons) {
             let mut query_containment_ani = None;
             let mut match_containment_ani = None;
             let mut average_containment_ani = None;
@@ -179,6 +425,7 @@ pub(crate) fn pairwise_obj(
                 average_containment_ani = Some(1.0);
                 max_containment_ani = Some(1.0);
             }
+            let angular_similarity = angular_similarity.then_some(1.0);
 
             send.send(MultiSearchResult {
                 query_name: query.name.clone(),
@@ -192,15 +439,25 @@ pub(crate) fn pairwise_obj(
                 max_containment: 1.0,
                 jaccard: 1.0,
                 intersect_hashes: query.minhash.size() as f64,
+                intersect_bp: query.minhash.size() as u64 * query.minhash.scaled() as u64,
+                query_n_hashes: query.minhash.size() as u64,
+                match_n_hashes: query.minhash.size() as u64,
+                query_bp: query.minhash.size() as u64 * query.minhash.scaled() as u64,
+                match_bp: query.minhash.size() as u64 * query.minhash.scaled() as u64,
                 query_containment_ani,
                 match_containment_ani,
                 average_containment_ani,
                 max_containment_ani,
+                angular_similarity,
                 prob_overlap,
                 prob_overlap_adjusted,
                 containment_adjusted,
                 containment_adjusted_log10,
                 tf_idf_score,
+                overlap_pvalue: None,
+                expected_false_positives: None,
+                bh_qvalue: None,
+                match_aliases: None,
             })
             .unwrap();
         }
@@ -209,8 +466,6 @@ pub(crate) fn pairwise_obj(
     // do some cleanup and error handling -
     drop(send); // close the channel
 
-    thrd.join().expect("Unable to join internal thread");
-
     // done!
     let i: usize = processed_cmp.load(atomic::Ordering::SeqCst);
     Ok(i)
@@ -0,0 +1,2114 @@
+//! Rust-to-Python interface code for sourmash_plugin_branchwater, using pyo3.
+//!
+//! Only built with the `python` feature. The functions here parse Python
+//! arguments, call the commands, and report errors as the CLI expects.
+
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyString};
+
+use camino::Utf8PathBuf as PathBuf;
+use pythonize::pythonize;
+
+use crate::check::HashStatsOptions;
+use crate::control::{search_control, CancelToken};
+use crate::errors::{add_exceptions, to_pyerr};
+use crate::index::IndexNames;
+use crate::pycollection::{collection_source, PyMultiCollection};
+use crate::pyindex::PyBranchwaterIndex;
+use crate::pysketches::PyLoadedSketches;
+use crate::resultstream::ResultStream;
+use crate::sketch::SketchParams;
+use crate::utils::columns::ColumnSelection;
+use crate::utils::distmatrix::DistanceOptions;
+use crate::utils::exclude::ExcludeList;
+use crate::utils::graph::GraphOptions;
+use crate::utils::metadata::MatchMetadata;
+use crate::utils::outputmode::OutputMode;
+use crate::utils::sizefilter::SizeFilter;
+use crate::utils::status;
+use crate::utils::stoplist::Stoplist;
+use crate::utils::taxonomy::TaxonomyOptions;
+use crate::utils::{
+    build_partial_selection, build_selection, is_revindex_database, require_sketch_type,
+};
+use crate::utils::{
+    BranchwaterGatherResult, CollectionSource, GatherOptions, GatherThreshold, ManySearchResult,
+    MinOverlap, MultiSearchResult, ReportType, RunContext, SearchMode,
+};
+use crate::{
+    bench, check, cluster, compat_check, convert, downsample, extract, fastgather, fastmultigather,
+    fastmultigather_rocksdb, fastprefetch, hash_lookup, index, intersect, manydescribe, manysearch,
+    manysearch_rocksdb, manysketch, merge, multisearch, overlap, overlap_summary, pairwise, rename,
+    serve, shard, singlesketch, subtract, summarize, validate_zip, zip_cat,
+};
+
+#[pyfunction]
+#[pyo3(signature = (querylist_path, siglist_path, threshold, ksize, scaled, moltype, output_path=None, ignore_abundance=false, output_all_comparisons=false, progress=None, cancel=None, progress_interval=100000, full_results=false, coverage_report=None, exclude_self_matches=false, output_columns=None, failed_paths_out=None, skipped_paths_out=None, search_mode=None, profile=None, exclude=None, output_matched_hashes=None, verify=false, per_query_scaled=false, detailed_exit_codes=false, summary_out=None, flush_rows=None, flush_interval_ms=None, append=false, output_shard_size=None, output_format=None, dedup_by_md5=false, min_overlap_bp=None, min_intersect_hashes=None, stoplist=None, match_metadata=None, rename_query=None, rename_match=None))]
+#[allow(clippy::too_many_arguments)]
+fn do_manysearch(
+    py: Python<'_>,
+    querylist_path: &Bound<'_, PyAny>,
+    siglist_path: &Bound<'_, PyAny>,
+    threshold: f64,
+    ksize: Option<u8>,
+    scaled: Option<u32>,
+    moltype: Option<String>,
+    output_path: Option<String>,
+    ignore_abundance: Option<bool>,
+    output_all_comparisons: Option<bool>,
+    progress: Option<PyObject>,
+    cancel: Option<PyRef<'_, CancelToken>>,
+    progress_interval: usize,
+    full_results: bool,
+    coverage_report: Option<String>,
+    exclude_self_matches: bool,
+    output_columns: Option<String>,
+    failed_paths_out: Option<String>,
+    skipped_paths_out: Option<String>,
+    search_mode: Option<String>,
+    profile: Option<String>,
+    exclude: Option<String>,
+    output_matched_hashes: Option<String>,
+    verify: bool,
+    per_query_scaled: bool,
+    detailed_exit_codes: bool,
+    summary_out: Option<String>,
+    flush_rows: Option<usize>,
+    flush_interval_ms: Option<u64>,
+    append: bool,
+    output_shard_size: Option<usize>,
+    output_format: Option<String>,
+    dedup_by_md5: bool,
+    min_overlap_bp: Option<u64>,
+    min_intersect_hashes: Option<u64>,
+    stoplist: Option<String>,
+    match_metadata: Option<String>,
+    rename_query: Option<Vec<String>>,
+    rename_match: Option<Vec<String>>,
+) -> anyhow::Result<u8> {
+    let query_source = collection_source(querylist_path)?;
+    let against_source = collection_source(siglist_path)?;
+    let selection = match build_partial_selection(ksize, scaled, moltype.as_deref()) {
+        Ok(selection) => selection,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    eprintln!("selection scaled: {:?}", selection.scaled());
+    let columns = match ColumnSelection::new::<ManySearchResult>(output_columns).and_then(|c| {
+        let metadata = match_metadata
+            .map(|path| MatchMetadata::load(&path))
+            .transpose()?;
+        c.with_match_metadata::<ManySearchResult>(metadata)?
+            .with_name_rewrites(rename_query, rename_match)
+    }) {
+        Ok(columns) => columns,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let output_mode = match OutputMode::new(append, output_shard_size, output_format.as_deref()) {
+        Ok(mode) => mode,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let min_overlap = match MinOverlap::new(min_overlap_bp, min_intersect_hashes) {
+        Ok(min_overlap) => min_overlap,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let allow_failed_sigpaths = true;
+    let exclude = match exclude.map(|spec| ExcludeList::load(&spec)).transpose() {
+        Ok(exclude) => exclude,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let ctx = RunContext::default()
+        .with_output_mode(output_mode)
+        .with_profile(profile, "manysearch")
+        .with_path_reports(failed_paths_out, skipped_paths_out)
+        .with_exit_status("manysearch", detailed_exit_codes, summary_out)
+        .with_flush_policy(flush_rows, flush_interval_ms)
+        .with_exclude_list(exclude)
+        .with_dedup_by_md5(dedup_by_md5)
+        .with_verification(verify);
+    let stoplist = match stoplist
+        .map(|path| Stoplist::load(&path, &selection))
+        .transpose()
+    {
+        Ok(stoplist) => stoplist,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+
+    let ignore_abundance = ignore_abundance.unwrap_or(false);
+    let output_all_comparisons = output_all_comparisons.unwrap_or(false);
+    let control = search_control(progress, cancel, progress_interval);
+
+    // if siglist_path is revindex, run rocksdb manysearch; otherwise run manysearch
+    let revindex_path =
+        match SearchMode::new(search_mode).and_then(|mode| mode.rocksdb_path(&against_source)) {
+            Ok(path) => path,
+            Err(e) => {
+                eprintln!("Error: {e}");
+                return Ok(1);
+            }
+        };
+
+    if let Some(againstfile_path) = revindex_path {
+        let CollectionSource::Path(querylist_path) = query_source else {
+            eprintln!("Error: searching a RocksDB database requires a query path");
+            return Ok(1);
+        };
+        if ctx.exclude_list().is_some() {
+            eprintln!("Error: --exclude is not supported for RocksDB databases");
+            return Ok(1);
+        }
+        if per_query_scaled {
+            eprintln!("Error: --per-query-scaled is not supported for RocksDB databases");
+            return Ok(1);
+        }
+        if dedup_by_md5 {
+            eprintln!("Error: --dedup-by-md5 is not supported for RocksDB databases");
+            return Ok(1);
+        }
+        if coverage_report.is_some() {
+            eprintln!(
+                "WARNING: --coverage-report is not supported for RocksDB databases; ignoring."
+            );
+        }
+        if output_matched_hashes.is_some() {
+            eprintln!(
+                "WARNING: --output-matched-hashes is not supported for RocksDB databases; ignoring."
+            );
+        }
+        match ctx.finish(manysearch_rocksdb::manysearch_rocksdb(
+            querylist_path,
+            againstfile_path,
+            selection,
+            threshold,
+            output_path,
+            allow_failed_sigpaths,
+            output_all_comparisons,
+            full_results,
+            exclude_self_matches,
+            ignore_abundance,
+            columns,
+            min_overlap,
+            stoplist.as_ref(),
+            &ctx,
+        )) {
+            Ok(status) => Ok(status),
+            Err(e) => {
+                eprintln!("Error: {e}");
+                Ok(1)
+            }
+        }
+    } else {
+        // manysearch always calculates full results, so `full_results`
+        // only matters for RocksDB databases.
+        // release the GIL so that the progress callback can take it.
+        match py.allow_threads(|| {
+            ctx.finish(manysearch::manysearch(
+                query_source,
+                against_source,
+                selection,
+                threshold,
+                output_path,
+                allow_failed_sigpaths,
+                ignore_abundance,
+                output_all_comparisons,
+                control,
+                coverage_report,
+                exclude_self_matches,
+                columns,
+                output_matched_hashes,
+                per_query_scaled,
+                min_overlap,
+                stoplist.as_ref(),
+                &ctx,
+            ))
+        }) {
+            Ok(status) => Ok(status),
+            Err(e) => {
+                eprintln!("Error: {e}");
+                Ok(1)
+            }
+        }
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (querylist_path, siglist_path, threshold, ksize, scaled, moltype, ignore_abundance=false, output_all_comparisons=false, progress=None, cancel=None, progress_interval=100000))]
+#[allow(clippy::too_many_arguments)]
+fn manysearch_iter(
+    py: Python<'_>,
+    querylist_path: &Bound<'_, PyAny>,
+    siglist_path: &Bound<'_, PyAny>,
+    threshold: f64,
+    ksize: u8,
+    scaled: Option<u32>,
+    moltype: String,
+    ignore_abundance: bool,
+    output_all_comparisons: bool,
+    progress: Option<PyObject>,
+    cancel: Option<PyRef<'_, CancelToken>>,
+    progress_interval: usize,
+) -> PyResult<ResultStream> {
+    let query_source = collection_source(querylist_path)?;
+    let against_source = collection_source(siglist_path)?;
+    if matches!(&against_source, CollectionSource::Path(p) if is_revindex_database(&PathBuf::from(p)))
+    {
+        return Err(to_pyerr(anyhow::anyhow!(
+            "manysearch_iter does not support RocksDB databases; use do_manysearch instead"
+        )));
+    }
+
+    let selection = build_selection(ksize, scaled, &moltype).map_err(to_pyerr)?;
+    let allow_failed_sigpaths = true;
+    let control = search_control(progress, cancel, progress_interval);
+
+    // load up front, so that loading errors are raised immediately.
+    let (query_sketchlist, against_collection, common_scaled) = py
+        .allow_threads(|| {
+            manysearch::load_manysearch_inputs(
+                &query_source,
+                &against_source,
+                selection,
+                allow_failed_sigpaths,
+                false,
+                None,
+                &RunContext::default(),
+            )
+        })
+        .map_err(to_pyerr)?;
+
+    Ok(ResultStream::spawn(move |send| {
+        manysearch::manysearch_obj(
+            &query_sketchlist,
+            &against_collection,
+            threshold,
+            common_scaled,
+            send,
+            ignore_abundance,
+            output_all_comparisons,
+            &control,
+            None,
+            None,
+            false,
+            None,
+            &RunContext::default(),
+        )?;
+        Ok(())
+    }))
+}
+
+#[pyfunction]
+#[allow(clippy::too_many_arguments)]
+#[pyo3(signature = (query_filename, siglist_path, threshold_bp, ksize, scaled, moltype, output_path_prefetch=None, output_path_gather=None, taxonomy=None, tax_summary_output=None, cami_output=None, kraken_output=None, threshold_hashes=None, threshold_fraction=None, max_matches=None, min_ani=None, abundance_weighted=false, output_columns=None, failed_paths_out=None, skipped_paths_out=None, profile=None, exclude=None, verify=false, require_abundance=false, detailed_exit_codes=false, summary_out=None, flush_rows=None, flush_interval_ms=None, append=false, output_shard_size=None, output_format=None, stoplist=None, rename_query=None, rename_match=None, sourmash_compat=false))]
+fn do_fastgather(
+    query_filename: &Bound<'_, PyAny>,
+    siglist_path: &Bound<'_, PyAny>,
+    threshold_bp: u64,
+    ksize: Option<u8>,
+    scaled: Option<u32>,
+    moltype: Option<String>,
+    output_path_prefetch: Option<String>,
+    output_path_gather: Option<String>,
+    taxonomy: Option<String>,
+    tax_summary_output: Option<String>,
+    cami_output: Option<String>,
+    kraken_output: Option<String>,
+    threshold_hashes: Option<u64>,
+    threshold_fraction: Option<f64>,
+    max_matches: Option<usize>,
+    min_ani: Option<f64>,
+    abundance_weighted: bool,
+    output_columns: Option<String>,
+    failed_paths_out: Option<String>,
+    skipped_paths_out: Option<String>,
+    profile: Option<String>,
+    exclude: Option<String>,
+    verify: bool,
+    require_abundance: bool,
+    detailed_exit_codes: bool,
+    summary_out: Option<String>,
+    flush_rows: Option<usize>,
+    flush_interval_ms: Option<u64>,
+    append: bool,
+    output_shard_size: Option<usize>,
+    output_format: Option<String>,
+    stoplist: Option<String>,
+    rename_query: Option<Vec<String>>,
+    rename_match: Option<Vec<String>>,
+    sourmash_compat: bool,
+) -> anyhow::Result<u8> {
+    let threshold = match GatherThreshold::new(threshold_bp, threshold_hashes, threshold_fraction) {
+        Ok(threshold) => threshold,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let gather_options = match GatherOptions::new(max_matches, min_ani, abundance_weighted) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    if sourmash_compat && output_columns.is_some() {
+        eprintln!("Error: --sourmash-compat cannot be used with --output-columns");
+        return Ok(1);
+    }
+    let columns = match ColumnSelection::new::<BranchwaterGatherResult>(output_columns)
+        .and_then(|c| c.with_name_rewrites(rename_query, rename_match))
+        .map(|c| c.with_sourmash_compat(sourmash_compat))
+    {
+        Ok(columns) => columns,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let output_mode = match OutputMode::new(append, output_shard_size, output_format.as_deref()) {
+        Ok(mode) => mode,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let selection = match build_partial_selection(ksize, scaled, moltype.as_deref())
+        .and_then(|selection| require_sketch_type(selection, require_abundance, None))
+    {
+        Ok(selection) => selection,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let taxonomy = taxonomy.map(|lineages_path| TaxonomyOptions {
+        lineages_path,
+        summary_output: tax_summary_output,
+        cami_output,
+        kraken_output,
+    });
+    let allow_failed_sigpaths = true;
+    let exclude = match exclude.map(|spec| ExcludeList::load(&spec)).transpose() {
+        Ok(exclude) => exclude,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let ctx = RunContext::default()
+        .with_output_mode(output_mode)
+        .with_profile(profile, "fastgather")
+        .with_path_reports(failed_paths_out, skipped_paths_out)
+        .with_exit_status("fastgather", detailed_exit_codes, summary_out)
+        .with_flush_policy(flush_rows, flush_interval_ms)
+        .with_exclude_list(exclude)
+        .with_verification(verify);
+    let stoplist = match stoplist
+        .map(|path| Stoplist::load(&path, &selection))
+        .transpose()
+    {
+        Ok(stoplist) => stoplist,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+
+    let query_source = collection_source(query_filename)?;
+    let against_source = collection_source(siglist_path)?;
+
+    match ctx.finish(fastgather::fastgather(
+        query_source,
+        against_source,
+        threshold,
+        selection,
+        output_path_prefetch,
+        output_path_gather,
+        allow_failed_sigpaths,
+        taxonomy,
+        gather_options,
+        columns,
+        stoplist.as_ref(),
+        &ctx,
+    )) {
+        Ok(status) => Ok(status),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            Ok(1)
+        }
+    }
+}
+
+#[pyfunction]
+#[allow(clippy::too_many_arguments)]
+#[pyo3(signature = (query_filename, siglist_path, threshold_bp, ksize, scaled, moltype, output_path=None, threshold_hashes=None, threshold_fraction=None, failed_paths_out=None, skipped_paths_out=None, search_mode=None, profile=None, exclude=None, verify=false, detailed_exit_codes=false, summary_out=None, flush_rows=None, flush_interval_ms=None, stoplist=None, sourmash_compat=false))]
+fn do_fastprefetch(
+    query_filename: &Bound<'_, PyAny>,
+    siglist_path: &Bound<'_, PyAny>,
+    threshold_bp: u64,
+    ksize: Option<u8>,
+    scaled: Option<u32>,
+    moltype: Option<String>,
+    output_path: Option<String>,
+    threshold_hashes: Option<u64>,
+    threshold_fraction: Option<f64>,
+    failed_paths_out: Option<String>,
+    skipped_paths_out: Option<String>,
+    search_mode: Option<String>,
+    profile: Option<String>,
+    exclude: Option<String>,
+    verify: bool,
+    detailed_exit_codes: bool,
+    summary_out: Option<String>,
+    flush_rows: Option<usize>,
+    flush_interval_ms: Option<u64>,
+    stoplist: Option<String>,
+    sourmash_compat: bool,
+) -> anyhow::Result<u8> {
+    let threshold = match GatherThreshold::new(threshold_bp, threshold_hashes, threshold_fraction) {
+        Ok(threshold) => threshold,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let selection = match build_partial_selection(ksize, scaled, moltype.as_deref()) {
+        Ok(selection) => selection,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let allow_failed_sigpaths = true;
+    let exclude = match exclude.map(|spec| ExcludeList::load(&spec)).transpose() {
+        Ok(exclude) => exclude,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let stoplist = match stoplist
+        .map(|path| Stoplist::load(&path, &selection))
+        .transpose()
+    {
+        Ok(stoplist) => stoplist,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+
+    let ctx = RunContext::default()
+        .with_profile(profile, "fastprefetch")
+        .with_path_reports(failed_paths_out, skipped_paths_out)
+        .with_exit_status("fastprefetch", detailed_exit_codes, summary_out)
+        .with_flush_policy(flush_rows, flush_interval_ms)
+        .with_exclude_list(exclude)
+        .with_verification(verify);
+    let query_source = collection_source(query_filename)?;
+    let against_source = collection_source(siglist_path)?;
+    let revindex_path =
+        match SearchMode::new(search_mode).and_then(|mode| mode.rocksdb_path(&against_source)) {
+            Ok(path) => path,
+            Err(e) => {
+                eprintln!("Error: {e}");
+                return Ok(1);
+            }
+        };
+
+    if let Some(againstfile_path) = revindex_path {
+        let CollectionSource::Path(query_path) = query_source else {
+            eprintln!("Error: searching a RocksDB database requires a query path");
+            return Ok(1);
+        };
+        if ctx.exclude_list().is_some() {
+            eprintln!("Error: --exclude is not supported for RocksDB databases");
+            return Ok(1);
+        }
+        match ctx.finish(fastprefetch::fastprefetch_rocksdb(
+            query_path,
+            againstfile_path,
+            threshold,
+            selection,
+            output_path,
+            sourmash_compat,
+            allow_failed_sigpaths,
+            stoplist.as_ref(),
+            &ctx,
+        )) {
+            Ok(status) => Ok(status),
+            Err(e) => {
+                eprintln!("Error: {e}");
+                Ok(1)
+            }
+        }
+    } else {
+        match ctx.finish(fastprefetch::fastprefetch(
+            query_source,
+            against_source,
+            threshold,
+            selection,
+            output_path,
+            sourmash_compat,
+            allow_failed_sigpaths,
+            stoplist.as_ref(),
+            &ctx,
+        )) {
+            Ok(status) => Ok(status),
+            Err(e) => {
+                eprintln!("Error: {e}");
+                Ok(1)
+            }
+        }
+    }
+}
+
+#[pyfunction]
+#[allow(clippy::too_many_arguments)]
+#[pyo3(signature = (query_filenames, siglist_path, threshold_bp, ksize, scaled, moltype, output_path=None, save_matches=false, create_empty_results=false, taxonomy=None, tax_summary_output=None, cami_output=None, kraken_output=None, output_dir=None, output_template=None, shared_prefetch=false, threshold_hashes=None, threshold_fraction=None, max_matches=None, min_ani=None, abundance_weighted=false, output_columns=None, failed_paths_out=None, skipped_paths_out=None, force=false, search_mode=None, profile=None, save_unassigned=false, exclude=None, verify=false, require_abundance=false, per_query_scaled=false, detailed_exit_codes=false, summary_out=None, flush_rows=None, flush_interval_ms=None, append=false, output_shard_size=None, output_format=None, refine_from=None, stoplist=None, rename_query=None, rename_match=None, sourmash_compat=false))]
+fn do_fastmultigather(
+    query_filenames: String,
+    siglist_path: String,
+    threshold_bp: u64,
+    ksize: Option<u8>,
+    scaled: Option<u32>,
+    moltype: Option<String>,
+    output_path: Option<String>,
+    save_matches: bool,
+    create_empty_results: bool,
+    taxonomy: Option<String>,
+    tax_summary_output: Option<String>,
+    cami_output: Option<String>,
+    kraken_output: Option<String>,
+    output_dir: Option<String>,
+    output_template: Option<String>,
+    shared_prefetch: bool,
+    threshold_hashes: Option<u64>,
+    threshold_fraction: Option<f64>,
+    max_matches: Option<usize>,
+    min_ani: Option<f64>,
+    abundance_weighted: bool,
+    output_columns: Option<String>,
+    failed_paths_out: Option<String>,
+    skipped_paths_out: Option<String>,
+    force: bool,
+    search_mode: Option<String>,
+    profile: Option<String>,
+    save_unassigned: bool,
+    exclude: Option<String>,
+    verify: bool,
+    require_abundance: bool,
+    per_query_scaled: bool,
+    detailed_exit_codes: bool,
+    summary_out: Option<String>,
+    flush_rows: Option<usize>,
+    flush_interval_ms: Option<u64>,
+    append: bool,
+    output_shard_size: Option<usize>,
+    output_format: Option<String>,
+    refine_from: Option<String>,
+    stoplist: Option<String>,
+    rename_query: Option<Vec<String>>,
+    rename_match: Option<Vec<String>>,
+    sourmash_compat: bool,
+) -> anyhow::Result<u8> {
+    let threshold = match GatherThreshold::new(threshold_bp, threshold_hashes, threshold_fraction) {
+        Ok(threshold) => threshold,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let gather_options = match GatherOptions::new(max_matches, min_ani, abundance_weighted) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    if sourmash_compat && output_columns.is_some() {
+        eprintln!("Error: --sourmash-compat cannot be used with --output-columns");
+        return Ok(1);
+    }
+    let columns = match ColumnSelection::new::<BranchwaterGatherResult>(output_columns)
+        .and_then(|c| c.with_name_rewrites(rename_query, rename_match))
+        .map(|c| c.with_sourmash_compat(sourmash_compat))
+    {
+        Ok(columns) => columns,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let output_mode = match OutputMode::new(append, output_shard_size, output_format.as_deref()) {
+        Ok(mode) => mode,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let revindex_path = match SearchMode::new(search_mode)
+        .and_then(|mode| mode.rocksdb_path(&CollectionSource::Path(siglist_path.clone())))
+    {
+        Ok(path) => path,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let taxonomy = taxonomy.map(|lineages_path| TaxonomyOptions {
+        lineages_path,
+        summary_output: tax_summary_output,
+        cami_output,
+        kraken_output,
+    });
+    let output_names =
+        match fastmultigather::QueryOutputNames::new(output_dir, output_template, force) {
+            Ok(names) => names,
+            Err(e) => {
+                eprintln!("Error: {e}");
+                return Ok(1);
+            }
+        };
+    let selection = match build_partial_selection(ksize, scaled, moltype.as_deref())
+        .and_then(|selection| require_sketch_type(selection, require_abundance, None))
+    {
+        Ok(selection) => selection,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let allow_failed_sigpaths = true;
+    let exclude = match exclude.map(|spec| ExcludeList::load(&spec)).transpose() {
+        Ok(exclude) => exclude,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let ctx = RunContext::default()
+        .with_output_mode(output_mode)
+        .with_profile(profile, "fastmultigather")
+        .with_path_reports(failed_paths_out, skipped_paths_out)
+        .with_exit_status("fastmultigather", detailed_exit_codes, summary_out)
+        .with_flush_policy(flush_rows, flush_interval_ms)
+        .with_exclude_list(exclude)
+        .with_verification(verify);
+    let stoplist = match stoplist
+        .map(|path| Stoplist::load(&path, &selection))
+        .transpose()
+    {
+        Ok(stoplist) => stoplist,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+
+    // if a siglist path is a revindex, run rocksdb fastmultigather. If not, run multigather
+    if let Some(againstfile_path) = revindex_path {
+        if ctx.exclude_list().is_some() {
+            eprintln!("Error: --exclude is not supported for RocksDB databases");
+            return Ok(1);
+        }
+        if per_query_scaled {
+            eprintln!("Error: --per-query-scaled is not supported for RocksDB databases");
+            return Ok(1);
+        }
+        if abundance_weighted && refine_from.is_none() {
+            eprintln!("WARNING: RocksDB gather picks matches by flat overlap; ignoring --abundance-weighted.");
+        }
+        if shared_prefetch {
+            eprintln!("WARNING: RocksDB indexes are already inverted indexes; ignoring --shared-prefetch.");
+        }
+        match ctx.finish(fastmultigather_rocksdb::fastmultigather_rocksdb(
+            query_filenames,
+            againstfile_path,
+            selection.clone(),
+            threshold,
+            output_path,
+            allow_failed_sigpaths,
+            taxonomy,
+            gather_options,
+            columns,
+            output_names,
+            save_matches,
+            save_unassigned,
+            refine_from,
+            stoplist.as_ref(),
+            &ctx,
+        )) {
+            Ok(status) => Ok(status),
+            Err(e) => {
+                eprintln!("Error: {e}");
+                Ok(1)
+            }
+        }
+    } else {
+        if refine_from.is_some() {
+            eprintln!("Error: --refine-from only applies to RocksDB databases");
+            return Ok(1);
+        }
+        match ctx.finish(fastmultigather::fastmultigather(
+            query_filenames,
+            siglist_path,
+            threshold,
+            scaled,
+            selection,
+            allow_failed_sigpaths,
+            save_matches,
+            save_unassigned,
+            output_path,
+            create_empty_results,
+            taxonomy,
+            output_names,
+            shared_prefetch,
+            gather_options,
+            columns,
+            per_query_scaled,
+            stoplist.as_ref(),
+            &ctx,
+        )) {
+            Ok(status) => Ok(status),
+            Err(e) => {
+                eprintln!("Error: {e}");
+                Ok(1)
+            }
+        }
+    }
+}
+
+#[pyfunction]
+fn set_global_thread_pool(num_threads: usize) -> PyResult<usize> {
+    if std::panic::catch_unwind(|| {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .build_global()
+    })
+    .is_ok()
+    {
+        Ok(rayon::current_num_threads())
+    } else {
+        Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
+            "Could not set the number of threads. Global thread pool might already be initialized.",
+        ))
+    }
+}
+
+#[pyfunction]
+#[allow(clippy::too_many_arguments)]
+#[pyo3(signature = (siglist, ksize, scaled, moltype, output, colors, use_internal_storage, force=false, verify=false, normalize_names=false, rename_from=None))]
+fn do_index(
+    siglist: String,
+    ksize: u8,
+    scaled: Option<u32>,
+    moltype: String,
+    output: String,
+    colors: bool,
+    use_internal_storage: bool,
+    force: bool,
+    verify: bool,
+    normalize_names: bool,
+    rename_from: Option<String>,
+) -> anyhow::Result<u8> {
+    let selection = match build_selection(ksize, scaled, &moltype) {
+        Ok(selection) => selection,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let names = match IndexNames::new(normalize_names, rename_from) {
+        Ok(names) => names,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let allow_failed_sigpaths = false;
+    let ctx = RunContext::default().with_verification(verify);
+    match index::index(
+        siglist,
+        selection,
+        output,
+        colors,
+        allow_failed_sigpaths,
+        use_internal_storage,
+        force,
+        names,
+        &ctx,
+    ) {
+        Ok(_) => Ok(0),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            Ok(1)
+        }
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (index, quick, rw, hash_stats=false, hash_stats_out=None, ubiquitous_fraction=0.9, output_stoplist=None))]
+fn do_check(
+    index: String,
+    quick: bool,
+    rw: bool,
+    hash_stats: bool,
+    hash_stats_out: Option<String>,
+    ubiquitous_fraction: f64,
+    output_stoplist: Option<String>,
+) -> anyhow::Result<u8> {
+    let idx: PathBuf = index.into();
+    // writing either output implies the report.
+    let hash_stats = hash_stats || hash_stats_out.is_some() || output_stoplist.is_some();
+    let hash_stats = match hash_stats
+        .then(|| HashStatsOptions::new(hash_stats_out, ubiquitous_fraction, output_stoplist))
+        .transpose()
+    {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    match check::check(idx, quick, rw, hash_stats) {
+        Ok(_) => Ok(0),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            Ok(1)
+        }
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (query_path, against_path, ksize, scaled, moltype))]
+fn do_compat_check(
+    query_path: String,
+    against_path: String,
+    ksize: u8,
+    scaled: Option<u32>,
+    moltype: String,
+) -> anyhow::Result<u8> {
+    let selection = match build_selection(ksize, scaled, &moltype) {
+        Ok(selection) => selection,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let allow_failed_sigpaths = true;
+    match compat_check::compat_check(query_path, against_path, selection, allow_failed_sigpaths) {
+        Ok(_) => Ok(0),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            Ok(1)
+        }
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (index, ksize, moltype, hashes_path=None, query_path=None, output=None))]
+fn do_hash_lookup(
+    index: String,
+    ksize: u8,
+    moltype: String,
+    hashes_path: Option<String>,
+    query_path: Option<String>,
+    output: Option<String>,
+) -> anyhow::Result<u8> {
+    let idx: PathBuf = index.into();
+    let selection = match build_selection(ksize, None, &moltype) {
+        Ok(selection) => selection,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let allow_failed_sigpaths = true;
+    match hash_lookup::hash_lookup(
+        idx,
+        hashes_path,
+        query_path,
+        selection,
+        output,
+        allow_failed_sigpaths,
+    ) {
+        Ok(_) => Ok(0),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            Ok(1)
+        }
+    }
+}
+
+#[pyfunction]
+#[allow(clippy::too_many_arguments)]
+#[pyo3(signature = (index, ksize, scaled, moltype, host="127.0.0.1".to_string(), port=8080, threshold=0.01, threshold_bp=50000))]
+fn do_serve(
+    py: Python<'_>,
+    index: String,
+    ksize: u8,
+    scaled: Option<u32>,
+    moltype: String,
+    host: String,
+    port: u16,
+    threshold: f64,
+    threshold_bp: u64,
+) -> anyhow::Result<u8> {
+    let selection = match build_selection(ksize, scaled, &moltype) {
+        Ok(selection) => selection,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let allow_failed_sigpaths = true;
+    // serve until interrupted; signals are only seen when we check for them.
+    let stop = || Python::with_gil(|py| py.check_signals().is_err());
+    match py.allow_threads(|| {
+        serve::serve(
+            index,
+            selection,
+            &host,
+            port,
+            threshold,
+            threshold_bp,
+            allow_failed_sigpaths,
+            &stop,
+        )
+    }) {
+        Ok(_) => Ok(0),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            Ok(1)
+        }
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (querylist_path, siglist_path, threshold, ksize, scaled, moltype, estimate_ani, estimate_prob_overlap, output_all_comparisons, output_path=None, progress=None, cancel=None, progress_interval=100000, output_graph=None, graph_format=None, graph_weight="max_containment".to_string(), coverage_report=None, knn=None, angular_similarity=false, exclude_self_matches=false, output_columns=None, failed_paths_out=None, skipped_paths_out=None, profile=None, min_hashes=None, max_hashes=None, exclude=None, query_groups=None, output_groups=None, verify=false, require_abundance=false, detailed_exit_codes=false, summary_out=None, flush_rows=None, flush_interval_ms=None, append=false, output_shard_size=None, output_format=None, dedup_by_md5=false, fdr=false, stoplist=None, rename_query=None, rename_match=None))]
+#[allow(clippy::too_many_arguments)]
+fn do_multisearch(
+    py: Python<'_>,
+    querylist_path: &Bound<'_, PyAny>,
+    siglist_path: &Bound<'_, PyAny>,
+    threshold: f64,
+    ksize: Option<u8>,
+    scaled: Option<u32>,
+    moltype: Option<String>,
+    estimate_ani: bool,
+    estimate_prob_overlap: bool,
+    output_all_comparisons: bool,
+    output_path: Option<String>,
+    progress: Option<PyObject>,
+    cancel: Option<PyRef<'_, CancelToken>>,
+    progress_interval: usize,
+    output_graph: Option<String>,
+    graph_format: Option<String>,
+    graph_weight: String,
+    coverage_report: Option<String>,
+    knn: Option<usize>,
+    angular_similarity: bool,
+    exclude_self_matches: bool,
+    output_columns: Option<String>,
+    failed_paths_out: Option<String>,
+    skipped_paths_out: Option<String>,
+    profile: Option<String>,
+    min_hashes: Option<usize>,
+    max_hashes: Option<usize>,
+    exclude: Option<String>,
+    query_groups: Option<String>,
+    output_groups: Option<String>,
+    verify: bool,
+    require_abundance: bool,
+    detailed_exit_codes: bool,
+    summary_out: Option<String>,
+    flush_rows: Option<usize>,
+    flush_interval_ms: Option<u64>,
+    append: bool,
+    output_shard_size: Option<usize>,
+    output_format: Option<String>,
+    dedup_by_md5: bool,
+    fdr: bool,
+    stoplist: Option<String>,
+    rename_query: Option<Vec<String>>,
+    rename_match: Option<Vec<String>>,
+) -> anyhow::Result<u8> {
+    let _ = env_logger::try_init();
+
+    let query_source = collection_source(querylist_path)?;
+    let against_source = collection_source(siglist_path)?;
+    let selection = match build_partial_selection(ksize, scaled, moltype.as_deref())
+        .and_then(|selection| require_sketch_type(selection, require_abundance, None))
+    {
+        Ok(selection) => selection,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let allow_failed_sigpaths = true;
+    let exclude = match exclude.map(|spec| ExcludeList::load(&spec)).transpose() {
+        Ok(exclude) => exclude,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let stoplist = match stoplist
+        .map(|path| Stoplist::load(&path, &selection))
+        .transpose()
+    {
+        Ok(stoplist) => stoplist,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let size_filter = match SizeFilter::new(min_hashes, max_hashes) {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let control = search_control(progress, cancel, progress_interval);
+    let columns = match ColumnSelection::new::<MultiSearchResult>(output_columns)
+        .and_then(|c| c.with_name_rewrites(rename_query, rename_match))
+    {
+        Ok(columns) => columns,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let output_mode = match OutputMode::new(append, output_shard_size, output_format.as_deref()) {
+        Ok(mode) => mode,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let ctx = RunContext::default()
+        .with_output_mode(output_mode)
+        .with_profile(profile, "multisearch")
+        .with_path_reports(failed_paths_out, skipped_paths_out)
+        .with_exit_status("multisearch", detailed_exit_codes, summary_out)
+        .with_flush_policy(flush_rows, flush_interval_ms)
+        .with_size_filter(size_filter)
+        .with_exclude_list(exclude)
+        .with_dedup_by_md5(dedup_by_md5)
+        .with_verification(verify);
+    let graph = match output_graph {
+        Some(path) => match GraphOptions::new(path, graph_format, graph_weight) {
+            Ok(g) => Some(g),
+            Err(e) => {
+                eprintln!("Error: {e}");
+                return Ok(1);
+            }
+        },
+        None => None,
+    };
+
+    let query_groups = match (query_groups, output_groups) {
+        (Some(groups), Some(output)) => Some((groups, output)),
+        (None, None) => None,
+        _ => {
+            eprintln!("Error: --query-groups and --output-groups must be given together");
+            return Ok(1);
+        }
+    };
+
+    // release the GIL so that the progress callback can take it.
+    match py.allow_threads(|| {
+        ctx.finish(multisearch::multisearch(
+            query_source,
+            against_source,
+            threshold,
+            selection,
+            allow_failed_sigpaths,
+            estimate_ani,
+            estimate_prob_overlap,
+            output_all_comparisons,
+            output_path,
+            control,
+            graph,
+            coverage_report,
+            knn,
+            angular_similarity,
+            exclude_self_matches,
+            columns,
+            query_groups,
+            fdr,
+            stoplist.as_ref(),
+            &ctx,
+        ))
+    }) {
+        Ok(status) => Ok(status),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            Ok(1)
+        }
+    }
+}
+
+/// Run multisearch and return the results as a list of dicts, one per
+/// match, e.g. for use with `pandas.DataFrame(results)`.
+#[pyfunction]
+#[pyo3(signature = (querylist_path, siglist_path, threshold, ksize, scaled, moltype, estimate_ani=false, estimate_prob_overlap=false, output_all_comparisons=false))]
+#[allow(clippy::too_many_arguments)]
+fn do_multisearch_df<'py>(
+    py: Python<'py>,
+    querylist_path: &Bound<'py, PyAny>,
+    siglist_path: &Bound<'py, PyAny>,
+    threshold: f64,
+    ksize: u8,
+    scaled: Option<u32>,
+    moltype: String,
+    estimate_ani: bool,
+    estimate_prob_overlap: bool,
+    output_all_comparisons: bool,
+) -> PyResult<Vec<Bound<'py, PyAny>>> {
+    let query_source = collection_source(querylist_path)?;
+    let against_source = collection_source(siglist_path)?;
+    let selection = build_selection(ksize, scaled, &moltype).map_err(to_pyerr)?;
+    let allow_failed_sigpaths = true;
+
+    let results = py
+        .allow_threads(|| {
+            multisearch::multisearch_collect(
+                query_source,
+                against_source,
+                threshold,
+                selection,
+                allow_failed_sigpaths,
+                estimate_ani,
+                estimate_prob_overlap,
+                output_all_comparisons,
+            )
+        })
+        .map_err(to_pyerr)?;
+
+    results.iter().map(|r| Ok(pythonize(py, r)?)).collect()
+}
+
+#[pyfunction]
+#[allow(clippy::too_many_arguments)]
+#[pyo3(signature = (siglist_path, threshold, ksize, scaled, moltype, estimate_ani, write_all, output_all_comparisons, output_path=None, output_graph=None, graph_format=None, graph_weight="max_containment".to_string(), candidates=None, angular_similarity=false, output_columns=None, failed_paths_out=None, skipped_paths_out=None, profile=None, min_hashes=None, max_hashes=None, output_distances=None, distance_measure="average_containment_ani".to_string(), verify=false, require_abundance=false, detailed_exit_codes=false, summary_out=None, flush_rows=None, flush_interval_ms=None, append=false, output_shard_size=None, output_format=None, both_directions=false, rename_query=None, rename_match=None))]
+fn do_pairwise(
+    siglist_path: String,
+    threshold: f64,
+    ksize: Option<u8>,
+    scaled: Option<u32>,
+    moltype: Option<String>,
+    estimate_ani: bool,
+    write_all: bool,
+    output_all_comparisons: bool,
+    output_path: Option<String>,
+    output_graph: Option<String>,
+    graph_format: Option<String>,
+    graph_weight: String,
+    candidates: Option<String>,
+    angular_similarity: bool,
+    output_columns: Option<String>,
+    failed_paths_out: Option<String>,
+    skipped_paths_out: Option<String>,
+    profile: Option<String>,
+    min_hashes: Option<usize>,
+    max_hashes: Option<usize>,
+    output_distances: Option<String>,
+    distance_measure: String,
+    verify: bool,
+    require_abundance: bool,
+    detailed_exit_codes: bool,
+    summary_out: Option<String>,
+    flush_rows: Option<usize>,
+    flush_interval_ms: Option<u64>,
+    append: bool,
+    output_shard_size: Option<usize>,
+    output_format: Option<String>,
+    both_directions: bool,
+    rename_query: Option<Vec<String>>,
+    rename_match: Option<Vec<String>>,
+) -> anyhow::Result<u8> {
+    let selection = match build_partial_selection(ksize, scaled, moltype.as_deref())
+        .and_then(|selection| require_sketch_type(selection, require_abundance, None))
+    {
+        Ok(selection) => selection,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let allow_failed_sigpaths = true;
+    let size_filter = match SizeFilter::new(min_hashes, max_hashes) {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let columns = match ColumnSelection::new::<MultiSearchResult>(output_columns)
+        .and_then(|c| c.with_name_rewrites(rename_query, rename_match))
+    {
+        Ok(columns) => columns,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let output_mode = match OutputMode::new(append, output_shard_size, output_format.as_deref()) {
+        Ok(mode) => mode,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let ctx = RunContext::default()
+        .with_output_mode(output_mode)
+        .with_profile(profile, "pairwise")
+        .with_path_reports(failed_paths_out, skipped_paths_out)
+        .with_exit_status("pairwise", detailed_exit_codes, summary_out)
+        .with_flush_policy(flush_rows, flush_interval_ms)
+        .with_size_filter(size_filter)
+        .with_verification(verify);
+    let graph = match output_graph {
+        Some(path) => match GraphOptions::new(path, graph_format, graph_weight) {
+            Ok(g) => Some(g),
+            Err(e) => {
+                eprintln!("Error: {e}");
+                return Ok(1);
+            }
+        },
+        None => None,
+    };
+    let distances = match output_distances {
+        Some(path) => match DistanceOptions::new(path, &distance_measure) {
+            Ok(d) => Some(d),
+            Err(e) => {
+                eprintln!("Error: {e}");
+                return Ok(1);
+            }
+        },
+        None => None,
+    };
+    match ctx.finish(pairwise::pairwise(
+        siglist_path,
+        threshold,
+        selection,
+        allow_failed_sigpaths,
+        estimate_ani,
+        write_all,
+        output_all_comparisons,
+        output_path,
+        graph,
+        candidates,
+        angular_similarity,
+        columns,
+        distances,
+        both_directions,
+        &ctx,
+    )) {
+        Ok(status) => Ok(status),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            Ok(1)
+        }
+    }
+}
+
+/// Run pairwise and return the results as a list of dicts, one per
+/// comparison, e.g. for use with `pandas.DataFrame(results)`.
+#[pyfunction]
+#[allow(clippy::too_many_arguments)]
+#[pyo3(signature = (siglist_path, threshold, ksize, scaled, moltype, estimate_ani=false, write_all=false, output_all_comparisons=false))]
+fn do_pairwise_df<'py>(
+    py: Python<'py>,
+    siglist_path: String,
+    threshold: f64,
+    ksize: u8,
+    scaled: Option<u32>,
+    moltype: String,
+    estimate_ani: bool,
+    write_all: bool,
+    output_all_comparisons: bool,
+) -> PyResult<Vec<Bound<'py, PyAny>>> {
+    let selection = build_selection(ksize, scaled, &moltype).map_err(to_pyerr)?;
+    let allow_failed_sigpaths = true;
+
+    let results = py
+        .allow_threads(|| {
+            pairwise::pairwise_collect(
+                siglist_path,
+                threshold,
+                selection,
+                allow_failed_sigpaths,
+                estimate_ani,
+                write_all,
+                output_all_comparisons,
+            )
+        })
+        .map_err(to_pyerr)?;
+
+    results.iter().map(|r| Ok(pythonize(py, r)?)).collect()
+}
+
+/// Run manysearch and return the results as a list of dicts, one per
+/// match. RocksDB indexes are loaded into memory and searched.
+#[pyfunction]
+#[allow(clippy::too_many_arguments)]
+#[pyo3(signature = (querylist_path, siglist_path, threshold, ksize, scaled, moltype, ignore_abundance=false, output_all_comparisons=false))]
+fn do_manysearch_df<'py>(
+    py: Python<'py>,
+    querylist_path: &Bound<'py, PyAny>,
+    siglist_path: &Bound<'py, PyAny>,
+    threshold: f64,
+    ksize: u8,
+    scaled: Option<u32>,
+    moltype: String,
+    ignore_abundance: bool,
+    output_all_comparisons: bool,
+) -> PyResult<Vec<Bound<'py, PyAny>>> {
+    let query_source = collection_source(querylist_path)?;
+    let against_source = collection_source(siglist_path)?;
+    let selection = build_selection(ksize, scaled, &moltype).map_err(to_pyerr)?;
+    let allow_failed_sigpaths = true;
+
+    let results = py
+        .allow_threads(|| {
+            manysearch::manysearch_collect(
+                query_source,
+                against_source,
+                selection,
+                threshold,
+                allow_failed_sigpaths,
+                ignore_abundance,
+                output_all_comparisons,
+            )
+        })
+        .map_err(to_pyerr)?;
+
+    results.iter().map(|r| Ok(pythonize(py, r)?)).collect()
+}
+
+/// Run fastgather and return the gather results as a list of dicts, one
+/// per match, for one or more queries.
+#[pyfunction]
+#[allow(clippy::too_many_arguments)]
+#[pyo3(signature = (query_filename, siglist_path, threshold_bp, ksize, scaled, moltype, threshold_hashes=None, threshold_fraction=None, max_matches=None, min_ani=None, abundance_weighted=false))]
+fn do_fastgather_df<'py>(
+    py: Python<'py>,
+    query_filename: &Bound<'py, PyAny>,
+    siglist_path: &Bound<'py, PyAny>,
+    threshold_bp: u64,
+    ksize: u8,
+    scaled: Option<u32>,
+    moltype: String,
+    threshold_hashes: Option<u64>,
+    threshold_fraction: Option<f64>,
+    max_matches: Option<usize>,
+    min_ani: Option<f64>,
+    abundance_weighted: bool,
+) -> PyResult<Vec<Bound<'py, PyAny>>> {
+    let threshold = GatherThreshold::new(threshold_bp, threshold_hashes, threshold_fraction)
+        .map_err(to_pyerr)?;
+    let gather_options =
+        GatherOptions::new(max_matches, min_ani, abundance_weighted).map_err(to_pyerr)?;
+    let query_source = collection_source(query_filename)?;
+    let against_source = collection_source(siglist_path)?;
+    let selection = build_selection(ksize, scaled, &moltype).map_err(to_pyerr)?;
+    let allow_failed_sigpaths = true;
+
+    let results = py
+        .allow_threads(|| {
+            fastgather::fastgather_collect(
+                query_source,
+                against_source,
+                threshold,
+                selection,
+                allow_failed_sigpaths,
+                gather_options,
+            )
+        })
+        .map_err(to_pyerr)?;
+
+    results.iter().map(|r| Ok(pythonize(py, r)?)).collect()
+}
+
+/// Count the hashes the query sketches share with each sketch in a
+/// database, as `BranchwaterIndex.screen` does, opening the database just
+/// for this call. Returns a list of dicts, one per match.
+#[pyfunction]
+#[pyo3(signature = (query, index, ksize=31, scaled=None, moltype="DNA", min_overlap=None))]
+fn do_screen<'py>(
+    py: Python<'py>,
+    query: &Bound<'py, PyAny>,
+    index: String,
+    ksize: u8,
+    scaled: Option<u32>,
+    moltype: &str,
+    min_overlap: Option<u64>,
+) -> PyResult<Vec<Bound<'py, PyAny>>> {
+    let query_source = collection_source(query)?;
+    let selection = build_selection(ksize, scaled, moltype).map_err(to_pyerr)?;
+    let allow_failed_sigpaths = true;
+
+    let results = py
+        .allow_threads(|| {
+            let index =
+                serve::LoadedIndex::load(index, selection, 0.01, 50000, allow_failed_sigpaths)?;
+            let queries = query_source.load(
+                index.selection(),
+                ReportType::Query,
+                allow_failed_sigpaths,
+                &RunContext::default(),
+            )?;
+            index.screen(queries, min_overlap)
+        })
+        .map_err(to_pyerr)?;
+
+    results.iter().map(|r| Ok(pythonize(py, r)?)).collect()
+}
+
+#[pyfunction]
+#[pyo3(signature = (siglist_path, ksize, scaled, moltype, output, groups=None, name=None))]
+fn do_intersect(
+    siglist_path: String,
+    ksize: u8,
+    scaled: Option<u32>,
+    moltype: String,
+    output: String,
+    groups: Option<String>,
+    name: Option<String>,
+) -> anyhow::Result<u8> {
+    let selection = match build_selection(ksize, scaled, &moltype) {
+        Ok(selection) => selection,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let allow_failed_sigpaths = true;
+    match intersect::intersect(
+        siglist_path,
+        selection,
+        groups,
+        name,
+        output,
+        allow_failed_sigpaths,
+    ) {
+        Ok(_) => Ok(0),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            Ok(1)
+        }
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (siglist_path, ksize, scaled, moltype, output=None))]
+fn do_overlap(
+    siglist_path: String,
+    ksize: u8,
+    scaled: Option<u32>,
+    moltype: String,
+    output: Option<String>,
+) -> anyhow::Result<u8> {
+    let selection = match build_selection(ksize, scaled, &moltype) {
+        Ok(selection) => selection,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let allow_failed_sigpaths = true;
+    match overlap::overlap(siglist_path, selection, output, allow_failed_sigpaths) {
+        Ok(_) => Ok(0),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            Ok(1)
+        }
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (query_path, against_path, ksizes, scaled, moltype, output))]
+fn do_overlap_summary(
+    query_path: String,
+    against_path: String,
+    ksizes: Vec<u8>,
+    scaled: Option<u32>,
+    moltype: String,
+    output: Option<String>,
+) -> anyhow::Result<u8> {
+    let allow_failed_sigpaths = true;
+    match overlap_summary::overlap_summary(
+        query_path,
+        against_path,
+        ksizes,
+        scaled,
+        moltype,
+        output,
+        allow_failed_sigpaths,
+    ) {
+        Ok(_) => Ok(0),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            Ok(1)
+        }
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (siglist_path, ksizes, scaled, moltype, output))]
+fn do_downsample(
+    siglist_path: String,
+    ksizes: Vec<u8>,
+    scaled: Option<u32>,
+    moltype: String,
+    output: String,
+) -> anyhow::Result<u8> {
+    let allow_failed_sigpaths = true;
+    match downsample::downsample(
+        siglist_path,
+        ksizes,
+        scaled,
+        moltype,
+        output,
+        allow_failed_sigpaths,
+    ) {
+        Ok(_) => Ok(0),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            Ok(1)
+        }
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (siglist_path, ksize, scaled, moltype, groups, output))]
+fn do_merge(
+    siglist_path: String,
+    ksize: u8,
+    scaled: Option<u32>,
+    moltype: String,
+    groups: String,
+    output: String,
+) -> anyhow::Result<u8> {
+    let selection = match build_selection(ksize, scaled, &moltype) {
+        Ok(selection) => selection,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let allow_failed_sigpaths = true;
+    match merge::merge(
+        siglist_path,
+        selection,
+        groups,
+        output,
+        allow_failed_sigpaths,
+    ) {
+        Ok(_) => Ok(0),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            Ok(1)
+        }
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (siglist_path, ksizes, moltype, n_shards, output_prefix, by="count".to_string()))]
+fn do_shard(
+    siglist_path: String,
+    ksizes: Vec<u8>,
+    moltype: String,
+    n_shards: usize,
+    output_prefix: String,
+    by: String,
+) -> anyhow::Result<u8> {
+    let allow_failed_sigpaths = true;
+    let by = match by.parse::<shard::ShardBy>() {
+        Ok(by) => by,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    match shard::shard(
+        siglist_path,
+        ksizes,
+        moltype,
+        n_shards,
+        by,
+        output_prefix,
+        allow_failed_sigpaths,
+    ) {
+        Ok(_) => Ok(0),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            Ok(1)
+        }
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (siglist_path, output=None, format="csv".to_string()))]
+fn do_summarize(
+    siglist_path: String,
+    output: Option<String>,
+    format: String,
+) -> anyhow::Result<u8> {
+    let allow_failed_sigpaths = true;
+    let format = match format.parse::<summarize::SummaryFormat>() {
+        Ok(format) => format,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    match summarize::summarize(siglist_path, output, format, allow_failed_sigpaths) {
+        Ok(_) => Ok(0),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            Ok(1)
+        }
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (n_sketches, n_hashes, scaled, overlap, n_groups, seed, kernels, threads=vec![], threshold=0.01, output=None))]
+#[allow(clippy::too_many_arguments)]
+fn do_bench(
+    n_sketches: usize,
+    n_hashes: usize,
+    scaled: u32,
+    overlap: f64,
+    n_groups: usize,
+    seed: u64,
+    kernels: Vec<String>,
+    threads: Vec<usize>,
+    threshold: f64,
+    output: Option<String>,
+) -> anyhow::Result<u8> {
+    let params = bench::SyntheticParams {
+        n_sketches,
+        n_hashes,
+        scaled,
+        overlap,
+        n_groups,
+        seed,
+    };
+    match bench::bench(params, kernels, threads, threshold, output) {
+        Ok(_) => Ok(0),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            Ok(1)
+        }
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (siglist_path, output=None))]
+fn do_manydescribe(siglist_path: String, output: Option<String>) -> anyhow::Result<u8> {
+    let allow_failed_sigpaths = true;
+    match manydescribe::manydescribe(siglist_path, output, allow_failed_sigpaths) {
+        Ok(_) => Ok(0),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            Ok(1)
+        }
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (zip_path, output=None))]
+fn do_validate_zip(zip_path: String, output: Option<String>) -> anyhow::Result<u8> {
+    match validate_zip::validate_zip(zip_path, output) {
+        Ok(0) => Ok(0),
+        Ok(_) => Ok(1),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            Ok(1)
+        }
+    }
+}
+
+#[pyfunction]
+fn do_zip_cat(zipfiles: Vec<String>, output: String) -> anyhow::Result<u8> {
+    match zip_cat::zip_cat(zipfiles, output) {
+        Ok(_) => Ok(0),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            Ok(1)
+        }
+    }
+}
+
+#[pyfunction]
+#[allow(clippy::too_many_arguments)]
+#[pyo3(signature = (input, output, format=None, ksize=None, scaled=None, moltype=None, force=false, verify=false, require_abundance=false, num=None))]
+fn do_convert(
+    input: String,
+    output: String,
+    format: Option<String>,
+    ksize: Option<u8>,
+    scaled: Option<u32>,
+    moltype: Option<String>,
+    force: bool,
+    verify: bool,
+    require_abundance: bool,
+    num: Option<u32>,
+) -> anyhow::Result<u8> {
+    let format = match format {
+        Some(format) => match format.parse() {
+            Ok(format) => format,
+            Err(e) => {
+                eprintln!("Error: {e}");
+                return Ok(1);
+            }
+        },
+        None => convert::ConvertFormat::from_output(&output),
+    };
+    let selection = match build_partial_selection(ksize, scaled, moltype.as_deref())
+        .and_then(|selection| require_sketch_type(selection, require_abundance, num))
+    {
+        Ok(selection) => selection,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let allow_failed_sigpaths = true;
+    let ctx = RunContext::default().with_verification(verify);
+    match convert::convert(
+        input,
+        output,
+        format,
+        selection,
+        allow_failed_sigpaths,
+        force,
+        &ctx,
+    ) {
+        Ok(_) => Ok(0),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            Ok(1)
+        }
+    }
+}
+
+#[pyfunction]
+#[allow(clippy::too_many_arguments)]
+#[pyo3(signature = (siglist_path, ksizes, moltype, output, name_pattern=None, md5s=vec![], picklist=None))]
+fn do_extract(
+    siglist_path: String,
+    ksizes: Vec<u8>,
+    moltype: String,
+    output: String,
+    name_pattern: Option<String>,
+    md5s: Vec<String>,
+    picklist: Option<String>,
+) -> anyhow::Result<u8> {
+    let allow_failed_sigpaths = true;
+    let filters = match extract::ExtractFilters::new(name_pattern, md5s, picklist) {
+        Ok(filters) => filters,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    match extract::extract(
+        siglist_path,
+        ksizes,
+        moltype,
+        filters,
+        output,
+        allow_failed_sigpaths,
+    ) {
+        Ok(_) => Ok(0),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            Ok(1)
+        }
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (siglist_path, ksizes, moltype, mapping, output))]
+fn do_rename(
+    siglist_path: String,
+    ksizes: Vec<u8>,
+    moltype: String,
+    mapping: String,
+    output: String,
+) -> anyhow::Result<u8> {
+    let allow_failed_sigpaths = true;
+    match rename::rename(
+        siglist_path,
+        ksizes,
+        moltype,
+        mapping,
+        output,
+        allow_failed_sigpaths,
+    ) {
+        Ok(_) => Ok(0),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            Ok(1)
+        }
+    }
+}
+
+#[pyfunction]
+#[allow(clippy::too_many_arguments)]
+#[pyo3(signature = (siglist_path, contaminants_path, ksize, scaled, moltype, output, report=None))]
+fn do_subtract(
+    siglist_path: String,
+    contaminants_path: String,
+    ksize: u8,
+    scaled: Option<u32>,
+    moltype: String,
+    output: String,
+    report: Option<String>,
+) -> anyhow::Result<u8> {
+    let selection = match build_selection(ksize, scaled, &moltype) {
+        Ok(selection) => selection,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    let allow_failed_sigpaths = true;
+    match subtract::subtract(
+        siglist_path,
+        contaminants_path,
+        selection,
+        output,
+        report,
+        allow_failed_sigpaths,
+    ) {
+        Ok(_) => Ok(0),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            Ok(1)
+        }
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (filelist, param_str, output, singleton, force, failures_out=None, on_duplicate="error".to_string()))]
+fn do_manysketch(
+    filelist: String,
+    param_str: String,
+    output: String,
+    singleton: bool,
+    force: bool,
+    failures_out: Option<String>,
+    on_duplicate: String,
+) -> anyhow::Result<u8> {
+    let on_duplicate = match on_duplicate.parse() {
+        Ok(on_duplicate) => on_duplicate,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(1);
+        }
+    };
+    match manysketch::manysketch(
+        filelist,
+        param_str,
+        output,
+        singleton,
+        force,
+        failures_out,
+        on_duplicate,
+    ) {
+        Ok(0) => Ok(0),
+        // some inputs failed, but the rest were sketched.
+        Ok(_) => Ok(2),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            Ok(1)
+        }
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (input_filenames, input_moltype, param_str, output, name))]
+fn do_singlesketch(
+    input_filenames: Vec<String>,
+    input_moltype: String,
+    param_str: String,
+    output: String,
+    name: String,
+) -> anyhow::Result<u8> {
+    match singlesketch::singlesketch(input_filenames, input_moltype, param_str, output, name) {
+        Ok(_) => Ok(0),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            Ok(1)
+        }
+    }
+}
+
+/// The sequences to sketch: one str or bytes, or a list of them.
+fn sequence_list(obj: &Bound<'_, PyAny>) -> PyResult<Vec<Vec<u8>>> {
+    let one = |item: &Bound<'_, PyAny>| -> PyResult<Vec<u8>> {
+        if let Ok(s) = item.downcast::<PyString>() {
+            return Ok(s.to_str()?.as_bytes().to_vec());
+        }
+        Ok(item.downcast::<PyBytes>()?.as_bytes().to_vec())
+    };
+    if obj.downcast::<PyString>().is_ok() || obj.downcast::<PyBytes>().is_ok() {
+        return Ok(vec![one(obj)?]);
+    }
+    obj.try_iter()?.map(|item| one(&item?)).collect()
+}
+
+/// Convert built signatures to sourmash `SourmashSignature` objects.
+fn to_sourmash_sigs<'py>(
+    py: Python<'py>,
+    sigs: Vec<sourmash::signature::Signature>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let json = serde_json::to_string(&sigs).map_err(|e| to_pyerr(e.into()))?;
+    let sigs = py
+        .import("sourmash")?
+        .call_method1("load_signatures", (json,))?;
+    py.import("builtins")?.call_method1("list", (sigs,))
+}
+
+/// Sketch sequences (a str or bytes, or a list of them, e.g. the contigs
+/// of one genome) together, returning one sourmash signature named `name`
+/// per set of parameters in `param_str`, as for `manysketch -p`.
+#[pyfunction]
+#[pyo3(signature = (sequences, param_str="k=31,scaled=1000", moltype="DNA", name=""))]
+fn sketch_sequences<'py>(
+    py: Python<'py>,
+    sequences: &Bound<'py, PyAny>,
+    param_str: &str,
+    moltype: &str,
+    name: &str,
+) -> PyResult<Bound<'py, PyAny>> {
+    let sequences = sequence_list(sequences)?;
+    let params = SketchParams::parse(param_str).map_err(to_pyerr)?;
+    let sigs = py
+        .allow_threads(|| params.sketch_sequences(sequences, moltype, name))
+        .map_err(to_pyerr)?;
+    to_sourmash_sigs(py, sigs)
+}
+
+/// Sketch the records of FASTA/FASTQ data (as bytes, optionally
+/// compressed) together, as for `sketch_sequences`.
+#[pyfunction]
+#[pyo3(signature = (data, param_str="k=31,scaled=1000", moltype="DNA", name=""))]
+fn sketch_fastx<'py>(
+    py: Python<'py>,
+    data: &[u8],
+    param_str: &str,
+    moltype: &str,
+    name: &str,
+) -> PyResult<Bound<'py, PyAny>> {
+    let data = data.to_vec();
+    let params = SketchParams::parse(param_str).map_err(to_pyerr)?;
+    let sigs = py
+        .allow_threads(|| params.sketch_fastx(data, moltype, name))
+        .map_err(to_pyerr)?;
+    to_sourmash_sigs(py, sigs)
+}
+
+#[pyfunction]
+#[pyo3(signature = (pairwise_csv, output_clusters, similarity_column, similarity_threshold, cluster_sizes=None))]
+fn do_cluster(
+    pairwise_csv: String,
+    output_clusters: String,
+    similarity_column: String,
+    similarity_threshold: f64,
+    cluster_sizes: Option<String>,
+) -> anyhow::Result<u8> {
+    match cluster::cluster(
+        pairwise_csv,
+        output_clusters,
+        similarity_column,
+        similarity_threshold,
+        cluster_sizes,
+    ) {
+        Ok(_) => Ok(0),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            Ok(1)
+        }
+    }
+}
+
+/// Module interface for the `sourmash_plugin_branchwater` extension module.
+
+#[pymodule]
+fn sourmash_plugin_branchwater(_py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(do_manysearch, m)?)?;
+    m.add_function(wrap_pyfunction!(manysearch_iter, m)?)?;
+    m.add_function(wrap_pyfunction!(do_fastgather, m)?)?;
+    m.add_function(wrap_pyfunction!(do_fastprefetch, m)?)?;
+    m.add_function(wrap_pyfunction!(do_fastmultigather, m)?)?;
+    m.add_function(wrap_pyfunction!(do_index, m)?)?;
+    m.add_function(wrap_pyfunction!(do_check, m)?)?;
+    m.add_function(wrap_pyfunction!(do_hash_lookup, m)?)?;
+    m.add_function(wrap_pyfunction!(do_serve, m)?)?;
+    m.add_function(wrap_pyfunction!(do_screen, m)?)?;
+    m.add_function(wrap_pyfunction!(do_compat_check, m)?)?;
+    m.add_function(wrap_pyfunction!(do_manysketch, m)?)?;
+    m.add_function(wrap_pyfunction!(set_global_thread_pool, m)?)?;
+    m.add_function(wrap_pyfunction!(do_multisearch, m)?)?;
+    m.add_function(wrap_pyfunction!(do_pairwise, m)?)?;
+    m.add_function(wrap_pyfunction!(do_multisearch_df, m)?)?;
+    m.add_function(wrap_pyfunction!(do_pairwise_df, m)?)?;
+    m.add_function(wrap_pyfunction!(do_manysearch_df, m)?)?;
+    m.add_function(wrap_pyfunction!(do_fastgather_df, m)?)?;
+    m.add_function(wrap_pyfunction!(do_cluster, m)?)?;
+    m.add_function(wrap_pyfunction!(do_singlesketch, m)?)?;
+    m.add_function(wrap_pyfunction!(sketch_sequences, m)?)?;
+    m.add_function(wrap_pyfunction!(sketch_fastx, m)?)?;
+    m.add_function(wrap_pyfunction!(do_intersect, m)?)?;
+    m.add_function(wrap_pyfunction!(do_overlap, m)?)?;
+    m.add_function(wrap_pyfunction!(do_overlap_summary, m)?)?;
+    m.add_function(wrap_pyfunction!(do_subtract, m)?)?;
+    m.add_function(wrap_pyfunction!(do_merge, m)?)?;
+    m.add_function(wrap_pyfunction!(do_downsample, m)?)?;
+    m.add_function(wrap_pyfunction!(do_rename, m)?)?;
+    m.add_function(wrap_pyfunction!(do_extract, m)?)?;
+    m.add_function(wrap_pyfunction!(do_shard, m)?)?;
+    m.add_function(wrap_pyfunction!(do_validate_zip, m)?)?;
+    m.add_function(wrap_pyfunction!(do_zip_cat, m)?)?;
+    m.add_function(wrap_pyfunction!(do_convert, m)?)?;
+    m.add_function(wrap_pyfunction!(do_summarize, m)?)?;
+    m.add_function(wrap_pyfunction!(do_manydescribe, m)?)?;
+    m.add_function(wrap_pyfunction!(do_bench, m)?)?;
+    m.add("EXIT_SUCCESS", status::SUCCESS)?;
+    m.add("EXIT_ERROR", status::ERROR)?;
+    m.add("EXIT_NO_RESULTS", status::NO_RESULTS)?;
+    m.add("EXIT_PARTIAL_FAILURE", status::PARTIAL_FAILURE)?;
+    m.add_class::<ResultStream>()?;
+    m.add_class::<CancelToken>()?;
+    m.add_class::<PyMultiCollection>()?;
+    m.add_class::<PyBranchwaterIndex>()?;
+    m.add_class::<PyLoadedSketches>()?;
+    add_exceptions(m)?;
+
+    Ok(())
+}
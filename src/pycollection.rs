@@ -0,0 +1,112 @@
+//! A Python wrapper around MultiCollection, so that a large collection can
+//! be loaded and selected once, and then searched many times.
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyList, PyString};
+use pythonize::pythonize;
+use sourmash::selection::{Select, Selection};
+
+use crate::errors::to_pyerr;
+use crate::utils::{
+    build_selection, load_collection, CollectionSource, MultiCollection, ReportType, RunContext,
+};
+
+#[pyclass(name = "MultiCollection")]
+pub struct PyMultiCollection {
+    inner: MultiCollection,
+}
+
+#[pymethods]
+impl PyMultiCollection {
+    /// Load a collection from a zip file, RocksDB index, manifest,
+    /// pathlist, or signature file.
+    #[new]
+    #[pyo3(signature = (path, allow_failed=true))]
+    fn new(py: Python<'_>, path: String, allow_failed: bool) -> PyResult<Self> {
+        let inner = py
+            .allow_threads(|| {
+                load_collection(
+                    &path,
+                    &Selection::default(),
+                    ReportType::General,
+                    allow_failed,
+                    &RunContext::default(),
+                )
+            })
+            .map_err(to_pyerr)?;
+        Ok(Self { inner })
+    }
+
+    /// Return a new collection containing only the matching sketches.
+    #[pyo3(signature = (ksize, scaled=None, moltype="DNA"))]
+    fn select(&self, ksize: u8, scaled: Option<u32>, moltype: &str) -> PyResult<Self> {
+        let selection = build_selection(ksize, scaled, moltype).map_err(to_pyerr)?;
+        let inner = self
+            .inner
+            .clone()
+            .select(&selection)
+            .map_err(|e| to_pyerr(e.into()))?;
+        Ok(Self { inner })
+    }
+
+    #[getter]
+    fn max_scaled(&self) -> Option<u32> {
+        self.inner.max_scaled().copied()
+    }
+
+    fn __len__(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Iterate over the manifest records, as dicts.
+    fn __iter__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let records = self
+            .inner
+            .item_iter()
+            .map(|(_, _, record)| Ok(pythonize(py, record)?))
+            .collect::<PyResult<Vec<_>>>()?;
+        Ok(PyList::new(py, records)?.try_iter()?.into_any())
+    }
+}
+
+/// Accept a path, a loaded `MultiCollection`, signature JSON (as str or
+/// bytes), or sourmash `SourmashSignature` objects (one, or a list) from
+/// Python.
+pub fn collection_source(obj: &Bound<'_, PyAny>) -> PyResult<CollectionSource> {
+    if let Ok(coll) = obj.downcast::<PyMultiCollection>() {
+        return Ok(CollectionSource::Loaded(coll.borrow().inner.clone()));
+    }
+
+    if let Ok(s) = obj.downcast::<PyString>() {
+        let s = s.to_str()?;
+        let trimmed = s.trim_start();
+        if trimmed.starts_with('[') || trimmed.starts_with('{') {
+            return from_json(s.as_bytes());
+        }
+        return Ok(CollectionSource::Path(s.to_string()));
+    }
+
+    if let Ok(b) = obj.downcast::<PyBytes>() {
+        return from_json(b.as_bytes());
+    }
+
+    // anything else: have sourmash serialize it for us.
+    let sigs = if obj.downcast::<PyList>().is_ok() {
+        obj.clone()
+    } else {
+        PyList::new(obj.py(), [obj])?.into_any()
+    };
+    let json = obj
+        .py()
+        .import("sourmash")?
+        .call_method1("save_signatures", (sigs,))?;
+    let json = match json.downcast::<PyBytes>() {
+        Ok(b) => b.as_bytes().to_vec(),
+        Err(_) => json.extract::<String>()?.into_bytes(),
+    };
+    from_json(&json)
+}
+
+fn from_json(data: &[u8]) -> PyResult<CollectionSource> {
+    let coll = MultiCollection::from_signature_json(data).map_err(to_pyerr)?;
+    Ok(CollectionSource::Loaded(coll))
+}
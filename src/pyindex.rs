@@ -0,0 +1,123 @@
+//! A Python session object holding an opened database, so that many
+//! searches and gathers can be run against it without re-opening a
+//! RocksDB index (or reloading sketches) for each one.
+use pyo3::prelude::*;
+use pythonize::pythonize;
+
+use crate::errors::to_pyerr;
+use crate::pycollection::collection_source;
+use crate::serve::LoadedIndex;
+use crate::utils::{build_selection, GatherOptions, MultiCollection, ReportType, RunContext};
+
+#[pyclass(name = "BranchwaterIndex")]
+pub struct PyBranchwaterIndex {
+    inner: LoadedIndex,
+}
+
+impl PyBranchwaterIndex {
+    /// Load queries from a path, a `MultiCollection`, signature JSON, or
+    /// sourmash signatures.
+    fn queries(&self, query: &Bound<'_, PyAny>) -> PyResult<MultiCollection> {
+        collection_source(query)?
+            .load(
+                self.inner.selection(),
+                ReportType::Query,
+                true,
+                &RunContext::default(),
+            )
+            .map_err(to_pyerr)
+    }
+}
+
+#[pymethods]
+impl PyBranchwaterIndex {
+    /// Open a RocksDB index, or load any other collection into memory.
+    /// `threshold` and `threshold_bp` are the defaults for `search` and
+    /// `gather`.
+    #[new]
+    #[pyo3(signature = (path, ksize=31, scaled=None, moltype="DNA", threshold=0.01, threshold_bp=50000, allow_failed=true))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        py: Python<'_>,
+        path: String,
+        ksize: u8,
+        scaled: Option<u32>,
+        moltype: &str,
+        threshold: f64,
+        threshold_bp: u64,
+        allow_failed: bool,
+    ) -> PyResult<Self> {
+        let selection = build_selection(ksize, scaled, moltype).map_err(to_pyerr)?;
+        let inner = py
+            .allow_threads(|| {
+                LoadedIndex::load(path, selection, threshold, threshold_bp, allow_failed)
+            })
+            .map_err(to_pyerr)?;
+        Ok(Self { inner })
+    }
+
+    /// Search the query sketches against the index, returning a list of
+    /// dicts with the same keys as the `manysearch` CSV columns.
+    #[pyo3(signature = (query, threshold=None))]
+    fn search<'py>(
+        &self,
+        py: Python<'py>,
+        query: &Bound<'py, PyAny>,
+        threshold: Option<f64>,
+    ) -> PyResult<Vec<Bound<'py, PyAny>>> {
+        let queries = self.queries(query)?;
+        let results = py
+            .allow_threads(|| self.inner.search(queries, threshold))
+            .map_err(to_pyerr)?;
+        results.iter().map(|r| Ok(pythonize(py, r)?)).collect()
+    }
+
+    /// Gather the query sketches against the index, returning a list of
+    /// dicts with the same keys as the `fastmultigather` CSV columns.
+    #[pyo3(signature = (query, threshold_bp=None, max_matches=None, min_ani=None))]
+    fn gather<'py>(
+        &self,
+        py: Python<'py>,
+        query: &Bound<'py, PyAny>,
+        threshold_bp: Option<u64>,
+        max_matches: Option<usize>,
+        min_ani: Option<f64>,
+    ) -> PyResult<Vec<Bound<'py, PyAny>>> {
+        let queries = self.queries(query)?;
+        let gather_options = GatherOptions::new(max_matches, min_ani, false).map_err(to_pyerr)?;
+        let results = py
+            .allow_threads(|| self.inner.gather(queries, threshold_bp, gather_options))
+            .map_err(to_pyerr)?;
+        results.iter().map(|r| Ok(pythonize(py, r)?)).collect()
+    }
+
+    /// Count the hashes the query sketches share with each sketch in the
+    /// index, without computing any other statistics. Returns a list of
+    /// dicts with `query_name`, `query_md5`, `match_name`, `match_md5`, and
+    /// `intersect_hashes`, for matches sharing at least `min_overlap`
+    /// hashes, largest first.
+    #[pyo3(signature = (query, min_overlap=None))]
+    fn screen<'py>(
+        &self,
+        py: Python<'py>,
+        query: &Bound<'py, PyAny>,
+        min_overlap: Option<u64>,
+    ) -> PyResult<Vec<Bound<'py, PyAny>>> {
+        let queries = self.queries(query)?;
+        let results = py
+            .allow_threads(|| self.inner.screen(queries, min_overlap))
+            .map_err(to_pyerr)?;
+        results.iter().map(|r| Ok(pythonize(py, r)?)).collect()
+    }
+
+    /// A dict describing the loaded index: its location, whether it is a
+    /// RocksDB index, the number of sketches, and ksize, scaled, and moltype.
+    #[getter]
+    fn info<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        Ok(pythonize(py, &self.inner.info())?)
+    }
+
+    fn __len__(&self) -> usize {
+        self.inner.info().n_sketches
+    }
+}
@@ -0,0 +1,244 @@
+//! A Python session object holding a collection's sketches in memory, so
+//! that several analyses (e.g. pairwise, clustering, and a distance matrix
+//! for a tree, or multisearch with --knn) can be run on them without
+//! reloading and downsampling the collection for each one.
+use pyo3::prelude::*;
+use pythonize::pythonize;
+
+use sourmash::prelude::Select;
+
+use crate::cluster::cluster_results;
+use crate::errors::{to_pyerr, BranchwaterError};
+use crate::multisearch::multisearch_obj;
+use crate::pairwise::pairwise_obj;
+use crate::pycollection::collection_source;
+use crate::utils::distmatrix::{DistanceMatrix, DistanceOptions};
+use crate::utils::{
+    build_selection, collect_results, MultiSearchResult, ReportType, RunContext, SearchControl,
+    SmallSignature,
+};
+
+#[pyclass(name = "LoadedSketches")]
+pub struct PyLoadedSketches {
+    sketches: Vec<SmallSignature>,
+    ksize: u8,
+    scaled: u32,
+}
+
+impl PyLoadedSketches {
+    fn to_dicts<'py>(
+        py: Python<'py>,
+        results: Vec<MultiSearchResult>,
+    ) -> PyResult<Vec<Bound<'py, PyAny>>> {
+        results.iter().map(|r| Ok(pythonize(py, r)?)).collect()
+    }
+}
+
+#[pymethods]
+impl PyLoadedSketches {
+    /// Load the sketches in a path, a `MultiCollection`, signature JSON, or
+    /// sourmash signatures into memory, downsampled to `scaled` (default:
+    /// the largest scaled in the collection).
+    #[new]
+    #[pyo3(signature = (source, ksize=31, scaled=None, moltype="DNA", allow_failed=true))]
+    fn new(
+        py: Python<'_>,
+        source: &Bound<'_, PyAny>,
+        ksize: u8,
+        scaled: Option<u32>,
+        moltype: &str,
+        allow_failed: bool,
+    ) -> PyResult<Self> {
+        let source = collection_source(source)?;
+        let selection = build_selection(ksize, scaled, moltype).map_err(to_pyerr)?;
+        py.allow_threads(|| -> anyhow::Result<Self> {
+            let collection = source.load(
+                &selection,
+                ReportType::General,
+                allow_failed,
+                &RunContext::default(),
+            )?;
+            let scaled = match scaled {
+                Some(s) => s,
+                None => match collection.max_scaled() {
+                    Some(s) => *s,
+                    None => bail!("no sketches to load"),
+                },
+            };
+            let mut selection = selection;
+            selection.set_scaled(scaled);
+            let sketches = collection.select(&selection)?.load_sketches()?;
+            Ok(Self {
+                sketches,
+                ksize,
+                scaled,
+            })
+        })
+        .map_err(to_pyerr)
+    }
+
+    /// Compare all pairs of sketches, returning a list of dicts with the
+    /// same keys as the `pairwise` CSV columns. With `output_distances`,
+    /// also write a condensed distance matrix, as `pairwise
+    /// --output-distances` does.
+    #[pyo3(signature = (threshold=0.01, estimate_ani=false, write_all=false, output_all_comparisons=false, both_directions=false, output_distances=None, distance_measure="average_containment_ani"))]
+    #[allow(clippy::too_many_arguments)]
+    fn pairwise<'py>(
+        &self,
+        py: Python<'py>,
+        threshold: f64,
+        estimate_ani: bool,
+        write_all: bool,
+        output_all_comparisons: bool,
+        both_directions: bool,
+        output_distances: Option<String>,
+        distance_measure: &str,
+    ) -> PyResult<Vec<Bound<'py, PyAny>>> {
+        let ksize = self.ksize as f64;
+        let distances = output_distances
+            .map(|path| DistanceOptions::new(path, distance_measure))
+            .transpose()
+            .map_err(to_pyerr)?
+            .map(|d| DistanceMatrix::new(d, self.sketches.len(), ksize));
+        let results = py
+            .allow_threads(|| -> anyhow::Result<_> {
+                let (_, results) = collect_results(|send| {
+                    pairwise_obj(
+                        &self.sketches,
+                        estimate_ani,
+                        write_all,
+                        output_all_comparisons,
+                        send,
+                        threshold,
+                        ksize,
+                        None,
+                        false,
+                        distances.as_ref(),
+                        both_directions,
+                    )
+                })?;
+                if let Some(distances) = distances {
+                    distances.write(&self.sketches)?;
+                }
+                Ok(results)
+            })
+            .map_err(to_pyerr)?;
+        Self::to_dicts(py, results)
+    }
+
+    /// Compare each of these sketches against each sketch in `against`
+    /// (default: these sketches), returning a list of dicts with the same
+    /// keys as the `multisearch` CSV columns. With `knn`, only the `knn`
+    /// most similar matches for each query are returned.
+    #[pyo3(signature = (against=None, threshold=0.01, estimate_ani=false, output_all_comparisons=false, knn=None, exclude_self_matches=false))]
+    #[allow(clippy::too_many_arguments)]
+    fn multisearch<'py>(
+        &self,
+        py: Python<'py>,
+        against: Option<PyRef<'py, PyLoadedSketches>>,
+        threshold: f64,
+        estimate_ani: bool,
+        output_all_comparisons: bool,
+        knn: Option<usize>,
+        exclude_self_matches: bool,
+    ) -> PyResult<Vec<Bound<'py, PyAny>>> {
+        let againsts = match &against {
+            Some(against) => {
+                if (against.ksize, against.scaled) != (self.ksize, self.scaled) {
+                    return Err(to_pyerr(
+                        BranchwaterError::IncompatibleSelection(format!(
+                            "against sketches have ksize={}, scaled={}; expected ksize={}, scaled={}",
+                            against.ksize, against.scaled, self.ksize, self.scaled
+                        ))
+                        .into(),
+                    ));
+                }
+                &against.sketches
+            }
+            None => &self.sketches,
+        };
+        if knn == Some(0) {
+            return Err(to_pyerr(anyhow::anyhow!("knn must be at least 1")));
+        }
+        let results = py
+            .allow_threads(|| {
+                collect_results(|send| {
+                    multisearch_obj(
+                        &self.sketches,
+                        againsts,
+                        threshold,
+                        estimate_ani,
+                        false,
+                        output_all_comparisons,
+                        send,
+                        self.scaled,
+                        self.ksize as f64,
+                        &SearchControl::default(),
+                        knn,
+                        false,
+                        exclude_self_matches,
+                        None,
+                        false,
+                        &RunContext::default(),
+                    )
+                })
+            })
+            .map_err(to_pyerr)?
+            .1;
+        Self::to_dicts(py, results)
+    }
+
+    /// Cluster the sketches, as `pairwise` followed by `cluster` would:
+    /// sketches are linked when `similarity_column` is at least
+    /// `similarity_threshold`. Returns a list of clusters, each a sorted
+    /// list of sketch names; unlinked sketches are clusters of one.
+    #[pyo3(signature = (similarity_column="average_containment_ani", similarity_threshold=0.95))]
+    fn cluster(
+        &self,
+        py: Python<'_>,
+        similarity_column: &str,
+        similarity_threshold: f64,
+    ) -> PyResult<Vec<Vec<String>>> {
+        let estimate_ani = similarity_column.ends_with("_ani");
+        py.allow_threads(|| {
+            let (_, results) = collect_results(|send| {
+                pairwise_obj(
+                    &self.sketches,
+                    estimate_ani,
+                    false,
+                    false,
+                    send,
+                    0.0,
+                    self.ksize as f64,
+                    None,
+                    false,
+                    None,
+                    false,
+                )
+            })?;
+            let names: Vec<&str> = self.sketches.iter().map(|s| &*s.name).collect();
+            cluster_results(results, &names, similarity_column, similarity_threshold)
+        })
+        .map_err(to_pyerr)
+    }
+
+    /// The names of the loaded sketches, in order.
+    #[getter]
+    fn names(&self) -> Vec<String> {
+        self.sketches.iter().map(|s| s.name.to_string()).collect()
+    }
+
+    #[getter]
+    fn ksize(&self) -> u8 {
+        self.ksize
+    }
+
+    #[getter]
+    fn scaled(&self) -> u32 {
+        self.scaled
+    }
+
+    fn __len__(&self) -> usize {
+        self.sketches.len()
+    }
+}
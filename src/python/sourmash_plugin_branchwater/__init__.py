@@ -11,6 +11,18 @@ from . import prettyprint
 
 __version__ = importlib.metadata.version("sourmash_plugin_branchwater")
 
+# exit statuses; search commands only return the last two with
+# --detailed-exit-codes.
+EXIT_SUCCESS = sourmash_plugin_branchwater.EXIT_SUCCESS
+EXIT_ERROR = sourmash_plugin_branchwater.EXIT_ERROR
+EXIT_NO_RESULTS = sourmash_plugin_branchwater.EXIT_NO_RESULTS
+EXIT_PARTIAL_FAILURE = sourmash_plugin_branchwater.EXIT_PARTIAL_FAILURE
+
+
+def finished(status):
+    "True if a command ran to the end, whether or not it found results."
+    return status in (EXIT_SUCCESS, EXIT_NO_RESULTS, EXIT_PARTIAL_FAILURE)
+
 
 def print_version():
     notify(
@@ -42,6 +54,395 @@ def set_thread_pool(user_cores):
     return actual_rayon_cores
 
 
+def add_taxonomy_args(p):
+    p.add_argument(
+        "--taxonomy",
+        "--lineages",
+        default=None,
+        help="lineages CSV (as for 'sourmash tax'); summarize matches by rank",
+    )
+    p.add_argument(
+        "--tax-summary-output",
+        default=None,
+        help="CSV output file for the taxonomic summary (default: based on -o)",
+    )
+    p.add_argument(
+        "--output-cami",
+        default=None,
+        help="write a CAMI Bioboxes profile to this file (requires --taxonomy)",
+    )
+    p.add_argument(
+        "--output-kraken",
+        default=None,
+        help="write a kraken-style report to this file (requires --taxonomy); one per query, if there are several",
+    )
+
+
+def add_graph_args(p):
+    p.add_argument(
+        "--output-graph",
+        default=None,
+        help="also write the comparison graph to this file, for Gephi/Cytoscape/graphviz",
+    )
+    p.add_argument(
+        "--graph-format",
+        default=None,
+        choices=["graphml", "dot"],
+        help="graph file format (default: guessed from the --output-graph extension)",
+    )
+    p.add_argument(
+        "--graph-weight",
+        default="max_containment",
+        choices=[
+            "containment",
+            "max_containment",
+            "jaccard",
+            "average_containment_ani",
+            "max_containment_ani",
+            "angular_similarity",
+        ],
+        help="column to use for edge weights (default: max_containment)",
+    )
+
+
+def add_angular_similarity_args(p):
+    p.add_argument(
+        "--angular-similarity",
+        action="store_true",
+        help="also report abundance-weighted angular similarity; requires sketches with abundances",
+    )
+
+
+def add_self_match_args(p):
+    group = p.add_mutually_exclusive_group()
+    group.add_argument(
+        "--include-self-matches",
+        dest="exclude_self_matches",
+        action="store_false",
+        default=False,
+        help="report matches between a query and an identical (same md5) against sketch (default)",
+    )
+    group.add_argument(
+        "--exclude-self-matches",
+        dest="exclude_self_matches",
+        action="store_true",
+        help="skip matches between a query and an identical (same md5) against sketch",
+    )
+
+
+def add_coverage_report_args(p):
+    p.add_argument(
+        "--coverage-report",
+        default=None,
+        help="also write a CSV with the fraction of each against sketch covered by the union of all queries, and vice versa",
+    )
+
+
+def add_output_columns_args(p):
+    p.add_argument(
+        "--output-columns",
+        default=None,
+        help="comma-separated list of columns to write to the output CSV, e.g. 'query_name,match_name,containment' (default: all columns)",
+    )
+
+
+def add_rename_args(p):
+    p.add_argument(
+        "--rename-query",
+        action="append",
+        default=None,
+        metavar="s/PATTERN/REPLACEMENT/",
+        help="rewrite query names in the output CSV with a sed-style regex substitution, e.g. 's/\\.fa.*$//'; may be repeated",
+    )
+    p.add_argument(
+        "--rename-match",
+        action="append",
+        default=None,
+        metavar="s/PATTERN/REPLACEMENT/",
+        help="rewrite match names in the output CSV with a sed-style regex substitution; may be repeated",
+    )
+
+
+def add_path_report_args(p):
+    p.add_argument(
+        "--failed-paths-out",
+        default=None,
+        help="write a CSV of input paths that failed to load, with reasons",
+    )
+    p.add_argument(
+        "--skipped-paths-out",
+        default=None,
+        help="write a CSV of input paths skipped because they held no compatible sketches, with reasons",
+    )
+
+
+def add_exclude_args(p):
+    p.add_argument(
+        "--exclude",
+        default=None,
+        help="drop the sketches listed in this manifest (matched by md5), or picklist ('file.csv:column:coltype'), from the against collection",
+    )
+
+
+def add_stoplist_args(p):
+    p.add_argument(
+        "--stoplist",
+        default=None,
+        help="remove the hashes in the sketches in this file (e.g. adapters or host) from each query before comparison",
+    )
+
+
+def add_sourmash_compat_args(p):
+    p.add_argument(
+        "--sourmash-compat",
+        action="store_true",
+        help="write gather and prefetch output with the same columns as 'sourmash gather' and 'sourmash prefetch', e.g. for 'sourmash tax'",
+    )
+
+
+def add_dedup_args(p):
+    p.add_argument(
+        "--dedup-by-md5",
+        action="store_true",
+        help="collapse against sketches with identical md5s, reporting one match and listing the other names in a 'match_aliases' column",
+    )
+
+
+def add_size_filter_args(p):
+    p.add_argument(
+        "--min-hashes",
+        default=None,
+        type=int,
+        help="skip sketches with fewer hashes than this, as recorded in their manifests",
+    )
+    p.add_argument(
+        "--max-hashes",
+        default=None,
+        type=int,
+        help="skip sketches with more hashes than this, as recorded in their manifests",
+    )
+
+
+def add_verify_args(p):
+    p.add_argument(
+        "--verify",
+        action="store_true",
+        help="recompute the md5 of every sketch loaded, and fail if any differs from its manifest; catches corrupted databases, at the cost of reading each sketch again",
+    )
+
+
+def add_require_abundance_args(p):
+    p.add_argument(
+        "--require-abundance",
+        action="store_true",
+        help="skip sketches without abundances, listing them, rather than reporting empty abundance-weighted results; with fastgather and fastmultigather, applies to queries only",
+    )
+
+
+def add_per_query_scaled_args(p):
+    p.add_argument(
+        "--per-query-scaled",
+        action="store_true",
+        help="compare each query at the max scaled of the against sketches, or at its own scaled if higher, rather than downsampling all queries to a single scaled; the scaled used is in each output row (non-RocksDB only)",
+    )
+
+
+def add_detailed_exit_codes_args(p):
+    p.add_argument(
+        "--detailed-exit-codes",
+        action="store_true",
+        help=f"exit with {EXIT_NO_RESULTS} if no results pass the threshold, and {EXIT_PARTIAL_FAILURE} if some input paths failed to load, rather than 0",
+    )
+
+
+def add_summary_out_args(p):
+    p.add_argument(
+        "--summary-out",
+        default=None,
+        help="write a JSON summary of the run (inputs loaded, skipped, and failed, comparisons, results, and runtime) to this file; it is also printed on a 'SUMMARY:' line of stderr",
+    )
+
+
+def add_output_mode_args(p):
+    p.add_argument(
+        "--append",
+        action="store_true",
+        help="add results to the end of an existing output CSV, without a header",
+    )
+    p.add_argument(
+        "--output-shard-size",
+        default=None,
+        type=int,
+        help="start a new output CSV after this many rows: 'out.csv', then 'out.part0001.csv', and so on",
+    )
+    p.add_argument(
+        "--output-format",
+        default=None,
+        choices=["csv", "arrow", "sqlite"],
+        help="write results as CSV, as an Arrow IPC stream (e.g. for pyarrow or polars), or into a SQLite database; default: SQLite for outputs ending in .db, .sqlite, or .sqlite3, and otherwise CSV",
+    )
+
+
+def add_flush_args(p):
+    p.add_argument(
+        "--flush-rows",
+        default=None,
+        type=int,
+        help="flush result outputs after every N rows; 0 disables. Setting this or --flush-interval-ms replaces the default of flushing after every row for named pipes and sockets, and only at the end for files",
+    )
+    p.add_argument(
+        "--flush-interval-ms",
+        default=None,
+        type=int,
+        help="flush result outputs once N milliseconds have passed since the last flush; 0 disables",
+    )
+
+
+def add_profile_args(p):
+    p.add_argument(
+        "--profile",
+        default=None,
+        help="write the time spent loading, selecting, comparing, and writing to this file; JSON if it ends in '.json', CSV otherwise",
+    )
+
+
+def add_search_mode_args(p):
+    p.add_argument(
+        "--search-mode",
+        choices=["auto", "in-memory", "rocksdb"],
+        default="auto",
+        help="search the database by loading its sketches into memory, or through a RocksDB index; 'auto' uses the index if the database is one (default: auto)",
+    )
+
+
+def add_force_args(p):
+    p.add_argument(
+        "--force",
+        action="store_true",
+        help="overwrite existing output files",
+    )
+
+
+# argument destinations that name output files or directories.
+OUTPUT_ARGS = (
+    "output",
+    "output_gather",
+    "output_prefetch",
+    "output_graph",
+    "output_distances",
+    "coverage_report",
+    "output_groups",
+    "output_matched_hashes",
+    "tax_summary_output",
+    "output_cami",
+    "output_kraken",
+    "cluster_sizes",
+    "report",
+    "failed_paths_out",
+    "skipped_paths_out",
+    "profile",
+    "summary_out",
+)
+
+# outputs written by the result writers, which --append adds to.
+APPENDABLE_OUTPUT_ARGS = ("output", "output_gather")
+
+
+def check_outputs(args):
+    "Complain and return False if any output exists, unless --force was given."
+    if args.force:
+        return True
+    for name in OUTPUT_ARGS:
+        # with --append, the results CSV is expected to exist.
+        if getattr(args, "append", False) and name in APPENDABLE_OUTPUT_ARGS:
+            continue
+        path = getattr(args, name, None)
+        # only regular files and directories; e.g. /dev/stdout is fine.
+        if path and (os.path.isfile(path) or os.path.isdir(path)):
+            notify(f"ERROR: output '{path}' already exists; use --force to overwrite")
+            return False
+    return True
+
+
+def add_threshold_args(p):
+    group = p.add_mutually_exclusive_group()
+    group.add_argument(
+        "-t",
+        "--threshold-bp",
+        default=50000,
+        type=float,
+        help="threshold in estimated base pairs, for reporting matches (default: 50kb)",
+    )
+    group.add_argument(
+        "--threshold-hashes",
+        default=None,
+        type=int,
+        help="threshold in number of shared hashes, for reporting matches",
+    )
+    group.add_argument(
+        "--threshold-fraction",
+        default=None,
+        type=float,
+        help="threshold as a fraction of the query's hashes, for reporting matches",
+    )
+
+
+def add_gather_options_args(p):
+    p.add_argument(
+        "--max-matches",
+        default=None,
+        type=int,
+        help="stop gather after this many matches for each query",
+    )
+    p.add_argument(
+        "--min-ani",
+        default=None,
+        type=float,
+        help="stop gather at the first match with max containment ANI below this (0 to 1)",
+    )
+    p.add_argument(
+        "--abundance-weighted",
+        action="store_true",
+        default=False,
+        help="at each rank, pick the match sharing the most query abundance rather than the most hashes (non-RocksDB only)",
+    )
+
+
+def describe_threshold(args):
+    if args.threshold_hashes is not None:
+        return f"threshold hashes: {args.threshold_hashes}"
+    if args.threshold_fraction is not None:
+        return f"threshold fraction: {args.threshold_fraction}"
+    return f"threshold bp: {args.threshold_bp}"
+
+
+def get_tax_summary_output(args, output):
+    """Check taxonomy args, and pick a default summary filename.
+
+    Returns (ok, tax_summary_output).
+    """
+    if not args.taxonomy:
+        if args.tax_summary_output or args.output_cami or args.output_kraken:
+            notify(
+                "ERROR: --tax-summary-output, --output-cami, and --output-kraken require --taxonomy"
+            )
+            return False, None
+        return True, None
+
+    if args.tax_summary_output:
+        return True, args.tax_summary_output
+    if output:
+        base = output
+        if base.endswith(".csv"):
+            base = base[:-4]
+        return True, f"{base}.summarized.csv"
+    if args.output_cami or args.output_kraken:
+        return True, None
+
+    notify("ERROR: --taxonomy requires -o/--output or --tax-summary-output")
+    return False, None
+
+
 class Branchwater_Manysearch(CommandLinePlugin):
     command = "manysearch"
     description = "search many metagenomes for contained genomes"
@@ -60,12 +461,25 @@ class Branchwater_Manysearch(CommandLinePlugin):
             type=float,
             help="containment threshold for reporting matches (default: 0.01)",
         )
+        group = p.add_mutually_exclusive_group()
+        group.add_argument(
+            "--min-overlap-bp",
+            default=None,
+            type=float,
+            help="also require matches to share at least this many estimated base pairs with the query",
+        )
+        group.add_argument(
+            "--min-intersect-hashes",
+            default=None,
+            type=int,
+            help="also require matches to share at least this many hashes with the query",
+        )
         p.add_argument(
             "-k",
             "--ksize",
-            default=31,
+            default=None,
             type=int,
-            help="k-mer size at which to select sketches",
+            help="k-mer size at which to select sketches (default: from the query sketches)",
         )
         p.add_argument(
             "-s",
@@ -77,9 +491,9 @@ class Branchwater_Manysearch(CommandLinePlugin):
         p.add_argument(
             "-m",
             "--moltype",
-            default="DNA",
+            default=None,
             choices=["DNA", "protein", "dayhoff", "hp", "skipm1n3", "skipm2n3"],
-            help="molecule type: DNA, protein, dayhoff, hp, or skipmer (skipm1n3 or skipm2n3); default DNA",
+            help="molecule type: DNA, protein, dayhoff, hp, or skipmer (skipm1n3 or skipm2n3); default: from the query sketches",
         )
         p.add_argument(
             "-c",
@@ -113,12 +527,48 @@ class Branchwater_Manysearch(CommandLinePlugin):
             action="store_true",
             help="ignore threshold and output all comparisons; against a RocksDB database, this will only output comparisons with some overlap",
         )
+        p.add_argument(
+            "--full-results",
+            action="store_true",
+            help="against a RocksDB database, also calculate match_md5, jaccard, max_containment, and match-direction ANI columns from match sizes; slower if sketches must be downsampled",
+        )
+        add_coverage_report_args(p)
+        p.add_argument(
+            "--output-matched-hashes",
+            default=None,
+            help="also write the hashes shared by each query and match to this binary file, e.g. to plot coverage of references by samples; read it with sourmash_plugin_branchwater.matchedhashes",
+        )
+        p.add_argument(
+            "--match-metadata",
+            default=None,
+            help="CSV mapping against sketch names (first column) to attributes such as biome or study; its other columns are added to each row for the match",
+        )
+        add_self_match_args(p)
+        add_output_columns_args(p)
+        add_rename_args(p)
+        add_path_report_args(p)
+        add_exclude_args(p)
+        add_stoplist_args(p)
+        add_dedup_args(p)
+        add_profile_args(p)
+        add_per_query_scaled_args(p)
+        add_search_mode_args(p)
+        add_verify_args(p)
+        add_detailed_exit_codes_args(p)
+        add_summary_out_args(p)
+        add_flush_args(p)
+        add_output_mode_args(p)
+        add_force_args(p)
 
     def main(self, args):
         print_version()
+        if not check_outputs(args):
+            return 1
+
         notify(
             f"ksize: {args.ksize} / scaled: {args.scaled} / moltype: {args.moltype} / threshold: {args.threshold}"
         )
+
         num_threads = set_thread_pool(args.cores)
 
         notify(
@@ -136,11 +586,47 @@ class Branchwater_Manysearch(CommandLinePlugin):
             args.output,
             args.ignore_abundance,
             args.output_all_comparisons,
-        )
-        if status == 0:
+            full_results=args.full_results,
+            coverage_report=args.coverage_report,
+            exclude_self_matches=args.exclude_self_matches,
+            output_columns=args.output_columns,
+            rename_query=args.rename_query,
+            rename_match=args.rename_match,
+            failed_paths_out=args.failed_paths_out,
+            skipped_paths_out=args.skipped_paths_out,
+            profile=args.profile,
+            search_mode=args.search_mode,
+            exclude=args.exclude,
+            stoplist=args.stoplist,
+            output_matched_hashes=args.output_matched_hashes,
+            verify=args.verify,
+            detailed_exit_codes=args.detailed_exit_codes,
+            summary_out=args.summary_out,
+            flush_rows=args.flush_rows,
+            flush_interval_ms=args.flush_interval_ms,
+            append=args.append,
+            output_shard_size=args.output_shard_size,
+            output_format=args.output_format,
+            dedup_by_md5=args.dedup_by_md5,
+            per_query_scaled=args.per_query_scaled,
+            min_overlap_bp=(
+                None if args.min_overlap_bp is None else int(args.min_overlap_bp)
+            ),
+            min_intersect_hashes=args.min_intersect_hashes,
+            match_metadata=args.match_metadata,
+        )
+        if finished(status):
             notify(f"...manysearch is done! results in '{args.output}'")
 
-            if args.pretty_print:
+            # pretty-printing re-reads the output, so needs the full set
+            # of columns in a regular CSV file (not e.g. a named pipe).
+            if (
+                args.pretty_print
+                and not args.output_columns
+                and args.output_format in (None, "csv")
+                and not args.output.lower().endswith((".db", ".sqlite", ".sqlite3"))
+                and os.path.isfile(args.output)
+            ):
                 prettyprint.pretty_print_manysearch(args.output)
         return status
 
@@ -151,7 +637,7 @@ class Branchwater_Fastgather(CommandLinePlugin):
 
     def __init__(self, p):
         super().__init__(p)
-        p.add_argument("query_sig", help="metagenome sketch")
+        p.add_argument("query_sig", help="metagenome sketch(es)")
         p.add_argument("against_paths", help="input file of sketches")
         p.add_argument(
             "-o",
@@ -162,19 +648,21 @@ class Branchwater_Fastgather(CommandLinePlugin):
         p.add_argument(
             "--output-prefetch", help="save prefetch output (all overlaps) to this file"
         )
-        p.add_argument(
-            "-t",
-            "--threshold-bp",
-            default=50000,
-            type=float,
-            help="threshold in estimated base pairs, for reporting matches (default: 50kb)",
-        )
+        add_threshold_args(p)
+        add_gather_options_args(p)
+        add_output_columns_args(p)
+        add_rename_args(p)
+        add_path_report_args(p)
+        add_exclude_args(p)
+        add_stoplist_args(p)
+        add_sourmash_compat_args(p)
+        add_profile_args(p)
         p.add_argument(
             "-k",
             "--ksize",
-            default=31,
+            default=None,
             type=int,
-            help="k-mer size at which to do comparisons (default: 31)",
+            help="k-mer size at which to select sketches (default: from the query sketches)",
         )
         p.add_argument(
             "-s",
@@ -186,9 +674,9 @@ class Branchwater_Fastgather(CommandLinePlugin):
         p.add_argument(
             "-m",
             "--moltype",
-            default="DNA",
+            default=None,
             choices=["DNA", "protein", "dayhoff", "hp", "skipm1n3", "skipm2n3"],
-            help="molecule type: DNA, protein, dayhoff, hp, or skipmer (skipm1n3 or skipm2n3); default DNA",
+            help="molecule type: DNA, protein, dayhoff, hp, or skipmer (skipm1n3 or skipm2n3); default: from the query sketches",
         )
         p.add_argument(
             "-c",
@@ -197,13 +685,28 @@ class Branchwater_Fastgather(CommandLinePlugin):
             type=int,
             help="number of cores to use (default is all available)",
         )
+        add_taxonomy_args(p)
+        add_require_abundance_args(p)
+        add_verify_args(p)
+        add_detailed_exit_codes_args(p)
+        add_summary_out_args(p)
+        add_flush_args(p)
+        add_output_mode_args(p)
+        add_force_args(p)
 
     def main(self, args):
         print_version()
+        if not check_outputs(args):
+            return 1
+
         notify(
-            f"ksize: {args.ksize} / scaled: {args.scaled} / moltype: {args.moltype} / threshold bp: {args.threshold_bp}"
+            f"ksize: {args.ksize} / scaled: {args.scaled} / moltype: {args.moltype} / {describe_threshold(args)}"
         )
 
+        ok, tax_summary_output = get_tax_summary_output(args, args.output_gather)
+        if not ok:
+            return 1
+
         num_threads = set_thread_pool(args.cores)
 
         notify(
@@ -219,14 +722,139 @@ class Branchwater_Fastgather(CommandLinePlugin):
             args.moltype,
             args.output_gather,
             args.output_prefetch,
-        )
-        if status == 0:
+            args.taxonomy,
+            tax_summary_output,
+            args.output_cami,
+            args.output_kraken,
+            args.threshold_hashes,
+            args.threshold_fraction,
+            args.max_matches,
+            args.min_ani,
+            args.abundance_weighted,
+            output_columns=args.output_columns,
+            rename_query=args.rename_query,
+            rename_match=args.rename_match,
+            failed_paths_out=args.failed_paths_out,
+            skipped_paths_out=args.skipped_paths_out,
+            profile=args.profile,
+            exclude=args.exclude,
+            stoplist=args.stoplist,
+            sourmash_compat=args.sourmash_compat,
+            verify=args.verify,
+            detailed_exit_codes=args.detailed_exit_codes,
+            summary_out=args.summary_out,
+            flush_rows=args.flush_rows,
+            flush_interval_ms=args.flush_interval_ms,
+            append=args.append,
+            output_shard_size=args.output_shard_size,
+            output_format=args.output_format,
+            require_abundance=args.require_abundance,
+        )
+        if finished(status):
             notify(f"...fastgather is done! gather results in '{args.output_gather}'")
             if args.output_prefetch:
                 notify(f"prefetch results in '{args.output_prefetch}'")
         return status
 
 
+class Branchwater_Fastprefetch(CommandLinePlugin):
+    command = "fastprefetch"
+    description = "massively parallel prefetch: find all sketches overlapping each query"
+
+    def __init__(self, p):
+        super().__init__(p)
+        p.add_argument("query_sig", help="query sketch(es)")
+        p.add_argument("against_paths", help="input file of sketches")
+        p.add_argument(
+            "-o",
+            "--output",
+            required=True,
+            help="save prefetch output (all overlaps above threshold) to this file",
+        )
+        add_threshold_args(p)
+        add_path_report_args(p)
+        add_exclude_args(p)
+        add_stoplist_args(p)
+        add_sourmash_compat_args(p)
+        add_profile_args(p)
+        p.add_argument(
+            "-k",
+            "--ksize",
+            default=None,
+            type=int,
+            help="k-mer size at which to select sketches (default: from the query sketches)",
+        )
+        p.add_argument(
+            "-s",
+            "--scaled",
+            default=None,
+            type=int,
+            help="scaled factor at which to do comparisons (default: determined from query)",
+        )
+        p.add_argument(
+            "-m",
+            "--moltype",
+            default=None,
+            choices=["DNA", "protein", "dayhoff", "hp", "skipm1n3", "skipm2n3"],
+            help="molecule type: DNA, protein, dayhoff, hp, or skipmer (skipm1n3 or skipm2n3); default: from the query sketches",
+        )
+        p.add_argument(
+            "-c",
+            "--cores",
+            default=0,
+            type=int,
+            help="number of cores to use (default is all available)",
+        )
+        add_search_mode_args(p)
+        add_verify_args(p)
+        add_detailed_exit_codes_args(p)
+        add_summary_out_args(p)
+        add_flush_args(p)
+        add_force_args(p)
+
+    def main(self, args):
+        print_version()
+        if not check_outputs(args):
+            return 1
+
+        notify(
+            f"ksize: {args.ksize} / scaled: {args.scaled} / moltype: {args.moltype} / {describe_threshold(args)}"
+        )
+
+        num_threads = set_thread_pool(args.cores)
+
+        notify(
+            f"prefetching all sketches in '{args.query_sig}' against '{args.against_paths}' using {num_threads} threads"
+        )
+        super().main(args)
+        status = sourmash_plugin_branchwater.do_fastprefetch(
+            args.query_sig,
+            args.against_paths,
+            int(args.threshold_bp),
+            args.ksize,
+            args.scaled,
+            args.moltype,
+            args.output,
+            threshold_hashes=args.threshold_hashes,
+            threshold_fraction=args.threshold_fraction,
+            failed_paths_out=args.failed_paths_out,
+            skipped_paths_out=args.skipped_paths_out,
+            search_mode=args.search_mode,
+            profile=args.profile,
+            exclude=args.exclude,
+            stoplist=args.stoplist,
+            sourmash_compat=args.sourmash_compat,
+            verify=args.verify,
+            detailed_exit_codes=args.detailed_exit_codes,
+            summary_out=args.summary_out,
+            flush_rows=args.flush_rows,
+            flush_interval_ms=args.flush_interval_ms,
+        )
+        if finished(status):
+            notify(f"...fastprefetch is done! prefetch results in '{args.output}'")
+        return status
+
+
 class Branchwater_Fastmultigather(CommandLinePlugin):
     command = "fastmultigather"
     description = "massively parallel sketch multigather"
@@ -239,19 +867,21 @@ class Branchwater_Fastmultigather(CommandLinePlugin):
             help="input file of sketches to search against \
                        OR a branchwater indexed database generated with 'sourmash scripts index'",
         )
-        p.add_argument(
-            "-t",
-            "--threshold-bp",
-            default=50000,
-            type=float,
-            help="threshold in estimated base pairs, for reporting matches (default: 50kb)",
-        )
+        add_threshold_args(p)
+        add_gather_options_args(p)
+        add_output_columns_args(p)
+        add_rename_args(p)
+        add_path_report_args(p)
+        add_exclude_args(p)
+        add_stoplist_args(p)
+        add_sourmash_compat_args(p)
+        add_profile_args(p)
         p.add_argument(
             "-k",
             "--ksize",
-            default=31,
+            default=None,
             type=int,
-            help="k-mer size at which to do comparisons (default: 31)",
+            help="k-mer size at which to select sketches (default: from the query sketches)",
         )
         p.add_argument(
             "-s",
@@ -263,9 +893,9 @@ class Branchwater_Fastmultigather(CommandLinePlugin):
         p.add_argument(
             "-m",
             "--moltype",
-            default="DNA",
+            default=None,
             choices=["DNA", "protein", "dayhoff", "hp", "skipm1n3", "skipm2n3"],
-            help="molecule type: DNA, protein, dayhoff, hp, or skipmer (skipm1n3 or skipm2n3); default DNA",
+            help="molecule type: DNA, protein, dayhoff, hp, or skipmer (skipm1n3 or skipm2n3); default: from the query sketches",
         )
         p.add_argument(
             "-c",
@@ -292,13 +922,54 @@ class Branchwater_Fastmultigather(CommandLinePlugin):
             default=False,
             help="save matched hashes for every input to a signature",
         )
+        p.add_argument(
+            "--save-unassigned",
+            action="store_true",
+            default=False,
+            help="save the hashes not matched for every input to a signature",
+        )
+        p.add_argument(
+            "--output-dir",
+            help="directory for per-query prefetch and matches outputs (default: current directory)",
+        )
+        p.add_argument(
+            "--output-template",
+            help="base name for per-query outputs, built from {name}, {ident}, {md5}, and {md5short} (default: first word of query name)",
+        )
+        p.add_argument(
+            "--shared-prefetch",
+            action="store_true",
+            default=False,
+            help="build a hash index of the database once and use it for every query's prefetch; faster for many queries, but uses more memory (non-RocksDB only)",
+        )
+        p.add_argument(
+            "--refine-from",
+            help="on a RocksDB database, find candidate matches with the index, then gather in memory against their sketches loaded from this collection (e.g. the zip the index was built from)",
+        )
+        add_taxonomy_args(p)
+        add_per_query_scaled_args(p)
+        add_search_mode_args(p)
+        add_require_abundance_args(p)
+        add_verify_args(p)
+        add_detailed_exit_codes_args(p)
+        add_summary_out_args(p)
+        add_flush_args(p)
+        add_output_mode_args(p)
+        add_force_args(p)
 
     def main(self, args):
         print_version()
+        if not check_outputs(args):
+            return 1
+
         notify(
-            f"ksize: {args.ksize} / scaled: {args.scaled} / moltype: {args.moltype} / threshold bp: {args.threshold_bp} / save matches: {args.save_matches}"
+            f"ksize: {args.ksize} / scaled: {args.scaled} / moltype: {args.moltype} / {describe_threshold(args)} / save matches: {args.save_matches} / save unassigned: {args.save_unassigned}"
         )
 
+        ok, tax_summary_output = get_tax_summary_output(args, args.output)
+        if not ok:
+            return 1
+
         num_threads = set_thread_pool(args.cores)
 
         notify(
@@ -315,8 +986,43 @@ class Branchwater_Fastmultigather(CommandLinePlugin):
             args.output,
             args.save_matches,
             args.create_empty_results,
-        )
-        if status == 0:
+            args.taxonomy,
+            tax_summary_output,
+            args.output_cami,
+            args.output_kraken,
+            args.output_dir,
+            args.output_template,
+            args.shared_prefetch,
+            args.threshold_hashes,
+            args.threshold_fraction,
+            args.max_matches,
+            args.min_ani,
+            args.abundance_weighted,
+            output_columns=args.output_columns,
+            rename_query=args.rename_query,
+            rename_match=args.rename_match,
+            failed_paths_out=args.failed_paths_out,
+            skipped_paths_out=args.skipped_paths_out,
+            profile=args.profile,
+            force=args.force,
+            search_mode=args.search_mode,
+            save_unassigned=args.save_unassigned,
+            exclude=args.exclude,
+            stoplist=args.stoplist,
+            sourmash_compat=args.sourmash_compat,
+            verify=args.verify,
+            detailed_exit_codes=args.detailed_exit_codes,
+            summary_out=args.summary_out,
+            flush_rows=args.flush_rows,
+            flush_interval_ms=args.flush_interval_ms,
+            append=args.append,
+            output_shard_size=args.output_shard_size,
+            output_format=args.output_format,
+            refine_from=args.refine_from,
+            require_abundance=args.require_abundance,
+            per_query_scaled=args.per_query_scaled,
+        )
+        if finished(status):
             notify(f"...fastmultigather is done!")
         return status
 
@@ -372,8 +1078,22 @@ class Branchwater_Index(CommandLinePlugin):
             help="do not store sketches in the index; index may not be relocatable (default: False)",
             dest="internal_storage",
         )
+        p.add_argument(
+            "--normalize-names",
+            action="store_true",
+            help="report sketches by their names without paths or file extensions in searches of the index",
+        )
+        p.add_argument(
+            "--rename-from",
+            help="CSV of 'name,new_name' (or 'md5,new_name') giving new names to report in searches of the index",
+        )
+        add_verify_args(p)
+        add_force_args(p)
 
     def main(self, args):
+        if not check_outputs(args):
+            return 1
+
         notify(
             f"ksize: {args.ksize} / scaled: {args.scaled} / moltype: {args.moltype} "
         )
@@ -391,6 +1111,10 @@ class Branchwater_Index(CommandLinePlugin):
             args.output,
             False,  # colors - currently must be false?
             args.internal_storage,
+            force=args.force,
+            verify=args.verify,
+            normalize_names=args.normalize_names,
+            rename_from=args.rename_from,
         )
         if status == 0:
             notify(f"...index is done! results in '{args.output}'")
@@ -411,49 +1135,226 @@ class Branchwater_Check(CommandLinePlugin):
             action="store_true",
             help="open database in read-write mode to upgrade the internal format if needed",
         )
+        p.add_argument(
+            "--hash-stats",
+            action="store_true",
+            help="report how many datasets each hash is found in, and flag hashes found in nearly all datasets",
+        )
+        p.add_argument(
+            "--hash-stats-out",
+            default=None,
+            help="write the number of hashes found in each number of datasets to this CSV; implies --hash-stats",
+        )
+        p.add_argument(
+            "--ubiquitous-fraction",
+            default=0.9,
+            type=float,
+            help="flag hashes found in at least this fraction of datasets (default: 0.9)",
+        )
+        p.add_argument(
+            "--output-stoplist",
+            default=None,
+            help="write the flagged hashes to this signature file, for use as a stoplist; implies --hash-stats",
+        )
 
     def main(self, args):
         notify(f"checking index '{args.index}'")
         super().main(args)
         status = sourmash_plugin_branchwater.do_check(
-            args.index, args.quick, args.writable
+            args.index,
+            args.quick,
+            args.writable,
+            hash_stats=args.hash_stats,
+            hash_stats_out=args.hash_stats_out,
+            ubiquitous_fraction=args.ubiquitous_fraction,
+            output_stoplist=args.output_stoplist,
         )
         if status == 0:
             notify(f"...index is ok!")
         return status
 
 
-class Branchwater_Multisearch(CommandLinePlugin):
-    command = "multisearch"
-    description = "massively parallel in-memory sketch search"
+class Branchwater_HashLookup(CommandLinePlugin):
+    command = "hash-lookup"
+    description = "report which datasets in a RocksDB index contain each hash"
 
     def __init__(self, p):
         super().__init__(p)
-        p.add_argument("query_paths", help="input file of sketches")
-        p.add_argument("against_paths", help="input file of sketches")
-        p.add_argument(
-            "-o", "--output", required=True, help="CSV output file for matches"
+        p.add_argument("index", help="RocksDB index file created with 'index'")
+        group = p.add_mutually_exclusive_group(required=True)
+        group.add_argument(
+            "--hashes", help="text file of hash values to look up, one per line"
+        )
+        group.add_argument(
+            "--query",
+            help="look up all hashes in these query sketches, downsampled to the index scaled",
+        )
+        p.add_argument("-o", "--output", help="CSV output file (default: stdout)")
+        p.add_argument(
+            "-k",
+            "--ksize",
+            default=31,
+            type=int,
+            help="k-mer size at which to select query sketches (default: 31)",
+        )
+        p.add_argument(
+            "-m",
+            "--moltype",
+            default="DNA",
+            choices=["DNA", "protein", "dayhoff", "hp", "skipm1n3", "skipm2n3"],
+            help="molecule type of query sketches: DNA, protein, dayhoff, hp, or skipmer (skipm1n3 or skipm2n3); default DNA",
+        )
+        p.add_argument(
+            "-c",
+            "--cores",
+            default=0,
+            type=int,
+            help="number of cores to use (default is all available)",
+        )
+        add_force_args(p)
+
+    def main(self, args):
+        print_version()
+        if not check_outputs(args):
+            return 1
+
+        num_threads = set_thread_pool(args.cores)
+
+        source = args.hashes if args.hashes else args.query
+        notify(
+            f"looking up hashes from '{source}' in '{args.index}' using {num_threads} threads"
+        )
+
+        super().main(args)
+        status = sourmash_plugin_branchwater.do_hash_lookup(
+            args.index,
+            args.ksize,
+            args.moltype,
+            hashes_path=args.hashes,
+            query_path=args.query,
+            output=args.output,
+        )
+        if status == 0:
+            notify("...hash-lookup is done!")
+        return status
+
+
+class Branchwater_Serve(CommandLinePlugin):
+    command = "serve"
+    description = "answer search and gather requests over HTTP from a loaded database"
+
+    def __init__(self, p):
+        super().__init__(p)
+        p.add_argument(
+            "index",
+            help="RocksDB index created with 'index', or any collection to load into memory",
+        )
+        p.add_argument(
+            "--host",
+            default="127.0.0.1",
+            help="address to listen on (default: 127.0.0.1)",
+        )
+        p.add_argument(
+            "-p",
+            "--port",
+            default=8080,
+            type=int,
+            help="port to listen on; 0 picks a free port (default: 8080)",
         )
         p.add_argument(
             "-t",
             "--threshold",
             default=0.01,
             type=float,
-            help="containment threshold for reporting matches (default: 0.01)",
+            help="default containment threshold for search requests (default: 0.01)",
+        )
+        p.add_argument(
+            "--threshold-bp",
+            default=50000,
+            type=float,
+            help="default threshold in estimated base pairs for gather requests (default: 50kb)",
         )
         p.add_argument(
             "-k",
             "--ksize",
             default=31,
             type=int,
-            help="k-mer size at which to select sketches",
+            help="k-mer size at which to do comparisons (default: 31)",
         )
         p.add_argument(
             "-s",
             "--scaled",
             default=None,
             type=int,
-            help="scaled factor at which to do comparisons (default: determined from query collection)",
+            help="scaled factor at which to do comparisons (default: determined from database)",
+        )
+        p.add_argument(
+            "-m",
+            "--moltype",
+            default="DNA",
+            choices=["DNA", "protein", "dayhoff", "hp", "skipm1n3", "skipm2n3"],
+            help="molecule type: DNA, protein, dayhoff, hp, or skipmer (skipm1n3 or skipm2n3); default DNA",
+        )
+        p.add_argument(
+            "-c",
+            "--cores",
+            default=0,
+            type=int,
+            help="number of cores to use (default is all available)",
+        )
+
+    def main(self, args):
+        print_version()
+        notify(
+            f"ksize: {args.ksize} / scaled: {args.scaled} / moltype: {args.moltype}"
+        )
+
+        num_threads = set_thread_pool(args.cores)
+
+        notify(
+            f"loading '{args.index}' to serve requests using {num_threads} threads"
+        )
+        super().main(args)
+        status = sourmash_plugin_branchwater.do_serve(
+            args.index,
+            args.ksize,
+            args.scaled,
+            args.moltype,
+            host=args.host,
+            port=args.port,
+            threshold=args.threshold,
+            threshold_bp=int(args.threshold_bp),
+        )
+        if status == 0:
+            notify("...serve is done!")
+        return status
+
+
+class Branchwater_CompatCheck(CommandLinePlugin):
+    command = "compat-check"
+    description = "check that query and against sketches are compatible, without searching"
+
+    def __init__(self, p):
+        super().__init__(p)
+        p.add_argument("query_paths", help="input file of sketches to query")
+        p.add_argument(
+            "against_paths",
+            help="input file of sketches to search against \
+                       OR a branchwater indexed database generated with 'sourmash scripts index'",
+        )
+        p.add_argument(
+            "-k",
+            "--ksize",
+            default=31,
+            type=int,
+            help="k-mer size at which to do comparisons (default: 31)",
+        )
+        p.add_argument(
+            "-s",
+            "--scaled",
+            default=None,
+            type=int,
+            help="scaled factor at which to do comparisons",
         )
         p.add_argument(
             "-m",
@@ -469,6 +1370,77 @@ class Branchwater_Multisearch(CommandLinePlugin):
             type=int,
             help="number of cores to use (default is all available)",
         )
+
+    def main(self, args):
+        print_version()
+        notify(
+            f"ksize: {args.ksize} / scaled: {args.scaled} / moltype: {args.moltype}"
+        )
+        set_thread_pool(args.cores)
+
+        notify(
+            f"checking sketches in '{args.query_paths}' against '{args.against_paths}'"
+        )
+
+        super().main(args)
+        status = sourmash_plugin_branchwater.do_compat_check(
+            args.query_paths,
+            args.against_paths,
+            args.ksize,
+            args.scaled,
+            args.moltype,
+        )
+        if status == 0:
+            notify("...compat-check is done! inputs are compatible.")
+        return status
+
+
+class Branchwater_Multisearch(CommandLinePlugin):
+    command = "multisearch"
+    description = "massively parallel in-memory sketch search"
+
+    def __init__(self, p):
+        super().__init__(p)
+        p.add_argument("query_paths", help="input file of sketches")
+        p.add_argument("against_paths", help="input file of sketches")
+        p.add_argument(
+            "-o", "--output", required=True, help="CSV output file for matches"
+        )
+        p.add_argument(
+            "-t",
+            "--threshold",
+            default=0.01,
+            type=float,
+            help="containment threshold for reporting matches (default: 0.01)",
+        )
+        p.add_argument(
+            "-k",
+            "--ksize",
+            default=None,
+            type=int,
+            help="k-mer size at which to select sketches (default: from the query sketches)",
+        )
+        p.add_argument(
+            "-s",
+            "--scaled",
+            default=None,
+            type=int,
+            help="scaled factor at which to do comparisons (default: determined from query collection)",
+        )
+        p.add_argument(
+            "-m",
+            "--moltype",
+            default=None,
+            choices=["DNA", "protein", "dayhoff", "hp", "skipm1n3", "skipm2n3"],
+            help="molecule type: DNA, protein, dayhoff, hp, or skipmer (skipm1n3 or skipm2n3); default: from the query sketches",
+        )
+        p.add_argument(
+            "-c",
+            "--cores",
+            default=0,
+            type=int,
+            help="number of cores to use (default is all available)",
+        )
         p.add_argument(
             "-a", "--ani", action="store_true", help="estimate ANI from containment"
         )
@@ -484,9 +1456,53 @@ class Branchwater_Multisearch(CommandLinePlugin):
             action="store_true",
             help="ignore threshold and output all comparisons",
         )
+        p.add_argument(
+            "--knn",
+            default=None,
+            type=int,
+            metavar="K",
+            help="only output the K most similar matches (by max_containment) for each query",
+        )
+        p.add_argument(
+            "--fdr",
+            action="store_true",
+            help="estimate the chance of each overlap between random sketches, and add Benjamini-Hochberg q-values in a second pass over the output",
+        )
+        add_angular_similarity_args(p)
+        add_graph_args(p)
+        add_coverage_report_args(p)
+        p.add_argument(
+            "--query-groups",
+            default=None,
+            help="CSV file with 'group' and 'name' columns assigning queries to groups; summarize each group's matches to --output-groups",
+        )
+        p.add_argument(
+            "--output-groups",
+            default=None,
+            help="CSV output file for the max and mean containment of each query group in each against sketch",
+        )
+        add_self_match_args(p)
+        add_output_columns_args(p)
+        add_rename_args(p)
+        add_path_report_args(p)
+        add_exclude_args(p)
+        add_stoplist_args(p)
+        add_dedup_args(p)
+        add_size_filter_args(p)
+        add_profile_args(p)
+        add_require_abundance_args(p)
+        add_verify_args(p)
+        add_detailed_exit_codes_args(p)
+        add_summary_out_args(p)
+        add_flush_args(p)
+        add_output_mode_args(p)
+        add_force_args(p)
 
     def main(self, args):
         print_version()
+        if not check_outputs(args):
+            return 1
+
         notify(
             f"ksize: {args.ksize} / scaled: {args.scaled} / moltype: {args.moltype} / threshold: {args.threshold}"
         )
@@ -512,8 +1528,38 @@ class Branchwater_Multisearch(CommandLinePlugin):
             args.prob_significant_overlap,
             args.output_all_comparisons,
             args.output,
-        )
-        if status == 0:
+            output_graph=args.output_graph,
+            graph_format=args.graph_format,
+            graph_weight=args.graph_weight,
+            coverage_report=args.coverage_report,
+            knn=args.knn,
+            angular_similarity=args.angular_similarity,
+            exclude_self_matches=args.exclude_self_matches,
+            output_columns=args.output_columns,
+            rename_query=args.rename_query,
+            rename_match=args.rename_match,
+            failed_paths_out=args.failed_paths_out,
+            skipped_paths_out=args.skipped_paths_out,
+            profile=args.profile,
+            min_hashes=args.min_hashes,
+            max_hashes=args.max_hashes,
+            exclude=args.exclude,
+            stoplist=args.stoplist,
+            query_groups=args.query_groups,
+            output_groups=args.output_groups,
+            verify=args.verify,
+            detailed_exit_codes=args.detailed_exit_codes,
+            summary_out=args.summary_out,
+            flush_rows=args.flush_rows,
+            flush_interval_ms=args.flush_interval_ms,
+            append=args.append,
+            output_shard_size=args.output_shard_size,
+            output_format=args.output_format,
+            dedup_by_md5=args.dedup_by_md5,
+            require_abundance=args.require_abundance,
+            fdr=args.fdr,
+        )
+        if finished(status):
             notify(f"...multisearch is done! results in '{args.output}'")
         return status
 
@@ -538,9 +1584,9 @@ class Branchwater_Pairwise(CommandLinePlugin):
         p.add_argument(
             "-k",
             "--ksize",
-            default=31,
+            default=None,
             type=int,
-            help="k-mer size at which to select sketches",
+            help="k-mer size at which to select sketches (default: from the sketches)",
         )
         p.add_argument(
             "-s",
@@ -552,9 +1598,9 @@ class Branchwater_Pairwise(CommandLinePlugin):
         p.add_argument(
             "-m",
             "--moltype",
-            default="DNA",
+            default=None,
             choices=["DNA", "protein", "dayhoff", "hp", "skipm1n3", "skipm2n3"],
-            help="molecule type: DNA, protein, dayhoff, hp, or skipmer (skipm1n3 or skipm2n3); default DNA",
+            help="molecule type: DNA, protein, dayhoff, hp, or skipmer (skipm1n3 or skipm2n3); default: from the sketches",
         )
         p.add_argument(
             "-c",
@@ -578,18 +1624,66 @@ class Branchwater_Pairwise(CommandLinePlugin):
             action="store_true",
             help="ignore threshold and output all comparisons",
         )
+        p.add_argument(
+            "--both-directions",
+            action="store_true",
+            help="write each reported pair twice, once with each sketch as the query, so that containment and ANI are given in both orientations",
+        )
+        p.add_argument(
+            "--candidates",
+            default=None,
+            help="CSV of candidate pairs to compare, e.g. a previous multisearch output; matched on query_md5/match_md5, or query_name/match_name",
+        )
+        add_angular_similarity_args(p)
+        add_graph_args(p)
+        p.add_argument(
+            "--output-distances",
+            default=None,
+            help="also write a condensed distance matrix for scipy.cluster.hierarchy.linkage to this .npy file, with labels in <file>.labels.txt",
+        )
+        p.add_argument(
+            "--distance-measure",
+            default="average_containment_ani",
+            choices=[
+                "jaccard",
+                "max_containment",
+                "average_containment_ani",
+                "max_containment_ani",
+            ],
+            help="similarity measure for --output-distances; distance is 1 - similarity (default: average_containment_ani)",
+        )
+        add_output_columns_args(p)
+        add_rename_args(p)
+        add_path_report_args(p)
+        add_size_filter_args(p)
+        add_profile_args(p)
+        add_require_abundance_args(p)
+        add_verify_args(p)
+        add_detailed_exit_codes_args(p)
+        add_summary_out_args(p)
+        add_flush_args(p)
+        add_output_mode_args(p)
+        add_force_args(p)
 
     def main(self, args):
         print_version()
+        if not check_outputs(args):
+            return 1
+
         notify(
             f"ksize: {args.ksize} / scaled: {args.scaled} / moltype: {args.moltype} / threshold: {args.threshold}"
         )
 
         num_threads = set_thread_pool(args.cores)
 
-        notify(
-            f"pairwise-comparing all sketches in '{args.sig_paths}' using {num_threads} threads"
-        )
+        if args.candidates:
+            notify(
+                f"pairwise-comparing candidate pairs from '{args.candidates}' in '{args.sig_paths}' using {num_threads} threads"
+            )
+        else:
+            notify(
+                f"pairwise-comparing all sketches in '{args.sig_paths}' using {num_threads} threads"
+            )
 
         super().main(args)
         status = sourmash_plugin_branchwater.do_pairwise(
@@ -602,8 +1696,33 @@ class Branchwater_Pairwise(CommandLinePlugin):
             args.write_all,
             args.output_all_comparisons,
             args.output,
-        )
-        if status == 0:
+            output_graph=args.output_graph,
+            graph_format=args.graph_format,
+            graph_weight=args.graph_weight,
+            candidates=args.candidates,
+            angular_similarity=args.angular_similarity,
+            output_columns=args.output_columns,
+            rename_query=args.rename_query,
+            rename_match=args.rename_match,
+            failed_paths_out=args.failed_paths_out,
+            skipped_paths_out=args.skipped_paths_out,
+            profile=args.profile,
+            min_hashes=args.min_hashes,
+            max_hashes=args.max_hashes,
+            output_distances=args.output_distances,
+            distance_measure=args.distance_measure,
+            verify=args.verify,
+            detailed_exit_codes=args.detailed_exit_codes,
+            summary_out=args.summary_out,
+            flush_rows=args.flush_rows,
+            flush_interval_ms=args.flush_interval_ms,
+            append=args.append,
+            output_shard_size=args.output_shard_size,
+            output_format=args.output_format,
+            require_abundance=args.require_abundance,
+            both_directions=args.both_directions,
+        )
+        if finished(status):
             notify(f"...pairwise is done! results in '{args.output}'")
         return status
 
@@ -644,9 +1763,13 @@ class Branchwater_SingleSketch(CommandLinePlugin):
             default="DNA",
             help="molecule type of input sequence (DNA or protein)",
         )
+        add_force_args(p)
 
     def main(self, args):
         print_version()
+        if not check_outputs(args):
+            return 1
+
         if not args.param_string:
             args.param_string = ["k=31,scaled=1000,dna"]
 
@@ -734,9 +1857,24 @@ class Branchwater_Manysketch(CommandLinePlugin):
             action="store_true",
             help="allow use of individual FASTA files in more than more sketch",
         )
+        p.add_argument(
+            "--failures-out",
+            default="sketch_failures.csv",
+            help="output csv of inputs that failed to sketch, if any (default: sketch_failures.csv)",
+        )
+        p.add_argument(
+            "--on-duplicate",
+            choices=["error", "merge", "suffix"],
+            default="error",
+            help="what to do with sketches that have the same name and parameters: stop with an error, merge them, or rename them with _2, _3, ... suffixes (default: error)",
+        )
+        add_force_args(p)
 
     def main(self, args):
         print_version()
+        if not check_outputs(args):
+            return 1
+
         if not args.param_string:
             args.param_string = ["dna,k=31,scaled=1000"]
         notify(f"params: {args.param_string}")
@@ -759,9 +1897,15 @@ class Branchwater_Manysketch(CommandLinePlugin):
             args.output,
             args.singleton,
             args.force,
+            failures_out=args.failures_out,
+            on_duplicate=args.on_duplicate,
         )
         if status == 0:
             notify(f"...manysketch is done! results in '{args.output}'")
+        elif status == 2:
+            notify(
+                f"...manysketch is done, but some inputs failed; see '{args.failures_out}'. Other results in '{args.output}'"
+            )
         return status
 
 
@@ -794,6 +1938,7 @@ class Branchwater_Cluster(CommandLinePlugin):
                 "jaccard",
                 "average_containment_ani",
                 "max_containment_ani",
+                "angular_similarity",
             ],
             help="column to use as similarity measure",
         )
@@ -811,9 +1956,12 @@ class Branchwater_Cluster(CommandLinePlugin):
             type=int,
             help="number of cores to use (default is all available)",
         )
+        add_force_args(p)
 
     def main(self, args):
         print_version()
+        if not check_outputs(args):
+            return 1
 
         num_threads = set_thread_pool(args.cores)
 
@@ -833,3 +1981,1035 @@ class Branchwater_Cluster(CommandLinePlugin):
             notify(f"...clustering is done! results in '{args.output}'")
             notify(f"                       cluster counts in '{args.cluster_sizes}'")
         return status
+
+
+class Branchwater_Intersect(CommandLinePlugin):
+    command = "intersect"
+    description = "intersect the hashes of many sketches, optionally by group"
+
+    def __init__(self, p):
+        super().__init__(p)
+        p.add_argument("sig_paths", help="input file of sketches")
+        p.add_argument(
+            "-o",
+            "--output",
+            required=True,
+            help="output zip file (or .sig/.sig.gz) for the intersected signatures",
+        )
+        p.add_argument(
+            "--groups",
+            default=None,
+            help="CSV file with 'group' and 'name' columns; intersect the sketches in each group separately",
+        )
+        p.add_argument(
+            "--name",
+            default=None,
+            help="name for the intersected signature (without --groups)",
+        )
+        p.add_argument(
+            "-k",
+            "--ksize",
+            default=31,
+            type=int,
+            help="k-mer size at which to select sketches (default: 31)",
+        )
+        p.add_argument(
+            "-s",
+            "--scaled",
+            default=None,
+            type=int,
+            help="scaled factor at which to intersect (default: max scaled in collection)",
+        )
+        p.add_argument(
+            "-m",
+            "--moltype",
+            default="DNA",
+            choices=["DNA", "protein", "dayhoff", "hp", "skipm1n3", "skipm2n3"],
+            help="molecule type: DNA, protein, dayhoff, hp, or skipmer (skipm1n3 or skipm2n3); default DNA",
+        )
+        p.add_argument(
+            "-c",
+            "--cores",
+            default=0,
+            type=int,
+            help="number of cores to use (default is all available)",
+        )
+        add_force_args(p)
+
+    def main(self, args):
+        print_version()
+        if not check_outputs(args):
+            return 1
+
+        notify(
+            f"ksize: {args.ksize} / scaled: {args.scaled} / moltype: {args.moltype}"
+        )
+
+        if args.groups and args.name:
+            notify("ERROR: --name cannot be used with --groups")
+            return 1
+
+        num_threads = set_thread_pool(args.cores)
+
+        notify(f"intersecting sketches in '{args.sig_paths}' using {num_threads} threads")
+
+        super().main(args)
+        status = sourmash_plugin_branchwater.do_intersect(
+            args.sig_paths,
+            args.ksize,
+            args.scaled,
+            args.moltype,
+            args.output,
+            args.groups,
+            args.name,
+        )
+        if status == 0:
+            notify(f"...intersect is done! signatures in '{args.output}'")
+        return status
+
+
+
+class Branchwater_Overlap(CommandLinePlugin):
+    command = "overlap"
+    description = "count the hashes in each combination of up to 64 sketches, for UpSet plots"
+
+    def __init__(self, p):
+        super().__init__(p)
+        p.add_argument("sig_paths", help="input file of sketches")
+        p.add_argument(
+            "-o",
+            "--output",
+            default=None,
+            help="CSV output file for the combination counts (default: stdout)",
+        )
+        p.add_argument(
+            "-k",
+            "--ksize",
+            default=31,
+            type=int,
+            help="k-mer size at which to select sketches (default: 31)",
+        )
+        p.add_argument(
+            "-s",
+            "--scaled",
+            default=None,
+            type=int,
+            help="scaled factor at which to compare (default: max scaled in collection)",
+        )
+        p.add_argument(
+            "-m",
+            "--moltype",
+            default="DNA",
+            choices=["DNA", "protein", "dayhoff", "hp", "skipm1n3", "skipm2n3"],
+            help="molecule type: DNA, protein, dayhoff, hp, or skipmer (skipm1n3 or skipm2n3); default DNA",
+        )
+        p.add_argument(
+            "-c",
+            "--cores",
+            default=0,
+            type=int,
+            help="number of cores to use (default is all available)",
+        )
+        add_force_args(p)
+
+    def main(self, args):
+        print_version()
+        if not check_outputs(args):
+            return 1
+
+        notify(
+            f"ksize: {args.ksize} / scaled: {args.scaled} / moltype: {args.moltype}"
+        )
+
+        num_threads = set_thread_pool(args.cores)
+
+        notify(
+            f"counting hash overlaps between sketches in '{args.sig_paths}' using {num_threads} threads"
+        )
+
+        super().main(args)
+        status = sourmash_plugin_branchwater.do_overlap(
+            args.sig_paths,
+            args.ksize,
+            args.scaled,
+            args.moltype,
+            args.output,
+        )
+        if status == 0:
+            notify("...overlap is done!")
+        return status
+
+
+class Branchwater_OverlapSummary(CommandLinePlugin):
+    command = "overlap-summary"
+    description = "count the distinct hashes in, and shared by, two collections"
+
+    def __init__(self, p):
+        super().__init__(p)
+        p.add_argument("query_paths", help="first input file of sketches")
+        p.add_argument("against_paths", help="second input file of sketches")
+        p.add_argument(
+            "-o",
+            "--output",
+            default=None,
+            help="CSV output file for the summary, one row per ksize (default: stdout)",
+        )
+        p.add_argument(
+            "-k",
+            "--ksize",
+            action="append",
+            type=int,
+            default=[],
+            help="k-mer size to summarize; may be given multiple times (default: 31)",
+        )
+        p.add_argument(
+            "-s",
+            "--scaled",
+            default=None,
+            type=int,
+            help="scaled factor at which to compare (default: max scaled in either collection)",
+        )
+        p.add_argument(
+            "-m",
+            "--moltype",
+            default="DNA",
+            choices=["DNA", "protein", "dayhoff", "hp", "skipm1n3", "skipm2n3"],
+            help="molecule type: DNA, protein, dayhoff, hp, or skipmer (skipm1n3 or skipm2n3); default DNA",
+        )
+        p.add_argument(
+            "-c",
+            "--cores",
+            default=0,
+            type=int,
+            help="number of cores to use (default is all available)",
+        )
+        add_force_args(p)
+
+    def main(self, args):
+        print_version()
+        if not check_outputs(args):
+            return 1
+
+        ksizes = args.ksize or [31]
+        notify(f"ksizes: {ksizes} / scaled: {args.scaled} / moltype: {args.moltype}")
+
+        num_threads = set_thread_pool(args.cores)
+
+        notify(
+            f"summarizing overlap between '{args.query_paths}' and '{args.against_paths}' using {num_threads} threads"
+        )
+
+        super().main(args)
+        status = sourmash_plugin_branchwater.do_overlap_summary(
+            args.query_paths,
+            args.against_paths,
+            ksizes,
+            args.scaled,
+            args.moltype,
+            args.output,
+        )
+        if status == 0:
+            notify("...overlap-summary is done!")
+        return status
+
+class Branchwater_Downsample(CommandLinePlugin):
+    command = "downsample"
+    description = "rewrite a collection at a higher scaled and/or subset of ksizes"
+
+    def __init__(self, p):
+        super().__init__(p)
+        p.add_argument("sig_paths", help="input file of sketches")
+        p.add_argument(
+            "-o",
+            "--output",
+            required=True,
+            help="output zip file for the downsampled signatures",
+        )
+        p.add_argument(
+            "-k",
+            "--ksize",
+            action="append",
+            type=int,
+            default=[],
+            help="k-mer size to keep; may be given multiple times (default: 31)",
+        )
+        p.add_argument(
+            "-s",
+            "--scaled",
+            default=None,
+            type=int,
+            help="scaled factor to downsample to (default: keep current scaled)",
+        )
+        p.add_argument(
+            "-m",
+            "--moltype",
+            default="DNA",
+            choices=["DNA", "protein", "dayhoff", "hp", "skipm1n3", "skipm2n3"],
+            help="molecule type: DNA, protein, dayhoff, hp, or skipmer (skipm1n3 or skipm2n3); default DNA",
+        )
+        p.add_argument(
+            "-c",
+            "--cores",
+            default=0,
+            type=int,
+            help="number of cores to use (default is all available)",
+        )
+        add_force_args(p)
+
+    def main(self, args):
+        print_version()
+        if not check_outputs(args):
+            return 1
+
+        ksizes = args.ksize or [31]
+        notify(f"ksizes: {ksizes} / scaled: {args.scaled} / moltype: {args.moltype}")
+
+        num_threads = set_thread_pool(args.cores)
+
+        notify(
+            f"downsampling sketches in '{args.sig_paths}' using {num_threads} threads"
+        )
+
+        super().main(args)
+        status = sourmash_plugin_branchwater.do_downsample(
+            args.sig_paths,
+            ksizes,
+            args.scaled,
+            args.moltype,
+            args.output,
+        )
+        if status == 0:
+            notify(f"...downsample is done! signatures in '{args.output}'")
+        return status
+
+
+class Branchwater_Rename(CommandLinePlugin):
+    command = "rename"
+    description = "rename and annotate signatures from a mapping CSV"
+
+    def __init__(self, p):
+        super().__init__(p)
+        p.add_argument("sig_paths", help="input file of sketches")
+        p.add_argument(
+            "mapping",
+            help="CSV file with an 'md5' or 'name' column, an optional 'new_name' column, and any extra columns to add to the manifest",
+        )
+        p.add_argument(
+            "-o",
+            "--output",
+            required=True,
+            help="output zip file for the renamed signatures",
+        )
+        p.add_argument(
+            "-k",
+            "--ksize",
+            action="append",
+            type=int,
+            default=[],
+            help="k-mer size to select; may be given multiple times (default: 31)",
+        )
+        p.add_argument(
+            "-m",
+            "--moltype",
+            default="DNA",
+            choices=["DNA", "protein", "dayhoff", "hp", "skipm1n3", "skipm2n3"],
+            help="molecule type: DNA, protein, dayhoff, hp, or skipmer (skipm1n3 or skipm2n3); default DNA",
+        )
+        p.add_argument(
+            "-c",
+            "--cores",
+            default=0,
+            type=int,
+            help="number of cores to use (default is all available)",
+        )
+        add_force_args(p)
+
+    def main(self, args):
+        print_version()
+        if not check_outputs(args):
+            return 1
+
+        ksizes = args.ksize or [31]
+        notify(f"ksizes: {ksizes} / moltype: {args.moltype}")
+
+        num_threads = set_thread_pool(args.cores)
+
+        notify(
+            f"renaming sketches in '{args.sig_paths}' from '{args.mapping}' using {num_threads} threads"
+        )
+
+        super().main(args)
+        status = sourmash_plugin_branchwater.do_rename(
+            args.sig_paths,
+            ksizes,
+            args.moltype,
+            args.mapping,
+            args.output,
+        )
+        if status == 0:
+            notify(f"...rename is done! signatures in '{args.output}'")
+        return status
+
+
+class Branchwater_Extract(CommandLinePlugin):
+    command = "extract"
+    description = "extract a subset of a collection by name, md5, or picklist"
+
+    def __init__(self, p):
+        super().__init__(p)
+        p.add_argument("sig_paths", help="input file of sketches")
+        p.add_argument(
+            "-o",
+            "--output",
+            required=True,
+            help="output zip file for the extracted signatures",
+        )
+        p.add_argument(
+            "--name-regex",
+            default=None,
+            help="keep signatures whose name matches this regular expression",
+        )
+        p.add_argument(
+            "--md5",
+            action="append",
+            default=[],
+            help="keep signatures with this md5 (or md5 prefix); may be given multiple times",
+        )
+        p.add_argument(
+            "--picklist",
+            default=None,
+            help="select signatures using a picklist, 'file.csv:column:coltype[:include|exclude]'",
+        )
+        p.add_argument(
+            "-k",
+            "--ksize",
+            action="append",
+            type=int,
+            default=[],
+            help="k-mer size to select; may be given multiple times (default: 31)",
+        )
+        p.add_argument(
+            "-m",
+            "--moltype",
+            default="DNA",
+            choices=["DNA", "protein", "dayhoff", "hp", "skipm1n3", "skipm2n3"],
+            help="molecule type: DNA, protein, dayhoff, hp, or skipmer (skipm1n3 or skipm2n3); default DNA",
+        )
+        p.add_argument(
+            "-c",
+            "--cores",
+            default=0,
+            type=int,
+            help="number of cores to use (default is all available)",
+        )
+        add_force_args(p)
+
+    def main(self, args):
+        print_version()
+        if not check_outputs(args):
+            return 1
+
+        ksizes = args.ksize or [31]
+        notify(f"ksizes: {ksizes} / moltype: {args.moltype}")
+
+        num_threads = set_thread_pool(args.cores)
+
+        notify(
+            f"extracting sketches from '{args.sig_paths}' using {num_threads} threads"
+        )
+
+        super().main(args)
+        status = sourmash_plugin_branchwater.do_extract(
+            args.sig_paths,
+            ksizes,
+            args.moltype,
+            args.output,
+            name_pattern=args.name_regex,
+            md5s=args.md5,
+            picklist=args.picklist,
+        )
+        if status == 0:
+            notify(f"...extract is done! signatures in '{args.output}'")
+        return status
+
+
+class Branchwater_Shard(CommandLinePlugin):
+    command = "shard"
+    description = "split a collection into balanced shards"
+
+    def __init__(self, p):
+        super().__init__(p)
+        p.add_argument("sig_paths", help="input file of sketches")
+        p.add_argument(
+            "-n",
+            "--num-shards",
+            required=True,
+            type=int,
+            help="number of shards to create",
+        )
+        p.add_argument(
+            "-o",
+            "--output-prefix",
+            required=True,
+            help="prefix for output files; writes '<prefix>.<i>.zip' shards and a '<prefix>.manifest.csv' shard manifest",
+        )
+        p.add_argument(
+            "--balance-by",
+            default="count",
+            choices=["count", "hashes"],
+            help="balance shards by number of signatures or by total number of hashes (default: count)",
+        )
+        p.add_argument(
+            "-k",
+            "--ksize",
+            action="append",
+            type=int,
+            default=[],
+            help="k-mer size to select; may be given multiple times (default: 31)",
+        )
+        p.add_argument(
+            "-m",
+            "--moltype",
+            default="DNA",
+            choices=["DNA", "protein", "dayhoff", "hp", "skipm1n3", "skipm2n3"],
+            help="molecule type: DNA, protein, dayhoff, hp, or skipmer (skipm1n3 or skipm2n3); default DNA",
+        )
+        p.add_argument(
+            "-c",
+            "--cores",
+            default=0,
+            type=int,
+            help="number of cores to use (default is all available)",
+        )
+
+    def main(self, args):
+        print_version()
+        ksizes = args.ksize or [31]
+        notify(f"ksizes: {ksizes} / moltype: {args.moltype}")
+
+        num_threads = set_thread_pool(args.cores)
+
+        notify(
+            f"splitting '{args.sig_paths}' into {args.num_shards} shards by {args.balance_by} using {num_threads} threads"
+        )
+
+        super().main(args)
+        status = sourmash_plugin_branchwater.do_shard(
+            args.sig_paths,
+            ksizes,
+            args.moltype,
+            args.num_shards,
+            args.output_prefix,
+            by=args.balance_by,
+        )
+        if status == 0:
+            notify(
+                f"...shard is done! shard manifest in '{args.output_prefix}.manifest.csv'"
+            )
+        return status
+
+
+class Branchwater_ValidateZip(CommandLinePlugin):
+    command = "validate-zip"
+    description = "check a sourmash zip file for missing, corrupt, or orphaned members"
+
+    def __init__(self, p):
+        super().__init__(p)
+        p.add_argument("zip_path", help="sourmash zip file to validate")
+        p.add_argument(
+            "-o",
+            "--output",
+            help="CSV output file for problems found (default: stdout)",
+        )
+        p.add_argument(
+            "-c",
+            "--cores",
+            default=0,
+            type=int,
+            help="number of cores to use (default is all available)",
+        )
+        add_force_args(p)
+
+    def main(self, args):
+        print_version()
+        if not check_outputs(args):
+            return 1
+
+        num_threads = set_thread_pool(args.cores)
+
+        notify(f"validating '{args.zip_path}' using {num_threads} threads")
+
+        super().main(args)
+        status = sourmash_plugin_branchwater.do_validate_zip(
+            args.zip_path, output=args.output
+        )
+        if status == 0:
+            notify(f"...validate-zip is done! no problems in '{args.zip_path}'")
+        return status
+
+
+class Branchwater_ZipCat(CommandLinePlugin):
+    command = "zip-cat"
+    description = "concatenate sourmash zip files, with a combined manifest"
+
+    def __init__(self, p):
+        super().__init__(p)
+        p.add_argument("zip_paths", nargs="+", help="sourmash zip files to combine")
+        p.add_argument(
+            "-o", "--output", required=True, help="output zip file for the sketches"
+        )
+        add_force_args(p)
+
+    def main(self, args):
+        print_version()
+        if not check_outputs(args):
+            return 1
+
+        notify(f"combining {len(args.zip_paths)} zip files")
+
+        super().main(args)
+        status = sourmash_plugin_branchwater.do_zip_cat(args.zip_paths, args.output)
+        if status == 0:
+            notify(f"...zip-cat is done! results in '{args.output}'")
+        return status
+
+
+class Branchwater_Convert(CommandLinePlugin):
+    command = "convert"
+    description = "convert a collection of sketches into another storage format"
+
+    def __init__(self, p):
+        super().__init__(p)
+        p.add_argument(
+            "input",
+            help="input collection: zip file, directory of signature files, standalone manifest, RocksDB index, or pathlist",
+        )
+        p.add_argument(
+            "-o",
+            "--output",
+            required=True,
+            help="output zip file, directory, standalone manifest CSV, or RocksDB index",
+        )
+        p.add_argument(
+            "--to",
+            default=None,
+            choices=["zip", "dir", "manifest", "rocksdb"],
+            help="output format (default: from the output name; '.zip' is a zip file, '.csv' a standalone manifest, '.rocksdb' a RocksDB index, and anything else a directory of signature files)",
+        )
+        p.add_argument(
+            "-k",
+            "--ksize",
+            default=None,
+            type=int,
+            help="k-mer size at which to select sketches (default: all)",
+        )
+        p.add_argument(
+            "-s",
+            "--scaled",
+            default=None,
+            type=int,
+            help="scaled factor at which to select sketches (default: all)",
+        )
+        p.add_argument(
+            "-m",
+            "--moltype",
+            default=None,
+            choices=["DNA", "protein", "dayhoff", "hp", "skipm1n3", "skipm2n3"],
+            help="molecule type at which to select sketches (default: all)",
+        )
+        p.add_argument(
+            "-c",
+            "--cores",
+            default=0,
+            type=int,
+            help="number of cores to use (default is all available)",
+        )
+        add_require_abundance_args(p)
+        p.add_argument(
+            "--num",
+            default=None,
+            type=int,
+            help="select num sketches of this size, rather than scaled sketches (default: all)",
+        )
+        add_verify_args(p)
+        add_force_args(p)
+
+    def main(self, args):
+        print_version()
+        if not check_outputs(args):
+            return 1
+
+        num_threads = set_thread_pool(args.cores)
+        notify(
+            f"converting '{args.input}' to '{args.output}' using {num_threads} threads"
+        )
+
+        super().main(args)
+        status = sourmash_plugin_branchwater.do_convert(
+            args.input,
+            args.output,
+            format=args.to,
+            ksize=args.ksize,
+            scaled=args.scaled,
+            moltype=args.moltype,
+            force=args.force,
+            verify=args.verify,
+            require_abundance=args.require_abundance,
+            num=args.num,
+        )
+        if status == 0:
+            notify(f"...convert is done! results in '{args.output}'")
+        return status
+
+
+class Branchwater_Summarize(CommandLinePlugin):
+    command = "summarize"
+    description = "summarize the sketches in a collection"
+
+    def __init__(self, p):
+        super().__init__(p)
+        p.add_argument("sig_paths", help="input file of sketches")
+        p.add_argument(
+            "-o",
+            "--output",
+            help="output file for summary (default: stdout)",
+        )
+        p.add_argument(
+            "--format",
+            default="csv",
+            choices=["csv", "json"],
+            help="output format: 'csv' gives one row per ksize/moltype/scaled/abundance group; 'json' also lists duplicated md5s and name collisions (default: csv)",
+        )
+        p.add_argument(
+            "-c",
+            "--cores",
+            default=0,
+            type=int,
+            help="number of cores to use (default is all available)",
+        )
+        add_force_args(p)
+
+    def main(self, args):
+        print_version()
+        if not check_outputs(args):
+            return 1
+
+        num_threads = set_thread_pool(args.cores)
+
+        notify(f"summarizing '{args.sig_paths}' using {num_threads} threads")
+
+        super().main(args)
+        status = sourmash_plugin_branchwater.do_summarize(
+            args.sig_paths, output=args.output, format=args.format
+        )
+        if status == 0:
+            notify("...summarize is done!")
+        return status
+
+
+class Branchwater_Manydescribe(CommandLinePlugin):
+    command = "manydescribe"
+    description = "write one CSV row describing each sketch in a collection"
+
+    def __init__(self, p):
+        super().__init__(p)
+        p.add_argument("sig_paths", help="input file of sketches")
+        p.add_argument(
+            "-o",
+            "--output",
+            help="CSV output file (default: stdout)",
+        )
+        p.add_argument(
+            "-c",
+            "--cores",
+            default=0,
+            type=int,
+            help="number of cores to use (default is all available)",
+        )
+        add_force_args(p)
+
+    def main(self, args):
+        print_version()
+        if not check_outputs(args):
+            return 1
+
+        num_threads = set_thread_pool(args.cores)
+
+        notify(f"describing '{args.sig_paths}' using {num_threads} threads")
+
+        super().main(args)
+        status = sourmash_plugin_branchwater.do_manydescribe(
+            args.sig_paths, output=args.output
+        )
+        if status == 0:
+            notify("...manydescribe is done!")
+        return status
+
+
+class Branchwater_Bench(CommandLinePlugin):
+    command = "bench"
+    description = "time the multisearch, pairwise, and gather kernels on synthetic sketches"
+
+    def __init__(self, p):
+        super().__init__(p)
+        p.add_argument(
+            "-n",
+            "--n-sketches",
+            default=1000,
+            type=int,
+            help="number of synthetic sketches (default: 1000)",
+        )
+        p.add_argument(
+            "--n-hashes",
+            default=1000,
+            type=int,
+            help="number of hashes in each sketch (default: 1000)",
+        )
+        p.add_argument(
+            "-s",
+            "--scaled",
+            default=1000,
+            type=int,
+            help="scaled factor of the sketches (default: 1000)",
+        )
+        p.add_argument(
+            "--overlap",
+            default=0.1,
+            type=float,
+            help="fraction of each sketch's hashes drawn from a pool shared with its group (default: 0.1)",
+        )
+        p.add_argument(
+            "--n-groups",
+            default=1,
+            type=int,
+            help="number of groups of sketches, each with its own shared pool (default: 1)",
+        )
+        p.add_argument(
+            "--seed",
+            default=42,
+            type=int,
+            help="random seed, for reproducible collections (default: 42)",
+        )
+        p.add_argument(
+            "--kernel",
+            action="append",
+            default=[],
+            choices=["multisearch", "pairwise", "gather"],
+            help="kernel to time; may be given multiple times (default: all)",
+        )
+        p.add_argument(
+            "--threads",
+            action="append",
+            type=int,
+            default=[],
+            help="number of threads to time the kernels with; may be given multiple times (default: --cores)",
+        )
+        p.add_argument(
+            "-t",
+            "--threshold",
+            default=0.01,
+            type=float,
+            help="containment threshold for multisearch and pairwise (default: 0.01)",
+        )
+        p.add_argument(
+            "-o",
+            "--output",
+            help="CSV output file for timings (default: stdout)",
+        )
+        p.add_argument(
+            "-c",
+            "--cores",
+            default=0,
+            type=int,
+            help="number of cores to use (default is all available)",
+        )
+        add_force_args(p)
+
+    def main(self, args):
+        print_version()
+        if not check_outputs(args):
+            return 1
+
+        kernels = args.kernel or ["multisearch", "pairwise", "gather"]
+        num_threads = set_thread_pool(args.cores)
+        threads = args.threads or [num_threads]
+
+        notify(
+            f"timing {', '.join(kernels)} on {args.n_sketches} synthetic sketches with {threads} threads"
+        )
+
+        super().main(args)
+        status = sourmash_plugin_branchwater.do_bench(
+            args.n_sketches,
+            args.n_hashes,
+            args.scaled,
+            args.overlap,
+            args.n_groups,
+            args.seed,
+            kernels,
+            threads=threads,
+            threshold=args.threshold,
+            output=args.output,
+        )
+        if status == 0:
+            notify("...bench is done!")
+        return status
+
+
+class Branchwater_Merge(CommandLinePlugin):
+    command = "merge"
+    description = "merge (union) many sketches by group, summing abundances"
+
+    def __init__(self, p):
+        super().__init__(p)
+        p.add_argument("sig_paths", help="input file of sketches")
+        p.add_argument(
+            "-g",
+            "--groups",
+            required=True,
+            help="CSV file with 'group' and 'name' columns; merge the sketches in each group",
+        )
+        p.add_argument(
+            "-o",
+            "--output",
+            required=True,
+            help="output zip file (or .sig/.sig.gz) for the merged signatures",
+        )
+        p.add_argument(
+            "-k",
+            "--ksize",
+            default=31,
+            type=int,
+            help="k-mer size at which to select sketches (default: 31)",
+        )
+        p.add_argument(
+            "-s",
+            "--scaled",
+            default=None,
+            type=int,
+            help="scaled factor at which to merge (default: max scaled in collection)",
+        )
+        p.add_argument(
+            "-m",
+            "--moltype",
+            default="DNA",
+            choices=["DNA", "protein", "dayhoff", "hp", "skipm1n3", "skipm2n3"],
+            help="molecule type: DNA, protein, dayhoff, hp, or skipmer (skipm1n3 or skipm2n3); default DNA",
+        )
+        p.add_argument(
+            "-c",
+            "--cores",
+            default=0,
+            type=int,
+            help="number of cores to use (default is all available)",
+        )
+        add_force_args(p)
+
+    def main(self, args):
+        print_version()
+        if not check_outputs(args):
+            return 1
+
+        notify(
+            f"ksize: {args.ksize} / scaled: {args.scaled} / moltype: {args.moltype}"
+        )
+
+        num_threads = set_thread_pool(args.cores)
+
+        notify(
+            f"merging sketches in '{args.sig_paths}' by group using {num_threads} threads"
+        )
+
+        super().main(args)
+        status = sourmash_plugin_branchwater.do_merge(
+            args.sig_paths,
+            args.ksize,
+            args.scaled,
+            args.moltype,
+            args.groups,
+            args.output,
+        )
+        if status == 0:
+            notify(f"...merge is done! signatures in '{args.output}'")
+        return status
+
+
+class Branchwater_Subtract(CommandLinePlugin):
+    command = "subtract"
+    description = "remove contaminant hashes from every sketch in a collection"
+
+    def __init__(self, p):
+        super().__init__(p)
+        p.add_argument("sig_paths", help="input file of sketches to clean")
+        p.add_argument(
+            "contaminants", help="input file of contaminant sketches to subtract"
+        )
+        p.add_argument(
+            "-o",
+            "--output",
+            required=True,
+            help="output zip file for the cleaned signatures",
+        )
+        p.add_argument(
+            "--report",
+            default=None,
+            help="output CSV file reporting the number of hashes removed from each sketch",
+        )
+        p.add_argument(
+            "-k",
+            "--ksize",
+            default=31,
+            type=int,
+            help="k-mer size at which to select sketches (default: 31)",
+        )
+        p.add_argument(
+            "-s",
+            "--scaled",
+            default=None,
+            type=int,
+            help="scaled factor at which to subtract (default: max scaled in inputs)",
+        )
+        p.add_argument(
+            "-m",
+            "--moltype",
+            default="DNA",
+            choices=["DNA", "protein", "dayhoff", "hp", "skipm1n3", "skipm2n3"],
+            help="molecule type: DNA, protein, dayhoff, hp, or skipmer (skipm1n3 or skipm2n3); default DNA",
+        )
+        p.add_argument(
+            "-c",
+            "--cores",
+            default=0,
+            type=int,
+            help="number of cores to use (default is all available)",
+        )
+        add_force_args(p)
+
+    def main(self, args):
+        print_version()
+        if not check_outputs(args):
+            return 1
+
+        notify(
+            f"ksize: {args.ksize} / scaled: {args.scaled} / moltype: {args.moltype}"
+        )
+
+        num_threads = set_thread_pool(args.cores)
+
+        notify(
+            f"subtracting '{args.contaminants}' from sketches in '{args.sig_paths}' using {num_threads} threads"
+        )
+
+        super().main(args)
+        status = sourmash_plugin_branchwater.do_subtract(
+            args.sig_paths,
+            args.contaminants,
+            args.ksize,
+            args.scaled,
+            args.moltype,
+            args.output,
+            args.report,
+        )
+        if status == 0:
+            notify(f"...subtract is done! signatures in '{args.output}'")
+        return status
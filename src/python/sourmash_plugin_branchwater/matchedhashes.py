@@ -0,0 +1,27 @@
+"Read the files written by `manysearch --output-matched-hashes`."
+import struct
+
+MAGIC = b"BWMATCH1"
+
+
+def read_matched_hashes(path):
+    """Return (scaled, matches) from a matched hashes file, where matches
+    is a list of (query_md5, match_md5, hashes) tuples."""
+    with open(path, "rb") as fp:
+        data = fp.read()
+
+    if data[:8] != MAGIC:
+        raise ValueError(f"'{path}' is not a branchwater matched hashes file")
+    (scaled,) = struct.unpack_from("<Q", data, 8)
+
+    matches = []
+    pos = 16
+    while pos < len(data):
+        query_md5 = data[pos : pos + 32].decode("ascii")
+        match_md5 = data[pos + 32 : pos + 64].decode("ascii")
+        (n_hashes,) = struct.unpack_from("<Q", data, pos + 64)
+        hashes = list(struct.unpack_from(f"<{n_hashes}Q", data, pos + 72))
+        matches.append((query_md5, match_md5, hashes))
+        pos += 72 + 8 * n_hashes
+
+    return scaled, matches
@@ -29,6 +29,28 @@ def zip_siglist(runtmp, siglist, db):
     return db
 
 
+def corrupt_zip(src, dest):
+    """Copy the zip collection 'src' to 'dest', dropping the first hash of
+    every sketch but keeping the stored md5s, as a damaged copy might."""
+    import gzip
+    import json
+    import zipfile
+
+    with zipfile.ZipFile(src) as zin, zipfile.ZipFile(dest, "w") as zout:
+        for info in zin.infolist():
+            data = zin.read(info.filename)
+            if info.filename.endswith(".sig.gz"):
+                sigs = json.loads(gzip.decompress(data))
+                for sig in sigs:
+                    for sketch in sig["signatures"]:
+                        del sketch["mins"][0]
+                        if "abundances" in sketch:
+                            del sketch["abundances"][0]
+                data = gzip.compress(json.dumps(sigs).encode())
+            zout.writestr(info.filename, data)
+    return dest
+
+
 def index_siglist(
     runtmp,
     siglist,
@@ -0,0 +1,83 @@
+"""
+Test 'sourmash scripts bench'
+"""
+
+import pytest
+import pandas
+
+from . import sourmash_tst_utils as utils
+
+
+def test_installed(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "bench", "--bad-option")
+
+    assert "usage:  bench" in runtmp.last_result.err
+
+
+def test_bench(runtmp):
+    output = runtmp.output("bench.csv")
+
+    runtmp.sourmash(
+        "scripts",
+        "bench",
+        "-n",
+        "30",
+        "--n-hashes",
+        "100",
+        "--overlap",
+        "0.5",
+        "--n-groups",
+        "3",
+        "--threads",
+        "1",
+        "--threads",
+        "2",
+        "-o",
+        output,
+    )
+
+    df = pandas.read_csv(output)
+    print(df)
+    assert list(df.columns) == [
+        "kernel",
+        "threads",
+        "n_sketches",
+        "n_hashes",
+        "scaled",
+        "overlap",
+        "n_groups",
+        "n_comparisons",
+        "n_results",
+        "seconds",
+    ]
+    assert list(df["kernel"]) == ["multisearch", "pairwise", "gather"] * 2
+    assert list(df["threads"]) == [1, 1, 1, 2, 2, 2]
+    assert (df["seconds"] >= 0).all()
+
+    # the same collection is searched with each thread count.
+    by_kernel = df.groupby("kernel")
+    assert (by_kernel["n_results"].nunique() == 1).all()
+
+    ms = df[df["kernel"] == "multisearch"].iloc[0]
+    assert ms["n_comparisons"] == 30 * 30
+    pw = df[df["kernel"] == "pairwise"].iloc[0]
+    assert pw["n_comparisons"] == 30 * 29 // 2
+
+
+def test_bench_one_kernel(runtmp):
+    output = runtmp.output("bench.csv")
+
+    runtmp.sourmash(
+        "scripts", "bench", "-n", "10", "--kernel", "pairwise", "-o", output
+    )
+
+    df = pandas.read_csv(output)
+    assert list(df["kernel"]) == ["pairwise"]
+
+
+def test_bench_bad_overlap(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "bench", "-n", "10", "--overlap", "1.5")
+
+    assert "overlap must be between 0 and 1" in runtmp.last_result.err
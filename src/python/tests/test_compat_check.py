@@ -0,0 +1,84 @@
+"""
+Test 'sourmash scripts compat-check'
+"""
+
+import pytest
+
+from . import sourmash_tst_utils as utils
+from .sourmash_tst_utils import get_test_data, make_file_list, index_siglist
+
+
+def test_installed(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "compat-check")
+
+    assert "usage:  compat-check" in runtmp.last_result.err
+
+
+def make_lists(runtmp):
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47])
+    make_file_list(against_list, [sig2, sig47, sig63])
+    return query_list, against_list
+
+
+@pytest.mark.parametrize("indexed", [False, True])
+def test_compatible(runtmp, capfd, indexed):
+    query_list, against_list = make_lists(runtmp)
+    if indexed:
+        against_list = index_siglist(runtmp, against_list, runtmp.output("db"))
+
+    runtmp.sourmash("scripts", "compat-check", query_list, against_list)
+
+    captured = capfd.readouterr()
+    print(captured.out)
+    print(captured.err)
+    assert "compatible! comparisons would be done at scaled=1000" in captured.out
+    assert "query: 2 compatible sketches (0 with abundance)" in captured.out
+    assert "search: 3 compatible sketches (0 with abundance)" in captured.out
+    assert "6 comparisons (2 x 3)" in captured.out
+
+
+def test_scaled(runtmp, capfd):
+    # an explicit scaled is used for the comparisons
+    query_list, against_list = make_lists(runtmp)
+
+    runtmp.sourmash("scripts", "compat-check", query_list, against_list, "-s", "10000")
+
+    captured = capfd.readouterr()
+    print(captured.out)
+    assert "comparisons would be done at scaled=10000" in captured.out
+
+
+def test_incompatible_ksize(runtmp, capfd):
+    query_list, against_list = make_lists(runtmp)
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "compat-check", query_list, against_list, "-k", "21")
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "no query sketches are compatible with k=21, moltype=DNA" in captured.err
+    assert "available query sketch parameters:" in captured.err
+    assert "DNA,k=31,scaled=1000: 2 sketches" in captured.err
+
+
+def test_incompatible_scaled(runtmp, capfd):
+    # sketches cannot be downsampled to a smaller scaled
+    query_list, against_list = make_lists(runtmp)
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts", "compat-check", query_list, against_list, "-s", "100"
+        )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    msg = "no query sketches are compatible with k=31, moltype=DNA, scaled=100"
+    assert msg in captured.err
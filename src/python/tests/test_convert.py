@@ -0,0 +1,175 @@
+"""
+Test 'sourmash scripts convert'
+"""
+
+import os
+import pytest
+import sourmash
+
+from . import sourmash_tst_utils as utils
+from .sourmash_tst_utils import get_test_data, make_file_list
+
+
+def md5s(path):
+    return sorted(ss.md5sum() for ss in sourmash.load_file_as_signatures(path))
+
+
+def make_zip(runtmp):
+    zips = [get_test_data(f"{n}.sig.zip") for n in (2, 47, 63)]
+    output = runtmp.output("all.zip")
+    runtmp.sourmash("scripts", "zip-cat", *zips, "-o", output)
+    return output
+
+
+def test_installed(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "convert")
+
+    assert "usage:  convert" in runtmp.last_result.err
+
+
+def test_zip_to_dir_to_zip(runtmp):
+    input_zip = make_zip(runtmp)
+    expected = md5s(input_zip)
+    assert len(expected) == 3
+
+    sigdir = runtmp.output("sigs")
+    runtmp.sourmash("scripts", "convert", input_zip, "-o", sigdir)
+    assert sorted(os.listdir(sigdir)) == sorted(f"{md5}.sig.gz" for md5 in expected)
+
+    output = runtmp.output("roundtrip.zip")
+    runtmp.sourmash("scripts", "convert", sigdir, "-o", output)
+    assert md5s(output) == expected
+    runtmp.sourmash("scripts", "validate-zip", output)
+
+
+def test_zip_to_manifest_to_zip(runtmp):
+    input_zip = make_zip(runtmp)
+    expected = md5s(input_zip)
+
+    manifest = runtmp.output("db.csv")
+    runtmp.sourmash("scripts", "convert", input_zip, "-o", manifest)
+    assert os.path.isfile(manifest)
+    assert len(os.listdir(runtmp.output("db"))) == 3
+
+    idx = sourmash.load_file_as_index(manifest)
+    assert sorted(row["md5"] for row in idx.manifest.rows) == expected
+
+    output = runtmp.output("roundtrip.zip")
+    runtmp.sourmash("scripts", "convert", manifest, "-o", output)
+    assert md5s(output) == expected
+
+
+def test_zip_to_rocksdb_to_zip(runtmp):
+    input_zip = make_zip(runtmp)
+    expected = md5s(input_zip)
+
+    db = runtmp.output("db.rocksdb")
+    runtmp.sourmash("scripts", "convert", input_zip, "-o", db)
+    assert os.path.exists(os.path.join(db, "CURRENT"))
+    runtmp.sourmash("scripts", "check", db)
+
+    output = runtmp.output("roundtrip.zip")
+    runtmp.sourmash("scripts", "convert", db, "-o", output)
+    assert md5s(output) == expected
+
+
+def test_dir_to_rocksdb(runtmp):
+    # directory input with many files must be loaded into memory to index
+    sigdir = runtmp.output("sigs")
+    runtmp.sourmash("scripts", "convert", make_zip(runtmp), "-o", sigdir)
+
+    db = runtmp.output("db")
+    runtmp.sourmash("scripts", "convert", sigdir, "-o", db, "--to", "rocksdb")
+    assert os.path.exists(os.path.join(db, "CURRENT"))
+
+    output = runtmp.output("roundtrip.zip")
+    runtmp.sourmash("scripts", "convert", db, "-o", output)
+    assert len(md5s(output)) == 3
+
+
+def test_pathlist_to_zip_select(runtmp):
+    # pathlist input, selecting one ksize from multi-ksize signatures
+    sig1 = get_test_data("1.combined.sig.gz")
+    sig2 = get_test_data("2.fa.k21.sig.gz")
+    sig_list = runtmp.output("sigs.txt")
+    make_file_list(sig_list, [sig1, sig2])
+
+    output = runtmp.output("k21.zip")
+    runtmp.sourmash("scripts", "convert", sig_list, "-o", output, "-k", "21")
+
+    sigs = list(sourmash.load_file_as_signatures(output))
+    assert len(sigs) == 2
+    assert all(ss.minhash.ksize == 21 for ss in sigs)
+
+
+def test_rocksdb_mixed_ksizes(runtmp, capfd):
+    sig1 = get_test_data("1.combined.sig.gz")
+    db = runtmp.output("db.rocksdb")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "convert", sig1, "-o", db)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "a RocksDB index holds sketches with one ksize" in captured.err
+    assert not os.path.exists(db)
+
+
+def test_existing_output(runtmp):
+    input_zip = make_zip(runtmp)
+    output = runtmp.output("out.zip")
+    with open(output, "wt") as fp:
+        fp.write("")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "convert", input_zip, "-o", output)
+    assert "already exists" in runtmp.last_result.err
+
+    runtmp.sourmash("scripts", "convert", input_zip, "-o", output, "--force")
+    assert len(md5s(output)) == 3
+
+
+def test_zip_output_name(runtmp, capfd):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "convert",
+            get_test_data("47.sig.zip"),
+            "-o",
+            runtmp.output("out.sig"),
+            "--to",
+            "zip",
+        )
+
+    captured = capfd.readouterr()
+    assert "Output must be a zip file" in captured.err
+
+
+def test_verify(runtmp, capfd):
+    input_zip = make_zip(runtmp)
+    output = runtmp.output("out.zip")
+
+    runtmp.sourmash("scripts", "convert", input_zip, "-o", output, "--verify")
+    assert len(md5s(output)) == 3
+
+    captured = capfd.readouterr()
+    assert "Verified md5s of 3 sketches." in captured.err
+
+
+def test_verify_corrupted(runtmp, capfd):
+    # the stored md5 still matches the manifest; only --verify notices.
+    corrupted = utils.corrupt_zip(
+        get_test_data("47.sig.zip"), runtmp.output("corrupted.zip")
+    )
+    runtmp.sourmash("scripts", "convert", corrupted, "-o", runtmp.output("ok.zip"))
+
+    output = runtmp.output("out.zip")
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "convert", corrupted, "-o", output, "--verify")
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "failed verification: md5 mismatch" in captured.err
+    assert "1 of 1 sketches failed md5 verification" in captured.err
+    assert not os.path.exists(output)
@@ -0,0 +1,112 @@
+"""
+Test 'sourmash scripts downsample'
+"""
+
+import os
+import pytest
+import sourmash
+
+from . import sourmash_tst_utils as utils
+from .sourmash_tst_utils import (
+    get_test_data,
+    make_file_list,
+    zip_siglist,
+)
+
+
+def test_installed(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "downsample")
+
+    assert "usage:  downsample" in runtmp.last_result.err
+
+
+@pytest.mark.parametrize("zip_input", [False, True])
+def test_simple(runtmp, zip_input):
+    # downsample to a higher scaled
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    sig_list = runtmp.output("sigs.txt")
+    make_file_list(sig_list, [sig47, sig63])
+    if zip_input:
+        sig_list = zip_siglist(runtmp, sig_list, runtmp.output("sigs.zip"))
+
+    output = runtmp.output("out.zip")
+    runtmp.sourmash("scripts", "downsample", sig_list, "-s", "10000", "-o", output)
+
+    idx = sourmash.load_file_as_index(output)
+    assert len(idx.manifest) == 2
+    sigs = {ss.name: ss for ss in idx.signatures()}
+
+    for filename in (sig47, sig63):
+        orig = sourmash.load_one_signature(filename, ksize=31)
+        ss = sigs[orig.name]
+        assert ss.minhash.scaled == 10000
+        assert ss.minhash == orig.minhash.downsample(scaled=10000)
+        assert ss.filename == orig.filename
+
+
+def test_ksizes(runtmp):
+    # keep only some ksizes
+    sig = get_test_data("1.combined.sig.gz")
+
+    output = runtmp.output("out.zip")
+    runtmp.sourmash("scripts", "downsample", sig, "-k", "21", "-k", "51", "-o", output)
+
+    idx = sourmash.load_file_as_index(output)
+    assert sorted(row["ksize"] for row in idx.manifest.rows) == [21, 51]
+    for ss in idx.signatures():
+        assert ss.minhash.scaled == 1000
+
+
+def test_abundance_preserved(runtmp):
+    sig = get_test_data("SRR606249.sig.gz")
+
+    output = runtmp.output("out.zip")
+    runtmp.sourmash("scripts", "downsample", sig, "-s", "200000", "-o", output)
+
+    orig = sourmash.load_one_signature(sig, ksize=31)
+    (ss,) = sourmash.load_file_as_signatures(output)
+    assert ss.minhash.track_abundance
+    assert ss.minhash == orig.minhash.downsample(scaled=200000)
+
+
+def test_scaled_too_low(runtmp, capfd):
+    # sketches at a higher scaled are skipped
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig_abund = get_test_data("SRR606249.sig.gz")
+
+    sig_list = runtmp.output("sigs.txt")
+    make_file_list(sig_list, [sig47, sig_abund])
+
+    output = runtmp.output("out.zip")
+    runtmp.sourmash("scripts", "downsample", sig_list, "-s", "10000", "-o", output)
+
+    idx = sourmash.load_file_as_index(output)
+    assert len(idx.manifest) == 1
+    captured = capfd.readouterr()
+    assert "skipped 1 " in captured.err
+
+
+def test_missing_ksize(runtmp, capfd):
+    sig47 = get_test_data("47.fa.sig.gz")
+
+    output = runtmp.output("out.zip")
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "downsample", sig47, "-k", "21", "-o", output)
+
+    captured = capfd.readouterr()
+    assert "No analysis signatures loaded" in captured.err
+    assert not os.path.exists(output)
+
+
+def test_output_not_zip(runtmp, capfd):
+    sig47 = get_test_data("47.fa.sig.gz")
+
+    output = runtmp.output("out.sig")
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "downsample", sig47, "-s", "10000", "-o", output)
+
+    captured = capfd.readouterr()
+    assert "Output must be a zip file" in captured.err
@@ -0,0 +1,205 @@
+"""
+Test 'sourmash scripts extract'
+"""
+
+import os
+import pytest
+import sourmash
+
+from . import sourmash_tst_utils as utils
+from .sourmash_tst_utils import (
+    get_test_data,
+    make_file_list,
+    zip_siglist,
+)
+
+MD5_47 = "09a08691ce52952152f0e866a59f6261"
+
+
+def load_names(filename):
+    return {ss.name for ss in sourmash.load_file_as_signatures(filename)}
+
+
+def make_sig_list(runtmp, zip_input=False):
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    sig_list = runtmp.output("sigs.txt")
+    make_file_list(sig_list, [sig2, sig47, sig63])
+    if zip_input:
+        sig_list = zip_siglist(runtmp, sig_list, runtmp.output("sigs.zip"))
+    return sig_list
+
+
+def test_installed(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "extract")
+
+    assert "usage:  extract" in runtmp.last_result.err
+
+
+@pytest.mark.parametrize("zip_input", [False, True])
+def test_name_regex(runtmp, zip_input):
+    sig_list = make_sig_list(runtmp, zip_input)
+
+    output = runtmp.output("out.zip")
+    runtmp.sourmash(
+        "scripts", "extract", sig_list, "--name-regex", "Shewanella", "-o", output
+    )
+
+    names = load_names(output)
+    assert len(names) == 2
+    assert all("Shewanella" in name for name in names)
+
+    idx = sourmash.load_file_as_index(output)
+    assert len(idx.manifest) == 2
+
+
+def test_md5(runtmp):
+    sig_list = make_sig_list(runtmp)
+
+    # full md5 and md5 prefix
+    for md5 in (MD5_47, MD5_47[:8]):
+        output = runtmp.output(f"out.{md5}.zip")
+        runtmp.sourmash("scripts", "extract", sig_list, "--md5", md5, "-o", output)
+
+        (ss,) = sourmash.load_file_as_signatures(output)
+        assert ss.md5sum() == MD5_47
+
+
+def test_filters_combined(runtmp):
+    # all filters must match
+    sig_list = make_sig_list(runtmp)
+
+    output = runtmp.output("out.zip")
+    runtmp.sourmash(
+        "scripts",
+        "extract",
+        sig_list,
+        "--name-regex",
+        "^NC_",
+        "--md5",
+        MD5_47,
+        "-o",
+        output,
+    )
+
+    (ss,) = sourmash.load_file_as_signatures(output)
+    assert ss.md5sum() == MD5_47
+
+
+@pytest.mark.parametrize("pickstyle", ["", ":include", ":exclude"])
+def test_picklist_ident(runtmp, pickstyle):
+    sig_list = make_sig_list(runtmp)
+
+    picklist = runtmp.output("pick.csv")
+    with open(picklist, "wt") as fp:
+        fp.write("accession,other\n")
+        fp.write("NC_009661.1,x\n")
+        fp.write("CP001071.1,y\n")
+
+    output = runtmp.output("out.zip")
+    runtmp.sourmash(
+        "scripts",
+        "extract",
+        sig_list,
+        "--picklist",
+        f"{picklist}:accession:ident{pickstyle}",
+        "-o",
+        output,
+    )
+
+    idents = {name.split(" ")[0] for name in load_names(output)}
+    if pickstyle == ":exclude":
+        assert idents == {"NC_011665.1"}
+    else:
+        assert idents == {"NC_009661.1", "CP001071.1"}
+
+
+def test_picklist_identprefix_md5short(runtmp):
+    sig_list = make_sig_list(runtmp)
+
+    picklist = runtmp.output("pick.csv")
+    with open(picklist, "wt") as fp:
+        fp.write("acc,md5\n")
+        fp.write(f"NC_009661,{MD5_47[:8]}\n")
+
+    for spec in ("acc:identprefix", "md5:md5short", "md5:md5prefix8"):
+        output = runtmp.output("out.zip")
+        runtmp.sourmash(
+            "scripts",
+            "extract",
+            sig_list,
+            "--picklist",
+            f"{picklist}:{spec}",
+            "-o",
+            output,
+        )
+
+        (ss,) = sourmash.load_file_as_signatures(output)
+        assert ss.md5sum() == MD5_47
+
+
+def test_ksizes(runtmp):
+    sig = get_test_data("1.combined.sig.gz")
+
+    output = runtmp.output("out.zip")
+    runtmp.sourmash("scripts", "extract", sig, "-k", "21", "-k", "51", "-o", output)
+
+    idx = sourmash.load_file_as_index(output)
+    assert sorted(row["ksize"] for row in idx.manifest.rows) == [21, 51]
+
+
+def test_no_match(runtmp, capfd):
+    sig_list = make_sig_list(runtmp)
+
+    output = runtmp.output("out.zip")
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts", "extract", sig_list, "--name-regex", "Escherichia", "-o", output
+        )
+
+    captured = capfd.readouterr()
+    assert "No signatures matched the given filters" in captured.err
+    assert not os.path.exists(output)
+
+
+def test_bad_regex(runtmp, capfd):
+    sig_list = make_sig_list(runtmp)
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "extract",
+            sig_list,
+            "--name-regex",
+            "(unclosed",
+            "-o",
+            runtmp.output("out.zip"),
+        )
+
+    captured = capfd.readouterr()
+    assert "invalid name pattern" in captured.err
+
+
+def test_bad_picklist(runtmp, capfd):
+    sig_list = make_sig_list(runtmp)
+
+    picklist = runtmp.output("pick.csv")
+    with open(picklist, "wt") as fp:
+        fp.write("acc\nNC_009661.1\n")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "extract",
+            sig_list,
+            "--picklist",
+            f"{picklist}:acc:accession",
+            "-o",
+            runtmp.output("out.zip"),
+        )
+
+    captured = capfd.readouterr()
+    assert "unknown picklist column type 'accession'" in captured.err
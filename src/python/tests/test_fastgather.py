@@ -1,4 +1,5 @@
 import os
+import csv
 import pytest
 import pandas
 
@@ -142,6 +143,7 @@ def test_simple_with_prefetch(runtmp, zip_against, indexed, toggle_internal_stor
         "match_name",
         "match_md5",
         "intersect_bp",
+        "intersect_hashes",
     }
 
 
@@ -197,6 +199,7 @@ def test_simple_with_prefetch_list_of_zips(runtmp):
         "match_name",
         "match_md5",
         "intersect_bp",
+        "intersect_hashes",
     }
 
 
@@ -237,17 +240,15 @@ def test_missing_query(runtmp, capfd, zip_against):
     assert "Error: No such file or directory" in captured.err
 
 
-def test_bad_query(runtmp, capfd, zip_against):
-    # test non-sig query
-    query = runtmp.output("no-such-file")
+def test_multiple_queries(runtmp, capfd, zip_against):
+    # a pathlist with more than one query sketch gathers each query
+    query = runtmp.output("queries.txt")
     against_list = runtmp.output("against.txt")
 
     sig2 = get_test_data("2.fa.sig.gz")
     sig47 = get_test_data("47.fa.sig.gz")
     sig63 = get_test_data("63.fa.sig.gz")
 
-    # query doesn't need to be a sig anymore - sig, zip, or pathlist welcome
-    # as long as there's only one sketch that matches params
     make_file_list(query, [sig2, sig47])
     make_file_list(against_list, [sig2, sig47, sig63])
 
@@ -257,26 +258,38 @@ def test_bad_query(runtmp, capfd, zip_against):
     g_output = runtmp.output("gather.csv")
     p_output = runtmp.output("prefetch.csv")
 
-    with pytest.raises(utils.SourmashCommandFailed):
-        runtmp.sourmash(
-            "scripts",
-            "fastgather",
-            query,
-            against_list,
-            "-o",
-            g_output,
-            "--output-prefetch",
-            p_output,
-            "-s",
-            "100000",
-        )
+    runtmp.sourmash(
+        "scripts",
+        "fastgather",
+        query,
+        against_list,
+        "-o",
+        g_output,
+        "--output-prefetch",
+        p_output,
+        "-s",
+        "100000",
+    )
 
     captured = capfd.readouterr()
     print(captured.err)
+    assert "DONE. Gathered 2 queries." in captured.err
+
+    df = pandas.read_csv(g_output)
+    query_md5s = {sourmash.load_one_signature(sig).md5sum() for sig in (sig2, sig47)}
+    assert set(df["query_md5"]) == query_md5s
+
+    # each query's best match is itself
+    for query_md5, rows in df.groupby("query_md5"):
+        best = rows[rows["gather_result_rank"] == 0].iloc[0]
+        assert best["match_md5"] == query_md5
+        assert set(rows["scaled"]) == {100000}
+
+    prefetch = pandas.read_csv(p_output)
+    assert set(prefetch["query_md5"]) == set(df["query_md5"])
+    for query_md5, rows in prefetch.groupby("query_md5"):
+        assert query_md5 in set(rows["match_md5"])
 
-    assert (
-        "Error: Fastgather requires a single query sketch. Check input:" in captured.err
-    )
 
 
 def test_missing_against(runtmp, capfd, zip_against):
@@ -483,25 +496,27 @@ def test_query_multisigfile(runtmp, capfd, zip_against):
     g_output = runtmp.output("gather.csv")
     p_output = runtmp.output("prefetch.csv")
 
-    with pytest.raises(utils.SourmashCommandFailed):
-        runtmp.sourmash(
-            "scripts",
-            "fastgather",
-            combined,
-            against_list,
-            "-o",
-            g_output,
-            "--output-prefetch",
-            p_output,
-            "-s",
-            "100000",
-        )
-    # this fails now :)
+    runtmp.sourmash(
+        "scripts",
+        "fastgather",
+        combined,
+        against_list,
+        "-o",
+        g_output,
+        "--output-prefetch",
+        p_output,
+        "-s",
+        "100000",
+    )
+
     captured = capfd.readouterr()
     print(captured.err)
-    assert (
-        "Error: Fastgather requires a single query sketch. Check input:" in captured.err
-    )
+    assert "DONE. Gathered 3 queries." in captured.err
+
+    df = pandas.read_csv(g_output)
+    assert len(set(df["query_name"])) == 3
+    assert set(df[df["gather_result_rank"] == 0]["match_md5"]) == set(df["query_md5"])
+
 
 
 def test_against_nomatch(runtmp, capfd, zip_against):
@@ -608,6 +623,7 @@ def test_md5s(runtmp, zip_against):
         "match_name",
         "match_md5",
         "intersect_bp",
+        "intersect_hashes",
     }
 
     md5s = list(df["match_md5"])
@@ -617,6 +633,9 @@ def test_md5s(runtmp, zip_against):
         for ss in sourmash.load_file_as_signatures(against_file, ksize=31):
             assert ss.md5sum() in md5s
 
+    # intersect_bp is in bp, like sourmash prefetch, not in hashes.
+    assert list(df["intersect_bp"]) == list(df["intersect_hashes"] * 100000)
+
 
 def test_csv_columns_vs_sourmash_prefetch(runtmp, zip_against):
     # the column names should be strict subsets of sourmash prefetch cols
@@ -678,6 +697,7 @@ def test_csv_columns_vs_sourmash_prefetch(runtmp, zip_against):
     diff_keys = g_keys - sp_keys
     assert diff_keys == set(
         [
+            "intersect_hashes",
             "unique_intersect_bp",
             "median_abund",
             "f_match_orig",
@@ -1095,6 +1115,7 @@ def test_simple_full_output(runtmp):
         "query_abundance",
         "match_containment_ani",
         "intersect_bp",
+        "intersect_hashes",
         "total_weighted_hashes",
         "n_unique_weighted_found",
         "query_name",
@@ -1127,6 +1148,7 @@ def test_simple_full_output(runtmp):
 
     intersect_bp = set(df["intersect_bp"])
     assert intersect_bp == set([4400000, 4100000, 2200000])
+    assert set(df["intersect_hashes"]) == {44, 41, 22}
     f_unique_to_query = set([round(x, 4) for x in df["f_unique_to_query"]])
     assert f_unique_to_query == set([0.0052, 0.0105, 0.0043])
     query_containment_ani = set([round(x, 4) for x in df["query_containment_ani"]])
@@ -1182,6 +1204,7 @@ def test_fullres_vs_sourmash_gather(runtmp):
     sg_keys.update(
         modified_keys
     )  # fastgather is more explicit (match_md5 instead of md5, etc)
+    sg_keys.add("intersect_hashes")  # not reported by sourmash gather
     print("g_keys - sg_keys:", g_keys - sg_keys)
     assert not g_keys - sg_keys, g_keys - sg_keys
 
@@ -1391,3 +1414,705 @@ def test_simple_skipm2n3(
         assert (
             "WARNING: loading all sketches from a RocksDB into memory!" in captured.err
         )
+
+
+def test_prefetch_csv_quoting(runtmp):
+    # check that names with commas and quotes are properly escaped in
+    # prefetch output
+    base = sourmash.MinHash(scaled=1, ksize=31, n=0)
+
+    a = base.copy_and_clear()
+    c = base.copy_and_clear()
+
+    a.add_many(range(0, 1000))
+    c.add_many(range(0, 2000))
+
+    match_name = 'g_a, a "quoted" genome'
+    ss = sourmash.SourmashSignature(a, name=match_name)
+    sourmash.save_signatures([ss], open(runtmp.output("a.sig"), "wb"))
+    ss = sourmash.SourmashSignature(c, name="g_mg, metagenome")
+    sourmash.save_signatures([ss], open(runtmp.output("mg.sig"), "wb"))
+
+    runtmp.sourmash("sig", "cat", "a.sig", "-o", "against.sig.zip")
+
+    runtmp.sourmash(
+        "scripts",
+        "fastgather",
+        "mg.sig",
+        "against.sig.zip",
+        "-o",
+        "out.csv",
+        "--output-prefetch",
+        "prefetch.csv",
+        "--threshold-bp",
+        "0",
+    )
+
+    df = pandas.read_csv(runtmp.output("prefetch.csv"))
+    assert len(df) == 1
+    assert df["match_name"][0] == match_name
+    assert df["query_name"][0] == "g_mg, metagenome"
+
+
+def test_api_in_memory_query(runtmp):
+    # pass sourmash signatures & JSON directly, without writing .sig files
+    from sourmash_plugin_branchwater import sourmash_plugin_branchwater as api
+
+    base = sourmash.MinHash(scaled=1, ksize=31, n=0)
+
+    a = base.copy_and_clear()
+    b = base.copy_and_clear()
+    c = base.copy_and_clear()
+
+    a.add_many(range(0, 1000))
+    b.add_many(range(1000, 2000))
+    c.add_many(range(0, 2000))
+
+    ss_a = sourmash.SourmashSignature(a, name="g_a")
+    ss_b = sourmash.SourmashSignature(b, name="g_b")
+    ss_mg = sourmash.SourmashSignature(c, name="g_mg")
+
+    # query as a SourmashSignature, against as a list of them
+    output = runtmp.output("out.csv")
+    status = api.do_fastgather(ss_mg, [ss_a, ss_b], 0, 31, 1, "DNA", output)
+    assert status == 0
+
+    df = pandas.read_csv(output)
+    assert len(df) == 2
+    assert set(df["match_name"]) == {"g_a", "g_b"}
+    assert set(df["query_name"]) == {"g_mg"}
+
+    # query as JSON str, and as bytes
+    query_json = sourmash.save_signatures([ss_mg])
+    if isinstance(query_json, bytes):
+        query_json = query_json.decode("utf-8")
+
+    for query in (query_json, query_json.encode("utf-8")):
+        output = runtmp.output("out2.csv")
+        status = api.do_fastgather(query, [ss_a, ss_b], 0, 31, 1, "DNA", output)
+        assert status == 0
+        df = pandas.read_csv(output)
+        assert len(df) == 2
+
+
+def test_api_in_memory_bad_json(runtmp):
+    # invalid JSON is raised as an exception
+    from sourmash_plugin_branchwater import sourmash_plugin_branchwater as api
+
+    against = get_test_data("2.fa.sig.gz")
+    with pytest.raises(Exception):
+        api.do_fastgather("[not json", against, 0, 31, None, "DNA")
+
+
+def test_taxonomy_cami(runtmp):
+    # fastgather can also write a taxonomic summary and a CAMI profile
+    query = get_test_data("SRR606249.sig.gz")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    lineages = runtmp.output("lineages.csv")
+    with open(lineages, "wt") as fp:
+        fp.write("ident,superkingdom,genus,species\n")
+        fp.write("CP001071,d__Bacteria,g__Akkermansia,s__Akkermansia muciniphila\n")
+        fp.write("NC_009661,d__Bacteria,g__Shewanella,s__Shewanella baltica\n")
+        fp.write("NC_011665,d__Bacteria,g__Shewanella,s__Shewanella baltica\n")
+
+    g_output = runtmp.output("gather.csv")
+    cami = runtmp.output("gather.cami.tsv")
+    runtmp.sourmash(
+        "scripts",
+        "fastgather",
+        query,
+        against_list,
+        "-o",
+        g_output,
+        "-s",
+        "100000",
+        "--taxonomy",
+        lineages,
+        "--output-cami",
+        cami,
+    )
+
+    summary = runtmp.output("gather.summarized.csv")
+    assert os.path.exists(summary)
+    df = pandas.read_csv(summary)
+    genus = df[df["rank"] == "genus"]
+    assert len(genus) == 3  # two genera + unclassified
+
+    with open(cami) as fp:
+        rows = [
+            line.split("\t")
+            for line in fp.read().splitlines()
+            if line and not line.startswith("@")
+        ]
+    # no taxids, so lineage names are used instead
+    species = [r for r in rows if r[1] == "species"]
+    assert len(species) == 2
+    assert {r[0] for r in species} == {
+        "s__Akkermansia muciniphila",
+        "s__Shewanella baltica",
+    }
+
+
+def test_taxonomy_kraken(runtmp):
+    # write a kraken-style report
+    query = get_test_data("SRR606249.sig.gz")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    lineages = runtmp.output("lineages.csv")
+    with open(lineages, "wt") as fp:
+        fp.write("ident,taxpath,superkingdom,genus,species\n")
+        fp.write("CP001071.1,2|239934|239935,Bacteria,Akkermansia,muciniphila\n")
+        fp.write("NC_009661.1,2|22|62322,Bacteria,Shewanella,baltica\n")
+        fp.write("NC_011665.1,2|22|62322,Bacteria,Shewanella,baltica\n")
+
+    g_output = runtmp.output("gather.csv")
+    kreport = runtmp.output("gather.kreport")
+    runtmp.sourmash(
+        "scripts",
+        "fastgather",
+        query,
+        against_list,
+        "-o",
+        g_output,
+        "-s",
+        "100000",
+        "--taxonomy",
+        lineages,
+        "--output-kraken",
+        kreport,
+    )
+
+    with open(kreport) as fp:
+        rows = [line.split("\t") for line in fp.read().splitlines()]
+    print(rows)
+
+    # unclassified, root, then the tree in depth-first order
+    assert [r[3] for r in rows] == ["U", "R", "D", "G", "S", "G", "S"]
+    assert rows[1][5] == "root"
+    assert rows[2][4:] == ["2", "  Bacteria"]
+
+    # clade bp for root is the sum of its children; percents sum to 100
+    assert int(rows[1][1]) == int(rows[2][1])
+    assert int(rows[2][1]) == int(rows[3][1]) + int(rows[5][1])
+    assert abs(float(rows[0][0]) + float(rows[1][0]) - 100) < 0.02
+
+    species = {r[4]: r for r in rows if r[3] == "S"}
+    assert set(species) == {"239935", "62322"}
+    assert species["62322"][5] == "      baltica"
+    # two matches are rolled up into one Shewanella species
+    assert int(species["62322"][1]) == int(species["62322"][2])
+
+
+@pytest.mark.parametrize(
+    "threshold_args,expected",
+    [
+        (["-t", "3000000"], {44, 41}),
+        (["--threshold-hashes", "30"], {44, 41}),
+        (["--threshold-fraction", "0.01"], {44}),
+    ],
+)
+def test_threshold_units(runtmp, capfd, threshold_args, expected):
+    # thresholds in bp, hashes, and fractions of the query all work
+    query = get_test_data("SRR606249.sig.gz")
+    against_list = runtmp.output("against.txt")
+    make_file_list(
+        against_list,
+        [get_test_data(f) for f in ("2.fa.sig.gz", "47.fa.sig.gz", "63.fa.sig.gz")],
+    )
+
+    g_output = runtmp.output("gather.csv")
+    p_output = runtmp.output("prefetch.csv")
+
+    runtmp.sourmash(
+        "scripts",
+        "fastgather",
+        query,
+        against_list,
+        "-o",
+        g_output,
+        "--output-prefetch",
+        p_output,
+        "-s",
+        "100000",
+        *threshold_args,
+    )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    # 0.01 of 4200 query hashes is 42
+    threshold_hashes = 42 if "--threshold-fraction" in threshold_args else 30
+    assert f"using threshold overlap: {threshold_hashes} hashes" in captured.err
+
+    df = pandas.read_csv(p_output)
+    assert set(df["intersect_hashes"]) == expected
+
+
+def test_threshold_bad_fraction(runtmp, capfd):
+    query = get_test_data("SRR606249.sig.gz")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "fastgather",
+            query,
+            get_test_data("63.fa.sig.gz"),
+            "-o",
+            runtmp.output("gather.csv"),
+            "--threshold-fraction",
+            "1.5",
+        )
+
+    captured = capfd.readouterr()
+    assert "threshold fraction must be in (0, 1]" in captured.err
+
+
+def test_threshold_exclusive(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "fastgather",
+            get_test_data("SRR606249.sig.gz"),
+            get_test_data("63.fa.sig.gz"),
+            "-o",
+            runtmp.output("gather.csv"),
+            "-t",
+            "1000",
+            "--threshold-hashes",
+            "10",
+        )
+
+    assert "not allowed with argument" in runtmp.last_result.err
+
+
+def run_stop_gather(runtmp, *extra):
+    query = get_test_data("SRR606249.sig.gz")
+    against_list = runtmp.output("against.txt")
+    make_file_list(
+        against_list,
+        [get_test_data(f) for f in ("2.fa.sig.gz", "47.fa.sig.gz", "63.fa.sig.gz")],
+    )
+
+    g_output = runtmp.output("gather.csv")
+    runtmp.sourmash(
+        "scripts", "fastgather", query, against_list, "-o", g_output, "-s", "100000"
+    )
+    full = pandas.read_csv(g_output).sort_values("gather_result_rank")
+    assert len(full) == 3
+
+    runtmp.sourmash(
+        "scripts",
+        "fastgather",
+        query,
+        against_list,
+        "-o",
+        g_output,
+        "-s",
+        "100000",
+        *extra,
+    )
+    stopped = pandas.read_csv(g_output).sort_values("gather_result_rank")
+    return full, stopped
+
+
+@pytest.mark.parametrize("max_matches", [1, 2, 5])
+def test_max_matches(runtmp, max_matches):
+    full, stopped = run_stop_gather(runtmp, "--max-matches", str(max_matches))
+
+    assert len(stopped) == min(max_matches, 3)
+    assert list(stopped["match_md5"]) == list(full["match_md5"])[:max_matches]
+
+
+def test_min_ani(runtmp):
+    # use a cutoff just below the best match's ANI, and check that gather
+    # stops at the first match below it.
+    full, _ = run_stop_gather(runtmp)
+    anis = list(full["max_containment_ani"])
+    min_ani = anis[0] - 0.0001
+    n_kept = next((i for i, ani in enumerate(anis) if ani < min_ani), len(anis))
+
+    _, stopped = run_stop_gather(runtmp, "--min-ani", str(min_ani))
+
+    assert len(stopped) == n_kept
+    assert list(stopped["match_md5"]) == list(full["match_md5"])[:n_kept]
+
+
+def test_min_ani_bad(runtmp, capfd):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "fastgather",
+            get_test_data("SRR606249.sig.gz"),
+            get_test_data("63.fa.sig.gz"),
+            "-o",
+            runtmp.output("gather.csv"),
+            "--min-ani",
+            "95",
+        )
+
+    captured = capfd.readouterr()
+    assert "min ANI must be between 0 and 1" in captured.err
+
+
+def test_abundance_weighted(runtmp):
+    # the first match has the largest abundance-weighted overlap
+    _, weighted = run_stop_gather(runtmp, "--abundance-weighted")
+
+    assert len(weighted) > 0
+    found = list(weighted["n_unique_weighted_found"])
+    assert found[0] == max(found)
+    assert list(weighted["gather_result_rank"]) == list(range(len(weighted)))
+
+
+def test_abundance_weighted_flat_query(runtmp, capfd):
+    # without abundances, abundance weighting changes nothing
+    query = get_test_data("47.fa.sig.gz")
+    against_list = runtmp.output("against.txt")
+    make_file_list(
+        against_list,
+        [get_test_data(f) for f in ("2.fa.sig.gz", "47.fa.sig.gz", "63.fa.sig.gz")],
+    )
+
+    outputs = []
+    for extra in ([], ["--abundance-weighted"]):
+        g_output = runtmp.output(f"gather{len(outputs)}.csv")
+        runtmp.sourmash(
+            "scripts", "fastgather", query, against_list, "-o", g_output, *extra
+        )
+        outputs.append(pandas.read_csv(g_output))
+
+    assert list(outputs[0]["match_md5"]) == list(outputs[1]["match_md5"])
+
+    captured = capfd.readouterr()
+    assert "has no abundances; picking matches by flat overlap" in captured.err
+
+
+def test_require_abundance(runtmp):
+    # abundances are required of the query only, not the flat matches
+    _, weighted = run_stop_gather(runtmp, "--abundance-weighted", "--require-abundance")
+
+    assert len(weighted) > 0
+    found = list(weighted["n_unique_weighted_found"])
+    assert found[0] == max(found)
+
+
+def test_require_abundance_flat_query(runtmp, capfd):
+    # a query without abundances is skipped, with a clear error
+    query = get_test_data("47.fa.sig.gz")
+    against_list = runtmp.output("against.txt")
+    make_file_list(against_list, [get_test_data("63.fa.sig.gz")])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "fastgather",
+            query,
+            against_list,
+            "-o",
+            runtmp.output("gather.csv"),
+            "--abundance-weighted",
+            "--require-abundance",
+        )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "1 query sketches do not have the requested abundances" in captured.err
+    assert "no compatible sketch has abundances" in captured.err
+
+
+def test_output_columns(runtmp):
+    # write only the requested gather columns
+    query = get_test_data("SRR606249.sig.gz")
+    against_list = runtmp.output("against.txt")
+    make_file_list(
+        against_list,
+        [get_test_data(f) for f in ("2.fa.sig.gz", "47.fa.sig.gz", "63.fa.sig.gz")],
+    )
+
+    g_output = runtmp.output("gather.csv")
+    runtmp.sourmash(
+        "scripts",
+        "fastgather",
+        query,
+        against_list,
+        "-o",
+        g_output,
+        "-s",
+        "100000",
+        "--output-columns",
+        "gather_result_rank,match_name,f_unique_weighted",
+    )
+
+    df = pandas.read_csv(g_output)
+    print(df)
+    assert list(df.columns) == ["gather_result_rank", "match_name", "f_unique_weighted"]
+    assert list(df["gather_result_rank"]) == list(range(len(df)))
+
+
+def test_fastgather_df_api(runtmp):
+    # test the Python API that returns results directly, vs CSV output
+    from sourmash_plugin_branchwater import sourmash_plugin_branchwater as api
+
+    query = get_test_data("SRR606249.sig.gz")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    g_output = runtmp.output("gather.csv")
+    runtmp.sourmash(
+        "scripts", "fastgather", query, against_list, "-o", g_output, "-s", "100000"
+    )
+    csv_df = pandas.read_csv(g_output)
+
+    results = api.do_fastgather_df(query, against_list, 50000, 31, 100000, "DNA")
+    assert len(results) == len(csv_df) == 3
+
+    df = pandas.DataFrame(results)
+    assert list(df["match_name"]) == list(csv_df["match_name"])
+    assert list(df["gather_result_rank"]) == [0, 1, 2]
+
+
+def test_exclude_manifest(runtmp, capfd, zip_against):
+    # sketches in an --exclude manifest are dropped from the against collection
+    query = get_test_data("SRR606249.sig.gz")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    if zip_against:
+        against_list = zip_siglist(runtmp, against_list, runtmp.output("against.zip"))
+
+    exclude_mf = runtmp.output("exclude.csv")
+    runtmp.sourmash("sig", "manifest", sig47, "-o", exclude_mf)
+
+    g_output = runtmp.output("gather.csv")
+    runtmp.sourmash(
+        "scripts",
+        "fastgather",
+        query,
+        against_list,
+        "-o",
+        g_output,
+        "-s",
+        "100000",
+        "--exclude",
+        exclude_mf,
+    )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "Excluded 1 against sketches" in captured.err
+
+    df = pandas.read_csv(g_output)
+    assert len(df) == 2
+    assert not any(df["match_name"].str.startswith("NC_009661.1"))
+
+
+def test_exclude_picklist(runtmp, capfd):
+    # --exclude also takes a picklist of values to drop
+    query = get_test_data("SRR606249.sig.gz")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    picklist = runtmp.output("exclude.csv")
+    with open(picklist, "w", newline="") as fp:
+        w = csv.writer(fp)
+        w.writerow(["ident"])
+        w.writerow(["NC_009661.1"])
+        w.writerow(["NC_011665.1"])
+
+    g_output = runtmp.output("gather.csv")
+    runtmp.sourmash(
+        "scripts",
+        "fastgather",
+        query,
+        against_list,
+        "-o",
+        g_output,
+        "-s",
+        "100000",
+        "--exclude",
+        f"{picklist}:ident:ident",
+    )
+
+    df = pandas.read_csv(g_output)
+    assert len(df) == 1
+    assert df["match_name"][0].startswith("CP001071.1")
+
+
+def test_no_results_header(runtmp):
+    # with no matches above threshold, both outputs still have headers
+    query = get_test_data("47.fa.sig.gz")
+    against_list = runtmp.output("against.txt")
+    make_file_list(against_list, [get_test_data("63.fa.sig.gz")])
+
+    g_output = runtmp.output("gather.csv")
+    p_output = runtmp.output("prefetch.csv")
+    args = [
+        "scripts",
+        "fastgather",
+        query,
+        against_list,
+        "-o",
+        g_output,
+        "--output-prefetch",
+        p_output,
+        "--threshold-bp",
+        "100000000",
+    ]
+    runtmp.sourmash(*args)
+
+    gather = pandas.read_csv(g_output)
+    assert len(gather) == 0
+    assert "match_name" in gather.columns
+    prefetch = pandas.read_csv(p_output)
+    assert len(prefetch) == 0
+    assert "match_name" in prefetch.columns
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(*args, "--detailed-exit-codes", "--force")
+    assert runtmp.last_result.status == 3
+
+
+def test_stoplist(runtmp, capfd):
+    # a --stoplist removes hashes from the query before gather
+    query = get_test_data("SRR606249.sig.gz")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    g_output = runtmp.output("gather.csv")
+    runtmp.sourmash(
+        "scripts",
+        "fastgather",
+        query,
+        against_list,
+        "-o",
+        g_output,
+        "-s",
+        "100000",
+        "--stoplist",
+        sig47,
+    )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "stoplist hashes from 1 of 1 queries" in captured.err
+
+    df = pandas.read_csv(g_output)
+    assert not any(df["match_name"].str.startswith("NC_009661.1"))
+    assert any(df["match_name"].str.startswith("CP001071.1"))
+
+
+def test_sourmash_compat(runtmp):
+    # --sourmash-compat writes the columns of 'sourmash gather', so that
+    # the output can be read by 'sourmash tax'.
+    query = get_test_data("SRR606249.sig.gz")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    g_output = runtmp.output("gather.csv")
+    runtmp.sourmash(
+        "scripts",
+        "fastgather",
+        query,
+        against_list,
+        "-o",
+        g_output,
+        "-s",
+        "100000",
+        "--sourmash-compat",
+    )
+
+    sg_output = runtmp.output("sourmash-gather.csv")
+    runtmp.sourmash(
+        "gather", query, against_list, "-o", sg_output, "--scaled", "100000"
+    )
+
+    df = pandas.read_csv(g_output)
+    sg_df = pandas.read_csv(sg_output)
+    assert list(df.columns) == list(sg_df.columns)
+    assert set(df["name"]) == set(sg_df["name"])
+    assert set(df["intersect_bp"]) == set(sg_df["intersect_bp"])
+    assert not any(df["potential_false_negative"])
+
+    lineages = runtmp.output("lineages.csv")
+    with open(lineages, "wt") as fp:
+        fp.write("ident,superkingdom,phylum,class\n")
+        fp.write("CP001071,d__Bacteria,p__Verrucomicrobiota,c__Verrucomicrobiae\n")
+        fp.write("NC_009661,d__Bacteria,p__Proteobacteria,c__Gammaproteobacteria\n")
+        fp.write("NC_011665,d__Bacteria,p__Proteobacteria,c__Gammaproteobacteria\n")
+
+    runtmp.sourmash(
+        "tax",
+        "metagenome",
+        "-g",
+        g_output,
+        "-t",
+        lineages,
+        "-o",
+        "compat",
+        "--output-dir",
+        runtmp.output(""),
+    )
+    summary = pandas.read_csv(runtmp.output("compat.summarized.csv"))
+    classes = summary[summary["rank"] == "class"]
+    assert "c__Gammaproteobacteria" in set(classes["lineage"].str.split(";").str[-1])
+
+
+def test_sourmash_compat_with_output_columns(runtmp):
+    # --sourmash-compat sets the columns, so can't be used with a selection.
+    query = get_test_data("SRR606249.sig.gz")
+    against_list = runtmp.output("against.txt")
+    make_file_list(against_list, [get_test_data("47.fa.sig.gz")])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "fastgather",
+            query,
+            against_list,
+            "-o",
+            runtmp.output("gather.csv"),
+            "-s",
+            "100000",
+            "--sourmash-compat",
+            "--output-columns",
+            "match_name",
+        )
+
+    assert "cannot be used with --output-columns" in runtmp.last_result.err
@@ -71,6 +71,7 @@ def test_simple(runtmp, zip_against):
         "match_name",
         "match_md5",
         "intersect_bp",
+        "intersect_hashes",
     }
 
     assert os.path.exists(g_output)
@@ -133,6 +134,7 @@ def test_simple_list_of_zips(runtmp):
         "match_name",
         "match_md5",
         "intersect_bp",
+        "intersect_hashes",
     }
 
     assert os.path.exists(g_output)
@@ -235,6 +237,7 @@ def test_simple_zip_query(runtmp):
         "match_name",
         "match_md5",
         "intersect_bp",
+        "intersect_hashes",
     }
 
     assert os.path.exists(g_output)
@@ -299,6 +302,7 @@ def test_simple_read_manifests(runtmp):
         "match_name",
         "match_md5",
         "intersect_bp",
+        "intersect_hashes",
     }
 
     assert os.path.exists(g_output)
@@ -366,6 +370,7 @@ def test_simple_indexed(runtmp, zip_query, toggle_internal_storage):
         "query_abundance",
         "match_containment_ani",
         "intersect_bp",
+        "intersect_hashes",
         "total_weighted_hashes",
         "n_unique_weighted_found",
         "query_name",
@@ -439,6 +444,7 @@ def test_simple_indexed_query_manifest(runtmp, toggle_internal_storage):
         "query_abundance",
         "match_containment_ani",
         "intersect_bp",
+        "intersect_hashes",
         "total_weighted_hashes",
         "n_unique_weighted_found",
         "query_name",
@@ -812,6 +818,7 @@ def test_md5(runtmp, zip_query):
         "match_name",
         "match_md5",
         "intersect_bp",
+        "intersect_hashes",
     }
 
     md5s = set(df["match_md5"])
@@ -885,6 +892,7 @@ def test_md5_indexed(runtmp, zip_query):
         "query_abundance",
         "match_containment_ani",
         "intersect_bp",
+        "intersect_hashes",
         "total_weighted_hashes",
         "n_unique_weighted_found",
         "query_name",
@@ -982,6 +990,7 @@ def test_csv_columns_vs_sourmash_prefetch(runtmp, zip_query, zip_against):
             "f_match_orig",
             "f_unique_weighted",
             "average_abund",
+            "intersect_hashes",
             "unique_intersect_bp",
             "std_abund",
             "sum_weighted_found",
@@ -1041,6 +1050,7 @@ def test_csv_columns_vs_sourmash_gather_fullresults(runtmp):
         "query_abundance",
         "match_containment_ani",
         "intersect_bp",
+        "intersect_hashes",
         "total_weighted_hashes",
         "n_unique_weighted_found",
         "query_name",
@@ -1073,6 +1083,7 @@ def test_csv_columns_vs_sourmash_gather_fullresults(runtmp):
     sg_keys.update(
         modified_keys
     )  # fastmultigather is more explicit (match_md5 instead of md5, etc)
+    sg_keys.add("intersect_hashes")  # not reported by sourmash gather
     print("g_keys - sg_keys:", g_keys - sg_keys)
     assert not g_keys - sg_keys, g_keys - sg_keys
 
@@ -1124,6 +1135,7 @@ def test_csv_columns_vs_sourmash_gather_indexed(runtmp):
         "query_abundance",
         "match_containment_ani",
         "intersect_bp",
+        "intersect_hashes",
         "total_weighted_hashes",
         "n_unique_weighted_found",
         "query_name",
@@ -1156,6 +1168,7 @@ def test_csv_columns_vs_sourmash_gather_indexed(runtmp):
     sg_keys.update(
         modified_keys
     )  # fastmultigather is more explicit (match_md5 instead of md5, etc)
+    sg_keys.add("intersect_hashes")  # not reported by sourmash gather
     print("g_keys - sg_keys:", g_keys - sg_keys)
     assert not g_keys - sg_keys, g_keys - sg_keys
 
@@ -1333,6 +1346,7 @@ def test_simple_protein_indexed(runtmp):
         "query_abundance",
         "match_containment_ani",
         "intersect_bp",
+        "intersect_hashes",
         "total_weighted_hashes",
         "n_unique_weighted_found",
         "query_name",
@@ -1402,6 +1416,7 @@ def test_simple_dayhoff_indexed(runtmp):
         "query_abundance",
         "match_containment_ani",
         "intersect_bp",
+        "intersect_hashes",
         "total_weighted_hashes",
         "n_unique_weighted_found",
         "query_name",
@@ -1471,6 +1486,7 @@ def test_simple_hp_indexed(runtmp):
         "query_abundance",
         "match_containment_ani",
         "intersect_bp",
+        "intersect_hashes",
         "total_weighted_hashes",
         "n_unique_weighted_found",
         "query_name",
@@ -1544,6 +1560,7 @@ def test_indexed_full_output(runtmp):
         "query_abundance",
         "match_containment_ani",
         "intersect_bp",
+        "intersect_hashes",
         "total_weighted_hashes",
         "n_unique_weighted_found",
         "query_name",
@@ -1629,6 +1646,7 @@ def test_nonindexed_full_vs_sourmash_gather(runtmp):
     sg_keys.update(
         modified_keys
     )  # fastmultigather is more explicit (match_md5 instead of md5, etc)
+    sg_keys.add("intersect_hashes")  # not reported by sourmash gather
     print("g_keys - sg_keys:", g_keys - sg_keys)
     assert not g_keys - sg_keys, g_keys - sg_keys
 
@@ -1894,6 +1912,7 @@ def test_save_matches(runtmp):
         "match_name",
         "match_md5",
         "intersect_bp",
+        "intersect_hashes",
     }
 
     assert os.path.exists(g_output)
@@ -1925,6 +1944,163 @@ def test_save_matches(runtmp):
     assert mg_ss.minhash.contained_by(match_mh) < 1
 
 
+def test_save_matches_and_unassigned(runtmp, indexed):
+    # matched and unassigned hashes split the query, on both databases
+    query = get_test_data("SRR606249.sig.gz")
+    against_list = runtmp.output("against.txt")
+    make_file_list(
+        against_list,
+        [get_test_data(f) for f in ("2.fa.sig.gz", "47.fa.sig.gz", "63.fa.sig.gz")],
+    )
+    if indexed:
+        against_list = index_siglist(
+            runtmp, against_list, runtmp.output("against.rocksdb"), scaled=100000
+        )
+
+    runtmp.sourmash(
+        "scripts",
+        "fastmultigather",
+        query,
+        against_list,
+        "-s",
+        "100000",
+        "-t",
+        "0",
+        "--save-matches",
+        "--save-unassigned",
+        "--output-dir",
+        "out",
+        "-o",
+        runtmp.output("out.csv"),
+        in_directory=runtmp.output(""),
+    )
+
+    m_output = runtmp.output("out/SRR606249.matches.sig")
+    u_output = runtmp.output("out/SRR606249.unassigned.sig")
+    match_mh = sourmash.load_one_signature(m_output, ksize=31).minhash
+    unassigned_mh = sourmash.load_one_signature(u_output, ksize=31).minhash
+
+    query_mh = sourmash.load_one_signature(query, ksize=31).minhash
+    query_mh = query_mh.downsample(scaled=100000)
+
+    assert len(match_mh) > 0
+    assert len(unassigned_mh) > 0
+    assert not set(match_mh.hashes) & set(unassigned_mh.hashes)
+    assert set(match_mh.hashes) | set(unassigned_mh.hashes) == set(query_mh.hashes)
+
+    # unassigned hashes keep their abundances.
+    assert unassigned_mh.track_abundance
+    for hashval, abund in unassigned_mh.hashes.items():
+        assert query_mh.hashes[hashval] == abund
+
+
+def test_save_unassigned_exists(runtmp, indexed):
+    # don't overwrite earlier unassigned outputs without --force
+    query = get_test_data("SRR606249.sig.gz")
+    against_list = runtmp.output("against.txt")
+    make_file_list(against_list, [get_test_data("47.fa.sig.gz")])
+    if indexed:
+        against_list = index_siglist(
+            runtmp, against_list, runtmp.output("against.rocksdb"), scaled=100000
+        )
+
+    with open(runtmp.output("SRR606249.unassigned.sig"), "w") as fp:
+        fp.write("keep me")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "fastmultigather",
+            query,
+            against_list,
+            "-s",
+            "100000",
+            "--save-unassigned",
+            "-o",
+            "out.csv",
+            in_directory=runtmp.output(""),
+        )
+
+    assert "already exists; use --force to overwrite" in runtmp.last_result.err
+    with open(runtmp.output("SRR606249.unassigned.sig")) as fp:
+        assert fp.read() == "keep me"
+
+
+def test_weighted_columns_indexed(runtmp):
+    # the abundance-weighted columns come from the query, so they are the
+    # same against a RocksDB index as against the sketches.
+    query = get_test_data("SRR606249.sig.gz")
+    against_list = runtmp.output("against.txt")
+    make_file_list(
+        against_list,
+        [get_test_data(f) for f in ("2.fa.sig.gz", "47.fa.sig.gz", "63.fa.sig.gz")],
+    )
+    db = index_siglist(runtmp, against_list, runtmp.output("db"), scaled=100000)
+
+    args = ["-s", "100000", "-t", "0"]
+    plain_out = runtmp.output("plain.csv")
+    runtmp.sourmash(
+        "scripts",
+        "fastmultigather",
+        query,
+        against_list,
+        "-o",
+        plain_out,
+        *args,
+        in_directory=runtmp.output(""),
+    )
+    indexed_out = runtmp.output("indexed.csv")
+    runtmp.sourmash(
+        "scripts",
+        "fastmultigather",
+        query,
+        db,
+        "-o",
+        indexed_out,
+        *args,
+        in_directory=runtmp.output(""),
+    )
+
+    plain = pandas.read_csv(plain_out).set_index("match_md5")
+    indexed = pandas.read_csv(indexed_out).set_index("match_md5")
+    assert len(plain) == len(indexed) == 3
+    assert plain["f_unique_weighted"].notnull().all()
+    for col in [
+        "f_unique_weighted",
+        "average_abund",
+        "median_abund",
+        "n_unique_weighted_found",
+        "total_weighted_hashes",
+    ]:
+        for key in plain.index:
+            assert indexed.loc[key, col] == pytest.approx(plain.loc[key, col])
+
+
+def test_indexed_moltype_mismatch(runtmp):
+    # gathering against a protein index with DNA queries fails clearly
+    protsigs = get_test_data("protein.zip")
+    protsigs_db = index_siglist(
+        runtmp, protsigs, runtmp.output("db"), ksize=19, moltype="protein", scaled=100
+    )
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "fastmultigather",
+            protsigs,
+            protsigs_db,
+            "-k",
+            "19",
+            "-s",
+            "100",
+            "-o",
+            runtmp.output("out.csv"),
+        )
+
+    assert "database has protein (k=19) sketches" in runtmp.last_result.err
+    assert "use --moltype protein" in runtmp.last_result.err
+
+
 def test_create_empty_prefetch_results(runtmp):
     # sig2 has 0 hashes in common with 47 and 63
     sig2 = get_test_data("2.fa.sig.gz")
@@ -2229,6 +2405,7 @@ def test_rocksdb_v0_9_5(runtmp):
         "out.csv",
         "-s",
         "100_000",
+        "--force",
         in_dir=runtmp.output(""),
     )
 
@@ -2290,3 +2467,844 @@ def test_rocksdb_v0_9_13_external(runtmp):
     )
 
     assert os.path.exists(runtmp.output("out.csv"))
+
+
+def _write_lineages(filename):
+    # NC_011665.1 (from 63.fa) is deliberately left out.
+    with open(filename, "wt") as fp:
+        fp.write("ident,superkingdom,phylum,class,order,family,genus,species\n")
+        fp.write(
+            "CP001071,d__Bacteria,p__Verrucomicrobiota,c__Verrucomicrobiae,o__Verrucomicrobiales,f__Akkermansiaceae,g__Akkermansia,s__Akkermansia muciniphila\n"
+        )
+        fp.write(
+            "NC_009661.1,d__Bacteria,p__Proteobacteria,c__Gammaproteobacteria,o__Enterobacterales,f__Shewanellaceae,g__Shewanella,s__Shewanella baltica\n"
+        )
+
+
+@pytest.mark.parametrize("indexed", [False, True])
+def test_taxonomy_summary(runtmp, capfd, indexed):
+    # summarize gather results by rank with --taxonomy
+    query = get_test_data("SRR606249.sig.gz")
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    make_file_list(query_list, [query])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    if indexed:
+        against_list = index_siglist(
+            runtmp, against_list, runtmp.output("test.rocksdb")
+        )
+
+    lineages = runtmp.output("lineages.csv")
+    _write_lineages(lineages)
+
+    g_output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts",
+        "fastmultigather",
+        query_list,
+        against_list,
+        "-s",
+        "100000",
+        "-t",
+        "0",
+        "-o",
+        g_output,
+        "--taxonomy",
+        lineages,
+        in_directory=runtmp.output(""),
+    )
+
+    gather_df = pandas.read_csv(g_output)
+    assert len(gather_df) == 3
+
+    summary = runtmp.output("out.summarized.csv")
+    assert os.path.exists(summary)
+    df = pandas.read_csv(summary)
+    assert list(df.columns) == [
+        "query_name",
+        "rank",
+        "fraction",
+        "lineage",
+        "query_md5",
+        "query_filename",
+        "f_weighted_at_rank",
+        "bp_match_at_rank",
+        "total_weighted_hashes",
+    ]
+
+    # two classified lineages + unclassified at each of 7 ranks
+    assert set(df["rank"]) == {
+        "superkingdom",
+        "phylum",
+        "class",
+        "order",
+        "family",
+        "genus",
+        "species",
+    }
+    sk = df[df["rank"] == "superkingdom"]
+    assert list(sk["lineage"]) == ["d__Bacteria", "unclassified"]
+    assert abs(sk["fraction"].sum() - 1.0) < 1e-6
+
+    # superkingdom sums the two matches that have lineages
+    classified = gather_df[~gather_df["match_name"].str.startswith("NC_011665")]
+    assert abs(sk["fraction"].iloc[0] - classified["f_unique_to_query"].sum()) < 1e-6
+
+    species = df[df["rank"] == "species"]
+    assert len(species) == 3
+    assert species["lineage"].iloc[2] == "unclassified"
+    assert set(species["lineage"].str.split(";").str[-1]) == {
+        "s__Akkermansia muciniphila",
+        "s__Shewanella baltica",
+        "unclassified",
+    }
+
+    captured = capfd.readouterr()
+    assert "no lineage found for 'NC_011665.1" in captured.err
+
+
+def test_taxonomy_summary_explicit_output(runtmp):
+    # --tax-summary-output overrides the default name
+    query = get_test_data("SRR606249.sig.gz")
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    make_file_list(query_list, [query])
+    make_file_list(against_list, [sig2, sig47])
+
+    lineages = runtmp.output("lineages.csv")
+    _write_lineages(lineages)
+
+    summary = runtmp.output("tax.csv")
+    runtmp.sourmash(
+        "scripts",
+        "fastmultigather",
+        query_list,
+        against_list,
+        "-s",
+        "100000",
+        "-t",
+        "0",
+        "-o",
+        runtmp.output("out.csv"),
+        "--taxonomy",
+        lineages,
+        "--tax-summary-output",
+        summary,
+        in_directory=runtmp.output(""),
+    )
+
+    df = pandas.read_csv(summary)
+    genus = df[df["rank"] == "genus"]
+    assert set(genus["lineage"].str.split(";").str[-1]) == {
+        "g__Akkermansia",
+        "g__Shewanella",
+        "unclassified",
+    }
+
+
+def test_taxonomy_bad_lineages(runtmp, capfd):
+    # a lineages file without an ident column is an error
+    query = get_test_data("SRR606249.sig.gz")
+    sig2 = get_test_data("2.fa.sig.gz")
+
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+    make_file_list(query_list, [query])
+    make_file_list(against_list, [sig2])
+
+    lineages = runtmp.output("lineages.csv")
+    with open(lineages, "wt") as fp:
+        fp.write("name,species\nfoo,s__bar\n")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "fastmultigather",
+            query_list,
+            against_list,
+            "-s",
+            "100000",
+            "-o",
+            runtmp.output("out.csv"),
+            "--taxonomy",
+            lineages,
+            in_directory=runtmp.output(""),
+        )
+
+    captured = capfd.readouterr()
+    assert "has no identifier column" in captured.err
+
+
+@pytest.mark.parametrize("indexed", [False, True])
+def test_taxonomy_cami(runtmp, indexed):
+    # write a CAMI profile, using taxids from the 'taxpath' column
+    query = get_test_data("SRR606249.sig.gz")
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    make_file_list(query_list, [query])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    if indexed:
+        against_list = index_siglist(
+            runtmp, against_list, runtmp.output("test.rocksdb")
+        )
+
+    lineages = runtmp.output("lineages.csv")
+    with open(lineages, "wt") as fp:
+        fp.write("ident,taxpath,superkingdom,genus,species\n")
+        fp.write("CP001071.1,2|239934|239935,Bacteria,Akkermansia,muciniphila\n")
+        fp.write("NC_009661.1,2|22|62322,Bacteria,Shewanella,baltica\n")
+
+    cami = runtmp.output("out.cami.tsv")
+    runtmp.sourmash(
+        "scripts",
+        "fastmultigather",
+        query_list,
+        against_list,
+        "-s",
+        "100000",
+        "-t",
+        "0",
+        "--taxonomy",
+        lineages,
+        "--output-cami",
+        cami,
+        "-o",
+        runtmp.output("out.csv"),
+        in_directory=runtmp.output(""),
+    )
+
+    with open(cami) as fp:
+        lines = fp.read().splitlines()
+
+    print("\n".join(lines))
+    assert lines[0].startswith("@SampleID:")
+    assert "@Ranks:superkingdom|genus|species" in lines
+    assert "@@TAXID\tRANK\tTAXPATH\tTAXPATHSN\tPERCENTAGE" in lines
+
+    rows = [line.split("\t") for line in lines if line and not line.startswith("@")]
+    assert len(rows) == 5
+    assert rows[0][:4] == ["2", "superkingdom", "2", "Bacteria"]
+
+    species = {r[0]: r for r in rows if r[1] == "species"}
+    assert set(species) == {"239935", "62322"}
+    assert species["62322"][2] == "2|22|62322"
+    assert species["62322"][3] == "Bacteria|Shewanella|baltica"
+
+    # percentages are abundance-weighted, and sum to no more than 100
+    total = sum(float(r[4]) for r in rows if r[1] == "species")
+    assert 0 < total <= 100
+
+
+def test_taxonomy_cami_requires_taxonomy(runtmp):
+    query = get_test_data("SRR606249.sig.gz")
+    sig2 = get_test_data("2.fa.sig.gz")
+
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+    make_file_list(query_list, [query])
+    make_file_list(against_list, [sig2])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "fastmultigather",
+            query_list,
+            against_list,
+            "-o",
+            runtmp.output("out.csv"),
+            "--output-cami",
+            runtmp.output("out.cami.tsv"),
+            in_directory=runtmp.output(""),
+        )
+
+    assert "require --taxonomy" in runtmp.last_result.err
+
+
+def test_taxonomy_kraken_multiple_queries(runtmp):
+    # with several queries, one kraken report is written per query
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    make_file_list(query_list, [sig2, sig47])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    lineages = runtmp.output("lineages.csv")
+    _write_lineages(lineages)
+
+    runtmp.sourmash(
+        "scripts",
+        "fastmultigather",
+        query_list,
+        against_list,
+        "-t",
+        "0",
+        "--taxonomy",
+        lineages,
+        "--output-kraken",
+        runtmp.output("out.kreport"),
+        "-o",
+        runtmp.output("out.csv"),
+        in_directory=runtmp.output(""),
+    )
+
+    assert not os.path.exists(runtmp.output("out.kreport"))
+    for name in [
+        "CP001071.1 Akkermansia muciniphila ATCC BAA-835, complete genome",
+        "NC_009661.1 Shewanella baltica OS185 plasmid pS18501, complete sequence",
+    ]:
+        kreport = runtmp.output(f"out.{name}.kreport")
+        assert os.path.exists(kreport)
+        with open(kreport) as fp:
+            rows = [line.split("\t") for line in fp.read().splitlines()]
+        assert "R" in [r[3] for r in rows]
+        assert rows[-1][3] == "S"
+
+
+def run_output_dir(runtmp, *extra):
+    query = get_test_data("SRR606249.sig.gz")
+    against_list = runtmp.output("against.txt")
+    make_file_list(
+        against_list,
+        [get_test_data(f) for f in ("2.fa.sig.gz", "47.fa.sig.gz", "63.fa.sig.gz")],
+    )
+
+    runtmp.sourmash(
+        "scripts",
+        "fastmultigather",
+        query,
+        against_list,
+        "-s",
+        "100000",
+        "-t",
+        "0",
+        "--save-matches",
+        "-o",
+        runtmp.output("out.csv"),
+        *extra,
+        in_directory=runtmp.output(""),
+    )
+
+
+def test_output_dir(runtmp):
+    # per-query outputs go into --output-dir, which is created as needed
+    run_output_dir(runtmp, "--output-dir", "per-query/results")
+
+    outdir = runtmp.output("per-query/results")
+    assert sorted(os.listdir(outdir)) == [
+        "SRR606249.matches.sig",
+        "SRR606249.prefetch.csv",
+    ]
+    assert not os.path.exists(runtmp.output("SRR606249.prefetch.csv"))
+    assert os.path.exists(runtmp.output("out.csv"))
+
+    df = pandas.read_csv(os.path.join(outdir, "SRR606249.prefetch.csv"))
+    assert len(df) == 3
+
+
+@pytest.mark.parametrize(
+    "template,basename",
+    [
+        ("{md5}", "dec29ca72e68db0f15de0b1b46f82fc5"),
+        ("{md5short}", "dec29ca7"),
+        ("sample.{ident}.{md5short}", "sample.SRR606249.dec29ca7"),
+    ],
+)
+def test_output_template(runtmp, template, basename):
+    run_output_dir(runtmp, "--output-dir", "out", "--output-template", template)
+
+    assert sorted(os.listdir(runtmp.output("out"))) == [
+        f"{basename}.matches.sig",
+        f"{basename}.prefetch.csv",
+    ]
+
+
+def test_output_template_name_with_slash(runtmp):
+    # '/' in query names must not create subdirectories
+    query = runtmp.output("query.sig")
+    runtmp.sourmash(
+        "sig", "rename", get_test_data("SRR606249.sig.gz"), "a/b c", "-o", query
+    )
+
+    runtmp.sourmash(
+        "scripts",
+        "fastmultigather",
+        query,
+        get_test_data("63.fa.sig.gz"),
+        "-s",
+        "100000",
+        "-t",
+        "0",
+        "-o",
+        runtmp.output("out.csv"),
+        "--output-dir",
+        "out",
+        "--output-template",
+        "{name}",
+        in_directory=runtmp.output(""),
+    )
+
+    assert os.listdir(runtmp.output("out")) == ["a_b c.prefetch.csv"]
+
+
+@pytest.mark.parametrize("template", ["{filename}", "{name", "dir/{md5}"])
+def test_output_template_bad(runtmp, capfd, template):
+    with pytest.raises(utils.SourmashCommandFailed):
+        run_output_dir(runtmp, "--output-template", template)
+
+    captured = capfd.readouterr()
+    assert "output template" in captured.err
+
+
+@pytest.mark.parametrize("save_matches", [False, True])
+def test_shared_prefetch(runtmp, save_matches):
+    # --shared-prefetch gives the same outputs as the default prefetch
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+    sigs = [get_test_data(f) for f in ("2.fa.sig.gz", "47.fa.sig.gz", "63.fa.sig.gz")]
+    make_file_list(query_list, sigs + [get_test_data("SRR606249.sig.gz")])
+    make_file_list(against_list, sigs)
+
+    extra = ["--save-matches"] if save_matches else []
+    for outdir, flags in [("default", []), ("shared", ["--shared-prefetch"])]:
+        runtmp.sourmash(
+            "scripts",
+            "fastmultigather",
+            query_list,
+            against_list,
+            "-s",
+            "100000",
+            "-t",
+            "0",
+            "-o",
+            runtmp.output(f"{outdir}.gather.csv"),
+            "--output-dir",
+            outdir,
+            "--output-template",
+            "{md5}",
+            *extra,
+            *flags,
+            in_directory=runtmp.output(""),
+        )
+
+    default_files = sorted(os.listdir(runtmp.output("default")))
+    assert default_files == sorted(os.listdir(runtmp.output("shared")))
+    assert len([f for f in default_files if f.endswith(".prefetch.csv")]) == 4
+
+    sort_cols = ["query_md5", "gather_result_rank"]
+    default = pandas.read_csv(runtmp.output("default.gather.csv"))
+    shared = pandas.read_csv(runtmp.output("shared.gather.csv"))
+    default = default.sort_values(sort_cols).reset_index(drop=True)
+    shared = shared.sort_values(sort_cols).reset_index(drop=True)
+    pandas.testing.assert_frame_equal(default, shared)
+
+    sort_cols = ["query_md5", "match_md5"]
+    for filename in default_files:
+        if filename.endswith(".csv"):
+            default = pandas.read_csv(runtmp.output(f"default/{filename}"))
+            shared = pandas.read_csv(runtmp.output(f"shared/{filename}"))
+            default = default.sort_values(sort_cols).reset_index(drop=True)
+            shared = shared.sort_values(sort_cols).reset_index(drop=True)
+            pandas.testing.assert_frame_equal(default, shared)
+        elif filename.endswith(".matches.sig"):
+            default = sourmash.load_one_signature(runtmp.output(f"default/{filename}"))
+            shared = sourmash.load_one_signature(runtmp.output(f"shared/{filename}"))
+            assert default.minhash.hashes == shared.minhash.hashes
+
+
+@pytest.mark.parametrize(
+    "threshold_args,expected",
+    [
+        (["--threshold-hashes", "30"], {44, 41}),
+        (["--threshold-fraction", "0.01"], {44}),
+    ],
+)
+def test_threshold_units(runtmp, threshold_args, expected):
+    # thresholds in hashes and fractions of the query all work
+    against_list = runtmp.output("against.txt")
+    make_file_list(
+        against_list,
+        [get_test_data(f) for f in ("2.fa.sig.gz", "47.fa.sig.gz", "63.fa.sig.gz")],
+    )
+
+    runtmp.sourmash(
+        "scripts",
+        "fastmultigather",
+        get_test_data("SRR606249.sig.gz"),
+        against_list,
+        "-s",
+        "100000",
+        "-o",
+        runtmp.output("gather.csv"),
+        *threshold_args,
+        in_directory=runtmp.output(""),
+    )
+
+    df = pandas.read_csv(runtmp.output("SRR606249.prefetch.csv"))
+    assert set(df["intersect_hashes"]) == expected
+
+
+@pytest.mark.parametrize("indexed", [False, True])
+def test_max_matches(runtmp, indexed):
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+    make_file_list(query_list, [get_test_data("SRR606249.sig.gz")])
+    make_file_list(
+        against_list,
+        [get_test_data(f) for f in ("2.fa.sig.gz", "47.fa.sig.gz", "63.fa.sig.gz")],
+    )
+    if indexed:
+        against_list = index_siglist(runtmp, against_list, runtmp.output("db"))
+
+    outputs = {}
+    for name, extra in [("full", []), ("stopped", ["--max-matches", "2"])]:
+        outputs[name] = runtmp.output(f"{name}.csv")
+        runtmp.sourmash(
+            "scripts",
+            "fastmultigather",
+            query_list,
+            against_list,
+            "-s",
+            "100000",
+            "-t",
+            "0",
+            "-o",
+            outputs[name],
+            *extra,
+            in_directory=runtmp.output(""),
+        )
+
+    full = pandas.read_csv(outputs["full"]).sort_values("gather_result_rank")
+    stopped = pandas.read_csv(outputs["stopped"]).sort_values("gather_result_rank")
+    assert len(full) == 3
+    assert list(stopped["match_md5"]) == list(full["match_md5"])[:2]
+
+
+def test_skipped_paths_out(runtmp):
+    # against sketches with no compatible ksize are reported as skipped
+    query = get_test_data("SRR606249.sig.gz")
+    sig2_k21 = get_test_data("2.fa.k21.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    make_file_list(query_list, [query])
+    make_file_list(against_list, [sig2_k21, sig47, sig63])
+
+    skipped = runtmp.output("skipped.csv")
+    runtmp.sourmash(
+        "scripts",
+        "fastmultigather",
+        query_list,
+        against_list,
+        "-s",
+        "100000",
+        "-t",
+        "0",
+        "-o",
+        runtmp.output("out.csv"),
+        "--skipped-paths-out",
+        skipped,
+        in_directory=runtmp.output(""),
+    )
+
+    df = pandas.read_csv(skipped)
+    print(df)
+    assert list(df["path"]) == [sig2_k21]
+
+
+def test_prefetch_output_exists(runtmp, capfd):
+    # refuse to overwrite per-query prefetch output without --force
+    query = get_test_data("SRR606249.sig.gz")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    p_output = runtmp.output("SRR606249.prefetch.csv")
+    with open(p_output, "w") as fp:
+        fp.write("precious")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "fastmultigather",
+            query,
+            against_list,
+            "-s",
+            "100000",
+            "-t",
+            "0",
+            "-o",
+            runtmp.output("gather.csv"),
+            in_directory=runtmp.output(""),
+        )
+
+    captured = capfd.readouterr()
+    assert "SRR606249.prefetch.csv' already exists" in captured.err
+    with open(p_output) as fp:
+        assert fp.read() == "precious"
+
+    runtmp.sourmash(
+        "scripts",
+        "fastmultigather",
+        query,
+        against_list,
+        "-s",
+        "100000",
+        "-t",
+        "0",
+        "-o",
+        runtmp.output("gather.csv"),
+        "--force",
+        in_directory=runtmp.output(""),
+    )
+
+    df = pandas.read_csv(p_output)
+    assert len(df) == 3
+
+
+def test_search_mode_in_memory(runtmp):
+    # load the sketches from a RocksDB index rather than searching it
+    query = get_test_data("SRR606249.sig.gz")
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    against_list = runtmp.output("against.txt")
+    make_file_list(against_list, [sig2, sig47, sig63])
+    against_db = index_siglist(runtmp, against_list, runtmp.output("db"))
+
+    g_output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts",
+        "fastmultigather",
+        query,
+        against_db,
+        "-s",
+        "100000",
+        "-t",
+        "0",
+        "-o",
+        g_output,
+        "--search-mode",
+        "in-memory",
+        in_directory=runtmp.output(""),
+    )
+
+    df = pandas.read_csv(g_output)
+    assert len(df) == 3
+    # only in-memory gather writes per-query prefetch output
+    assert os.path.exists(runtmp.output("SRR606249.prefetch.csv"))
+
+
+def test_search_mode_rocksdb_not_index(runtmp, capfd):
+    # asking for a RocksDB search of something else is an error
+    query = get_test_data("SRR606249.sig.gz")
+    sig2 = get_test_data("2.fa.sig.gz")
+
+    against_list = runtmp.output("against.txt")
+    make_file_list(against_list, [sig2])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "fastmultigather",
+            query,
+            against_list,
+            "-o",
+            runtmp.output("out.csv"),
+            "--search-mode",
+            "rocksdb",
+        )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "search mode 'rocksdb' requires a RocksDB index" in captured.err
+
+
+def test_exclude_rocksdb(runtmp, capfd):
+    # --exclude is not supported for RocksDB databases
+    query = get_test_data("SRR606249.sig.gz")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+
+    make_file_list(against_list, [sig2, sig47])
+    db = index_siglist(runtmp, against_list, runtmp.output("db"))
+
+    exclude_mf = runtmp.output("exclude.csv")
+    runtmp.sourmash("sig", "manifest", sig47, "-o", exclude_mf)
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "fastmultigather",
+            query,
+            db,
+            "-s",
+            "100000",
+            "-o",
+            runtmp.output("out.csv"),
+            "--exclude",
+            exclude_mf,
+        )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "--exclude is not supported for RocksDB databases" in captured.err
+
+
+def test_per_query_scaled(runtmp):
+    # each query is gathered at its own scaled, if higher than the against
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    query = get_test_data("SRR606249.sig.gz")
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [query, sig47])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    g_output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts",
+        "fastmultigather",
+        query_list,
+        against_list,
+        "-t",
+        "0",
+        "-o",
+        g_output,
+        "--per-query-scaled",
+        in_directory=runtmp.output(""),
+    )
+
+    df = pandas.read_csv(g_output)
+    scaled = dict(zip(df["query_name"], df["scaled"]))
+    name47 = sourmash.load_one_signature(sig47, ksize=31).name
+    assert scaled["SRR606249"] == 100000
+    assert scaled[name47] == 1000
+    assert len(df[df["query_name"] == "SRR606249"]) == 3
+
+    # the 47 query is found in full at its own scaled
+    first = df[df["query_name"] == name47].sort_values("gather_result_rank").iloc[0]
+    assert first["match_name"] == name47
+    assert first["f_orig_query"] == 1.0
+
+
+def test_refine_from(runtmp):
+    # two-stage gather gives the same results as gather on the index
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    query = get_test_data("SRR606249.sig.gz")
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [query])
+    make_file_list(against_list, [sig2, sig47, sig63])
+    against_db = index_siglist(runtmp, against_list, runtmp.output("db"))
+
+    outputs = {}
+    for name, extra in (("index", []), ("refined", ["--refine-from", against_list])):
+        outputs[name] = runtmp.output(f"{name}.csv")
+        runtmp.sourmash(
+            "scripts",
+            "fastmultigather",
+            query_list,
+            against_db,
+            "-s",
+            "100000",
+            "-t",
+            "0",
+            "-o",
+            outputs[name],
+            *extra,
+        )
+
+    keys = ["gather_result_rank", "match_md5", "intersect_bp", "unique_intersect_bp"]
+    index_df = pandas.read_csv(outputs["index"]).sort_values("gather_result_rank")
+    refined_df = pandas.read_csv(outputs["refined"]).sort_values("gather_result_rank")
+    assert len(refined_df) == 3
+    assert refined_df[keys].values.tolist() == index_df[keys].values.tolist()
+
+
+def test_refine_from_missing_match(runtmp, capfd):
+    # the companion collection must hold every candidate match
+    query = get_test_data("SRR606249.sig.gz")
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    against_list = runtmp.output("against.txt")
+    make_file_list(against_list, [sig2, sig47, sig63])
+    against_db = index_siglist(runtmp, against_list, runtmp.output("db"))
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "fastmultigather",
+            query,
+            against_db,
+            "-s",
+            "100000",
+            "-o",
+            runtmp.output("out.csv"),
+            "--refine-from",
+            sig47,
+        )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "is not in the --refine-from collection" in captured.err
+
+
+def test_refine_from_not_rocksdb(runtmp, capfd):
+    query = get_test_data("SRR606249.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "fastmultigather",
+            query,
+            sig47,
+            "-s",
+            "100000",
+            "-o",
+            runtmp.output("out.csv"),
+            "--refine-from",
+            sig47,
+        )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "--refine-from only applies to RocksDB databases" in captured.err
@@ -0,0 +1,198 @@
+"""
+Test 'sourmash scripts fastprefetch'
+"""
+
+import pytest
+import pandas
+
+from . import sourmash_tst_utils as utils
+from .sourmash_tst_utils import (
+    get_test_data,
+    make_file_list,
+    zip_siglist,
+    index_siglist,
+)
+
+PREFETCH_COLUMNS = [
+    "query_filename",
+    "query_name",
+    "query_md5",
+    "match_name",
+    "match_md5",
+    "intersect_hashes",
+    "intersect_bp",
+]
+
+
+def make_against_list(runtmp):
+    against_list = runtmp.output("against.txt")
+    sigs = [get_test_data(f"{n}.fa.sig.gz") for n in (2, 47, 63)]
+    make_file_list(against_list, sigs)
+    return against_list
+
+
+def matches(path):
+    df = pandas.read_csv(path)
+    assert list(df.columns) == PREFETCH_COLUMNS
+    return sorted(zip(df["match_md5"], df["intersect_hashes"]))
+
+
+def test_installed(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "fastprefetch")
+
+    assert "usage:  fastprefetch" in runtmp.last_result.err
+
+
+@pytest.mark.parametrize("against_type", ["pathlist", "zip", "rocksdb"])
+def test_same_as_fastgather(runtmp, capfd, against_type):
+    # fastprefetch finds the same overlaps as fastgather's prefetch step
+    query = get_test_data("SRR606249.sig.gz")
+    against_list = make_against_list(runtmp)
+
+    gather_prefetch = runtmp.output("gather-prefetch.csv")
+    runtmp.sourmash(
+        "scripts",
+        "fastgather",
+        query,
+        against_list,
+        "-o",
+        runtmp.output("gather.csv"),
+        "--output-prefetch",
+        gather_prefetch,
+        "-s",
+        "100000",
+    )
+    expected = matches(gather_prefetch)
+    assert len(expected) == 3
+
+    if against_type == "zip":
+        against_list = zip_siglist(runtmp, against_list, runtmp.output("db.zip"))
+    elif against_type == "rocksdb":
+        against_list = index_siglist(
+            runtmp, against_list, runtmp.output("db"), scaled=100000
+        )
+
+    output = runtmp.output("prefetch.csv")
+    runtmp.sourmash(
+        "scripts", "fastprefetch", query, against_list, "-o", output, "-s", "100000"
+    )
+    assert matches(output) == expected
+
+    captured = capfd.readouterr()
+    assert "DONE. Prefetched 1 queries; found 3 matches." in captured.err
+
+
+def test_threshold(runtmp):
+    query = get_test_data("SRR606249.sig.gz")
+    against_list = make_against_list(runtmp)
+
+    output = runtmp.output("prefetch.csv")
+    runtmp.sourmash(
+        "scripts",
+        "fastprefetch",
+        query,
+        against_list,
+        "-o",
+        output,
+        "-s",
+        "100000",
+        "--threshold-hashes",
+        "30",
+    )
+    # overlaps are 44, 41, and 22 hashes at this scaled.
+    found = matches(output)
+    assert len(found) == 2
+    assert all(n >= 30 for _, n in found)
+
+
+def test_no_matches(runtmp, capfd):
+    # no overlaps above threshold still writes a header
+    query = get_test_data("SRR606249.sig.gz")
+    against_list = make_against_list(runtmp)
+
+    output = runtmp.output("prefetch.csv")
+    runtmp.sourmash(
+        "scripts",
+        "fastprefetch",
+        query,
+        against_list,
+        "-o",
+        output,
+        "-s",
+        "100000",
+        "--threshold-bp",
+        "1e12",
+    )
+    assert matches(output) == []
+
+    captured = capfd.readouterr()
+    assert "found 0 matches" in captured.err
+
+
+@pytest.mark.parametrize("against_type", ["list", "rocksdb"])
+def test_sourmash_compat(runtmp, against_type):
+    # --sourmash-compat writes the same columns and values as sourmash prefetch
+    query = get_test_data("SRR606249.sig.gz")
+    against_list = make_against_list(runtmp)
+    if against_type == "rocksdb":
+        against_list = index_siglist(
+            runtmp, against_list, runtmp.output("db"), scaled=100000
+        )
+
+    output = runtmp.output("prefetch.csv")
+    runtmp.sourmash(
+        "scripts",
+        "fastprefetch",
+        query,
+        against_list,
+        "-o",
+        output,
+        "-s",
+        "100000",
+        "--sourmash-compat",
+    )
+
+    sp_output = runtmp.output("sourmash-prefetch.csv")
+    runtmp.sourmash(
+        "prefetch",
+        query,
+        make_against_list(runtmp),
+        "-o",
+        sp_output,
+        "--scaled",
+        "100000",
+    )
+
+    df = pandas.read_csv(output).sort_values("match_md5")
+    expected = pandas.read_csv(sp_output).sort_values("match_md5")
+    assert list(df.columns) == list(expected.columns)
+    for column in ["match_md5", "intersect_bp", "query_bp", "scaled"]:
+        assert list(df[column]) == list(expected[column])
+    assert list(df["f_query_match"]) == pytest.approx(list(expected["f_query_match"]))
+    if against_type == "list":
+        for column in ["jaccard", "f_match_query", "max_containment"]:
+            assert list(df[column]) == pytest.approx(list(expected[column]))
+
+
+def test_sourmash_compat_no_matches(runtmp):
+    query = get_test_data("SRR606249.sig.gz")
+    against_list = make_against_list(runtmp)
+
+    output = runtmp.output("prefetch.csv")
+    runtmp.sourmash(
+        "scripts",
+        "fastprefetch",
+        query,
+        against_list,
+        "-o",
+        output,
+        "-s",
+        "100000",
+        "--threshold-bp",
+        "1e12",
+        "--sourmash-compat",
+    )
+    df = pandas.read_csv(output)
+    assert len(df) == 0
+    assert "potential_false_negative" in df.columns
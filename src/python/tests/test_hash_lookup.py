@@ -0,0 +1,144 @@
+"""
+Test 'sourmash scripts hash-lookup'
+"""
+
+import os
+import pytest
+import pandas
+
+import sourmash
+from . import sourmash_tst_utils as utils
+from .sourmash_tst_utils import get_test_data, make_file_list, index_siglist
+
+
+def load_sig(filename):
+    return sourmash.load_one_signature(filename, ksize=31)
+
+
+def test_installed(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "hash-lookup")
+
+    assert "usage:  hash-lookup" in runtmp.last_result.err
+
+
+def make_index(runtmp):
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    siglist = runtmp.output("db-sigs.txt")
+    make_file_list(siglist, [sig2, sig47, sig63])
+
+    return index_siglist(runtmp, siglist, runtmp.output("db.rocksdb"))
+
+
+def test_hashes(runtmp, capfd):
+    db = make_index(runtmp)
+
+    ss47 = load_sig(get_test_data("47.fa.sig.gz"))
+    ss63 = load_sig(get_test_data("63.fa.sig.gz"))
+    hashes47 = set(ss47.minhash.hashes)
+    hashes63 = set(ss63.minhash.hashes)
+
+    shared = min(hashes47 & hashes63)
+    only47 = min(hashes47 - hashes63)
+    missing = 1
+
+    hashes_file = runtmp.output("hashes.txt")
+    with open(hashes_file, "wt") as fp:
+        fp.write(f"{shared}\n{only47}\n\n{missing}\n")
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts", "hash-lookup", db, "--hashes", hashes_file, "-o", output
+    )
+    assert os.path.exists(output)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "Found 2 of 3 hashes in the database." in captured.err
+
+    df = pandas.read_csv(output)
+    assert set(df.keys()) == {"query_name", "hash", "match_name", "match_md5"}
+    assert len(df) == 3
+
+    shared_md5s = set(df[df["hash"] == shared]["match_md5"])
+    assert shared_md5s == {ss47.md5sum(), ss63.md5sum()}
+
+    only47_names = list(df[df["hash"] == only47]["match_name"])
+    assert only47_names == [ss47.name]
+
+    assert missing not in set(df["hash"])
+
+
+def test_query(runtmp, capfd):
+    db = make_index(runtmp)
+    query = get_test_data("47.fa.sig.gz")
+    ss47 = load_sig(query)
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash("scripts", "hash-lookup", db, "--query", query, "-o", output)
+    assert os.path.exists(output)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    n_hashes = len(ss47.minhash.hashes)
+    assert f"Found {n_hashes} of {n_hashes} hashes in the database." in captured.err
+
+    df = pandas.read_csv(output)
+    assert set(df["query_name"]) == {ss47.name}
+    # every query hash is found in the query itself.
+    self_rows = df[df["match_md5"] == ss47.md5sum()]
+    assert set(self_rows["hash"]) == set(ss47.minhash.hashes)
+
+
+def test_hashes_bad(runtmp, capfd):
+    db = make_index(runtmp)
+
+    hashes_file = runtmp.output("hashes.txt")
+    with open(hashes_file, "wt") as fp:
+        fp.write("12345\nnot_a_hash\n")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "hash-lookup", db, "--hashes", hashes_file)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "invalid hash 'not_a_hash' on line 2" in captured.err
+
+
+def test_not_rocksdb(runtmp, capfd):
+    hashes_file = runtmp.output("hashes.txt")
+    with open(hashes_file, "wt") as fp:
+        fp.write("12345\n")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "hash-lookup",
+            get_test_data("47.fa.sig.gz"),
+            "--hashes",
+            hashes_file,
+        )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "is not a valid RevIndex database" in captured.err
+
+
+def test_hashes_and_query_exclusive(runtmp):
+    db = make_index(runtmp)
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "hash-lookup",
+            db,
+            "--hashes",
+            runtmp.output("hashes.txt"),
+            "--query",
+            get_test_data("47.fa.sig.gz"),
+        )
+
+    assert "not allowed with argument" in runtmp.last_result.err
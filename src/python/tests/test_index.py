@@ -649,3 +649,165 @@ def test_rocksdb_v0_9_13_internal(runtmp):
         "podar-ref-subset.branch0_9_13.internal.rocksdb",
         in_dir=runtmp.output(""),
     )
+
+
+def test_index_exists(runtmp, capfd):
+    # refuse to overwrite an existing index without --force
+    siglist = runtmp.output("db-sigs.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    make_file_list(siglist, [sig2, sig47])
+
+    output = runtmp.output("db.rocksdb")
+    runtmp.sourmash("scripts", "index", siglist, "-o", output)
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "index", siglist, "-o", output)
+
+    assert "already exists; use --force to overwrite" in runtmp.last_result.err
+
+
+def test_index_exists_force(runtmp, capfd):
+    # --force replaces an existing index
+    siglist = runtmp.output("db-sigs.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    output = runtmp.output("db.rocksdb")
+    make_file_list(siglist, [sig2, sig47, sig63])
+    runtmp.sourmash("scripts", "index", siglist, "-o", output)
+
+    capfd.readouterr()
+
+    make_file_list(siglist, [sig2])
+    runtmp.sourmash("scripts", "index", siglist, "-o", output, "--force")
+
+    captured = capfd.readouterr()
+    assert "Removing existing index" in captured.err
+    assert "Indexing 1 sketches." in captured.err
+
+    runtmp.sourmash("scripts", "check", output)
+
+
+def test_index_exists_force_not_rocksdb(runtmp, capfd):
+    # --force won't remove a directory that isn't an index
+    siglist = runtmp.output("db-sigs.txt")
+    make_file_list(siglist, [get_test_data("2.fa.sig.gz")])
+
+    output = runtmp.output("somedir")
+    os.mkdir(output)
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "index", siglist, "-o", output, "--force")
+
+    captured = capfd.readouterr()
+    assert "which is not a RocksDB index" in captured.err
+    assert os.path.isdir(output)
+
+
+def test_index_check_hash_stats(runtmp, capfd, toggle_internal_storage):
+    # report how many datasets each hash is in, and write a stoplist
+    sigs = [get_test_data(f"{n}.fa.sig.gz") for n in (2, 47, 63)]
+    siglist = runtmp.output("db-sigs.txt")
+    make_file_list(siglist, sigs)
+
+    output = runtmp.output("db.rocksdb")
+    runtmp.sourmash("scripts", "index", siglist, "-o", output, toggle_internal_storage)
+
+    stats = runtmp.output("stats.csv")
+    stoplist = runtmp.output("stoplist.sig")
+    runtmp.sourmash(
+        "scripts",
+        "check",
+        output,
+        "--hash-stats-out",
+        stats,
+        "--ubiquitous-fraction",
+        "0.6",
+        "--output-stoplist",
+        stoplist,
+    )
+    captured = capfd.readouterr()
+    print(captured.out)
+    assert "index is ok" in captured.err
+    assert "are found in at least 2 datasets" in captured.out
+
+    mhs = [sourmash.load_one_signature(sig, ksize=31).minhash for sig in sigs]
+    df = pandas.read_csv(stats)
+    assert list(df.columns) == ["n_datasets", "n_hashes"]
+    assert (df["n_datasets"] * df["n_hashes"]).sum() == sum(len(mh) for mh in mhs)
+
+    # only 47 and 63 share hashes.
+    shared = set(mhs[1].hashes) & set(mhs[2].hashes)
+    assert dict(zip(df["n_datasets"], df["n_hashes"]))[2] == len(shared)
+    stop = sourmash.load_one_signature(stoplist)
+    assert set(stop.minhash.hashes) == shared
+    assert stop.minhash.ksize == 31
+    assert stop.minhash.scaled == 1000
+
+
+def test_index_check_bad_ubiquitous_fraction(runtmp, capfd):
+    siglist = runtmp.output("db-sigs.txt")
+    make_file_list(siglist, [get_test_data("2.fa.sig.gz")])
+    output = runtmp.output("db.rocksdb")
+    runtmp.sourmash("scripts", "index", siglist, "-o", output)
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts", "check", output, "--hash-stats", "--ubiquitous-fraction", "2"
+        )
+
+    captured = capfd.readouterr()
+    assert "--ubiquitous-fraction must be above 0 and at most 1" in captured.err
+
+
+def test_index_normalize_and_rename(runtmp, capfd):
+    # searches of the index report the new names from the alias table
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = sourmash.load_one_signature(get_test_data("63.fa.sig.gz"), ksize=31)
+    sig63 = sourmash.SourmashSignature(sig63.minhash, name="genomes/63.fa.gz")
+    path63 = runtmp.output("63.sig")
+    sourmash.save_signatures([sig63], open(path63, "wb"))
+
+    siglist = runtmp.output("db-sigs.txt")
+    make_file_list(siglist, [sig2, sig47, path63])
+
+    name47 = "NC_009661.1 Shewanella baltica OS185 plasmid pS18501, complete sequence"
+    mapping = runtmp.output("mapping.csv")
+    pandas.DataFrame({"name": [name47], "new_name": ["shew47"]}).to_csv(
+        mapping, index=False
+    )
+
+    output = runtmp.output("db.rocksdb")
+    runtmp.sourmash(
+        "scripts",
+        "index",
+        siglist,
+        "-o",
+        output,
+        "--normalize-names",
+        "--rename-from",
+        mapping,
+    )
+    captured = capfd.readouterr()
+    assert "Stored new names for 2 sketches." in captured.err
+
+    aliases = pandas.read_csv(os.path.join(output, "branchwater-aliases.csv"))
+    assert list(aliases.columns) == ["md5", "name", "alias"]
+    assert dict(zip(aliases["name"], aliases["alias"])) == {
+        name47: "shew47",
+        "genomes/63.fa.gz": "63",
+    }
+
+    query_list = runtmp.output("query.txt")
+    make_file_list(query_list, [sig2, sig47, path63])
+    out = runtmp.output("out.csv")
+    runtmp.sourmash("scripts", "manysearch", query_list, output, "-o", out)
+
+    df = pandas.read_csv(out)
+    name2 = "CP001071.1 Akkermansia muciniphila ATCC BAA-835, complete genome"
+    assert set(df["match_name"]) == {name2, "shew47", "63"}
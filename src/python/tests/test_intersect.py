@@ -0,0 +1,149 @@
+"""
+Test 'sourmash scripts intersect'
+"""
+
+import os
+import pytest
+import sourmash
+
+from . import sourmash_tst_utils as utils
+from .sourmash_tst_utils import (
+    get_test_data,
+    make_file_list,
+    zip_siglist,
+)
+
+
+def load_sigs(filename):
+    return {
+        ss.name: ss for ss in sourmash.load_file_as_signatures(filename, ksize=31)
+    }
+
+
+def test_installed(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "intersect")
+
+    assert "usage:  intersect" in runtmp.last_result.err
+
+
+@pytest.mark.parametrize("zip_input", [False, True])
+def test_simple(runtmp, zip_input):
+    # intersect two sketches into one
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    sig_list = runtmp.output("sigs.txt")
+    make_file_list(sig_list, [sig47, sig63])
+    if zip_input:
+        sig_list = zip_siglist(runtmp, sig_list, runtmp.output("sigs.zip"))
+
+    output = runtmp.output("out.zip")
+    runtmp.sourmash("scripts", "intersect", sig_list, "-o", output)
+
+    sigs = load_sigs(output)
+    assert list(sigs) == ["intersection of 2 sketches"]
+    mh = sigs["intersection of 2 sketches"].minhash
+    assert len(mh) == 2529
+    assert not mh.track_abundance
+
+    # compare to python intersection
+    mh47 = sourmash.load_one_signature(sig47, ksize=31).minhash
+    mh63 = sourmash.load_one_signature(sig63, ksize=31).minhash
+    assert set(mh.hashes) == set(mh47.hashes) & set(mh63.hashes)
+
+
+def test_simple_name(runtmp):
+    # set the output name
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    sig_list = runtmp.output("sigs.txt")
+    make_file_list(sig_list, [sig47, sig63])
+
+    output = runtmp.output("out.sig.gz")
+    runtmp.sourmash(
+        "scripts", "intersect", sig_list, "-o", output, "--name", "shewanella-core"
+    )
+
+    sigs = load_sigs(output)
+    assert list(sigs) == ["shewanella-core"]
+
+
+def test_groups(runtmp, capfd):
+    # intersect per group; match on full name or first word
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    sig_list = runtmp.output("sigs.txt")
+    make_file_list(sig_list, [sig2, sig47, sig63])
+
+    groups = runtmp.output("groups.csv")
+    with open(groups, "wt") as fp:
+        fp.write("group,name\n")
+        fp.write("shewanella,NC_009661.1\n")
+        fp.write(
+            "shewanella,NC_011665.1 Shewanella baltica OS223 plasmid pS22303, complete sequence\n"
+        )
+
+    output = runtmp.output("out.zip")
+    runtmp.sourmash(
+        "scripts", "intersect", sig_list, "-o", output, "--groups", groups
+    )
+
+    sigs = load_sigs(output)
+    assert list(sigs) == ["shewanella"]
+    assert len(sigs["shewanella"].minhash) == 2529
+
+    captured = capfd.readouterr()
+    assert "1 sketches are not in any group" in captured.err
+
+
+def test_groups_no_match(runtmp, capfd):
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    sig_list = runtmp.output("sigs.txt")
+    make_file_list(sig_list, [sig47, sig63])
+
+    groups = runtmp.output("groups.csv")
+    with open(groups, "wt") as fp:
+        fp.write("group,name\n")
+        fp.write("a,no-such-sketch\n")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "intersect",
+            sig_list,
+            "-o",
+            runtmp.output("out.zip"),
+            "--groups",
+            groups,
+        )
+
+    captured = capfd.readouterr()
+    assert "No sketches matched the names" in captured.err
+
+
+def test_groups_bad_csv(runtmp, capfd):
+    sig47 = get_test_data("47.fa.sig.gz")
+
+    groups = runtmp.output("groups.csv")
+    with open(groups, "wt") as fp:
+        fp.write("a,b\n1,2\n")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "intersect",
+            sig47,
+            "-o",
+            runtmp.output("out.zip"),
+            "--groups",
+            groups,
+        )
+
+    captured = capfd.readouterr()
+    assert "must have 'group' and 'name' columns" in captured.err
@@ -0,0 +1,95 @@
+"""
+Test 'sourmash scripts manydescribe'
+"""
+
+import pytest
+import pandas
+
+from . import sourmash_tst_utils as utils
+from .sourmash_tst_utils import get_test_data, make_file_list, zip_siglist
+
+
+def make_sig_list(runtmp, zip_input=False):
+    sigs = [
+        get_test_data(f)
+        for f in ("2.fa.sig.gz", "47.fa.sig.gz", "63.fa.sig.gz", "SRR606249.sig.gz")
+    ]
+    sig_list = runtmp.output("sigs.txt")
+    make_file_list(sig_list, sigs)
+    if zip_input:
+        sig_list = zip_siglist(runtmp, sig_list, runtmp.output("sigs.zip"))
+    return sig_list
+
+
+def test_installed(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "manydescribe")
+
+    assert "usage:  manydescribe" in runtmp.last_result.err
+
+
+@pytest.mark.parametrize("zip_input", [False, True])
+def test_manydescribe(runtmp, capfd, zip_input):
+    sig_list = make_sig_list(runtmp, zip_input)
+    output = runtmp.output("describe.csv")
+
+    runtmp.sourmash("scripts", "manydescribe", sig_list, "-o", output)
+
+    df = pandas.read_csv(output)
+    assert list(df.columns) == [
+        "name",
+        "md5",
+        "ksize",
+        "moltype",
+        "scaled",
+        "num",
+        "n_hashes",
+        "with_abundance",
+        "filename",
+        "internal_location",
+    ]
+    assert len(df) == 4
+
+    row = df[df["md5"] == "09a08691ce52952152f0e866a59f6261"].iloc[0]
+    assert row["name"].startswith("NC_009661.1 Shewanella baltica OS185")
+    assert row["ksize"] == 31
+    assert row["moltype"] == "DNA"
+    assert row["scaled"] == 1000
+    assert row["n_hashes"] == 5177
+    assert not row["with_abundance"]
+
+    row = df[df["name"] == "SRR606249"].iloc[0]
+    assert row["scaled"] == 100000
+    assert row["n_hashes"] == 4200
+    assert row["with_abundance"]
+
+    captured = capfd.readouterr()
+    assert "Described 4 sketches" in captured.err
+
+
+def test_manydescribe_all_ksizes(runtmp):
+    # all ksizes are described, not just k=31
+    output = runtmp.output("describe.csv")
+
+    runtmp.sourmash(
+        "scripts", "manydescribe", get_test_data("1.combined.sig.gz"), "-o", output
+    )
+
+    df = pandas.read_csv(output)
+    assert sorted(df["ksize"]) == [21, 31, 51]
+
+
+def test_manydescribe_stdout(runtmp):
+    runtmp.sourmash("scripts", "manydescribe", get_test_data("47.sig.zip"))
+
+    out = runtmp.last_result.out
+    assert "name,md5,ksize,moltype,scaled" in out
+    assert "09a08691ce52952152f0e866a59f6261,31,DNA,1000,0,5177,false" in out
+
+
+def test_manydescribe_missing_file(runtmp, capfd):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "manydescribe", "no-such-file.zip")
+
+    captured = capfd.readouterr()
+    assert "No such file or directory" in captured.err
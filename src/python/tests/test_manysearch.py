@@ -1,3 +1,4 @@
+import json
 import os
 import pytest
 import pandas
@@ -47,6 +48,7 @@ def test_simple(runtmp, zip_query, zip_against):
 
     df = pandas.read_csv(output)
     assert len(df) == 5
+    assert list(df["intersect_bp"]) == list(df["intersect_hashes"] * 1000)
 
     dd = df.to_dict(orient="index")
     print(dd)
@@ -307,6 +309,66 @@ def test_simple_abund(runtmp):
     assert total_weighted_hashes == 73489
 
 
+@pytest.mark.parametrize("internal_storage", [True, False])
+def test_simple_abund_indexed(runtmp, internal_storage):
+    # the index stores abundances, so the weighted columns match a search
+    # against the sketches themselves.
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+    query_list = runtmp.output("query.txt")
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    against = get_test_data("SRR606249.sig.gz")
+    against_list = runtmp.output("against.txt")
+    make_file_list(against_list, [against])
+    toggle = "--internal-storage" if internal_storage else "--no-internal-storage"
+    db = index_siglist(
+        runtmp,
+        against_list,
+        runtmp.output("db"),
+        scaled=100000,
+        toggle_internal_storage=toggle,
+    )
+    assert "Stored abundances" in runtmp.last_result.err
+
+    args = ["-s", "100000", "-k", "31", "-t", "0.01"]
+    indexed_out = runtmp.output("indexed.csv")
+    runtmp.sourmash("scripts", "manysearch", query_list, db, "-o", indexed_out, *args)
+    plain_out = runtmp.output("plain.csv")
+    runtmp.sourmash("scripts", "manysearch", query_list, against, "-o", plain_out, *args)
+
+    indexed = pandas.read_csv(indexed_out).set_index("query_md5")
+    plain = pandas.read_csv(plain_out).set_index("query_md5")
+    assert len(indexed) == len(plain) == 3
+
+    for col in [
+        "average_abund",
+        "median_abund",
+        "std_abund",
+        "n_weighted_found",
+        "total_weighted_hashes",
+    ]:
+        for key in plain.index:
+            assert indexed.loc[key, col] == pytest.approx(plain.loc[key, col])
+
+    # --ignore-abundance leaves them empty, as before.
+    ignore_out = runtmp.output("ignore.csv")
+    runtmp.sourmash(
+        "scripts",
+        "manysearch",
+        query_list,
+        db,
+        "-o",
+        ignore_out,
+        "--ignore-abundance",
+        *args,
+    )
+    df = pandas.read_csv(ignore_out)
+    assert len(df) == 3
+    assert df["average_abund"].isnull().all()
+
+
 def test_simple_indexed(runtmp, zip_query, indexed_query):
     # test basic execution!
     query_list = runtmp.output("query.txt")
@@ -337,6 +399,7 @@ def test_simple_indexed(runtmp, zip_query, indexed_query):
 
     df = pandas.read_csv(output)
     assert len(df) == 5
+    assert list(df["intersect_bp"]) == list(df["intersect_hashes"] * 1000)
 
     dd = df.to_dict(orient="index")
     print(dd)
@@ -368,6 +431,88 @@ def test_simple_indexed(runtmp, zip_query, indexed_query):
                 assert query_ani == 0.9772
 
 
+FULL_RESULT_COLUMNS = [
+    "match_md5",
+    "jaccard",
+    "max_containment",
+    "match_containment_ani",
+    "average_containment_ani",
+    "max_containment_ani",
+]
+
+
+def test_simple_indexed_no_full_results(runtmp):
+    # by default, rocksdb manysearch leaves match-direction columns empty
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+    against_list = index_siglist(runtmp, against_list, runtmp.output("db"))
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash("scripts", "manysearch", query_list, against_list, "-o", output)
+
+    df = pandas.read_csv(output)
+    assert len(df) == 5
+    for col in FULL_RESULT_COLUMNS:
+        assert df[col].isnull().all()
+
+
+@pytest.mark.parametrize("scaled", [None, "10000"])
+def test_simple_indexed_full_results(runtmp, scaled):
+    # --full-results gives the same match-direction columns as a search
+    # against regular sketches, including when downsampling.
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+    db = index_siglist(runtmp, against_list, runtmp.output("db"))
+
+    scaled_args = ["-s", scaled] if scaled else []
+    indexed_out = runtmp.output("indexed.csv")
+    runtmp.sourmash(
+        "scripts",
+        "manysearch",
+        query_list,
+        db,
+        "-o",
+        indexed_out,
+        "--full-results",
+        *scaled_args,
+    )
+    plain_out = runtmp.output("plain.csv")
+    runtmp.sourmash(
+        "scripts", "manysearch", query_list, against_list, "-o", plain_out, *scaled_args
+    )
+
+    indexed = pandas.read_csv(indexed_out).set_index(["query_md5", "match_md5"])
+    plain = pandas.read_csv(plain_out).set_index(["query_md5", "match_md5"])
+    assert len(indexed) == len(plain)
+    assert set(indexed.index) == set(plain.index)
+
+    for col in FULL_RESULT_COLUMNS[1:]:
+        for key in plain.index:
+            assert indexed.loc[key, col] == pytest.approx(plain.loc[key, col])
+
+    # hand-checked: 63 in 47
+    row = indexed.loc[
+        ("38729c6374925585db28916b82a6f513", "09a08691ce52952152f0e866a59f6261")
+    ]
+    if scaled is None:
+        assert round(row["jaccard"], 4) == 0.3207
+        assert round(row["max_containment"], 4) == 0.4885
+
+
 def test_simple_list_of_zips(runtmp):
     # test basic execution!
     query_list = runtmp.output("query.txt")
@@ -720,6 +865,74 @@ def test_nomatch_against(runtmp, capfd):
 
     captured = capfd.readouterr()
     assert "No search signatures loaded, exiting." in captured.err
+    # the sketch that can't be upsampled is named, with a usable scaled
+    assert "'SRR606249' (" in captured.err
+    assert "use --scaled 100000 or higher" in captured.err
+
+
+def test_some_against_scaled_too_high(runtmp, capfd):
+    # against sketches with a higher scaled than requested are reported
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+    coarse = get_test_data("SRR606249.sig.gz")
+
+    make_file_list(query_list, [sig47])
+    make_file_list(against_list, [sig63, coarse])
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts",
+        "manysearch",
+        query_list,
+        against_list,
+        "-o",
+        output,
+        "-s",
+        "1000",
+    )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert (
+        "WARNING: 1 search sketches have a scaled above the requested scaled=1000, and cannot be upsampled"
+        in captured.err
+    )
+    assert "Use --scaled 100000 or higher to include them." in captured.err
+    assert "Loaded 1 search signature(s)" in captured.err
+
+
+def test_indexed_scaled_too_low(runtmp, capfd):
+    # a RocksDB index can't be searched below its scaled
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+    make_file_list(query_list, [sig47])
+    make_file_list(against_list, [sig47, sig63])
+    db = index_siglist(runtmp, against_list, runtmp.output("db"))
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "manysearch",
+            query_list,
+            db,
+            "-o",
+            runtmp.output("out.csv"),
+            "-s",
+            "100",
+        )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert (
+        "database sketches have scaled=1000, and cannot be upsampled to the requested scaled=100; use --scaled 1000 or higher"
+        in captured.err
+    )
 
 
 def test_bad_against(runtmp, capfd):
@@ -1008,6 +1221,57 @@ def test_simple_protein(runtmp):
                 assert max_ani == 0.9605
 
 
+def test_simple_protein_detect_selection(runtmp, capfd):
+    # without -k/--moltype, use the only ksize and moltype in the queries
+    protsigs = get_test_data("protein.zip")
+    output = runtmp.output("out.csv")
+
+    runtmp.sourmash(
+        "scripts",
+        "manysearch",
+        protsigs,
+        protsigs,
+        "-s",
+        "100",
+        "-o",
+        output,
+        "-t",
+        "0.01",
+    )
+
+    assert os.path.exists(output)
+
+    df = pandas.read_csv(output)
+    assert len(df) == 4
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "Setting ksize=19 from the sketches" in captured.err
+    assert "Setting moltype=protein from the sketches" in captured.err
+
+
+def test_ambiguous_selection(runtmp, capfd):
+    # queries with several ksizes/moltypes, none of them k=31 DNA, are an error
+    query_list = runtmp.output("query.txt")
+    output = runtmp.output("out.csv")
+
+    sig1 = get_test_data("1.fa.k21.sig.gz")
+    snap25 = get_test_data("snap25.protein.k5.sig")
+    protsigs = get_test_data("protein.zip")
+    make_file_list(query_list, [sig1, snap25])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts", "manysearch", query_list, protsigs, "-s", "100", "-o", output
+        )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "sketches have more than one ksize/moltype" in captured.err
+    assert "k=5 protein, k=21 DNA" in captured.err
+    assert not os.path.exists(output)
+
+
 def test_simple_protein_indexed(runtmp):
     # test basic execution with proteins
     protsigs = get_test_data("protein.zip")
@@ -1391,6 +1655,35 @@ def test_simple_hp_indexed(runtmp):
                 assert query_ani == 0.9911
 
 
+@pytest.mark.parametrize(
+    "args,msg",
+    [
+        (["-k", "19", "--moltype", "DNA"], "database has protein (k=19) sketches"),
+        (["-k", "21", "--moltype", "protein"], "database has ksize=19 (protein)"),
+    ],
+)
+def test_indexed_selection_mismatch(runtmp, args, msg):
+    # searching a protein index with the wrong ksize or moltype fails clearly
+    protsigs = get_test_data("protein.zip")
+    protsigs_db = index_siglist(
+        runtmp, protsigs, runtmp.output("db"), ksize=19, moltype="protein", scaled=100
+    )
+    assert "Sketches have ksize=19, moltype=protein" in runtmp.last_result.err
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "manysearch",
+            protsigs,
+            protsigs_db,
+            "-o",
+            runtmp.output("out.csv"),
+            *args,
+        )
+
+    assert msg in runtmp.last_result.err
+
+
 def test_pretty_print(runtmp):
     # test pretty-printing of output
     query = get_test_data("hmp-queries.sig.zip")
@@ -1493,6 +1786,7 @@ def test_rocksdb_v0_9_5(runtmp):
         "out.csv",
         "-s",
         "100_000",
+        "--force",
         in_dir=runtmp.output(""),
     )
 
@@ -1554,3 +1848,1158 @@ def test_rocksdb_v0_9_13_external(runtmp):
     )
 
     assert os.path.exists(runtmp.output("out.csv"))
+
+
+def test_manysearch_iter(runtmp):
+    # test streaming results back to Python, vs CSV output
+    from sourmash_plugin_branchwater import sourmash_plugin_branchwater as api
+
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts", "manysearch", query_list, against_list, "-o", output, "-t", "0.01"
+    )
+    csv_df = pandas.read_csv(output)
+
+    results = list(
+        api.manysearch_iter(query_list, against_list, 0.01, 31, None, "DNA")
+    )
+    assert len(results) == len(csv_df) == 5
+
+    df = pandas.DataFrame(results)
+    df = df.sort_values(["query_name", "match_name"]).reset_index(drop=True)
+    csv_df = csv_df.sort_values(["query_name", "match_name"]).reset_index(drop=True)
+    assert list(df["query_md5"]) == list(csv_df["query_md5"])
+    assert list(df["match_md5"]) == list(csv_df["match_md5"])
+    assert list(df["intersect_hashes"]) == list(csv_df["intersect_hashes"])
+
+
+def test_manysearch_iter_early_stop(runtmp):
+    # stopping iteration early should not hang or raise
+    from sourmash_plugin_branchwater import sourmash_plugin_branchwater as api
+
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    stream = api.manysearch_iter(
+        query_list, against_list, 0, 31, None, "DNA", output_all_comparisons=True
+    )
+    hit = next(stream)
+    assert "containment" in hit
+    stream.close()
+
+    assert list(stream) == []
+
+
+def test_manysearch_iter_bad_query(runtmp):
+    # loading errors should be raised when the stream is created
+    from sourmash_plugin_branchwater import sourmash_plugin_branchwater as api
+
+    against = get_test_data("2.fa.sig.gz")
+
+    with pytest.raises(Exception):
+        api.manysearch_iter(
+            runtmp.output("no-such-file"), against, 0.01, 31, None, "DNA"
+        )
+
+
+def test_manysearch_progress_callback(runtmp):
+    # progress callback is called with (n_done, n_total)
+    from sourmash_plugin_branchwater import sourmash_plugin_branchwater as api
+
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    output = runtmp.output("out.csv")
+    calls = []
+    status = api.do_manysearch(
+        query_list,
+        against_list,
+        0.01,
+        31,
+        None,
+        "DNA",
+        output,
+        progress=lambda n_done, n_total: calls.append((n_done, n_total)),
+        progress_interval=3,
+    )
+    assert status == 0
+    assert calls
+    assert calls[-1] == (9, 9)
+    assert all(n_total == 9 for (_, n_total) in calls)
+
+
+def test_manysearch_cancel(runtmp):
+    # a cancelled search stops and returns an error status
+    from sourmash_plugin_branchwater import sourmash_plugin_branchwater as api
+
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    token = api.CancelToken()
+    assert not token.cancelled
+    token.cancel()
+    assert token.cancelled
+
+    output = runtmp.output("out.csv")
+    status = api.do_manysearch(
+        query_list, against_list, 0.01, 31, None, "DNA", output, cancel=token
+    )
+    assert status == 1
+
+
+def test_manysearch_iter_in_memory_query(runtmp):
+    # queries can be passed as sourmash signature objects
+    from sourmash_plugin_branchwater import sourmash_plugin_branchwater as api
+
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    queries = [sourmash.load_one_signature(sig2), sourmash.load_one_signature(sig47)]
+    results = list(api.manysearch_iter(queries, against_list, 0.01, 31, None, "DNA"))
+
+    # 2 self matches, + 47/63 match.
+    assert len(results) == 3
+    assert {r["query_name"] for r in results} == {q.name for q in queries}
+
+
+def test_coverage_report(runtmp, capfd):
+    # report coverage of each against by the union of queries, and vice versa
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig47])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    output = runtmp.output("out.csv")
+    coverage = runtmp.output("coverage.csv")
+
+    runtmp.sourmash(
+        "scripts",
+        "manysearch",
+        query_list,
+        against_list,
+        "-o",
+        output,
+        "--coverage-report",
+        coverage,
+    )
+    assert os.path.exists(coverage)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "1 against sketches share no hashes with any query" in captured.err
+
+    ss2 = sourmash.load_one_signature(sig2, ksize=31)
+    ss47 = sourmash.load_one_signature(sig47, ksize=31)
+    ss63 = sourmash.load_one_signature(sig63, ksize=31)
+
+    df = pandas.read_csv(coverage)
+    assert list(df["role"]) == ["query", "against", "against", "against"]
+    rows = {(row["role"], row["md5"]): row for row in df.to_dict(orient="records")}
+
+    # the query is itself in the against collection.
+    row = rows[("query", ss47.md5sum())]
+    assert row["n_covered"] == row["n_hashes"] == len(ss47.minhash)
+    assert row["f_covered"] == 1.0
+
+    assert rows[("against", ss2.md5sum())]["n_covered"] == 0
+    assert rows[("against", ss47.md5sum())]["f_covered"] == 1.0
+
+    row = rows[("against", ss63.md5sum())]
+    n_shared = len(set(ss47.minhash.hashes) & set(ss63.minhash.hashes))
+    assert row["n_covered"] == n_shared
+    assert row["n_hashes"] == len(ss63.minhash)
+    assert round(row["f_covered"], 4) == round(n_shared / len(ss63.minhash), 4)
+
+
+def test_coverage_report_rocksdb(runtmp, capfd):
+    # coverage reports are not supported against RocksDB databases
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig47])
+    make_file_list(against_list, [sig47, sig63])
+    db = index_siglist(runtmp, against_list, runtmp.output("db.rocksdb"))
+
+    output = runtmp.output("out.csv")
+    coverage = runtmp.output("coverage.csv")
+
+    runtmp.sourmash(
+        "scripts",
+        "manysearch",
+        query_list,
+        db,
+        "-o",
+        output,
+        "--coverage-report",
+        coverage,
+    )
+    assert os.path.exists(output)
+    assert not os.path.exists(coverage)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "--coverage-report is not supported for RocksDB databases" in captured.err
+
+
+def test_output_matched_hashes(runtmp, capfd):
+    # write the hashes shared by each query and match
+    from sourmash_plugin_branchwater.matchedhashes import read_matched_hashes
+
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    output = runtmp.output("out.csv")
+    matched = runtmp.output("matches.bin")
+
+    runtmp.sourmash(
+        "scripts",
+        "manysearch",
+        query_list,
+        against_list,
+        "-o",
+        output,
+        "-t",
+        "0.01",
+        "--output-matched-hashes",
+        matched,
+    )
+    assert os.path.exists(matched)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "Wrote matched hashes for 5 matches" in captured.err
+
+    sigs = {}
+    for filename in (sig2, sig47, sig63):
+        sig = sourmash.load_one_signature(filename, ksize=31)
+        sigs[sig.md5sum()] = sig
+
+    scaled, matches = read_matched_hashes(matched)
+    assert scaled == 1000
+    assert len(matches) == 5
+
+    # one block per result row, with the same number of shared hashes
+    df = pandas.read_csv(output)
+    expected = {
+        (row.query_md5, row.match_md5): row.intersect_hashes
+        for row in df.itertuples()
+    }
+    for query_md5, match_md5, hashes in matches:
+        assert len(query_md5) == 32
+        assert len(hashes) == expected[(query_md5, match_md5)]
+        assert hashes == sorted(hashes)
+
+        # self matches share every hash
+        if query_md5 == match_md5:
+            assert set(hashes) == set(sigs[query_md5].minhash.hashes)
+
+
+@pytest.mark.parametrize("indexed", [False, True])
+def test_exclude_self_matches(runtmp, indexed):
+    # skip matches between identical sketches, by md5
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    if indexed:
+        against_list = index_siglist(runtmp, against_list, runtmp.output("db"))
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts",
+        "manysearch",
+        query_list,
+        against_list,
+        "-o",
+        output,
+        "-t",
+        "0.01",
+        "--exclude-self-matches",
+    )
+
+    df = pandas.read_csv(output)
+    print(df)
+    # only 47 <-> 63, in both directions.
+    assert len(df) == 2
+    assert set(df["query_name"]) == set(df["match_name"])
+    assert (df["query_name"] != df["match_name"]).all()
+
+
+@pytest.mark.parametrize("indexed", [False, True])
+def test_output_columns(runtmp, indexed):
+    # write only the requested columns, in the requested order
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    if indexed:
+        against_list = index_siglist(runtmp, against_list, runtmp.output("db"))
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts",
+        "manysearch",
+        query_list,
+        against_list,
+        "-o",
+        output,
+        "-t",
+        "0.01",
+        "--output-columns",
+        "match_name,query_name,containment",
+    )
+
+    df = pandas.read_csv(output)
+    print(df)
+    assert list(df.columns) == ["match_name", "query_name", "containment"]
+    assert len(df) == 5
+
+    self_rows = df[df["query_name"] == df["match_name"]]
+    assert set(self_rows["containment"]) == {1.0}
+
+
+def test_output_columns_bad(runtmp, capfd):
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig47 = get_test_data("47.fa.sig.gz")
+    make_file_list(query_list, [sig47])
+    make_file_list(against_list, [sig47])
+
+    output = runtmp.output("out.csv")
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "manysearch",
+            query_list,
+            against_list,
+            "-o",
+            output,
+            "--output-columns",
+            "query_name,no_such_column",
+        )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "unknown output column(s): no_such_column" in captured.err
+    assert not os.path.exists(output)
+
+
+def test_failed_and_skipped_paths_out(runtmp, capfd):
+    # write paths that failed to load or held no compatible sketches
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig2_k21 = get_test_data("2.fa.k21.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+    make_file_list(query_list, [sig2, "no-exist"])
+    make_file_list(against_list, [sig47, sig63, sig2_k21])
+
+    output = runtmp.output("out.csv")
+    failed = runtmp.output("failed.csv")
+    skipped = runtmp.output("skipped.csv")
+
+    runtmp.sourmash(
+        "scripts",
+        "manysearch",
+        query_list,
+        against_list,
+        "-o",
+        output,
+        "--failed-paths-out",
+        failed,
+        "--skipped-paths-out",
+        skipped,
+    )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert f"Wrote 1 failed paths to '{failed}'" in captured.err
+    assert f"Wrote 1 skipped paths to '{skipped}'" in captured.err
+
+    failed_df = pandas.read_csv(failed)
+    print(failed_df)
+    assert list(failed_df.columns) == ["path", "reason"]
+    assert list(failed_df["path"]) == ["no-exist"]
+    assert failed_df["reason"].notnull().all()
+
+    skipped_df = pandas.read_csv(skipped)
+    print(skipped_df)
+    assert list(skipped_df["path"]) == [sig2_k21]
+    assert "k=31" in skipped_df["reason"][0]
+
+
+def test_failed_paths_out_empty(runtmp):
+    # reports are written, with headers, even when nothing goes wrong
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+    make_file_list(query_list, [sig47])
+    make_file_list(against_list, [sig47, sig63])
+
+    failed = runtmp.output("failed.csv")
+    runtmp.sourmash(
+        "scripts",
+        "manysearch",
+        query_list,
+        against_list,
+        "-o",
+        runtmp.output("out.csv"),
+        "--failed-paths-out",
+        failed,
+    )
+
+    failed_df = pandas.read_csv(failed)
+    assert list(failed_df.columns) == ["path", "reason"]
+    assert len(failed_df) == 0
+
+
+def test_verify_corrupted_against(runtmp, capfd):
+    # a damaged against sketch fails --verify, and is listed as failed
+    sig2 = get_test_data("2.fa.sig.gz")
+    corrupted = utils.corrupt_zip(
+        get_test_data("47.sig.zip"), runtmp.output("corrupted.zip")
+    )
+
+    output = runtmp.output("out.csv")
+    failed = runtmp.output("failed.csv")
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "manysearch",
+            sig2,
+            corrupted,
+            "-o",
+            output,
+            "--verify",
+            "--failed-paths-out",
+            failed,
+        )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "1 of 1 sketches failed md5 verification" in captured.err
+
+    failed_df = pandas.read_csv(failed)
+    assert len(failed_df) == 1
+    assert failed_df["reason"][0].startswith("md5 mismatch")
+
+
+def test_profile(runtmp):
+    # write the time spent in each stage
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    profile = runtmp.output("profile.csv")
+    runtmp.sourmash(
+        "scripts",
+        "manysearch",
+        query_list,
+        against_list,
+        "-o",
+        runtmp.output("out.csv"),
+        "--profile",
+        profile,
+    )
+
+    df = pandas.read_csv(profile)
+    print(df)
+    assert list(df.columns) == ["command", "stage", "seconds"]
+    assert set(df["command"]) == {"manysearch"}
+    assert list(df["stage"]) == [
+        "loading",
+        "selection",
+        "comparison",
+        "writing",
+        "total",
+    ]
+    assert (df["seconds"] >= 0).all()
+
+
+def test_output_exists(runtmp):
+    # refuse to overwrite an existing output without --force
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+    make_file_list(query_list, [sig47])
+    make_file_list(against_list, [sig47, sig63])
+
+    output = runtmp.output("out.csv")
+    with open(output, "w") as fp:
+        fp.write("precious")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts", "manysearch", query_list, against_list, "-o", output
+        )
+
+    assert "already exists; use --force to overwrite" in runtmp.last_result.err
+    with open(output) as fp:
+        assert fp.read() == "precious"
+
+
+def test_output_exists_force(runtmp):
+    # --force overwrites, and no temporary file is left behind
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+    make_file_list(query_list, [sig47])
+    make_file_list(against_list, [sig47, sig63])
+
+    output = runtmp.output("out.csv")
+    with open(output, "w") as fp:
+        fp.write("precious")
+
+    runtmp.sourmash(
+        "scripts",
+        "manysearch",
+        query_list,
+        against_list,
+        "-o",
+        output,
+        "--force",
+    )
+
+    df = pandas.read_csv(output)
+    assert len(df) == 2
+    assert not os.path.exists(output + ".tmp")
+
+
+def test_search_mode_in_memory(runtmp):
+    # load the sketches from a RocksDB index rather than searching it
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+    against_list = index_siglist(runtmp, against_list, runtmp.output("db"))
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts",
+        "manysearch",
+        query_list,
+        against_list,
+        "-o",
+        output,
+        "--search-mode",
+        "in-memory",
+    )
+
+    df = pandas.read_csv(output)
+    assert len(df) == 5
+    # in-memory search always calculates the match-direction columns
+    for col in FULL_RESULT_COLUMNS:
+        assert not df[col].isnull().any()
+
+
+def test_search_mode_rocksdb_not_index(runtmp, capfd):
+    # asking for a RocksDB search of something else is an error
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+    make_file_list(query_list, [sig47])
+    make_file_list(against_list, [sig47, sig63])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "manysearch",
+            query_list,
+            against_list,
+            "-o",
+            runtmp.output("out.csv"),
+            "--search-mode",
+            "rocksdb",
+        )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "search mode 'rocksdb' requires a RocksDB index" in captured.err
+
+
+def test_manysearch_df_api(runtmp):
+    # test the Python API that returns results directly, vs CSV output
+    from sourmash_plugin_branchwater import sourmash_plugin_branchwater as api
+
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts", "manysearch", query_list, against_list, "-o", output, "-t", "0.01"
+    )
+    csv_df = pandas.read_csv(output)
+
+    results = api.do_manysearch_df(query_list, against_list, 0.01, 31, None, "DNA")
+    assert len(results) == len(csv_df) == 5
+
+    df = pandas.DataFrame(results)
+    assert set(zip(df["query_name"], df["match_name"])) == set(
+        zip(csv_df["query_name"], csv_df["match_name"])
+    )
+    assert sorted(df["intersect_hashes"]) == sorted(csv_df["intersect_hashes"])
+
+
+def test_output_fifo(runtmp):
+    # results can be written to a named pipe, for a consumer to read as
+    # they are found.
+    import threading
+
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    fifo = runtmp.output("out.fifo")
+    os.mkfifo(fifo)
+
+    lines = []
+
+    def read_fifo():
+        with open(fifo) as fp:
+            lines.extend(fp)
+
+    reader = threading.Thread(target=read_fifo)
+    reader.start()
+
+    runtmp.sourmash(
+        "scripts", "manysearch", query_list, against_list, "-o", fifo, "-t", "0.01"
+    )
+    reader.join(timeout=60)
+    assert not reader.is_alive()
+
+    print("".join(lines))
+    assert lines[0].startswith("query_name,")
+    assert len(lines) == 6
+
+
+def test_per_query_scaled(runtmp):
+    # each query is compared at its own scaled, if higher than the against
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+    sig63_10k = runtmp.output("63.s10k.sig")
+    runtmp.sourmash("sig", "downsample", sig63, "--scaled", "10000", "-o", sig63_10k)
+
+    make_file_list(query_list, [sig47, sig63_10k])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash("scripts", "manysearch", query_list, against_list, "-o", output)
+    df = pandas.read_csv(output)
+    assert set(df["scaled"]) == {10000}
+
+    runtmp.sourmash(
+        "scripts",
+        "manysearch",
+        query_list,
+        against_list,
+        "-o",
+        output,
+        "--per-query-scaled",
+    )
+    df = pandas.read_csv(output)
+    md5_47 = sourmash.load_one_signature(sig47, ksize=31).md5sum()
+    md5_63 = sourmash.load_one_signature(sig63_10k, ksize=31).md5sum()
+    scaled = dict(zip(df["query_md5"], df["scaled"]))
+    assert scaled == {md5_47: 1000, md5_63: 10000}
+
+    # self-matches are still exact at the coarser scaled
+    is_63 = df["query_md5"] == md5_63
+    self_match = df[is_63 & (df["match_name"] == df["query_name"])]
+    assert list(self_match["containment"]) == [1.0]
+
+
+def test_per_query_scaled_with_scaled(runtmp, capfd):
+    query_list = runtmp.output("query.txt")
+    sig47 = get_test_data("47.fa.sig.gz")
+    make_file_list(query_list, [sig47])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "manysearch",
+            query_list,
+            query_list,
+            "-o",
+            runtmp.output("out.csv"),
+            "-s",
+            "1000",
+            "--per-query-scaled",
+        )
+
+    captured = capfd.readouterr()
+    assert "--per-query-scaled cannot be combined with --scaled" in captured.err
+
+
+def test_no_results_header(runtmp):
+    # with no matches above threshold, the output still has a header
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    make_file_list(query_list, [get_test_data("47.fa.sig.gz")])
+    make_file_list(against_list, [get_test_data("63.fa.sig.gz")])
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts", "manysearch", query_list, against_list, "-o", output, "-t", "0.9"
+    )
+
+    df = pandas.read_csv(output)
+    assert len(df) == 0
+    assert "query_name" in df.columns
+    assert "containment" in df.columns
+
+
+def test_detailed_exit_codes(runtmp):
+    # distinct exit codes for no results and for partial failures
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+    bad_against_list = runtmp.output("bad_against.txt")
+
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+    make_file_list(query_list, [sig47])
+    make_file_list(against_list, [sig63])
+    make_file_list(bad_against_list, [sig63, "no-exist"])
+
+    output = runtmp.output("out.csv")
+    args = ["scripts", "manysearch", query_list, "-o", output, "--detailed-exit-codes"]
+
+    runtmp.sourmash(*args, against_list)
+    assert runtmp.last_result.status == 0
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(*args, against_list, "-t", "0.9", "--force")
+    assert runtmp.last_result.status == 3
+    assert len(pandas.read_csv(output)) == 0
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(*args, bad_against_list, "--force")
+    assert runtmp.last_result.status == 4
+    assert len(pandas.read_csv(output)) == 1
+
+
+def test_summary_out(runtmp, capfd):
+    # a JSON summary of the run is printed on stderr, and written on request
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+    make_file_list(query_list, [sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63, "no-exist"])
+
+    output = runtmp.output("out.csv")
+    summary_out = runtmp.output("summary.json")
+    runtmp.sourmash(
+        "scripts",
+        "manysearch",
+        query_list,
+        against_list,
+        "-o",
+        output,
+        "--summary-out",
+        summary_out,
+    )
+
+    with open(summary_out) as fp:
+        summary = json.load(fp)
+    print(summary)
+    assert summary["command"] == "manysearch"
+    assert summary["status"] == 0
+    assert summary["error"] is None
+    assert summary["inputs"]["query"] == {
+        "loaded": 2,
+        "skipped_paths": 0,
+        "failed_paths": 0,
+    }
+    assert summary["inputs"]["against"]["loaded"] == 3
+    assert summary["inputs"]["against"]["failed_paths"] == 1
+    assert summary["failed_paths"] == 1
+    assert summary["results"] == len(pandas.read_csv(output))
+    assert summary["seconds"] >= 0
+
+    captured = capfd.readouterr()
+    lines = [x for x in captured.err.splitlines() if x.startswith("SUMMARY: ")]
+    assert len(lines) == 1
+    printed = json.loads(lines[0][len("SUMMARY: ") :])
+    assert printed["processed"] == summary["processed"]
+    assert printed["results"] == summary["results"]
+
+
+def test_dedup_by_md5(runtmp, capfd):
+    # the same sketch under two names is reported once, with an alias
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+    alias63 = runtmp.output("alias63.sig")
+    runtmp.sourmash("sig", "rename", sig63, "alias of 63", "-o", alias63)
+
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+    make_file_list(query_list, [sig47])
+    make_file_list(against_list, [sig63, alias63])
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash("scripts", "manysearch", query_list, against_list, "-o", output)
+    assert len(pandas.read_csv(output)) == 2
+
+    runtmp.sourmash(
+        "scripts",
+        "manysearch",
+        query_list,
+        against_list,
+        "-o",
+        output,
+        "--dedup-by-md5",
+        "--force",
+    )
+    df = pandas.read_csv(output)
+    assert len(df) == 1
+    row = df.iloc[0]
+    names = {row["match_name"], row["match_aliases"]}
+    assert "alias of 63" in names
+    assert any(name.startswith("NC_011665.1") for name in names)
+
+    captured = capfd.readouterr()
+    assert "Collapsed 1 against sketches with the same md5" in captured.err
+
+
+@pytest.mark.parametrize("indexed", [False, True])
+def test_min_overlap(runtmp, indexed):
+    # matches must also share a minimum number of hashes or bp
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sigs = [get_test_data(f"{n}.fa.sig.gz") for n in (2, 47, 63)]
+    make_file_list(query_list, sigs)
+    make_file_list(against_list, sigs)
+    if indexed:
+        against_list = index_siglist(runtmp, against_list, runtmp.output("db"))
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash("scripts", "manysearch", query_list, against_list, "-o", output)
+    df = pandas.read_csv(output)
+    assert len(df) == 5
+
+    # only the self matches share more hashes than 47 and 63 do
+    cross = df[df["query_name"] != df["match_name"]]
+    min_hashes = cross["intersect_hashes"].max() + 1
+
+    for option, value in (
+        ("--min-intersect-hashes", min_hashes),
+        ("--min-overlap-bp", min_hashes * 1000),
+    ):
+        runtmp.sourmash(
+            "scripts",
+            "manysearch",
+            query_list,
+            against_list,
+            "-o",
+            output,
+            option,
+            str(value),
+            "--force",
+        )
+        df = pandas.read_csv(output)
+        assert len(df) == 3
+        assert all(df["intersect_hashes"] >= min_hashes)
+
+
+def test_min_overlap_both(runtmp):
+    sig47 = get_test_data("47.fa.sig.gz")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "manysearch",
+            sig47,
+            sig47,
+            "-o",
+            runtmp.output("out.csv"),
+            "--min-intersect-hashes",
+            "10",
+            "--min-overlap-bp",
+            "10000",
+        )
+
+    assert "not allowed with argument" in runtmp.last_result.err
+
+
+@pytest.mark.parametrize("indexed", [False, True])
+def test_sketch_sizes(runtmp, indexed):
+    # query and match sizes are reported, at the search scaled
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sigs = [get_test_data(f"{n}.fa.sig.gz") for n in (2, 47, 63)]
+    make_file_list(query_list, sigs)
+    make_file_list(against_list, sigs)
+    if indexed:
+        against_list = index_siglist(runtmp, against_list, runtmp.output("db"))
+
+    sizes = {}
+    for sig in sigs:
+        ss = sourmash.load_one_signature(sig, ksize=31)
+        sizes[ss.name] = len(ss.minhash)
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash("scripts", "manysearch", query_list, against_list, "-o", output)
+    df = pandas.read_csv(output)
+    assert len(df) == 5
+
+    for row in df.to_dict(orient="records"):
+        assert row["query_n_hashes"] == sizes[row["query_name"]]
+        assert row["query_bp"] == row["query_n_hashes"] * 1000
+        assert row["containment"] == pytest.approx(
+            row["intersect_hashes"] / row["query_n_hashes"]
+        )
+        if indexed:
+            # match sizes need --full-results on RocksDB
+            assert pandas.isna(row["match_n_hashes"])
+        else:
+            assert row["match_n_hashes"] == sizes[row["match_name"]]
+            assert row["match_bp"] == row["match_n_hashes"] * 1000
+
+    if indexed:
+        runtmp.sourmash(
+            "scripts",
+            "manysearch",
+            query_list,
+            against_list,
+            "-o",
+            output,
+            "--full-results",
+            "--force",
+        )
+        df = pandas.read_csv(output)
+        matches = df["match_name"].map(sizes)
+        assert list(df["match_n_hashes"]) == list(matches)
+        assert list(df["match_bp"]) == list(matches * 1000)
+
+
+def test_stoplist(runtmp, capfd, indexed):
+    # hashes in a --stoplist are removed from queries before comparison
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    if indexed:
+        against_list = index_siglist(runtmp, against_list, runtmp.output("db"))
+
+    # stoplist the hashes shared by 47 and 63.
+    mh47 = sourmash.load_one_signature(sig47, ksize=31).minhash
+    mh63 = sourmash.load_one_signature(sig63, ksize=31).minhash
+    shared = mh47.intersection(mh63)
+    assert len(shared)
+    stoplist = runtmp.output("stoplist.sig")
+    with open(stoplist, "wb") as fp:
+        sourmash.save_signatures([sourmash.SourmashSignature(shared, name="stop")], fp)
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts",
+        "manysearch",
+        query_list,
+        against_list,
+        "-o",
+        output,
+        "--stoplist",
+        stoplist,
+    )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert (
+        f"Removed {2 * len(shared)} stoplist hashes from 2 of 3 queries"
+        in captured.err
+    )
+
+    # only the self matches are left.
+    df = pandas.read_csv(output)
+    assert len(df) == 3
+    assert list(df["query_name"]) == list(df["match_name"])
+
+
+def test_match_metadata(runtmp, capfd, indexed):
+    # --match-metadata adds columns for each match from a metadata CSV
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    if indexed:
+        against_list = index_siglist(runtmp, against_list, runtmp.output("db"))
+
+    # 63 (NC_011665.1) is not listed; 47 is listed without its version.
+    metadata = runtmp.output("metadata.csv")
+    with open(metadata, "w") as fp:
+        fp.write("name,biome,study\n")
+        fp.write("CP001071.1,soil,study1\n")
+        fp.write("NC_009661,marine,study2\n")
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts",
+        "manysearch",
+        query_list,
+        against_list,
+        "-o",
+        output,
+        "--match-metadata",
+        metadata,
+    )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "WARNING: 2 rows have a match not listed in metadata file" in captured.err
+
+    df = pandas.read_csv(output, keep_default_na=False)
+    assert len(df) == 5
+    assert list(df.columns[-2:]) == ["biome", "study"]
+    for row in df.to_dict(orient="records"):
+        ident = row["match_name"].split()[0]
+        expected = {
+            "CP001071.1": ("soil", "study1"),
+            "NC_009661.1": ("marine", "study2"),
+            "NC_011665.1": ("", ""),
+        }[ident]
+        assert (row["biome"], row["study"]) == expected
+
+
+def test_match_metadata_column_clash(runtmp, capfd):
+    # metadata columns must not have the same names as output columns
+    query = get_test_data("47.fa.sig.gz")
+    against = get_test_data("63.fa.sig.gz")
+
+    metadata = runtmp.output("metadata.csv")
+    with open(metadata, "w") as fp:
+        fp.write("name,containment\n")
+        fp.write("NC_011665.1,high\n")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "manysearch",
+            query,
+            against,
+            "-o",
+            runtmp.output("out.csv"),
+            "--match-metadata",
+            metadata,
+        )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "metadata column(s) containment clash with output columns" in captured.err
@@ -0,0 +1,171 @@
+"""
+Test 'sourmash scripts merge'
+"""
+
+import os
+import pytest
+import sourmash
+
+from . import sourmash_tst_utils as utils
+from .sourmash_tst_utils import (
+    get_test_data,
+    make_file_list,
+    zip_siglist,
+)
+
+
+def load_sigs(filename):
+    return {
+        ss.name: ss for ss in sourmash.load_file_as_signatures(filename, ksize=31)
+    }
+
+
+def write_groups(filename, rows):
+    with open(filename, "wt") as fp:
+        fp.write("group,name\n")
+        for group, name in rows:
+            fp.write(f"{group},{name}\n")
+
+
+def test_installed(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "merge")
+
+    assert "usage:  merge" in runtmp.last_result.err
+
+
+@pytest.mark.parametrize("zip_input", [False, True])
+def test_simple(runtmp, capfd, zip_input):
+    # merge by group
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    sig_list = runtmp.output("sigs.txt")
+    make_file_list(sig_list, [sig2, sig47, sig63])
+    if zip_input:
+        sig_list = zip_siglist(runtmp, sig_list, runtmp.output("sigs.zip"))
+
+    groups = runtmp.output("groups.csv")
+    write_groups(
+        groups,
+        [("shewanella", "NC_009661.1"), ("shewanella", "NC_011665.1")],
+    )
+
+    output = runtmp.output("out.zip")
+    runtmp.sourmash("scripts", "merge", sig_list, "-g", groups, "-o", output)
+
+    sigs = load_sigs(output)
+    assert list(sigs) == ["shewanella"]
+    mh = sigs["shewanella"].minhash
+
+    # compare to python merge
+    mh47 = sourmash.load_one_signature(sig47, ksize=31).minhash
+    mh63 = sourmash.load_one_signature(sig63, ksize=31).minhash
+    assert set(mh.hashes) == set(mh47.hashes) | set(mh63.hashes)
+    assert len(mh) == len(mh47) + len(mh63) - 2529
+
+    captured = capfd.readouterr()
+    assert "1 sketches are not in any group" in captured.err
+
+
+def test_multiple_groups(runtmp):
+    # a sketch may be in more than one group
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    sig_list = runtmp.output("sigs.txt")
+    make_file_list(sig_list, [sig2, sig47, sig63])
+
+    groups = runtmp.output("groups.csv")
+    write_groups(
+        groups,
+        [
+            ("a", "CP001071.1"),
+            ("a", "NC_009661.1"),
+            ("b", "NC_009661.1"),
+            ("b", "NC_011665.1"),
+        ],
+    )
+
+    output = runtmp.output("out.zip")
+    runtmp.sourmash("scripts", "merge", sig_list, "-g", groups, "-o", output)
+
+    mh2 = sourmash.load_one_signature(sig2, ksize=31).minhash
+    mh47 = sourmash.load_one_signature(sig47, ksize=31).minhash
+    mh63 = sourmash.load_one_signature(sig63, ksize=31).minhash
+
+    sigs = load_sigs(output)
+    assert set(sigs) == {"a", "b"}
+    assert set(sigs["a"].minhash.hashes) == set(mh2.hashes) | set(mh47.hashes)
+    assert set(sigs["b"].minhash.hashes) == set(mh47.hashes) | set(mh63.hashes)
+
+    # output is a zip with a manifest
+    idx = sourmash.load_file_as_index(output)
+    assert len(idx.manifest) == 2
+
+
+def test_abundances_summed(runtmp):
+    # abundances are summed when all sketches track abundance
+    sig = get_test_data("SRR606249.sig.gz")
+
+    sig_list = runtmp.output("sigs.txt")
+    make_file_list(sig_list, [sig, sig])
+
+    groups = runtmp.output("groups.csv")
+    write_groups(groups, [("doubled", "SRR606249")])
+
+    output = runtmp.output("out.zip")
+    runtmp.sourmash("scripts", "merge", sig_list, "-g", groups, "-o", output)
+
+    orig = sourmash.load_one_signature(sig, ksize=31).minhash
+    mh = load_sigs(output)["doubled"].minhash
+    assert mh.track_abundance
+    assert mh.hashes == {k: 2 * v for k, v in orig.hashes.items()}
+
+
+def test_abundances_flattened(runtmp):
+    # merging with a flat sketch produces a flat sketch
+    sig_abund = get_test_data("SRR606249.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+
+    sig_list = runtmp.output("sigs.txt")
+    make_file_list(sig_list, [sig_abund, sig47])
+
+    groups = runtmp.output("groups.csv")
+    write_groups(groups, [("mixed", "SRR606249"), ("mixed", "NC_009661.1")])
+
+    output = runtmp.output("out.zip")
+    runtmp.sourmash("scripts", "merge", sig_list, "-g", groups, "-o", output)
+
+    mh = load_sigs(output)["mixed"].minhash
+    assert not mh.track_abundance
+
+
+def test_groups_no_match(runtmp, capfd):
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    sig_list = runtmp.output("sigs.txt")
+    make_file_list(sig_list, [sig47, sig63])
+
+    groups = runtmp.output("groups.csv")
+    write_groups(groups, [("a", "no-such-sketch")])
+
+    output = runtmp.output("out.zip")
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "merge", sig_list, "-g", groups, "-o", output)
+
+    captured = capfd.readouterr()
+    assert "No sketches matched the names" in captured.err
+    assert not os.path.exists(output)
+
+
+def test_groups_required(runtmp):
+    sig47 = get_test_data("47.fa.sig.gz")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "merge", sig47, "-o", runtmp.output("out.zip"))
+
+    assert "--groups" in runtmp.last_result.err
@@ -0,0 +1,210 @@
+import os
+import pytest
+import pandas
+
+from .sourmash_tst_utils import (
+    get_test_data,
+    make_file_list,
+    zip_siglist,
+)
+
+from sourmash_plugin_branchwater import sourmash_plugin_branchwater as api
+
+
+def test_load_pathlist(runtmp):
+    # load a pathlist & check len/records
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    coll = api.MultiCollection(against_list)
+    assert len(coll) == 3
+    assert coll.max_scaled == 1000
+
+    records = list(coll)
+    assert len(records) == 3
+    assert len({r["md5"] for r in records}) == 3
+    assert {r["ksize"] for r in records} == {31}
+
+
+def test_load_zip_and_select(runtmp):
+    # select down to one ksize; original collection is unchanged
+    against_list = runtmp.output("against.txt")
+
+    sig2_k21 = get_test_data("2.fa.k21.sig.gz")
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+
+    make_file_list(against_list, [sig2_k21, sig2, sig47])
+    against_zip = zip_siglist(runtmp, against_list, runtmp.output("against.zip"))
+
+    coll = api.MultiCollection(against_zip)
+    assert len(coll) == 3
+
+    k31 = coll.select(31)
+    assert len(k31) == 2
+    assert len(coll.select(21)) == 1
+    assert len(coll) == 3
+
+    assert len(coll.select(31, moltype="protein")) == 0
+
+
+def test_load_nonexistent(runtmp):
+    with pytest.raises(Exception):
+        api.MultiCollection(runtmp.output("no-such-file.zip"))
+
+
+def test_manysearch_with_collection(runtmp):
+    # loaded collections can be searched directly, multiple times
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    against = api.MultiCollection(against_list).select(31)
+
+    output = runtmp.output("out.csv")
+    status = api.do_manysearch(query_list, against, 0.01, 31, None, "DNA", output)
+    assert status == 0
+    df = pandas.read_csv(output)
+    assert len(df) == 5
+
+    results = list(api.manysearch_iter(query_list, against, 0.01, 31, None, "DNA"))
+    assert len(results) == 5
+
+    results = api.do_multisearch_df(against, against, 0.01, 31, None, "DNA")
+    assert len(results) == 5
+
+
+def test_fastgather_with_collection(runtmp):
+    # pass a loaded against collection into fastgather
+    query = get_test_data("SRR606249.sig.gz")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(against_list, [sig2, sig47, sig63])
+    against = api.MultiCollection(against_list)
+
+    g_output = runtmp.output("gather.csv")
+    status = api.do_fastgather(query, against, 50000, 31, 100000, "DNA", g_output)
+    assert status == 0
+    assert os.path.exists(g_output)
+
+    df = pandas.read_csv(g_output)
+    assert len(df) == 3
+
+
+def test_typed_errors_missing_file(runtmp):
+    # missing files raise MissingFileError, a BranchwaterError
+    with pytest.raises(api.MissingFileError) as exc:
+        api.MultiCollection(runtmp.output("no-such-file.zip"))
+
+    assert isinstance(exc.value, api.BranchwaterError)
+    assert "No such file or directory" in str(exc.value)
+
+    against = get_test_data("2.fa.sig.gz")
+    with pytest.raises(api.MissingFileError):
+        api.do_multisearch_df(
+            runtmp.output("no-such-file"), against, 0.01, 31, None, "DNA"
+        )
+
+
+def test_typed_errors_empty_collection(runtmp):
+    # selecting nothing raises EmptyCollectionError
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+
+    with pytest.raises(api.EmptyCollectionError) as exc:
+        api.do_multisearch_df(sig2, sig47, 0.01, 21, None, "DNA")
+
+    assert "No query signatures loaded" in str(exc.value)
+
+
+def test_typed_errors_bad_json(runtmp):
+    # non-specific errors are still BranchwaterErrors
+    sig47 = get_test_data("47.fa.sig.gz")
+
+    with pytest.raises(api.BranchwaterError):
+        api.do_multisearch_df("[not json", sig47, 0.01, 31, None, "DNA")
+
+
+def test_typed_errors_unknown_moltype(runtmp):
+    # unknown molecule types raise IncompatibleSelectionError
+    sig2 = get_test_data("2.fa.sig.gz")
+    coll = api.MultiCollection(sig2)
+
+    with pytest.raises(api.IncompatibleSelectionError) as exc:
+        coll.select(31, moltype="RNA")
+
+    assert "unknown molecule type 'RNA'" in str(exc.value)
+
+    with pytest.raises(api.IncompatibleSelectionError):
+        api.do_multisearch_df(sig2, sig2, 0.01, 31, None, "RNA")
+
+
+def test_loaded_sketches(runtmp):
+    # load sketches once, and run several analyses on them
+    sigs = [get_test_data(f"{n}.fa.sig.gz") for n in (2, 47, 63)]
+    sig_list = runtmp.output("sigs.txt")
+    make_file_list(sig_list, sigs)
+
+    sketches = api.LoadedSketches(sig_list, ksize=31)
+    assert len(sketches) == 3
+    assert sketches.scaled == 1000
+    assert sketches.ksize == 31
+    names = sorted(sketches.names)
+
+    results = sketches.pairwise(estimate_ani=True)
+    assert len(results) == 1
+    assert results[0]["average_containment_ani"] > 0.95
+
+    results = sketches.pairwise(both_directions=True, write_all=True)
+    assert len(results) == 5
+
+    distances = runtmp.output("dist.npy")
+    sketches.pairwise(output_distances=distances)
+    assert os.path.exists(distances)
+    assert os.path.exists(distances + ".labels.txt")
+
+    # 47 and 63 are linked; 2 is on its own.
+    clusters = sketches.cluster(similarity_threshold=0.95)
+    assert sorted(map(len, clusters)) == [1, 2]
+    assert sorted(name for c in clusters for name in c) == names
+
+    clusters = sketches.cluster(similarity_column="jaccard", similarity_threshold=0.9)
+    assert len(clusters) == 3
+
+    results = sketches.multisearch(knn=1)
+    assert len(results) == 3
+    assert all(r["query_md5"] == r["match_md5"] for r in results)
+
+    results = sketches.multisearch(knn=1, exclude_self_matches=True)
+    assert len(results) == 2
+
+
+def test_loaded_sketches_against(runtmp):
+    # multisearch between two sets of loaded sketches
+    queries = api.LoadedSketches(get_test_data("47.fa.sig.gz"), ksize=31)
+    against = api.LoadedSketches(get_test_data("63.fa.sig.gz"), ksize=31)
+
+    results = queries.multisearch(against)
+    assert len(results) == 1
+    assert results[0]["match_name"].startswith("NC_011665.1")
+
+    downsampled = api.LoadedSketches(
+        get_test_data("63.fa.sig.gz"), ksize=31, scaled=10000
+    )
+    with pytest.raises(api.IncompatibleSelectionError):
+        queries.multisearch(downsampled)
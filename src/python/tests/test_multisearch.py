@@ -1,7 +1,10 @@
 import os
 import csv
+import json
 import pytest
 import pandas
+import pyarrow.ipc
+import sqlite3
 import sourmash
 
 from . import sourmash_tst_utils as utils
@@ -48,6 +51,7 @@ def test_simple_no_ani(runtmp, zip_query, zip_db):
 
     df = pandas.read_csv(output)
     assert len(df) == 5
+    assert list(df["intersect_bp"]) == list(df["intersect_hashes"] * 1000)
 
     dd = df.to_dict(orient="index")
     print(dd)
@@ -851,7 +855,7 @@ def test_nomatch_query_exit(runtmp, capfd, zip_query):
 
     with pytest.raises(utils.SourmashCommandFailed):
         runtmp.sourmash(
-            "scripts", "multisearch", query_list, against_list, "-o", output
+            "scripts", "multisearch", query_list, against_list, "-o", output, "-k", "31"
         )
 
     captured = capfd.readouterr()
@@ -1549,3 +1553,892 @@ def test_simple_scaled_heterogeneous_a(runtmp, zip_query, zip_db):
     assert (
         len(df) == 3
     )  # CTB: this feels slightly odd - it's dropping the 10k sketch in against
+
+
+def test_multisearch_df_api(runtmp):
+    # test the Python API that returns results directly, vs CSV output
+    from sourmash_plugin_branchwater import sourmash_plugin_branchwater as api
+
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts", "multisearch", query_list, against_list, "-o", output, "--ani"
+    )
+    csv_df = pandas.read_csv(output)
+
+    results = api.do_multisearch_df(
+        query_list, against_list, 0.01, 31, None, "DNA", estimate_ani=True
+    )
+    assert len(results) == len(csv_df) == 5
+
+    df = pandas.DataFrame(results)
+    assert set(df.columns) == set(csv_df.columns)
+
+    df = df.sort_values(["query_name", "match_name"]).reset_index(drop=True)
+    csv_df = csv_df.sort_values(["query_name", "match_name"]).reset_index(drop=True)
+    assert list(df["query_md5"]) == list(csv_df["query_md5"])
+    assert list(df["match_md5"]) == list(csv_df["match_md5"])
+    assert list(df["intersect_hashes"]) == list(csv_df["intersect_hashes"])
+    for a, b in zip(df["max_containment_ani"], csv_df["max_containment_ani"]):
+        assert round(a, 4) == round(b, 4)
+
+
+def test_multisearch_df_api_bad_query(runtmp):
+    # errors should be raised as Python exceptions
+    from sourmash_plugin_branchwater import sourmash_plugin_branchwater as api
+
+    against = get_test_data("2.fa.sig.gz")
+
+    with pytest.raises(Exception):
+        api.do_multisearch_df(
+            runtmp.output("no-such-file"), against, 0.01, 31, None, "DNA"
+        )
+
+
+def test_multisearch_progress_and_cancel(runtmp):
+    # progress callbacks fire from the Rust loop; cancelling stops the run
+    from sourmash_plugin_branchwater import sourmash_plugin_branchwater as api
+
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    output = runtmp.output("out.csv")
+    token = api.CancelToken()
+    calls = []
+
+    def progress(n_done, n_total):
+        calls.append((n_done, n_total))
+        token.cancel()
+
+    status = api.do_multisearch(
+        query_list,
+        against_list,
+        0.01,
+        31,
+        None,
+        "DNA",
+        False,
+        False,
+        False,
+        output,
+        progress=progress,
+        cancel=token,
+        progress_interval=1,
+    )
+    assert status == 1
+    assert calls
+    assert calls[0][1] == 9
+
+
+def test_output_graph(runtmp):
+    # multisearch graphs have one edge per pair, even when both
+    # directions are compared
+    query_list = runtmp.output("query.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    output = runtmp.output("out.csv")
+    graph = runtmp.output("out.gv")
+
+    runtmp.sourmash(
+        "scripts",
+        "multisearch",
+        query_list,
+        query_list,
+        "-o",
+        output,
+        "--output-graph",
+        graph,
+        "--graph-weight",
+        "containment",
+    )
+
+    df = pandas.read_csv(output)
+    assert len(df) == 5
+
+    with open(graph) as fp:
+        lines = fp.read().splitlines()
+    print(lines)
+
+    nodes = [line for line in lines if "[label=" in line]
+    edges = [line for line in lines if " -- " in line]
+    assert len(nodes) == 3
+    assert len(edges) == 1
+
+    # the higher of the two directional containments is kept
+    weight = float(edges[0].split("weight=")[1].rstrip("];"))
+    assert round(weight, 4) == 0.4885
+
+
+def test_coverage_report(runtmp, capfd):
+    # report coverage of each against by the union of queries, and vice versa
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig63])
+    make_file_list(against_list, [sig47])
+
+    output = runtmp.output("out.csv")
+    coverage = runtmp.output("coverage.csv")
+
+    runtmp.sourmash(
+        "scripts",
+        "multisearch",
+        query_list,
+        against_list,
+        "-o",
+        output,
+        "--coverage-report",
+        coverage,
+    )
+    assert os.path.exists(coverage)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "0 against sketches share no hashes with any query" in captured.err
+
+    ss2 = sourmash.load_one_signature(sig2, ksize=31)
+    ss47 = sourmash.load_one_signature(sig47, ksize=31)
+    ss63 = sourmash.load_one_signature(sig63, ksize=31)
+    n_shared = len(set(ss47.minhash.hashes) & set(ss63.minhash.hashes))
+
+    df = pandas.read_csv(coverage)
+    assert list(df["role"]) == ["query", "query", "against"]
+    rows = {(row["role"], row["md5"]): row for row in df.to_dict(orient="records")}
+
+    assert rows[("query", ss2.md5sum())]["n_covered"] == 0
+    assert rows[("query", ss63.md5sum())]["n_covered"] == n_shared
+
+    row = rows[("against", ss47.md5sum())]
+    assert row["n_covered"] == n_shared
+    assert row["n_hashes"] == len(ss47.minhash)
+
+
+def test_knn(runtmp):
+    # keep only the K most similar matches for each query
+    query_list = runtmp.output("query.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    all_output = runtmp.output("all.csv")
+    runtmp.sourmash(
+        "scripts", "multisearch", query_list, query_list, "-o", all_output, "-A"
+    )
+    all_df = pandas.read_csv(all_output)
+    assert len(all_df) == 9
+
+    output = runtmp.output("knn.csv")
+    runtmp.sourmash(
+        "scripts",
+        "multisearch",
+        query_list,
+        query_list,
+        "-o",
+        output,
+        "-A",
+        "--knn",
+        "2",
+    )
+
+    df = pandas.read_csv(output)
+    assert len(df) == 6
+
+    for query_name, group in df.groupby("query_name", sort=False):
+        assert len(group) == 2
+        # most similar first, and the best match is the query itself.
+        assert list(group["max_containment"]) == sorted(
+            group["max_containment"], reverse=True
+        )
+        assert group["match_name"].iloc[0] == query_name
+
+        expected = all_df[all_df["query_name"] == query_name]
+        expected = sorted(expected["max_containment"], reverse=True)[:2]
+        assert list(group["max_containment"]) == expected
+
+
+def test_knn_threshold(runtmp):
+    # without -A, --knn picks from the matches above threshold
+    query_list = runtmp.output("query.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    output = runtmp.output("knn.csv")
+    runtmp.sourmash(
+        "scripts",
+        "multisearch",
+        query_list,
+        query_list,
+        "-o",
+        output,
+        "--knn",
+        "5",
+    )
+
+    df = pandas.read_csv(output)
+    # 3 self matches, plus 47 <-> 63 in both directions.
+    assert len(df) == 5
+
+
+def test_knn_zero(runtmp, capfd):
+    query_list = runtmp.output("query.txt")
+    make_file_list(query_list, [get_test_data("2.fa.sig.gz")])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "multisearch",
+            query_list,
+            query_list,
+            "-o",
+            runtmp.output("knn.csv"),
+            "--knn",
+            "0",
+        )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "--knn must be at least 1" in captured.err
+
+
+def test_angular_similarity(runtmp):
+    # report abundance-weighted angular similarity
+    sigs = []
+    for name in ["short", "short2", "short3"]:
+        out = runtmp.output(f"{name}.sig")
+        runtmp.sourmash(
+            "scripts",
+            "singlesketch",
+            get_test_data(f"{name}.fa"),
+            "-o",
+            out,
+            "-p",
+            "k=31,scaled=1,abund",
+        )
+        sigs.append(out)
+
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+    make_file_list(query_list, sigs[:1])
+    make_file_list(against_list, sigs)
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts",
+        "multisearch",
+        query_list,
+        against_list,
+        "-o",
+        output,
+        "-A",
+        "--angular-similarity",
+    )
+
+    df = pandas.read_csv(output)
+    assert len(df) == 3
+
+    query_mh = sourmash.load_one_signature(sigs[0]).minhash
+    for row in df.to_dict(orient="records"):
+        match_mh = [
+            ss.minhash
+            for ss in map(sourmash.load_one_signature, sigs)
+            if ss.md5sum() == row["match_md5"]
+        ][0]
+        expected = query_mh.angular_similarity(match_mh)
+        assert round(row["angular_similarity"], 5) == round(expected, 5)
+
+
+def test_angular_similarity_requires_abund(runtmp, capfd):
+    query_list = runtmp.output("query.txt")
+    make_file_list(query_list, [get_test_data("2.fa.sig.gz")])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "multisearch",
+            query_list,
+            query_list,
+            "-o",
+            runtmp.output("out.csv"),
+            "--angular-similarity",
+        )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "1 query sketches have none" in captured.err
+
+
+def test_exclude_self_matches(runtmp):
+    # skip matches between identical sketches, by md5
+    query_list = runtmp.output("query.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts",
+        "multisearch",
+        query_list,
+        query_list,
+        "-o",
+        output,
+        "--exclude-self-matches",
+    )
+
+    df = pandas.read_csv(output)
+    # only 47 <-> 63, in both directions.
+    assert len(df) == 2
+    assert (df["query_md5"] != df["match_md5"]).all()
+
+    runtmp.sourmash(
+        "scripts",
+        "multisearch",
+        query_list,
+        query_list,
+        "-o",
+        output,
+        "--include-self-matches",
+        "--force",
+    )
+
+    df = pandas.read_csv(output)
+    assert len(df) == 5
+
+
+def test_self_matches_exclusive(runtmp):
+    query_list = runtmp.output("query.txt")
+    make_file_list(query_list, [get_test_data("2.fa.sig.gz")])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "multisearch",
+            query_list,
+            query_list,
+            "-o",
+            runtmp.output("out.csv"),
+            "--include-self-matches",
+            "--exclude-self-matches",
+        )
+
+    assert "not allowed with argument" in runtmp.last_result.err
+
+
+def test_output_columns(runtmp):
+    # optional columns may be selected; they are empty unless calculated
+    query_list = runtmp.output("query.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts",
+        "multisearch",
+        query_list,
+        query_list,
+        "-o",
+        output,
+        "--ani",
+        "--output-columns",
+        "query_md5,match_md5,max_containment_ani,angular_similarity",
+    )
+
+    df = pandas.read_csv(output)
+    print(df)
+    assert list(df.columns) == [
+        "query_md5",
+        "match_md5",
+        "max_containment_ani",
+        "angular_similarity",
+    ]
+    assert len(df) == 5
+    assert df["max_containment_ani"].notnull().all()
+    assert df["angular_similarity"].isnull().all()
+
+
+def test_profile_json(runtmp):
+    # a profile ending in .json is written as JSON
+    query_list = runtmp.output("query.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    profile = runtmp.output("profile.json")
+    runtmp.sourmash(
+        "scripts",
+        "multisearch",
+        query_list,
+        query_list,
+        "-o",
+        runtmp.output("out.csv"),
+        "--profile",
+        profile,
+    )
+
+    with open(profile) as fp:
+        report = json.load(fp)
+    print(report)
+    assert report["command"] == "multisearch"
+    assert set(report["stages"]) == {"loading", "selection", "comparison", "writing"}
+    assert report["total_seconds"] >= report["stages"]["comparison"]
+
+
+def test_min_hashes(runtmp, capfd):
+    # --min-hashes applies to both queries and against sketches
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts",
+        "multisearch",
+        query_list,
+        against_list,
+        "-o",
+        output,
+        "--min-hashes",
+        "5200",
+    )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "WARNING: skipped 2 sketches without at least 5200 hashes." in captured.err
+
+    df = pandas.read_csv(output)
+    assert len(df) == 1
+    assert df["query_name"][0] == df["match_name"][0]
+    assert df["query_name"][0].startswith("NC_011665.1")
+
+
+def test_query_groups(runtmp, capfd):
+    # summarize containment by query group
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    groups = runtmp.output("groups.csv")
+    with open(groups, "wt") as fp:
+        fp.write("group,name\n")
+        fp.write("shewanella,NC_009661.1\n")
+        fp.write("shewanella,NC_011665.1\n")
+        fp.write("other,CP001071.1\n")
+
+    output = runtmp.output("out.csv")
+    grouped = runtmp.output("groups-out.csv")
+    runtmp.sourmash(
+        "scripts",
+        "multisearch",
+        query_list,
+        against_list,
+        "-o",
+        output,
+        "--query-groups",
+        groups,
+        "--output-groups",
+        grouped,
+    )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "Wrote 3 group summaries" in captured.err
+
+    # per-query results are still written
+    assert len(pandas.read_csv(output)) == 5
+
+    df = pandas.read_csv(grouped)
+    print(df)
+    assert list(df.columns) == [
+        "group",
+        "n_members",
+        "match_name",
+        "match_md5",
+        "max_query_containment",
+        "mean_query_containment",
+        "best_query_name",
+        "n_matching_queries",
+    ]
+    rows = df.to_dict(orient="records")
+    assert [(r["group"], r["match_name"].split()[0]) for r in rows] == [
+        ("other", "CP001071.1"),
+        ("shewanella", "NC_009661.1"),
+        ("shewanella", "NC_011665.1"),
+    ]
+
+    other, s47, s63 = rows
+    assert other["n_members"] == 1
+    assert other["max_query_containment"] == 1.0
+    assert other["mean_query_containment"] == 1.0
+
+    assert s47["n_members"] == 2
+    assert s47["n_matching_queries"] == 2
+    assert s47["max_query_containment"] == 1.0
+    assert s47["best_query_name"].startswith("NC_009661.1")
+    assert round(s47["mean_query_containment"], 4) == round((1 + 2529 / 5238) / 2, 4)
+
+    assert s63["best_query_name"].startswith("NC_011665.1")
+    assert round(s63["mean_query_containment"], 4) == round((1 + 2529 / 5177) / 2, 4)
+
+
+def test_query_groups_requires_output(runtmp, capfd):
+    # --query-groups and --output-groups go together
+    query_list = runtmp.output("query.txt")
+    sig47 = get_test_data("47.fa.sig.gz")
+    make_file_list(query_list, [sig47])
+
+    groups = runtmp.output("groups.csv")
+    with open(groups, "wt") as fp:
+        fp.write("group,name\n")
+        fp.write("shewanella,NC_009661.1\n")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "multisearch",
+            query_list,
+            query_list,
+            "-o",
+            runtmp.output("out.csv"),
+            "--query-groups",
+            groups,
+        )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "--query-groups and --output-groups must be given together" in captured.err
+
+
+def run_all_vs_all(runtmp, output, *extra):
+    sigs = [get_test_data(f"{n}.fa.sig.gz") for n in (2, 47, 63)]
+    sig_list = runtmp.output("sigs.txt")
+    make_file_list(sig_list, sigs)
+    runtmp.sourmash("scripts", "multisearch", sig_list, sig_list, "-o", output, *extra)
+
+
+def test_output_shard_size(runtmp, capfd):
+    output = runtmp.output("out.csv")
+    run_all_vs_all(runtmp, output, "--output-shard-size", "2")
+
+    shards = [output] + [runtmp.output(f"out.part000{n}.csv") for n in (1, 2)]
+    assert [len(pandas.read_csv(shard)) for shard in shards] == [2, 2, 1]
+    assert not os.path.exists(runtmp.output("out.part0003.csv"))
+
+    captured = capfd.readouterr()
+    assert "Wrote 5 rows to 3 output shards." in captured.err
+
+
+def test_append(runtmp):
+    output = runtmp.output("out.csv")
+    run_all_vs_all(runtmp, output)
+    run_all_vs_all(runtmp, output, "--append")
+
+    df = pandas.read_csv(output)
+    assert len(df) == 10
+    assert "query_name" not in set(df["query_name"])
+
+    # selected columns, too
+    output = runtmp.output("cols.csv")
+    columns = ["--output-columns", "query_name,match_name"]
+    run_all_vs_all(runtmp, output, *columns, "--append")
+    run_all_vs_all(runtmp, output, *columns, "--append")
+
+    df = pandas.read_csv(output)
+    assert list(df.columns) == ["query_name", "match_name"]
+    assert len(df) == 10
+
+
+def test_append_with_shards(runtmp, capfd):
+    output = runtmp.output("out.csv")
+    with pytest.raises(utils.SourmashCommandFailed):
+        run_all_vs_all(runtmp, output, "--append", "--output-shard-size", "2")
+
+    captured = capfd.readouterr()
+    assert "--append and --output-shard-size cannot be used together" in captured.err
+
+
+def test_output_format_arrow(runtmp):
+    csv_output = runtmp.output("out.csv")
+    run_all_vs_all(runtmp, csv_output)
+    output = runtmp.output("out.arrow")
+    run_all_vs_all(runtmp, output, "--output-format", "arrow")
+
+    with pyarrow.ipc.open_stream(output) as reader:
+        table = reader.read_all()
+    assert table.schema.field("match_name").type == pyarrow.string()
+    assert table.schema.field("intersect_bp").type == pyarrow.uint64()
+    assert table.schema.field("containment").type == pyarrow.float64()
+
+    df = table.to_pandas()
+    expected = pandas.read_csv(csv_output)
+    assert list(df.columns) == list(expected.columns)
+    assert list(df["match_name"]) == list(expected["match_name"])
+    assert list(df["containment"]) == pytest.approx(list(expected["containment"]))
+
+
+def test_output_format_arrow_with_fdr(runtmp, capfd):
+    output = runtmp.output("out.arrow")
+    with pytest.raises(utils.SourmashCommandFailed):
+        run_all_vs_all(runtmp, output, "--output-format", "arrow", "--fdr")
+
+    captured = capfd.readouterr()
+    assert "--fdr can only add q-values to CSV output" in captured.err
+
+
+def test_output_sqlite(runtmp):
+    csv_output = runtmp.output("out.csv")
+    run_all_vs_all(runtmp, csv_output)
+    output = runtmp.output("out.db")
+    run_all_vs_all(runtmp, output)
+
+    expected = pandas.read_csv(csv_output)
+    with sqlite3.connect(output) as conn:
+        df = pandas.read_sql("SELECT * FROM multisearch", conn)
+        indexes = conn.execute(
+            "SELECT name FROM sqlite_master WHERE type = 'index'"
+        ).fetchall()
+    assert list(df.columns) == list(expected.columns)
+    assert sorted(df["match_name"]) == sorted(expected["match_name"])
+    assert {name for (name,) in indexes} == {
+        "multisearch_query_name",
+        "multisearch_match_name",
+    }
+
+    # --append adds rows to the table.
+    run_all_vs_all(runtmp, output, "--append")
+    with sqlite3.connect(output) as conn:
+        (n_rows,) = conn.execute("SELECT COUNT(*) FROM multisearch").fetchone()
+    assert n_rows == 2 * len(expected)
+
+
+def test_dedup_by_md5(runtmp):
+    sigs = [get_test_data(f"{n}.fa.sig.gz") for n in (2, 47, 63)]
+    alias63 = runtmp.output("alias63.sig")
+    runtmp.sourmash("sig", "rename", sigs[2], "alias of 63", "-o", alias63)
+
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+    make_file_list(query_list, sigs)
+    make_file_list(against_list, sigs + [alias63])
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts",
+        "multisearch",
+        query_list,
+        against_list,
+        "-o",
+        output,
+        "--dedup-by-md5",
+    )
+    df = pandas.read_csv(output, keep_default_na=False)
+    assert len(df) == 5
+    assert set(df["match_aliases"]) == {"", "alias of 63"}
+
+
+def test_sketch_sizes(runtmp):
+    # query and match sizes are reported, at the search scaled
+    output = runtmp.output("out.csv")
+    run_all_vs_all(runtmp, output)
+
+    sizes = {}
+    for n in (2, 47, 63):
+        ss = sourmash.load_one_signature(get_test_data(f"{n}.fa.sig.gz"), ksize=31)
+        sizes[ss.name] = len(ss.minhash)
+
+    df = pandas.read_csv(output)
+    assert len(df) == 5
+    assert list(df["query_n_hashes"]) == list(df["query_name"].map(sizes))
+    assert list(df["match_n_hashes"]) == list(df["match_name"].map(sizes))
+    assert list(df["query_bp"]) == list(df["query_n_hashes"] * 1000)
+    assert list(df["match_bp"]) == list(df["match_n_hashes"] * 1000)
+
+
+def test_fdr(runtmp, capfd):
+    # chance overlaps are estimated per row, and q-values added afterwards
+    output = runtmp.output("out.csv")
+    run_all_vs_all(runtmp, output, "--fdr", "-A")
+
+    df = pandas.read_csv(output)
+    print(df)
+    assert len(df) == 9
+    assert list(df.columns[-3:]) == [
+        "overlap_pvalue",
+        "expected_false_positives",
+        "bh_qvalue",
+    ]
+    assert (df["expected_false_positives"] == df["overlap_pvalue"] * 9).all()
+    assert (df["bh_qvalue"] >= df["overlap_pvalue"]).all()
+    assert (df["bh_qvalue"] <= 1).all()
+
+    # no overlap at all is no evidence of anything.
+    no_overlap = df[df["intersect_hashes"] == 0]
+    assert len(no_overlap) == 4
+    assert (no_overlap["overlap_pvalue"] == 1).all()
+    assert (no_overlap["bh_qvalue"] == 1).all()
+
+    # self matches are as significant as can be.
+    self_matches = df[df["query_md5"] == df["match_md5"]]
+    assert (self_matches["bh_qvalue"] < 1e-10).all()
+
+    captured = capfd.readouterr()
+    assert "Added Benjamini-Hochberg q-values for 9 rows" in captured.err
+
+
+def test_fdr_output_columns(runtmp):
+    # a selected bh_qvalue column is filled in place
+    output = runtmp.output("out.csv")
+    run_all_vs_all(
+        runtmp,
+        output,
+        "--fdr",
+        "--output-columns",
+        "query_md5,match_md5,bh_qvalue,overlap_pvalue",
+    )
+
+    df = pandas.read_csv(output)
+    print(df)
+    assert list(df.columns) == [
+        "query_md5",
+        "match_md5",
+        "bh_qvalue",
+        "overlap_pvalue",
+    ]
+    assert len(df) == 5
+    assert df["bh_qvalue"].notnull().all()
+
+
+def test_fdr_needs_pvalue_column(runtmp, capfd):
+    output = runtmp.output("out.csv")
+    with pytest.raises(utils.SourmashCommandFailed):
+        run_all_vs_all(
+            runtmp, output, "--fdr", "--output-columns", "query_md5,match_md5"
+        )
+
+    captured = capfd.readouterr()
+    assert "--fdr needs the 'overlap_pvalue' column in the output" in captured.err
+
+
+def test_fdr_needs_output_file(runtmp, capfd):
+    with pytest.raises(utils.SourmashCommandFailed):
+        run_all_vs_all(runtmp, "/dev/stdout", "--fdr")
+
+    captured = capfd.readouterr()
+    assert "--fdr needs an output file" in captured.err
+
+
+def test_rename_query_and_match(runtmp):
+    # --rename-query and --rename-match rewrite names in the output
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+    make_file_list(against_list, [sig2, sig47, sig63])
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts",
+        "multisearch",
+        query_list,
+        against_list,
+        "-o",
+        output,
+        "--rename-query",
+        r"s/^(\w+)\.\d+ .*/\1/",
+        "--rename-match",
+        "s/ .*//",
+        "--rename-match",
+        "s/_/-/g",
+    )
+
+    df = pandas.read_csv(output)
+    assert len(df) == 5
+    assert set(df["query_name"]) == {"CP001071", "NC_009661", "NC_011665"}
+    assert set(df["match_name"]) == {"CP001071.1", "NC-009661.1", "NC-011665.1"}
+
+    # the other columns are unchanged.
+    expected = runtmp.output("expected.csv")
+    runtmp.sourmash("scripts", "multisearch", query_list, against_list, "-o", expected)
+    expected = pandas.read_csv(expected)
+    assert list(df.columns) == list(expected.columns)
+    key = ["query_md5", "match_md5"]
+    df = df.sort_values(key).reset_index(drop=True)
+    expected = expected.sort_values(key).reset_index(drop=True)
+    assert list(df["containment"]) == list(expected["containment"])
+
+
+def test_rename_bad_rewrite(runtmp, capfd):
+    # rewrites must look like s/pattern/replacement/
+    sig47 = get_test_data("47.fa.sig.gz")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "multisearch",
+            sig47,
+            sig47,
+            "-o",
+            runtmp.output("out.csv"),
+            "--rename-match",
+            "s/foo/bar",
+        )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "invalid rewrite 's/foo/bar'" in captured.err
@@ -0,0 +1,129 @@
+"""
+Test 'sourmash scripts overlap'
+"""
+
+import os
+import pytest
+import pandas
+import sourmash
+
+from . import sourmash_tst_utils as utils
+from .sourmash_tst_utils import (
+    get_test_data,
+    make_file_list,
+    zip_siglist,
+)
+
+
+def test_installed(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "overlap")
+
+    assert "usage:  overlap" in runtmp.last_result.err
+
+
+@pytest.mark.parametrize("zip_input", [False, True])
+def test_simple(runtmp, zip_input):
+    # count hashes in each combination of two sketches
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    sig_list = runtmp.output("sigs.txt")
+    make_file_list(sig_list, [sig47, sig63])
+    if zip_input:
+        sig_list = zip_siglist(runtmp, sig_list, runtmp.output("sigs.zip"))
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash("scripts", "overlap", sig_list, "-o", output)
+    assert os.path.exists(output)
+
+    ss47 = sourmash.load_one_signature(sig47, ksize=31)
+    ss63 = sourmash.load_one_signature(sig63, ksize=31)
+
+    df = pandas.read_csv(output)
+    assert list(df.columns) == [ss47.name, ss63.name, "degree", "n_hashes", "bp"]
+    assert len(df) == 3
+
+    # sorted by decreasing n_hashes
+    rows = [
+        (bool(r[ss47.name]), bool(r[ss63.name]), r["degree"], r["n_hashes"], r["bp"])
+        for _, r in df.iterrows()
+    ]
+    assert rows == [
+        (False, True, 1, 2709, 2709000),
+        (True, False, 1, 2648, 2648000),
+        (True, True, 2, 2529, 2529000),
+    ]
+
+
+def test_three_sketches(runtmp):
+    # compare combination counts to python set operations
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    sig_list = runtmp.output("sigs.txt")
+    make_file_list(sig_list, [sig2, sig47, sig63])
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash("scripts", "overlap", sig_list, "-o", output)
+
+    sigs = [
+        sourmash.load_one_signature(filename, ksize=31)
+        for filename in (sig2, sig47, sig63)
+    ]
+    names = [ss.name for ss in sigs]
+    hashes = [set(ss.minhash.hashes) for ss in sigs]
+
+    expected = {}
+    for h in set.union(*hashes):
+        pattern = tuple(h in hs for hs in hashes)
+        expected[pattern] = expected.get(pattern, 0) + 1
+
+    df = pandas.read_csv(output)
+    assert list(df.columns[:3]) == names
+    got = {
+        tuple(bool(r[name]) for name in names): r["n_hashes"]
+        for _, r in df.iterrows()
+    }
+    assert got == expected
+    for _, r in df.iterrows():
+        assert r["degree"] == sum(bool(r[name]) for name in names)
+    assert list(df["n_hashes"]) == sorted(df["n_hashes"], reverse=True)
+    assert df["n_hashes"].sum() == len(set.union(*hashes))
+
+
+def test_stdout(runtmp, capfd):
+    # write to stdout by default
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    sig_list = runtmp.output("sigs.txt")
+    make_file_list(sig_list, [sig47, sig63])
+
+    runtmp.sourmash("scripts", "overlap", sig_list)
+
+    captured = capfd.readouterr()
+    print(captured.out)
+    assert "degree,n_hashes,bp" in captured.out
+    assert "True,True,2,2529,2529000" in captured.out
+
+
+def test_duplicate_names(runtmp, capfd):
+    # sketch names become columns, so must be unique
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+    name47 = sourmash.load_one_signature(sig47, ksize=31).name
+
+    renamed = runtmp.output("renamed.sig")
+    runtmp.sourmash("sig", "rename", sig63, name47, "-o", renamed)
+
+    sig_list = runtmp.output("sigs.txt")
+    make_file_list(sig_list, [sig47, renamed])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "overlap", sig_list, "-o", runtmp.output("out.csv"))
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "more than one sketch is named" in captured.err
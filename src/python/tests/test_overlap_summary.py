@@ -0,0 +1,142 @@
+"""
+Test 'sourmash scripts overlap-summary'
+"""
+
+import os
+import pytest
+import pandas
+import sourmash
+
+from . import sourmash_tst_utils as utils
+from .sourmash_tst_utils import (
+    get_test_data,
+    make_file_list,
+    zip_siglist,
+)
+
+
+def test_installed(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "overlap-summary")
+
+    assert "usage:  overlap-summary" in runtmp.last_result.err
+
+
+@pytest.mark.parametrize("zip_input", [False, True])
+def test_simple(runtmp, zip_input):
+    # compare the summary to python set operations
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    query_list = runtmp.output("query.txt")
+    against_list = runtmp.output("against.txt")
+    make_file_list(query_list, [sig47, sig63])
+    make_file_list(against_list, [sig2, sig63])
+    if zip_input:
+        query_list = zip_siglist(runtmp, query_list, runtmp.output("query.zip"))
+        against_list = zip_siglist(runtmp, against_list, runtmp.output("against.zip"))
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts", "overlap-summary", query_list, against_list, "-o", output
+    )
+    assert os.path.exists(output)
+
+    def hashes(*paths):
+        mins = set()
+        for path in paths:
+            ss = sourmash.load_one_signature(path, ksize=31)
+            mins.update(ss.minhash.hashes)
+        return mins
+
+    query_hashes = hashes(sig47, sig63)
+    against_hashes = hashes(sig2, sig63)
+
+    df = pandas.read_csv(output)
+    assert len(df) == 1
+    row = df.iloc[0]
+    assert row["ksize"] == 31
+    assert row["scaled"] == 1000
+    assert row["query_sketches"] == 2
+    assert row["against_sketches"] == 2
+    assert row["query_hashes"] == len(query_hashes)
+    assert row["against_hashes"] == len(against_hashes)
+    assert row["intersect_hashes"] == len(query_hashes & against_hashes)
+    assert row["union_hashes"] == len(query_hashes | against_hashes)
+    assert row["intersect_bp"] == row["intersect_hashes"] * 1000
+    assert round(row["jaccard"], 6) == round(
+        len(query_hashes & against_hashes) / len(query_hashes | against_hashes), 6
+    )
+    assert round(row["query_containment"], 6) == round(
+        len(query_hashes & against_hashes) / len(query_hashes), 6
+    )
+
+
+def test_ksizes(runtmp, capfd):
+    # one row per ksize; a collection compared to itself overlaps entirely
+    sig = get_test_data("1.combined.sig.gz")
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts",
+        "overlap-summary",
+        sig,
+        sig,
+        "-k",
+        "21",
+        "-k",
+        "31",
+        "-k",
+        "51",
+        "-o",
+        output,
+    )
+
+    df = pandas.read_csv(output)
+    assert list(df["ksize"]) == [21, 31, 51]
+    assert (df["intersect_hashes"] == df["query_hashes"]).all()
+    assert (df["union_hashes"] == df["against_hashes"]).all()
+    assert (df["jaccard"] == 1.0).all()
+
+    captured = capfd.readouterr()
+    assert "Across all ksizes" in captured.err
+
+
+def test_scaled(runtmp):
+    # --scaled downsamples both collections
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts", "overlap-summary", sig47, sig63, "-s", "10000", "-o", output
+    )
+
+    ss47 = sourmash.load_one_signature(sig47, ksize=31).minhash
+    ss63 = sourmash.load_one_signature(sig63, ksize=31).minhash
+    mh47 = ss47.downsample(scaled=10000)
+    mh63 = ss63.downsample(scaled=10000)
+
+    df = pandas.read_csv(output)
+    row = df.iloc[0]
+    assert row["scaled"] == 10000
+    assert row["query_hashes"] == len(mh47)
+    assert row["intersect_hashes"] == mh47.count_common(mh63)
+
+
+def test_missing_ksize(runtmp):
+    # every ksize must be present in both collections
+    sig47 = get_test_data("47.fa.sig.gz")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "overlap-summary",
+            sig47,
+            sig47,
+            "-k",
+            "21",
+            "-o",
+            runtmp.output("out.csv"),
+        )
@@ -54,6 +54,7 @@ def test_simple_no_ani(runtmp, capfd, zip_query, indexed):
 
     df = pandas.read_csv(output)
     assert len(df) == 3
+    assert list(df["intersect_bp"]) == list(df["intersect_hashes"] * 1000)
 
     dd = df.to_dict(orient="index")
     print(dd)
@@ -366,7 +367,7 @@ def test_nomatch_query_exit(runtmp, capfd, zip_query):
         query_list = zip_siglist(runtmp, query_list, runtmp.output("query.zip"))
 
     with pytest.raises(utils.SourmashCommandFailed):
-        runtmp.sourmash("scripts", "pairwise", query_list, "-o", output)
+        runtmp.sourmash("scripts", "pairwise", query_list, "-o", output, "-k", "31")
 
     captured = capfd.readouterr()
     print(captured.err)
@@ -827,3 +828,708 @@ def test_simple_scaled_heterogenous(runtmp):
     df = pandas.read_csv(output)
     assert len(df) == 1
     assert set(list(df["scaled"])) == {15_000}
+
+
+def test_pairwise_df_api(runtmp):
+    # test the Python API that returns results directly, vs CSV output
+    from sourmash_plugin_branchwater import sourmash_plugin_branchwater as api
+
+    query_list = runtmp.output("query.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash("scripts", "pairwise", query_list, "-o", output, "--write-all")
+    csv_df = pandas.read_csv(output)
+
+    results = api.do_pairwise_df(query_list, 0.01, 31, None, "DNA", write_all=True)
+    assert len(results) == len(csv_df)
+
+    df = pandas.DataFrame(results)
+    assert set(df.columns) == set(csv_df.columns)
+    assert set(zip(df["query_name"], df["match_name"])) == set(
+        zip(csv_df["query_name"], csv_df["match_name"])
+    )
+
+
+def test_output_graph_graphml(runtmp):
+    # write the thresholded comparison graph as GraphML
+    import xml.etree.ElementTree as ET
+
+    query_list = runtmp.output("query.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    output = runtmp.output("out.csv")
+    graph = runtmp.output("out.graphml")
+
+    runtmp.sourmash(
+        "scripts",
+        "pairwise",
+        query_list,
+        "-o",
+        output,
+        "-t",
+        "0",
+        "--write-all",
+        "--output-graph",
+        graph,
+        "--graph-weight",
+        "jaccard",
+    )
+    assert os.path.exists(graph)
+
+    ns = {"g": "http://graphml.graphdrawing.org/xmlns"}
+    root = ET.parse(graph).getroot()
+    nodes = root.findall(".//g:node", ns)
+    edges = root.findall(".//g:edge", ns)
+
+    # self-comparisons add nodes, but not edges
+    assert len(nodes) == 3
+    assert len(edges) == 1
+
+    names = {
+        n.get("id"): n.find("g:data[@key='name']", ns).text.split()[0] for n in nodes
+    }
+    edge = edges[0]
+    assert {names[edge.get("source")], names[edge.get("target")]} == {
+        "NC_011665.1",
+        "NC_009661.1",
+    }
+    weight = float(edge.find("g:data[@key='weight']", ns).text)
+    assert round(weight, 4) == 0.3207
+
+
+def test_output_graph_dot(runtmp):
+    # write the graph in DOT format, weighted by max_containment
+    query_list = runtmp.output("query.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    output = runtmp.output("out.csv")
+    graph = runtmp.output("out.dot")
+
+    runtmp.sourmash(
+        "scripts", "pairwise", query_list, "-o", output, "--output-graph", graph
+    )
+
+    with open(graph) as fp:
+        lines = fp.read().splitlines()
+    print(lines)
+
+    assert lines[0] == "graph similarity {"
+    assert lines[-1] == "}"
+    edges = [line for line in lines if " -- " in line]
+    assert len(edges) == 1
+    weight = float(edges[0].split("weight=")[1].rstrip("];"))
+    assert round(weight, 4) == 0.4885
+
+
+def test_output_graph_ani_requires_ani(runtmp, capfd):
+    # ANI weights require --ani
+    query_list = runtmp.output("query.txt")
+
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+    make_file_list(query_list, [sig47, sig63])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "pairwise",
+            query_list,
+            "-o",
+            runtmp.output("out.csv"),
+            "--output-graph",
+            runtmp.output("out.graphml"),
+            "--graph-weight",
+            "average_containment_ani",
+        )
+
+    captured = capfd.readouterr()
+    assert "requires ANI estimation" in captured.err
+
+
+def test_output_graph_bad_extension(runtmp, capfd):
+    # unknown extensions need --graph-format
+    query_list = runtmp.output("query.txt")
+
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+    make_file_list(query_list, [sig47, sig63])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "pairwise",
+            query_list,
+            "-o",
+            runtmp.output("out.csv"),
+            "--output-graph",
+            runtmp.output("out.txt"),
+        )
+
+    captured = capfd.readouterr()
+    assert "cannot guess graph format" in captured.err
+
+    runtmp.sourmash(
+        "scripts",
+        "pairwise",
+        query_list,
+        "-o",
+        runtmp.output("out.csv"),
+        "--output-graph",
+        runtmp.output("out.txt"),
+        "--graph-format",
+        "dot",
+        "--force",
+    )
+    with open(runtmp.output("out.txt")) as fp:
+        assert fp.read().startswith("graph similarity {")
+
+
+def test_candidates_md5(runtmp, capfd):
+    # only compare the pairs listed in a candidates CSV, matched by md5
+    query_list = runtmp.output("query.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    ss47 = sourmash.load_one_signature(sig47, ksize=31)
+    ss63 = sourmash.load_one_signature(sig63, ksize=31)
+
+    candidates = runtmp.output("candidates.csv")
+    with open(candidates, "w", newline="") as fp:
+        w = csv.writer(fp)
+        w.writerow(["query_md5", "match_md5"])
+        w.writerow([ss63.md5sum(), ss47.md5sum()])
+        w.writerow([ss47.md5sum(), ss63.md5sum()])  # duplicate, reversed
+        w.writerow([ss47.md5sum(), ss47.md5sum()])  # self-comparison
+        w.writerow([ss47.md5sum(), "nosuchmd5"])
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts",
+        "pairwise",
+        query_list,
+        "-o",
+        output,
+        "-A",
+        "--candidates",
+        candidates,
+    )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "skipped 1 candidate pairs" in captured.err
+    assert "Comparing 1 candidate pairs" in captured.err
+
+    df = pandas.read_csv(output)
+    assert len(df) == 1
+    assert {df["query_md5"][0], df["match_md5"][0]} == {
+        ss47.md5sum(),
+        ss63.md5sum(),
+    }
+
+
+def test_candidates_names_from_output(runtmp):
+    # use a previous pairwise output, matched by name, to refine results
+    query_list = runtmp.output("query.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    coarse = runtmp.output("coarse.csv")
+    runtmp.sourmash("scripts", "pairwise", query_list, "-o", coarse)
+    coarse_df = pandas.read_csv(coarse)
+
+    names = runtmp.output("names.csv")
+    coarse_df[["query_name", "match_name"]].to_csv(names, index=False)
+
+    refined = runtmp.output("refined.csv")
+    runtmp.sourmash(
+        "scripts",
+        "pairwise",
+        query_list,
+        "-o",
+        refined,
+        "-A",
+        "--ani",
+        "--candidates",
+        names,
+    )
+
+    refined_df = pandas.read_csv(refined)
+    assert len(refined_df) == len(coarse_df)
+    assert set(zip(refined_df["query_name"], refined_df["match_name"])) == set(
+        zip(coarse_df["query_name"], coarse_df["match_name"])
+    )
+    assert refined_df["max_containment_ani"].notnull().all()
+
+
+def test_candidates_bad_columns(runtmp, capfd):
+    query_list = runtmp.output("query.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47])
+
+    candidates = runtmp.output("candidates.csv")
+    with open(candidates, "wt") as fp:
+        fp.write("a,b\n1,2\n")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "pairwise",
+            query_list,
+            "-o",
+            runtmp.output("out.csv"),
+            "--candidates",
+            candidates,
+        )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "needs 'query_md5' and 'match_md5'" in captured.err
+
+
+def sketch_abund(runtmp, names):
+    # sketch short*.fa with abundances, returning the sig paths
+    sigs = []
+    for name in names:
+        out = runtmp.output(f"{name}.sig")
+        runtmp.sourmash(
+            "scripts",
+            "singlesketch",
+            get_test_data(f"{name}.fa"),
+            "-o",
+            out,
+            "-p",
+            "k=31,scaled=1,abund",
+        )
+        sigs.append(out)
+    return sigs
+
+
+def test_angular_similarity(runtmp):
+    # report abundance-weighted angular similarity
+    sigs = sketch_abund(runtmp, ["short", "short2", "short3"])
+    query_list = runtmp.output("query.txt")
+    make_file_list(query_list, sigs)
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts",
+        "pairwise",
+        query_list,
+        "-o",
+        output,
+        "-A",
+        "--write-all",
+        "--angular-similarity",
+    )
+
+    df = pandas.read_csv(output)
+    assert len(df) == 6
+    assert "angular_similarity" in df.columns
+
+    mhs = {}
+    for sig in sigs:
+        ss = sourmash.load_one_signature(sig)
+        mhs[ss.md5sum()] = ss.minhash
+
+    for row in df.to_dict(orient="records"):
+        q, m = mhs[row["query_md5"]], mhs[row["match_md5"]]
+        expected = q.angular_similarity(m)
+        assert round(row["angular_similarity"], 5) == round(expected, 5)
+        if row["query_md5"] == row["match_md5"]:
+            assert row["angular_similarity"] == 1.0
+
+
+def test_angular_similarity_requires_abund(runtmp, capfd):
+    query_list = runtmp.output("query.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "pairwise",
+            query_list,
+            "-o",
+            runtmp.output("out.csv"),
+            "--angular-similarity",
+        )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "--angular-similarity requires sketches with abundances" in captured.err
+
+
+def test_angular_similarity_require_abundance(runtmp, capfd):
+    # --require-abundance skips flat sketches, rather than failing
+    sigs = sketch_abund(runtmp, ["short", "short2", "short3"])
+    sig47 = get_test_data("47.fa.sig.gz")
+    query_list = runtmp.output("query.txt")
+    make_file_list(query_list, sigs + [sig47])
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts",
+        "pairwise",
+        query_list,
+        "-o",
+        output,
+        "-A",
+        "--write-all",
+        "--angular-similarity",
+        "--require-abundance",
+    )
+
+    df = pandas.read_csv(output)
+    assert len(df) == 6
+    assert df["angular_similarity"].notnull().all()
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "1 analysis sketches do not have the requested abundances" in captured.err
+    assert f"({sig47}): no abundances" in captured.err
+
+
+def test_angular_similarity_graph_weight(runtmp, capfd):
+    # angular_similarity as a graph weight requires --angular-similarity
+    sigs = sketch_abund(runtmp, ["short", "short2"])
+    query_list = runtmp.output("query.txt")
+    make_file_list(query_list, sigs)
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "pairwise",
+            query_list,
+            "-o",
+            runtmp.output("out.csv"),
+            "--output-graph",
+            runtmp.output("out.graphml"),
+            "--graph-weight",
+            "angular_similarity",
+        )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "requires --angular-similarity" in captured.err
+
+
+def test_output_columns(runtmp):
+    query_list = runtmp.output("query.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts",
+        "pairwise",
+        query_list,
+        "-o",
+        output,
+        "--output-columns",
+        "query_name,match_name,jaccard",
+    )
+
+    df = pandas.read_csv(output)
+    print(df)
+    assert list(df.columns) == ["query_name", "match_name", "jaccard"]
+    assert len(df) == 1
+
+
+@pytest.mark.parametrize("flush_rows", ["1", "2", "0"])
+def test_flush_rows(runtmp, flush_rows):
+    # how often output is flushed doesn't change what is written.
+    query_list = runtmp.output("query.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    output = runtmp.output("out.csv")
+
+    runtmp.sourmash(
+        "scripts",
+        "pairwise",
+        query_list,
+        "-o",
+        output,
+        "-t",
+        "-1",
+        "--flush-rows",
+        flush_rows,
+        "--flush-interval-ms",
+        "10",
+    )
+
+    df = pandas.read_csv(output)
+    assert len(df) == 3
+
+
+def test_flush_rows_invalid(runtmp):
+    query_list = runtmp.output("query.txt")
+    make_file_list(query_list, [get_test_data("2.fa.sig.gz")])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "pairwise",
+            query_list,
+            "-o",
+            runtmp.output("out.csv"),
+            "--flush-rows",
+            "nope",
+        )
+
+    assert "invalid int value: 'nope'" in runtmp.last_result.err
+
+
+def test_min_hashes(runtmp, capfd):
+    # sketches smaller than --min-hashes are skipped, and reported
+    query_list = runtmp.output("query.txt")
+    skipped = runtmp.output("skipped.csv")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts",
+        "pairwise",
+        query_list,
+        "-o",
+        output,
+        "--min-hashes",
+        "3000",
+        "--skipped-paths-out",
+        skipped,
+    )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "WARNING: skipped 1 sketches without at least 3000 hashes." in captured.err
+
+    df = pandas.read_csv(output)
+    assert len(df) == 1
+    assert "CP001071.1" not in set(df["query_name"]) | set(df["match_name"])
+
+    skipped_df = pandas.read_csv(skipped)
+    assert list(skipped_df["path"]) == [sig2]
+    assert "sketch has 2701 hashes" in skipped_df["reason"][0]
+
+
+def test_max_hashes_all_skipped(runtmp, capfd):
+    # skipping every sketch is an error, as when none are compatible
+    query_list = runtmp.output("query.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47])
+
+    output = runtmp.output("out.csv")
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts", "pairwise", query_list, "-o", output, "--max-hashes", "100"
+        )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "WARNING: skipped 2 sketches without at most 100 hashes." in captured.err
+
+
+def test_min_hashes_greater_than_max(runtmp, capfd):
+    query_list = runtmp.output("query.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47])
+
+    output = runtmp.output("out.csv")
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "pairwise",
+            query_list,
+            "-o",
+            output,
+            "--min-hashes",
+            "1000",
+            "--max-hashes",
+            "100",
+        )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "--min-hashes (1000) must not be greater than --max-hashes (100)" in captured.err
+
+
+@pytest.mark.parametrize(
+    "measure",
+    ["jaccard", "max_containment", "average_containment_ani", "max_containment_ani"],
+)
+def test_output_distances(runtmp, measure):
+    # write a condensed distance matrix with labels
+    import numpy
+
+    query_list = runtmp.output("query.txt")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig2, sig47, sig63])
+
+    output = runtmp.output("out.csv")
+    dist_out = runtmp.output("dist.npy")
+
+    runtmp.sourmash(
+        "scripts",
+        "pairwise",
+        query_list,
+        "-o",
+        output,
+        "--ani",
+        "--output-distances",
+        dist_out,
+        "--distance-measure",
+        measure,
+    )
+
+    dist = numpy.load(dist_out)
+    assert dist.shape == (3,)
+    with open(dist_out + ".labels.txt") as fp:
+        labels = [x.split()[0] for x in fp.read().splitlines()]
+    assert sorted(labels) == ["CP001071.1", "NC_009661.1", "NC_011665.1"]
+
+    # only 47 and 63 share hashes; all other pairs are at distance 1.
+    i, j = sorted([labels.index("NC_009661.1"), labels.index("NC_011665.1")])
+    pair_idx = 3 * i - i * (i + 1) // 2 + (j - i - 1)
+
+    df = pandas.read_csv(output)
+    assert len(df) == 1
+    expected = 1 - df[measure][0]
+    assert round(dist[pair_idx], 6) == round(expected, 6)
+    assert [d for k, d in enumerate(dist) if k != pair_idx] == [1.0, 1.0]
+
+
+def test_output_distances_not_npy(runtmp, capfd):
+    query_list = runtmp.output("query.txt")
+
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    make_file_list(query_list, [sig47, sig63])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "pairwise",
+            query_list,
+            "-o",
+            runtmp.output("out.csv"),
+            "--output-distances",
+            runtmp.output("dist.csv"),
+        )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "must end in '.npy'" in captured.err
+
+
+def test_sketch_sizes(runtmp):
+    # sizes of both sketches are reported, including for self comparisons
+    sigs = [get_test_data(f"{n}.fa.sig.gz") for n in (47, 63)]
+    query_list = runtmp.output("query.txt")
+    make_file_list(query_list, sigs)
+
+    sizes = {}
+    for sig in sigs:
+        ss = sourmash.load_one_signature(sig, ksize=31)
+        sizes[ss.name] = len(ss.minhash)
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash("scripts", "pairwise", query_list, "-o", output, "--write-all")
+
+    df = pandas.read_csv(output)
+    assert len(df) == 3
+    assert list(df["query_n_hashes"]) == list(df["query_name"].map(sizes))
+    assert list(df["match_n_hashes"]) == list(df["match_name"].map(sizes))
+    assert list(df["match_bp"]) == list(df["match_n_hashes"] * 1000)
+
+
+def test_both_directions(runtmp):
+    # each pair is written once from each side
+    sigs = [get_test_data(f"{n}.fa.sig.gz") for n in (2, 47, 63)]
+    query_list = runtmp.output("query.txt")
+    make_file_list(query_list, sigs)
+
+    output = runtmp.output("out.csv")
+    runtmp.sourmash(
+        "scripts", "pairwise", query_list, "-o", output, "--ani", "--both-directions"
+    )
+
+    df = pandas.read_csv(output)
+    print(df)
+    # only 47 and 63 overlap.
+    assert len(df) == 2
+    fwd, rev = df.iloc[0], df.iloc[1]
+    assert (fwd["query_md5"], fwd["match_md5"]) == (rev["match_md5"], rev["query_md5"])
+    assert fwd["query_n_hashes"] == rev["match_n_hashes"]
+    assert fwd["match_bp"] == rev["query_bp"]
+    assert fwd["query_containment_ani"] == rev["match_containment_ani"]
+    assert fwd["match_containment_ani"] == rev["query_containment_ani"]
+    for col in ("jaccard", "max_containment", "max_containment_ani"):
+        assert fwd[col] == rev[col]
+
+    # containment is of each query in its match.
+    for row in (fwd, rev):
+        assert row["containment"] == pytest.approx(
+            row["intersect_hashes"] / row["query_n_hashes"]
+        )
+    assert fwd["containment"] != rev["containment"]
+
+    # the same as multisearch's rows for the pair.
+    ms_output = runtmp.output("ms.csv")
+    runtmp.sourmash("scripts", "multisearch", query_list, query_list, "-o", ms_output)
+    ms = pandas.read_csv(ms_output)
+    ms = ms[ms["query_md5"] != ms["match_md5"]]
+    assert sorted(ms["containment"]) == sorted(df["containment"])
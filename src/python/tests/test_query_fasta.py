@@ -0,0 +1,219 @@
+"""
+Test FASTA/FASTQ queries, sketched on the fly by search and gather commands.
+"""
+
+import gzip
+import os
+import pytest
+import pandas
+
+from . import sourmash_tst_utils as utils
+from .sourmash_tst_utils import get_test_data, make_file_list
+
+
+def sketch_against(runtmp, *fasta, params="k=31,scaled=1", input_moltype="dna"):
+    # build an against list out of singlesketch signatures
+    sigs = []
+    for fa in fasta:
+        name = os.path.basename(fa).split(".fa")[0]
+        out = runtmp.output(f"{name}.sig")
+        runtmp.sourmash(
+            "scripts",
+            "singlesketch",
+            fa,
+            "-o",
+            out,
+            "-p",
+            params,
+            "-n",
+            name,
+            "-I",
+            input_moltype,
+        )
+        sigs.append(out)
+
+    against_list = runtmp.output("against.txt")
+    make_file_list(against_list, sigs)
+    return against_list
+
+
+def make_fromfile(filename, fasta):
+    with open(filename, "wt") as fp:
+        fp.write("name,genome_filename,protein_filename\n")
+        for fa in fasta:
+            name = os.path.basename(fa).split(".fa")[0]
+            fp.write(f"{name},{fa},\n")
+
+
+def test_manysearch_fasta_query(runtmp, capfd):
+    query = get_test_data("short.fa")
+    against_list = sketch_against(runtmp, query, get_test_data("short2.fa"))
+    output = runtmp.output("out.csv")
+
+    runtmp.sourmash(
+        "scripts",
+        "manysearch",
+        query,
+        against_list,
+        "-s",
+        "1",
+        "-t",
+        "0",
+        "-o",
+        output,
+    )
+    assert os.path.exists(output)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "Sketching 1 queries from" in captured.err
+    assert "'dna,k=31,scaled=1,abund'" in captured.err
+
+    df = pandas.read_csv(output)
+    row = df[df["match_name"] == "short"].iloc[0]
+    assert row["containment"] == 1.0
+    assert set(df["query_name"]) == {query}
+
+
+def test_fastgather_fasta_query(runtmp, capfd):
+    query = get_test_data("short.fa")
+    against_list = sketch_against(
+        runtmp, get_test_data("short2.fa"), query, get_test_data("short3.fa")
+    )
+    g_output = runtmp.output("gather.csv")
+
+    runtmp.sourmash(
+        "scripts",
+        "fastgather",
+        query,
+        against_list,
+        "-s",
+        "1",
+        "-t",
+        "0",
+        "-o",
+        g_output,
+    )
+    assert os.path.exists(g_output)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "Sketching 1 queries from" in captured.err
+
+    df = pandas.read_csv(g_output)
+    assert df["match_name"][0] == "short"
+    assert df["f_unique_weighted"][0] == 1.0
+
+
+def test_fastgather_fastq_gz_query(runtmp, capfd):
+    # compressed FASTQ is recognized as sequence input, too
+    query = get_test_data("short.fa")
+    against_list = sketch_against(runtmp, query)
+    g_output = runtmp.output("gather.csv")
+
+    fq = runtmp.output("short.fq.gz")
+    with open(query, "rt") as fp:
+        seq = "".join(line.strip() for line in fp if not line.startswith(">"))
+
+    with gzip.open(fq, "wt") as fp:
+        fp.write(f"@read1\n{seq}\n+\n{'I' * len(seq)}\n")
+
+    runtmp.sourmash(
+        "scripts", "fastgather", fq, against_list, "-s", "1", "-t", "0", "-o", g_output
+    )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "Sketching 1 queries from" in captured.err
+
+    df = pandas.read_csv(g_output)
+    assert list(df["match_name"]) == ["short"]
+
+
+def test_fastmultigather_fromfile_query(runtmp, capfd):
+    fa1 = get_test_data("short.fa")
+    fa2 = get_test_data("short2.fa")
+    against_list = sketch_against(runtmp, fa1, fa2)
+
+    query_csv = runtmp.output("queries.csv")
+    make_fromfile(query_csv, [fa1, fa2])
+    g_output = runtmp.output("gather.csv")
+
+    runtmp.sourmash(
+        "scripts",
+        "fastmultigather",
+        query_csv,
+        against_list,
+        "-s",
+        "1",
+        "-t",
+        "0",
+        "-o",
+        g_output,
+        in_directory=runtmp.output(""),
+    )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "Sketching 2 queries from" in captured.err
+
+    df = pandas.read_csv(g_output)
+    for name in ("short", "short2"):
+        rows = df[df["query_name"] == name]
+        assert rows["match_name"].iloc[0] == name
+
+
+def test_fasta_query_protein(runtmp, capfd):
+    query = get_test_data("short-protein.fa")
+    against_list = sketch_against(
+        runtmp, query, params="protein,k=10,scaled=1", input_moltype="protein"
+    )
+    output = runtmp.output("out.csv")
+
+    runtmp.sourmash(
+        "scripts",
+        "manysearch",
+        query,
+        against_list,
+        "-m",
+        "protein",
+        "-k",
+        "10",
+        "-s",
+        "1",
+        "-t",
+        "0",
+        "-o",
+        output,
+    )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "'protein,k=10,scaled=1,abund'" in captured.err
+
+    df = pandas.read_csv(output)
+    assert list(df["containment"]) == [1.0]
+
+
+def test_fasta_query_no_sequences(runtmp, capfd):
+    query = runtmp.output("empty.fa")
+    with open(query, "wt") as fp:
+        pass
+
+    against_list = sketch_against(runtmp, get_test_data("short.fa"))
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "fastgather",
+            query,
+            against_list,
+            "-s",
+            "1",
+            "-o",
+            runtmp.output("gather.csv"),
+        )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "No query sketches could be built from" in captured.err
@@ -0,0 +1,174 @@
+"""
+Test 'sourmash scripts rename'
+"""
+
+import os
+import zipfile
+import pytest
+import pandas
+import sourmash
+
+from . import sourmash_tst_utils as utils
+from .sourmash_tst_utils import (
+    get_test_data,
+    make_file_list,
+    zip_siglist,
+)
+
+MD5_47 = "09a08691ce52952152f0e866a59f6261"
+NAME_47 = "NC_009661.1 Shewanella baltica OS185 plasmid pS18501, complete sequence"
+NAME_63 = "NC_011665.1 Shewanella baltica OS223 plasmid pS22303, complete sequence"
+
+
+def load_manifest(filename):
+    with zipfile.ZipFile(filename) as zf:
+        with zf.open("SOURMASH-MANIFEST.csv") as fp:
+            return pandas.read_csv(fp, comment="#", keep_default_na=False)
+
+
+def test_installed(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "rename")
+
+    assert "usage:  rename" in runtmp.last_result.err
+
+
+@pytest.mark.parametrize("zip_input", [False, True])
+def test_rename_by_md5(runtmp, capfd, zip_input):
+    # rename and annotate by md5; unmatched sketches are unchanged
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    sig_list = runtmp.output("sigs.txt")
+    make_file_list(sig_list, [sig47, sig63])
+    if zip_input:
+        sig_list = zip_siglist(runtmp, sig_list, runtmp.output("sigs.zip"))
+
+    mapping = runtmp.output("mapping.csv")
+    with open(mapping, "wt") as fp:
+        fp.write("md5,new_name,species\n")
+        fp.write(f"{MD5_47},shew47,s__Shewanella baltica\n")
+
+    output = runtmp.output("out.zip")
+    runtmp.sourmash("scripts", "rename", sig_list, mapping, "-o", output)
+
+    sigs = {ss.md5sum(): ss for ss in sourmash.load_file_as_signatures(output)}
+    assert len(sigs) == 2
+    assert sigs[MD5_47].name == "shew47"
+    orig47 = sourmash.load_one_signature(sig47, ksize=31)
+    assert sigs[MD5_47].minhash == orig47.minhash
+    assert sigs[MD5_47].filename == orig47.filename
+    assert NAME_63 in [ss.name for ss in sigs.values()]
+
+    mf = load_manifest(output)
+    assert list(mf.columns)[-1] == "species"
+    row = mf[mf["md5"] == MD5_47].iloc[0]
+    assert row["name"] == "shew47"
+    assert row["species"] == "s__Shewanella baltica"
+    row = mf[mf["md5"] != MD5_47].iloc[0]
+    assert row["name"] == NAME_63
+    assert row["species"] == ""
+
+    # sourmash can still load the manifest
+    idx = sourmash.load_file_as_index(output)
+    assert len(idx.manifest) == 2
+
+    captured = capfd.readouterr()
+    assert "1 sketches were not in the mapping file" in captured.err
+
+
+def test_rename_by_name(runtmp, capfd):
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    sig_list = runtmp.output("sigs.txt")
+    make_file_list(sig_list, [sig47, sig63])
+
+    mapping = runtmp.output("mapping.csv")
+    pandas.DataFrame(
+        {
+            "name": [NAME_47, NAME_63, "no-such-sketch"],
+            "new_name": ["shew47", "shew63", "nothing"],
+        }
+    ).to_csv(mapping, index=False)
+
+    output = runtmp.output("out.zip")
+    runtmp.sourmash("scripts", "rename", sig_list, mapping, "-o", output)
+
+    names = {ss.name for ss in sourmash.load_file_as_signatures(output)}
+    assert names == {"shew47", "shew63"}
+
+    # no extra columns
+    mf = load_manifest(output)
+    assert list(mf.columns)[-1] == "filename"
+
+    captured = capfd.readouterr()
+    assert "1 entries in the mapping file did not match any sketch" in captured.err
+
+
+def test_annotate_only(runtmp):
+    # without new_name, names are unchanged
+    sig47 = get_test_data("47.fa.sig.gz")
+
+    mapping = runtmp.output("mapping.csv")
+    with open(mapping, "wt") as fp:
+        fp.write("md5,genus,source\n")
+        fp.write(f"{MD5_47},g__Shewanella,refseq\n")
+
+    output = runtmp.output("out.zip")
+    runtmp.sourmash("scripts", "rename", sig47, mapping, "-o", output)
+
+    (ss,) = sourmash.load_file_as_signatures(output)
+    assert ss.name == NAME_47
+
+    mf = load_manifest(output)
+    assert list(mf.columns)[-2:] == ["genus", "source"]
+    assert mf["source"][0] == "refseq"
+
+
+def test_mapping_no_key(runtmp, capfd):
+    sig47 = get_test_data("47.fa.sig.gz")
+
+    mapping = runtmp.output("mapping.csv")
+    with open(mapping, "wt") as fp:
+        fp.write("ident,new_name\nNC_009661.1,shew47\n")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts", "rename", sig47, mapping, "-o", runtmp.output("out.zip")
+        )
+
+    captured = capfd.readouterr()
+    assert "must have an 'md5' or 'name' column" in captured.err
+
+
+def test_mapping_conflicting_column(runtmp, capfd):
+    sig47 = get_test_data("47.fa.sig.gz")
+
+    mapping = runtmp.output("mapping.csv")
+    with open(mapping, "wt") as fp:
+        fp.write(f"md5,ksize\n{MD5_47},21\n")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts", "rename", sig47, mapping, "-o", runtmp.output("out.zip")
+        )
+
+    captured = capfd.readouterr()
+    assert "mapping column 'ksize' conflicts with a manifest column" in captured.err
+
+
+def test_mapping_duplicate(runtmp, capfd):
+    sig47 = get_test_data("47.fa.sig.gz")
+
+    mapping = runtmp.output("mapping.csv")
+    with open(mapping, "wt") as fp:
+        fp.write(f"md5,new_name\n{MD5_47},a\n{MD5_47},b\n")
+
+    output = runtmp.output("out.zip")
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "rename", sig47, mapping, "-o", output)
+
+    captured = capfd.readouterr()
+    assert "duplicate entry" in captured.err
+    assert not os.path.exists(output)
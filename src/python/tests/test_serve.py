@@ -0,0 +1,210 @@
+"""
+Test 'sourmash scripts serve'
+"""
+
+import json
+import signal
+import subprocess
+import sys
+import urllib.error
+import urllib.request
+
+import pytest
+
+import sourmash
+from . import sourmash_tst_utils as utils
+from .sourmash_tst_utils import get_test_data, make_file_list, index_siglist
+
+
+def test_installed(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "serve")
+
+    assert "usage:  serve" in runtmp.last_result.err
+
+
+class Server:
+    "Run 'sourmash scripts serve' in the background on a free port."
+
+    def __init__(self, db, *args):
+        cmd = [sys.executable, "-m", "sourmash", "scripts", "serve", db, "-p", "0"]
+        self.proc = subprocess.Popen(
+            cmd + list(args), stderr=subprocess.PIPE, text=True
+        )
+        self.url = None
+        for line in self.proc.stderr:
+            print(line, end="")
+            if line.startswith("Listening on "):
+                self.url = line.split()[-1]
+                break
+        assert self.url, "server did not start"
+
+    def request(self, endpoint, data=None):
+        req = urllib.request.Request(self.url + endpoint, data=data)
+        with urllib.request.urlopen(req, timeout=60) as resp:
+            return json.load(resp)
+
+    def stop(self):
+        self.proc.send_signal(signal.SIGINT)
+        self.proc.wait(timeout=30)
+
+
+def make_db(runtmp):
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    siglist = runtmp.output("db-sigs.txt")
+    make_file_list(siglist, [sig2, sig47, sig63])
+    return siglist
+
+
+@pytest.mark.parametrize("rocksdb", [False, True])
+def test_serve_search_gather(runtmp, rocksdb):
+    # load a database once, and answer several requests
+    db = make_db(runtmp)
+    if rocksdb:
+        db = index_siglist(runtmp, db, runtmp.output("db.rocksdb"))
+
+    with open(get_test_data("47.fa.sig.gz"), "rb") as fp:
+        query47 = fp.read()
+    with open(get_test_data("63.fa.sig.gz"), "rb") as fp:
+        query63 = fp.read()
+
+    server = Server(db)
+    try:
+        info = server.request("/info")
+        assert info["n_sketches"] == 3
+        assert info["rocksdb"] == rocksdb
+        assert info["ksize"] == 31
+
+        results = server.request("/search", query47)
+        names = {r["match_name"].split()[0] for r in results}
+        assert names == {"NC_009661.1", "NC_011665.1"}
+
+        results = server.request("/search?threshold=0.5", query47)
+        assert len(results) == 1
+        assert results[0]["containment"] == 1.0
+
+        results = server.request("/gather", query63)
+        assert len(results) == 1
+        assert results[0]["match_name"].startswith("NC_011665.1")
+        assert results[0]["gather_result_rank"] == 0
+    finally:
+        server.stop()
+
+
+def test_serve_bad_request(runtmp):
+    # bad requests get an error, and the server keeps running
+    db = make_db(runtmp)
+
+    server = Server(db)
+    try:
+        with pytest.raises(urllib.error.HTTPError) as exc:
+            server.request("/search", b"not a sketch")
+        assert exc.value.code == 400
+        assert "error" in json.load(exc.value)
+
+        with pytest.raises(urllib.error.HTTPError) as exc:
+            server.request("/nothing")
+        assert exc.value.code == 404
+
+        # protein sketches don't match a DNA database
+        with open(get_test_data("snap25.protein.k5.sig"), "rb") as fp:
+            query = fp.read()
+        with pytest.raises(urllib.error.HTTPError) as exc:
+            server.request("/search", query)
+        assert exc.value.code == 400
+        assert "no compatible sketches" in json.load(exc.value)["error"]
+
+        info = server.request("/info")
+        assert info["n_sketches"] == 3
+    finally:
+        server.stop()
+
+
+def test_serve_missing_db(runtmp, capfd):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "serve", runtmp.output("nope.zip"), "-p", "0")
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "Error:" in captured.err
+
+
+@pytest.mark.parametrize("rocksdb", [False, True])
+def test_index_session(runtmp, rocksdb):
+    # open an index once from Python, and search and gather repeatedly
+    from sourmash_plugin_branchwater import sourmash_plugin_branchwater as api
+
+    db = make_db(runtmp)
+    if rocksdb:
+        db = index_siglist(runtmp, db, runtmp.output("db.rocksdb"))
+
+    idx = api.BranchwaterIndex(db, ksize=31)
+    assert len(idx) == 3
+    assert idx.info["rocksdb"] == rocksdb
+    assert idx.info["scaled"] == 1000
+
+    # queries can be paths, signature JSON, or sourmash signatures
+    query47 = get_test_data("47.fa.sig.gz")
+    results = idx.search(query47)
+    names = {r["match_name"].split()[0] for r in results}
+    assert names == {"NC_009661.1", "NC_011665.1"}
+
+    ss63 = sourmash.load_one_signature(get_test_data("63.fa.sig.gz"), ksize=31)
+    results = idx.search(ss63, threshold=0.5)
+    assert len(results) == 1
+    assert results[0]["match_name"].startswith("NC_011665.1")
+
+    results = idx.gather(ss63)
+    assert len(results) == 1
+    assert results[0]["match_name"].startswith("NC_011665.1")
+
+    sig2 = get_test_data("2.fa.sig.gz")
+    results = idx.gather(sig2, threshold_bp=0, max_matches=1)
+    assert len(results) == 1
+
+
+@pytest.mark.parametrize("rocksdb", [False, True])
+def test_index_screen(runtmp, rocksdb):
+    # count shared hashes only, from an open index or a path
+    from sourmash_plugin_branchwater import sourmash_plugin_branchwater as api
+
+    db = make_db(runtmp)
+    if rocksdb:
+        db = index_siglist(runtmp, db, runtmp.output("db.rocksdb"))
+
+    query47 = get_test_data("47.fa.sig.gz")
+    ss47 = sourmash.load_one_signature(query47, ksize=31)
+    idx = api.BranchwaterIndex(db, ksize=31)
+    results = idx.screen(query47)
+    assert set(results[0]) == {
+        "query_name",
+        "query_md5",
+        "match_name",
+        "match_md5",
+        "intersect_hashes",
+    }
+
+    # 47 shares all of its hashes with itself, and some with 63
+    assert len(results) == 2
+    assert results[0]["match_md5"] == ss47.md5sum()
+    assert results[0]["intersect_hashes"] == len(ss47.minhash)
+    assert 0 < results[1]["intersect_hashes"] < results[0]["intersect_hashes"]
+
+    results = idx.screen(query47, min_overlap=len(ss47.minhash))
+    assert len(results) == 1
+
+    assert api.do_screen(query47, db, ksize=31) == idx.screen(query47)
+
+
+def test_index_session_no_compatible_query(runtmp):
+    from sourmash_plugin_branchwater import sourmash_plugin_branchwater as api
+
+    idx = api.BranchwaterIndex(make_db(runtmp), ksize=31)
+    with pytest.raises(api.BranchwaterError):
+        idx.search(get_test_data("snap25.protein.k5.sig"))
+
+    # the index is still usable afterwards
+    assert len(idx.search(get_test_data("47.fa.sig.gz"))) == 2
@@ -0,0 +1,129 @@
+"""
+Test 'sourmash scripts shard'
+"""
+
+import os
+import pytest
+import pandas
+import sourmash
+
+from . import sourmash_tst_utils as utils
+from .sourmash_tst_utils import (
+    get_test_data,
+    make_file_list,
+    zip_siglist,
+)
+
+
+def load_shard_manifest(filename):
+    return pandas.read_csv(filename, comment="#")
+
+
+def make_sig_list(runtmp, zip_input=False):
+    sigs = [
+        get_test_data(f)
+        for f in ("2.fa.sig.gz", "47.fa.sig.gz", "63.fa.sig.gz", "1.fa.k31.sig.gz")
+    ]
+    sig_list = runtmp.output("sigs.txt")
+    make_file_list(sig_list, sigs)
+    if zip_input:
+        sig_list = zip_siglist(runtmp, sig_list, runtmp.output("sigs.zip"))
+    return sig_list
+
+
+def test_installed(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "shard")
+
+    assert "usage:  shard" in runtmp.last_result.err
+
+
+@pytest.mark.parametrize("zip_input", [False, True])
+def test_by_count(runtmp, zip_input):
+    sig_list = make_sig_list(runtmp, zip_input)
+
+    prefix = runtmp.output("shards/db")
+    os.mkdir(runtmp.output("shards"))
+    runtmp.sourmash("scripts", "shard", sig_list, "-n", "2", "-o", prefix)
+
+    shard0 = prefix + ".0.zip"
+    shard1 = prefix + ".1.zip"
+    assert len(sourmash.load_file_as_index(shard0).manifest) == 2
+    assert len(sourmash.load_file_as_index(shard1).manifest) == 2
+
+    # the shard manifest covers all shards
+    mf = load_shard_manifest(prefix + ".manifest.csv")
+    assert len(mf) == 4
+    assert set(mf["internal_location"]) == {shard0, shard1}
+    assert mf["md5"].nunique() == 4
+
+    # ...and can be loaded as a collection
+    idx = sourmash.load_file_as_index(prefix + ".manifest.csv")
+    assert len(list(idx.signatures())) == 4
+
+
+def test_by_hashes(runtmp):
+    # 63 (5238) + 1 (1478) vs 47 (5177) + 2 (2701)
+    sig_list = make_sig_list(runtmp)
+
+    prefix = runtmp.output("db")
+    runtmp.sourmash(
+        "scripts", "shard", sig_list, "-n", "2", "-o", prefix, "--balance-by", "hashes"
+    )
+
+    mf = load_shard_manifest(prefix + ".manifest.csv")
+    by_shard = mf.groupby("internal_location")["n_hashes"].sum().to_dict()
+    assert by_shard == {prefix + ".0.zip": 6716, prefix + ".1.zip": 7878}
+
+    idents = {
+        loc: {name.split(" ")[0] for name in names}
+        for loc, names in mf.groupby("internal_location")["name"]
+    }
+    assert idents[prefix + ".0.zip"] == {"NC_011665.1", "CP001941.1"}
+    assert idents[prefix + ".1.zip"] == {"NC_009661.1", "CP001071.1"}
+
+
+@pytest.mark.parametrize("zip_input", [False, True])
+def test_ksizes_kept_together(runtmp, zip_input):
+    # all ksizes of a signature go in the same shard
+    sig_list = runtmp.output("sigs.txt")
+    make_file_list(
+        sig_list, [get_test_data("1.combined.sig.gz"), get_test_data("47.fa.sig.gz")]
+    )
+    if zip_input:
+        sig_list = zip_siglist(runtmp, sig_list, runtmp.output("sigs.zip"))
+
+    prefix = runtmp.output("db")
+    runtmp.sourmash(
+        "scripts",
+        "shard",
+        sig_list,
+        "-n",
+        "2",
+        "-o",
+        prefix,
+        "-k",
+        "21",
+        "-k",
+        "31",
+        "-k",
+        "51",
+    )
+
+    mf = load_shard_manifest(prefix + ".manifest.csv")
+    assert len(mf) == 4
+    for _, rows in mf.groupby("name"):
+        assert rows["internal_location"].nunique() == 1
+    assert sorted(mf.groupby("internal_location").size()) == [1, 3]
+
+
+def test_too_many_shards(runtmp, capfd):
+    sig47 = get_test_data("47.fa.sig.gz")
+
+    prefix = runtmp.output("db")
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "shard", sig47, "-n", "2", "-o", prefix)
+
+    captured = capfd.readouterr()
+    assert "Cannot split 1 signatures into 2 shards" in captured.err
+    assert not os.path.exists(prefix + ".0.zip")
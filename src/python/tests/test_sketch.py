@@ -1,6 +1,11 @@
 import os
 import pytest
 import csv
+import bz2
+import gzip
+import lzma
+import struct
+import zlib
 import pandas
 import sourmash
 import subprocess
@@ -8,6 +13,8 @@ from sourmash import index, sourmash_args
 import io
 from . import sourmash_tst_utils as utils
 
+from sourmash_plugin_branchwater import sourmash_plugin_branchwater as api
+
 
 def get_test_data(filename):
     thisdir = os.path.dirname(__file__)
@@ -78,6 +85,77 @@ def test_manysketch_simple(runtmp):
     assert len(sigs) == 3
 
 
+def bgzip_compress(data, block_size=64):
+    "BGZF-compress data in small blocks, as bgzip does in 64kb blocks."
+    out = b""
+    for start in list(range(0, len(data), block_size)) + [len(data)]:
+        # ...the last, empty block is the BGZF end-of-file marker.
+        chunk = data[start : start + block_size]
+        compressor = zlib.compressobj(9, zlib.DEFLATED, -15)
+        deflated = compressor.compress(chunk) + compressor.flush()
+        bsize = 18 + len(deflated) + 8 - 1
+        out += struct.pack(
+            "<BBBBIBBHBBHH", 31, 139, 8, 4, 0, 0, 255, 6, 66, 67, 2, bsize
+        )
+        out += deflated + struct.pack("<II", zlib.crc32(chunk), len(chunk))
+    return out
+
+
+@pytest.mark.parametrize("compression", ["gz", "bgz", "bz2", "xz", "zst"])
+def test_manysketch_compressed_multi_member(runtmp, compression):
+    # compress each record separately and concatenate, as bgzip, pbzip2,
+    # and 'cat a.fa.gz b.fa.gz' do; every record must still be sketched.
+    fa1 = get_test_data("short.fa")
+    fa2 = get_test_data("short2.fa")
+    parts = []
+    for filename in (fa1, fa2):
+        with open(filename, "rb") as fp:
+            parts.append(fp.read())
+
+    plain_fa = runtmp.output("plain.fa")
+    with open(plain_fa, "wb") as fp:
+        fp.write(b"".join(parts))
+
+    if compression == "zst":
+        # no zstd in the python stdlib; made with 'zstd -c' on each file.
+        multi_fa = get_test_data("short-multiframe.fa.zst")
+    else:
+        compress = {
+            "gz": gzip.compress,
+            "bgz": bgzip_compress,
+            "bz2": bz2.compress,
+            "xz": lzma.compress,
+        }[compression]
+        multi_fa = runtmp.output(f"multi.fa.{compression}")
+        with open(multi_fa, "wb") as fp:
+            for part in parts:
+                fp.write(compress(part))
+
+    fa_csv = runtmp.output("db-fa.txt")
+    make_assembly_csv(fa_csv, [plain_fa, multi_fa])
+
+    output = runtmp.output("db.zip")
+    runtmp.sourmash(
+        "scripts",
+        "manysketch",
+        fa_csv,
+        "-o",
+        output,
+        "--param-str",
+        "dna,k=31,scaled=1",
+    )
+
+    idx = sourmash.load_file_as_index(output)
+    sigs = {os.path.basename(ss.filename): ss for ss in idx.signatures()}
+    print(sigs)
+    assert len(sigs) == 2
+
+    plain = sigs["plain.fa"]
+    multi = sigs[os.path.basename(multi_fa)]
+    assert len(multi.minhash) == len(plain.minhash)
+    assert multi.minhash == plain.minhash
+
+
 def test_manysketch_mult_k(runtmp):
     fa_csv = runtmp.output("db-fa.txt")
 
@@ -156,6 +234,53 @@ def test_manysketch_mult_k_2(runtmp):
     assert names.count("short3") == 2
 
 
+def test_manysketch_mult_k_matches_sketch(runtmp):
+    # a ksize sweep, with templates sharing ksizes, gives the same sketches
+    # as sourmash sketch
+    fa_csv = runtmp.output("db-fa.txt")
+    fa1 = runtmp.output("mixed.fa")
+    with open(fa1, "wt") as fp:
+        fp.write(">mixed\n")
+        fp.write("ATGCGATCGATCGTAGCtagctagcTGATCNATCGTAGCTAGCTAGCATCGATCGA\n")
+        fp.write("GATTACAGATTACAGATTACANNNNACGTTGCAACGTTGCAACGTTGCAAGGCCTT\n")
+    make_assembly_csv(fa_csv, [fa1])
+
+    params = "dna,k=5,k=11,k=21,scaled=1,abund"
+    output = runtmp.output("db.zip")
+    runtmp.sourmash(
+        "scripts",
+        "manysketch",
+        fa_csv,
+        "-o",
+        output,
+        "--param-str",
+        params,
+        "--param-str",
+        "dna,k=11,scaled=2",
+    )
+
+    expected = runtmp.output("expected.zip")
+    runtmp.sourmash(
+        "sketch",
+        "dna",
+        fa1,
+        "-o",
+        expected,
+        "-p",
+        params.replace("dna,", ""),
+        "-p",
+        "k=11,scaled=2",
+    )
+
+    def by_params(path):
+        sigs = sourmash.load_file_as_signatures(path)
+        return {(ss.minhash.ksize, ss.minhash.scaled): ss.minhash for ss in sigs}
+
+    sigs = by_params(output)
+    assert set(sigs) == {(5, 1), (11, 1), (21, 1), (11, 2)}
+    assert sigs == by_params(expected)
+
+
 def test_manysketch_mult_moltype(runtmp):
     fa_csv = runtmp.output("db-fa.csv")
 
@@ -385,6 +510,106 @@ def test_manysketch_bad_fa_csv_2(runtmp, capfd):
     assert "Error building signatures from file: bad2.fa" in captured.err
 
 
+@pytest.mark.parametrize("singleton", [False, True])
+def test_manysketch_some_inputs_fail(runtmp, capfd, singleton):
+    # a malformed FASTA file shouldn't stop the others from being sketched
+    fa_csv = runtmp.output("db-fa.txt")
+
+    fa1 = get_test_data("short.fa")
+    fa2 = get_test_data("short2.fa")
+    bad_fa = runtmp.output("bad.fa")
+    with open(bad_fa, "wt") as fp:
+        fp.write("this is not a FASTA file\n")
+
+    make_assembly_csv(fa_csv, [fa1, fa2, bad_fa])
+
+    output = runtmp.output("db.zip")
+    args = ["scripts", "manysketch", fa_csv, "-o", output, "-p", "dna,k=31,scaled=1"]
+    if singleton:
+        args.append("--singleton")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(*args)
+
+    assert runtmp.last_result.status == 2
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "some inputs failed" in runtmp.last_result.err
+
+    # the good inputs were sketched
+    idx = sourmash.load_file_as_index(output)
+    sigs = list(idx.signatures())
+    assert len(sigs) == 2
+    assert not any(os.path.basename(ss.filename) == "bad.fa" for ss in sigs)
+
+    # and the bad one was reported, by default to sketch_failures.csv
+    failures = runtmp.output("sketch_failures.csv")
+    with open(failures, newline="") as fp:
+        rows = list(csv.DictReader(fp))
+    print(rows)
+    assert len(rows) == 1
+    assert rows[0]["name"] == "bad"
+    assert rows[0]["filename"] == bad_fa
+    assert rows[0]["error"]
+
+
+def test_manysketch_failed_sample_dropped(runtmp, capfd):
+    # a sample with one bad input is left out, not partially sketched
+    fa_csv = runtmp.output("db-fa.csv")
+
+    fa1 = get_test_data("short.fa")
+    fa2 = get_test_data("short2.fa")
+    fa3 = get_test_data("short3.fa")
+    bad_fq = runtmp.output("bad_2.fq")
+    with open(bad_fq, "wt") as fp:
+        fp.write("@read1\nACGTACGTACGT\n+\nIII\n")
+
+    with open(fa_csv, "wt") as fp:
+        fp.write("name,read1,read2\n")
+        fp.write(f"good,{fa1},{fa2}\n")
+        fp.write(f"bad,{fa3},{bad_fq}\n")
+
+    output = runtmp.output("db.zip")
+    failures = runtmp.output("failures.csv")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "manysketch",
+            fa_csv,
+            "-o",
+            output,
+            "--failures-out",
+            failures,
+        )
+
+    assert runtmp.last_result.status == 2
+
+    idx = sourmash.load_file_as_index(output)
+    names = [ss.name for ss in idx.signatures()]
+    assert names == ["good"]
+
+    with open(failures, newline="") as fp:
+        rows = list(csv.DictReader(fp))
+    assert [(r["name"], r["filename"]) for r in rows] == [("bad", bad_fq)]
+    assert not os.path.exists(runtmp.output("sketch_failures.csv"))
+
+
+def test_manysketch_no_failures_no_report(runtmp):
+    # the failures report is only written if something failed
+    fa_csv = runtmp.output("db-fa.txt")
+
+    fa1 = get_test_data("short.fa")
+    fa2 = get_test_data("short2.fa")
+    make_assembly_csv(fa_csv, [fa1, fa2])
+
+    output = runtmp.output("db.zip")
+    runtmp.sourmash("scripts", "manysketch", fa_csv, "-o", output)
+
+    assert os.path.exists(output)
+    assert not os.path.exists(runtmp.output("sketch_failures.csv"))
+
+
 def test_manysketch_bad_fa_csv_3(runtmp, capfd):
     # test sketch with fasta provided instead of fa_csv
     output = runtmp.output("out.zip")
@@ -512,6 +737,139 @@ def test_manysketch_duplicated_rows(runtmp, capfd):
     assert "DONE. Processed 2 fasta files" in captured.err
 
 
+def make_same_name_csv(fa_csv, name, paths):
+    with open(fa_csv, "wt") as fp:
+        fp.write("name,genome_filename,protein_filename\n")
+        for path in paths:
+            fp.write(f"{name},{path},\n")
+
+
+def test_manysketch_duplicate_names_error(runtmp, capfd):
+    # rows with the same name are an error by default
+    fa_csv = runtmp.output("db-fa.csv")
+    fa1 = get_test_data("short.fa")
+    fa2 = get_test_data("short2.fa")
+    make_same_name_csv(fa_csv, "same", [fa1, fa2])
+
+    output = runtmp.output("db.zip")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "manysketch", fa_csv, "-o", output)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "more than one row has name 'same'" in captured.err
+    assert not os.path.exists(output)
+
+
+def test_manysketch_duplicate_names_merge(runtmp):
+    # --on-duplicate merge makes one sketch from all rows with a name
+    fa_csv = runtmp.output("db-fa.csv")
+    fa1 = get_test_data("short.fa")
+    fa2 = get_test_data("short2.fa")
+    make_same_name_csv(fa_csv, "same", [fa1, fa2])
+
+    output = runtmp.output("db.zip")
+    runtmp.sourmash(
+        "scripts",
+        "manysketch",
+        fa_csv,
+        "-o",
+        output,
+        "-p",
+        "dna,k=31,scaled=1",
+        "--on-duplicate",
+        "merge",
+    )
+
+    sigs = list(sourmash.load_file_as_index(output).signatures())
+    assert len(sigs) == 1
+    assert sigs[0].name == "same"
+
+    # the merged sketch is the same as sketching both files together
+    both = runtmp.output("both.fa")
+    with open(both, "wt") as fp:
+        for fa in (fa1, fa2):
+            with open(fa) as fa_fp:
+                fp.write(fa_fp.read())
+    both_csv = runtmp.output("both.csv")
+    make_same_name_csv(both_csv, "same", [both])
+    both_zip = runtmp.output("both.zip")
+    runtmp.sourmash(
+        "scripts", "manysketch", both_csv, "-o", both_zip, "-p", "dna,k=31,scaled=1"
+    )
+    expected = list(sourmash.load_file_as_index(both_zip).signatures())[0]
+    assert sigs[0].minhash == expected.minhash
+
+    # ...and the manifest agrees with the merged sketch.
+    idx = sourmash.load_file_as_index(output)
+    manifest = list(idx.manifest.rows)
+    assert len(manifest) == 1
+    assert manifest[0]["md5"] == sigs[0].md5sum()
+    assert manifest[0]["n_hashes"] == len(sigs[0].minhash)
+
+
+def test_manysketch_duplicate_names_suffix(runtmp, capfd):
+    # --on-duplicate suffix keeps every sketch, renaming later ones
+    fa_csv = runtmp.output("db-fa.csv")
+    fa1 = get_test_data("short.fa")
+    fa2 = get_test_data("short2.fa")
+    fa3 = get_test_data("short3.fa")
+    make_same_name_csv(fa_csv, "same", [fa1, fa2, fa3])
+
+    output = runtmp.output("db.zip")
+    runtmp.sourmash(
+        "scripts",
+        "manysketch",
+        fa_csv,
+        "-o",
+        output,
+        "--on-duplicate",
+        "suffix",
+    )
+
+    idx = sourmash.load_file_as_index(output)
+    names = sorted(ss.name for ss in idx.signatures())
+    assert names == ["same", "same_2", "same_3"]
+    assert sorted(row["name"] for row in idx.manifest.rows) == names
+    captured = capfd.readouterr()
+    assert "renamed 2 sketches with duplicate names" in captured.err
+
+
+def test_manysketch_duplicate_names_singleton(runtmp, capfd):
+    # with --singleton, duplicate record names are caught as sketches are written
+    fa_csv = runtmp.output("db-fa.csv")
+    fa1 = runtmp.output("dup.fa")
+    with open(fa1, "wt") as fp:
+        fp.write(">read\nACGTTGCAGGCTAGCTAGGCATCGATCGACTGACTAGCATCGACTAG\n")
+        fp.write(">read\nTTGACGATCGGCATGCGCGATATATCGCGTACGATCGATGCATGCAAT\n")
+    make_assembly_csv(fa_csv, [fa1])
+
+    output = runtmp.output("db.zip")
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts", "manysketch", fa_csv, "-o", output, "--singleton"
+        )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "more than one sketch is named 'read'" in captured.err
+    assert not os.path.exists(output)
+
+    runtmp.sourmash(
+        "scripts",
+        "manysketch",
+        fa_csv,
+        "-o",
+        output,
+        "--singleton",
+        "--on-duplicate",
+        "suffix",
+    )
+    names = sorted(ss.name for ss in sourmash.load_file_as_index(output).signatures())
+    assert names == ["read", "read_2"]
+
+
 def test_manysketch_N_in_dna(runtmp):
     # make sure we can handle Ns in DNA sequences
     fa_csv = runtmp.output("db-fa.txt")
@@ -538,6 +896,78 @@ def test_manysketch_N_in_dna(runtmp):
     assert len(sigs) == 1
 
 
+def test_manysketch_window(runtmp, capfd):
+    # windowed minimizer sketches keep a subset of the regular hashes
+    import zipfile
+
+    fa_csv = runtmp.output("db-fa.txt")
+    fa1 = get_test_data("short.fa")
+    make_assembly_csv(fa_csv, [fa1])
+
+    output = runtmp.output("db.zip")
+    runtmp.sourmash(
+        "scripts",
+        "manysketch",
+        fa_csv,
+        "-o",
+        output,
+        "-p",
+        "dna,k=21,scaled=1,window=10",
+        "-p",
+        "dna,k=21,scaled=1",
+    )
+
+    # the window is recorded in the manifest
+    with zipfile.ZipFile(output) as zf:
+        with zf.open("SOURMASH-MANIFEST.csv") as fp:
+            lines = io.TextIOWrapper(fp).read().splitlines()
+    rows = list(csv.DictReader(lines[1:]))
+    assert len(rows) == 2
+    windowed_md5 = [r["md5"] for r in rows if r["window"] == "10"]
+    regular_md5 = [r["md5"] for r in rows if r["window"] == ""]
+    assert len(windowed_md5) == 1
+    assert len(regular_md5) == 1
+
+    sigs = {
+        ss.md5sum(): ss for ss in sourmash.load_file_as_signatures(output, ksize=21)
+    }
+    windowed_mh = sigs[windowed_md5[0]].minhash
+    regular_mh = sigs[regular_md5[0]].minhash
+    windowed = set(windowed_mh.hashes)
+    regular = set(regular_mh.hashes)
+    assert windowed < regular
+    # roughly 2 / (window + 1) of k-mers are minimizers
+    assert 0.1 < len(windowed) / len(regular) < 0.3
+
+    # the window is kept in the seed, so the sketches can't be compared
+    assert windowed_mh.seed == (10 << 32) | 42
+    assert regular_mh.seed == 42
+    with pytest.raises(sourmash.exceptions.SourmashError):
+        windowed_mh.count_common(regular_mh)
+
+
+def test_manysketch_window_protein(runtmp, capfd):
+    # windows are only supported for DNA
+    fa_csv = runtmp.output("db-fa.txt")
+    fa1 = get_test_data("short.fa")
+    make_assembly_csv(fa_csv, [fa1])
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "manysketch",
+            fa_csv,
+            "-o",
+            runtmp.output("db.zip"),
+            "-p",
+            "protein,k=10,window=10",
+        )
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "'window' is only supported for DNA sketches" in captured.err
+
+
 def test_zip_manifest(runtmp, capfd):
     # test basic manifest-generating functionality.
     fa_csv = runtmp.output("db-fa.txt")
@@ -1674,3 +2104,75 @@ def test_singlesketch_multifiles(runtmp, capfd):
     sig1 = sourmash.load_one_signature(s1)
 
     assert made_sig == sig1
+
+
+def test_manysketch_output_exists(runtmp):
+    # refuse to overwrite an existing zip without --force
+    fa_csv = runtmp.output("db-fa.txt")
+
+    fa1 = get_test_data("short.fa")
+    make_assembly_csv(fa_csv, [fa1])
+
+    output = runtmp.output("db.zip")
+    with open(output, "w") as fp:
+        fp.write("precious")
+
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "manysketch", fa_csv, "-o", output)
+
+    assert "already exists; use --force to overwrite" in runtmp.last_result.err
+
+    runtmp.sourmash("scripts", "manysketch", fa_csv, "-o", output, "--force")
+
+    # the zip is written to a temporary file and moved into place
+    assert not os.path.exists(output + ".tmp")
+    idx = sourmash.load_file_as_index(output)
+    assert len(list(idx.signatures())) == 1
+
+
+def test_sketch_api_matches_manysketch(runtmp):
+    # sketching in-process gives the same sketches as manysketch
+    fa1 = get_test_data("short.fa")
+    fa_csv = runtmp.output("db-fa.txt")
+    make_assembly_csv(fa_csv, [fa1])
+
+    params = "k=21,k=31,scaled=1"
+    output = runtmp.output("db.zip")
+    runtmp.sourmash("scripts", "manysketch", fa_csv, "-o", output, "-p", params)
+    expected = {
+        ss.minhash.ksize: ss.minhash
+        for ss in sourmash.load_file_as_signatures(output)
+    }
+
+    with open(fa1, "rb") as fp:
+        data = fp.read()
+    sigs = api.sketch_fastx(data, params, name="short")
+    assert {ss.name for ss in sigs} == {"short"}
+    assert {ss.minhash.ksize: ss.minhash for ss in sigs} == expected
+
+    # the same, from the sequences of the records.
+    sequences = [
+        "".join(lines.splitlines()[1:])
+        for lines in data.decode().split(">")
+        if lines.strip()
+    ]
+    sigs = api.sketch_sequences(sequences, params, name="short")
+    assert {ss.minhash.ksize: ss.minhash for ss in sigs} == expected
+
+
+def test_sketch_api_protein():
+    seq = "MVKVGVNGFGRIGRLVTRAAF"
+    sigs = api.sketch_sequences(seq, "protein,k=5,scaled=1", "protein")
+    assert len(sigs) == 1
+    assert sigs[0].minhash.moltype == "protein"
+    assert sigs[0].minhash.ksize == 5
+    assert len(sigs[0].minhash) == 17
+
+
+def test_sketch_api_bad_params():
+    with pytest.raises(api.BranchwaterError):
+        api.sketch_sequences("ACGT", "k=31,scaled=1000,foo")
+
+    # no DNA sketches in the params.
+    with pytest.raises(api.BranchwaterError):
+        api.sketch_sequences("ACGT", "protein,k=10,scaled=1")
@@ -0,0 +1,119 @@
+"""
+Test 'sourmash scripts subtract'
+"""
+
+import os
+import pytest
+import pandas
+import sourmash
+
+from . import sourmash_tst_utils as utils
+from .sourmash_tst_utils import (
+    get_test_data,
+    make_file_list,
+    zip_siglist,
+)
+
+
+def load_sigs(filename):
+    return {
+        ss.name: ss for ss in sourmash.load_file_as_signatures(filename, ksize=31)
+    }
+
+
+def test_installed(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "subtract")
+
+    assert "usage:  subtract" in runtmp.last_result.err
+
+
+@pytest.mark.parametrize("zip_input", [False, True])
+def test_simple(runtmp, zip_input):
+    # subtract 63 from 2 and 47
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    sig_list = runtmp.output("sigs.txt")
+    make_file_list(sig_list, [sig2, sig47])
+    if zip_input:
+        sig_list = zip_siglist(runtmp, sig_list, runtmp.output("sigs.zip"))
+
+    output = runtmp.output("out.zip")
+    report = runtmp.output("report.csv")
+    runtmp.sourmash(
+        "scripts", "subtract", sig_list, sig63, "-o", output, "--report", report
+    )
+
+    mh47 = sourmash.load_one_signature(sig47, ksize=31).minhash
+    mh63 = sourmash.load_one_signature(sig63, ksize=31).minhash
+    mh2 = sourmash.load_one_signature(sig2, ksize=31).minhash
+
+    sigs = load_sigs(output)
+    assert len(sigs) == 2
+    name47 = "NC_009661.1 Shewanella baltica OS185 plasmid pS18501, complete sequence"
+    cleaned47 = sigs[name47].minhash
+    assert set(cleaned47.hashes) == set(mh47.hashes) - set(mh63.hashes)
+    cleaned2 = sigs["CP001071.1 Akkermansia muciniphila ATCC BAA-835, complete genome"]
+    assert set(cleaned2.minhash.hashes) == set(mh2.hashes)
+
+    df = pandas.read_csv(report)
+    assert len(df) == 2
+    row = df[df["name"].str.startswith("NC_009661.1")].iloc[0]
+    assert row["n_hashes"] == len(mh47)
+    assert row["n_removed"] == 2529
+    assert row["n_remaining"] == len(mh47) - 2529
+    assert row["cleaned_md5"] == sigs[row["name"]].md5sum()
+    row = df[df["name"].str.startswith("CP001071.1")].iloc[0]
+    assert row["n_removed"] == 0
+    assert row["f_removed"] == 0.0
+
+
+def test_no_report(runtmp, capfd):
+    # report is optional
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    output = runtmp.output("out.zip")
+    runtmp.sourmash("scripts", "subtract", sig47, sig63, "-o", output)
+
+    assert os.path.exists(output)
+    captured = capfd.readouterr()
+    assert "Removed 2529 hashes in total from 1 sketches" in captured.err
+
+
+def test_multiple_contaminants(runtmp):
+    # contaminant hashes are combined
+    sig2 = get_test_data("2.fa.sig.gz")
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    contam_list = runtmp.output("contam.txt")
+    make_file_list(contam_list, [sig2, sig63])
+
+    output = runtmp.output("out.zip")
+    runtmp.sourmash("scripts", "subtract", sig47, contam_list, "-o", output)
+
+    mh2 = sourmash.load_one_signature(sig2, ksize=31).minhash
+    mh47 = sourmash.load_one_signature(sig47, ksize=31).minhash
+    mh63 = sourmash.load_one_signature(sig63, ksize=31).minhash
+
+    sigs = load_sigs(output)
+    (cleaned,) = sigs.values()
+    assert set(cleaned.minhash.hashes) == (
+        set(mh47.hashes) - set(mh63.hashes) - set(mh2.hashes)
+    )
+
+
+def test_output_not_zip(runtmp, capfd):
+    # output must be a zip file
+    sig47 = get_test_data("47.fa.sig.gz")
+    sig63 = get_test_data("63.fa.sig.gz")
+
+    output = runtmp.output("out.sig")
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "subtract", sig47, sig63, "-o", output)
+
+    captured = capfd.readouterr()
+    assert "Output must be a zip file" in captured.err
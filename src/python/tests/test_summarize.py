@@ -0,0 +1,140 @@
+"""
+Test 'sourmash scripts summarize'
+"""
+
+import json
+import shutil
+import pytest
+import pandas
+
+from . import sourmash_tst_utils as utils
+from .sourmash_tst_utils import get_test_data, make_file_list
+
+NAME_47 = "NC_009661.1 Shewanella baltica OS185 plasmid pS18501, complete sequence"
+MD5_47 = "09a08691ce52952152f0e866a59f6261"
+
+
+def test_installed(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "summarize")
+
+    assert "usage:  summarize" in runtmp.last_result.err
+
+
+def test_summarize_csv(runtmp, capfd):
+    sigs = [
+        get_test_data(f)
+        for f in ("2.fa.sig.gz", "47.fa.sig.gz", "63.fa.sig.gz", "SRR606249.sig.gz")
+    ]
+    sig_list = runtmp.output("sigs.txt")
+    make_file_list(sig_list, sigs)
+    output = runtmp.output("summary.csv")
+
+    runtmp.sourmash("scripts", "summarize", sig_list, "-o", output)
+
+    df = pandas.read_csv(output)
+    assert len(df) == 2
+    assert set(df["ksize"]) == {31}
+    assert set(df["moltype"]) == {"DNA"}
+
+    flat = df[df["scaled"] == 1000].iloc[0]
+    assert flat["n_sketches"] == 3
+    assert not flat["with_abundance"]
+    assert flat["total_hashes"] == 2701 + 5177 + 5238
+    assert flat["min_hashes"] == 2701
+    assert flat["max_hashes"] == 5238
+    assert flat["mean_hashes"] == pytest.approx(13116 / 3)
+    assert flat["n_duplicate_md5s"] == 0
+    assert flat["n_name_collisions"] == 0
+
+    abund = df[df["scaled"] == 100000].iloc[0]
+    assert abund["n_sketches"] == 1
+    assert abund["with_abundance"]
+    assert abund["total_hashes"] == 4200
+
+    captured = capfd.readouterr()
+    assert "Summarized 4 sketches in 2 groups" in captured.err
+
+
+def test_summarize_zip_stdout(runtmp):
+    runtmp.sourmash("scripts", "summarize", get_test_data("hmp-queries.sig.zip"))
+
+    out = runtmp.last_result.out
+    assert "ksize,moltype,scaled,num,with_abundance,n_sketches" in out
+    assert "31,DNA,1000,0,false,2,7874," in out
+
+
+def make_problem_list(runtmp):
+    "a pathlist with a duplicated sketch, and a name shared by two sketches"
+    dup = runtmp.output("47-copy.sig.gz")
+    shutil.copyfile(get_test_data("47.fa.sig.gz"), dup)
+
+    renamed = runtmp.output("63-renamed.sig.gz")
+    runtmp.sourmash(
+        "sig", "rename", get_test_data("63.fa.sig.gz"), NAME_47, "-o", renamed
+    )
+
+    sig_list = runtmp.output("sigs.txt")
+    make_file_list(sig_list, [get_test_data("47.fa.sig.gz"), dup, renamed])
+    return sig_list
+
+
+def test_summarize_duplicates_csv(runtmp, capfd):
+    sig_list = make_problem_list(runtmp)
+    output = runtmp.output("summary.csv")
+
+    runtmp.sourmash("scripts", "summarize", sig_list, "-o", output)
+
+    df = pandas.read_csv(output)
+    assert len(df) == 1
+    row = df.iloc[0]
+    assert row["n_sketches"] == 3
+    assert row["n_duplicate_md5s"] == 1
+    assert row["n_name_collisions"] == 1
+
+    captured = capfd.readouterr()
+    assert "WARNING: 1 md5s appear more than once." in captured.err
+    assert (
+        "WARNING: 1 names are shared by sketches with different md5s." in captured.err
+    )
+
+
+def test_summarize_json(runtmp):
+    sig_list = make_problem_list(runtmp)
+    output = runtmp.output("summary.json")
+
+    runtmp.sourmash("scripts", "summarize", sig_list, "-o", output, "--format", "json")
+
+    with open(output) as fp:
+        summary = json.load(fp)
+
+    assert summary["source"] == sig_list
+    assert summary["n_sketches"] == 3
+    assert summary["total_hashes"] == 5177 * 2 + 5238
+    assert len(summary["groups"]) == 1
+    assert summary["groups"][0]["ksize"] == 31
+
+    assert summary["duplicate_md5s"] == [{"md5": MD5_47, "name": NAME_47, "count": 2}]
+
+    (collision,) = summary["name_collisions"]
+    assert collision["name"] == NAME_47
+    assert collision["ksize"] == 31
+    assert len(collision["md5s"]) == 2
+    assert MD5_47 in collision["md5s"]
+
+
+def test_summarize_bad_format(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts", "summarize", get_test_data("47.sig.zip"), "--format", "yaml"
+        )
+
+    assert "invalid choice: 'yaml'" in runtmp.last_result.err
+
+
+def test_summarize_missing_file(runtmp, capfd):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "summarize", "no-such-file.zip")
+
+    captured = capfd.readouterr()
+    assert "No such file or directory" in captured.err
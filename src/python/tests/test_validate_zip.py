@@ -0,0 +1,174 @@
+"""
+Test 'sourmash scripts validate-zip'
+"""
+
+import zipfile
+import pytest
+import pandas
+
+from . import sourmash_tst_utils as utils
+from .sourmash_tst_utils import get_test_data
+
+SIG_A = "signatures/2cfe4d5ffc4a2a0e8192a85ad4c5e6c4.sig.gz"
+SIG_B = "signatures/ac33d51c04aa6a402fbf3c12d5990404.sig.gz"
+
+
+def rewrite_zip(src, dest, drop=(), replace={}, add={}):
+    "copy a zip file member by member, dropping, replacing, or adding members"
+    with zipfile.ZipFile(src) as zin, zipfile.ZipFile(dest, "w") as zout:
+        for info in zin.infolist():
+            if info.filename in drop:
+                continue
+            data = replace.get(info.filename, zin.read(info.filename))
+            zout.writestr(info, data)
+        for name, data in add.items():
+            zout.writestr(name, data)
+
+
+def read_member(zipname, member):
+    with zipfile.ZipFile(zipname) as zf:
+        return zf.read(member)
+
+
+def run_validate(runtmp, zipname, expect_fail=True):
+    output = runtmp.output("problems.csv")
+    if expect_fail:
+        with pytest.raises(utils.SourmashCommandFailed):
+            runtmp.sourmash("scripts", "validate-zip", zipname, "-o", output)
+    else:
+        runtmp.sourmash("scripts", "validate-zip", zipname, "-o", output)
+    return pandas.read_csv(output, keep_default_na=False)
+
+
+def test_installed(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "validate-zip")
+
+    assert "usage:  validate-zip" in runtmp.last_result.err
+
+
+@pytest.mark.parametrize("zipname", ["hmp-against.sig.zip", "47.sig.zip"])
+def test_good_zip(runtmp, capfd, zipname):
+    df = run_validate(runtmp, get_test_data(zipname), expect_fail=False)
+
+    assert len(df) == 0
+    assert list(df.columns) == [
+        "internal_location",
+        "md5",
+        "name",
+        "problem",
+        "detail",
+    ]
+
+    captured = capfd.readouterr()
+    assert "No problems found" in captured.err
+
+
+def test_good_zip_stdout(runtmp):
+    runtmp.sourmash("scripts", "validate-zip", get_test_data("47.sig.zip"))
+
+    assert "internal_location,md5,name,problem,detail" in runtmp.last_result.out
+
+
+def test_missing_member(runtmp, capfd):
+    bad = runtmp.output("bad.zip")
+    rewrite_zip(get_test_data("hmp-against.sig.zip"), bad, drop=[SIG_A])
+
+    df = run_validate(runtmp, bad)
+    assert len(df) == 1
+    row = df.iloc[0]
+    assert row["problem"] == "missing_member"
+    assert row["internal_location"] == SIG_A
+    assert row["name"] == "CD136"
+
+    captured = capfd.readouterr()
+    assert "Found 1 problems" in captured.err
+
+
+def test_orphan_member(runtmp):
+    src = get_test_data("hmp-against.sig.zip")
+    bad = runtmp.output("bad.zip")
+    rewrite_zip(src, bad, add={"signatures/extra.sig.gz": read_member(src, SIG_A)})
+
+    df = run_validate(runtmp, bad)
+    assert len(df) == 1
+    assert df.iloc[0]["problem"] == "orphan_member"
+    assert df.iloc[0]["internal_location"] == "signatures/extra.sig.gz"
+
+
+def test_truncated_gzip(runtmp):
+    src = get_test_data("hmp-against.sig.zip")
+    data = read_member(src, SIG_B)
+    bad = runtmp.output("bad.zip")
+    rewrite_zip(src, bad, replace={SIG_B: data[: len(data) // 2]})
+
+    df = run_validate(runtmp, bad)
+    assert len(df) == 1
+    assert df.iloc[0]["problem"] == "bad_compression"
+    assert df.iloc[0]["name"] == "CD237"
+
+
+def test_unreadable_signature(runtmp):
+    src = get_test_data("hmp-against.sig.zip")
+    bad = runtmp.output("bad.zip")
+    rewrite_zip(src, bad, replace={SIG_B: b"not a signature"})
+
+    df = run_validate(runtmp, bad)
+    assert len(df) == 1
+    assert df.iloc[0]["problem"] == "unreadable_signature"
+
+
+def test_md5_mismatch(runtmp):
+    src = get_test_data("hmp-against.sig.zip")
+    bad = runtmp.output("bad.zip")
+    rewrite_zip(src, bad, replace={SIG_A: read_member(src, SIG_B)})
+
+    df = run_validate(runtmp, bad)
+    assert len(df) == 1
+    row = df.iloc[0]
+    assert row["problem"] == "md5_mismatch"
+    assert row["md5"] == "2cfe4d5ffc4a2a0e8192a85ad4c5e6c4"
+    assert "ac33d51c04aa6a402fbf3c12d5990404" in row["detail"]
+
+
+def test_missing_manifest(runtmp):
+    bad = runtmp.output("bad.zip")
+    rewrite_zip(
+        get_test_data("hmp-against.sig.zip"), bad, drop=["SOURMASH-MANIFEST.csv"]
+    )
+
+    df = run_validate(runtmp, bad)
+    assert len(df) == 1
+    assert df.iloc[0]["problem"] == "missing_manifest"
+
+
+def test_multiple_problems(runtmp, capfd):
+    src = get_test_data("hmp-against.sig.zip")
+    bad = runtmp.output("bad.zip")
+    rewrite_zip(
+        src,
+        bad,
+        drop=[SIG_A],
+        replace={SIG_B: b"not a signature"},
+        add={"signatures/extra.sig.gz": b""},
+    )
+
+    df = run_validate(runtmp, bad)
+    assert set(df["problem"]) == {
+        "missing_member",
+        "unreadable_signature",
+        "orphan_member",
+    }
+
+    captured = capfd.readouterr()
+    assert "Found 3 problems" in captured.err
+
+
+def test_not_a_zip(runtmp, capfd):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts", "validate-zip", get_test_data("47.fa.sig.gz"), "-o", "out.csv"
+        )
+
+    captured = capfd.readouterr()
+    assert "Failed to read zip file" in captured.err
@@ -0,0 +1,172 @@
+"""
+Test 'sourmash scripts zip-cat'
+"""
+
+import os
+import zipfile
+import pytest
+import sourmash
+
+from . import sourmash_tst_utils as utils
+from .sourmash_tst_utils import get_test_data
+
+MANIFEST = "SOURMASH-MANIFEST.csv"
+
+
+def read_manifest_rows(zipname):
+    idx = sourmash.load_file_as_index(zipname)
+    return list(idx.manifest.rows)
+
+
+def test_installed(runtmp):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "zip-cat")
+
+    assert "usage:  zip-cat" in runtmp.last_result.err
+
+
+def test_simple(runtmp):
+    zips = [get_test_data(f"{n}.sig.zip") for n in (2, 47, 63)]
+    output = runtmp.output("all.zip")
+
+    runtmp.sourmash("scripts", "zip-cat", *zips, "-o", output)
+    assert os.path.exists(output)
+
+    expected = set()
+    for zipname in zips:
+        expected.update(ss.md5sum() for ss in sourmash.load_file_as_signatures(zipname))
+
+    sigs = list(sourmash.load_file_as_signatures(output))
+    assert len(sigs) == 3
+    assert set(ss.md5sum() for ss in sigs) == expected
+
+    rows = read_manifest_rows(output)
+    assert set(row["md5"] for row in rows) == expected
+
+    # the result is a valid zip collection
+    runtmp.sourmash("scripts", "validate-zip", output)
+
+
+def test_members_not_recompressed(runtmp):
+    # members are copied byte for byte
+    zip47 = get_test_data("47.sig.zip")
+    output = runtmp.output("all.zip")
+
+    runtmp.sourmash("scripts", "zip-cat", zip47, "-o", output)
+
+    with zipfile.ZipFile(zip47) as zin, zipfile.ZipFile(output) as zout:
+        for info in zin.infolist():
+            if info.filename == MANIFEST:
+                continue
+            out_info = zout.getinfo(info.filename)
+            assert out_info.compress_type == info.compress_type
+            assert out_info.CRC == info.CRC
+            assert zout.read(info.filename) == zin.read(info.filename)
+
+
+def test_dedup_by_md5(runtmp, capfd):
+    zip47 = get_test_data("47.sig.zip")
+    zip63 = get_test_data("63.sig.zip")
+    output = runtmp.output("all.zip")
+
+    runtmp.sourmash("scripts", "zip-cat", zip47, zip63, zip47, "-o", output)
+
+    sigs = list(sourmash.load_file_as_signatures(output))
+    assert len(sigs) == 2
+    assert len(read_manifest_rows(output)) == 2
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "Skipped 1 sketches with the same md5" in captured.err
+
+
+def test_location_collision(runtmp):
+    # a different sketch at an already-used location is renamed
+    zip47 = get_test_data("47.sig.zip")
+    zip63 = get_test_data("63.sig.zip")
+
+    with zipfile.ZipFile(zip47) as zf:
+        (loc47,) = [n for n in zf.namelist() if n != MANIFEST]
+    with zipfile.ZipFile(zip63) as zf:
+        (loc63,) = [n for n in zf.namelist() if n != MANIFEST]
+        sig63 = zf.read(loc63)
+        manifest63 = zf.read(MANIFEST).decode().replace(loc63, loc47)
+
+    # 63's sketch, stored where 47's sketch is in 47.sig.zip
+    moved = runtmp.output("moved.zip")
+    with zipfile.ZipFile(moved, "w") as zf:
+        zf.writestr(loc47, sig63)
+        zf.writestr(MANIFEST, manifest63)
+
+    output = runtmp.output("all.zip")
+    runtmp.sourmash("scripts", "zip-cat", zip47, moved, "-o", output)
+
+    renamed = loc47.replace(".sig.gz", "_2.sig.gz")
+    with zipfile.ZipFile(output) as zf:
+        assert sorted(zf.namelist()) == sorted([loc47, renamed, MANIFEST])
+
+    rows = read_manifest_rows(output)
+    assert sorted(row["internal_location"] for row in rows) == sorted(
+        [loc47, renamed]
+    )
+    assert len(list(sourmash.load_file_as_signatures(output))) == 2
+    runtmp.sourmash("scripts", "validate-zip", output)
+
+
+def test_extra_manifest_columns(runtmp):
+    # columns beyond the standard ones are kept
+    fa_csv = runtmp.output("db-fa.csv")
+    fa1 = get_test_data("short.fa")
+    with open(fa_csv, "wt") as fp:
+        fp.write("name,genome_filename,protein_filename\n")
+        fp.write(f"short,{fa1},\n")
+
+    windowed = runtmp.output("windowed.zip")
+    runtmp.sourmash(
+        "scripts", "manysketch", fa_csv, "-o", windowed, "-p", "dna,k=21,window=5"
+    )
+
+    output = runtmp.output("all.zip")
+    runtmp.sourmash(
+        "scripts", "zip-cat", get_test_data("47.sig.zip"), windowed, "-o", output
+    )
+
+    with zipfile.ZipFile(output) as zf:
+        lines = zf.read(MANIFEST).decode().splitlines()
+    header = lines[1].split(",")
+    assert header[:2] == ["internal_location", "md5"]
+    assert header[-1] == "window"
+    assert lines[2].endswith(",")
+    assert lines[3].endswith(",5")
+
+
+def test_no_manifest(runtmp, capfd):
+    zip47 = get_test_data("47.sig.zip")
+    no_manifest = runtmp.output("no-manifest.zip")
+    with zipfile.ZipFile(zip47) as zin, zipfile.ZipFile(no_manifest, "w") as zout:
+        for info in zin.infolist():
+            if info.filename != MANIFEST:
+                zout.writestr(info, zin.read(info.filename))
+
+    output = runtmp.output("all.zip")
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash("scripts", "zip-cat", zip47, no_manifest, "-o", output)
+
+    captured = capfd.readouterr()
+    print(captured.err)
+    assert "has no manifest" in captured.err
+    assert not os.path.exists(output)
+
+
+def test_output_not_zip(runtmp, capfd):
+    with pytest.raises(utils.SourmashCommandFailed):
+        runtmp.sourmash(
+            "scripts",
+            "zip-cat",
+            get_test_data("47.sig.zip"),
+            "-o",
+            runtmp.output("all.sig"),
+        )
+
+    captured = capfd.readouterr()
+    assert "Output must be a zip file" in captured.err
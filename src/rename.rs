@@ -0,0 +1,215 @@
+/// rename: rename and annotate signatures from a mapping CSV.
+use anyhow::{anyhow, Result};
+use camino::Utf8Path as Path;
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic;
+use std::sync::atomic::AtomicUsize;
+
+use sourmash::signature::Signature;
+
+use crate::utils::buildutils::{BuildCollection, BuildManifest};
+use crate::utils::{load_collection_ksizes, zipwriter_handle, ReportType};
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum MappingKey {
+    Md5,
+    Name,
+}
+
+/// New name and extra manifest columns for one signature.
+pub(crate) struct Annotation {
+    pub(crate) new_name: Option<String>,
+    pub(crate) extra: Vec<(String, String)>,
+}
+
+/// Read a mapping CSV, keyed on `md5` if present, or else `name`. An
+/// optional `new_name` column gives the new name; all other columns are
+/// added to the output manifest.
+pub(crate) fn load_mapping(path: &str) -> Result<(MappingKey, HashMap<String, Annotation>)> {
+    let mut rdr = csv::Reader::from_path(path)
+        .map_err(|e| anyhow!("Failed to open mapping file '{}': {}", path, e))?;
+    let headers = rdr.headers()?.clone();
+
+    let position = |column: &str| headers.iter().position(|h| h == column);
+    let (key, key_col) = match (position("md5"), position("name")) {
+        (Some(col), _) => (MappingKey::Md5, col),
+        (None, Some(col)) => (MappingKey::Name, col),
+        (None, None) => bail!(
+            "mapping file '{}' must have an 'md5' or 'name' column",
+            path
+        ),
+    };
+    let new_name_col = position("new_name");
+
+    let extra_cols: Vec<(usize, String)> = headers
+        .iter()
+        .enumerate()
+        .filter(|(_, h)| !["md5", "name", "new_name"].contains(h))
+        .map(|(i, h)| (i, h.to_string()))
+        .collect();
+    for (_, column) in &extra_cols {
+        if BuildManifest::COLUMNS.contains(&column.as_str()) {
+            bail!(
+                "mapping column '{}' conflicts with a manifest column",
+                column
+            );
+        }
+    }
+
+    let mut mapping = HashMap::new();
+    for record in rdr.records() {
+        let record = record?;
+        let key_value = record.get(key_col).unwrap_or_default().to_string();
+        let new_name = new_name_col
+            .and_then(|col| record.get(col))
+            .filter(|name| !name.is_empty())
+            .map(|name| name.to_string());
+        let extra = extra_cols
+            .iter()
+            .map(|(i, column)| {
+                (
+                    column.clone(),
+                    record.get(*i).unwrap_or_default().to_string(),
+                )
+            })
+            .collect();
+
+        if mapping
+            .insert(key_value.clone(), Annotation { new_name, extra })
+            .is_some()
+        {
+            bail!("duplicate entry '{}' in mapping file '{}'", key_value, path);
+        }
+    }
+
+    eprintln!(
+        "Loaded {} entries from mapping file '{}'",
+        mapping.len(),
+        path
+    );
+    Ok((key, mapping))
+}
+
+pub fn rename(
+    siglist: String,
+    ksizes: Vec<u8>,
+    moltype: String,
+    mapping_csv: String,
+    output: String,
+    allow_failed_sigpaths: bool,
+) -> Result<()> {
+    // if output doesn't end in zip, bail
+    if Path::new(&output).extension() != Some("zip") {
+        bail!("Output must be a zip file.");
+    }
+
+    let (key, mapping) = load_mapping(&mapping_csv)?;
+
+    let collection = load_collection_ksizes(
+        &siglist,
+        &ksizes,
+        None,
+        &moltype,
+        ReportType::General,
+        allow_failed_sigpaths,
+    )?;
+
+    let (send, recv) =
+        std::sync::mpsc::sync_channel::<Option<BuildCollection>>(rayon::current_num_threads());
+    let thrd = zipwriter_handle(recv, output.clone());
+
+    let processed_sigs = AtomicUsize::new(0);
+    let annotated_sigs = AtomicUsize::new(0);
+    let failed_sigs = AtomicUsize::new(0);
+
+    let send_result = collection.par_iter().try_for_each_with(
+        send.clone(),
+        |s, (coll, _idx, record)| -> Result<()> {
+            let sig = match coll.sig_from_record(record) {
+                Ok(sig) => sig,
+                Err(e) => {
+                    eprintln!(
+                        "WARNING: could not load sketch from '{}': {}",
+                        record.internal_location(),
+                        e
+                    );
+                    failed_sigs.fetch_add(1, atomic::Ordering::SeqCst);
+                    return Ok(());
+                }
+            };
+
+            let annotation = match key {
+                MappingKey::Md5 => mapping.get(record.md5()),
+                MappingKey::Name => mapping.get(record.name()),
+            };
+
+            let mut sig = Signature::from(sig);
+            if let Some(name) = annotation.and_then(|a| a.new_name.as_ref()) {
+                sig.set_name(name);
+            }
+
+            let mut sigs = BuildCollection::new();
+            sigs.add_sig(sig)?;
+            if let Some(annotation) = annotation {
+                for build_record in sigs.manifest.iter_mut() {
+                    build_record.extra = annotation.extra.clone();
+                }
+                annotated_sigs.fetch_add(1, atomic::Ordering::SeqCst);
+            }
+            s.send(Some(sigs))
+                .map_err(|e| anyhow!("Unable to send internal data: {:?}", e))?;
+
+            processed_sigs.fetch_add(1, atomic::Ordering::SeqCst);
+            Ok(())
+        },
+    );
+
+    // Send None to sigwriter to signal completion + write manifest
+    if let Err(e) = send.send(None) {
+        eprintln!("Unable to send completion signal: {:?}", e);
+    }
+
+    thrd.join()
+        .unwrap_or_else(|e| Err(anyhow!("Thread panicked: {:?}", e)))?;
+    send_result?;
+
+    let n_processed = processed_sigs.load(atomic::Ordering::SeqCst);
+    let n_annotated = annotated_sigs.load(atomic::Ordering::SeqCst);
+    eprintln!(
+        "DONE. Wrote {} sketches to '{}'; {} matched the mapping file.",
+        n_processed, output, n_annotated
+    );
+    if n_annotated < n_processed {
+        eprintln!(
+            "WARNING: {} sketches were not in the mapping file, and were not changed.",
+            n_processed - n_annotated
+        );
+    }
+
+    // report mapping entries that matched nothing
+    let seen: HashSet<&String> = collection
+        .par_iter()
+        .map(|(_, _, record)| match key {
+            MappingKey::Md5 => record.md5(),
+            MappingKey::Name => record.name(),
+        })
+        .collect();
+    let n_unused = mapping.keys().filter(|k| !seen.contains(k)).count();
+    if n_unused > 0 {
+        eprintln!(
+            "WARNING: {} entries in the mapping file did not match any sketch.",
+            n_unused
+        );
+    }
+
+    let failed_sigs = failed_sigs.load(atomic::Ordering::SeqCst);
+    if failed_sigs > 0 {
+        eprintln!(
+            "WARNING: {} sketches failed to load. See error messages above.",
+            failed_sigs
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,83 @@
+//! ResultStream: a Python iterator over results produced by a search
+//! running in a background thread.
+//!
+//! Results are handed over through a bounded channel, so memory use stays
+//! constant no matter how many results the search produces. Dropping or
+//! closing the stream hangs up the channel, which stops the search early.
+use anyhow::Result;
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use pythonize::pythonize;
+use serde::Serialize;
+use std::sync::mpsc::SyncSender;
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+
+use crate::errors::to_pyerr;
+
+type NextFn = Box<dyn for<'py> FnMut(Python<'py>) -> PyResult<Option<Bound<'py, PyAny>>> + Send>;
+
+#[pyclass]
+pub struct ResultStream {
+    next_fn: Mutex<Option<NextFn>>,
+    handle: Mutex<Option<JoinHandle<Result<()>>>>,
+}
+
+impl ResultStream {
+    /// Run `f` in a new thread, streaming everything it sends back to
+    /// Python as dicts.
+    pub fn spawn<T, F>(f: F) -> Self
+    where
+        T: Serialize + Send + 'static,
+        F: FnOnce(SyncSender<T>) -> Result<()> + Send + 'static,
+    {
+        let (send, recv) = std::sync::mpsc::sync_channel::<T>(rayon::current_num_threads());
+        let handle = std::thread::spawn(move || f(send));
+
+        let recv = Mutex::new(recv);
+        let next_fn: NextFn = Box::new(move |py| {
+            let recv = &recv;
+            match py.allow_threads(|| recv.lock().unwrap().recv()) {
+                Ok(item) => Ok(Some(pythonize(py, &item)?)),
+                Err(_) => Ok(None), // sender hung up; search is done.
+            }
+        });
+
+        Self {
+            next_fn: Mutex::new(Some(next_fn)),
+            handle: Mutex::new(Some(handle)),
+        }
+    }
+}
+
+#[pymethods]
+impl ResultStream {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyAny>>> {
+        let mut next_fn = self.next_fn.lock().unwrap();
+        if let Some(f) = next_fn.as_mut() {
+            if let Some(item) = f(py)? {
+                return Ok(Some(item));
+            }
+        }
+        next_fn.take();
+
+        // exhausted: join the search thread and report any error it hit.
+        if let Some(handle) = self.handle.lock().unwrap().take() {
+            match py.allow_threads(|| handle.join()) {
+                Ok(res) => res.map_err(to_pyerr)?,
+                Err(_) => return Err(PyRuntimeError::new_err("search thread panicked")),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Stop consuming results; the background search stops at the next
+    /// attempt to send a result.
+    fn close(&self) {
+        self.next_fn.lock().unwrap().take();
+    }
+}
@@ -0,0 +1,821 @@
+//! serve: load a database once and answer search and gather requests over
+//! a small HTTP+JSON API, so that interactive services don't pay the cost
+//! of opening a large index for every query. The loaded database is also
+//! available from Python, as `BranchwaterIndex`.
+//!
+//! Endpoints:
+//!  * `GET /info` - describe the loaded database.
+//!  * `POST /search` - body is signature JSON; returns manysearch results.
+//!    Optional parameter: `threshold`.
+//!  * `POST /gather` - body is signature JSON; returns gather results.
+//!    Optional parameters: `threshold_bp`, `max_matches`, `min_ani`.
+//!  * `POST /screen` - body is signature JSON; returns the number of hashes
+//!    shared with each sketch. Optional parameter: `min_overlap`.
+use anyhow::{anyhow, Context, Result};
+use camino::Utf8PathBuf as PathBuf;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Take, Write};
+use std::net::{TcpListener, TcpStream};
+use std::str::FromStr;
+use std::sync::mpsc::{sync_channel, Receiver, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use sourmash::index::revindex::{RevIndex, RevIndexOps};
+use sourmash::prelude::Select;
+use sourmash::selection::Selection;
+
+use crate::errors::BranchwaterError;
+use crate::fastgather::fastgather_obj;
+use crate::fastmultigather_rocksdb::revindex_gather;
+use crate::manysearch::calculate_manysearch_result;
+use crate::manysearch_rocksdb::{revindex_search, MatchSizeCache};
+use crate::utils::{
+    collect_results, is_revindex_database, load_collection, revindex_selection,
+    BranchwaterGatherResult, GatherOptions, GatherThreshold, Interner, ManySearchResult,
+    MultiCollection, ReportType, RunContext, SmallSignature,
+};
+
+/// Largest request body accepted, in bytes; plenty for a query sketch.
+const MAX_BODY_SIZE: usize = 64 << 20;
+/// Largest request line plus headers accepted, in bytes.
+const MAX_HEADER_BYTES: u64 = 64 << 10;
+/// Most headers accepted in one request.
+const MAX_HEADERS: usize = 100;
+/// Request bodies are read this many bytes at a time, so that memory is
+/// only used as the body actually arrives.
+const BODY_CHUNK_SIZE: usize = 64 << 10;
+/// Connections handled at once; more wait in a queue of the same size,
+/// and beyond that are turned away with a 503.
+const MAX_CONNECTIONS: usize = 8;
+/// How often to check whether the server should stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long to wait on a slow client before giving up.
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+enum Database {
+    RocksDB {
+        db: RevIndex,
+        size_cache: MatchSizeCache,
+        names: Interner,
+    },
+    InMemory(Vec<SmallSignature>),
+}
+
+#[derive(Serialize)]
+pub struct IndexInfo {
+    database: String,
+    rocksdb: bool,
+    pub n_sketches: usize,
+    ksize: u32,
+    scaled: u32,
+    moltype: String,
+}
+
+/// The number of hashes a query shares with one sketch in the database.
+#[derive(Serialize)]
+pub struct ScreenResult {
+    pub query_name: Arc<str>,
+    pub query_md5: Arc<str>,
+    pub match_name: Arc<str>,
+    pub match_md5: Arc<str>,
+    pub intersect_hashes: u64,
+}
+
+/// A database opened (or loaded into memory) once, and then searched
+/// many times.
+pub struct LoadedIndex {
+    database: Database,
+    location: String,
+    selection: Selection,
+    n_sketches: usize,
+    threshold: f64,
+    threshold_bp: u64,
+}
+
+impl LoadedIndex {
+    /// Open a RocksDB index, or load any other collection into memory.
+    /// `threshold` and `threshold_bp` are the defaults for search and
+    /// gather requests.
+    pub fn load(
+        index: String,
+        selection: Selection,
+        threshold: f64,
+        threshold_bp: u64,
+        allow_failed_sigpaths: bool,
+    ) -> Result<Self> {
+        let mut selection = selection;
+        let path = PathBuf::from(&index);
+
+        let (database, n_sketches) = if is_revindex_database(&path) {
+            let db = match RevIndex::open(path, true, None) {
+                Ok(db) => db,
+                Err(e) => {
+                    bail!(BranchwaterError::InvalidRocksDB(format!(
+                        "cannot open RocksDB database. Error is: {}",
+                        e
+                    )))
+                }
+            };
+
+            selection = revindex_selection(&db, selection)?;
+
+            let n_sketches = db.collection().len();
+            let database = Database::RocksDB {
+                db,
+                size_cache: MatchSizeCache::new(1000),
+                names: Interner::default(),
+            };
+            (database, n_sketches)
+        } else {
+            let collection = load_collection(
+                &index,
+                &selection,
+                ReportType::Against,
+                allow_failed_sigpaths,
+                &RunContext::default(),
+            )?;
+            if selection.scaled().is_none() {
+                let scaled = *collection.max_scaled().expect("no records!?");
+                eprintln!("Setting scaled={} from the database", scaled);
+                selection.set_scaled(scaled);
+            }
+            let sketches = collection.select(&selection)?.load_sketches()?;
+            let n_sketches = sketches.len();
+            (Database::InMemory(sketches), n_sketches)
+        };
+
+        Ok(LoadedIndex {
+            database,
+            location: index,
+            selection,
+            n_sketches,
+            threshold,
+            threshold_bp,
+        })
+    }
+
+    fn scaled(&self) -> u32 {
+        self.selection.scaled().expect("scaled is not set!?")
+    }
+
+    pub fn info(&self) -> IndexInfo {
+        IndexInfo {
+            database: self.location.clone(),
+            rocksdb: matches!(self.database, Database::RocksDB { .. }),
+            n_sketches: self.n_sketches,
+            ksize: self.selection.ksize().expect("ksize not set!?"),
+            scaled: self.scaled(),
+            moltype: self
+                .selection
+                .moltype()
+                .map(|m| m.to_string())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// The ksize, moltype, and scaled that queries are compared at.
+    pub fn selection(&self) -> &Selection {
+        &self.selection
+    }
+
+    /// Select the query sketches compatible with the database, at its
+    /// ksize, moltype, and scaled.
+    pub fn select_queries(&self, queries: MultiCollection) -> Result<MultiCollection> {
+        let queries = queries.select(&self.selection)?;
+        if queries.is_empty() {
+            bail!(
+                "no compatible sketches in query; need ksize={} and scaled <= {}",
+                self.selection.ksize().expect("ksize not set!?"),
+                self.scaled()
+            );
+        }
+        Ok(queries)
+    }
+
+    /// Search each query against the database, at `threshold` or the
+    /// default containment threshold.
+    pub fn search(
+        &self,
+        queries: MultiCollection,
+        threshold: Option<f64>,
+    ) -> Result<Vec<ManySearchResult>> {
+        let queries = self.select_queries(queries)?.load_sketches()?;
+        let threshold = threshold.unwrap_or(self.threshold);
+
+        let results = match &self.database {
+            Database::RocksDB {
+                db,
+                size_cache,
+                names,
+            } => queries
+                .par_iter()
+                .flat_map_iter(|query| {
+                    revindex_search(
+                        db,
+                        &query.minhash,
+                        &query.name,
+                        &query.md5sum,
+                        threshold,
+                        false,
+                        true,
+                        false,
+                        size_cache,
+                        names,
+                        None,
+                    )
+                })
+                .collect(),
+            Database::InMemory(against) => queries
+                .iter()
+                .flat_map(|query| {
+                    against
+                        .par_iter()
+                        .filter_map(|against| {
+                            calculate_manysearch_result(
+                                query,
+                                &against.minhash,
+                                &against.name,
+                                &against.md5sum,
+                                threshold,
+                                self.scaled(),
+                                false,
+                                false,
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect(),
+        };
+        Ok(results)
+    }
+
+    /// Gather each query against the database, at `threshold_bp` or the
+    /// default gather threshold.
+    pub fn gather(
+        &self,
+        queries: MultiCollection,
+        threshold_bp: Option<u64>,
+        gather_options: GatherOptions,
+    ) -> Result<Vec<BranchwaterGatherResult>> {
+        let queries = self.select_queries(queries)?;
+        let threshold = GatherThreshold::Bp(threshold_bp.unwrap_or(self.threshold_bp));
+
+        match &self.database {
+            Database::RocksDB { db, .. } => {
+                let results = queries
+                    .load_sketches()?
+                    .par_iter()
+                    .map(|query| {
+                        revindex_gather(
+                            db,
+                            &query.minhash,
+                            &query.location,
+                            &query.name,
+                            &query.md5sum,
+                            &self.selection,
+                            threshold,
+                            &gather_options,
+                            None,
+                        )
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(results.into_iter().flatten().collect())
+            }
+            Database::InMemory(against) => {
+                let (_, results) = collect_results(|send| {
+                    Ok(fastgather_obj(
+                        &queries,
+                        against,
+                        self.scaled(),
+                        threshold,
+                        &gather_options,
+                        send,
+                        &RunContext::default(),
+                    ))
+                })?;
+                Ok(results)
+            }
+        }
+    }
+
+    /// Count the hashes each query shares with each sketch in the
+    /// database, without computing any other statistics, as a cheap
+    /// presence/absence screen. Sketches sharing fewer than `min_overlap`
+    /// hashes (default 1) are left out; the rest are listed by overlap,
+    /// largest first, for each query.
+    pub fn screen(
+        &self,
+        queries: MultiCollection,
+        min_overlap: Option<u64>,
+    ) -> Result<Vec<ScreenResult>> {
+        let queries = self.select_queries(queries)?.load_sketches()?;
+        let min_overlap = min_overlap.unwrap_or(1).max(1);
+
+        let results = match &self.database {
+            Database::RocksDB { db, names, .. } => queries
+                .par_iter()
+                .map(|query| {
+                    // most_common is sorted by overlap, largest first.
+                    db.counter_for_query(&query.minhash)
+                        .most_common()
+                        .into_iter()
+                        .take_while(|(_, overlap)| *overlap as u64 >= min_overlap)
+                        .map(|(dataset_id, overlap)| {
+                            let record = db.collection().record_for_dataset(dataset_id)?;
+                            Ok(ScreenResult {
+                                query_name: query.name.clone(),
+                                query_md5: query.md5sum.clone(),
+                                match_name: names.intern(record.name()),
+                                match_md5: names.intern(record.md5()),
+                                intersect_hashes: overlap as u64,
+                            })
+                        })
+                        .collect::<Result<Vec<_>>>()
+                })
+                .collect::<Result<Vec<_>>>()?,
+            Database::InMemory(against) => queries
+                .iter()
+                .map(|query| {
+                    let mut matches: Vec<ScreenResult> = against
+                        .par_iter()
+                        .filter_map(|against| {
+                            let overlap =
+                                against.minhash.count_common(&query.minhash, true).ok()?;
+                            (overlap >= min_overlap).then(|| ScreenResult {
+                                query_name: query.name.clone(),
+                                query_md5: query.md5sum.clone(),
+                                match_name: against.name.clone(),
+                                match_md5: against.md5sum.clone(),
+                                intersect_hashes: overlap,
+                            })
+                        })
+                        .collect();
+                    matches.sort_by_key(|m| Reverse(m.intersect_hashes));
+                    Ok(matches)
+                })
+                .collect::<Result<Vec<_>>>()?,
+        };
+        Ok(results.into_iter().flatten().collect())
+    }
+
+    /// Handle one request, returning the HTTP status and JSON body.
+    fn respond(
+        &self,
+        method: &str,
+        path: &str,
+        params: &HashMap<String, String>,
+        body: &[u8],
+    ) -> (u16, String) {
+        let result = match (method, path) {
+            ("GET", "/info") => to_json(&self.info()),
+            ("POST", "/search") => optional_param(params, "threshold")
+                .and_then(|threshold| self.search(from_body(body)?, threshold))
+                .and_then(|results| to_json(&results)),
+            ("POST", "/gather") => gather_params(params)
+                .and_then(|(threshold_bp, options)| {
+                    self.gather(from_body(body)?, threshold_bp, options)
+                })
+                .and_then(|results| to_json(&results)),
+            ("POST", "/screen") => optional_param(params, "min_overlap")
+                .and_then(|min_overlap| self.screen(from_body(body)?, min_overlap))
+                .and_then(|results| to_json(&results)),
+            (_, "/info" | "/search" | "/gather" | "/screen") => {
+                return error_response(405, &format!("method {} not allowed", method))
+            }
+            _ => return error_response(404, &format!("unknown endpoint '{}'", path)),
+        };
+
+        match result {
+            Ok(json) => (200, json),
+            Err(e) => error_response(400, &format!("{:#}", e)),
+        }
+    }
+}
+
+/// Load the query sketches in a request body.
+fn from_body(body: &[u8]) -> Result<MultiCollection> {
+    MultiCollection::from_signature_json(body)
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<String> {
+    Ok(serde_json::to_string(value)?)
+}
+
+fn error_response(status: u16, message: &str) -> (u16, String) {
+    let body = serde_json::json!({ "error": message });
+    (status, body.to_string())
+}
+
+/// Parse a request parameter, if given.
+fn optional_param<T: FromStr>(params: &HashMap<String, String>, name: &str) -> Result<Option<T>> {
+    params
+        .get(name)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| anyhow!("invalid value '{}' for parameter '{}'", value, name))
+        })
+        .transpose()
+}
+
+fn gather_params(params: &HashMap<String, String>) -> Result<(Option<u64>, GatherOptions)> {
+    let threshold_bp = optional_param(params, "threshold_bp")?;
+    let options = GatherOptions::new(
+        optional_param(params, "max_matches")?,
+        optional_param(params, "min_ani")?,
+        false,
+    )?;
+    Ok((threshold_bp, options))
+}
+
+/// A request line or headers over the size limits, answered with a 431
+/// rather than a 400.
+#[derive(Debug)]
+struct HeadTooLarge(String);
+
+impl std::fmt::Display for HeadTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for HeadTooLarge {}
+
+struct RequestHead {
+    method: String,
+    path: String,
+    params: HashMap<String, String>,
+    content_length: usize,
+    expect_continue: bool,
+}
+
+/// Read one line of the request head, from a reader limited to what is
+/// left of `MAX_HEADER_BYTES`.
+fn read_head_line<R: BufRead>(reader: &mut Take<R>, line: &mut String) -> Result<usize> {
+    let n = reader.read_line(line)?;
+    if n > 0 && !line.ends_with('\n') {
+        if reader.limit() == 0 {
+            bail!(HeadTooLarge(format!(
+                "request line and headers are larger than the maximum of {} bytes",
+                MAX_HEADER_BYTES
+            )));
+        }
+        bail!("unexpected end of request headers");
+    }
+    Ok(n)
+}
+
+/// Read the request line and headers, up to the blank line.
+fn read_head(reader: &mut impl BufRead) -> Result<RequestHead> {
+    let mut reader = reader.take(MAX_HEADER_BYTES);
+    let mut line = String::new();
+    read_head_line(&mut reader, &mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        bail!("malformed request line");
+    };
+
+    let mut content_length = 0;
+    let mut expect_continue = false;
+    let mut n_headers = 0;
+    loop {
+        let mut header = String::new();
+        if read_head_line(&mut reader, &mut header)? == 0 {
+            bail!("unexpected end of request headers");
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        n_headers += 1;
+        if n_headers > MAX_HEADERS {
+            bail!(HeadTooLarge(format!(
+                "more than the maximum of {} request headers",
+                MAX_HEADERS
+            )));
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            bail!("malformed header '{}'", header);
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value
+                .parse()
+                .map_err(|_| anyhow!("invalid Content-Length '{}'", value))?;
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            bail!("chunked request bodies are not supported; send a Content-Length");
+        } else if name.eq_ignore_ascii_case("expect") {
+            expect_continue = value.eq_ignore_ascii_case("100-continue");
+        }
+    }
+    if content_length > MAX_BODY_SIZE {
+        bail!(
+            "request body of {} bytes is larger than the maximum of {}",
+            content_length,
+            MAX_BODY_SIZE
+        );
+    }
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let params = query
+        .split('&')
+        .filter(|kv| !kv.is_empty())
+        .map(|kv| {
+            let (k, v) = kv.split_once('=').unwrap_or((kv, ""));
+            Ok((percent_decode(k)?, percent_decode(v)?))
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(RequestHead {
+        method: method.to_string(),
+        path: path.to_string(),
+        params,
+        content_length,
+        expect_continue,
+    })
+}
+
+/// Decode a query string component: `%XX` escapes, and `+` for a space.
+fn percent_decode(s: &str) -> Result<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        rest = tail;
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = rest
+                    .get(..2)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| anyhow!("invalid percent-encoding in '{}'", s))?;
+                bytes.push(hex);
+                rest = &rest[2..];
+            }
+            _ => bytes.push(b),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| anyhow!("invalid UTF-8 in '{}'", s))
+}
+
+fn write_response(mut stream: impl Write, status: u16, body: &str) -> Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "Error",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Read a body of `content_length` bytes, a chunk at a time.
+fn read_body(reader: &mut impl Read, content_length: usize) -> Result<Vec<u8>> {
+    let mut body = Vec::with_capacity(content_length.min(BODY_CHUNK_SIZE));
+    let mut chunk = vec![0; BODY_CHUNK_SIZE];
+    while body.len() < content_length {
+        let want = (content_length - body.len()).min(BODY_CHUNK_SIZE);
+        let n = reader
+            .read(&mut chunk[..want])
+            .context("failed to read request body")?;
+        if n == 0 {
+            bail!(
+                "request body ended after {} of {} bytes",
+                body.len(),
+                content_length
+            );
+        }
+        body.extend_from_slice(&chunk[..n]);
+    }
+    Ok(body)
+}
+
+/// Handle connections from `queue` until it is closed.
+fn worker(server: &LoadedIndex, queue: &Mutex<Receiver<TcpStream>>) {
+    loop {
+        // hold the lock only while waiting, not while handling.
+        let stream = queue.lock().unwrap().recv();
+        let Ok(stream) = stream else {
+            return;
+        };
+        if let Err(e) = handle_connection(server, stream) {
+            eprintln!("WARNING: error handling request: {:#}", e);
+        }
+    }
+}
+
+fn handle_connection(server: &LoadedIndex, stream: TcpStream) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+
+    let (status, body) = match read_head(&mut reader) {
+        Ok(head) => {
+            if head.expect_continue {
+                (&stream).write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+            }
+            let body = read_body(&mut reader, head.content_length)?;
+            server.respond(&head.method, &head.path, &head.params, &body)
+        }
+        Err(e) => {
+            let status = if e.is::<HeadTooLarge>() { 431 } else { 400 };
+            error_response(status, &format!("{:#}", e))
+        }
+    };
+
+    write_response(&stream, status, &body)
+}
+
+/// Load `index` and answer requests on `host:port` until `stop` returns
+/// true. Connections are handled by a fixed pool of worker threads.
+#[allow(clippy::too_many_arguments)]
+pub fn serve(
+    index: String,
+    selection: Selection,
+    host: &str,
+    port: u16,
+    threshold: f64,
+    threshold_bp: u64,
+    allow_failed_sigpaths: bool,
+    stop: &(dyn Fn() -> bool + Sync),
+) -> Result<()> {
+    let server = LoadedIndex::load(
+        index,
+        selection,
+        threshold,
+        threshold_bp,
+        allow_failed_sigpaths,
+    )?;
+    eprintln!(
+        "Loaded {} sketches from '{}'",
+        server.n_sketches, server.location
+    );
+
+    let listener = TcpListener::bind((host, port))
+        .with_context(|| format!("cannot listen on {}:{}", host, port))?;
+    listener.set_nonblocking(true)?;
+    eprintln!("Listening on http://{}", listener.local_addr()?);
+
+    let (send, recv) = sync_channel::<TcpStream>(MAX_CONNECTIONS);
+    let queue = Mutex::new(recv);
+    std::thread::scope(|s| {
+        for _ in 0..MAX_CONNECTIONS {
+            s.spawn(|| worker(&server, &queue));
+        }
+        while !stop() {
+            match listener.accept() {
+                Ok((stream, _)) => match send.try_send(stream) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(stream)) => {
+                        let (status, body) = error_response(503, "server busy; try again later");
+                        if let Err(e) = write_response(&stream, status, &body) {
+                            eprintln!("WARNING: error turning away request: {:#}", e);
+                        }
+                    }
+                    Err(TrySendError::Disconnected(_)) => unreachable!("workers stopped early"),
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => std::thread::sleep(POLL_INTERVAL),
+                Err(e) => eprintln!("WARNING: failed to accept connection: {}", e),
+            }
+        }
+        // let the workers finish the queued connections, and stop.
+        drop(send);
+    });
+
+    eprintln!("Server stopped.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::build_selection;
+
+    fn test_data(name: &str) -> String {
+        format!(
+            "{}/src/python/tests/test-data/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        )
+    }
+
+    #[test]
+    fn test_read_head() {
+        let request = "POST /gather?threshold_bp=1000&min_ani=0.9 HTTP/1.1\r\n\
+                       Host: localhost\r\n\
+                       Content-Length: 4\r\n\
+                       \r\n\
+                       body";
+        let mut reader = request.as_bytes();
+        let head = read_head(&mut reader).unwrap();
+        assert_eq!(head.method, "POST");
+        assert_eq!(head.path, "/gather");
+        assert_eq!(head.content_length, 4);
+        assert_eq!(head.params["threshold_bp"], "1000");
+
+        let (threshold_bp, options) = gather_params(&head.params).unwrap();
+        assert_eq!(threshold_bp, Some(1000));
+        assert_eq!(options.min_ani, Some(0.9));
+        assert_eq!(options.max_matches, None);
+        assert_eq!(reader, b"body");
+    }
+
+    #[test]
+    fn test_read_head_decodes_params() {
+        let request = "GET /search?threshold=0%2E5&name=a+b%20c HTTP/1.1\r\n\r\n";
+        let head = read_head(&mut request.as_bytes()).unwrap();
+        assert_eq!(head.params["threshold"], "0.5");
+        assert_eq!(head.params["name"], "a b c");
+
+        let request = "GET /search?threshold=%zz HTTP/1.1\r\n\r\n";
+        let err = read_head(&mut request.as_bytes()).err().unwrap();
+        assert!(!err.is::<HeadTooLarge>());
+    }
+
+    #[test]
+    fn test_read_head_limits() {
+        let long_header = format!(
+            "GET /info HTTP/1.1\r\nX-Long: {}\r\n\r\n",
+            "a".repeat(MAX_HEADER_BYTES as usize)
+        );
+        let err = read_head(&mut long_header.as_bytes()).err().unwrap();
+        assert!(err.is::<HeadTooLarge>());
+
+        let many_headers = format!(
+            "GET /info HTTP/1.1\r\n{}\r\n",
+            "X-Header: 1\r\n".repeat(MAX_HEADERS + 1)
+        );
+        let err = read_head(&mut many_headers.as_bytes()).err().unwrap();
+        assert!(err.is::<HeadTooLarge>());
+
+        let truncated = "GET /info HTTP/1.1\r\nHost: local";
+        let err = read_head(&mut truncated.as_bytes()).err().unwrap();
+        assert!(!err.is::<HeadTooLarge>());
+    }
+
+    #[test]
+    fn test_read_body() {
+        let body = vec![7u8; BODY_CHUNK_SIZE * 2 + 10];
+        assert_eq!(read_body(&mut body.as_slice(), body.len()).unwrap(), body);
+
+        let err = read_body(&mut &body[..10], 20).unwrap_err();
+        assert!(err.to_string().contains("ended after 10 of 20 bytes"));
+    }
+
+    #[test]
+    fn test_respond() {
+        let selection = build_selection(31, None, "DNA").unwrap();
+        let server =
+            LoadedIndex::load(test_data("47.fa.sig.gz"), selection, 0.01, 50000, true).unwrap();
+        let query = std::fs::read(test_data("47.fa.sig.gz")).unwrap();
+        let no_params = HashMap::new();
+
+        let (status, body) = server.respond("GET", "/info", &no_params, &[]);
+        assert_eq!(status, 200);
+        let info: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(info["n_sketches"], 1);
+        assert_eq!(info["scaled"], 1000);
+
+        let (status, body) = server.respond("POST", "/search", &no_params, &query);
+        assert_eq!(status, 200);
+        let results: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(results.as_array().unwrap().len(), 1);
+        assert_eq!(results[0]["containment"], 1.0);
+
+        let (status, body) = server.respond("POST", "/gather", &no_params, &query);
+        assert_eq!(status, 200);
+        let results: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(results.as_array().unwrap().len(), 1);
+        assert_eq!(results[0]["f_unique_to_query"], 1.0);
+
+        let (status, body) = server.respond("POST", "/screen", &no_params, &query);
+        assert_eq!(status, 200);
+        let results: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(results.as_array().unwrap().len(), 1);
+        assert_eq!(results[0]["match_md5"], results[0]["query_md5"]);
+
+        let (status, body) = server.respond("POST", "/search", &no_params, b"not a sketch");
+        assert_eq!(status, 400);
+        assert!(body.contains("error"));
+
+        let (status, _) = server.respond("GET", "/search", &no_params, &[]);
+        assert_eq!(status, 405);
+        let (status, _) = server.respond("GET", "/nothing", &no_params, &[]);
+        assert_eq!(status, 404);
+    }
+}
@@ -0,0 +1,189 @@
+/// shard: split a collection into balanced shards.
+use anyhow::{anyhow, Result};
+use rayon::prelude::*;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::atomic;
+use std::sync::atomic::AtomicUsize;
+
+use sourmash::collection::Collection;
+use sourmash::manifest::Record;
+use sourmash::signature::Signature;
+
+use crate::utils::atomicfile::AtomicFile;
+use crate::utils::buildutils::{BuildCollection, BuildManifest, BuildRecord};
+use crate::utils::{load_collection_ksizes, zipwriter_handle, ReportType};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShardBy {
+    Count,
+    Hashes,
+}
+
+impl std::str::FromStr for ShardBy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "count" => Ok(ShardBy::Count),
+            "hashes" => Ok(ShardBy::Hashes),
+            _ => bail!("unknown shard balancing '{}'; use 'count' or 'hashes'", s),
+        }
+    }
+}
+
+/// Assign weighted items to `n_shards` bins, largest first, each to the
+/// currently lightest bin. Ties go to the lowest-numbered bin.
+fn balance(weights: &[usize], n_shards: usize) -> Vec<Vec<usize>> {
+    let mut order: Vec<usize> = (0..weights.len()).collect();
+    order.sort_by_key(|i| Reverse(weights[*i]));
+
+    let mut bins: BinaryHeap<Reverse<(usize, usize)>> =
+        (0..n_shards).map(|i| Reverse((0, i))).collect();
+    let mut shards = vec![vec![]; n_shards];
+    for item in order {
+        let Reverse((total, shard)) = bins.pop().expect("no shards!?");
+        shards[shard].push(item);
+        bins.push(Reverse((total + weights[item], shard)));
+    }
+    shards
+}
+
+pub fn shard(
+    siglist: String,
+    ksizes: Vec<u8>,
+    moltype: String,
+    n_shards: usize,
+    by: ShardBy,
+    output_prefix: String,
+    allow_failed_sigpaths: bool,
+) -> Result<()> {
+    if n_shards == 0 {
+        bail!("Number of shards must be at least 1.");
+    }
+
+    let collection = load_collection_ksizes(
+        &siglist,
+        &ksizes,
+        None,
+        &moltype,
+        ReportType::General,
+        allow_failed_sigpaths,
+    )?;
+
+    // keep all sketches from the same signature (e.g. ksizes) together;
+    // in zip files each sketch is stored separately, so use name/filename.
+    let mut groups: Vec<Vec<(&Collection, &Record)>> = vec![];
+    let mut group_idx: HashMap<(&str, &str), usize> = HashMap::new();
+    for (coll, _idx, record) in collection.item_iter() {
+        let key = (record.name().as_str(), record.filename().as_str());
+        let idx = *group_idx.entry(key).or_insert_with(|| {
+            groups.push(vec![]);
+            groups.len() - 1
+        });
+        groups[idx].push((coll, record));
+    }
+
+    if groups.len() < n_shards {
+        bail!(
+            "Cannot split {} signatures into {} shards.",
+            groups.len(),
+            n_shards
+        );
+    }
+
+    let weights: Vec<usize> = groups
+        .iter()
+        .map(|records| match by {
+            ShardBy::Count => 1,
+            ShardBy::Hashes => records.iter().map(|(_, r)| *r.n_hashes()).sum(),
+        })
+        .collect();
+    let shards = balance(&weights, n_shards);
+
+    let width = (n_shards - 1).to_string().len();
+    let failed_sigs = AtomicUsize::new(0);
+    let mut shard_manifest = BuildManifest::new();
+
+    // write one shard at a time, loading each shard's sketches in parallel.
+    for (i, shard) in shards.iter().enumerate() {
+        let shard_path = format!("{}.{:0width$}.zip", output_prefix, i, width = width);
+        let records: Vec<(&Collection, &Record)> = shard
+            .iter()
+            .flat_map(|g| groups[*g].iter().copied())
+            .collect();
+
+        let (send, recv) =
+            std::sync::mpsc::sync_channel::<Option<BuildCollection>>(rayon::current_num_threads());
+        let thrd = zipwriter_handle(recv, shard_path.clone());
+
+        let written: Result<Vec<Vec<BuildRecord>>> = records
+            .par_iter()
+            .map_with(send.clone(), |s, (coll, record)| {
+                let sig = match coll.sig_from_record(record) {
+                    Ok(sig) => sig,
+                    Err(e) => {
+                        eprintln!(
+                            "WARNING: could not load sketch from '{}': {}",
+                            record.internal_location(),
+                            e
+                        );
+                        failed_sigs.fetch_add(1, atomic::Ordering::SeqCst);
+                        return Ok(vec![]);
+                    }
+                };
+
+                let mut sigs = BuildCollection::new();
+                sigs.add_sig(Signature::from(sig))?;
+                let built: Vec<BuildRecord> = sigs.manifest.iter().cloned().collect();
+                s.send(Some(sigs))
+                    .map_err(|e| anyhow!("Unable to send internal data: {:?}", e))?;
+                Ok(built)
+            })
+            .collect();
+
+        // Send None to sigwriter to signal completion + write manifest
+        if let Err(e) = send.send(None) {
+            eprintln!("Unable to send completion signal: {:?}", e);
+        }
+        thrd.join()
+            .unwrap_or_else(|e| Err(anyhow!("Thread panicked: {:?}", e)))?;
+
+        let mut n_hashes = 0;
+        let mut n_sketches = 0;
+        for mut record in written?.into_iter().flatten() {
+            n_hashes += record.n_hashes().unwrap_or_default();
+            n_sketches += 1;
+            record.set_internal_location(Some(shard_path.clone().into()));
+            shard_manifest.add_record(record);
+        }
+        eprintln!(
+            "Wrote shard '{}': {} sketches from {} signatures, {} hashes",
+            shard_path,
+            n_sketches,
+            shard.len(),
+            n_hashes
+        );
+    }
+
+    // a standalone manifest covering all shards, loadable as one collection.
+    let manifest_path = format!("{}.manifest.csv", output_prefix);
+    let mut file = AtomicFile::create(&manifest_path)?;
+    shard_manifest.to_writer(&mut file)?;
+    file.commit()?;
+
+    eprintln!(
+        "DONE. Wrote {} shards; shard manifest in '{}'",
+        n_shards, manifest_path
+    );
+
+    let failed_sigs = failed_sigs.load(atomic::Ordering::SeqCst);
+    if failed_sigs > 0 {
+        eprintln!(
+            "WARNING: {} sketches failed to load. See error messages above.",
+            failed_sigs
+        );
+    }
+
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use crate::utils::buildutils::BuildCollection;
+use crate::sketch::SketchParams;
 use anyhow::{bail, Result};
 
 pub fn singlesketch(
@@ -9,17 +9,12 @@ pub fn singlesketch(
     name: String,
 ) -> Result<()> {
     // parse params --> signature templates
-    let sig_template_result = BuildCollection::from_param_str(param_str.as_str());
-    let mut sigs = match sig_template_result {
-        Ok(sigs) => sigs,
-        Err(e) => {
-            bail!("Failed to parse params string: {}", e);
-        }
-    };
+    let params = SketchParams::parse(&param_str)?;
     // print sig templates to build
-    let _params = sigs.summarize_params();
+    params.summarize();
 
     let input_moltype = input_moltype.to_ascii_lowercase();
+    let mut sigs = params.templates(&input_moltype)?;
 
     // Build signature templates based on parsed parameters and detected moltype
     if sigs.is_empty() {
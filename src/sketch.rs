@@ -0,0 +1,111 @@
+//! Build branchwater-compatible sketches in-process.
+//!
+//! This is the sketching core of `manysketch` and `singlesketch` - the
+//! same param strings, hashing, and output files - for tools that hold
+//! their sequences in memory rather than in FASTA files:
+//!
+//! ```no_run
+//! use sourmash_plugin_branchwater::{write_sketches, SketchParams};
+//!
+//! let params = SketchParams::parse("k=21,k=31,scaled=1000")?;
+//! let sigs = params.sketch_sequences(["ACGTACGTTGCA", "GATTACAGATTACA"], "DNA", "genome1")?;
+//! write_sketches(sigs, "genome1.sig.zip")?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+use anyhow::{anyhow, Result};
+use sourmash::signature::Signature;
+
+use crate::utils::buildutils::{BuildCollection, MultiSelect, MultiSelection};
+
+/// Sketching parameters, parsed from a param string as for `manysketch
+/// -p`, e.g. `k=21,k=31,scaled=1000,abund`. Several param strings can be
+/// joined with `_`, e.g. `dna,k=31,scaled=1000_protein,k=10,scaled=200`.
+#[derive(Debug, Clone)]
+pub struct SketchParams {
+    templates: BuildCollection,
+}
+
+impl SketchParams {
+    pub fn parse(param_str: &str) -> Result<Self> {
+        let templates = BuildCollection::from_param_str(param_str)
+            .map_err(|e| anyhow!("Failed to parse params string: {}", e))?;
+        Ok(SketchParams { templates })
+    }
+
+    /// Print the sketch types to build.
+    #[cfg(feature = "python")]
+    pub(crate) fn summarize(&self) {
+        let _params = self.templates.summarize_params();
+    }
+
+    /// The signature templates that can be built from `input_moltype`
+    /// sequence (DNA or protein); empty if there are none.
+    pub(crate) fn templates(&self, input_moltype: &str) -> Result<BuildCollection> {
+        let mut templates = self.templates.clone();
+        let selection = MultiSelection::from_input_moltype(input_moltype)?;
+        templates.select(&selection)?;
+        Ok(templates)
+    }
+
+    fn compatible_templates(&self, input_moltype: &str) -> Result<(BuildCollection, String)> {
+        let input_moltype = input_moltype.to_ascii_lowercase();
+        if input_moltype != "dna" && input_moltype != "protein" {
+            bail!(
+                "input moltype must be DNA or protein, not '{}'",
+                input_moltype
+            );
+        }
+        let templates = self.templates(&input_moltype)?;
+        if templates.is_empty() {
+            bail!(
+                "no sketches in the params can be built from {} sequence",
+                input_moltype
+            );
+        }
+        Ok((templates, input_moltype))
+    }
+
+    /// Sketch `sequences` (e.g. the contigs of one genome) together, as
+    /// one signature named `name` per set of parameters.
+    pub fn sketch_sequences<I, S>(
+        &self,
+        sequences: I,
+        input_moltype: &str,
+        name: &str,
+    ) -> Result<Vec<Signature>>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<[u8]>,
+    {
+        let (mut sigs, input_moltype) = self.compatible_templates(input_moltype)?;
+        for seq in sequences {
+            sigs.add_sequence(&input_moltype, seq.as_ref())?;
+        }
+        sigs.update_info(name.to_string(), String::new());
+        Ok(sigs.into_sigs())
+    }
+
+    /// Sketch the records of FASTA/FASTQ `data`, which may be compressed,
+    /// together, as one signature named `name` per set of parameters.
+    pub fn sketch_fastx(
+        &self,
+        data: Vec<u8>,
+        input_moltype: &str,
+        name: &str,
+    ) -> Result<Vec<Signature>> {
+        let (mut sigs, input_moltype) = self.compatible_templates(input_moltype)?;
+        sigs.build_sigs_from_data(data, &input_moltype, name.to_string(), String::new())?;
+        Ok(sigs.into_sigs())
+    }
+}
+
+/// Write signatures to `output`, as `manysketch` and `singlesketch` do: a
+/// zip file with a manifest, or JSON (gzipped if `output` ends in `.gz`),
+/// or JSON to stdout for `-`.
+pub fn write_sketches(sigs: Vec<Signature>, output: &str) -> Result<()> {
+    let mut collection = BuildCollection::new();
+    for sig in sigs {
+        collection.add_sig(sig)?;
+    }
+    collection.write_sigs(output)
+}
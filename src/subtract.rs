@@ -0,0 +1,203 @@
+/// subtract: remove contaminant hashes from many sketches, in parallel.
+use anyhow::{anyhow, Result};
+use camino::Utf8Path as Path;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::atomic;
+use std::sync::atomic::AtomicUsize;
+
+use sourmash::selection::Selection;
+use sourmash::signature::{Signature, SigsTrait};
+use sourmash::sketch::minhash::KmerMinHash;
+use sourmash::sketch::Sketch;
+use sourmash::storage::SigStore;
+
+use crate::utils::buildutils::BuildCollection;
+use crate::utils::{csvwriter_thread, load_collection, zipwriter_handle, ReportType, RunContext};
+
+#[derive(Serialize)]
+struct SubtractResult {
+    name: String,
+    filename: String,
+    md5: String,
+    n_hashes: usize,
+    n_removed: usize,
+    n_remaining: usize,
+    f_removed: f64,
+    cleaned_md5: String,
+}
+
+pub fn subtract(
+    siglist: String,
+    contaminants: String,
+    selection: Selection,
+    output: String,
+    report: Option<String>,
+    allow_failed_sigpaths: bool,
+) -> Result<()> {
+    // if output doesn't end in zip, bail
+    if Path::new(&output).extension() != Some("zip") {
+        bail!("Output must be a zip file.");
+    }
+
+    let collection = load_collection(
+        &siglist,
+        &selection,
+        ReportType::General,
+        allow_failed_sigpaths,
+        &RunContext::default(),
+    )?;
+    let contam_collection = load_collection(
+        &contaminants,
+        &selection,
+        ReportType::Against,
+        allow_failed_sigpaths,
+        &RunContext::default(),
+    )?;
+
+    // subtract at the coarsest scaled of the two, unless told otherwise.
+    let scaled = match selection.scaled() {
+        Some(s) => s,
+        None => {
+            let s = *collection
+                .max_scaled()
+                .max(contam_collection.max_scaled())
+                .expect("no records!?");
+            eprintln!("Setting scaled={} based on max scaled in inputs", s);
+            s
+        }
+    };
+
+    let mut contam_hashes: HashSet<u64> = HashSet::new();
+    for sketch in contam_collection.load_sketches()? {
+        let mh = if sketch.minhash.scaled() != scaled {
+            sketch.minhash.downsample_scaled(scaled)?
+        } else {
+            sketch.minhash
+        };
+        contam_hashes.extend(mh.mins());
+    }
+    eprintln!(
+        "Loaded {} contaminant hashes from '{}'",
+        contam_hashes.len(),
+        contaminants
+    );
+
+    let (send, recv) =
+        std::sync::mpsc::sync_channel::<Option<BuildCollection>>(rayon::current_num_threads());
+    let thrd = zipwriter_handle(recv, output);
+
+    let (report_send, report_thrd) = match report {
+        Some(path) => {
+            let (send, recv) =
+                std::sync::mpsc::sync_channel::<SubtractResult>(rayon::current_num_threads());
+            (Some(send), Some(csvwriter_thread(recv, Some(path))))
+        }
+        None => (None, None),
+    };
+
+    let processed_sigs = AtomicUsize::new(0);
+    let failed_sigs = AtomicUsize::new(0);
+    let total_removed = AtomicUsize::new(0);
+
+    let send_result = collection.par_iter().try_for_each_with(
+        (send.clone(), report_send.clone()),
+        |(s, r), (coll, _idx, record)| -> Result<()> {
+            let sig = match coll.sig_from_record(record) {
+                Ok(sig) => sig,
+                Err(e) => {
+                    eprintln!(
+                        "WARNING: could not load sketch from '{}': {}",
+                        record.internal_location(),
+                        e
+                    );
+                    failed_sigs.fetch_add(1, atomic::Ordering::SeqCst);
+                    return Ok(());
+                }
+            };
+            let name = sig.name();
+            let filename = sig.filename();
+            let md5 = sig.md5sum();
+
+            let mut mh = <SigStore as TryInto<KmerMinHash>>::try_into(sig)?;
+            if mh.scaled() != scaled {
+                mh = mh.downsample_scaled(scaled)?;
+            }
+
+            let n_hashes = mh.size();
+            let to_remove: Vec<u64> = mh
+                .mins()
+                .into_iter()
+                .filter(|h| contam_hashes.contains(h))
+                .collect();
+            let n_removed = to_remove.len();
+            mh.remove_many(to_remove)?;
+
+            let mut cleaned = Signature::default();
+            cleaned.push(Sketch::MinHash(mh));
+            cleaned.set_name(&name);
+            cleaned.set_filename(&filename);
+            let cleaned_md5 = cleaned.md5sum();
+
+            let mut sigs = BuildCollection::new();
+            sigs.add_sig(cleaned)?;
+            s.send(Some(sigs))
+                .map_err(|e| anyhow!("Unable to send internal data: {:?}", e))?;
+
+            if let Some(r) = r {
+                r.send(SubtractResult {
+                    name,
+                    filename,
+                    md5,
+                    n_hashes,
+                    n_removed,
+                    n_remaining: n_hashes - n_removed,
+                    f_removed: if n_hashes > 0 {
+                        n_removed as f64 / n_hashes as f64
+                    } else {
+                        0.0
+                    },
+                    cleaned_md5,
+                })
+                .map_err(|e| anyhow!("Unable to send internal data: {:?}", e))?;
+            }
+
+            processed_sigs.fetch_add(1, atomic::Ordering::SeqCst);
+            total_removed.fetch_add(n_removed, atomic::Ordering::SeqCst);
+            Ok(())
+        },
+    );
+
+    // Send None to sigwriter to signal completion + write manifest
+    if let Err(e) = send.send(None) {
+        eprintln!("Unable to send completion signal: {:?}", e);
+    }
+    drop(report_send);
+
+    thrd.join()
+        .unwrap_or_else(|e| Err(anyhow!("Thread panicked: {:?}", e)))?;
+    if let Some(report_thrd) = report_thrd {
+        report_thrd
+            .join()
+            .expect("Unable to join CSV writing thread.");
+    }
+    send_result?;
+
+    let n_processed = processed_sigs.load(atomic::Ordering::SeqCst);
+    eprintln!(
+        "DONE. Removed {} hashes in total from {} sketches.",
+        total_removed.load(atomic::Ordering::SeqCst),
+        n_processed
+    );
+
+    let failed_sigs = failed_sigs.load(atomic::Ordering::SeqCst);
+    if failed_sigs > 0 {
+        eprintln!(
+            "WARNING: {} sketches failed to load. See error messages above.",
+            failed_sigs
+        );
+    }
+
+    Ok(())
+}
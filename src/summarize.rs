@@ -0,0 +1,263 @@
+/// summarize: report what is in a collection, using only its manifest.
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+
+use sourmash::manifest::Record;
+use sourmash::selection::Selection;
+
+use crate::utils::{finish_csv, load_collection, open_stdout_or_file, ReportType, RunContext};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SummaryFormat {
+    Csv,
+    Json,
+}
+
+impl std::str::FromStr for SummaryFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "csv" => Ok(SummaryFormat::Csv),
+            "json" => Ok(SummaryFormat::Json),
+            _ => bail!("unknown summary format '{}'; use 'csv' or 'json'", s),
+        }
+    }
+}
+
+/// Sketches sharing ksize, moltype, scaled, num, and abundance tracking.
+#[derive(Serialize)]
+struct GroupSummary {
+    ksize: u32,
+    moltype: String,
+    scaled: u32,
+    num: u32,
+    with_abundance: bool,
+    n_sketches: usize,
+    total_hashes: usize,
+    mean_hashes: f64,
+    min_hashes: usize,
+    max_hashes: usize,
+    n_duplicate_md5s: usize,
+    n_name_collisions: usize,
+}
+
+#[derive(Serialize)]
+struct DuplicateMd5 {
+    md5: String,
+    name: String,
+    count: usize,
+}
+
+/// A name used by sketches with different md5s, in the same group.
+#[derive(Serialize)]
+struct NameCollision {
+    name: String,
+    ksize: u32,
+    moltype: String,
+    md5s: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct CollectionSummary {
+    source: String,
+    n_sketches: usize,
+    total_hashes: usize,
+    mean_hashes: f64,
+    groups: Vec<GroupSummary>,
+    duplicate_md5s: Vec<DuplicateMd5>,
+    name_collisions: Vec<NameCollision>,
+}
+
+type GroupKey = (u32, String, u32, u32, bool);
+
+fn group_key(record: &Record) -> GroupKey {
+    (
+        record.ksize(),
+        record.moltype().to_string(),
+        *record.scaled(),
+        *record.num(),
+        record.with_abundance(),
+    )
+}
+
+fn mean(total: usize, n: usize) -> f64 {
+    if n == 0 {
+        0.0
+    } else {
+        total as f64 / n as f64
+    }
+}
+
+fn summarize_records(source: String, records: &[&Record]) -> CollectionSummary {
+    let mut by_group: BTreeMap<GroupKey, Vec<&Record>> = BTreeMap::new();
+    for record in records {
+        by_group.entry(group_key(record)).or_default().push(record);
+    }
+
+    let mut groups = vec![];
+    let mut duplicate_md5s = vec![];
+    let mut name_collisions = vec![];
+    for ((ksize, moltype, scaled, num, with_abundance), members) in by_group {
+        let mut md5_counts: BTreeMap<&str, (usize, &str)> = BTreeMap::new();
+        let mut name_md5s: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+        for record in &members {
+            md5_counts
+                .entry(record.md5().as_str())
+                .or_insert((0, record.name().as_str()))
+                .0 += 1;
+            name_md5s
+                .entry(record.name().as_str())
+                .or_default()
+                .insert(record.md5().as_str());
+        }
+
+        let n_duplicate_md5s = md5_counts.values().filter(|(n, _)| *n > 1).count();
+        duplicate_md5s.extend(md5_counts.iter().filter(|(_, (n, _))| *n > 1).map(
+            |(md5, (count, name))| DuplicateMd5 {
+                md5: md5.to_string(),
+                name: name.to_string(),
+                count: *count,
+            },
+        ));
+
+        let n_name_collisions = name_md5s.values().filter(|m| m.len() > 1).count();
+        name_collisions.extend(name_md5s.iter().filter(|(_, m)| m.len() > 1).map(
+            |(name, md5s)| NameCollision {
+                name: name.to_string(),
+                ksize,
+                moltype: moltype.clone(),
+                md5s: md5s.iter().map(|m| m.to_string()).collect(),
+            },
+        ));
+
+        let hashes: Vec<usize> = members.iter().map(|r| *r.n_hashes()).collect();
+        let total_hashes: usize = hashes.iter().sum();
+        groups.push(GroupSummary {
+            ksize,
+            moltype,
+            scaled,
+            num,
+            with_abundance,
+            n_sketches: members.len(),
+            total_hashes,
+            mean_hashes: mean(total_hashes, members.len()),
+            min_hashes: hashes.iter().copied().min().unwrap_or_default(),
+            max_hashes: hashes.iter().copied().max().unwrap_or_default(),
+            n_duplicate_md5s,
+            n_name_collisions,
+        });
+    }
+
+    let total_hashes = records.iter().map(|r| *r.n_hashes()).sum();
+    CollectionSummary {
+        source,
+        n_sketches: records.len(),
+        total_hashes,
+        mean_hashes: mean(total_hashes, records.len()),
+        groups,
+        duplicate_md5s,
+        name_collisions,
+    }
+}
+
+pub fn summarize(
+    siglist: String,
+    output: Option<String>,
+    format: SummaryFormat,
+    allow_failed_sigpaths: bool,
+) -> Result<()> {
+    // no selection: summarize every sketch in the collection.
+    let collection = load_collection(
+        &siglist,
+        &Selection::default(),
+        ReportType::General,
+        allow_failed_sigpaths,
+        &RunContext::default(),
+    )?;
+
+    let records: Vec<&Record> = collection.item_iter().map(|(_, _, r)| r).collect();
+    let summary = summarize_records(siglist, &records);
+
+    let mut out = open_stdout_or_file(output);
+    match format {
+        SummaryFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(out);
+            for group in &summary.groups {
+                writer.serialize(group)?;
+            }
+            finish_csv(writer)?.commit()?;
+        }
+        SummaryFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, &summary)?;
+            writeln!(out)?;
+            out.commit()?;
+        }
+    }
+
+    eprintln!(
+        "DONE. Summarized {} sketches in {} groups; {} total hashes ({:.1} mean).",
+        summary.n_sketches,
+        summary.groups.len(),
+        summary.total_hashes,
+        summary.mean_hashes
+    );
+    if !summary.duplicate_md5s.is_empty() {
+        eprintln!(
+            "WARNING: {} md5s appear more than once.",
+            summary.duplicate_md5s.len()
+        );
+    }
+    if !summary.name_collisions.is_empty() {
+        eprintln!(
+            "WARNING: {} names are shared by sketches with different md5s.",
+            summary.name_collisions.len()
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sourmash::manifest::Manifest;
+
+    #[test]
+    fn test_summarize_records() {
+        let manifest = "\
+# SOURMASH-MANIFEST-VERSION: 1.0
+internal_location,md5,md5short,ksize,moltype,num,scaled,n_hashes,with_abundance,name,filename
+a.sig,aaaa,aaaa,31,DNA,0,1000,10,False,A,a.fa
+b.sig,bbbb,bbbb,31,DNA,0,1000,20,False,A,b.fa
+c.sig,aaaa,aaaa,31,DNA,0,1000,10,False,A,a.fa
+d.sig,dddd,dddd,21,DNA,0,1000,30,True,A,a.fa
+";
+        let manifest = Manifest::from_reader(manifest.as_bytes()).unwrap();
+        let records: Vec<&Record> = manifest.iter().collect();
+        let summary = summarize_records("test".into(), &records);
+
+        assert_eq!(summary.n_sketches, 4);
+        assert_eq!(summary.total_hashes, 70);
+        assert_eq!(summary.groups.len(), 2);
+
+        let k31 = summary.groups.iter().find(|g| g.ksize == 31).unwrap();
+        assert_eq!(k31.n_sketches, 3);
+        assert_eq!(k31.min_hashes, 10);
+        assert_eq!(k31.max_hashes, 20);
+        assert_eq!(k31.n_duplicate_md5s, 1);
+        assert_eq!(k31.n_name_collisions, 1);
+
+        // the same name at a different ksize is not a collision.
+        let k21 = summary.groups.iter().find(|g| g.ksize == 21).unwrap();
+        assert!(k21.with_abundance);
+        assert_eq!(k21.n_name_collisions, 0);
+
+        assert_eq!(summary.duplicate_md5s.len(), 1);
+        assert_eq!(summary.duplicate_md5s[0].md5, "aaaa");
+        assert_eq!(summary.duplicate_md5s[0].count, 2);
+        assert_eq!(summary.name_collisions[0].md5s, vec!["aaaa", "bbbb"]);
+    }
+}
@@ -0,0 +1,212 @@
+//! Per-dataset hash abundances, stored alongside a RocksDB index.
+//!
+//! The RocksDB index only records which datasets contain each hash, so
+//! searches of the index can't report abundance-weighted results. `index`
+//! therefore also writes the (hash, abundance) pairs of every sketch that
+//! tracks abundance to a table in the index directory, which searches look
+//! up one matching dataset at a time.
+//!
+//! The table is a header of `MAGIC`, the number of datasets, and an
+//! (offset, number of hashes) pair per dataset, in dataset order, followed
+//! by each dataset's (hash, abundance) pairs, sorted by hash. All numbers
+//! are little-endian u64s. Datasets without abundances have no pairs.
+
+use anyhow::{Context, Result};
+use camino::Utf8Path as Path;
+use rayon::prelude::*;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::sync::Mutex;
+
+use sourmash::collection::CollectionSet;
+use sourmash::sketch::minhash::{max_hash_for_scaled, KmerMinHash};
+use stats::{median, stddev};
+
+use super::atomicfile::AtomicFile;
+
+/// The name of the table in the index directory.
+pub const ABUNDANCES_FILE: &str = "branchwater-abundances.bin";
+
+const MAGIC: &[u8; 8] = b"BWABUND1";
+
+/// How many sketches to load at once while writing the table.
+const CHUNK_SIZE: usize = 64;
+
+fn read_u64(reader: &mut impl Read) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Write the abundances of the sketches in `collection` to the index
+/// directory `index`. Returns false, writing nothing, if no sketch tracks
+/// abundance.
+pub fn write_abundances(index: &Path, collection: &CollectionSet) -> Result<bool> {
+    if !collection.iter().any(|(_, record)| record.with_abundance()) {
+        return Ok(false);
+    }
+
+    let n_hashes: Vec<u64> = collection
+        .iter()
+        .map(|(_, record)| {
+            if record.with_abundance() {
+                *record.n_hashes() as u64
+            } else {
+                0
+            }
+        })
+        .collect();
+
+    let path = index.join(ABUNDANCES_FILE);
+    let mut out = AtomicFile::create(path.as_str())?;
+
+    out.write_all(MAGIC)?;
+    out.write_all(&(n_hashes.len() as u64).to_le_bytes())?;
+    let mut offset = (MAGIC.len() + 8 + 16 * n_hashes.len()) as u64;
+    for n in n_hashes.iter() {
+        out.write_all(&offset.to_le_bytes())?;
+        out.write_all(&n.to_le_bytes())?;
+        offset += 16 * n;
+    }
+
+    let dataset_ids: Vec<u32> = collection.iter().map(|(idx, _)| idx).collect();
+    for chunk in dataset_ids.chunks(CHUNK_SIZE) {
+        let pairs: Vec<Vec<(u64, u64)>> = chunk
+            .par_iter()
+            .map(|&idx| {
+                if n_hashes[idx as usize] == 0 {
+                    return Ok(vec![]);
+                }
+                let sig = collection.sig_for_dataset(idx)?;
+                let mh: KmerMinHash = sig.try_into()?;
+                Ok(mh.to_vec_abunds())
+            })
+            .collect::<Result<_>>()?;
+
+        for (&idx, pairs) in chunk.iter().zip(pairs) {
+            if pairs.len() as u64 != n_hashes[idx as usize] {
+                bail!(
+                    "sketch {} has {} hashes, but its manifest says {}",
+                    idx,
+                    pairs.len(),
+                    n_hashes[idx as usize]
+                );
+            }
+            for (hash, abund) in pairs {
+                out.write_all(&hash.to_le_bytes())?;
+                out.write_all(&abund.to_le_bytes())?;
+            }
+        }
+    }
+
+    out.commit()?;
+    Ok(true)
+}
+
+/// Weighted statistics for the hashes a query shares with one dataset:
+/// (total_weighted_hashes, n_weighted_found, average_abund, median_abund,
+/// std_abund), as for `manysearch` on sketches.
+pub type WeightedStats = (u64, u64, f64, f64, f64);
+
+/// The abundance table of a RocksDB index.
+pub struct AbundanceTable {
+    file: Mutex<BufReader<File>>,
+    blocks: Vec<(u64, u64)>,
+}
+
+impl AbundanceTable {
+    /// Open the table in the index directory `index`, if it has one.
+    pub fn open(index: &Path) -> Result<Option<Self>> {
+        let path = index.join(ABUNDANCES_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let mut reader = BufReader::new(File::open(&path)?);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            bail!("'{}' is not a branchwater abundance table", path);
+        }
+        let n_datasets = read_u64(&mut reader)?;
+        let blocks = (0..n_datasets)
+            .map(|_| Ok((read_u64(&mut reader)?, read_u64(&mut reader)?)))
+            .collect::<Result<_>>()
+            .with_context(|| format!("cannot read abundance table '{}'", path))?;
+
+        Ok(Some(AbundanceTable {
+            file: Mutex::new(reader),
+            blocks,
+        }))
+    }
+
+    /// The (hash, abundance) pairs of a dataset, or None if its sketch
+    /// does not track abundance.
+    pub fn abundances(&self, dataset_id: u32) -> Result<Option<Vec<(u64, u64)>>> {
+        let Some(&(offset, n_hashes)) = self.blocks.get(dataset_id as usize) else {
+            bail!("dataset {} is not in the abundance table", dataset_id);
+        };
+        if n_hashes == 0 {
+            return Ok(None);
+        }
+
+        let mut buf = vec![0u8; 16 * n_hashes as usize];
+        {
+            let mut file = self.file.lock().unwrap();
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut buf)?;
+        }
+        let pairs = buf
+            .chunks_exact(16)
+            .map(|pair| {
+                let (hash, abund) = pair.split_at(8);
+                (
+                    u64::from_le_bytes(hash.try_into().unwrap()),
+                    u64::from_le_bytes(abund.try_into().unwrap()),
+                )
+            })
+            .collect();
+        Ok(Some(pairs))
+    }
+
+    /// "Borrow" the abundances of a dataset onto its intersection with
+    /// `query_mh`, downsampling the dataset to the query's scaled.
+    pub fn weighted_stats(
+        &self,
+        dataset_id: u32,
+        query_mh: &KmerMinHash,
+    ) -> Result<Option<WeightedStats>> {
+        let Some(pairs) = self.abundances(dataset_id)? else {
+            return Ok(None);
+        };
+        let max_hash = max_hash_for_scaled(query_mh.scaled());
+        let pairs: Vec<(u64, u64)> = pairs.into_iter().filter(|(h, _)| *h <= max_hash).collect();
+
+        let total_weighted_hashes = pairs.iter().map(|(_, abund)| abund).sum();
+        let abunds: Vec<u64> = query_mh
+            .iter_mins()
+            .filter_map(|hash| {
+                pairs
+                    .binary_search_by_key(hash, |(h, _)| *h)
+                    .ok()
+                    .map(|i| pairs[i].1)
+            })
+            .collect();
+        if abunds.is_empty() {
+            return Ok(None);
+        }
+
+        let n_weighted_found: u64 = abunds.iter().sum();
+        let average_abund = n_weighted_found as f64 / abunds.len() as f64;
+        let median_abund = median(abunds.iter().cloned()).expect("error");
+        let std_abund = stddev(abunds.iter().cloned());
+
+        Ok(Some((
+            total_weighted_hashes,
+            n_weighted_found,
+            average_abund,
+            median_abund,
+            std_abund,
+        )))
+    }
+}
@@ -0,0 +1,100 @@
+//! Clean names for the sketches in a RocksDB index.
+//!
+//! Sketch names are stored in the index manifest as they were built, and
+//! often embed paths and file extensions. `index --normalize-names` and
+//! `index --rename-from` instead store an alias table in the index
+//! directory, a CSV of `md5,name,alias` for each renamed sketch, and
+//! searches of the index report the alias in place of the name.
+
+use anyhow::{Context, Result};
+use camino::Utf8Path as Path;
+use csv::Reader;
+use std::collections::HashMap;
+
+use super::querysketch::{COMPRESSION_EXTENSIONS, SEQUENCE_EXTENSIONS};
+
+/// The name of the table in the index directory.
+pub const ALIASES_FILE: &str = "branchwater-aliases.csv";
+
+const SIGNATURE_EXTENSIONS: [&str; 3] = ["sig", "json", "zip"];
+
+/// Strip any directories and sequence or signature file extensions from a
+/// name that looks like a path (i.e. has no spaces), e.g.
+/// `genomes/GCA_1.fna.gz` -> `GCA_1`.
+pub fn normalize_name(name: &str) -> &str {
+    if name.contains(char::is_whitespace) {
+        return name;
+    }
+    let mut stem = name.rsplit('/').next().unwrap_or(name);
+    while let Some((rest, ext)) = stem.rsplit_once('.') {
+        let ext = ext.to_ascii_lowercase();
+        let known = [
+            &COMPRESSION_EXTENSIONS[..],
+            &SEQUENCE_EXTENSIONS[..],
+            &SIGNATURE_EXTENSIONS[..],
+        ]
+        .iter()
+        .any(|exts| exts.contains(&ext.as_str()));
+        if !known {
+            break;
+        }
+        stem = rest;
+    }
+    if stem.is_empty() {
+        name
+    } else {
+        stem
+    }
+}
+
+/// The alias table of a RocksDB index, by original name.
+pub struct NameAliases {
+    aliases: HashMap<String, String>,
+}
+
+impl NameAliases {
+    /// Open the table in the index directory `index`, if it has one.
+    pub fn open(index: &Path) -> Result<Option<Self>> {
+        let path = index.join(ALIASES_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let mut rdr = Reader::from_path(&path)
+            .with_context(|| format!("Failed to open alias table '{}'", path))?;
+        let mut aliases = HashMap::new();
+        for record in rdr.records() {
+            let record = record?;
+            let (Some(name), Some(alias)) = (record.get(1), record.get(2)) else {
+                bail!("'{}' is not a branchwater alias table", path);
+            };
+            aliases.insert(name.to_string(), alias.to_string());
+        }
+        eprintln!("Reporting {} sketches by their aliases", aliases.len());
+
+        Ok(Some(NameAliases { aliases }))
+    }
+
+    /// The alias for `name`, or `name` itself if it has none.
+    pub fn get<'a>(&'a self, name: &'a str) -> &'a str {
+        self.aliases.get(name).map(String::as_str).unwrap_or(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_name() {
+        assert_eq!(normalize_name("genomes/GCA_1.2.fna.gz"), "GCA_1.2");
+        assert_eq!(normalize_name("/abs/path/sample.fastq"), "sample");
+        assert_eq!(normalize_name("sketches/63.fa.sig.gz"), "63");
+        assert_eq!(normalize_name("GCA_1.2"), "GCA_1.2");
+        // names with spaces are not paths.
+        assert_eq!(
+            normalize_name("NC_009661.1 Shewanella baltica"),
+            "NC_009661.1 Shewanella baltica"
+        );
+    }
+}
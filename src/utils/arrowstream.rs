@@ -0,0 +1,196 @@
+//! Write results as an Arrow IPC stream, for `--output-format arrow`.
+//!
+//! Rows are buffered and written as record batches, so that a reader of
+//! a named pipe or socket - e.g. `pyarrow.ipc.open_stream` - receives
+//! results from long-running searches as they come. The schema is taken
+//! from the output columns, typed with `ResultType::column_type`.
+
+use anyhow::Result;
+use arrow_array::{ArrayRef, BooleanArray, Float64Array, RecordBatch, StringArray, UInt64Array};
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{DataType, Field, Schema};
+use serde_json::Value;
+use std::io::Write;
+use std::sync::Arc;
+
+use super::atomicfile::Output;
+use super::columns::ColumnType;
+
+/// Write a record batch after this many rows, even if not flushed.
+const BATCH_ROWS: usize = 8192;
+
+fn data_type(ty: ColumnType) -> DataType {
+    match ty {
+        ColumnType::Utf8 => DataType::Utf8,
+        ColumnType::UInt64 => DataType::UInt64,
+        ColumnType::Float64 => DataType::Float64,
+        ColumnType::Boolean => DataType::Boolean,
+    }
+}
+
+/// An array of the values in `rows` at `idx`; missing values are null.
+fn array(ty: ColumnType, rows: &[Vec<Value>], idx: usize) -> ArrayRef {
+    let values = rows.iter().map(|row| match &row[idx] {
+        Value::Null => None,
+        value => Some(value),
+    });
+    match ty {
+        ColumnType::Utf8 => Arc::new(StringArray::from_iter(values.map(|v| {
+            v.map(|v| match v {
+                Value::String(s) => s.clone(),
+                v => v.to_string(),
+            })
+        }))),
+        ColumnType::UInt64 => Arc::new(UInt64Array::from_iter(
+            values.map(|v| v.and_then(Value::as_u64)),
+        )),
+        ColumnType::Float64 => Arc::new(Float64Array::from_iter(
+            values.map(|v| v.and_then(Value::as_f64)),
+        )),
+        ColumnType::Boolean => Arc::new(BooleanArray::from_iter(
+            values.map(|v| v.and_then(Value::as_bool)),
+        )),
+    }
+}
+
+/// An Arrow IPC stream of result rows. The schema is written by `start`,
+/// before the first row.
+pub struct ArrowStream {
+    out: Option<Box<dyn Output>>,
+    writer: Option<StreamWriter<Box<dyn Output>>>,
+    schema: Arc<Schema>,
+    types: Vec<ColumnType>,
+    rows: Vec<Vec<Value>>,
+}
+
+impl ArrowStream {
+    pub fn new(out: Box<dyn Output>) -> Self {
+        ArrowStream {
+            out: Some(out),
+            writer: None,
+            schema: Arc::new(Schema::empty()),
+            types: vec![],
+            rows: vec![],
+        }
+    }
+
+    /// Write the schema, for the named and typed columns; every column is
+    /// nullable, as optional columns may be empty for some rows.
+    pub fn start<'a>(
+        &mut self,
+        columns: impl Iterator<Item = (&'a str, ColumnType)>,
+    ) -> Result<()> {
+        let Some(out) = self.out.take() else {
+            bail!("Arrow stream has already been started");
+        };
+        let (fields, types): (Vec<Field>, Vec<ColumnType>) = columns
+            .map(|(name, ty)| (Field::new(name, data_type(ty), true), ty))
+            .unzip();
+        self.schema = Arc::new(Schema::new(fields));
+        self.types = types;
+        self.writer = Some(StreamWriter::try_new(out, &self.schema)?);
+        Ok(())
+    }
+
+    /// Add a row, with one value per column.
+    pub fn push(&mut self, row: Vec<Value>) -> Result<()> {
+        if row.len() != self.types.len() {
+            bail!(
+                "row has {} values, but the Arrow schema has {} columns",
+                row.len(),
+                self.types.len()
+            );
+        }
+        self.rows.push(row);
+        if self.rows.len() >= BATCH_ROWS {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self) -> Result<()> {
+        let Some(writer) = &mut self.writer else {
+            return Ok(());
+        };
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let columns = self
+            .types
+            .iter()
+            .enumerate()
+            .map(|(idx, ty)| array(*ty, &self.rows, idx))
+            .collect();
+        let batch = RecordBatch::try_new(self.schema.clone(), columns)?;
+        writer.write(&batch)?;
+        self.rows.clear();
+        Ok(())
+    }
+
+    /// Write any buffered rows as a batch, and flush the output.
+    pub fn flush(&mut self) -> Result<()> {
+        self.write_batch()?;
+        match (&mut self.writer, &mut self.out) {
+            (Some(writer), _) => writer.get_mut().flush()?,
+            (None, Some(out)) => out.flush()?,
+            (None, None) => {}
+        }
+        Ok(())
+    }
+
+    /// Write any buffered rows and the end-of-stream marker, and commit
+    /// the output.
+    pub fn finish(mut self) -> Result<()> {
+        self.write_batch()?;
+        match (self.writer.take(), self.out.take()) {
+            (Some(writer), _) => writer.into_inner()?.commit(),
+            (None, Some(out)) => out.commit(),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::Array;
+    use arrow_ipc::reader::StreamReader;
+    use serde_json::json;
+    use std::fs::File;
+
+    #[test]
+    fn test_write_and_read_stream() {
+        let tmp = tempfile::NamedTempFile::new().unwrap();
+        let mut stream = ArrowStream::new(Box::new(tmp.reopen().unwrap()));
+        let columns = [
+            ("match_name", ColumnType::Utf8),
+            ("intersect_bp", ColumnType::UInt64),
+            ("jaccard", ColumnType::Float64),
+        ];
+        stream.start(columns.into_iter()).unwrap();
+        stream
+            .push(vec![json!("a"), json!(1000), json!(0.5)])
+            .unwrap();
+        stream
+            .push(vec![json!("b"), json!(2000), json!(null)])
+            .unwrap();
+        // rows must match the schema.
+        assert!(stream.push(vec![json!("c")]).is_err());
+        stream.finish().unwrap();
+
+        let reader = StreamReader::try_new(File::open(tmp.path()).unwrap(), None).unwrap();
+        let batches: Vec<RecordBatch> = reader.map(|b| b.unwrap()).collect();
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert_eq!(batch.num_rows(), 2);
+        assert_eq!(batch.schema().field(1).data_type(), &DataType::UInt64);
+
+        let jaccard = batch
+            .column(2)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert_eq!(jaccard.value(0), 0.5);
+        assert!(jaccard.is_null(1));
+    }
+}
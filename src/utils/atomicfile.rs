@@ -0,0 +1,149 @@
+//! Write output files atomically: everything goes to a temporary file
+//! next to the output, which is renamed into place once writing is done.
+//! Interrupted runs leave at most a `.tmp` file, never a half-written
+//! output that looks valid.
+
+use anyhow::{Context, Result};
+use std::fs::{metadata, remove_file, rename, File};
+use std::io::{BufWriter, Seek, SeekFrom, Stdout, Write};
+use std::os::unix::net::UnixStream;
+
+/// An output that is complete only once committed: output files are
+/// moved into place, and streams flushed. Outputs dropped without being
+/// committed, e.g. on an early error return, are discarded.
+pub trait Output: Write + Send {
+    fn commit(self: Box<Self>) -> Result<()>;
+}
+
+pub struct AtomicFile {
+    writer: Option<BufWriter<File>>,
+    path: String,
+    /// `None` when writing directly to `path`.
+    tmp_path: Option<String>,
+}
+
+impl AtomicFile {
+    /// Create a temporary file for `path`. Unless `commit` is called, the
+    /// output is discarded when dropped.
+    pub fn create(path: &str) -> Result<Self> {
+        // devices and pipes (e.g. /dev/stdout) can't be renamed over.
+        let is_special = metadata(path).map(|m| !m.is_file()).unwrap_or(false);
+        let tmp_path = (!is_special).then(|| format!("{}.tmp", path));
+        let file = File::create(tmp_path.as_deref().unwrap_or(path))
+            .with_context(|| format!("Failed to create output file '{}'", path))?;
+        Ok(AtomicFile {
+            writer: Some(BufWriter::new(file)),
+            path: path.to_string(),
+            tmp_path,
+        })
+    }
+
+    /// Flush and move the output into place.
+    pub fn commit(mut self) -> Result<()> {
+        self.finish()
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if let Some(mut writer) = self.writer.take() {
+            writer
+                .flush()
+                .with_context(|| format!("Failed to write output file '{}'", self.path))?;
+            drop(writer);
+            if let Some(tmp_path) = &self.tmp_path {
+                rename(tmp_path, &self.path).with_context(|| {
+                    format!("Failed to move output into place at '{}'", self.path)
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    fn writer(&mut self) -> &mut BufWriter<File> {
+        self.writer.as_mut().expect("output already committed")
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.writer().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer().flush()
+    }
+}
+
+impl Seek for AtomicFile {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.writer().seek(pos)
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if self.writer.take().is_none() {
+            return;
+        }
+        if let Some(tmp_path) = &self.tmp_path {
+            let _ = remove_file(tmp_path);
+        }
+    }
+}
+
+impl Output for AtomicFile {
+    fn commit(self: Box<Self>) -> Result<()> {
+        AtomicFile::commit(*self)
+    }
+}
+
+impl Output for BufWriter<File> {
+    fn commit(mut self: Box<Self>) -> Result<()> {
+        self.flush()?;
+        Ok(())
+    }
+}
+
+impl Output for File {
+    fn commit(mut self: Box<Self>) -> Result<()> {
+        self.flush()?;
+        Ok(())
+    }
+}
+
+impl Output for Stdout {
+    fn commit(mut self: Box<Self>) -> Result<()> {
+        self.flush()?;
+        Ok(())
+    }
+}
+
+impl Output for UnixStream {
+    fn commit(mut self: Box<Self>) -> Result<()> {
+        self.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commit_or_discard() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.csv");
+        let path = path.to_str().unwrap();
+
+        // dropped, e.g. by an early `?` return: nothing is written.
+        let mut file = AtomicFile::create(path).unwrap();
+        file.write_all(b"partial\n").unwrap();
+        drop(file);
+        assert!(metadata(path).is_err());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        let mut out: Box<dyn Output> = Box::new(AtomicFile::create(path).unwrap());
+        out.write_all(b"complete\n").unwrap();
+        out.commit().unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "complete\n");
+    }
+}
@@ -3,28 +3,34 @@
 use anyhow::{anyhow, Context, Result};
 use camino::Utf8PathBuf;
 use getset::{Getters, Setters};
-use needletail::parser::SequenceRecord;
-use needletail::{parse_fastx_file, parse_fastx_reader, parse_fastx_stdin};
+use needletail::parse_fastx_reader;
+use needletail::parser::{FastxReader, SequenceRecord};
 use serde::Serialize;
+use sourmash::_hash_murmur;
 use sourmash::cmd::ComputeParameters;
-use sourmash::encodings::{HashFunctions, Idx};
+use sourmash::encodings::{revcomp, HashFunctions, Idx};
 use sourmash::errors::SourmashError;
 use sourmash::manifest::Record;
 use sourmash::selection::Selection;
-use sourmash::signature::Signature;
 use sourmash::signature::SigsTrait;
+use sourmash::signature::{SeqToHashes, Signature};
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::collections::VecDeque;
 use std::fmt::Display;
-use std::fs::File;
 use std::hash::{Hash, Hasher};
-use std::io::{Cursor, Seek, Write};
+use std::io::{Cursor, Read, Seek, Write};
 use std::num::ParseIntError;
 use std::ops::Index;
 use std::str::FromStr;
 use zip::write::{FileOptions, ZipWriter};
 use zip::CompressionMethod;
 
+#[cfg(feature = "python")]
+use sourmash::sketch::Sketch;
+
+use super::atomicfile::AtomicFile;
+
 #[derive(Default, Debug, Clone)]
 pub struct MultiSelection {
     pub selections: Vec<Selection>,
@@ -70,6 +76,130 @@ impl MultiSelection {
     }
 }
 
+/// Wrap `reader` in a decompressor if it starts with gzip, bzip2, xz, or
+/// zstd magic bytes. Concatenated streams are read to the end: bgzip and
+/// Illumina output is multi-member gzip, and pbzip2 and parallel xz
+/// output can also have several streams, of which needletail's own
+/// decompression only reads the first.
+fn decompress<'a>(mut reader: Box<dyn Read + Send + 'a>) -> Result<Box<dyn Read + Send + 'a>> {
+    let mut magic = Vec::with_capacity(6);
+    (&mut reader).take(6).read_to_end(&mut magic)?;
+    let reader = Box::new(Cursor::new(magic.clone()).chain(reader));
+
+    let reader: Box<dyn Read + Send + 'a> = match magic.as_slice() {
+        [0x1f, 0x8b, ..] => Box::new(flate2::read::MultiGzDecoder::new(reader)),
+        [b'B', b'Z', b'h', ..] => Box::new(bzip2::read::MultiBzDecoder::new(reader)),
+        [0xfd, b'7', b'z', b'X', b'Z', 0x00] => {
+            Box::new(xz2::read::XzDecoder::new_multi_decoder(reader))
+        }
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => Box::new(zstd::stream::read::Decoder::new(reader)?),
+        _ => reader,
+    };
+    Ok(reader)
+}
+
+/// Open a FASTA/FASTQ file, or stdin for `-`, which may be uncompressed or
+/// compressed with gzip (including bgzip), bzip2, xz, or zstd.
+pub fn open_fastx(filename: &str) -> Result<Box<dyn FastxReader>> {
+    let reader: Box<dyn Read + Send> = if filename == "-" {
+        Box::new(std::io::stdin())
+    } else {
+        Box::new(std::io::BufReader::new(std::fs::File::open(filename)?))
+    };
+    Ok(parse_fastx_reader(decompress(reader)?)?)
+}
+
+/// The canonical k-mer hashes of `seq` that are window minimizers: the
+/// smallest hash in each run of `window` consecutive k-mers. Every window
+/// contributes a hash, so a sequencing error only changes the hashes of
+/// the windows overlapping it, rather than leaving a gap wherever the
+/// hashes kept by FracMinHash happen to be hit. k-mers with non-ACGT bases
+/// are skipped, as for regular DNA sketches.
+pub fn window_minimizers(seq: &[u8], ksize: u32, window: u32, seed: u64) -> Result<Vec<u64>> {
+    let hashes = SeqToHashes::new(
+        seq,
+        ksize as usize,
+        true,
+        false,
+        HashFunctions::Murmur64Dna,
+        seed,
+    )?
+    .collect::<Result<Vec<u64>, _>>()?;
+
+    let window = window as usize;
+    let mut minimizers = vec![];
+    // positions of candidate minimizers, with increasing hashes.
+    let mut candidates: VecDeque<usize> = VecDeque::new();
+    for (pos, hash) in hashes.iter().enumerate() {
+        // invalid k-mers hash to 0, and are never minimizers.
+        if *hash != 0 {
+            while candidates.back().is_some_and(|&c| hashes[c] >= *hash) {
+                candidates.pop_back();
+            }
+            candidates.push_back(pos);
+        }
+        if candidates.front().is_some_and(|&c| c + window <= pos) {
+            candidates.pop_front();
+        }
+        if pos + 1 >= window {
+            if let Some(&c) = candidates.front() {
+                // consecutive windows usually share a minimizer; add it once.
+                if minimizers.last() != Some(&hashes[c]) {
+                    minimizers.push(hashes[c]);
+                }
+            }
+        }
+    }
+    // sequences shorter than one window still get their smallest hash.
+    if hashes.len() < window {
+        if let Some(&c) = candidates.front() {
+            minimizers.push(hashes[c]);
+        }
+    }
+    Ok(minimizers)
+}
+
+/// A DNA sequence, uppercased and reverse complemented once, so that its
+/// canonical k-mers can be hashed at several ksizes (e.g. for a
+/// `k=21,k=31,k=51` sweep) without normalizing it again for each sketch.
+pub struct DnaSequence {
+    fw: Vec<u8>,
+    rc: Vec<u8>,
+}
+
+impl DnaSequence {
+    pub fn new(seq: &[u8]) -> Self {
+        let fw = seq.to_ascii_uppercase();
+        let rc = revcomp(&fw);
+        DnaSequence { fw, rc }
+    }
+
+    /// Call `f` with the canonical hash of each k-mer, in order, as
+    /// `add_sequence` computes them; k-mers with non-ACGT bases are
+    /// skipped.
+    pub fn for_each_hash(&self, ksize: usize, seed: u64, mut f: impl FnMut(u64)) {
+        let len = self.fw.len();
+        if ksize == 0 || len < ksize {
+            return;
+        }
+        // the number of consecutive valid bases ending at `end`.
+        let mut valid = 0;
+        for end in 0..len {
+            if matches!(self.fw[end], b'A' | b'C' | b'G' | b'T') {
+                valid += 1;
+            } else {
+                valid = 0;
+            }
+            if valid >= ksize {
+                let start = end + 1 - ksize;
+                let kmer = &self.fw[start..=end];
+                let krc = &self.rc[len - ksize - start..len - start];
+                f(_hash_murmur(std::cmp::min(kmer, krc), seed));
+            }
+        }
+    }
+}
+
 pub trait MultiSelect {
     fn select(&mut self, multi_selection: &MultiSelection) -> Result<(), SourmashError>;
 }
@@ -120,6 +250,15 @@ pub struct BuildRecord {
 
     #[serde(skip)]
     pub sequence_added: bool,
+
+    // minimizer window size, for windowed DNA sketches; see `window_minimizers`
+    #[getset(get_copy = "pub")]
+    #[serde(skip)]
+    pub window: Option<u32>,
+
+    // extra manifest columns, written after the standard ones
+    #[serde(skip)]
+    pub extra: Vec<(String, String)>,
 }
 
 // from sourmash (intbool is currently private there)
@@ -152,6 +291,8 @@ impl BuildRecord {
             seed: 42,
             hashed_params: 0,
             sequence_added: false,
+            window: None,
+            extra: vec![],
         }
     }
 
@@ -242,6 +383,15 @@ impl BuildRecord {
         valid
     }
 
+    /// The seed stored in the sketch. Windowed sketches keep the window in
+    /// the upper 32 bits, so that sourmash refuses to compare them with
+    /// regular DNA sketches (or sketches built with another window), whose
+    /// hashes are the same but sampled differently.
+    pub fn sketch_seed(&self) -> u64 {
+        let window = self.window.unwrap_or(0) as u64;
+        (window << 32) | self.seed as u64
+    }
+
     pub fn params(&self) -> (u32, String, bool, u32, u64) {
         (
             self.ksize,
@@ -260,6 +410,7 @@ impl PartialEq for BuildRecord {
             && self.with_abundance == other.with_abundance
             && self.num == other.num
             && self.scaled == other.scaled
+            && self.window == other.window
     }
 }
 
@@ -272,6 +423,7 @@ impl Hash for BuildRecord {
         self.scaled.hash(state);
         self.num.hash(state);
         self.with_abundance.hash(state);
+        self.window.hash(state);
     }
 }
 
@@ -281,6 +433,21 @@ pub struct BuildManifest {
 }
 
 impl BuildManifest {
+    // standard manifest columns, in BuildRecord serialization order
+    pub const COLUMNS: [&'static str; 11] = [
+        "internal_location",
+        "md5",
+        "md5short",
+        "ksize",
+        "moltype",
+        "num",
+        "scaled",
+        "n_hashes",
+        "with_abundance",
+        "name",
+        "filename",
+    ];
+
     pub fn new() -> Self {
         BuildManifest {
             records: Vec::new(),
@@ -299,6 +466,10 @@ impl BuildManifest {
         self.records.iter()
     }
 
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut BuildRecord> {
+        self.records.iter_mut()
+    }
+
     // clear all records
     pub fn clear(&mut self) {
         self.records.clear();
@@ -339,13 +510,50 @@ impl BuildManifest {
         // Write the manifest version as a comment
         wtr.write_all(b"# SOURMASH-MANIFEST-VERSION: 1.0\n")?;
 
-        // Use CSV writer to serialize records
-        let mut csv_writer = csv::Writer::from_writer(wtr);
+        // extra columns are kept in the order first seen
+        let mut extra_columns: Vec<&str> = vec![];
+        for record in &self.records {
+            for (column, _) in &record.extra {
+                if !extra_columns.contains(&column.as_str()) {
+                    extra_columns.push(column);
+                }
+            }
+        }
+
+        if extra_columns.is_empty() {
+            // Use CSV writer to serialize records
+            let mut csv_writer = csv::Writer::from_writer(wtr);
+
+            for record in &self.records {
+                // don't write empty records (empty template sigs aren't written from BuildCollection)
+                if record.sequence_added {
+                    csv_writer.serialize(record)?; // Serialize each BuildRecord
+                }
+            }
+
+            csv_writer.flush()?; // Ensure all data is written
+            return Ok(());
+        }
+
+        // headers can't be derived for (record, extras), so write them directly
+        let mut csv_writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(wtr);
+        csv_writer.write_record(Self::COLUMNS.iter().chain(extra_columns.iter()))?;
 
         for record in &self.records {
-            // don't write empty records (empty template sigs aren't written from BuildCollection)
             if record.sequence_added {
-                csv_writer.serialize(record)?; // Serialize each BuildRecord
+                let values: Vec<&str> = extra_columns
+                    .iter()
+                    .map(|column| {
+                        record
+                            .extra
+                            .iter()
+                            .find(|(c, _)| c == column)
+                            .map_or("", |(_, v)| v.as_str())
+                    })
+                    .collect();
+                csv_writer.serialize((record, values))?;
             }
         }
 
@@ -562,6 +770,7 @@ impl BuildCollection {
         let mut num: Option<u32> = None;
         let mut scaled: Option<u64> = None;
         let mut seed: Option<u32> = None;
+        let mut window: Option<u32> = None;
 
         for item in p_str.split(',') {
             match item {
@@ -583,6 +792,9 @@ impl BuildCollection {
                 _ if item.starts_with("seed=") => {
                     Self::parse_int_once(&item[5..], "seed", &mut seed)?;
                 }
+                _ if item.starts_with("window=") => {
+                    Self::parse_int_once(&item[7..], "window", &mut window)?;
+                }
                 _ => {
                     return Err(format!(
                         "Error parsing params string '{}': Unknown component '{}'",
@@ -629,7 +841,24 @@ impl BuildCollection {
         if let Some(s) = seed {
             base_record.seed = s;
         }
-
+        if let Some(w) = window {
+            if base_record.moltype != "DNA" {
+                return Err(format!(
+                    "Error parsing params string '{}': 'window' is only supported for DNA sketches",
+                    p_str
+                ));
+            }
+            if w < 2 {
+                return Err(format!(
+                    "Error parsing params string '{}': 'window' must be at least 2",
+                    p_str
+                ));
+            }
+            base_record.window = Some(w);
+            base_record
+                .extra
+                .push(("window".to_string(), w.to_string()));
+        }
         // Use the default ksize if none were specified.
         if ksizes.is_empty() {
             ksizes.push(base_record.ksize);
@@ -706,6 +935,7 @@ impl BuildCollection {
             .skipm2n3(record.moltype == "skipm2n3")
             .num_hashes(record.num)
             .track_abundance(record.with_abundance)
+            .seed(record.sketch_seed())
             .build();
 
         // Create a Signature from the ComputeParameters.
@@ -778,31 +1008,69 @@ impl BuildCollection {
         input_moltype: &str,
         record: &SequenceRecord,
     ) -> Result<()> {
-        // Optionally use `par_iter_mut` for parallel execution
-        self.iter_mut().try_for_each(|(rec, sig)| {
-            if input_moltype == "protein"
-                && (rec.moltype() == HashFunctions::Murmur64Protein
-                    || rec.moltype() == HashFunctions::Murmur64Dayhoff
-                    || rec.moltype() == HashFunctions::Murmur64Hp)
+        self.add_sequence(input_moltype, &record.seq())
+    }
+
+    /// Add a sequence to every template of a compatible moltype. Each
+    /// ksize of the regular DNA templates is hashed once; templates that
+    /// differ only in scaled, num, or abundance tracking share the hashes.
+    pub fn add_sequence(&mut self, input_moltype: &str, seq: &[u8]) -> Result<()> {
+        let is_protein = input_moltype == "protein";
+        let is_dna = input_moltype == "DNA" || input_moltype == "dna";
+
+        // regular DNA templates, by ksize and seed, to hash together below.
+        let mut dna_templates: Vec<((usize, u64), Vec<usize>)> = vec![];
+
+        for (i, (rec, sig)) in self.iter_mut().enumerate() {
+            let moltype = rec.moltype();
+            if is_protein
+                && (moltype == HashFunctions::Murmur64Protein
+                    || moltype == HashFunctions::Murmur64Dayhoff
+                    || moltype == HashFunctions::Murmur64Hp)
             {
-                sig.add_protein(&record.seq())
-                    .context("Failed to add protein")?;
-                if !rec.sequence_added {
-                    rec.sequence_added = true;
-                }
-            } else if (input_moltype == "DNA" || input_moltype == "dna")
-                && (rec.moltype() == HashFunctions::Murmur64Dna
-                    || rec.moltype() == HashFunctions::Murmur64Skipm1n3
-                    || rec.moltype() == HashFunctions::Murmur64Skipm2n3)
+                sig.add_protein(seq).context("Failed to add protein")?;
+            } else if is_dna
+                && (moltype == HashFunctions::Murmur64Dna
+                    || moltype == HashFunctions::Murmur64Skipm1n3
+                    || moltype == HashFunctions::Murmur64Skipm2n3)
             {
-                sig.add_sequence(&record.seq(), true)
-                    .context("Failed to add sequence")?;
-                if !rec.sequence_added {
-                    rec.sequence_added = true;
+                let seeds: Vec<u64> = sig.iter().map(|sketch| sketch.seed()).collect();
+                if let Some(window) = rec.window {
+                    let hashes = window_minimizers(seq, rec.ksize, window, rec.seed as u64)?;
+                    for sketch in sig.iter_mut() {
+                        for hash in hashes.iter() {
+                            sketch.add_hash(*hash);
+                        }
+                    }
+                } else if moltype == HashFunctions::Murmur64Dna && seeds.len() == 1 {
+                    let key = (rec.ksize as usize, seeds[0]);
+                    match dna_templates.iter_mut().find(|(k, _)| *k == key) {
+                        Some((_, templates)) => templates.push(i),
+                        None => dna_templates.push((key, vec![i])),
+                    }
+                } else {
+                    sig.add_sequence(seq, true)
+                        .context("Failed to add sequence")?;
                 }
+            } else {
+                continue;
             }
-            Ok(())
-        })
+            rec.sequence_added = true;
+        }
+
+        if !dna_templates.is_empty() {
+            let dna = DnaSequence::new(seq);
+            for ((ksize, seed), templates) in dna_templates.iter() {
+                dna.for_each_hash(*ksize, *seed, |hash| {
+                    for i in templates.iter() {
+                        for sketch in self.sigs[*i].iter_mut() {
+                            sketch.add_hash(hash);
+                        }
+                    }
+                });
+            }
+        }
+        Ok(())
     }
 
     pub fn build_sigs_from_data(
@@ -813,8 +1081,8 @@ impl BuildCollection {
         filename: String,
     ) -> Result<()> {
         let cursor = Cursor::new(data);
-        let mut fastx_reader =
-            parse_fastx_reader(cursor).context("Failed to parse FASTA/FASTQ data")?;
+        let mut fastx_reader = parse_fastx_reader(decompress(Box::new(cursor))?)
+            .context("Failed to parse FASTA/FASTQ data")?;
 
         // Iterate over FASTA records and add sequences/proteins to sigs
         while let Some(record) = fastx_reader.next() {
@@ -835,11 +1103,11 @@ impl BuildCollection {
         filename: String,
     ) -> Result<u64> {
         // Create a FASTX reader from the file or stdin
-        let mut fastx_reader = if filename == "-" {
-            parse_fastx_stdin().context("Failed to parse FASTA/FASTQ data from stdin")?
+        let mut fastx_reader = open_fastx(&filename).context(if filename == "-" {
+            "Failed to parse FASTA/FASTQ data from stdin"
         } else {
-            parse_fastx_file(&filename).context("Failed to open file for FASTA/FASTQ data")?
-        };
+            "Failed to open file for FASTA/FASTQ data"
+        })?;
 
         // Counter for the number of records processed
         let mut record_count: u64 = 0;
@@ -899,6 +1167,38 @@ impl BuildCollection {
         }
     }
 
+    /// The signatures that had sequence added, without the empty templates.
+    pub fn into_sigs(self) -> Vec<Signature> {
+        self.manifest
+            .records
+            .into_iter()
+            .zip(self.sigs)
+            .filter(|(record, _)| record.sequence_added)
+            .map(|(_, sig)| sig)
+            .collect()
+    }
+
+    /// Add a complete single-sketch signature (e.g. one computed from other
+    /// sketches rather than from sequence), so that it can be written out.
+    pub fn add_sig(&mut self, sig: Signature) -> Result<()> {
+        let record = Record::from_sig(&sig, "")
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("signature has no sketches"))?;
+
+        let mut build_record = BuildRecord::from_record(&record);
+        build_record.set_name(Some(sig.name_str()));
+        build_record.set_filename(Some(sig.filename()));
+        build_record.set_md5(Some(record.md5().clone()));
+        build_record.set_md5short(Some(record.md5()[0..8].into()));
+        build_record.set_n_hashes(Some(*record.n_hashes()));
+        build_record.sequence_added = true;
+
+        self.manifest.add_record(build_record);
+        self.sigs.push(sig);
+        Ok(())
+    }
+
     pub fn write_sigs(&mut self, output: &str) -> Result<()> {
         let gzip = output.ends_with(".gz");
         if output == "-" {
@@ -914,8 +1214,7 @@ impl BuildCollection {
                 .unix_permissions(0o644)
                 .large_file(true);
             // Write to a zip file
-            let file =
-                File::create(output).context(format!("Failed to create file: {}", output))?;
+            let file = AtomicFile::create(output)?;
             let mut zip = ZipWriter::new(file);
             let mut md5sum_occurrences: HashMap<String, usize> = HashMap::new();
             self.write_sigs_to_zip(&mut zip, &mut md5sum_occurrences, &options)
@@ -925,14 +1224,13 @@ impl BuildCollection {
                 ))?;
             println!("Writing manifest");
             self.manifest.write_manifest_to_zip(&mut zip, &options)?;
-            zip.finish()?;
+            zip.finish()?.commit()?;
         } else {
             // Write JSON to output file
-            let file =
-                File::create(output).context(format!("Failed to create file: {}", output))?;
-            let mut writer = std::io::BufWriter::new(file);
+            let mut writer = AtomicFile::create(output)?;
             self.write_sigs_as_json(&mut writer, gzip)
                 .context(format!("Failed to write signatures to file: {}", output))?;
+            writer.commit()?;
         }
         Ok(())
     }
@@ -1064,9 +1362,181 @@ impl MultiSelect for BuildCollection {
     }
 }
 
+/// What to do with sketches that have the same name and parameters, e.g.
+/// from two rows of a manysketch fromfile CSV with the same `name`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg(feature = "python")]
+pub enum DuplicatePolicy {
+    /// stop with an error.
+    Error,
+    /// merge the sketches into one.
+    Merge,
+    /// keep them all, renaming the second to `name_2`, the third to `name_3`, etc.
+    Suffix,
+}
+
+#[cfg(feature = "python")]
+impl FromStr for DuplicatePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "error" => Ok(DuplicatePolicy::Error),
+            "merge" => Ok(DuplicatePolicy::Merge),
+            "suffix" => Ok(DuplicatePolicy::Suffix),
+            _ => bail!(
+                "unknown duplicate name policy '{}'; use error, merge, or suffix",
+                s
+            ),
+        }
+    }
+}
+
+/// Applies a `DuplicatePolicy` to the sketches passing through a zip
+/// writer. With `Merge`, sketches are held until `finish`, since a later
+/// sketch may need to be merged into any of them.
+#[cfg(feature = "python")]
+pub struct DuplicateNames {
+    policy: DuplicatePolicy,
+    /// (name, sketch parameters) -> number of sketches seen.
+    seen: HashMap<(String, BuildRecord), usize>,
+    /// (name, sketch parameters) -> index in `held`, for `Merge`.
+    held_idx: HashMap<(String, BuildRecord), usize>,
+    held: BuildCollection,
+    n_duplicates: usize,
+}
+
+#[cfg(feature = "python")]
+impl DuplicateNames {
+    pub fn new(policy: DuplicatePolicy) -> Self {
+        DuplicateNames {
+            policy,
+            seen: HashMap::new(),
+            held_idx: HashMap::new(),
+            held: BuildCollection::new(),
+            n_duplicates: 0,
+        }
+    }
+
+    /// Check the built sketches in `collection`, returning those to write now.
+    pub fn check(&mut self, mut collection: BuildCollection) -> Result<BuildCollection> {
+        match self.policy {
+            DuplicatePolicy::Error => {
+                for (record, _) in collection.iter_mut().filter(|(r, _)| r.sequence_added) {
+                    let name = record.name.clone().unwrap_or_default();
+                    if self
+                        .seen
+                        .insert((name.clone(), record.clone()), 1)
+                        .is_some()
+                    {
+                        bail!(
+                            "more than one sketch is named '{}' with k={}, moltype={}, scaled={}; use --on-duplicate merge or suffix to allow this",
+                            name,
+                            record.ksize,
+                            record.moltype,
+                            record.scaled
+                        );
+                    }
+                }
+                Ok(collection)
+            }
+            DuplicatePolicy::Suffix => {
+                for (record, sig) in collection.iter_mut().filter(|(r, _)| r.sequence_added) {
+                    let name = record.name.clone().unwrap_or_default();
+                    let count = self.seen.entry((name.clone(), record.clone())).or_insert(0);
+                    *count += 1;
+                    if *count == 1 {
+                        continue;
+                    }
+
+                    // don't collide with a sketch that already has the new name.
+                    let mut n = *count;
+                    let mut new_name = format!("{}_{}", name, n);
+                    while self.seen.contains_key(&(new_name.clone(), record.clone())) {
+                        n += 1;
+                        new_name = format!("{}_{}", name, n);
+                    }
+                    self.seen.insert((new_name.clone(), record.clone()), 1);
+                    self.n_duplicates += 1;
+
+                    sig.set_name(&new_name);
+                    record.set_name(Some(new_name));
+                }
+                Ok(collection)
+            }
+            DuplicatePolicy::Merge => {
+                let (records, sigs) = (collection.manifest.records, collection.sigs);
+                for (record, sig) in records.into_iter().zip(sigs) {
+                    if !record.sequence_added {
+                        continue;
+                    }
+                    let key = (record.name.clone().unwrap_or_default(), record.clone());
+                    match self.held_idx.get(&key) {
+                        Some(&idx) => {
+                            merge_sketches(&mut self.held.sigs[idx], &sig)?;
+                            self.n_duplicates += 1;
+                        }
+                        None => {
+                            self.held_idx.insert(key, self.held.sigs.len());
+                            self.held.manifest.add_record(record);
+                            self.held.sigs.push(sig);
+                        }
+                    }
+                }
+                Ok(BuildCollection::new())
+            }
+        }
+    }
+
+    /// Any sketches held back for merging, to write at the end.
+    pub fn finish(mut self) -> BuildCollection {
+        match self.policy {
+            DuplicatePolicy::Error => {}
+            DuplicatePolicy::Suffix => {
+                if self.n_duplicates > 0 {
+                    eprintln!(
+                        "WARNING: renamed {} sketches with duplicate names.",
+                        self.n_duplicates
+                    );
+                }
+            }
+            DuplicatePolicy::Merge => {
+                if self.n_duplicates > 0 {
+                    eprintln!(
+                        "WARNING: merged {} sketches into others with the same name.",
+                        self.n_duplicates
+                    );
+                }
+                // merged sketches have new md5sums and sizes.
+                for (record, sig) in self.held.iter_mut() {
+                    record.set_md5(Some(sig.md5sum()));
+                    record.set_md5short(Some(sig.md5sum()[0..8].into()));
+                    record.set_n_hashes(Some(
+                        sig.get_sketch().expect("cannot retrieve sketch").size(),
+                    ));
+                }
+            }
+        }
+        self.held
+    }
+}
+
+#[cfg(feature = "python")]
+fn merge_sketches(sig: &mut Signature, other: &Signature) -> Result<()> {
+    for (sketch, other) in sig.iter_mut().zip(other.iter()) {
+        match (sketch, other) {
+            (Sketch::MinHash(mh), Sketch::MinHash(other)) => mh.merge(other)?,
+            (Sketch::LargeMinHash(mh), Sketch::LargeMinHash(other)) => mh.merge(other)?,
+            _ => bail!("cannot merge sketches of different types"),
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use sourmash::sketch::Sketch;
 
     #[test]
     fn test_valid_params_str() {
@@ -1083,7 +1553,7 @@ mod tests {
 
         // Verify that the Record, ksizes have the correct settings.
         assert_eq!(record.moltype, "DNA");
-        assert_eq!(record.with_abundance, true);
+        assert!(record.with_abundance);
         assert_eq!(ksizes, vec![31]);
         assert_eq!(record.scaled, 1000, "Expected default scaled value of 1000");
         assert_eq!(record.num, 0, "Expected default num value of 0");
@@ -1378,7 +1848,7 @@ mod tests {
         let added_record = &build_collection.manifest.records[0];
         assert_eq!(added_record.moltype, "DNA");
         assert_eq!(added_record.ksize, 31);
-        assert_eq!(added_record.with_abundance, true);
+        assert!(added_record.with_abundance);
 
         // Create a protein BuildRecord.
         let protein_record = BuildRecord {
@@ -1399,7 +1869,7 @@ mod tests {
         let added_protein_record = &build_collection.manifest.records[1];
         assert_eq!(added_protein_record.moltype, "protein");
         assert_eq!(added_protein_record.ksize, 10);
-        assert_eq!(added_protein_record.with_abundance, false);
+        assert!(!added_protein_record.with_abundance);
 
         // Create a BuildRecord with a non-matching moltype.
         let dayhoff_record = BuildRecord {
@@ -1420,6 +1890,193 @@ mod tests {
         let added_dayhoff_record = &build_collection.manifest.records[2];
         assert_eq!(added_dayhoff_record.moltype, "dayhoff");
         assert_eq!(added_dayhoff_record.ksize, 10);
-        assert_eq!(added_dayhoff_record.with_abundance, true);
+        assert!(added_dayhoff_record.with_abundance);
+    }
+
+    #[test]
+    fn test_dna_sequence_hashes() {
+        // the same hashes as add_sequence, at each ksize, skipping Ns.
+        let seq = b"ATGCGATCGATCGTAGCtagctagcTGATCNATCGTAGCTAGCTAGCATCGATCGA";
+        let dna = DnaSequence::new(seq);
+        for ksize in [3, 5, 21, 56, 57] {
+            let expected: Vec<u64> =
+                SeqToHashes::new(seq, ksize, true, false, HashFunctions::Murmur64Dna, 42)
+                    .unwrap()
+                    .map(|h| h.unwrap())
+                    .filter(|h| *h != 0)
+                    .collect();
+            let mut hashes = vec![];
+            dna.for_each_hash(ksize, 42, |h| hashes.push(h));
+            assert_eq!(hashes, expected, "ksize {}", ksize);
+        }
+    }
+
+    #[test]
+    fn test_window_params() {
+        let coll = BuildCollection::from_param_str("k=21,dna,scaled=1,window=10_k=21,dna").unwrap();
+        // windowed and regular sketches are different templates.
+        assert_eq!(coll.size(), 2);
+        let windowed = &coll.manifest.records[0];
+        assert_eq!(windowed.window, Some(10));
+        assert_eq!(
+            windowed.extra,
+            vec![("window".to_string(), "10".to_string())]
+        );
+        assert_eq!(coll.manifest.records[1].window, None);
+
+        let result = BuildCollection::parse_params("k=10,protein,window=10");
+        assert!(result.unwrap_err().contains("only supported for DNA"));
+        let result = BuildCollection::parse_params("k=21,dna,window=1");
+        assert!(result.unwrap_err().contains("must be at least 2"));
+    }
+
+    #[test]
+    fn test_window_minimizers() {
+        let seq = b"ATGCGATCGATCGTAGCTAGCTAGCTGATCGATCGTAGCTAGCTAGCATCGATCGA";
+        let ksize = 5;
+        let window = 4;
+        let hashes: Vec<u64> =
+            SeqToHashes::new(seq, ksize, true, false, HashFunctions::Murmur64Dna, 42)
+                .unwrap()
+                .map(|h| h.unwrap())
+                .collect();
+
+        // brute force: the minimum of each window, without repeats.
+        let mut expected: Vec<u64> = vec![];
+        for w in hashes.windows(window) {
+            let min = *w.iter().min().unwrap();
+            if expected.last() != Some(&min) {
+                expected.push(min);
+            }
+        }
+
+        let minimizers = window_minimizers(seq, ksize as u32, window as u32, 42).unwrap();
+        assert_eq!(minimizers, expected);
+        assert!(minimizers.len() < hashes.len());
+
+        // short sequences keep their smallest hash.
+        let short = window_minimizers(&seq[..7], ksize as u32, window as u32, 42).unwrap();
+        assert_eq!(short, vec![*hashes[..3].iter().min().unwrap()]);
+    }
+
+    #[test]
+    fn test_window_sketches_not_comparable() {
+        let mut coll =
+            BuildCollection::from_param_str("k=5,dna,scaled=1,window=4_k=5,dna,scaled=1").unwrap();
+        let fasta = b">seq\nATGCGATCGATCGTAGCTAGCTAGCTGATCGATCGTAGCTAGCTAGCATCGATCGA\n";
+        coll.build_sigs_from_data(fasta.to_vec(), "DNA", "seq".into(), "seq.fa".into())
+            .unwrap();
+
+        let minhash = |sig: &Signature| match sig.sketches().pop() {
+            Some(Sketch::LargeMinHash(mh)) => mh,
+            _ => panic!("expected a scaled sketch"),
+        };
+        let windowed = minhash(&coll.sigs[0]);
+        let regular = minhash(&coll.sigs[1]);
+        assert_eq!(windowed.seed(), (4 << 32) | 42);
+        assert_eq!(regular.seed(), 42);
+
+        // minimizers are a subset of the regular hashes, but the sketches
+        // can't be compared.
+        let mins: HashSet<u64> = windowed.mins().into_iter().collect();
+        let all: HashSet<u64> = regular.mins().into_iter().collect();
+        assert!(mins.is_subset(&all) && mins.len() < all.len());
+        assert!(matches!(
+            windowed.count_common(&regular, false),
+            Err(SourmashError::MismatchSeed)
+        ));
+    }
+
+    #[test]
+    fn test_manifest_extra_columns() {
+        let mut manifest = BuildManifest::new();
+        let mut rec1 = BuildRecord {
+            name: Some("first".to_string()),
+            md5: Some("abc".to_string()),
+            n_hashes: Some(10),
+            sequence_added: true,
+            ..BuildRecord::default_dna()
+        };
+        rec1.extra = vec![("taxon".to_string(), "s__a".to_string())];
+        let rec2 = BuildRecord {
+            name: Some("second".to_string()),
+            md5: Some("def".to_string()),
+            n_hashes: Some(20),
+            sequence_added: true,
+            ..BuildRecord::default_dna()
+        };
+        manifest.add_record(rec1);
+        manifest.add_record(rec2);
+
+        let mut buf = vec![];
+        manifest.to_writer(&mut buf).unwrap();
+        let text = String::from_utf8(buf.clone()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[1],
+            "internal_location,md5,md5short,ksize,moltype,num,scaled,n_hashes,with_abundance,name,filename,taxon"
+        );
+        assert!(lines[2].ends_with(",first,,s__a"));
+        assert!(lines[3].ends_with(",second,,"));
+
+        // extra columns must not break manifest loading.
+        let loaded = sourmash::manifest::Manifest::from_reader(Cursor::new(buf)).unwrap();
+        assert_eq!(loaded.len(), 2);
+    }
+
+    #[cfg(feature = "python")]
+    fn built(name: &str, seq: &str) -> BuildCollection {
+        let mut collection = BuildCollection::from_param_str("dna,k=21,scaled=1").unwrap();
+        let fasta = format!(">{}\n{}\n", name, seq);
+        collection
+            .build_sigs_from_data(fasta.into_bytes(), "DNA", name.into(), "-".into())
+            .unwrap();
+        collection
+    }
+
+    #[test]
+    #[cfg(feature = "python")]
+    fn test_duplicate_names() {
+        let seq1 = "ACGTTGCAGGCTAGCTAGGCATCGATCGACTGACTAGCATCGACTAG";
+        let seq2 = "TTGACGATCGGCATGCGCGATATATCGCGTACGATCGATGCATGCAAT";
+
+        let mut error = DuplicateNames::new(DuplicatePolicy::Error);
+        assert!(error.check(built("a", seq1)).is_ok());
+        assert!(error.check(built("b", seq1)).is_ok());
+        let err = error.check(built("a", seq2)).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("more than one sketch is named 'a'"));
+
+        let mut suffix = DuplicateNames::new(DuplicatePolicy::Suffix);
+        let names: Vec<String> = ["a", "a_2", "a", "a"]
+            .iter()
+            .map(|name| {
+                let mut collection = suffix.check(built(name, seq1)).unwrap();
+                let (record, sig) = collection.iter_mut().next().unwrap();
+                assert_eq!(record.name().as_deref(), Some(sig.name_str().as_str()));
+                sig.name_str()
+            })
+            .collect();
+        assert_eq!(names, ["a", "a_2", "a_3", "a_4"]);
+
+        let mut merge = DuplicateNames::new(DuplicatePolicy::Merge);
+        assert!(merge.check(built("a", seq1)).unwrap().sigs.is_empty());
+        merge.check(built("a", seq2)).unwrap();
+        merge.check(built("b", seq2)).unwrap();
+        let mut merged = merge.finish();
+        assert_eq!(merged.size(), 2);
+
+        let n_hashes = |seq| built("x", seq).sigs[0].get_sketch().unwrap().size();
+        let (record, sig) = merged.iter_mut().next().unwrap();
+        assert_eq!(sig.name_str(), "a");
+        assert_eq!(
+            sig.get_sketch().unwrap().size(),
+            n_hashes(seq1) + n_hashes(seq2)
+        );
+        assert_eq!(record.n_hashes().unwrap(), sig.get_sketch().unwrap().size());
+        assert_eq!(record.md5().as_deref(), Some(sig.md5sum().as_str()));
     }
 }
@@ -0,0 +1,688 @@
+//! Select a subset of output columns, for `--output-columns`, and write
+//! result rows.
+
+use anyhow::Result;
+use csv::{Writer, WriterBuilder};
+use serde::Serialize;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use super::arrowstream::ArrowStream;
+use super::atomicfile::Output;
+use super::metadata::MatchMetadata;
+use super::namerewrite::NameRewrites;
+use super::outputmode::{open_append, open_shard, OutputFormat, OutputMode};
+use super::profile::Stage;
+use super::runcontext::RunContext;
+use super::sqlitetable::SqliteTable;
+use super::{
+    batch_rows, finish_csv, is_stream_output, open_output, open_stdout_or_file, Batched,
+    BranchwaterGatherResult, ManySearchResult, MultiSearchResult,
+};
+
+/// The type of an output column, in typed (Arrow and SQLite) outputs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColumnType {
+    Utf8,
+    UInt64,
+    Float64,
+    Boolean,
+}
+
+/// A row type written to CSV output, with the full list of columns it
+/// may contain (in output order, including optional columns).
+pub trait ResultType: Serialize {
+    const COLUMNS: &'static [&'static str];
+    /// The table for these rows in SQLite output.
+    const TABLE: &'static str;
+
+    /// The columns of the equivalent sourmash output, for
+    /// `--sourmash-compat`, each with the column it is taken from; None
+    /// for a column that branchwater doesn't compute, which is written as
+    /// false.
+    const SOURMASH_COLUMNS: Option<&'static [(&'static str, Option<&'static str>)]> = None;
+
+    /// The type of `column` in typed output; other columns, such as
+    /// `--match-metadata` columns, are text.
+    fn column_type(column: &str) -> ColumnType;
+}
+
+impl ResultType for ManySearchResult {
+    const TABLE: &'static str = "manysearch";
+    const COLUMNS: &'static [&'static str] = &[
+        "query_name",
+        "query_md5",
+        "match_name",
+        "containment",
+        "intersect_hashes",
+        "intersect_bp",
+        "query_n_hashes",
+        "match_n_hashes",
+        "query_bp",
+        "match_bp",
+        "ksize",
+        "scaled",
+        "moltype",
+        "match_md5",
+        "jaccard",
+        "max_containment",
+        "average_abund",
+        "median_abund",
+        "std_abund",
+        "query_containment_ani",
+        "match_containment_ani",
+        "average_containment_ani",
+        "max_containment_ani",
+        "n_weighted_found",
+        "total_weighted_hashes",
+        "match_aliases",
+    ];
+
+    fn column_type(column: &str) -> ColumnType {
+        match column {
+            "intersect_hashes"
+            | "intersect_bp"
+            | "query_n_hashes"
+            | "match_n_hashes"
+            | "query_bp"
+            | "match_bp"
+            | "ksize"
+            | "scaled"
+            | "n_weighted_found"
+            | "total_weighted_hashes" => ColumnType::UInt64,
+            "query_name" | "query_md5" | "match_name" | "moltype" | "match_md5"
+            | "match_aliases" => ColumnType::Utf8,
+            c if Self::COLUMNS.contains(&c) => ColumnType::Float64,
+            _ => ColumnType::Utf8,
+        }
+    }
+}
+
+impl ResultType for MultiSearchResult {
+    const TABLE: &'static str = "multisearch";
+    const COLUMNS: &'static [&'static str] = &[
+        "query_name",
+        "query_md5",
+        "match_name",
+        "match_md5",
+        "containment",
+        "max_containment",
+        "jaccard",
+        "intersect_hashes",
+        "intersect_bp",
+        "query_n_hashes",
+        "match_n_hashes",
+        "query_bp",
+        "match_bp",
+        "ksize",
+        "scaled",
+        "moltype",
+        "query_containment_ani",
+        "match_containment_ani",
+        "average_containment_ani",
+        "max_containment_ani",
+        "angular_similarity",
+        "prob_overlap",
+        "prob_overlap_adjusted",
+        "containment_adjusted",
+        "containment_adjusted_log10",
+        "tf_idf_score",
+        "overlap_pvalue",
+        "expected_false_positives",
+        "bh_qvalue",
+        "match_aliases",
+    ];
+
+    fn column_type(column: &str) -> ColumnType {
+        match column {
+            "intersect_bp" | "query_n_hashes" | "match_n_hashes" | "query_bp" | "match_bp"
+            | "ksize" | "scaled" => ColumnType::UInt64,
+            "query_name" | "query_md5" | "match_name" | "match_md5" | "moltype"
+            | "match_aliases" => ColumnType::Utf8,
+            // including intersect_hashes, which is a float here.
+            c if Self::COLUMNS.contains(&c) => ColumnType::Float64,
+            _ => ColumnType::Utf8,
+        }
+    }
+}
+
+impl ResultType for BranchwaterGatherResult {
+    const TABLE: &'static str = "gather";
+    const COLUMNS: &'static [&'static str] = &[
+        "intersect_bp",
+        "intersect_hashes",
+        "f_orig_query",
+        "f_match",
+        "f_unique_to_query",
+        "f_unique_weighted",
+        "average_abund",
+        "median_abund",
+        "std_abund",
+        "match_filename",
+        "match_name",
+        "match_md5",
+        "f_match_orig",
+        "unique_intersect_bp",
+        "gather_result_rank",
+        "remaining_bp",
+        "query_filename",
+        "query_name",
+        "query_md5",
+        "query_bp",
+        "ksize",
+        "moltype",
+        "scaled",
+        "query_n_hashes",
+        "query_abundance",
+        "query_containment_ani",
+        "match_containment_ani",
+        "average_containment_ani",
+        "max_containment_ani",
+        "n_unique_weighted_found",
+        "sum_weighted_found",
+        "total_weighted_hashes",
+        "query_containment_ani_ci_low",
+        "query_containment_ani_ci_high",
+        "match_containment_ani_ci_low",
+        "match_containment_ani_ci_high",
+    ];
+    /// The columns of `sourmash gather`, as read by `sourmash tax`.
+    const SOURMASH_COLUMNS: Option<&'static [(&'static str, Option<&'static str>)]> = Some(&[
+        ("intersect_bp", Some("intersect_bp")),
+        ("f_orig_query", Some("f_orig_query")),
+        ("f_match", Some("f_match")),
+        ("f_unique_to_query", Some("f_unique_to_query")),
+        ("f_unique_weighted", Some("f_unique_weighted")),
+        ("average_abund", Some("average_abund")),
+        ("median_abund", Some("median_abund")),
+        ("std_abund", Some("std_abund")),
+        ("filename", Some("match_filename")),
+        ("name", Some("match_name")),
+        ("md5", Some("match_md5")),
+        ("f_match_orig", Some("f_match_orig")),
+        ("unique_intersect_bp", Some("unique_intersect_bp")),
+        ("gather_result_rank", Some("gather_result_rank")),
+        ("remaining_bp", Some("remaining_bp")),
+        ("query_filename", Some("query_filename")),
+        ("query_name", Some("query_name")),
+        ("query_md5", Some("query_md5")),
+        ("query_bp", Some("query_bp")),
+        ("ksize", Some("ksize")),
+        ("moltype", Some("moltype")),
+        ("scaled", Some("scaled")),
+        ("query_n_hashes", Some("query_n_hashes")),
+        ("query_abundance", Some("query_abundance")),
+        ("query_containment_ani", Some("query_containment_ani")),
+        ("match_containment_ani", Some("match_containment_ani")),
+        ("average_containment_ani", Some("average_containment_ani")),
+        ("max_containment_ani", Some("max_containment_ani")),
+        ("potential_false_negative", None),
+        ("n_unique_weighted_found", Some("n_unique_weighted_found")),
+        ("sum_weighted_found", Some("sum_weighted_found")),
+        ("total_weighted_hashes", Some("total_weighted_hashes")),
+    ]);
+
+    fn column_type(column: &str) -> ColumnType {
+        match column {
+            "intersect_bp"
+            | "intersect_hashes"
+            | "unique_intersect_bp"
+            | "gather_result_rank"
+            | "remaining_bp"
+            | "query_bp"
+            | "ksize"
+            | "scaled"
+            | "query_n_hashes"
+            | "n_unique_weighted_found"
+            | "sum_weighted_found"
+            | "total_weighted_hashes" => ColumnType::UInt64,
+            "query_abundance" | "potential_false_negative" => ColumnType::Boolean,
+            "match_filename" | "match_name" | "match_md5" | "query_filename" | "query_name"
+            | "query_md5" | "moltype" => ColumnType::Utf8,
+            c if Self::COLUMNS.contains(&c) => ColumnType::Float64,
+            _ => ColumnType::Utf8,
+        }
+    }
+}
+
+/// The columns requested with `--output-columns`; `None` means all. Any
+/// `--match-metadata` columns are written after them, and query and match
+/// names are rewritten with `--rename-query` and `--rename-match`.
+#[derive(Clone, Debug, Default)]
+pub struct ColumnSelection {
+    columns: Option<Vec<String>>,
+    metadata: Option<Arc<MatchMetadata>>,
+    rewrites: Option<Arc<NameRewrites>>,
+    sourmash_compat: bool,
+}
+
+impl ColumnSelection {
+    /// Parse a comma-separated list of column names, and check that each
+    /// is a column of `T`.
+    pub fn new<T: ResultType>(spec: Option<String>) -> Result<Self> {
+        let Some(spec) = spec else {
+            return Ok(ColumnSelection::default());
+        };
+
+        let columns: Vec<String> = spec
+            .split(',')
+            .map(|c| c.trim())
+            .filter(|c| !c.is_empty())
+            .map(String::from)
+            .collect();
+        if columns.is_empty() {
+            bail!("--output-columns must name at least one column");
+        }
+
+        let unknown: Vec<&str> = columns
+            .iter()
+            .map(String::as_str)
+            .filter(|c| !T::COLUMNS.contains(c))
+            .collect();
+        if !unknown.is_empty() {
+            bail!(
+                "unknown output column(s): {}; valid columns are: {}",
+                unknown.join(", "),
+                T::COLUMNS.join(", ")
+            );
+        }
+        Ok(ColumnSelection {
+            columns: Some(columns),
+            ..Default::default()
+        })
+    }
+
+    /// Also write the columns in `metadata` for each row's match, checking
+    /// that none of them is already a column of `T`.
+    pub fn with_match_metadata<T: ResultType>(
+        self,
+        metadata: Option<MatchMetadata>,
+    ) -> Result<Self> {
+        let Some(metadata) = metadata else {
+            return Ok(self);
+        };
+        let clashes: Vec<&str> = metadata
+            .columns()
+            .iter()
+            .map(String::as_str)
+            .filter(|c| T::COLUMNS.contains(c))
+            .collect();
+        if !clashes.is_empty() {
+            bail!(
+                "metadata column(s) {} clash with output columns; please rename them",
+                clashes.join(", ")
+            );
+        }
+        Ok(ColumnSelection {
+            metadata: Some(Arc::new(metadata)),
+            ..self
+        })
+    }
+
+    /// Also rewrite query and match names with sed-style substitutions.
+    pub fn with_name_rewrites(
+        self,
+        rename_query: Option<Vec<String>>,
+        rename_match: Option<Vec<String>>,
+    ) -> Result<Self> {
+        let rewrites = NameRewrites::new(rename_query, rename_match)?;
+        Ok(ColumnSelection {
+            rewrites: rewrites.map(Arc::new),
+            ..self
+        })
+    }
+
+    /// Write the columns of sourmash's own output instead, where `T` has
+    /// them, if `enabled`.
+    pub fn with_sourmash_compat(self, enabled: bool) -> Self {
+        ColumnSelection {
+            sourmash_compat: enabled,
+            ..self
+        }
+    }
+
+    /// Whether outputs should match sourmash's; see `with_sourmash_compat`.
+    pub fn sourmash_compat(&self) -> bool {
+        self.sourmash_compat
+    }
+
+    /// Whether rows can be serialized as they are.
+    fn writes_whole_rows(&self) -> bool {
+        self.columns.is_none() && self.metadata.is_none() && self.rewrites.is_none()
+    }
+
+    /// The header for `columns`, followed by any metadata columns.
+    fn header<'a>(&'a self, columns: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+        let metadata_columns = self.metadata.iter().flat_map(|m| m.columns());
+        columns
+            .chain(metadata_columns.map(String::as_str))
+            .collect()
+    }
+}
+
+/// Where rows are written: a CSV, an Arrow IPC stream, or a SQLite table.
+enum Sink {
+    Csv(Writer<Box<dyn Output>>),
+    Arrow(ArrowStream),
+    Sqlite(SqliteTable),
+    /// The output has been finished and committed.
+    Finished,
+}
+
+impl Sink {
+    /// Open `output`, or stdout if None, in `format` and `mode`; returns
+    /// the sink and whether the output already has a header.
+    fn open(output: Option<&str>, format: OutputFormat, mode: &OutputMode) -> Result<(Self, bool)> {
+        if format == OutputFormat::Sqlite {
+            if mode.shard_size.is_some() {
+                bail!("--output-shard-size cannot be used with SQLite output");
+            }
+            if let Some(path) = output.filter(|_| !is_stream_output(output)) {
+                return Ok((Sink::Sqlite(SqliteTable::open(path, mode.append)?), false));
+            }
+        }
+        let (out, has_header) = match output {
+            Some(path) if mode.append => open_append(path)?,
+            Some(path) => (open_output(path)?, false),
+            None => (open_stdout_or_file(None), false),
+        };
+        Ok((Sink::from_writer(out, format, has_header)?, has_header))
+    }
+
+    fn from_writer(out: Box<dyn Output>, format: OutputFormat, has_header: bool) -> Result<Self> {
+        match format {
+            OutputFormat::Csv => Ok(Sink::Csv(
+                WriterBuilder::new()
+                    .has_headers(!has_header)
+                    .from_writer(out),
+            )),
+            OutputFormat::Arrow => Ok(Sink::Arrow(ArrowStream::new(out))),
+            OutputFormat::Sqlite => {
+                bail!("SQLite output must be a regular file, not stdout, a pipe, or a socket")
+            }
+        }
+    }
+
+    /// Write the CSV header, the Arrow schema, or the SQLite table.
+    fn write_header<T: ResultType>(&mut self, header: Vec<&str>) -> Result<()> {
+        let typed = header.iter().map(|c| (*c, T::column_type(c)));
+        match self {
+            Sink::Csv(writer) => writer.write_record(&header)?,
+            Sink::Arrow(stream) => stream.start(typed)?,
+            Sink::Sqlite(table) => table.start(T::TABLE, typed)?,
+            Sink::Finished => bail!("output has already been finished"),
+        }
+        Ok(())
+    }
+
+    fn write_values(&mut self, values: Vec<serde_json::Value>) -> Result<()> {
+        match self {
+            Sink::Csv(writer) => {
+                let fields = values.into_iter().map(|v| match v {
+                    serde_json::Value::Null => String::new(),
+                    serde_json::Value::String(s) => s,
+                    v => v.to_string(),
+                });
+                writer.write_record(fields)?;
+            }
+            Sink::Arrow(stream) => stream.push(values)?,
+            Sink::Sqlite(table) => table.push(values)?,
+            Sink::Finished => bail!("output has already been finished"),
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            Sink::Csv(writer) => writer.flush()?,
+            Sink::Arrow(stream) => stream.flush()?,
+            Sink::Sqlite(table) => table.flush()?,
+            Sink::Finished => {}
+        }
+        Ok(())
+    }
+
+    /// Flush and commit the output; outputs that are dropped without
+    /// being finished are discarded.
+    fn finish(&mut self) -> Result<()> {
+        match std::mem::replace(self, Sink::Finished) {
+            Sink::Csv(writer) => finish_csv(writer)?.commit()?,
+            Sink::Arrow(stream) => stream.finish()?,
+            Sink::Sqlite(mut table) => table.finish()?,
+            Sink::Finished => {}
+        }
+        Ok(())
+    }
+}
+
+/// A result writer that writes either whole rows or only the selected
+/// columns, as CSV, an Arrow IPC stream, or a SQLite table.
+/// With `--append`, rows are added to an existing output without a header;
+/// with `--output-shard-size`, a new output file (with its own header) is
+/// started after every so many rows. The time spent writing is added to
+/// the profile when it is dropped.
+pub struct ResultWriter {
+    ctx: RunContext,
+    sink: Sink,
+    format: OutputFormat,
+    columns: ColumnSelection,
+    wrote_header: bool,
+    rows: usize,
+    output: Option<String>,
+    shard_size: Option<usize>,
+    shard: usize,
+    shard_rows: usize,
+    busy: Duration,
+}
+
+impl ResultWriter {
+    /// Open `output`, or stdout if None, in the output mode of `ctx`.
+    pub fn open(
+        output: Option<String>,
+        columns: ColumnSelection,
+        ctx: &RunContext,
+    ) -> Result<Self> {
+        let mode = ctx.output_mode(output.as_deref());
+        let format = mode.format(output.as_deref());
+        let (sink, has_header) = Sink::open(output.as_deref(), format, &mode)?;
+        Ok(ResultWriter {
+            ctx: ctx.clone(),
+            sink,
+            format,
+            columns,
+            wrote_header: has_header,
+            rows: 0,
+            output,
+            shard_size: mode.shard_size,
+            shard: 0,
+            shard_rows: 0,
+            busy: Duration::ZERO,
+        })
+    }
+
+    pub fn write<T: ResultType>(&mut self, row: &T) -> Result<()> {
+        let start = Instant::now();
+        let result = self.next_shard().and_then(|_| self.write_row(row));
+        self.busy += start.elapsed();
+        if result.is_ok() {
+            self.rows += 1;
+            self.shard_rows += 1;
+        }
+        result
+    }
+
+    /// Finish writing: write the header if there were no rows, so that
+    /// empty results are still a valid CSV (or Arrow stream), and flush.
+    pub fn finish<T: ResultType>(&mut self) -> Result<()> {
+        if self.rows == 0 && !self.wrote_header {
+            let header = match (self.sourmash_columns::<T>(), &self.columns.columns) {
+                (Some(compat), _) => self.columns.header(compat.iter().map(|(c, _)| *c)),
+                (None, Some(columns)) => self.columns.header(columns.iter().map(String::as_str)),
+                (None, None) => self.columns.header(T::COLUMNS.iter().copied()),
+            };
+            self.sink.write_header::<T>(header)?;
+            self.wrote_header = true;
+        }
+        if let Some(metadata) = &self.columns.metadata {
+            metadata.report_missing();
+        }
+        self.ctx.record_rows(self.rows);
+        let start = Instant::now();
+        let result = self.sink.finish();
+        self.busy += start.elapsed();
+        result?;
+        if self.shard > 0 {
+            eprintln!(
+                "Wrote {} rows to {} output shards.",
+                self.rows,
+                self.shard + 1
+            );
+        }
+        Ok(())
+    }
+
+    /// If the current shard is full, move on to a new one.
+    fn next_shard(&mut self) -> Result<()> {
+        let (Some(output), Some(shard_size)) = (&self.output, self.shard_size) else {
+            return Ok(());
+        };
+        if self.shard_rows < shard_size {
+            return Ok(());
+        }
+
+        self.sink.finish()?;
+        self.shard += 1;
+        self.shard_rows = 0;
+        self.sink = Sink::from_writer(open_shard(output, self.shard)?, self.format, false)?;
+        self.wrote_header = false;
+        Ok(())
+    }
+
+    /// The sourmash columns to write for `T` under `--sourmash-compat`.
+    fn sourmash_columns<T: ResultType>(
+        &self,
+    ) -> Option<&'static [(&'static str, Option<&'static str>)]> {
+        T::SOURMASH_COLUMNS.filter(|_| self.columns.sourmash_compat)
+    }
+
+    fn write_row<T: ResultType>(&mut self, row: &T) -> Result<()> {
+        let compat = self.sourmash_columns::<T>();
+        let whole_rows = self.columns.writes_whole_rows() && compat.is_none();
+        if let (Sink::Csv(writer), true) = (&mut self.sink, whole_rows) {
+            writer.serialize(row)?;
+            return Ok(());
+        }
+
+        // go through serde_json so that field names match the CSV headers.
+        let mut value = serde_json::to_value(row)?;
+        if let Some(rewrites) = &self.columns.rewrites {
+            if let Some(serde_json::Value::String(name)) = value.get_mut("query_name") {
+                *name = rewrites.query(name);
+            }
+            if let Some(serde_json::Value::String(name)) = value.get_mut("match_name") {
+                *name = rewrites.match_name(name);
+            }
+        }
+        if let Some(compat) = compat {
+            if !self.wrote_header {
+                let header = self.columns.header(compat.iter().map(|(c, _)| *c));
+                self.sink.write_header::<T>(header)?;
+                self.wrote_header = true;
+            }
+            let values = compat
+                .iter()
+                .map(|(_, source)| match source {
+                    Some(source) => value.get(*source).cloned().unwrap_or_default(),
+                    None => serde_json::Value::Bool(false),
+                })
+                .collect();
+            return self.write_values(&value, values);
+        }
+
+        // with no selection, write the columns of the first row, as
+        // serializing it would.
+        if self.columns.columns.is_none() {
+            let present = T::COLUMNS
+                .iter()
+                .filter(|c| value.get(**c).is_some())
+                .map(|c| c.to_string())
+                .collect();
+            self.columns.columns = Some(present);
+        }
+        let selection = &self.columns;
+        let columns = selection.columns.as_deref().unwrap_or_default();
+
+        if !self.wrote_header {
+            let header = selection.header(columns.iter().map(String::as_str));
+            self.sink.write_header::<T>(header)?;
+            self.wrote_header = true;
+        }
+
+        let values = columns
+            .iter()
+            .map(|c| value.get(c).cloned().unwrap_or_default())
+            .collect();
+        self.write_values(&value, values)
+    }
+
+    /// Write the `values` of `row`, followed by any metadata values for
+    /// its match.
+    fn write_values(
+        &mut self,
+        row: &serde_json::Value,
+        mut values: Vec<serde_json::Value>,
+    ) -> Result<()> {
+        if let Some(metadata) = &self.columns.metadata {
+            let match_name = row.get("match_name").and_then(|v| v.as_str());
+            let metadata_values = metadata.values(match_name.unwrap_or_default());
+            values.extend(metadata_values.into_iter().map(serde_json::Value::String));
+        }
+        self.sink.write_values(values)
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        let start = Instant::now();
+        let result = self.sink.flush();
+        self.busy += start.elapsed();
+        result
+    }
+
+    /// Flush partway through writing; errors are reported but not fatal.
+    pub fn flush_or_warn(&mut self) {
+        if let Err(e) = self.flush() {
+            eprintln!("Error flushing output: {:?}", e);
+        }
+    }
+}
+
+impl Drop for ResultWriter {
+    fn drop(&mut self) {
+        self.ctx.record_time(Stage::Writing, self.busy);
+    }
+}
+
+/// Like `csvwriter_thread`, but writes only the selected columns.
+pub fn result_csvwriter_thread<T: ResultType + Send + 'static>(
+    recv: Receiver<T>,
+    output: Option<String>,
+    columns: ColumnSelection,
+    ctx: &RunContext,
+) -> Result<JoinHandle<()>> {
+    let flush = ctx.flush_policy(output.as_deref());
+    let mut writer = ResultWriter::open(output, columns, ctx)?;
+    Ok(std::thread::spawn(move || {
+        for item in batch_rows(recv, flush) {
+            match item {
+                Batched::Row(res) => {
+                    if let Err(e) = writer.write(&res) {
+                        eprintln!("Error writing item: {:?}", e);
+                    }
+                }
+                Batched::Flush => writer.flush_or_warn(),
+            }
+        }
+        writer.finish::<T>().expect("Failed to flush writer.");
+    }))
+}
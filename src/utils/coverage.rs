@@ -0,0 +1,118 @@
+//! Coverage of against sketches by the union of all queries, and of
+//! queries by the union of all against sketches.
+
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use sourmash::signature::SigsTrait;
+use sourmash::sketch::minhash::KmerMinHash;
+
+#[cfg(feature = "python")]
+use super::multicollection::SmallSignature;
+#[cfg(feature = "python")]
+use super::{finish_csv, open_stdout_or_file};
+#[cfg(feature = "python")]
+use anyhow::Result;
+
+#[derive(Serialize)]
+struct CoverageRow {
+    role: &'static str,
+    name: String,
+    md5: String,
+    n_hashes: usize,
+    n_covered: usize,
+    f_covered: f64,
+}
+
+impl CoverageRow {
+    fn new(role: &'static str, name: &str, md5: &str, n_hashes: usize, n_covered: usize) -> Self {
+        let f_covered = if n_hashes > 0 {
+            n_covered as f64 / n_hashes as f64
+        } else {
+            0.0
+        };
+        CoverageRow {
+            role,
+            name: name.to_string(),
+            md5: md5.to_string(),
+            n_hashes,
+            n_covered,
+            f_covered,
+        }
+    }
+}
+
+/// Tracks coverage while against sketches stream by, so that they need
+/// not all be held in memory.
+pub struct CoverageReport {
+    query_hashes: HashSet<u64>,
+    /// query hashes present in at least one against sketch.
+    covered_query_hashes: Mutex<HashSet<u64>>,
+    against_rows: Mutex<Vec<CoverageRow>>,
+}
+
+impl CoverageReport {
+    #[cfg(feature = "python")]
+    pub fn new(queries: &[SmallSignature]) -> Self {
+        let query_hashes = queries
+            .iter()
+            .flat_map(|q| q.minhash.iter_mins().copied())
+            .collect();
+        CoverageReport {
+            query_hashes,
+            covered_query_hashes: Mutex::new(HashSet::new()),
+            against_rows: Mutex::new(vec![]),
+        }
+    }
+
+    /// Record the coverage of one against sketch by the query union.
+    pub fn add_against(&self, name: &str, md5: &str, against_mh: &KmerMinHash) {
+        let covered: Vec<u64> = against_mh
+            .iter_mins()
+            .filter(|h| self.query_hashes.contains(h))
+            .copied()
+            .collect();
+
+        let row = CoverageRow::new("against", name, md5, against_mh.size(), covered.len());
+        self.against_rows.lock().unwrap().push(row);
+        self.covered_query_hashes.lock().unwrap().extend(covered);
+    }
+
+    /// Write one row per query, in query order, and then one row per
+    /// against sketch, sorted by name and md5.
+    #[cfg(feature = "python")]
+    pub fn write(self, queries: &[SmallSignature], output: &str) -> Result<()> {
+        let covered = self.covered_query_hashes.into_inner().unwrap();
+        let mut against_rows = self.against_rows.into_inner().unwrap();
+        against_rows.sort_by(|a, b| (&a.name, &a.md5).cmp(&(&b.name, &b.md5)));
+
+        let n_untouched = against_rows.iter().filter(|r| r.n_covered == 0).count();
+
+        let mut writer = csv::Writer::from_writer(open_stdout_or_file(Some(output.into())));
+        for query in queries {
+            let n_covered = query
+                .minhash
+                .iter_mins()
+                .filter(|h| covered.contains(h))
+                .count();
+            writer.serialize(CoverageRow::new(
+                "query",
+                &query.name,
+                &query.md5sum,
+                query.minhash.size(),
+                n_covered,
+            ))?;
+        }
+        for row in against_rows {
+            writer.serialize(row)?;
+        }
+        finish_csv(writer)?.commit()?;
+
+        eprintln!(
+            "Wrote coverage report to '{}'; {} against sketches share no hashes with any query.",
+            output, n_untouched
+        );
+        Ok(())
+    }
+}
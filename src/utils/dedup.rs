@@ -0,0 +1,76 @@
+//! Collapsing against sketches with identical md5s, for `--dedup-by-md5`.
+//!
+//! Databases often hold the same genome under several names, which then
+//! match every query equally well. With deduplication set in a command's
+//! `RunContext`, `load_collection` keeps only the first sketch with each md5 in
+//! each against collection it loads, and the others' names are reported
+//! in the `match_aliases` column of its matches instead. Since the
+//! sketches are identical, so are their scores.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use super::{MultiCollection, ReportType, RunContext};
+
+/// Names of the dropped duplicates, separated by ';', by md5.
+pub type Aliases = HashMap<String, String>;
+
+/// The aliases noted while loading the against collections of one command.
+#[derive(Debug, Default)]
+pub struct Dedup {
+    aliases: Mutex<Arc<Aliases>>,
+}
+
+impl Dedup {
+    /// The aliases noted so far. Take these once the against collection
+    /// is loaded, rather than for each match.
+    pub fn aliases(&self) -> Arc<Aliases> {
+        self.aliases.lock().unwrap().clone()
+    }
+}
+
+/// Drop all but the first sketch with each md5 from an against collection
+/// if `ctx` deduplicates, noting the names of the others.
+pub fn apply(
+    collection: MultiCollection,
+    report_type: ReportType,
+    ctx: &RunContext,
+) -> MultiCollection {
+    if !matches!(report_type, ReportType::Against) {
+        return collection;
+    }
+    let Some(dedup) = ctx.dedup() else {
+        return collection;
+    };
+
+    let mut names: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (_, _, record) in collection.item_iter() {
+        names
+            .entry(record.md5().as_str())
+            .or_default()
+            .push(record.name().as_str());
+    }
+    let n_dropped: usize = names.values().map(|names| names.len() - 1).sum();
+    if n_dropped == 0 {
+        return collection;
+    }
+
+    let mut aliases = dedup.aliases.lock().unwrap();
+    let aliases = Arc::make_mut(&mut aliases);
+    for (md5, names) in names.into_iter().filter(|(_, names)| names.len() > 1) {
+        aliases.insert(md5.to_string(), names[1..].join(";"));
+    }
+    eprintln!(
+        "Collapsed {} against sketches with the same md5 as another; see 'match_aliases'.",
+        n_dropped
+    );
+
+    // keep the first of each md5, in the order listed above.
+    let seen = Mutex::new(HashSet::new());
+    collection.filter_records(|record| seen.lock().unwrap().insert(record.md5().clone()))
+}
+
+/// The `match_aliases` value for a match with `md5`.
+pub fn match_aliases(aliases: Option<&Arc<Aliases>>, md5: &str) -> Option<String> {
+    aliases.map(|aliases| aliases.get(md5).cloned().unwrap_or_default())
+}
@@ -0,0 +1,147 @@
+//! Condensed distance matrices for `pairwise --output-distances`.
+//!
+//! The matrix is written as a NumPy `.npy` file holding the upper
+//! triangle of the distance matrix, row by row, in the "condensed" form
+//! that `scipy.cluster.hierarchy.linkage` takes directly. Sketch names
+//! are written one per line to `<output>.labels.txt`, in matrix order,
+//! as for `sourmash compare`.
+
+use anyhow::Result;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use sourmash::ani_utils::ani_from_containment;
+
+#[cfg(feature = "python")]
+use super::atomicfile::AtomicFile;
+#[cfg(feature = "python")]
+use super::multicollection::SmallSignature;
+#[cfg(feature = "python")]
+use std::io::Write;
+
+/// Symmetric similarity measures; distance is 1 - similarity.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DistanceMeasure {
+    Jaccard,
+    MaxContainment,
+    AverageContainmentAni,
+    MaxContainmentAni,
+}
+
+impl std::str::FromStr for DistanceMeasure {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "jaccard" => Ok(DistanceMeasure::Jaccard),
+            "max_containment" => Ok(DistanceMeasure::MaxContainment),
+            "average_containment_ani" => Ok(DistanceMeasure::AverageContainmentAni),
+            "max_containment_ani" => Ok(DistanceMeasure::MaxContainmentAni),
+            _ => bail!(
+                "unknown distance measure '{}'; use jaccard, max_containment, average_containment_ani, or max_containment_ani",
+                s
+            ),
+        }
+    }
+}
+
+/// Distance matrix output requested for a pairwise run.
+pub struct DistanceOptions {
+    #[cfg(feature = "python")]
+    pub path: String,
+    pub measure: DistanceMeasure,
+}
+
+impl DistanceOptions {
+    #[cfg(feature = "python")]
+    pub fn new(path: String, measure: &str) -> Result<Self> {
+        if !path.ends_with(".npy") {
+            bail!("distance matrix output '{}' must end in '.npy'", path);
+        }
+        Ok(DistanceOptions {
+            path,
+            measure: measure.parse()?,
+        })
+    }
+}
+
+/// The condensed distance matrix of `n` sketches, filled in as pairs are
+/// compared. Pairs that are never compared (e.g. with `--candidates`) are
+/// left at the maximum distance of 1.
+pub struct DistanceMatrix {
+    options: DistanceOptions,
+    n: usize,
+    ksize: f64,
+    /// f64 distances, as bits, so that rows can be filled in parallel.
+    distances: Vec<AtomicU64>,
+}
+
+impl DistanceMatrix {
+    #[cfg(feature = "python")]
+    pub fn new(options: DistanceOptions, n: usize, ksize: f64) -> Self {
+        let distances = (0..n * n.saturating_sub(1) / 2)
+            .map(|_| AtomicU64::new(1f64.to_bits()))
+            .collect();
+        DistanceMatrix {
+            options,
+            n,
+            ksize,
+            distances,
+        }
+    }
+
+    /// Record the comparison of sketches `i` and `j`, from the fraction of
+    /// each contained in the other and their Jaccard similarity.
+    pub fn set(&self, i: usize, j: usize, containment_ij: f64, containment_ji: f64, jaccard: f64) {
+        let similarity = match self.options.measure {
+            DistanceMeasure::Jaccard => jaccard,
+            DistanceMeasure::MaxContainment => containment_ij.max(containment_ji),
+            DistanceMeasure::AverageContainmentAni => {
+                (ani_from_containment(containment_ij, self.ksize)
+                    + ani_from_containment(containment_ji, self.ksize))
+                    / 2.
+            }
+            DistanceMeasure::MaxContainmentAni => ani_from_containment(containment_ij, self.ksize)
+                .max(ani_from_containment(containment_ji, self.ksize)),
+        };
+        let (i, j) = (i.min(j), i.max(j));
+        let idx = self.n * i - i * (i + 1) / 2 + (j - i - 1);
+        self.distances[idx].store((1. - similarity).to_bits(), Ordering::Relaxed);
+    }
+
+    /// Write the matrix and its labels.
+    #[cfg(feature = "python")]
+    pub fn write(self, sketches: &[SmallSignature]) -> Result<()> {
+        let path = &self.options.path;
+
+        let mut header = format!(
+            "{{'descr': '<f8', 'fortran_order': False, 'shape': ({},), }}",
+            self.distances.len()
+        );
+        // pad so that the data starts on a 64-byte boundary.
+        let unpadded = 6 + 2 + 2 + header.len() + 1;
+        header.push_str(&" ".repeat((64 - unpadded % 64) % 64));
+        header.push('\n');
+
+        let mut out = AtomicFile::create(path)?;
+        out.write_all(b"\x93NUMPY\x01\x00")?;
+        out.write_all(&(header.len() as u16).to_le_bytes())?;
+        out.write_all(header.as_bytes())?;
+        for d in self.distances.iter() {
+            out.write_all(&f64::from_bits(d.load(Ordering::Relaxed)).to_le_bytes())?;
+        }
+        out.commit()?;
+
+        let labels_path = format!("{}.labels.txt", path);
+        let mut labels = AtomicFile::create(&labels_path)?;
+        for sketch in sketches {
+            writeln!(labels, "{}", sketch.name)?;
+        }
+        labels.commit()?;
+
+        eprintln!(
+            "Wrote condensed distances for {} sketches to '{}', and labels to '{}'",
+            self.n, path, labels_path
+        );
+        Ok(())
+    }
+}
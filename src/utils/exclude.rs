@@ -0,0 +1,106 @@
+//! Records to drop from against collections, for `--exclude`.
+//!
+//! An exclude list is either a sourmash manifest, whose sketches are
+//! matched by md5, or a picklist (`file.csv:column:coltype`), whose listed
+//! values are dropped. Like the size filter, it is carried in the
+//! command's `RunContext` and applied by `load_collection` to every
+//! against collection it loads.
+
+use anyhow::{Context, Result};
+use camino::Utf8Path as Path;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::BufReader;
+
+use sourmash::manifest::{Manifest, Record};
+
+use super::picklist::Picklist;
+use super::{MultiCollection, ReportType, RunContext};
+
+#[derive(Debug)]
+pub enum ExcludeList {
+    Manifest { path: String, md5s: HashSet<String> },
+    Picklist { spec: String, picklist: Picklist },
+}
+
+impl ExcludeList {
+    /// Load a manifest CSV, or a picklist if `spec` is not an existing file.
+    pub fn load(spec: &str) -> Result<Self> {
+        if !Path::new(spec).is_file() {
+            let picklist = Picklist::load(spec)?;
+            return Ok(ExcludeList::Picklist {
+                spec: spec.to_string(),
+                picklist,
+            });
+        }
+
+        let file = File::open(spec).with_context(|| format!("Failed to open file: '{}'", spec))?;
+        let manifest = Manifest::from_reader(BufReader::new(file))
+            .with_context(|| format!("Failed to read manifest from: '{}'", spec))?;
+        if manifest.is_empty() {
+            bail!("no sketches listed in exclude manifest '{}'", spec);
+        }
+        let md5s: HashSet<String> = manifest.iter().map(|r| r.md5().to_string()).collect();
+        eprintln!(
+            "Loaded {} sketches to exclude from manifest '{}'",
+            md5s.len(),
+            spec
+        );
+
+        Ok(ExcludeList::Manifest {
+            path: spec.to_string(),
+            md5s,
+        })
+    }
+
+    fn excludes(&self, record: &Record) -> bool {
+        match self {
+            ExcludeList::Manifest { md5s, .. } => md5s.contains(record.md5()),
+            // listed values pass an include picklist and fail an exclude one.
+            ExcludeList::Picklist { picklist, .. } => picklist.matches(record) != picklist.exclude,
+        }
+    }
+
+    fn source(&self) -> &str {
+        match self {
+            ExcludeList::Manifest { path, .. } => path,
+            ExcludeList::Picklist { spec, .. } => spec,
+        }
+    }
+}
+
+/// Drop the records listed in the exclude list of `ctx`, if any, from an
+/// against collection.
+pub fn apply(
+    collection: MultiCollection,
+    report_type: ReportType,
+    ctx: &RunContext,
+) -> MultiCollection {
+    if !matches!(report_type, ReportType::Against) {
+        return collection;
+    }
+    let Some(exclude) = ctx.exclude_list() else {
+        return collection;
+    };
+
+    let excluded: Vec<String> = collection
+        .item_iter()
+        .filter(|(_, _, record)| exclude.excludes(record))
+        .map(|(_, _, record)| record.internal_location().to_string())
+        .collect();
+    if excluded.is_empty() {
+        return collection;
+    }
+
+    eprintln!(
+        "Excluded {} against sketches listed in '{}'",
+        excluded.len(),
+        exclude.source()
+    );
+    let reason = format!("listed in --exclude '{}'", exclude.source());
+    for location in excluded {
+        ctx.record_skipped(&location, &reason);
+    }
+
+    collection.filter_records(|record| !exclude.excludes(record))
+}
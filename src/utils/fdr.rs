@@ -0,0 +1,249 @@
+//! False discovery rate control for large screens, for `--fdr`.
+//!
+//! Each reported comparison gets the probability of seeing at least its
+//! overlap by chance, `overlap_pvalue`. Sketches hold hashes of k-mers
+//! drawn from the `alphabet^k` possible ones for their ksize and moltype,
+//! or the 2^64 possible hashes if that is fewer. Under random sequence,
+//! each of the query's hashes is one of the match's with probability
+//! `match_n_hashes * scaled / min(alphabet^k, 2^64)`, so the chance
+//! overlap is Poisson with mean
+//! `query_n_hashes * match_n_hashes * scaled / min(alphabet^k, 2^64)`.
+//! Multiplied by the number of comparisons, this is
+//! `expected_false_positives`, the number of comparisons expected to
+//! share as many hashes by chance.
+//!
+//! Benjamini-Hochberg q-values need the rank of each p-value among all of
+//! them, so they are added to the output CSV in a second pass once it is
+//! written: one pass reads the p-values, and another copies the rows with
+//! their `bh_qvalue` filled in. Comparisons that were not reported (below
+//! the threshold) are counted as tests with p = 1, which keeps the
+//! q-values conservative.
+
+use sourmash::encodings::HashFunctions;
+
+#[cfg(feature = "python")]
+use super::atomicfile::AtomicFile;
+#[cfg(feature = "python")]
+use super::finish_csv;
+#[cfg(feature = "python")]
+use super::outputmode::{OutputFormat, OutputMode};
+#[cfg(feature = "python")]
+use anyhow::{Context, Result};
+#[cfg(feature = "python")]
+use csv::{ReaderBuilder, StringRecord, WriterBuilder};
+#[cfg(feature = "python")]
+use std::fs::metadata;
+
+/// Check that q-values can be added to `output` in a second pass: it must
+/// be a new, unsharded CSV file, written in `mode`.
+#[cfg(feature = "python")]
+pub fn check_output(output: Option<&str>, mode: OutputMode) -> Result<()> {
+    // e.g. /dev/stdout, pipes, and sockets can't be read back.
+    let is_special = output.is_some_and(|path| metadata(path).is_ok_and(|m| !m.is_file()));
+    if output.is_none() || is_special {
+        bail!("--fdr needs an output file, to add q-values to once it is written");
+    }
+    if mode.append || mode.shard_size.is_some() {
+        bail!("--fdr cannot be used with --append or --output-shard-size");
+    }
+    if mode.format(output) != OutputFormat::Csv {
+        bail!("--fdr can only add q-values to CSV output");
+    }
+    Ok(())
+}
+
+/// The number of distinct k-mers that sketches of `ksize` and `moltype`
+/// can hold hashes of, capped at the 2^64 possible hashes. As in the
+/// sketches themselves, protein-type ksizes are in nucleotides.
+fn kmer_space(ksize: u32, moltype: &HashFunctions) -> f64 {
+    let (alphabet, k): (f64, u32) = match moltype {
+        HashFunctions::Murmur64Protein => (20.0, ksize / 3),
+        HashFunctions::Murmur64Dayhoff => (6.0, ksize / 3),
+        HashFunctions::Murmur64Hp => (2.0, ksize / 3),
+        _ => (4.0, ksize),
+    };
+    alphabet.powi(k as i32).min(2f64.powi(64))
+}
+
+/// The probability that a query and match sketch with `query_n_hashes`
+/// and `match_n_hashes` hashes at `scaled`, `ksize`, and `moltype` share
+/// at least `intersect_hashes` by chance.
+pub fn overlap_pvalue(
+    intersect_hashes: u64,
+    query_n_hashes: u64,
+    match_n_hashes: u64,
+    scaled: u32,
+    ksize: u32,
+    moltype: &HashFunctions,
+) -> f64 {
+    // the number of possible k-mers that are kept at this scaled.
+    let sampled_space = kmer_space(ksize, moltype) / scaled as f64;
+    let expected = query_n_hashes as f64 * match_n_hashes as f64 / sampled_space;
+    poisson_sf(intersect_hashes, expected)
+}
+
+/// P(X >= k) for X ~ Poisson(lambda).
+fn poisson_sf(k: u64, lambda: f64) -> f64 {
+    if k == 0 {
+        return 1.0;
+    }
+    if lambda <= 0.0 {
+        return 0.0;
+    }
+
+    if k as f64 <= lambda {
+        // the upper tail is large; take it from the lower tail.
+        let mut term = (-lambda).exp();
+        let mut cdf = 0.0;
+        for i in 0..k {
+            cdf += term;
+            term *= lambda / (i + 1) as f64;
+        }
+        return (1.0 - cdf).clamp(0.0, 1.0);
+    }
+
+    // sum the upper tail from k until the terms stop contributing.
+    let mut term = (k as f64 * lambda.ln() - lambda - ln_factorial(k)).exp();
+    let mut sf = 0.0;
+    let mut i = k;
+    while term > sf * f64::EPSILON {
+        sf += term;
+        i += 1;
+        term *= lambda / i as f64;
+    }
+    sf.min(1.0)
+}
+
+/// ln(k!), exactly for small k and by Stirling's series otherwise.
+fn ln_factorial(k: u64) -> f64 {
+    if k < 20 {
+        return (2..=k).map(|i| (i as f64).ln()).sum();
+    }
+    let k = k as f64;
+    k * k.ln() - k + 0.5 * (2.0 * std::f64::consts::PI * k).ln() + 1.0 / (12.0 * k)
+        - 1.0 / (360.0 * k.powi(3))
+}
+
+/// Benjamini-Hochberg q-values for `pvalues`, out of `n_tests` tests in
+/// all; tests not in `pvalues` are taken to have p = 1.
+#[cfg(feature = "python")]
+fn bh_qvalues(pvalues: &[f64], n_tests: usize) -> Vec<f64> {
+    let n_tests = n_tests.max(pvalues.len()) as f64;
+    let mut order: Vec<usize> = (0..pvalues.len()).collect();
+    order.sort_by(|&a, &b| pvalues[a].total_cmp(&pvalues[b]));
+
+    let mut qvalues = vec![1.0; pvalues.len()];
+    let mut running_min: f64 = 1.0;
+    for (rank, &idx) in order.iter().enumerate().rev() {
+        let q = pvalues[idx] * n_tests / (rank + 1) as f64;
+        running_min = running_min.min(q);
+        qvalues[idx] = running_min;
+    }
+    qvalues
+}
+
+/// Add Benjamini-Hochberg q-values to the multisearch CSV at `path`, out
+/// of `n_tests` comparisons, filling in its `bh_qvalue` column or adding
+/// one at the end.
+#[cfg(feature = "python")]
+pub fn add_qvalues(path: &str, n_tests: usize) -> Result<()> {
+    let open = || {
+        ReaderBuilder::new()
+            .from_path(path)
+            .with_context(|| format!("Failed to reopen output '{}' to add q-values", path))
+    };
+
+    // first pass: the p-values, in row order.
+    let mut reader = open()?;
+    let headers = reader.headers()?.clone();
+    let Some(p_col) = headers.iter().position(|h| h == "overlap_pvalue") else {
+        bail!("--fdr needs the 'overlap_pvalue' column in the output");
+    };
+    let mut pvalues = vec![];
+    for record in reader.records() {
+        let record = record?;
+        let p: f64 = record[p_col]
+            .parse()
+            .with_context(|| format!("invalid overlap_pvalue '{}'", &record[p_col]))?;
+        pvalues.push(p);
+    }
+    let qvalues = bh_qvalues(&pvalues, n_tests);
+
+    // second pass: copy the rows, with their q-values.
+    let q_col = headers.iter().position(|h| h == "bh_qvalue");
+    let mut headers = headers;
+    if q_col.is_none() {
+        headers.push_field("bh_qvalue");
+    }
+    let mut writer = WriterBuilder::new().from_writer(AtomicFile::create(path)?);
+    writer.write_record(&headers)?;
+    for (record, q) in open()?.records().zip(qvalues) {
+        let record = record?;
+        let q = q.to_string();
+        let row: StringRecord = match q_col {
+            Some(q_col) => record
+                .iter()
+                .enumerate()
+                .map(|(i, field)| if i == q_col { q.as_str() } else { field })
+                .collect(),
+            None => record.iter().chain(std::iter::once(q.as_str())).collect(),
+        };
+        writer.write_record(&row)?;
+    }
+    finish_csv(writer)
+        .and_then(AtomicFile::commit)
+        .with_context(|| format!("Failed to write q-values to '{}'", path))?;
+
+    eprintln!(
+        "Added Benjamini-Hochberg q-values for {} rows, out of {} comparisons.",
+        pvalues.len(),
+        n_tests.max(pvalues.len())
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_poisson_sf() {
+        assert_eq!(poisson_sf(0, 2.0), 1.0);
+        // P(X >= 1) = 1 - e^-lambda
+        assert!((poisson_sf(1, 2.0) - (1.0 - (-2.0f64).exp())).abs() < 1e-12);
+        // P(X >= 3) for lambda = 0.5
+        let expected = 1.0 - (-0.5f64).exp() * (1.0 + 0.5 + 0.125);
+        assert!((poisson_sf(3, 0.5) - expected).abs() < 1e-12);
+        // tiny tails don't round to zero until they must.
+        assert!(poisson_sf(25, 0.01) > 0.0);
+    }
+
+    #[test]
+    fn test_overlap_pvalue() {
+        // DNA k=21: 4^21 possible k-mers, 4^21 / 1000 of them kept, so
+        // lambda = 10^4 * 10^4 / (4^21 / 1000) = 0.0227373675443232.
+        let p = overlap_pvalue(1, 10_000, 10_000, 1000, 21, &HashFunctions::Murmur64Dna);
+        assert!((p - 0.022480821674083863).abs() < 1e-15);
+
+        // DNA k=51 has more k-mers than hashes, so the 2^64 hashes bound
+        // it: lambda = 10^4 * 10^4 * 1000 / 2^64.
+        let p = overlap_pvalue(1, 10_000, 10_000, 1000, 51, &HashFunctions::Murmur64Dna);
+        assert!((p - 5.421010847733843e-9).abs() < 1e-21);
+
+        // protein k=10, written as ksize 30: 20^10 possible k-mers, so
+        // lambda = 2000 * 3000 / (20^10 / 100) = 5.859375e-5.
+        let p = overlap_pvalue(3, 2000, 3000, 100, 30, &HashFunctions::Murmur64Protein);
+        assert!((p - 3.352613933927216e-14).abs() < 1e-24);
+    }
+
+    #[test]
+    #[cfg(feature = "python")]
+    fn test_bh_qvalues() {
+        let q = bh_qvalues(&[0.01, 0.04, 0.03, 0.5], 4);
+        assert_eq!(q, vec![0.04, 0.04 * 4.0 / 3.0, 0.04 * 4.0 / 3.0, 0.5]);
+
+        // unreported tests count towards the total.
+        let q = bh_qvalues(&[0.01], 10);
+        assert_eq!(q, vec![0.1]);
+    }
+}
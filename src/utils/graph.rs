@@ -0,0 +1,256 @@
+//! Export of thresholded comparison results as a similarity graph, in
+//! GraphML or DOT format, for use with Gephi/Cytoscape/graphviz.
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::sync::mpsc::Receiver;
+use std::thread::JoinHandle;
+
+use crate::utils::columns::{ColumnSelection, ResultWriter};
+use crate::utils::{batch_rows, open_stdout_or_file, Batched, MultiSearchResult, RunContext};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GraphFormat {
+    GraphML,
+    Dot,
+}
+
+impl std::str::FromStr for GraphFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "graphml" => Ok(GraphFormat::GraphML),
+            "dot" | "gv" => Ok(GraphFormat::Dot),
+            _ => bail!("unknown graph format '{}'; use 'graphml' or 'dot'", s),
+        }
+    }
+}
+
+/// Graph output requested for a comparison run.
+pub struct GraphOptions {
+    pub path: String,
+    pub format: GraphFormat,
+    pub weight_column: String,
+}
+
+impl GraphOptions {
+    /// If `format` is not given, guess it from the file extension.
+    pub fn new(path: String, format: Option<String>, weight_column: String) -> Result<Self> {
+        let format = match format {
+            Some(f) => f.parse()?,
+            None => match path.rsplit_once('.') {
+                Some((_, ext)) => ext.parse().map_err(|_| {
+                    anyhow::anyhow!(
+                        "cannot guess graph format for '{}'; please specify 'graphml' or 'dot'",
+                        path
+                    )
+                })?,
+                None => bail!(
+                    "cannot guess graph format for '{}'; please specify 'graphml' or 'dot'",
+                    path
+                ),
+            },
+        };
+
+        if !MultiSearchResult::SIMILARITY_COLUMNS.contains(&weight_column.as_str()) {
+            bail!("Invalid similarity measure: {}", weight_column);
+        }
+
+        Ok(GraphOptions {
+            path,
+            format,
+            weight_column,
+        })
+    }
+
+    /// Check that the weight column will actually be calculated.
+    pub fn check_columns(&self, estimate_ani: bool, angular_similarity: bool) -> Result<()> {
+        if self.weight_column.ends_with("_ani") && !estimate_ani {
+            bail!(
+                "graph weight '{}' requires ANI estimation; please use --ani",
+                self.weight_column
+            );
+        }
+        if self.weight_column == "angular_similarity" && !angular_similarity {
+            bail!("graph weight 'angular_similarity' requires --angular-similarity");
+        }
+        Ok(())
+    }
+}
+
+/// Undirected similarity graph, keyed on md5 so that sketches with the
+/// same name are kept distinct.
+pub struct SimilarityGraph {
+    pub options: GraphOptions,
+    nodes: Vec<(String, String)>,
+    node_idx: HashMap<String, usize>,
+    /// (lower node, higher node) -> weight; BTreeMap for stable output.
+    edges: BTreeMap<(usize, usize), f64>,
+}
+
+impl SimilarityGraph {
+    pub fn new(options: GraphOptions) -> Self {
+        SimilarityGraph {
+            options,
+            nodes: vec![],
+            node_idx: HashMap::new(),
+            edges: BTreeMap::new(),
+        }
+    }
+
+    fn node(&mut self, name: &str, md5: &str) -> usize {
+        if let Some(idx) = self.node_idx.get(md5) {
+            return *idx;
+        }
+        let idx = self.nodes.len();
+        self.nodes.push((name.to_string(), md5.to_string()));
+        self.node_idx.insert(md5.to_string(), idx);
+        idx
+    }
+
+    /// Add a result; self-matches add a node but no edge. Pairs seen in
+    /// both directions keep the higher weight.
+    pub fn add(&mut self, result: &MultiSearchResult) {
+        let a = self.node(&result.query_name, &result.query_md5);
+        let b = self.node(&result.match_name, &result.match_md5);
+        if a == b {
+            return;
+        }
+
+        // the weight column is checked up front, so this won't fail.
+        let Ok(weight) = result.similarity(&self.options.weight_column) else {
+            return;
+        };
+        let edge = self.edges.entry((a.min(b), a.max(b))).or_insert(weight);
+        *edge = edge.max(weight);
+    }
+
+    pub fn n_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn n_edges(&self) -> usize {
+        self.edges.len()
+    }
+
+    pub fn write_dot(&self, out: &mut dyn Write) -> Result<()> {
+        writeln!(out, "graph similarity {{")?;
+        for (i, (name, md5)) in self.nodes.iter().enumerate() {
+            writeln!(
+                out,
+                "  n{} [label=\"{}\", md5=\"{}\"];",
+                i,
+                escape_dot(name),
+                md5
+            )?;
+        }
+        for ((a, b), weight) in &self.edges {
+            writeln!(out, "  n{} -- n{} [weight={}];", a, b, weight)?;
+        }
+        writeln!(out, "}}")?;
+        Ok(())
+    }
+
+    pub fn write_graphml(&self, out: &mut dyn Write) -> Result<()> {
+        writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            out,
+            r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+        )?;
+        writeln!(
+            out,
+            r#"  <key id="name" for="node" attr.name="name" attr.type="string"/>"#
+        )?;
+        writeln!(
+            out,
+            r#"  <key id="md5" for="node" attr.name="md5" attr.type="string"/>"#
+        )?;
+        writeln!(
+            out,
+            r#"  <key id="weight" for="edge" attr.name="{}" attr.type="double"/>"#,
+            self.options.weight_column
+        )?;
+        writeln!(out, r#"  <graph id="similarity" edgedefault="undirected">"#)?;
+        for (i, (name, md5)) in self.nodes.iter().enumerate() {
+            writeln!(
+                out,
+                r#"    <node id="n{}"><data key="name">{}</data><data key="md5">{}</data></node>"#,
+                i,
+                escape_xml(name),
+                md5
+            )?;
+        }
+        for ((a, b), weight) in &self.edges {
+            writeln!(
+                out,
+                r#"    <edge source="n{}" target="n{}"><data key="weight">{}</data></edge>"#,
+                a, b, weight
+            )?;
+        }
+        writeln!(out, "  </graph>")?;
+        writeln!(out, "</graphml>")?;
+        Ok(())
+    }
+
+    pub fn write(&self) -> Result<()> {
+        let mut out = open_stdout_or_file(Some(self.options.path.clone()));
+        match self.options.format {
+            GraphFormat::GraphML => self.write_graphml(&mut out)?,
+            GraphFormat::Dot => self.write_dot(&mut out)?,
+        }
+        out.commit()?;
+        eprintln!(
+            "Wrote graph with {} nodes and {} edges to '{}'",
+            self.n_nodes(),
+            self.n_edges(),
+            self.options.path
+        );
+        Ok(())
+    }
+}
+
+fn escape_dot(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Like `csvwriter_thread`, but also builds the similarity graph from
+/// results as they are written. Returns the graph when the channel closes.
+pub fn graph_csvwriter_thread(
+    recv: Receiver<MultiSearchResult>,
+    output: Option<String>,
+    columns: ColumnSelection,
+    mut graph: Option<SimilarityGraph>,
+    ctx: &RunContext,
+) -> Result<JoinHandle<Option<SimilarityGraph>>> {
+    let flush = ctx.flush_policy(output.as_deref());
+    let mut writer = ResultWriter::open(output, columns, ctx)?;
+    Ok(std::thread::spawn(move || {
+        for item in batch_rows(recv, flush) {
+            let res = match item {
+                Batched::Row(res) => res,
+                Batched::Flush => {
+                    writer.flush_or_warn();
+                    continue;
+                }
+            };
+            if let Some(g) = graph.as_mut() {
+                g.add(&res);
+            }
+            if let Err(e) = writer.write(&res) {
+                eprintln!("Error writing item: {:?}", e);
+            }
+        }
+        writer
+            .finish::<MultiSearchResult>()
+            .expect("Failed to flush writer.");
+        graph
+    }))
+}
@@ -0,0 +1,90 @@
+//! The hashes shared by each query and match, for `--output-matched-hashes`.
+//!
+//! sourmash sketches don't record where in a genome each hash came from,
+//! so this records the shared hash values themselves, e.g. for plotting
+//! which parts of a reference are covered by a sample. The output is a
+//! compact binary file: `MAGIC`, then the scaled of the search, then one
+//! block per match of the query md5 and match md5 (32 ASCII
+//! bytes each), the number of shared hashes, and the hashes, sorted.
+//! Numbers are little-endian u64s. Blocks are in no particular order.
+
+use std::io::Write;
+use std::sync::Mutex;
+
+use super::atomicfile::AtomicFile;
+use super::multicollection::SmallSignature;
+
+#[cfg(feature = "python")]
+use anyhow::Result;
+
+#[cfg(feature = "python")]
+const MAGIC: &[u8; 8] = b"BWMATCH1";
+
+pub struct MatchedHashesWriter {
+    out: Mutex<AtomicFile>,
+    #[cfg(feature = "python")]
+    path: String,
+    n_written: Mutex<usize>,
+    /// the first write error; reported by `finish`, since `add` is called
+    /// from inside the search loop.
+    error: Mutex<Option<std::io::Error>>,
+}
+
+impl MatchedHashesWriter {
+    #[cfg(feature = "python")]
+    pub fn create(path: &str, scaled: u32) -> Result<Self> {
+        let mut out = AtomicFile::create(path)?;
+        out.write_all(MAGIC)?;
+        out.write_all(&(scaled as u64).to_le_bytes())?;
+        Ok(MatchedHashesWriter {
+            out: Mutex::new(out),
+            path: path.to_string(),
+            n_written: Mutex::new(0),
+            error: Mutex::new(None),
+        })
+    }
+
+    /// Write the hashes `query` shares with a match whose (sorted) hashes
+    /// are `against_mins`, at the query's scaled or finer. Matches with
+    /// nothing shared are skipped.
+    pub fn add(&self, query: &SmallSignature, against_md5: &str, against_mins: &[u64]) {
+        let shared: Vec<u64> = query
+            .minhash
+            .iter_mins()
+            .filter(|h| against_mins.binary_search(h).is_ok())
+            .copied()
+            .collect();
+        if shared.is_empty() {
+            return;
+        }
+
+        let mut block = Vec::with_capacity(72 + 8 * shared.len());
+        block.extend_from_slice(query.md5sum.as_bytes());
+        block.extend_from_slice(against_md5.as_bytes());
+        block.extend_from_slice(&(shared.len() as u64).to_le_bytes());
+        for hash in shared {
+            block.extend_from_slice(&hash.to_le_bytes());
+        }
+
+        match self.out.lock().unwrap().write_all(&block) {
+            Ok(()) => *self.n_written.lock().unwrap() += 1,
+            Err(e) => {
+                self.error.lock().unwrap().get_or_insert(e);
+            }
+        }
+    }
+
+    #[cfg(feature = "python")]
+    pub fn finish(self) -> Result<()> {
+        if let Some(e) = self.error.into_inner().unwrap() {
+            bail!("cannot write matched hashes to '{}': {}", self.path, e);
+        }
+        let n_written = self.n_written.into_inner().unwrap();
+        self.out.into_inner().unwrap().commit()?;
+        eprintln!(
+            "Wrote matched hashes for {} matches to '{}'",
+            n_written, self.path
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+//! Per-match metadata columns, for `--match-metadata`.
+//!
+//! A metadata CSV maps against sketch names (in its first column) to
+//! attributes such as biome, study, or country; its other columns are
+//! added to each output row for the row's match, so that results don't
+//! need to be joined to sample metadata afterwards. As for lineages, a
+//! match is looked up by its full name, then by its first space-separated
+//! word, ignoring versions.
+
+use anyhow::{Context, Result};
+use csv::Reader;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::taxonomy::strip_version;
+
+#[derive(Debug)]
+pub struct MatchMetadata {
+    path: String,
+    columns: Vec<String>,
+    values: HashMap<String, Vec<String>>,
+    n_missing: AtomicUsize,
+}
+
+impl MatchMetadata {
+    pub fn load(path: &str) -> Result<Self> {
+        let mut rdr = Reader::from_path(path)
+            .with_context(|| format!("Failed to open metadata file: '{}'", path))?;
+        let headers = rdr.headers()?.clone();
+        if headers.len() < 2 {
+            bail!(
+                "metadata file '{}' needs a name column and at least one more column",
+                path
+            );
+        }
+        let columns: Vec<String> = headers.iter().skip(1).map(String::from).collect();
+
+        let mut values = HashMap::new();
+        for record in rdr.records() {
+            let record = record?;
+            let name = record.get(0).unwrap_or_default().to_string();
+            let row: Vec<String> = (1..headers.len())
+                .map(|col| record.get(col).unwrap_or_default().to_string())
+                .collect();
+            let stripped = strip_version(&name).to_string();
+            if stripped != name {
+                values.entry(stripped).or_insert_with(|| row.clone());
+            }
+            values.insert(name, row);
+        }
+        eprintln!(
+            "Loaded {} metadata columns for {} names from '{}'",
+            columns.len(),
+            values.len(),
+            path
+        );
+
+        Ok(MatchMetadata {
+            path: path.to_string(),
+            columns,
+            values,
+            n_missing: AtomicUsize::new(0),
+        })
+    }
+
+    /// The names of the metadata columns, in order.
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// The metadata values for `match_name`, or empty values if it isn't
+    /// listed.
+    pub fn values(&self, match_name: &str) -> Vec<String> {
+        let ident = match_name.split(' ').next().unwrap_or_default();
+        let found = self
+            .values
+            .get(match_name)
+            .or_else(|| self.values.get(ident))
+            .or_else(|| self.values.get(strip_version(ident)));
+        match found {
+            Some(values) => values.clone(),
+            None => {
+                self.n_missing.fetch_add(1, Ordering::SeqCst);
+                vec![String::new(); self.columns.len()]
+            }
+        }
+    }
+
+    /// Warn about rows whose match had no metadata.
+    pub fn report_missing(&self) {
+        let n_missing = self.n_missing.load(Ordering::SeqCst);
+        if n_missing > 0 {
+            eprintln!(
+                "WARNING: {} rows have a match not listed in metadata file '{}'",
+                n_missing, self.path
+            );
+        }
+    }
+}
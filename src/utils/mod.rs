@@ -3,105 +3,311 @@ use rayon::prelude::*;
 
 use sourmash::encodings::HashFunctions;
 use sourmash::selection::Select;
-use sourmash::ScaledType;
 
-use anyhow::{anyhow, Result};
-use camino::Utf8Path as Path;
+use anyhow::{anyhow, Context, Result};
 use camino::Utf8PathBuf as PathBuf;
 use csv::Writer;
 use glob::glob;
 use serde::{Deserialize, Serialize};
 // use rust_decimal::{MathematicalOps, Decimal};
+use std::borrow::Cow;
 use std::cmp::{Ordering, PartialOrd};
 use std::collections::BinaryHeap;
-use std::fs::{create_dir_all, metadata, File};
-use std::io::{BufWriter, Write};
-use std::panic;
+use std::fs::metadata;
+use std::io::Write;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixStream;
 use std::sync::atomic;
 use std::sync::atomic::AtomicUsize;
-use std::sync::mpsc::{Receiver, SyncSender};
-use std::thread::JoinHandle;
-use zip::write::{FileOptions, ZipWriter};
-use zip::CompressionMethod;
+use std::sync::mpsc::SyncSender;
+use std::sync::Arc;
 
 use sourmash::ani_utils::{ani_ci_from_containment, ani_from_containment};
 use sourmash::selection::Selection;
 use sourmash::signature::SigsTrait;
 use sourmash::sketch::minhash::KmerMinHash;
 use stats::{median, stddev};
-use std::collections::{HashMap, HashSet};
-use std::hash::{Hash, Hasher};
-
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::errors::BranchwaterError;
+
+#[cfg(feature = "python")]
+pub mod abundances;
+#[cfg(feature = "python")]
+pub mod aliases;
+#[cfg(feature = "python")]
+pub mod arrowstream;
+pub mod atomicfile;
+#[cfg(feature = "python")]
+pub mod columns;
+pub mod coverage;
+pub mod dedup;
+pub mod distmatrix;
+pub mod exclude;
+pub mod fdr;
+pub mod matchedhashes;
+#[cfg(feature = "python")]
+pub mod metadata;
 pub mod multicollection;
+#[cfg(feature = "python")]
+pub mod namerewrite;
+pub mod outputmode;
+pub mod pathreport;
+pub mod picklist;
+pub mod profile;
+pub mod querygroups;
+pub mod querysketch;
+pub mod runcontext;
+pub mod sizefilter;
+#[cfg(feature = "python")]
+pub mod sourmashcompat;
+#[cfg(feature = "python")]
+pub mod sqlitetable;
+pub mod status;
+pub mod stoplist;
+pub mod verify;
 pub use multicollection::{MultiCollection, SmallSignature};
+pub use runcontext::RunContext;
 
 pub mod buildutils;
-use buildutils::{BuildCollection, BuildManifest};
 
-/// Structure to hold overlap information from comparisons.
-pub struct PrefetchResult {
-    pub name: String,
-    pub md5sum: String,
+#[cfg(feature = "python")]
+pub mod taxonomy;
+
+#[cfg(feature = "python")]
+pub mod graph;
+use atomicfile::{AtomicFile, Output};
+#[cfg(feature = "python")]
+use buildutils::{BuildCollection, BuildManifest, DuplicateNames, DuplicatePolicy};
+#[cfg(feature = "python")]
+use camino::Utf8Path as Path;
+use profile::Stage;
+#[cfg(feature = "python")]
+use sourmash::index::revindex::{RevIndex, RevIndexOps};
+#[cfg(feature = "python")]
+use std::collections::BTreeMap;
+#[cfg(feature = "python")]
+use std::fs::create_dir_all;
+#[cfg(feature = "python")]
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+#[cfg(feature = "python")]
+use std::thread::JoinHandle;
+use std::time::Duration;
+#[cfg(feature = "python")]
+use std::time::Instant;
+#[cfg(feature = "python")]
+use zip::{
+    write::{FileOptions, ZipWriter},
+    CompressionMethod,
+};
+
+/// Structure to hold overlap information from comparisons. The sketch is
+/// borrowed when it is already in memory (e.g. in a `SmallSignature`),
+/// so that matching many queries doesn't copy it for each one.
+pub struct PrefetchResult<'a> {
+    pub name: Arc<str>,
+    pub md5sum: Arc<str>,
     pub location: String,
-    pub minhash: KmerMinHash,
+    pub minhash: Cow<'a, KmerMinHash>,
     pub overlap: u64,
 }
 
-impl Ord for PrefetchResult {
-    fn cmp(&self, other: &PrefetchResult) -> Ordering {
+impl Ord for PrefetchResult<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.overlap.cmp(&other.overlap)
+    }
+}
+
+impl PartialOrd for PrefetchResult<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for PrefetchResult<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.overlap == other.overlap
+    }
+}
+
+impl Eq for PrefetchResult<'_> {}
+
+/// A match still in play during gather: an index into the prefetch
+/// matches, its current overlap with the query, and the (sorted) hashes it
+/// shared with the query when first checked. Hashes are only ever removed
+/// from the query, so the overlap is kept up to date by subtracting the
+/// hashes removed since, and `shared` itself is never rewritten.
+struct GatherCandidate {
+    idx: usize,
+    overlap: u64,
+    shared: Option<Vec<u64>>,
+}
+
+impl Ord for GatherCandidate {
+    fn cmp(&self, other: &Self) -> Ordering {
         self.overlap.cmp(&other.overlap)
     }
 }
 
-impl PartialOrd for PrefetchResult {
+impl PartialOrd for GatherCandidate {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
 
-impl PartialEq for PrefetchResult {
+impl PartialEq for GatherCandidate {
     fn eq(&self, other: &Self) -> bool {
         self.overlap == other.overlap
     }
 }
 
-impl Eq for PrefetchResult {}
+impl Eq for GatherCandidate {}
 
-/// Find sketches in 'sketchlist' that overlap with 'query' above
-/// specified threshold.
+/// Below this many hashes, gather does its per-sketch work serially;
+/// above it, a single deep query is split across threads too.
+const PAR_MIN_HASHES: usize = 10_000;
+
+/// The hashes in `mh` that are also in `query_mins` (sorted), in order.
+fn shared_hashes(mh: &KmerMinHash, query_mins: &[u64]) -> Vec<u64> {
+    let in_query = |hash: &u64| query_mins.binary_search(hash).is_ok();
+    if mh.size() < PAR_MIN_HASHES {
+        mh.iter_mins().copied().filter(in_query).collect()
+    } else {
+        mh.mins().into_par_iter().filter(in_query).collect()
+    }
+}
+
+/// Remove `removed` (sorted) from `mh`. Unlike `remove_from`, which shifts
+/// the whole sketch once per removed hash, this is a single parallel pass.
+pub(crate) fn remove_hashes(mh: &KmerMinHash, removed: &[u64]) -> Result<KmerMinHash> {
+    let kept: Vec<(u64, u64)> = mh
+        .to_vec_abunds()
+        .into_par_iter()
+        .with_min_len(PAR_MIN_HASHES)
+        .filter(|(hash, _)| removed.binary_search(hash).is_err())
+        .collect();
 
-pub fn prefetch(
+    let mut new_mh = KmerMinHash::new(
+        mh.scaled(),
+        mh.ksize() as u32,
+        mh.hash_function(),
+        mh.seed(),
+        mh.track_abundance(),
+        mh.num(),
+    );
+    new_mh.add_many_with_abund(&kept)?;
+    Ok(new_mh)
+}
+
+/// Update the overlaps between the (reduced) query and the remaining
+/// candidates after `removed` (sorted) was taken out of the query, dropping
+/// those now below the threshold. Candidates with cached shared hashes only
+/// look up the removed hashes; the others are compared against the whole
+/// query the first time through.
+fn update_candidates(
     query_mh: &KmerMinHash,
-    sketchlist: BinaryHeap<PrefetchResult>,
+    matches: &[PrefetchResult],
+    candidates: BinaryHeap<GatherCandidate>,
+    removed: &[u64],
     threshold_hashes: u64,
-) -> BinaryHeap<PrefetchResult> {
-    sketchlist
+) -> BinaryHeap<GatherCandidate> {
+    let query_mins = query_mh.mins();
+
+    candidates
         .into_par_iter()
-        .filter_map(|result| {
-            let mut mm = None;
-            let searchsig = &result.minhash;
-            let overlap = searchsig.count_common(query_mh, false);
-            if let Ok(overlap) = overlap {
-                if overlap > 0 && overlap >= threshold_hashes {
-                    let result = PrefetchResult { overlap, ..result };
-                    mm = Some(result);
+        .filter_map(|candidate| {
+            let match_mh = &matches[candidate.idx].minhash;
+            // like count_common, sketches at a different scaled are not compared.
+            if match_mh.scaled() != query_mh.scaled() {
+                return None;
+            }
+            let (overlap, shared) = match candidate.shared {
+                Some(shared) => {
+                    let lost = removed
+                        .par_iter()
+                        .with_min_len(PAR_MIN_HASHES)
+                        .filter(|hash| shared.binary_search(hash).is_ok())
+                        .count() as u64;
+                    (candidate.overlap - lost, shared)
+                }
+                None => {
+                    let shared = shared_hashes(match_mh, &query_mins);
+                    (shared.len() as u64, shared)
                 }
+            };
+            if overlap > 0 && overlap >= threshold_hashes {
+                Some(GatherCandidate {
+                    idx: candidate.idx,
+                    overlap,
+                    shared: Some(shared),
+                })
+            } else {
+                None
             }
-            mm
         })
         .collect()
 }
 
-/// Write list of prefetch matches.
-pub fn write_prefetch(
-    query_filename: String,
-    query_name: String,
-    query_md5: String,
+#[cfg(feature = "python")]
+const PREFETCH_HEADER: [&str; 7] = [
+    "query_filename",
+    "query_name",
+    "query_md5",
+    "match_name",
+    "match_md5",
+    "intersect_hashes",
+    "intersect_bp",
+];
+
+/// The query of a set of prefetch matches.
+#[cfg(feature = "python")]
+pub struct PrefetchQuery<'a> {
+    pub filename: &'a str,
+    pub name: &'a str,
+    pub md5: &'a str,
+    pub minhash: &'a KmerMinHash,
+}
+
+/// One prefetch match, sharing `overlap` hashes with the query at `scaled`.
+#[cfg(feature = "python")]
+pub struct PrefetchMatch<'a> {
+    pub filename: &'a str,
+    pub name: &'a str,
+    pub md5: &'a str,
+    pub n_hashes: u64,
+    pub overlap: u64,
+    pub scaled: u32,
+}
+
+/// A CSV writer for prefetch output, with the columns of `sourmash
+/// prefetch` under `--sourmash-compat`.
+#[cfg(feature = "python")]
+pub struct PrefetchWriter {
+    writer: Writer<Box<dyn Output>>,
+    sourmash_compat: bool,
+}
+
+#[cfg(feature = "python")]
+impl PrefetchWriter {
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+
+    /// Flush and commit the output.
+    pub fn finish(self) -> Result<()> {
+        finish_csv(self.writer)?.commit()
+    }
+}
+
+/// Open a CSV writer for prefetch output, writing to stdout if no path is
+/// given, with the columns of `sourmash prefetch` if `sourmash_compat`.
+#[cfg(feature = "python")]
+pub fn prefetch_writer(
     prefetch_output: Option<String>,
-    matchlist: &BinaryHeap<PrefetchResult>,
-) -> Result<()> {
+    sourmash_compat: bool,
+) -> Result<PrefetchWriter> {
     // Define the writer to stdout by default
-    let mut writer: Box<dyn Write> = Box::new(std::io::stdout());
+    let mut writer: Box<dyn Output> = Box::new(std::io::stdout());
 
     if let Some(output_path) = &prefetch_output {
         // Account for potential missing dir in output path
@@ -112,28 +318,173 @@ pub fn write_prefetch(
             create_dir_all(dir)?;
         }
 
-        let file = File::create(output_path)?;
-        writer = Box::new(BufWriter::new(file));
+        writer = open_output(output_path)?;
     }
 
-    writeln!(
-        &mut writer,
-        "query_filename,query_name,query_md5,match_name,match_md5,intersect_bp"
-    )
-    .ok();
+    Ok(PrefetchWriter {
+        writer: Writer::from_writer(writer),
+        sourmash_compat,
+    })
+}
 
+/// Write the prefetch matches for one query. Returns the number of rows written.
+#[cfg(feature = "python")]
+pub fn write_prefetch_rows(
+    writer: &mut PrefetchWriter,
+    query: &PrefetchQuery,
+    matchlist: &BinaryHeap<PrefetchResult<'_>>,
+) -> Result<usize> {
     for m in matchlist.iter() {
-        writeln!(
-            &mut writer,
-            "{},\"{}\",{},\"{}\",{},{}",
-            query_filename, query_name, query_md5, m.name, m.md5sum, m.overlap
-        )
-        .ok();
+        let prefetch_match = PrefetchMatch {
+            filename: &m.location,
+            name: &m.name,
+            md5: &m.md5sum,
+            n_hashes: m.minhash.size() as u64,
+            overlap: m.overlap,
+            scaled: m.minhash.scaled(),
+        };
+        write_prefetch_row(writer, query, &prefetch_match)?;
+    }
+    Ok(matchlist.len())
+}
+
+/// Write one prefetch match.
+#[cfg(feature = "python")]
+pub fn write_prefetch_row(
+    writer: &mut PrefetchWriter,
+    query: &PrefetchQuery,
+    m: &PrefetchMatch,
+) -> Result<()> {
+    let intersect_bp = m.overlap * m.scaled as u64;
+    if !writer.sourmash_compat {
+        writer.writer.serialize(PrefetchCSVResult {
+            query_filename: query.filename,
+            query_name: query.name,
+            query_md5: query.md5,
+            match_name: m.name,
+            match_md5: m.md5,
+            intersect_hashes: m.overlap,
+            intersect_bp,
+        })?;
+        return Ok(());
+    }
+
+    let query_n_hashes = query.minhash.size() as u64;
+    let f_query_match = m.overlap as f64 / query_n_hashes as f64;
+    let f_match_query = m.overlap as f64 / m.n_hashes as f64;
+    let union = query_n_hashes + m.n_hashes - m.overlap;
+    let ksize = query.minhash.ksize() as f64;
+    let query_containment_ani = ani_from_containment(f_query_match, ksize);
+    let match_containment_ani = ani_from_containment(f_match_query, ksize);
+    let mut moltype = query.minhash.hash_function().to_string();
+    if moltype.to_lowercase() == "dna" {
+        moltype = moltype.to_uppercase();
     }
 
+    writer.writer.serialize(SourmashPrefetchCSVResult {
+        intersect_bp,
+        jaccard: m.overlap as f64 / union as f64,
+        max_containment: f_query_match.max(f_match_query),
+        f_query_match,
+        f_match_query,
+        match_filename: m.filename,
+        match_name: m.name,
+        match_md5: m.md5,
+        match_bp: m.n_hashes * m.scaled as u64,
+        query_filename: query.filename,
+        query_name: query.name,
+        query_md5: query.md5,
+        query_bp: query_n_hashes * m.scaled as u64,
+        ksize: query.minhash.ksize() as u16,
+        moltype,
+        scaled: m.scaled,
+        query_n_hashes,
+        query_abundance: query.minhash.track_abundance(),
+        query_containment_ani,
+        match_containment_ani,
+        average_containment_ani: (query_containment_ani + match_containment_ani) / 2.,
+        max_containment_ani: query_containment_ani.max(match_containment_ani),
+        potential_false_negative: false,
+    })?;
+    Ok(())
+}
+
+/// Write the prefetch header; used when there are no matches to write.
+#[cfg(feature = "python")]
+pub fn write_prefetch_header(writer: &mut PrefetchWriter) -> Result<()> {
+    if writer.sourmash_compat {
+        writer
+            .writer
+            .write_record(sourmashcompat::PREFETCH_COLUMNS)?;
+    } else {
+        writer.writer.write_record(PREFETCH_HEADER)?;
+    }
     Ok(())
 }
 
+/// Write list of prefetch matches.
+#[cfg(feature = "python")]
+pub fn write_prefetch(
+    query: &PrefetchQuery,
+    prefetch_output: Option<String>,
+    matchlist: &BinaryHeap<PrefetchResult<'_>>,
+    sourmash_compat: bool,
+) -> Result<()> {
+    let mut writer = prefetch_writer(prefetch_output, sourmash_compat)?;
+
+    write_prefetch_rows(&mut writer, query, matchlist)?;
+
+    // make sure the header gets written even if there are no matches.
+    if matchlist.is_empty() {
+        write_prefetch_header(&mut writer)?;
+    }
+    writer.finish()
+}
+
+/// A single row of prefetch CSV output.
+#[cfg(feature = "python")]
+#[derive(Serialize)]
+struct PrefetchCSVResult<'a> {
+    query_filename: &'a str,
+    query_name: &'a str,
+    query_md5: &'a str,
+    match_name: &'a str,
+    match_md5: &'a str,
+    intersect_hashes: u64,
+    intersect_bp: u64,
+}
+
+/// A single row of prefetch CSV output, with the columns of `sourmash
+/// prefetch`. branchwater doesn't estimate whether an ANI is a potential
+/// false negative, so that is always false.
+#[cfg(feature = "python")]
+#[derive(Serialize)]
+struct SourmashPrefetchCSVResult<'a> {
+    intersect_bp: u64,
+    jaccard: f64,
+    max_containment: f64,
+    f_query_match: f64,
+    f_match_query: f64,
+    match_filename: &'a str,
+    match_name: &'a str,
+    match_md5: &'a str,
+    match_bp: u64,
+    query_filename: &'a str,
+    query_name: &'a str,
+    query_md5: &'a str,
+    query_bp: u64,
+    ksize: u16,
+    moltype: String,
+    scaled: u32,
+    query_n_hashes: u64,
+    query_abundance: bool,
+    query_containment_ani: f64,
+    match_containment_ani: f64,
+    average_containment_ani: f64,
+    max_containment_ani: f64,
+    potential_false_negative: bool,
+}
+
 pub struct FastaData {
     pub name: String,
     pub paths: Vec<PathBuf>,
@@ -433,19 +784,22 @@ fn process_prefix_csv(
 
 /// Load a collection of sketches from a file, filtering to keep only
 /// those with a minimum overlap.
-
+#[cfg(feature = "python")]
 pub fn load_sketches_above_threshold(
     against_collection: MultiCollection,
     query: &KmerMinHash,
     threshold_hashes: u64,
-) -> Result<(BinaryHeap<PrefetchResult>, usize, usize)> {
+    ctx: &RunContext,
+) -> Result<(BinaryHeap<PrefetchResult<'static>>, usize, usize)> {
+    // reading the sketches dominates, so this is all counted as loading.
+    let _timer = ctx.timer(Stage::Loading);
     let skipped_paths = AtomicUsize::new(0);
     let failed_paths = AtomicUsize::new(0);
 
     if against_collection.contains_revindex {
         eprintln!("WARNING: loading all sketches from a RocksDB into memory!");
     }
-    let matchlist: BinaryHeap<PrefetchResult> = against_collection
+    let matchlist: BinaryHeap<PrefetchResult<'static>> = against_collection
         .par_iter()
         .filter_map(|(coll, _idx, against_record)| {
             let mut results = Vec::new();
@@ -463,9 +817,9 @@ pub fn load_sketches_above_threshold(
                 if let Ok(overlap) = against_mh_ds.count_common(query, false) {
                     if overlap > 0 && overlap >= threshold_hashes {
                         let result = PrefetchResult {
-                            name: against_record.name().to_string(),
-                            md5sum: against_md5,
-                            minhash: against_mh_ds,
+                            name: against_record.name().as_str().into(),
+                            md5sum: against_md5.into(),
+                            minhash: Cow::Owned(against_mh_ds),
                             location: against_record.internal_location().to_string(),
                             overlap,
                         };
@@ -476,6 +830,7 @@ pub fn load_sketches_above_threshold(
                         "WARNING: no compatible sketches in path '{}'",
                         against_filename
                     );
+                    ctx.record_skipped(&against_filename, "no compatible sketches");
                     let _i = skipped_paths.fetch_add(1, atomic::Ordering::SeqCst);
                 }
             } else {
@@ -484,6 +839,10 @@ pub fn load_sketches_above_threshold(
                     "WARNING: could not load sketches for record '{}'",
                     against_record.internal_location()
                 );
+                ctx.record_skipped(
+                    against_record.internal_location().as_str(),
+                    "could not load sketches",
+                );
                 let _i = skipped_paths.fetch_add(1, atomic::Ordering::SeqCst);
             }
             if results.is_empty() {
@@ -501,6 +860,7 @@ pub fn load_sketches_above_threshold(
     Ok((matchlist, skipped_paths, failed_paths))
 }
 
+#[derive(Clone, Copy)]
 pub enum ReportType {
     Query,
     Against,
@@ -518,24 +878,166 @@ impl std::fmt::Display for ReportType {
     }
 }
 
-/// Load a multi collection from a path - this is the new top-level load function.
+/// Read a CSV with `group` and `name` columns, mapping sketch names to
+/// the group(s) they belong to.
+#[cfg(feature = "python")]
+pub fn load_groups(path: &str) -> Result<HashMap<String, Vec<String>>> {
+    let mut rdr = csv::Reader::from_path(path)
+        .map_err(|e| anyhow!("Failed to open groups file '{}': {}", path, e))?;
+    let headers = rdr.headers()?.clone();
+    let (Some(group_col), Some(name_col)) = (
+        headers.iter().position(|h| h == "group"),
+        headers.iter().position(|h| h == "name"),
+    ) else {
+        bail!(
+            "groups file '{}' must have 'group' and 'name' columns",
+            path
+        );
+    };
 
+    let mut groups: HashMap<String, Vec<String>> = HashMap::new();
+    for record in rdr.records() {
+        let record = record?;
+        let group = record.get(group_col).unwrap_or_default();
+        let name = record.get(name_col).unwrap_or_default();
+        groups
+            .entry(name.to_string())
+            .or_default()
+            .push(group.to_string());
+    }
+    eprintln!(
+        "Loaded {} sketch names from groups file '{}'",
+        groups.len(),
+        path
+    );
+    Ok(groups)
+}
+
+/// Assign sketches to groups; a sketch is matched on its full name, or
+/// else its first word. Returns group -> sketch indices, along with the
+/// number of sketches that are not in any group.
+#[cfg(feature = "python")]
+pub fn group_sketches(
+    sketches: &[SmallSignature],
+    groups: &HashMap<String, Vec<String>>,
+) -> (BTreeMap<String, Vec<usize>>, usize) {
+    let mut grouped: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    let mut n_ungrouped = 0;
+    for (idx, sketch) in sketches.iter().enumerate() {
+        let ident = sketch.name.split(' ').next().unwrap_or_default();
+        match groups.get(&*sketch.name).or_else(|| groups.get(ident)) {
+            Some(names) => {
+                for group in names {
+                    grouped.entry(group.clone()).or_default().push(idx);
+                }
+            }
+            None => n_ungrouped += 1,
+        }
+    }
+    (grouped, n_ungrouped)
+}
+
+/// Load all sketches into memory, downsampled to the selection's scaled,
+/// or else to the largest scaled in the collection. Returns the sketches
+/// and the scaled used.
+#[cfg(feature = "python")]
+pub fn load_sketches_at_common_scaled(
+    path: &String,
+    selection: &Selection,
+    allow_failed_sigpaths: bool,
+) -> Result<(Vec<SmallSignature>, u32)> {
+    let collection = load_collection(
+        path,
+        selection,
+        ReportType::General,
+        allow_failed_sigpaths,
+        &RunContext::default(),
+    )?;
+
+    let common_scaled = match selection.scaled() {
+        Some(s) => s,
+        None => {
+            let s = *collection.max_scaled().expect("no records!?");
+            eprintln!("Setting scaled={} based on max scaled in collection", s);
+            s
+        }
+    };
+
+    let sketches = collection
+        .load_sketches()?
+        .into_iter()
+        .map(|mut sketch| {
+            if sketch.minhash.scaled() != common_scaled {
+                sketch.minhash = sketch.minhash.downsample_scaled(common_scaled)?;
+            }
+            Ok(sketch)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok((sketches, common_scaled))
+}
+
+/// Load a collection once for each of several ksizes, and combine them.
+/// Each ksize must match at least one sketch.
+#[cfg(feature = "python")]
+pub fn load_collection_ksizes(
+    siglist: &String,
+    ksizes: &[u8],
+    scaled: Option<u32>,
+    moltype: &str,
+    report_type: ReportType,
+    allow_failed: bool,
+) -> Result<MultiCollection> {
+    if ksizes.is_empty() {
+        bail!("Please specify at least one ksize.");
+    }
+
+    let collections = ksizes
+        .iter()
+        .map(|ksize| {
+            let selection = build_selection(*ksize, scaled, moltype)?;
+            load_collection(
+                siglist,
+                &selection,
+                report_type,
+                allow_failed,
+                &RunContext::default(),
+            )
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(MultiCollection::from(collections))
+}
+
+/// Load a multi collection from a path - this is the new top-level load function.
+/// Loading and selection time is added to the profile in `ctx`, if any.
 pub fn load_collection(
     siglist: &String,
     selection: &Selection,
     report_type: ReportType,
     allow_failed: bool,
+    ctx: &RunContext,
 ) -> Result<MultiCollection> {
+    let _timer = ctx.timer(Stage::Loading);
     let sigpath = PathBuf::from(siglist);
 
     if !sigpath.exists() {
-        bail!("No such file or directory: '{}'", &sigpath);
+        bail!(BranchwaterError::MissingFile(format!(
+            "No such file or directory: '{}'",
+            &sigpath
+        )));
+    }
+
+    // sequence files given as queries are sketched with the command's parameters.
+    if matches!(report_type, ReportType::Query) && querysketch::is_sequence_input(&sigpath) {
+        let coll = querysketch::sketch_queries(&sigpath, selection)?;
+        report_on_collection_loading(&coll, 0, 0, report_type, allow_failed, ctx)?;
+        return Ok(coll);
     }
 
     eprintln!("Reading {}(s) from: '{}'", report_type, &siglist);
     let mut last_error = None;
 
-    let collection = if sigpath.extension().map_or(false, |ext| ext == "zip") {
+    let collection = if sigpath.extension() == Some("zip") {
         match MultiCollection::from_zipfile(&sigpath) {
             Ok(coll) => Some((coll, 0)),
             Err(e) => {
@@ -565,7 +1067,7 @@ pub fn load_collection(
 
     let collection =
         collection.or_else(
-            || match MultiCollection::from_standalone_manifest(&sigpath) {
+            || match MultiCollection::from_standalone_manifest(&sigpath, ctx) {
                 Ok(coll) => Some((coll, 0)),
                 Err(e) => {
                     last_error = Some(e);
@@ -582,7 +1084,7 @@ pub fn load_collection(
         }
     });
 
-    let collection = collection.or_else(|| match MultiCollection::from_pathlist(&sigpath) {
+    let collection = collection.or_else(|| match MultiCollection::from_pathlist(&sigpath, ctx) {
         Ok((coll, n_failed)) => Some((coll, n_failed)),
         Err(e) => {
             last_error = Some(e);
@@ -593,15 +1095,28 @@ pub fn load_collection(
     match collection {
         Some((coll, n_failed)) => {
             let n_total = coll.len();
+            let unselected = ctx.reports_paths().then(|| coll.clone());
 
-            let selected = coll.select(selection)?;
+            let coarse = find_coarse_sketches(&coll, selection);
+            let mistyped = find_mistyped_sketches(&coll, selection);
+            let selected = ctx.time(Stage::Selection, || coll.select(selection))?;
             let n_skipped = n_total - selected.len();
+            if let Some(unselected) = unselected {
+                report_unselected(&unselected, &selected, selection, ctx);
+            }
+            check_coarse_sketches(&coarse, &selected, selection, report_type)?;
+            check_mistyped_sketches(&mistyped, &selected, selection, report_type)?;
+            let selected = sizefilter::apply(selected, ctx);
+            let selected = exclude::apply(selected, report_type, ctx);
+            let selected = dedup::apply(selected, report_type, ctx);
+            verify::apply(&selected, ctx)?;
             report_on_collection_loading(
                 &selected,
                 n_skipped,
                 n_failed,
                 report_type,
                 allow_failed,
+                ctx,
             )?;
             Ok(selected)
         }
@@ -618,6 +1133,358 @@ pub fn load_collection(
     }
 }
 
+/// Record the locations of sketches that `select` dropped, for
+/// `--skipped-paths-out`.
+fn report_unselected(
+    all: &MultiCollection,
+    selected: &MultiCollection,
+    selection: &Selection,
+    ctx: &RunContext,
+) {
+    let kept: HashSet<String> = selected
+        .item_iter()
+        .map(|(_, _, record)| record.internal_location().to_string())
+        .collect();
+
+    let mut wanted = vec![];
+    if let Some(ksize) = selection.ksize() {
+        wanted.push(format!("k={}", ksize));
+    }
+    if let Some(moltype) = selection.moltype() {
+        wanted.push(moltype.to_string());
+    }
+    if let Some(scaled) = selection.scaled() {
+        wanted.push(format!("scaled<={}", scaled));
+    }
+    if let Some(num) = selection.num() {
+        wanted.push(format!("num={}", num));
+    }
+    if selection.abund() == Some(true) {
+        wanted.push("with abundances".to_string());
+    }
+    let reason = format!("no compatible sketches ({})", wanted.join(", "));
+
+    let skipped: BTreeSet<String> = all
+        .item_iter()
+        .map(|(_, _, record)| record.internal_location().to_string())
+        .filter(|location| !kept.contains(location))
+        .collect();
+    for location in skipped {
+        ctx.record_skipped(&location, &reason);
+    }
+}
+
+/// A sketch that matches the ksize and moltype of a selection, but whose
+/// scaled is above the selection's; sketches can be downsampled to a
+/// higher scaled, but not upsampled to a lower one.
+struct CoarseSketch {
+    location: String,
+    name: String,
+    scaled: u32,
+}
+
+fn find_coarse_sketches(collection: &MultiCollection, selection: &Selection) -> Vec<CoarseSketch> {
+    let Some(scaled) = selection.scaled() else {
+        return vec![];
+    };
+    collection
+        .item_iter()
+        .filter(|(_, _, record)| {
+            selection.ksize().is_none_or(|k| record.ksize() == k)
+                && selection.moltype().is_none_or(|m| record.moltype() == m)
+                && *record.scaled() > scaled
+        })
+        .map(|(_, _, record)| CoarseSketch {
+            location: record.internal_location().to_string(),
+            name: record.name().clone(),
+            scaled: *record.scaled(),
+        })
+        .collect()
+}
+
+/// Report the sketches that selection dropped because their scaled is
+/// above the requested scaled, and fail with the minimum usable scaled if
+/// no sketches were selected.
+fn check_coarse_sketches(
+    coarse: &[CoarseSketch],
+    selected: &MultiCollection,
+    selection: &Selection,
+    report_type: ReportType,
+) -> Result<()> {
+    if coarse.is_empty() {
+        return Ok(());
+    }
+    let scaled = selection
+        .scaled()
+        .expect("coarse sketches without scaled?!");
+    let min_usable = coarse.iter().map(|c| c.scaled).min().unwrap();
+    let max_usable = coarse.iter().map(|c| c.scaled).max().unwrap();
+
+    const MAX_LISTED: usize = 10;
+    eprintln!(
+        "WARNING: {} {} sketches have a scaled above the requested scaled={}, and cannot be upsampled:",
+        coarse.len(),
+        report_type,
+        scaled
+    );
+    for c in coarse.iter().take(MAX_LISTED) {
+        eprintln!("    '{}' ({}): scaled={}", c.name, c.location, c.scaled);
+    }
+    if coarse.len() > MAX_LISTED {
+        eprintln!("    ... and {} more", coarse.len() - MAX_LISTED);
+    }
+
+    if selected.is_empty() {
+        let suggestion = if min_usable == max_usable {
+            format!("use --scaled {} or higher", min_usable)
+        } else {
+            format!(
+                "use --scaled {} or higher (--scaled {} to use all of them)",
+                min_usable, max_usable
+            )
+        };
+        bail!(BranchwaterError::ScaledMismatch(format!(
+            "No {} signatures loaded, exiting: the requested scaled={} is below the scaled of every compatible sketch; {}.",
+            report_type, scaled, suggestion
+        )));
+    }
+    eprintln!("Use --scaled {} or higher to include them.", max_usable);
+    Ok(())
+}
+
+/// A sketch that matches the ksize and moltype of a selection, but is not
+/// the type of sketch it asks for: one with abundances, or a num sketch of
+/// the requested size.
+struct MistypedSketch {
+    location: String,
+    name: String,
+    problem: String,
+}
+
+fn find_mistyped_sketches(
+    collection: &MultiCollection,
+    selection: &Selection,
+) -> Vec<MistypedSketch> {
+    let require_abund = selection.abund() == Some(true);
+    if !require_abund && selection.num().is_none() {
+        return vec![];
+    }
+    collection
+        .item_iter()
+        .filter(|(_, _, record)| {
+            selection.ksize().is_none_or(|k| record.ksize() == k)
+                && selection.moltype().is_none_or(|m| record.moltype() == m)
+        })
+        .filter_map(|(_, _, record)| {
+            let problem = match selection.num() {
+                Some(num) if *record.num() != num => {
+                    if *record.num() == 0 {
+                        format!("scaled={}, not a num sketch", record.scaled())
+                    } else {
+                        format!("num={}", record.num())
+                    }
+                }
+                _ if require_abund && !record.with_abundance() => "no abundances".to_string(),
+                _ => return None,
+            };
+            Some(MistypedSketch {
+                location: record.internal_location().to_string(),
+                name: record.name().clone(),
+                problem,
+            })
+        })
+        .collect()
+}
+
+/// Report the sketches that selection dropped because they lack
+/// abundances or are the wrong num, and fail if no sketches were selected.
+fn check_mistyped_sketches(
+    mistyped: &[MistypedSketch],
+    selected: &MultiCollection,
+    selection: &Selection,
+    report_type: ReportType,
+) -> Result<()> {
+    if mistyped.is_empty() {
+        return Ok(());
+    }
+    let mut wanted = vec![];
+    if let Some(num) = selection.num() {
+        wanted.push(format!("num={}", num));
+    }
+    if selection.abund() == Some(true) {
+        wanted.push("abundances".to_string());
+    }
+    let wanted = wanted.join(" and ");
+
+    const MAX_LISTED: usize = 10;
+    eprintln!(
+        "WARNING: {} {} sketches do not have the requested {}, and were skipped:",
+        mistyped.len(),
+        report_type,
+        wanted
+    );
+    for m in mistyped.iter().take(MAX_LISTED) {
+        eprintln!("    '{}' ({}): {}", m.name, m.location, m.problem);
+    }
+    if mistyped.len() > MAX_LISTED {
+        eprintln!("    ... and {} more", mistyped.len() - MAX_LISTED);
+    }
+
+    if selected.is_empty() {
+        let hint = if selection.abund() == Some(true) {
+            "; sketch with '-p abund' to track abundances"
+        } else {
+            ""
+        };
+        bail!(BranchwaterError::IncompatibleSelection(format!(
+            "No {} signatures loaded, exiting: no compatible sketch has {}{}.",
+            report_type, wanted, hint
+        )));
+    }
+    Ok(())
+}
+
+/// A collection to search: either a path to load, or a collection that
+/// has already been loaded (e.g. a `MultiCollection` held by Python).
+pub enum CollectionSource {
+    Path(String),
+    Loaded(MultiCollection),
+}
+
+impl CollectionSource {
+    /// Load (or re-select) the collection, reporting as `load_collection` does.
+    pub fn load(
+        &self,
+        selection: &Selection,
+        report_type: ReportType,
+        allow_failed: bool,
+        ctx: &RunContext,
+    ) -> Result<MultiCollection> {
+        match self {
+            CollectionSource::Path(siglist) => {
+                load_collection(siglist, selection, report_type, allow_failed, ctx)
+            }
+            CollectionSource::Loaded(coll) => {
+                let n_total = coll.len();
+                let coarse = find_coarse_sketches(coll, selection);
+                let mistyped = find_mistyped_sketches(coll, selection);
+                let selected = ctx.time(Stage::Selection, || coll.clone().select(selection))?;
+                let n_skipped = n_total - selected.len();
+                check_coarse_sketches(&coarse, &selected, selection, report_type)?;
+                check_mistyped_sketches(&mistyped, &selected, selection, report_type)?;
+                let selected = sizefilter::apply(selected, ctx);
+                let selected = exclude::apply(selected, report_type, ctx);
+                let selected = dedup::apply(selected, report_type, ctx);
+                report_on_collection_loading(
+                    &selected,
+                    n_skipped,
+                    0,
+                    report_type,
+                    allow_failed,
+                    ctx,
+                )?;
+                Ok(selected)
+            }
+        }
+    }
+
+    /// Load the collection, first choosing any ksize and moltype left unset
+    /// in `selection` from its sketches; see
+    /// `MultiCollection::complete_selection`. Returns the collection and the
+    /// completed selection.
+    pub fn load_and_complete_selection(
+        &self,
+        selection: Selection,
+        report_type: ReportType,
+        allow_failed: bool,
+        ctx: &RunContext,
+    ) -> Result<(MultiCollection, Selection)> {
+        let collection = self.load(&selection, report_type, allow_failed, ctx)?;
+        let completed = collection.complete_selection(selection)?;
+
+        let (ksize, moltype) = (completed.ksize(), completed.moltype());
+        let all_selected = collection.item_iter().all(|(_, _, record)| {
+            Some(record.ksize()) == ksize && Some(record.moltype()) == moltype
+        });
+        if all_selected {
+            return Ok((collection, completed));
+        }
+
+        // fell back on the defaults; reload so that skipped sketches are
+        // reported as usual.
+        let collection = self.load(&completed, report_type, allow_failed, ctx)?;
+        Ok((collection, completed))
+    }
+}
+
+impl std::fmt::Display for CollectionSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CollectionSource::Path(siglist) => write!(f, "{}", siglist),
+            CollectionSource::Loaded(coll) => {
+                write!(f, "<loaded collection of {} sketches>", coll.len())
+            }
+        }
+    }
+}
+
+/// How to search an against collection: load its sketches into memory,
+/// or use a RocksDB inverted index. `Auto` picks based on the input.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg(feature = "python")]
+pub enum SearchMode {
+    #[default]
+    Auto,
+    InMemory,
+    RocksDB,
+}
+
+#[cfg(feature = "python")]
+impl std::str::FromStr for SearchMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(SearchMode::Auto),
+            "in-memory" | "memory" => Ok(SearchMode::InMemory),
+            "rocksdb" => Ok(SearchMode::RocksDB),
+            _ => bail!(
+                "unknown search mode '{}'; use 'auto', 'in-memory', or 'rocksdb'",
+                s
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "python")]
+impl SearchMode {
+    /// Parse an optional search mode; `None` means `Auto`.
+    pub fn new(mode: Option<String>) -> Result<Self> {
+        mode.map_or(Ok(SearchMode::Auto), |m| m.parse())
+    }
+
+    /// Decide how to search `against`, returning the RocksDB path if it
+    /// should be searched through the index. In-memory search works for
+    /// any input, including RocksDB indexes, whose sketches are loaded.
+    pub fn rocksdb_path(self, against: &CollectionSource) -> Result<Option<PathBuf>> {
+        let path = match against {
+            CollectionSource::Path(p) => Some(PathBuf::from(p)),
+            CollectionSource::Loaded(_) => None,
+        };
+        let is_rocksdb = path.as_ref().is_some_and(is_revindex_database);
+
+        match self {
+            SearchMode::Auto => Ok(path.filter(|_| is_rocksdb)),
+            SearchMode::InMemory => Ok(None),
+            SearchMode::RocksDB if is_rocksdb => Ok(path),
+            SearchMode::RocksDB => bail!(
+                "search mode 'rocksdb' requires a RocksDB index, but '{}' is not one",
+                against
+            ),
+        }
+    }
+}
+
 /// Uses the output of collection loading function to report the
 /// total number of sketches loaded, as well as the number of files,
 /// if any, that failed to load or contained no compatible sketches.
@@ -629,7 +1496,8 @@ pub fn load_collection(
 /// * `skipped_paths` - # paths that contained no compatible sketches.
 /// * `failed_paths` - # paths that failed to load.
 /// * `report_type` - ReportType Enum (Query or Against). Used to specify
-///                   which sketch input this information pertains to.
+///   which sketch input this information pertains to.
+/// * `ctx` - the `RunContext` to record the loaded sketches in.
 ///
 /// # Returns
 ///
@@ -646,7 +1514,9 @@ pub fn report_on_collection_loading(
     failed_paths: usize,
     report_type: ReportType,
     allow_failed: bool,
+    ctx: &RunContext,
 ) -> Result<()> {
+    ctx.record_loaded(report_type, collection.len(), skipped_paths, failed_paths);
     if failed_paths > 0 {
         eprintln!(
             "WARNING: {} {} paths failed to load. See error messages above.",
@@ -665,7 +1535,10 @@ pub fn report_on_collection_loading(
 
     // Validate sketches
     if collection.is_empty() {
-        bail!("No {} signatures loaded, exiting.", report_type);
+        bail!(BranchwaterError::EmptyCollection(format!(
+            "No {} signatures loaded, exiting.",
+            report_type
+        )));
     }
     eprintln!("Loaded {} {} signature(s)", collection.len(), report_type);
     Ok(())
@@ -768,6 +1641,7 @@ pub fn branchwater_calculate_gather_stats(
 
     let result = InterimGatherResult {
         intersect_bp,
+        intersect_hashes: intersect_orig,
         f_orig_query,
         f_match,
         f_unique_to_query,
@@ -797,19 +1671,184 @@ pub fn branchwater_calculate_gather_stats(
     Ok(result)
 }
 
+/// Minimum overlap for reporting gather matches.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GatherThreshold {
+    /// Estimated base pairs, converted to hashes using scaled.
+    Bp(u64),
+    /// Number of shared hashes.
+    Hashes(u64),
+    /// Fraction of the query's hashes.
+    Fraction(f64),
+}
+
+impl GatherThreshold {
+    /// Build a threshold from the command-line options; `threshold_bp` is
+    /// used unless one of the others is given.
+    pub fn new(
+        threshold_bp: u64,
+        threshold_hashes: Option<u64>,
+        threshold_fraction: Option<f64>,
+    ) -> Result<Self> {
+        match (threshold_hashes, threshold_fraction) {
+            (Some(_), Some(_)) => {
+                bail!("specify at most one of threshold_hashes and threshold_fraction")
+            }
+            (Some(n), None) => Ok(GatherThreshold::Hashes(n)),
+            (None, Some(f)) if f > 0.0 && f <= 1.0 => Ok(GatherThreshold::Fraction(f)),
+            (None, Some(f)) => bail!("threshold fraction must be in (0, 1], not {}", f),
+            (None, None) => Ok(GatherThreshold::Bp(threshold_bp)),
+        }
+    }
+
+    /// The threshold in hashes for a query with `query_size` hashes at
+    /// `scaled`. May be 0 for small bp thresholds.
+    pub fn raw_hashes(&self, scaled: u32, query_size: usize) -> u64 {
+        match self {
+            GatherThreshold::Bp(bp) => bp / scaled as u64,
+            GatherThreshold::Hashes(n) => *n,
+            GatherThreshold::Fraction(f) => (f * query_size as f64).ceil() as u64,
+        }
+    }
+
+    /// Like `raw_hashes`, but always at least 1.
+    pub fn hashes(&self, scaled: u32, query_size: usize) -> u64 {
+        self.raw_hashes(scaled, query_size).max(1)
+    }
+
+    /// Whether the threshold in hashes depends on the query.
+    pub fn is_per_query(&self) -> bool {
+        matches!(self, GatherThreshold::Fraction(_))
+    }
+}
+
+impl std::fmt::Display for GatherThreshold {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            GatherThreshold::Bp(bp) => write!(f, "{} bp", bp),
+            GatherThreshold::Hashes(n) => write!(f, "{} hashes", n),
+            GatherThreshold::Fraction(x) => write!(f, "{} of query hashes", x),
+        }
+    }
+}
+
+/// Minimum overlap for reporting manysearch matches, applied alongside
+/// the containment threshold, so that small queries don't match on a
+/// handful of hashes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MinOverlap {
+    value: u64,
+    /// If true, `value` is in estimated base pairs, `intersect_hashes` ×
+    /// `scaled`; otherwise it is a number of shared hashes.
+    in_bp: bool,
+}
+
+impl MinOverlap {
+    /// Build the minimum from the command-line options, if either is given.
+    #[cfg(feature = "python")]
+    pub fn new(
+        min_overlap_bp: Option<u64>,
+        min_intersect_hashes: Option<u64>,
+    ) -> Result<Option<Self>> {
+        match (min_overlap_bp, min_intersect_hashes) {
+            (Some(_), Some(_)) => {
+                bail!("specify at most one of min_overlap_bp and min_intersect_hashes")
+            }
+            (Some(bp), None) => Ok(Some(MinOverlap {
+                value: bp,
+                in_bp: true,
+            })),
+            (None, Some(n)) => Ok(Some(MinOverlap {
+                value: n,
+                in_bp: false,
+            })),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Whether a match overlaps the query by at least the minimum.
+    pub fn passes(&self, result: &ManySearchResult) -> bool {
+        if self.in_bp {
+            result.intersect_bp >= self.value
+        } else {
+            result.intersect_hashes >= self.value
+        }
+    }
+}
+
+impl std::fmt::Display for MinOverlap {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.in_bp {
+            write!(f, "{} bp", self.value)
+        } else {
+            write!(f, "{} hashes", self.value)
+        }
+    }
+}
+
+/// Options changing how gather picks matches and when it stops.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GatherOptions {
+    /// Report at most this many matches per query.
+    pub max_matches: Option<usize>,
+    /// Stop at the first match whose max_containment_ani is below this.
+    pub min_ani: Option<f64>,
+    /// Pick the match with the largest abundance-weighted overlap at each
+    /// rank, rather than the largest flat overlap.
+    pub abundance_weighted: bool,
+}
+
+impl GatherOptions {
+    pub fn new(
+        max_matches: Option<usize>,
+        min_ani: Option<f64>,
+        abundance_weighted: bool,
+    ) -> Result<Self> {
+        if let Some(ani) = min_ani {
+            if !(0.0..=1.0).contains(&ani) {
+                bail!("min ANI must be between 0 and 1, not {}", ani);
+            }
+        }
+        Ok(GatherOptions {
+            max_matches,
+            min_ani,
+            abundance_weighted,
+        })
+    }
+
+    /// Whether a match at `rank` (starting at 0) should be reported, or
+    /// gather should stop before it.
+    pub fn keep(&self, rank: usize, max_containment_ani: f64) -> bool {
+        self.max_matches.is_none_or(|n| rank < n)
+            && self.min_ani.is_none_or(|ani| max_containment_ani >= ani)
+    }
+}
+
 /// Execute the gather algorithm, greedy min-set-cov, by iteratively
 /// removing matches in 'matchlist' from 'query'.
-
+#[allow(clippy::too_many_arguments)]
 pub fn consume_query_by_gather(
     query_name: String,
     query_filename: String,
     orig_query_mh: KmerMinHash,
     scaled: u32,
-    matchlist: BinaryHeap<PrefetchResult>,
+    matchlist: BinaryHeap<PrefetchResult<'_>>,
     threshold_hashes: u64,
+    gather_options: &GatherOptions,
     gather_output: Option<SyncSender<BranchwaterGatherResult>>,
 ) -> Result<()> {
-    let mut matching_sketches = matchlist;
+    // the matches stay put; gather works on a heap of indices into them,
+    // in the same order, so ties are broken just as for the matches.
+    let matches = matchlist.into_vec();
+    let mut matching_sketches: BinaryHeap<GatherCandidate> = matches
+        .iter()
+        .enumerate()
+        .map(|(idx, m)| GatherCandidate {
+            idx,
+            overlap: m.overlap,
+            shared: None,
+        })
+        .collect();
     let mut rank = 0;
 
     let mut last_matches = matching_sketches.len();
@@ -836,6 +1875,14 @@ pub fn consume_query_by_gather(
 
     let mut orig_query_ds = orig_query_mh.downsample_scaled(scaled)?;
 
+    let abundance_weighted = gather_options.abundance_weighted && calc_abund_stats;
+    if gather_options.abundance_weighted && !calc_abund_stats {
+        eprintln!(
+            "WARNING: query '{}' has no abundances; picking matches by flat overlap.",
+            query_name
+        );
+    }
+
     // track for full gather results
     let mut sum_weighted_found = 0;
 
@@ -852,7 +1899,23 @@ pub fn consume_query_by_gather(
     );
 
     while !matching_sketches.is_empty() {
-        let best_element = matching_sketches.peek().unwrap();
+        let best = if abundance_weighted {
+            // sum of query abundances for the hashes each match shares with the query
+            matching_sketches
+                .par_iter()
+                .max_by_key(|m| {
+                    let weighted = matches[m.idx]
+                        .minhash
+                        .inflated_abundances(&query_mh)
+                        .map(|(_, total)| total)
+                        .unwrap_or(0);
+                    (weighted, m.overlap)
+                })
+                .unwrap()
+        } else {
+            matching_sketches.peek().unwrap()
+        };
+        let best_element = &matches[best.idx];
 
         query_mh = query_mh.downsample_scaled(best_element.minhash.scaled())?;
 
@@ -867,9 +1930,9 @@ pub fn consume_query_by_gather(
             &orig_query_ds,
             &query_mh,
             &best_element.minhash,
-            best_element.name.clone(),
-            best_element.md5sum.clone(),
-            best_element.overlap,
+            best_element.name.to_string(),
+            best_element.md5sum.to_string(),
+            best.overlap,
             best_element.location.clone(),
             rank,
             sum_weighted_found,
@@ -879,9 +1942,20 @@ pub fn consume_query_by_gather(
             ani_confidence_interval_fraction,
         )?;
 
+        if !gather_options.keep(rank as usize, match_.max_containment_ani) {
+            eprintln!(
+                "{} iter {}: stopping; {} matches left",
+                query_filename,
+                rank,
+                matching_sketches.len()
+            );
+            break;
+        }
+
         // build full gather result, then write
         let gather_result = BranchwaterGatherResult {
             intersect_bp: match_.intersect_bp,
+            intersect_hashes: match_.intersect_hashes,
             f_orig_query: match_.f_orig_query,
             f_match: match_.f_match,
             f_unique_to_query: match_.f_unique_to_query,
@@ -924,14 +1998,22 @@ pub fn consume_query_by_gather(
             s.send(gather_result)?;
         }
 
+        // the hashes this match takes from the query, for updating the
+        // other candidates' overlaps below.
+        let removed = shared_hashes(&best_element.minhash, &query_mh.mins());
+
         // remove!
-        query_mh.remove_from(&best_element.minhash)?;
-        // to do -- switch to KmerMinHashTree, for faster removal.
-        //query.remove_many(best_element.iter_mins().copied())?; // from sourmash core
+        query_mh = remove_hashes(&query_mh, &removed)?;
 
-        // recalculate remaining overlaps between query and all sketches.
+        // update remaining overlaps between query and all sketches.
         // note: this is parallelized.
-        matching_sketches = prefetch(&query_mh, matching_sketches, threshold_hashes);
+        matching_sketches = update_candidates(
+            &query_mh,
+            &matches,
+            matching_sketches,
+            &removed,
+            threshold_hashes,
+        );
         rank += 1;
 
         let sub_hashes = last_hashes - query_mh.size();
@@ -954,34 +2036,125 @@ pub fn consume_query_by_gather(
     Ok(())
 }
 
-pub fn build_selection(ksize: u8, scaled: Option<u32>, moltype: &str) -> Selection {
-    let hash_function = match moltype {
-        "DNA" => HashFunctions::Murmur64Dna,
-        "protein" => HashFunctions::Murmur64Protein,
-        "dayhoff" => HashFunctions::Murmur64Dayhoff,
-        "hp" => HashFunctions::Murmur64Hp,
-        "skipm1n3" => HashFunctions::Murmur64Skipm1n3,
-        "skipm2n3" => HashFunctions::Murmur64Skipm2n3,
-        _ => panic!("Unknown molecule type: {}", moltype),
-    };
-    // let hash_function = HashFunctions::try_from(moltype)
-    //     .map_err(|_| panic!("Unknown molecule type: {}", moltype))
-    //     .unwrap();
+/// Parse a molecule type given on the command line.
+pub fn parse_moltype(moltype: &str) -> Result<HashFunctions> {
+    match moltype {
+        "DNA" => Ok(HashFunctions::Murmur64Dna),
+        "protein" => Ok(HashFunctions::Murmur64Protein),
+        "dayhoff" => Ok(HashFunctions::Murmur64Dayhoff),
+        "hp" => Ok(HashFunctions::Murmur64Hp),
+        "skipm1n3" => Ok(HashFunctions::Murmur64Skipm1n3),
+        "skipm2n3" => Ok(HashFunctions::Murmur64Skipm2n3),
+        _ => bail!(BranchwaterError::IncompatibleSelection(format!(
+            "unknown molecule type '{}'; use DNA, protein, dayhoff, hp, skipm1n3, or skipm2n3",
+            moltype
+        ))),
+    }
+}
 
+pub fn build_selection(ksize: u8, scaled: Option<u32>, moltype: &str) -> Result<Selection> {
+    build_partial_selection(Some(ksize), scaled, Some(moltype))
+}
+
+/// Like `build_selection`, but ksize and moltype may be left unset, to be
+/// chosen from the query sketches with `MultiCollection::complete_selection`
+/// (or from a RocksDB index with `revindex_selection`).
+pub fn build_partial_selection(
+    ksize: Option<u8>,
+    scaled: Option<u32>,
+    moltype: Option<&str>,
+) -> Result<Selection> {
+    let mut selection = Selection::default();
+    if let Some(ksize) = ksize {
+        selection.set_ksize(ksize.into());
+    }
+    if let Some(moltype) = moltype {
+        selection.set_moltype(parse_moltype(moltype)?);
+    }
     if let Some(scaled) = scaled {
-        Selection::builder()
-            .ksize(ksize.into())
-            .scaled(scaled)
-            .moltype(hash_function)
-            .build()
-    } else {
-        Selection::builder()
-            .ksize(ksize.into())
-            .moltype(hash_function)
-            .build()
+        selection.set_scaled(scaled);
+    }
+    Ok(selection)
+}
+
+/// Restrict `selection` to sketches with abundances, if
+/// `require_abundance`, and to num sketches of size `num`, if given. num
+/// sketches have no scaled, so `num` cannot be combined with a scaled.
+#[cfg(feature = "python")]
+pub fn require_sketch_type(
+    mut selection: Selection,
+    require_abundance: bool,
+    num: Option<u32>,
+) -> Result<Selection> {
+    if require_abundance {
+        selection.set_abund(true);
     }
+    if let Some(num) = num {
+        if selection.scaled().is_some() {
+            bail!(BranchwaterError::IncompatibleSelection(
+                "cannot select num sketches at a scaled; use --num or --scaled, not both"
+                    .to_string()
+            ));
+        }
+        selection.set_num(num);
+    }
+    Ok(selection)
+}
+
+/// `selection` without any abundance requirement: commands that weight by
+/// abundance need it of their queries, but not of the sketches they search.
+pub fn without_abundance_requirement(selection: &Selection) -> Selection {
+    let mut relaxed = Selection::default();
+    if let Some(ksize) = selection.ksize() {
+        relaxed.set_ksize(ksize);
+    }
+    if let Some(moltype) = selection.moltype() {
+        relaxed.set_moltype(moltype);
+    }
+    if let Some(scaled) = selection.scaled() {
+        relaxed.set_scaled(scaled);
+    }
+    if let Some(num) = selection.num() {
+        relaxed.set_num(num);
+    }
+    if let Some(containment) = selection.containment() {
+        relaxed.set_containment(containment);
+    }
+    if let Some(picklist) = selection.picklist() {
+        relaxed.set_picklist(picklist);
+    }
+    relaxed
+}
+
+/// Select `collection` at the largest scaled among its sketches, so that
+/// all of them are downsampled to it as they are loaded. Returns the
+/// collection and that scaled.
+pub fn select_at_max_scaled(
+    collection: MultiCollection,
+    selection: &Selection,
+) -> Result<(MultiCollection, u32)> {
+    let Some(&scaled) = collection.max_scaled() else {
+        bail!(BranchwaterError::EmptyCollection(
+            "no sketches to select a scaled from".to_string()
+        ));
+    };
+    let mut selection = selection.clone();
+    selection.set_scaled(scaled);
+    Ok((collection.select(&selection)?, scaled))
 }
 
+/// Downsample a query to `against_scaled`, the scaled of the sketches it
+/// is compared against, unless the query's own scaled is higher; in that
+/// case it is compared at its own scaled, and against sketches are
+/// downsampled to match.
+pub fn downsample_query(mh: KmerMinHash, against_scaled: u32) -> Result<KmerMinHash> {
+    if mh.scaled() >= against_scaled {
+        return Ok(mh);
+    }
+    Ok(mh.downsample_scaled(against_scaled)?)
+}
+
+#[cfg(feature = "python")]
 pub fn is_revindex_database(path: &camino::Utf8PathBuf) -> bool {
     // quick file check for Revindex database:
     // is path a directory that contains a file named 'CURRENT'?
@@ -993,17 +2166,122 @@ pub fn is_revindex_database(path: &camino::Utf8PathBuf) -> bool {
     }
 }
 
+/// Check `selection` against the ksize, moltype, and scaled of the
+/// sketches in a RocksDB index (from the manifest stored in the index),
+/// filling in any that are unset. Queries must be selected with the
+/// result: the index compares hashes without knowing how they were made,
+/// so mismatched queries would silently find nothing.
+#[cfg(feature = "python")]
+pub fn revindex_selection(db: &RevIndex, selection: Selection) -> Result<Selection> {
+    let Some((_, record)) = db.collection().iter().next() else {
+        bail!(BranchwaterError::EmptyCollection(
+            "RocksDB database has no sketches".to_string()
+        ));
+    };
+    let (db_ksize, db_moltype) = (record.ksize(), record.moltype());
+    let (_, max_db_scaled) = db
+        .collection()
+        .min_max_scaled()
+        .expect("no records in db?!");
+    let mut selection = selection;
+
+    match (selection.ksize(), selection.moltype()) {
+        (Some(ksize), _) if ksize != db_ksize => {
+            bail!(BranchwaterError::IncompatibleSelection(format!(
+                "database has ksize={} ({}) sketches, but ksize={} was requested; use -k {}",
+                db_ksize, db_moltype, ksize, db_ksize
+            )));
+        }
+        (_, Some(moltype)) if moltype != db_moltype => {
+            bail!(BranchwaterError::IncompatibleSelection(format!(
+                "database has {} (k={}) sketches, but {} was requested; use --moltype {}",
+                db_moltype, db_ksize, moltype, db_moltype
+            )));
+        }
+        (ksize, moltype) => {
+            if ksize.is_none() {
+                eprintln!("Setting ksize={} from the database", db_ksize);
+                selection.set_ksize(db_ksize);
+            }
+            if moltype.is_none() {
+                eprintln!("Setting moltype={} from the database", db_moltype);
+                selection.set_moltype(db_moltype);
+            }
+        }
+    }
+
+    match selection.scaled() {
+        Some(scaled) if *max_db_scaled > scaled => {
+            bail!(BranchwaterError::ScaledMismatch(format!(
+                "database sketches have scaled={}, and cannot be upsampled to the requested scaled={}; use --scaled {} or higher",
+                max_db_scaled, scaled, max_db_scaled
+            )));
+        }
+        Some(_) => {}
+        None => {
+            eprintln!("Setting scaled={} from the database", *max_db_scaled);
+            selection.set_scaled(*max_db_scaled);
+        }
+    }
+
+    Ok(selection)
+}
+
+/// Shares one allocation between equal strings, e.g. match names that
+/// recur across the results for many queries.
+///
+/// The strings are spread over several independently locked shards, so
+/// rayon workers interning different names rarely wait on each other.
+#[cfg(feature = "python")]
+pub struct Interner {
+    shards: Box<[std::sync::Mutex<HashSet<Arc<str>>>]>,
+    hasher: std::collections::hash_map::RandomState,
+}
+
+#[cfg(feature = "python")]
+impl Default for Interner {
+    fn default() -> Self {
+        let n_shards = (rayon::current_num_threads() * 4).next_power_of_two();
+        Self {
+            shards: (0..n_shards).map(|_| Default::default()).collect(),
+            hasher: Default::default(),
+        }
+    }
+}
+
+#[cfg(feature = "python")]
+impl Interner {
+    pub fn intern(&self, s: &str) -> Arc<str> {
+        use std::hash::BuildHasher;
+
+        let shard = self.hasher.hash_one(s) as usize & (self.shards.len() - 1);
+        let mut strings = self.shards[shard].lock().unwrap();
+        if let Some(interned) = strings.get(s) {
+            return interned.clone();
+        }
+        let interned: Arc<str> = Arc::from(s);
+        strings.insert(interned.clone());
+        interned
+    }
+}
+
 #[derive(Serialize)]
 pub struct ManySearchResult {
-    pub query_name: String,
-    pub query_md5: String,
-    pub match_name: String,
+    pub query_name: Arc<str>,
+    pub query_md5: Arc<str>,
+    pub match_name: Arc<str>,
     pub containment: f64,
     pub intersect_hashes: u64,
+    pub intersect_bp: u64,
+    // sketch sizes at `scaled`; match sizes may be unknown for RocksDB.
+    pub query_n_hashes: u64,
+    pub match_n_hashes: Option<u64>,
+    pub query_bp: u64,
+    pub match_bp: Option<u64>,
     pub ksize: u16,
     pub scaled: u32,
     pub moltype: String,
-    pub match_md5: Option<String>,
+    pub match_md5: Option<Arc<str>>,
     pub jaccard: Option<f64>,
     pub max_containment: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -1024,10 +2302,14 @@ pub struct ManySearchResult {
     pub n_weighted_found: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub total_weighted_hashes: Option<u64>,
+    // other names of the match, with `--dedup-by-md5`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub match_aliases: Option<String>,
 }
 
 pub struct InterimGatherResult {
     intersect_bp: u64,
+    intersect_hashes: u64,
     f_orig_query: f64,
     f_match: f64,
     f_unique_to_query: f64,
@@ -1058,6 +2340,7 @@ pub struct InterimGatherResult {
 #[derive(Serialize)]
 pub struct BranchwaterGatherResult {
     pub intersect_bp: u64,
+    pub intersect_hashes: u64,
     pub f_orig_query: f64,
     pub f_match: f64,
     pub f_unique_to_query: f64,
@@ -1101,14 +2384,25 @@ pub struct BranchwaterGatherResult {
 
 #[derive(Serialize, Deserialize)]
 pub struct MultiSearchResult {
-    pub query_name: String,
-    pub query_md5: String,
-    pub match_name: String,
-    pub match_md5: String,
+    pub query_name: Arc<str>,
+    pub query_md5: Arc<str>,
+    pub match_name: Arc<str>,
+    pub match_md5: Arc<str>,
     pub containment: f64,
     pub max_containment: f64,
     pub jaccard: f64,
     pub intersect_hashes: f64,
+    // not in pairwise CSVs written by older versions.
+    #[serde(default)]
+    pub intersect_bp: u64,
+    #[serde(default)]
+    pub query_n_hashes: u64,
+    #[serde(default)]
+    pub match_n_hashes: u64,
+    #[serde(default)]
+    pub query_bp: u64,
+    #[serde(default)]
+    pub match_bp: u64,
     pub ksize: u16,
     pub scaled: u32,
     pub moltype: String,
@@ -1122,6 +2416,10 @@ pub struct MultiSearchResult {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_containment_ani: Option<f64>,
 
+    // abundance-weighted cosine similarity, as in `sourmash compare`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub angular_similarity: Option<f64>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     pub prob_overlap: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -1135,61 +2433,251 @@ pub struct MultiSearchResult {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tf_idf_score: Option<f64>,
+
+    // chance of this overlap between random sketches, with `--fdr`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub overlap_pvalue: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_false_positives: Option<f64>,
+    // filled in by a second pass over the output.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bh_qvalue: Option<f64>,
+
+    // other names of the match, with `--dedup-by-md5`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub match_aliases: Option<String>,
+}
+
+impl MultiSearchResult {
+    /// Similarity columns that can be used to weight or cluster results.
+    pub const SIMILARITY_COLUMNS: [&'static str; 6] = [
+        "containment",
+        "max_containment",
+        "jaccard",
+        "average_containment_ani",
+        "max_containment_ani",
+        "angular_similarity",
+    ];
+
+    /// Get the value of a similarity column by name.
+    pub fn similarity(&self, measure: &str) -> Result<f64> {
+        let value = match measure {
+            "containment" => self.containment,
+            "max_containment" => self.max_containment,
+            "jaccard" => self.jaccard,
+            "average_containment_ani" => match self.average_containment_ani {
+                Some(value) => value,
+                None => bail!("average_containment_ani is None. Did you estimate ANI?"),
+            },
+            "max_containment_ani" => match self.max_containment_ani {
+                Some(value) => value,
+                None => bail!("max_containment_ani is None. Did you estimate ANI?"),
+            },
+            "angular_similarity" => match self.angular_similarity {
+                Some(value) => value,
+                None => bail!("angular_similarity is None. Did you use --angular-similarity?"),
+            },
+            _ => bail!("Invalid similarity measure: {}", measure),
+        };
+        Ok(value)
+    }
+}
+
+/// Angular similarity needs abundances, so check for them up front
+/// rather than leaving holes in the output.
+#[cfg(feature = "python")]
+pub fn require_abundance(sketches: &[SmallSignature], what: &str) -> Result<()> {
+    let n_flat = sketches
+        .iter()
+        .filter(|s| !s.minhash.track_abundance())
+        .count();
+    if n_flat > 0 {
+        bail!(
+            "--angular-similarity requires sketches with abundances, but {} {} sketches have none",
+            n_flat,
+            what
+        );
+    }
+    Ok(())
 }
 
-pub fn open_stdout_or_file(output: Option<String>) -> Box<dyn Write + Send + 'static> {
-    // if output is a file, use open_output_file
+pub fn open_stdout_or_file(output: Option<String>) -> Box<dyn Output> {
+    // if output is a file, use open_output
     if let Some(path) = output {
-        let outpath: PathBuf = path.into();
-        Box::new(open_output_file(&outpath))
+        open_output(&path).unwrap_or_else(|e| {
+            eprintln!("Error creating output file: {:?}", e);
+            std::process::exit(1);
+        })
     } else {
         Box::new(std::io::stdout())
     }
 }
 
-pub fn open_output_file(output: &PathBuf) -> BufWriter<File> {
-    let file = File::create(output).unwrap_or_else(|e| {
-        eprintln!("Error creating output file: {:?}", e);
-        std::process::exit(1);
-    });
-    BufWriter::new(file)
+/// Flush a CSV writer and return its output, to be committed.
+pub fn finish_csv<W: Write>(writer: Writer<W>) -> Result<W> {
+    Ok(writer.into_inner().map_err(|e| e.into_error())?)
+}
+
+/// Open an output path for writing: connect to it if it is a Unix socket,
+/// and otherwise write a file that is moved into place once the output
+/// is committed.
+pub fn open_output(path: &str) -> Result<Box<dyn Output>> {
+    if metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        let stream = UnixStream::connect(path)
+            .with_context(|| format!("Failed to connect to output socket '{}'", path))?;
+        return Ok(Box::new(stream));
+    }
+    Ok(Box::new(AtomicFile::create(path)?))
+}
+
+/// Is `output` a named pipe or Unix socket? Results written there are
+/// flushed as they come, so that a consumer can process them live.
+pub fn is_stream_output(output: Option<&str>) -> bool {
+    output
+        .and_then(|path| metadata(path).ok())
+        .is_some_and(|m| m.file_type().is_fifo() || m.file_type().is_socket())
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct Params {
-    pub ksize: u32,
-    pub track_abundance: bool,
-    pub num: u32,
-    pub scaled: ScaledType,
-    pub seed: u32,
-    pub is_protein: bool,
-    pub is_dayhoff: bool,
-    pub is_hp: bool,
-    pub is_dna: bool,
+/// When the CSV writer threads flush their output: after every `rows`
+/// rows, and/or once `interval` has passed since the last flush. Output
+/// is always flushed when the writer finishes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FlushPolicy {
+    pub rows: Option<usize>,
+    pub interval: Option<Duration>,
 }
 
-impl Hash for Params {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.ksize.hash(state);
-        self.track_abundance.hash(state);
-        self.num.hash(state);
-        self.scaled.hash(state);
-        self.seed.hash(state);
-        self.is_protein.hash(state);
-        self.is_dayhoff.hash(state);
-        self.is_hp.hash(state);
-        self.is_dna.hash(state);
+impl FlushPolicy {
+    /// Flush every `rows` rows or every `interval_ms` milliseconds,
+    /// whichever comes first; 0 disables either one, so `batched(1, 0)`
+    /// flushes after every row.
+    pub fn batched(rows: usize, interval_ms: u64) -> Self {
+        FlushPolicy {
+            rows: (rows > 0).then_some(rows),
+            interval: (interval_ms > 0).then(|| Duration::from_millis(interval_ms)),
+        }
+    }
+
+    /// The default policy for `output`: named pipes and sockets are
+    /// flushed after every row, so that a consumer sees each result as
+    /// soon as it is found, and files only at the end.
+    pub fn for_output(output: Option<&str>) -> Self {
+        if is_stream_output(output) {
+            FlushPolicy::batched(1, 0)
+        } else {
+            FlushPolicy::default()
+        }
     }
 }
 
+/// Tracks rows written since the last flush, and says when the next
+/// flush is due under a `FlushPolicy`.
+#[cfg(feature = "python")]
+pub struct Flusher {
+    policy: FlushPolicy,
+    pending: usize,
+    last_flush: Instant,
+}
+
+#[cfg(feature = "python")]
+impl Flusher {
+    pub fn new(policy: FlushPolicy) -> Self {
+        Flusher {
+            policy,
+            pending: 0,
+            last_flush: Instant::now(),
+        }
+    }
+
+    /// Record `n` rows written; returns true if the output should now be
+    /// flushed.
+    pub fn wrote(&mut self, n: usize) -> bool {
+        self.pending += n;
+        self.due()
+    }
+
+    fn due(&mut self) -> bool {
+        let by_rows = self.policy.rows.is_some_and(|rows| self.pending >= rows);
+        let by_time = self
+            .policy
+            .interval
+            .is_some_and(|interval| self.last_flush.elapsed() >= interval);
+        if self.pending > 0 && (by_rows || by_time) {
+            self.reset();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn reset(&mut self) {
+        self.pending = 0;
+        self.last_flush = Instant::now();
+    }
+
+    /// How long until a timed flush of pending rows is due, if any.
+    fn timeout(&self) -> Option<Duration> {
+        let interval = self.policy.interval.filter(|_| self.pending > 0)?;
+        Some(interval.saturating_sub(self.last_flush.elapsed()))
+    }
+}
+
+/// An item from `batch_rows`: a row to write, or a request to flush.
+#[cfg(feature = "python")]
+pub enum Batched<T> {
+    Row(T),
+    Flush,
+}
+
+/// Iterate over rows from a writer thread's channel, interleaving
+/// `Batched::Flush` whenever `policy` says output should be flushed.
+/// Timed flushes happen even while no new rows are arriving.
+#[cfg(feature = "python")]
+pub fn batch_rows<T>(recv: Receiver<T>, policy: FlushPolicy) -> impl Iterator<Item = Batched<T>> {
+    let mut flusher = Flusher::new(policy);
+    let mut flush_next = false;
+    std::iter::from_fn(move || {
+        if std::mem::take(&mut flush_next) {
+            return Some(Batched::Flush);
+        }
+        let row = match flusher.timeout() {
+            Some(timeout) => match recv.recv_timeout(timeout) {
+                Ok(row) => row,
+                Err(RecvTimeoutError::Timeout) => {
+                    flusher.reset();
+                    return Some(Batched::Flush);
+                }
+                Err(RecvTimeoutError::Disconnected) => return None,
+            },
+            None => recv.recv().ok()?,
+        };
+        flush_next = flusher.wrote(1);
+        Some(Batched::Row(row))
+    })
+}
+
+#[cfg(feature = "python")]
 pub fn zipwriter_handle(
     recv: Receiver<Option<BuildCollection>>,
     output: String,
+) -> JoinHandle<Result<()>> {
+    zipwriter_handle_on_duplicate(recv, output, None)
+}
+
+/// As `zipwriter_handle`, applying `on_duplicate` to sketches with the
+/// same name and parameters.
+#[cfg(feature = "python")]
+pub fn zipwriter_handle_on_duplicate(
+    recv: Receiver<Option<BuildCollection>>,
+    output: String,
+    on_duplicate: Option<DuplicatePolicy>,
 ) -> JoinHandle<Result<()>> {
     std::thread::spawn(move || -> Result<()> {
         // Convert output to PathBuf
         let outpath: PathBuf = output.into();
-        let file_writer = open_output_file(&outpath);
+        // only commit the zip once the manifest is written, so that an
+        // interrupted build doesn't leave a valid-looking partial zip.
+        let file_writer = AtomicFile::create(outpath.as_str())?;
 
         let options = FileOptions::default()
             .compression_method(CompressionMethod::Stored)
@@ -1199,55 +2687,260 @@ pub fn zipwriter_handle(
         let mut zip = ZipWriter::new(file_writer);
         let mut md5sum_occurrences: HashMap<String, usize> = HashMap::new();
         let mut zip_manifest = BuildManifest::new();
+        let mut duplicates = on_duplicate.map(DuplicateNames::new);
 
         // Process each incoming Option<BuildCollection>
         while let Ok(message) = recv.recv() {
-            match message {
-                Some(mut build_collection) => {
-                    // Use BuildCollection's method to write signatures to the zip file
-                    match build_collection.write_sigs_to_zip(
-                        &mut zip,
-                        &mut md5sum_occurrences,
-                        &options,
-                    ) {
-                        Ok(_) => {
-                            zip_manifest.extend_from_manifest(&build_collection.manifest);
-                        }
-                        Err(e) => {
-                            let error = e.context("Error processing signature in BuildCollection");
-                            eprintln!("Error: {}", error);
-                            return Err(error);
-                        }
-                    }
+            let (mut build_collection, done) = match message {
+                Some(build_collection) => match duplicates.as_mut() {
+                    Some(duplicates) => (duplicates.check(build_collection)?, false),
+                    None => (build_collection, false),
+                },
+                // None signals completion; write any sketches held back for merging
+                None => (
+                    duplicates
+                        .take()
+                        .map(DuplicateNames::finish)
+                        .unwrap_or_default(),
+                    true,
+                ),
+            };
+
+            // Use BuildCollection's method to write signatures to the zip file
+            match build_collection.write_sigs_to_zip(&mut zip, &mut md5sum_occurrences, &options) {
+                Ok(_) => {
+                    zip_manifest.extend_from_manifest(&build_collection.manifest);
                 }
-                None => {
-                    // Finalize and write the manifest when None is received
-                    println!("Writing manifest");
-                    zip_manifest.write_manifest_to_zip(&mut zip, &options)?;
-                    zip.finish()?;
-                    break;
+                Err(e) => {
+                    let error = e.context("Error processing signature in BuildCollection");
+                    eprintln!("Error: {}", error);
+                    return Err(error);
                 }
             }
+
+            if done {
+                // Finalize and write the manifest
+                println!("Writing manifest");
+                zip_manifest.write_manifest_to_zip(&mut zip, &options)?;
+                zip.finish()?.commit()?;
+                break;
+            }
         }
         Ok(())
     })
 }
 
+#[cfg(feature = "python")]
 pub fn csvwriter_thread<T: Serialize + Send + 'static>(
     recv: std::sync::mpsc::Receiver<T>,
     output: Option<String>,
 ) -> std::thread::JoinHandle<()> {
     // create output file
+    let flush = FlushPolicy::for_output(output.as_deref());
     let out = open_stdout_or_file(output);
     // spawn a thread that is dedicated to printing to a buffered output
     std::thread::spawn(move || {
         let mut writer = Writer::from_writer(out);
 
-        for res in recv.iter() {
-            if let Err(e) = writer.serialize(res) {
-                eprintln!("Error writing item: {:?}", e);
+        for item in batch_rows(recv, flush) {
+            match item {
+                Batched::Row(res) => {
+                    if let Err(e) = writer.serialize(res) {
+                        eprintln!("Error writing item: {:?}", e);
+                    }
+                }
+                Batched::Flush => {
+                    if let Err(e) = writer.flush() {
+                        eprintln!("Error flushing output: {:?}", e);
+                    }
+                }
             }
         }
-        writer.flush().expect("Failed to flush writer.");
+        finish_csv(writer)
+            .and_then(|out| out.commit())
+            .expect("Failed to flush writer.");
     })
 }
+
+/// Run a search core that sends its results over a channel, collecting
+/// them into a Vec for callers that want results in memory rather than
+/// in a CSV. Returns the core's own return value along with the results.
+pub fn collect_results<T: Send + 'static, R>(
+    core: impl FnOnce(SyncSender<T>) -> Result<R>,
+) -> Result<(R, Vec<T>)> {
+    let (send, recv) = std::sync::mpsc::sync_channel::<T>(rayon::current_num_threads());
+    let thrd = std::thread::spawn(move || recv.iter().collect::<Vec<T>>());
+
+    // the core owns the sender, so the collector finishes when it returns.
+    let ret = core(send);
+    let results = thrd.join().expect("Unable to join internal thread");
+    Ok((ret?, results))
+}
+
+pub type ProgressFn = Box<dyn Fn(usize, usize) + Send + Sync>;
+
+/// Progress reporting and cancellation for the comparison loops, so that
+/// callers (e.g. GUI or web frontends) can watch and stop long searches.
+#[derive(Default)]
+pub struct SearchControl {
+    cancel: Option<std::sync::Arc<atomic::AtomicBool>>,
+    progress: Option<ProgressFn>,
+    interval: usize,
+    n_done: AtomicUsize,
+}
+
+impl SearchControl {
+    /// `progress` is called with (comparisons done, total comparisons)
+    /// every `interval` comparisons; `cancel` is checked periodically.
+    #[cfg(feature = "python")]
+    pub fn new(
+        cancel: Option<std::sync::Arc<atomic::AtomicBool>>,
+        progress: Option<ProgressFn>,
+        interval: usize,
+    ) -> Self {
+        SearchControl {
+            cancel,
+            progress,
+            interval: interval.max(1),
+            n_done: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(|c| c.load(atomic::Ordering::Relaxed))
+    }
+
+    /// Record `n` more comparisons, out of `n_total`.
+    pub fn add_progress(&self, n: usize, n_total: usize) {
+        if let Some(progress) = &self.progress {
+            let before = self.n_done.fetch_add(n, atomic::Ordering::SeqCst);
+            let after = before + n;
+            if after / self.interval != before / self.interval || after == n_total {
+                progress(after, n_total);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixListener;
+
+    #[derive(Serialize)]
+    struct Row {
+        name: String,
+        value: u32,
+    }
+
+    #[test]
+    fn test_stream_output_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.sock").to_str().unwrap().to_string();
+        let listener = UnixListener::bind(&path).unwrap();
+        assert!(is_stream_output(Some(&path)));
+
+        let (send, recv) = std::sync::mpsc::sync_channel::<Row>(1);
+        let thrd = csvwriter_thread(recv, Some(path));
+        let (stream, _) = listener.accept().unwrap();
+        let mut lines = BufReader::new(stream).lines();
+
+        // each row arrives while the writer is still running.
+        send.send(Row {
+            name: "a".into(),
+            value: 1,
+        })
+        .unwrap();
+        assert_eq!(lines.next().unwrap().unwrap(), "name,value");
+        assert_eq!(lines.next().unwrap().unwrap(), "a,1");
+
+        drop(send);
+        thrd.join().unwrap();
+        assert!(lines.next().is_none());
+
+        let file = dir.path().join("out.csv");
+        std::fs::write(&file, "").unwrap();
+        assert!(!is_stream_output(file.to_str()));
+        assert!(!is_stream_output(None));
+    }
+
+    #[test]
+    fn test_flush_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("out.sock").to_str().unwrap().to_string();
+        let _listener = UnixListener::bind(&socket).unwrap();
+        let file = dir.path().join("out.csv").to_str().unwrap().to_string();
+
+        // streams are flushed after every row, and files only at the end.
+        let ctx = RunContext::default();
+        assert_eq!(ctx.flush_policy(Some(&socket)), FlushPolicy::batched(1, 0));
+        assert_eq!(ctx.flush_policy(Some(&file)), FlushPolicy::default());
+
+        // either option replaces the default for all outputs.
+        let ctx = RunContext::default().with_flush_policy(None, Some(100));
+        for output in [&socket, &file] {
+            assert_eq!(ctx.flush_policy(Some(output)), FlushPolicy::batched(0, 100));
+        }
+    }
+
+    fn batches(recv: Receiver<u32>, policy: FlushPolicy) -> Vec<Option<u32>> {
+        batch_rows(recv, policy)
+            .map(|item| match item {
+                Batched::Row(n) => Some(n),
+                Batched::Flush => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_batch_rows() {
+        let (send, recv) = std::sync::mpsc::channel();
+        (1..=5).for_each(|n| send.send(n).unwrap());
+        drop(send);
+        let items = batches(recv, FlushPolicy::batched(2, 0));
+        assert_eq!(
+            items,
+            [Some(1), Some(2), None, Some(3), Some(4), None, Some(5)]
+        );
+
+        // rows are flushed once the interval passes, even with no new rows;
+        // a row arriving after the interval has passed is flushed at once.
+        let (send, recv) = std::sync::mpsc::channel();
+        let thrd = std::thread::spawn(move || batches(recv, FlushPolicy::batched(0, 10)));
+        send.send(1).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        send.send(2).unwrap();
+        drop(send);
+        assert_eq!(thrd.join().unwrap(), [Some(1), None, Some(2), None]);
+
+        // with no policy, only the final flush (by the caller) happens.
+        let (send, recv) = std::sync::mpsc::channel();
+        (1..=3).for_each(|n| send.send(n).unwrap());
+        drop(send);
+        assert_eq!(
+            batches(recv, FlushPolicy::default()),
+            [Some(1), Some(2), Some(3)]
+        );
+    }
+
+    #[test]
+    fn test_interner() {
+        let names = Interner::default();
+        let a = names.intern("NC_009665.1");
+        let b = names.intern(&String::from("NC_009665.1"));
+        assert!(Arc::ptr_eq(&a, &b));
+        assert!(!Arc::ptr_eq(&a, &names.intern("NC_011663.1")));
+
+        // concurrent interning still hands out one allocation per string.
+        let interned: Vec<Arc<str>> = (0..1000)
+            .into_par_iter()
+            .map(|i| names.intern(&format!("match{}", i % 10)))
+            .collect();
+        for (i, name) in interned.iter().enumerate() {
+            assert!(Arc::ptr_eq(name, &interned[i % 10]));
+        }
+    }
+}
@@ -6,15 +6,17 @@ use sourmash::prelude::*;
 use anyhow::{anyhow, Context, Result};
 use camino::Utf8Path as Path;
 use camino::Utf8PathBuf;
+use glob::glob;
 use log::{debug, trace};
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet};
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 use std::sync::atomic;
 use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
 
 use sourmash::collection::Collection;
-use sourmash::encodings::Idx;
+use sourmash::encodings::{HashFunctions, Idx};
 use sourmash::errors::SourmashError;
 use sourmash::manifest::{Manifest, Record};
 use sourmash::selection::{Select, Selection};
@@ -23,6 +25,9 @@ use sourmash::sketch::minhash::KmerMinHash;
 use sourmash::storage::{FSStorage, InnerStorage, SigStore};
 use sourmash::ScaledType;
 
+use crate::errors::BranchwaterError;
+use crate::utils::RunContext;
+
 /// A collection of sketches, potentially stored in multiple files.
 #[derive(Clone)]
 pub struct MultiCollection {
@@ -90,7 +95,7 @@ impl MultiCollection {
 
     // Turn a set of paths into list of Collections - works recursively
     // if needed, and can handle paths of any supported type.
-    fn load_set_of_paths(paths: &HashSet<String>) -> (MultiCollection, usize) {
+    fn load_set_of_paths(paths: &HashSet<String>, ctx: &RunContext) -> (MultiCollection, usize) {
         let n_failed = AtomicUsize::new(0);
 
         // could just use a variant of load_collection here?
@@ -109,7 +114,13 @@ impl MultiCollection {
 
                     let x: String = x.into();
                     let utf_path: &Path = x.as_str().into();
-                    MultiCollection::from_standalone_manifest(utf_path).ok()
+                    match MultiCollection::from_standalone_manifest(utf_path, ctx) {
+                        Ok(coll) => Some(coll),
+                        Err(err) => {
+                            ctx.record_failed(&x, format!("{:#}", err));
+                            None
+                        }
+                    }
                 }
                 // load from (by default) a sigfile
                 _ => {
@@ -118,6 +129,7 @@ impl MultiCollection {
                         Ok(signatures) => Some(signatures),
                         Err(err) => {
                             eprintln!("Sketch loading error: {}", err);
+                            ctx.record_failed(iloc, &err);
                             None
                         }
                     };
@@ -158,7 +170,7 @@ impl MultiCollection {
     /// Build from a standalone manifest.  Note: the tricky bit here
     /// is that the manifest may select only a subset of the rows,
     /// using (name, md5) tuples.
-    pub fn from_standalone_manifest(sigpath: &Path) -> Result<Self> {
+    pub fn from_standalone_manifest(sigpath: &Path, ctx: &RunContext) -> Result<Self> {
         debug!("multi from standalone manifest!");
         let file =
             File::open(sigpath).with_context(|| format!("Failed to open file: '{}'", sigpath))?;
@@ -172,7 +184,7 @@ impl MultiCollection {
             Err(anyhow!("could not read as manifest: '{}'", sigpath))
         } else {
             let ilocs: HashSet<_> = manifest.internal_locations().map(String::from).collect();
-            let (mut colls, _n_failed) = MultiCollection::load_set_of_paths(&ilocs);
+            let (mut colls, _n_failed) = MultiCollection::load_set_of_paths(&ilocs, ctx);
 
             colls.intersect_manifest(&manifest);
 
@@ -181,6 +193,12 @@ impl MultiCollection {
     }
 
     /// Load a collection from a .zip file.
+    ///
+    /// Note: sourmash's `ZipStorage` memory-maps the zip file, so the
+    /// returned collection reads sketches straight out of the page
+    /// cache. The storage is shared (not copied) when the collection is
+    /// cloned or iterated over in parallel, so all rayon workers use the
+    /// same mapping.
     pub fn from_zipfile(sigpath: &Path) -> Result<Self> {
         debug!("multi from zipfile!");
         match Collection::from_zipfile(sigpath) {
@@ -210,7 +228,10 @@ impl MultiCollection {
                     debug!("...rocksdb successful!");
                     Ok(MultiCollection::new(vec![collection], true))
                 }
-                Err(_) => bail!("failed to load rocksdb: '{}'", sigpath),
+                Err(_) => bail!(BranchwaterError::InvalidRocksDB(format!(
+                    "failed to load rocksdb: '{}'",
+                    sigpath
+                ))),
             }
         } else {
             bail!("not a rocksdb: '{}'", sigpath)
@@ -218,20 +239,14 @@ impl MultiCollection {
     }
 
     /// Load a collection from a list of paths.
-    pub fn from_pathlist(sigpath: &Path) -> Result<(Self, usize)> {
+    pub fn from_pathlist(sigpath: &Path, ctx: &RunContext) -> Result<(Self, usize)> {
         debug!("multi from pathlist!");
         let file = File::open(sigpath)
             .with_context(|| format!("Failed to open pathlist file: '{}'", sigpath))?;
         let reader = BufReader::new(file);
 
         // load set of paths
-        let lines: HashSet<_> = reader
-            .lines()
-            .filter_map(|line| match line {
-                Ok(path) => Some(path),
-                Err(_err) => None,
-            })
-            .collect();
+        let lines: HashSet<_> = reader.lines().map_while(Result::ok).collect();
 
         let val = MultiCollection::load_set_of_json_files(&lines);
 
@@ -244,13 +259,33 @@ impl MultiCollection {
             }
             Err(_) => {
                 eprintln!("FAILED to load as JSON files; falling back to general recursive");
-                MultiCollection::load_set_of_paths(&lines)
+                MultiCollection::load_set_of_paths(&lines, ctx)
             }
         };
 
         Ok((multi, n_failed))
     }
 
+    /// Load the signature files (`.sig` or `.sig.gz`) in a directory and
+    /// its subdirectories, e.g. one written by `convert`. This is not
+    /// tried by `load_collection`, which doesn't take directories other
+    /// than RocksDB indexes.
+    pub fn from_directory(dirpath: &Path, ctx: &RunContext) -> Result<(Self, usize)> {
+        debug!("multi from directory!");
+        let pattern = dirpath.join("**").join("*.sig*");
+        let paths: HashSet<String> = glob(pattern.as_str())?
+            .filter_map(|entry| entry.ok())
+            .filter(|path| path.is_file())
+            .filter_map(|path| path.to_str().map(String::from))
+            .filter(|path| path.ends_with(".sig") || path.ends_with(".sig.gz"))
+            .collect();
+        if paths.is_empty() {
+            bail!("no signature files found in directory '{}'", dirpath);
+        }
+
+        Ok(MultiCollection::load_set_of_paths(&paths, ctx))
+    }
+
     // Load from a sig file
     pub fn from_signature(sigpath: &Path) -> Result<Self> {
         debug!("multi from signature!");
@@ -266,6 +301,24 @@ impl MultiCollection {
         Ok(MultiCollection::new(vec![coll], false))
     }
 
+    /// Load signatures from (possibly compressed) JSON held in memory,
+    /// e.g. passed in from Python.
+    pub fn from_signature_json(data: &[u8]) -> Result<Self> {
+        debug!("multi from signature JSON!");
+        let signatures = match Signature::from_reader(data) {
+            Ok(sigs) => sigs,
+            // also accept a single signature, not in a list.
+            Err(e) => match serde_json::from_slice::<Signature>(data) {
+                Ok(sig) => vec![sig],
+                Err(_) => return Err(e).context("Failed to load signatures from JSON"),
+            },
+        };
+
+        let coll = Collection::from_sigs(signatures)
+            .context("Loaded signatures but failed to load as collection")?;
+        Ok(MultiCollection::new(vec![coll], false))
+    }
+
     pub fn len(&self) -> usize {
         let val: usize = self.collections.iter().map(|c| c.len()).sum();
         val
@@ -280,6 +333,90 @@ impl MultiCollection {
         self.item_iter().map(|(_, _, record)| record.scaled()).max()
     }
 
+    /// Fill in the ksize and moltype of `selection`, if unset, from the
+    /// sketches in this collection that match the rest of it. If they have
+    /// more than one ksize/moltype, fall back on the old defaults of k=31
+    /// and DNA when present, and fail otherwise.
+    pub fn complete_selection(&self, selection: Selection) -> Result<Selection> {
+        let (ksize, moltype) = (selection.ksize(), selection.moltype());
+        if ksize.is_some() && moltype.is_some() {
+            return Ok(selection);
+        }
+
+        let found: BTreeSet<(u32, String)> = self
+            .item_iter()
+            .map(|(_, _, record)| (record.ksize(), record.moltype()))
+            .filter(|(k, m)| {
+                ksize.is_none_or(|ksize| ksize == *k) && moltype.as_ref().is_none_or(|mt| mt == m)
+            })
+            .map(|(k, m)| (k, m.to_string()))
+            .collect();
+
+        let (found_ksize, found_moltype) = match found.len() {
+            0 => bail!(BranchwaterError::EmptyCollection(
+                "no sketches match the requested ksize and moltype".to_string()
+            )),
+            1 => found.into_iter().next().unwrap(),
+            _ => {
+                let options: Vec<String> = found
+                    .iter()
+                    .map(|(k, m)| format!("k={} {}", k, m))
+                    .collect();
+                let default = (
+                    ksize.unwrap_or(31),
+                    moltype
+                        .clone()
+                        .unwrap_or(HashFunctions::Murmur64Dna)
+                        .to_string(),
+                );
+                if !found.contains(&default) {
+                    bail!(BranchwaterError::IncompatibleSelection(format!(
+                        "sketches have more than one ksize/moltype ({}); choose one with -k/--ksize and -m/--moltype",
+                        options.join(", ")
+                    )));
+                }
+                eprintln!(
+                    "WARNING: sketches have more than one ksize/moltype ({}); using k={} {}",
+                    options.join(", "),
+                    default.0,
+                    default.1
+                );
+                default
+            }
+        };
+
+        let mut selection = selection;
+        if ksize.is_none() {
+            eprintln!("Setting ksize={} from the sketches", found_ksize);
+            selection.set_ksize(found_ksize);
+        }
+        if moltype.is_none() {
+            eprintln!("Setting moltype={} from the sketches", found_moltype);
+            selection.set_moltype(found_moltype.as_str().try_into()?);
+        }
+        Ok(selection)
+    }
+
+    /// Keep only the sketches whose manifest records satisfy `keep`.
+    pub fn filter_records(self, keep: impl Fn(&Record) -> bool) -> Self {
+        let collections = self
+            .collections
+            .into_iter()
+            .map(|c| {
+                let records: Vec<Record> = c
+                    .iter()
+                    .map(|(_, record)| record)
+                    .filter(|record| keep(record))
+                    .cloned()
+                    .collect();
+                Collection::new(records.into(), c.storage().clone())
+            })
+            .filter(|c| !c.is_empty())
+            .collect();
+
+        MultiCollection::new(collections, self.contains_revindex)
+    }
+
     // iterate over tuples
     pub fn item_iter(&self) -> impl Iterator<Item = (&Collection, Idx, &Record)> {
         let s: Vec<_> = self
@@ -334,8 +471,8 @@ impl MultiCollection {
 
                     Some(SmallSignature {
                         location: record.internal_location().to_string(),
-                        name: sig_name,
-                        md5sum: sig_md5,
+                        name: sig_name.into(),
+                        md5sum: sig_md5.into(),
                         minhash,
                     })
                 }
@@ -426,10 +563,11 @@ impl TryFrom<MultiCollection> for Collection {
     }
 }
 
-/// Track a name/minhash.
+/// Track a name/minhash. Names and md5sums are shared with the results
+/// that refer to this sketch, rather than copied into every row.
 pub struct SmallSignature {
     pub location: String,
-    pub name: String,
-    pub md5sum: String,
+    pub name: Arc<str>,
+    pub md5sum: Arc<str>,
     pub minhash: KmerMinHash,
 }
@@ -0,0 +1,197 @@
+//! Rewrite query and match names as results are written, for
+//! `--rename-query` and `--rename-match`.
+//!
+//! Database names often embed long paths or file extensions, which bloat
+//! outputs and break joins with other tables. Each rewrite is a sed-style
+//! substitution, `s/pattern/replacement/flags`, applied in order in the
+//! writer thread, so the searches themselves are unaffected.
+
+use anyhow::{anyhow, Result};
+use regex::{Regex, RegexBuilder};
+use std::borrow::Cow;
+
+/// One `s/pattern/replacement/flags` substitution.
+#[derive(Debug)]
+pub struct NameRewrite {
+    regex: Regex,
+    replacement: String,
+    global: bool,
+}
+
+impl NameRewrite {
+    /// Parse a sed-style substitution. Any character after the `s` is the
+    /// delimiter; the flags are `g` (replace every match, not just the
+    /// first) and `i` (ignore case). In the replacement, `\1` and `&`
+    /// are a capture group and the whole match, as in sed.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let invalid = |reason: &str| anyhow!("invalid rewrite '{}': {}", spec, reason);
+
+        let mut chars = spec.chars();
+        if chars.next() != Some('s') {
+            return Err(invalid("must look like 's/pattern/replacement/'"));
+        }
+        let Some(delim) = chars.next().filter(|c| !c.is_alphanumeric() && *c != '\\') else {
+            return Err(invalid("must look like 's/pattern/replacement/'"));
+        };
+
+        // split on unescaped delimiters; escaped ones are literal.
+        let mut parts = vec![String::new()];
+        let mut escaped = false;
+        for c in chars {
+            let in_pattern = parts.len() == 1;
+            let part = parts.last_mut().unwrap();
+            if escaped {
+                if c != delim {
+                    part.push('\\');
+                    part.push(c);
+                } else if in_pattern {
+                    part.push_str(&regex::escape(&c.to_string()));
+                } else {
+                    part.push(c);
+                }
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == delim {
+                parts.push(String::new());
+            } else {
+                part.push(c);
+            }
+        }
+        if escaped {
+            parts.last_mut().unwrap().push('\\');
+        }
+        let [pattern, replacement, flags] = <[String; 3]>::try_from(parts)
+            .map_err(|_| invalid("must look like 's/pattern/replacement/'"))?;
+
+        let mut global = false;
+        let mut ignore_case = false;
+        for flag in flags.chars() {
+            match flag {
+                'g' => global = true,
+                'i' => ignore_case = true,
+                other => return Err(invalid(&format!("unknown flag '{}'", other))),
+            }
+        }
+
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(ignore_case)
+            .build()
+            .map_err(|e| invalid(&e.to_string()))?;
+
+        Ok(NameRewrite {
+            regex,
+            replacement: sed_replacement(&replacement),
+            global,
+        })
+    }
+
+    pub fn apply<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        if self.global {
+            self.regex.replace_all(name, self.replacement.as_str())
+        } else {
+            self.regex.replace(name, self.replacement.as_str())
+        }
+    }
+}
+
+/// Translate a sed replacement into the `regex` crate's syntax: `\N` and
+/// `&` become `${N}` and `${0}`, and a literal `$` is escaped.
+fn sed_replacement(replacement: &str) -> String {
+    let mut out = String::new();
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(d) if d.is_ascii_digit() => out.push_str(&format!("${{{}}}", d)),
+                Some('$') => out.push_str("$$"),
+                Some(other) => out.push(other),
+                None => out.push('\\'),
+            },
+            '&' => out.push_str("${0}"),
+            '$' => out.push_str("$$"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// The rewrites for query names and for match names.
+#[derive(Debug, Default)]
+pub struct NameRewrites {
+    query: Vec<NameRewrite>,
+    matches: Vec<NameRewrite>,
+}
+
+impl NameRewrites {
+    /// Parse the query and match rewrites, or None if there are none.
+    pub fn new(query: Option<Vec<String>>, matches: Option<Vec<String>>) -> Result<Option<Self>> {
+        let parse = |specs: Option<Vec<String>>| -> Result<Vec<NameRewrite>> {
+            specs
+                .unwrap_or_default()
+                .iter()
+                .map(|spec| NameRewrite::parse(spec))
+                .collect()
+        };
+        let rewrites = NameRewrites {
+            query: parse(query)?,
+            matches: parse(matches)?,
+        };
+        if rewrites.query.is_empty() && rewrites.matches.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(rewrites))
+    }
+
+    fn apply(rewrites: &[NameRewrite], name: &str) -> String {
+        rewrites.iter().fold(name.to_string(), |name, rewrite| {
+            rewrite.apply(&name).into_owned()
+        })
+    }
+
+    pub fn query(&self, name: &str) -> String {
+        Self::apply(&self.query, name)
+    }
+
+    pub fn match_name(&self, name: &str) -> String {
+        Self::apply(&self.matches, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_apply() {
+        let rewrite = NameRewrite::parse(r"s/\.fa.*$//").unwrap();
+        assert_eq!(rewrite.apply("genomes/GCA_1.fa.gz"), "genomes/GCA_1");
+
+        // other delimiters, escaped delimiters, and flags.
+        let rewrite = NameRewrite::parse(r"s|^.*\|||").unwrap();
+        assert_eq!(rewrite.apply("a|b|c"), "c");
+        let rewrite = NameRewrite::parse("s/A/x/gi").unwrap();
+        assert_eq!(rewrite.apply("aAa"), "xxx");
+        let rewrite = NameRewrite::parse("s/A/x/").unwrap();
+        assert_eq!(rewrite.apply("AAA"), "xAA");
+    }
+
+    #[test]
+    fn test_sed_replacement() {
+        let rewrite = NameRewrite::parse(r"s/^(\w+) (\w+)/\2_\1/").unwrap();
+        assert_eq!(rewrite.apply("foo bar baz"), "bar_foo baz");
+        let rewrite = NameRewrite::parse("s/o+/[&]/").unwrap();
+        assert_eq!(rewrite.apply("foo"), "f[oo]");
+        let rewrite = NameRewrite::parse("s/x/$1/").unwrap();
+        assert_eq!(rewrite.apply("x"), "$1");
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(NameRewrite::parse("foo").is_err());
+        assert!(NameRewrite::parse("s/a/b").is_err());
+        assert!(NameRewrite::parse("s/a/b/c/").is_err());
+        assert!(NameRewrite::parse("s/a/b/q").is_err());
+        assert!(NameRewrite::parse("s/(/b/").is_err());
+    }
+}
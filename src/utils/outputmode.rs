@@ -0,0 +1,163 @@
+//! Appending to and sharding result CSVs, and writing results in other
+//! formats, for `--append`, `--output-shard-size`, and `--output-format`.
+//!
+//! The mode is carried in the command's `RunContext`, and read by the
+//! result writers when they open their output.
+
+use anyhow::Result;
+use camino::Utf8Path as Path;
+
+use super::is_stream_output;
+
+#[cfg(feature = "python")]
+use super::atomicfile::Output;
+#[cfg(feature = "python")]
+use super::open_output;
+#[cfg(feature = "python")]
+use anyhow::Context;
+#[cfg(feature = "python")]
+use std::fs::{metadata, OpenOptions};
+#[cfg(feature = "python")]
+use std::io::BufWriter;
+
+/// The format results are written in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    /// An Arrow IPC stream, written a batch of rows at a time.
+    Arrow,
+    /// A table in a SQLite database, indexed by query and match name.
+    Sqlite,
+}
+
+/// Output files with these extensions are written as SQLite databases.
+const SQLITE_EXTENSIONS: [&str; 3] = ["db", "sqlite", "sqlite3"];
+
+impl OutputFormat {
+    pub fn parse(format: &str) -> Result<Self> {
+        match format.to_ascii_lowercase().as_str() {
+            "csv" => Ok(OutputFormat::Csv),
+            "arrow" => Ok(OutputFormat::Arrow),
+            "sqlite" => Ok(OutputFormat::Sqlite),
+            _ => bail!(
+                "unknown output format '{}'; valid formats are: csv, arrow, sqlite",
+                format
+            ),
+        }
+    }
+
+    /// The format for an output path: SQLite for database files, and
+    /// otherwise CSV.
+    pub fn for_path(output: Option<&str>) -> Self {
+        let ext = output
+            .and_then(|path| Path::new(path).extension())
+            .map(|ext| ext.to_ascii_lowercase());
+        match ext {
+            Some(ext) if SQLITE_EXTENSIONS.contains(&ext.as_str()) => OutputFormat::Sqlite,
+            _ => OutputFormat::Csv,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OutputMode {
+    /// Append rows to an existing output, without a header.
+    pub append: bool,
+    /// Start a new output file after this many rows.
+    pub shard_size: Option<usize>,
+    /// The output format, or None to choose it from the output path.
+    pub format: Option<OutputFormat>,
+}
+
+impl OutputMode {
+    /// Build a mode, or None for the default of one new output file in
+    /// the format for its path.
+    pub fn new(
+        append: bool,
+        shard_size: Option<usize>,
+        format: Option<&str>,
+    ) -> Result<Option<Self>> {
+        if shard_size == Some(0) {
+            bail!("--output-shard-size must be at least 1");
+        }
+        if append && shard_size.is_some() {
+            bail!("--append and --output-shard-size cannot be used together");
+        }
+        let format = format.map(OutputFormat::parse).transpose()?;
+        if append && format == Some(OutputFormat::Arrow) {
+            bail!("--append cannot be used with --output-format arrow");
+        }
+        if shard_size.is_some() && format == Some(OutputFormat::Sqlite) {
+            bail!("--output-shard-size cannot be used with --output-format sqlite");
+        }
+        if !append && shard_size.is_none() && format.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(OutputMode {
+            append,
+            shard_size,
+            format,
+        }))
+    }
+
+    /// The format to write `output` in.
+    pub fn format(&self, output: Option<&str>) -> OutputFormat {
+        self.format
+            .unwrap_or_else(|| OutputFormat::for_path(output))
+    }
+
+    /// The mode for writing `mode` to `output`: stdout, named pipes, and
+    /// sockets can be neither appended to nor sharded, but keep their format.
+    pub fn for_output(mode: OutputMode, output: Option<&str>) -> Self {
+        match output {
+            Some(path) if !is_stream_output(Some(path)) => mode,
+            _ => {
+                if mode.append || mode.shard_size.is_some() {
+                    eprintln!(
+                        "WARNING: --append and --output-shard-size only apply to output files; ignoring."
+                    );
+                }
+                OutputMode {
+                    format: mode.format,
+                    ..Default::default()
+                }
+            }
+        }
+    }
+}
+
+/// The name of shard `n` of `output`: `out.csv` is followed by
+/// `out.part0001.csv`, `out.part0002.csv`, and so on.
+#[cfg(feature = "python")]
+pub fn shard_path(output: &str, n: usize) -> String {
+    if n == 0 {
+        return output.to_string();
+    }
+    let path = Path::new(output);
+    match path.extension() {
+        Some(ext) => path
+            .with_extension(format!("part{:04}.{}", n, ext))
+            .to_string(),
+        None => format!("{}.part{:04}", output, n),
+    }
+}
+
+/// Open `path` to add rows to the end; returns the writer and whether the
+/// file already has content (and so a header).
+#[cfg(feature = "python")]
+pub fn open_append(path: &str) -> Result<(Box<dyn Output>, bool)> {
+    let has_header = metadata(path).is_ok_and(|m| m.len() > 0);
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open output file '{}' for appending", path))?;
+    Ok((Box::new(BufWriter::new(file)), has_header))
+}
+
+/// Open shard `n` of `output`, as a new file.
+#[cfg(feature = "python")]
+pub fn open_shard(output: &str, n: usize) -> Result<Box<dyn Output>> {
+    open_output(&shard_path(output, n))
+}
@@ -0,0 +1,88 @@
+//! Reports of input paths that failed to load or were skipped, for
+//! `--failed-paths-out` and `--skipped-paths-out`.
+//!
+//! Paths are recorded wherever the loading and search code already warns
+//! about them, through the command's `RunContext`.
+
+use anyhow::Result;
+use std::fmt::Display;
+use std::sync::Mutex;
+
+use super::{finish_csv, open_stdout_or_file};
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct PathIssue {
+    path: String,
+    reason: String,
+}
+
+/// The paths that failed or were skipped during one command, written to
+/// CSV files with `path` and `reason` columns once it is done.
+#[derive(Debug)]
+pub struct PathReport {
+    failed_out: Option<String>,
+    skipped_out: Option<String>,
+    failed: Mutex<Vec<PathIssue>>,
+    skipped: Mutex<Vec<PathIssue>>,
+}
+
+impl PathReport {
+    pub fn new(failed_out: Option<String>, skipped_out: Option<String>) -> Self {
+        Self {
+            failed_out,
+            skipped_out,
+            failed: Mutex::new(Vec::new()),
+            skipped: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Record a path that could not be loaded.
+    pub fn record_failed(&self, path: &str, reason: impl Display) {
+        if self.failed_out.is_some() {
+            self.failed.lock().unwrap().push(PathIssue {
+                path: path.to_string(),
+                reason: reason.to_string(),
+            });
+        }
+    }
+
+    /// Record a path that loaded but held no usable sketches.
+    pub fn record_skipped(&self, path: &str, reason: impl Display) {
+        if self.skipped_out.is_some() {
+            self.skipped.lock().unwrap().push(PathIssue {
+                path: path.to_string(),
+                reason: reason.to_string(),
+            });
+        }
+    }
+
+    /// Write the reports asked for.
+    pub fn write(&self) -> Result<()> {
+        let failed = std::mem::take(&mut *self.failed.lock().unwrap());
+        write_issues(self.failed_out.as_deref(), failed, "failed")?;
+        let skipped = std::mem::take(&mut *self.skipped.lock().unwrap());
+        write_issues(self.skipped_out.as_deref(), skipped, "skipped")?;
+        Ok(())
+    }
+}
+
+fn write_issues(output: Option<&str>, mut issues: Vec<PathIssue>, what: &str) -> Result<()> {
+    let Some(output) = output else {
+        return Ok(());
+    };
+
+    // paths are recorded from parallel loops; sort for stable output.
+    issues.sort();
+    issues.dedup();
+
+    let mut writer = csv::Writer::from_writer(open_stdout_or_file(Some(output.to_string())));
+    // write the header explicitly, so that an empty report still has one.
+    writer.write_record(["path", "reason"])?;
+    for issue in &issues {
+        writer.write_record([&issue.path, &issue.reason])?;
+    }
+    finish_csv(writer)?.commit()?;
+
+    eprintln!("Wrote {} {} paths to '{}'", issues.len(), what, output);
+    Ok(())
+}
@@ -0,0 +1,115 @@
+//! Picklists, following sourmash's `file.csv:column:coltype[:pickstyle]` spec.
+use anyhow::{anyhow, Result};
+use std::collections::HashSet;
+
+use sourmash::manifest::Record;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PickColumn {
+    Name,
+    Ident,
+    IdentPrefix,
+    Md5,
+    Md5Prefix8,
+}
+
+impl std::str::FromStr for PickColumn {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "name" => Ok(PickColumn::Name),
+            "ident" => Ok(PickColumn::Ident),
+            "identprefix" => Ok(PickColumn::IdentPrefix),
+            "md5" => Ok(PickColumn::Md5),
+            "md5prefix8" | "md5short" => Ok(PickColumn::Md5Prefix8),
+            _ => bail!(
+                "unknown picklist column type '{}'; use name, ident, identprefix, md5, md5prefix8, or md5short",
+                s
+            ),
+        }
+    }
+}
+
+impl PickColumn {
+    /// Reduce a signature name or md5 to the form stored in the picklist.
+    fn key(&self, value: &str) -> String {
+        match self {
+            PickColumn::Name | PickColumn::Md5 => value.to_string(),
+            PickColumn::Ident => value.split(' ').next().unwrap_or_default().to_string(),
+            PickColumn::IdentPrefix => value
+                .split(' ')
+                .next()
+                .unwrap_or_default()
+                .split('.')
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            PickColumn::Md5Prefix8 => value.chars().take(8).collect(),
+        }
+    }
+
+    fn record_key(&self, record: &Record) -> String {
+        match self {
+            PickColumn::Md5 | PickColumn::Md5Prefix8 => self.key(record.md5()),
+            _ => self.key(record.name()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Picklist {
+    pub column_type: PickColumn,
+    pub exclude: bool,
+    values: HashSet<String>,
+}
+
+impl Picklist {
+    /// Load a picklist from `file.csv:column:coltype[:include|exclude]`.
+    pub fn load(spec: &str) -> Result<Self> {
+        let parts: Vec<&str> = spec.split(':').collect();
+        let (path, column, column_type, exclude) = match parts.as_slice() {
+            [path, column, coltype] => (*path, *column, coltype.parse::<PickColumn>()?, false),
+            [path, column, coltype, "include"] => {
+                (*path, *column, coltype.parse::<PickColumn>()?, false)
+            }
+            [path, column, coltype, "exclude"] => {
+                (*path, *column, coltype.parse::<PickColumn>()?, true)
+            }
+            _ => bail!(
+                "invalid picklist '{}'; use 'file.csv:column:coltype[:include|exclude]'",
+                spec
+            ),
+        };
+
+        let mut rdr = csv::Reader::from_path(path)
+            .map_err(|e| anyhow!("Failed to open picklist '{}': {}", path, e))?;
+        let Some(col) = rdr.headers()?.iter().position(|h| h == column) else {
+            bail!("column '{}' not found in picklist '{}'", column, path);
+        };
+
+        let mut values = HashSet::new();
+        for record in rdr.records() {
+            let record = record?;
+            if let Some(value) = record.get(col).filter(|v| !v.is_empty()) {
+                values.insert(column_type.key(value));
+            }
+        }
+        eprintln!(
+            "Loaded {} distinct values from picklist '{}'",
+            values.len(),
+            path
+        );
+
+        Ok(Picklist {
+            column_type,
+            exclude,
+            values,
+        })
+    }
+
+    /// Does this record pass the picklist?
+    pub fn matches(&self, record: &Record) -> bool {
+        self.values.contains(&self.column_type.record_key(record)) != self.exclude
+    }
+}
@@ -0,0 +1,151 @@
+//! Per-stage wall time reports, for `--profile`.
+//!
+//! A profile is carried in the command's `RunContext`, and the loading,
+//! search, and writing code adds the time it spends to the matching stage. Time in a stage nested inside another
+//! (e.g. selection while loading a collection) is only counted once, in
+//! the inner stage. Writing happens in its own thread, so its time
+//! overlaps with comparison.
+
+use anyhow::Result;
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::{finish_csv, open_stdout_or_file};
+
+thread_local! {
+    // time spent in stages nested inside the current one, on this thread.
+    static NESTED: Cell<Duration> = const { Cell::new(Duration::ZERO) };
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    /// Opening collections and reading sketches.
+    Loading,
+    /// Selecting sketches by ksize, moltype, and scaled.
+    Selection,
+    /// Comparing sketches; the search itself.
+    Comparison,
+    /// Writing results.
+    Writing,
+}
+
+impl Stage {
+    const ALL: [Stage; 4] = [
+        Stage::Loading,
+        Stage::Selection,
+        Stage::Comparison,
+        Stage::Writing,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Stage::Loading => "loading",
+            Stage::Selection => "selection",
+            Stage::Comparison => "comparison",
+            Stage::Writing => "writing",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Profile {
+    output: String,
+    command: String,
+    started: Instant,
+    stages: Mutex<BTreeMap<Stage, Duration>>,
+}
+
+impl Profile {
+    /// Start profiling `command`, to write the report to `output`.
+    pub fn new(output: String, command: &str) -> Self {
+        Profile {
+            output,
+            command: command.to_string(),
+            started: Instant::now(),
+            stages: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Add `elapsed` to `stage`, e.g. for time accumulated by a writer thread.
+    pub fn record(&self, stage: Stage, elapsed: Duration) {
+        *self.stages.lock().unwrap().entry(stage).or_default() += elapsed;
+    }
+
+    /// Write the report as JSON if the output ends in `.json`, and as a
+    /// CSV with `command`, `stage`, and `seconds` columns otherwise.
+    pub fn write(&self) -> Result<()> {
+        let total = self.started.elapsed();
+        let stages = self.stages.lock().unwrap();
+        let seconds = |stage: &Stage| stages.get(stage).copied().unwrap_or_default().as_secs_f64();
+
+        let mut out = open_stdout_or_file(Some(self.output.clone()));
+        if self.output.ends_with(".json") {
+            let stages: serde_json::Map<String, serde_json::Value> = Stage::ALL
+                .iter()
+                .map(|stage| (stage.name().to_string(), seconds(stage).into()))
+                .collect();
+            let report = serde_json::json!({
+                "command": self.command,
+                "total_seconds": total.as_secs_f64(),
+                "stages": stages,
+            });
+            serde_json::to_writer_pretty(&mut out, &report)?;
+            writeln!(out)?;
+            out.commit()?;
+        } else {
+            let mut writer = csv::Writer::from_writer(out);
+            writer.write_record(["command", "stage", "seconds"])?;
+            for stage in Stage::ALL.iter() {
+                writer.write_record([
+                    self.command.as_str(),
+                    stage.name(),
+                    &seconds(stage).to_string(),
+                ])?;
+            }
+            writer.write_record([
+                self.command.as_str(),
+                "total",
+                &total.as_secs_f64().to_string(),
+            ])?;
+            finish_csv(writer)?.commit()?;
+        }
+
+        eprintln!("Wrote profile to '{}'", self.output);
+        Ok(())
+    }
+}
+
+/// Adds the time until it is dropped to a stage, less any time spent in
+/// stages started (on the same thread) while it was running.
+pub struct StageTimer<'a> {
+    profile: Option<&'a Profile>,
+    stage: Stage,
+    started: Instant,
+    outer_nested: Duration,
+}
+
+impl<'a> StageTimer<'a> {
+    /// Start timing `stage` for `profile`, if any, until the returned
+    /// timer is dropped.
+    pub fn start(profile: Option<&'a Profile>, stage: Stage) -> Self {
+        StageTimer {
+            profile,
+            stage,
+            started: Instant::now(),
+            outer_nested: NESTED.with(|n| n.replace(Duration::ZERO)),
+        }
+    }
+}
+
+impl Drop for StageTimer<'_> {
+    fn drop(&mut self) {
+        let elapsed = self.started.elapsed();
+        let nested = NESTED.with(|n| n.replace(self.outer_nested + elapsed));
+        if let Some(profile) = self.profile {
+            profile.record(self.stage, elapsed.saturating_sub(nested));
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! Per-group summaries of query containment, for `--query-groups`.
+//!
+//! Queries (e.g. the MAGs from one sample) are assigned to groups with a
+//! groups CSV, as for `intersect` and `merge`. While the search runs, the
+//! containment of every group member in each against sketch is folded
+//! into one row per group and against sketch, so that the full set of
+//! comparisons need not be written out and aggregated afterwards.
+
+use serde::Serialize;
+use std::sync::Mutex;
+
+use super::multicollection::SmallSignature;
+#[cfg(feature = "python")]
+use super::{finish_csv, group_sketches, load_groups, open_stdout_or_file};
+#[cfg(feature = "python")]
+use anyhow::Result;
+
+#[derive(Serialize)]
+struct GroupRow {
+    group: String,
+    n_members: usize,
+    match_name: String,
+    match_md5: String,
+    max_query_containment: f64,
+    mean_query_containment: f64,
+    best_query_name: String,
+    n_matching_queries: usize,
+}
+
+/// The containment of one group's members in one against sketch.
+#[derive(Clone, Default)]
+struct Aggregate {
+    n_compared: usize,
+    sum: f64,
+    max: f64,
+    best_query_idx: Option<usize>,
+    n_matching: usize,
+}
+
+pub struct QueryGroups {
+    names: Vec<String>,
+    /// query index -> indices of the groups it belongs to.
+    query_groups: Vec<Vec<usize>>,
+    n_members: Vec<usize>,
+    rows: Mutex<Vec<GroupRow>>,
+}
+
+/// Accumulates the comparisons of every query with one against sketch.
+pub struct GroupAccumulator<'a> {
+    groups: &'a QueryGroups,
+    aggregates: Vec<Aggregate>,
+}
+
+impl QueryGroups {
+    /// Assign `queries` to the groups in the CSV at `path`.
+    #[cfg(feature = "python")]
+    pub fn load(path: &str, queries: &[SmallSignature]) -> Result<Self> {
+        let groups = load_groups(path)?;
+        let (grouped, n_ungrouped) = group_sketches(queries, &groups);
+        if n_ungrouped > 0 {
+            eprintln!(
+                "WARNING: {} queries are not in any group, and will not be summarized.",
+                n_ungrouped
+            );
+        }
+        if grouped.is_empty() {
+            bail!("No queries matched the names in '{}'", path);
+        }
+
+        let mut query_groups = vec![vec![]; queries.len()];
+        let mut names = vec![];
+        let mut n_members = vec![];
+        for (group_idx, (name, idxs)) in grouped.into_iter().enumerate() {
+            for idx in idxs.iter() {
+                query_groups[*idx].push(group_idx);
+            }
+            names.push(name);
+            n_members.push(idxs.len());
+        }
+        eprintln!("Summarizing queries in {} groups", names.len());
+
+        Ok(QueryGroups {
+            names,
+            query_groups,
+            n_members,
+            rows: Mutex::new(vec![]),
+        })
+    }
+
+    pub fn accumulator(&self) -> GroupAccumulator<'_> {
+        GroupAccumulator {
+            groups: self,
+            aggregates: vec![Aggregate::default(); self.names.len()],
+        }
+    }
+
+    /// Write one row per group and against sketch, sorted by group and
+    /// then by decreasing `max_query_containment`.
+    #[cfg(feature = "python")]
+    pub fn write(self, output: &str) -> Result<()> {
+        let mut rows = self.rows.into_inner().unwrap();
+        rows.sort_by(|a, b| {
+            a.group
+                .cmp(&b.group)
+                .then(b.max_query_containment.total_cmp(&a.max_query_containment))
+                .then_with(|| (&a.match_name, &a.match_md5).cmp(&(&b.match_name, &b.match_md5)))
+        });
+
+        let mut writer = csv::Writer::from_writer(open_stdout_or_file(Some(output.into())));
+        for row in rows.iter() {
+            writer.serialize(row)?;
+        }
+        finish_csv(writer)?.commit()?;
+
+        eprintln!("Wrote {} group summaries to '{}'", rows.len(), output);
+        Ok(())
+    }
+}
+
+impl GroupAccumulator<'_> {
+    /// Add the containment of query `query_idx` in the against sketch;
+    /// `matched` is whether it passed the search threshold.
+    pub fn add(&mut self, query_idx: usize, containment: f64, matched: bool) {
+        for group_idx in self.groups.query_groups[query_idx].iter() {
+            let agg = &mut self.aggregates[*group_idx];
+            agg.n_compared += 1;
+            agg.sum += containment;
+            if agg.best_query_idx.is_none() || containment > agg.max {
+                agg.max = containment;
+                agg.best_query_idx = Some(query_idx);
+            }
+            if matched {
+                agg.n_matching += 1;
+            }
+        }
+    }
+
+    /// Record the summaries for `against`, for groups with at least one
+    /// matching member (or all groups, if `keep_all`).
+    pub fn finish(self, queries: &[SmallSignature], against: &SmallSignature, keep_all: bool) {
+        let rows: Vec<GroupRow> = self
+            .aggregates
+            .into_iter()
+            .enumerate()
+            .filter(|(_, agg)| agg.n_compared > 0 && (keep_all || agg.n_matching > 0))
+            .map(|(group_idx, agg)| GroupRow {
+                group: self.groups.names[group_idx].clone(),
+                n_members: self.groups.n_members[group_idx],
+                match_name: against.name.to_string(),
+                match_md5: against.md5sum.to_string(),
+                max_query_containment: agg.max,
+                mean_query_containment: agg.sum / agg.n_compared as f64,
+                best_query_name: queries[agg.best_query_idx.unwrap()].name.to_string(),
+                n_matching_queries: agg.n_matching,
+            })
+            .collect();
+        if !rows.is_empty() {
+            self.groups.rows.lock().unwrap().extend(rows);
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! Sketch FASTA/FASTQ queries on the fly, so that search and gather
+//! commands can take sequence files as queries.
+
+use anyhow::{anyhow, Result};
+use camino::Utf8Path as Path;
+use rayon::prelude::*;
+
+use sourmash::collection::Collection;
+use sourmash::encodings::HashFunctions;
+use sourmash::selection::Selection;
+use sourmash::signature::Signature;
+
+use super::buildutils::{BuildCollection, MultiSelect, MultiSelection};
+use super::{detect_csv_type, load_fasta_fromfile, CSVType, FastaData, MultiCollection};
+
+pub(crate) const SEQUENCE_EXTENSIONS: [&str; 9] = [
+    "fa", "fasta", "fna", "ffn", "faa", "fas", "fq", "fastq", "fnq",
+];
+pub(crate) const COMPRESSION_EXTENSIONS: [&str; 4] = ["gz", "bz2", "xz", "zst"];
+
+/// Scaled to use when sketching queries, if the command doesn't give one.
+const DEFAULT_QUERY_SCALED: u32 = 1000;
+
+/// Is `path` a FASTA/FASTQ file (possibly compressed), or a CSV in the
+/// `manysketch` fromfile format?
+pub fn is_sequence_input(path: &Path) -> bool {
+    let mut stem = path.as_str();
+    if let Some(ext) = Path::new(stem).extension() {
+        if COMPRESSION_EXTENSIONS.contains(&ext) {
+            stem = &stem[..stem.len() - ext.len() - 1];
+        }
+    }
+
+    match Path::new(stem).extension() {
+        Some(ext) if SEQUENCE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()) => true,
+        Some("csv") => csv::Reader::from_path(path)
+            .and_then(|mut rdr| rdr.headers().cloned())
+            .is_ok_and(|headers| !matches!(detect_csv_type(&headers), CSVType::Unknown)),
+        _ => false,
+    }
+}
+
+/// The `manysketch` param string for sketches matching `selection`.
+fn param_str(selection: &Selection) -> Result<String> {
+    let moltype = match selection.moltype() {
+        Some(HashFunctions::Murmur64Dna) | None => "dna",
+        Some(HashFunctions::Murmur64Protein) => "protein",
+        Some(HashFunctions::Murmur64Dayhoff) => "dayhoff",
+        Some(HashFunctions::Murmur64Hp) => "hp",
+        Some(HashFunctions::Murmur64Skipm1n3) => "skipm1n3",
+        Some(HashFunctions::Murmur64Skipm2n3) => "skipm2n3",
+        Some(other) => bail!("cannot sketch queries for moltype '{}'", other),
+    };
+    let ksize = selection
+        .ksize()
+        .ok_or_else(|| anyhow!("a ksize is needed to sketch queries"))?;
+    let scaled = selection.scaled().unwrap_or(DEFAULT_QUERY_SCALED);
+    Ok(format!("{},k={},scaled={},abund", moltype, ksize, scaled))
+}
+
+fn sketch_one(templates: &BuildCollection, fasta: &FastaData) -> Result<Vec<Signature>> {
+    let mut sigs = templates.clone();
+    sigs.select(&MultiSelection::from_input_moltype(&fasta.input_type)?)?;
+
+    for path in &fasta.paths {
+        sigs.build_sigs_from_file_or_stdin(&fasta.input_type, fasta.name.clone(), path.to_string())
+            .map_err(|e| anyhow!("could not sketch '{}': {}", path, e))?;
+    }
+
+    Ok(sigs
+        .iter_mut()
+        .filter(|(record, _)| record.sequence_added)
+        .map(|(_, sig)| sig.clone())
+        .collect())
+}
+
+/// Sketch the sequence files in `path`, a FASTA/FASTQ file or a fromfile
+/// CSV, with the ksize, scaled, and moltype in `selection`.
+pub fn sketch_queries(path: &Path, selection: &Selection) -> Result<MultiCollection> {
+    let param_str = param_str(selection)?;
+    let templates = BuildCollection::from_param_str(&param_str)
+        .map_err(|e| anyhow!("Failed to parse params string: {}", e))?;
+
+    let fastas = if path.extension() == Some("csv") {
+        load_fasta_fromfile(path.to_string(), false)?.0
+    } else {
+        // protein-alphabet sketches are built from protein sequences.
+        let input_type = match param_str.split(',').next() {
+            Some("dna") | Some("skipm1n3") | Some("skipm2n3") => "dna",
+            _ => "protein",
+        };
+        vec![FastaData {
+            name: path.to_string(),
+            paths: vec![path.to_path_buf()],
+            input_type: input_type.to_string(),
+        }]
+    };
+
+    eprintln!(
+        "Sketching {} queries from '{}' with '{}'",
+        fastas.len(),
+        path,
+        param_str
+    );
+
+    let sigs: Vec<Signature> = fastas
+        .par_iter()
+        .map(|fasta| sketch_one(&templates, fasta))
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .collect();
+
+    if sigs.is_empty() {
+        bail!("No query sketches could be built from '{}'", path);
+    }
+
+    let coll = Collection::from_sigs(sigs)?;
+    Ok(MultiCollection::from(coll))
+}
@@ -0,0 +1,241 @@
+//! Per-run options and reports for the search commands: how results are
+//! written, and what is reported about the run once it is done.
+//!
+//! The Python bindings build one context for each command and pass it
+//! down to the loading, search, and writing code. Library callers use
+//! `RunContext::default()`, which writes plain output files and reports
+//! nothing.
+
+use anyhow::Result;
+use std::fmt::Display;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::dedup::{Aliases, Dedup};
+use super::exclude::ExcludeList;
+use super::outputmode::OutputMode;
+use super::pathreport::PathReport;
+use super::profile::{Profile, Stage, StageTimer};
+use super::sizefilter::SizeFilter;
+use super::status::{self, RunStatus};
+use super::{FlushPolicy, ReportType};
+
+#[derive(Clone, Debug, Default)]
+pub struct RunContext {
+    output_mode: Option<OutputMode>,
+    flush: Option<FlushPolicy>,
+    profile: Option<Arc<Profile>>,
+    path_report: Option<Arc<PathReport>>,
+    status: Option<Arc<RunStatus>>,
+    size_filter: Option<SizeFilter>,
+    exclude: Option<Arc<ExcludeList>>,
+    dedup: Option<Arc<Dedup>>,
+    verify: bool,
+}
+
+impl RunContext {
+    /// Write results with `mode`, if given.
+    pub fn with_output_mode(mut self, mode: Option<OutputMode>) -> Self {
+        self.output_mode = mode;
+        self
+    }
+
+    /// Flush result outputs every `rows` rows and/or every `interval_ms`
+    /// milliseconds, if either is given, in place of the default for each
+    /// output; see `FlushPolicy::for_output`.
+    pub fn with_flush_policy(mut self, rows: Option<usize>, interval_ms: Option<u64>) -> Self {
+        self.flush = (rows.is_some() || interval_ms.is_some())
+            .then(|| FlushPolicy::batched(rows.unwrap_or(0), interval_ms.unwrap_or(0)));
+        self
+    }
+
+    /// Write the time `command` spends in each stage to `output`, if given.
+    pub fn with_profile(mut self, output: Option<String>, command: &str) -> Self {
+        self.profile = output.map(|output| Arc::new(Profile::new(output, command)));
+        self
+    }
+
+    /// Write the paths that failed to load to `failed_out`, and those
+    /// skipped for holding no usable sketches to `skipped_out`, if given.
+    pub fn with_path_reports(
+        mut self,
+        failed_out: Option<String>,
+        skipped_out: Option<String>,
+    ) -> Self {
+        self.path_report = (failed_out.is_some() || skipped_out.is_some())
+            .then(|| Arc::new(PathReport::new(failed_out, skipped_out)));
+        self
+    }
+
+    /// Collect the outcome of `command`, for its exit status and summary;
+    /// see `RunStatus::finish`.
+    pub fn with_exit_status(
+        mut self,
+        command: &str,
+        detailed: bool,
+        summary_out: Option<String>,
+    ) -> Self {
+        self.status = Some(Arc::new(RunStatus::new(command, detailed, summary_out)));
+        self
+    }
+
+    /// Load only sketches that pass `filter`, if given.
+    pub fn with_size_filter(mut self, filter: Option<SizeFilter>) -> Self {
+        self.size_filter = filter;
+        self
+    }
+
+    /// Drop the records in `exclude`, if given, from against collections.
+    pub fn with_exclude_list(mut self, exclude: Option<ExcludeList>) -> Self {
+        self.exclude = exclude.map(Arc::new);
+        self
+    }
+
+    /// Collapse against sketches with identical md5s, if `dedup`.
+    pub fn with_dedup_by_md5(mut self, dedup: bool) -> Self {
+        self.dedup = dedup.then(Default::default);
+        self
+    }
+
+    /// Check the md5s of the sketches loaded, if `verify`.
+    pub fn with_verification(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// The mode for writing to `output`; see `OutputMode::for_output`.
+    pub fn output_mode(&self, output: Option<&str>) -> OutputMode {
+        OutputMode::for_output(self.output_mode.unwrap_or_default(), output)
+    }
+
+    /// When to flush the results written to `output`.
+    pub fn flush_policy(&self, output: Option<&str>) -> FlushPolicy {
+        self.flush
+            .unwrap_or_else(|| FlushPolicy::for_output(output))
+    }
+
+    /// The filter on sketch sizes, if any.
+    pub fn size_filter(&self) -> Option<SizeFilter> {
+        self.size_filter
+    }
+
+    /// The records to drop from against collections, if any.
+    pub fn exclude_list(&self) -> Option<&ExcludeList> {
+        self.exclude.as_deref()
+    }
+
+    /// The deduplication of against sketches, if any.
+    pub fn dedup(&self) -> Option<&Dedup> {
+        self.dedup.as_deref()
+    }
+
+    /// The aliases of the against sketches collapsed so far, if
+    /// deduplicating; see `Dedup::aliases`.
+    pub fn aliases(&self) -> Option<Arc<Aliases>> {
+        self.dedup.as_ref().map(|dedup| dedup.aliases())
+    }
+
+    /// True if the md5s of the sketches loaded are checked.
+    pub fn verifies(&self) -> bool {
+        self.verify
+    }
+
+    /// Start timing `stage`, until the returned timer is dropped.
+    pub fn timer(&self, stage: Stage) -> StageTimer<'_> {
+        StageTimer::start(self.profile.as_deref(), stage)
+    }
+
+    /// Run `f`, adding its wall time to `stage`.
+    pub fn time<T>(&self, stage: Stage, f: impl FnOnce() -> T) -> T {
+        let _timer = self.timer(stage);
+        f()
+    }
+
+    /// Add `elapsed` to `stage`, e.g. for time accumulated by a writer thread.
+    pub fn record_time(&self, stage: Stage, elapsed: Duration) {
+        if let Some(profile) = &self.profile {
+            profile.record(stage, elapsed);
+        }
+    }
+
+    /// True if failed or skipped paths are being reported, e.g. to skip
+    /// extra work otherwise.
+    pub fn reports_paths(&self) -> bool {
+        self.path_report.is_some()
+    }
+
+    /// Record the sketches loaded from a collection, and the paths skipped
+    /// and failed while loading it.
+    pub fn record_loaded(
+        &self,
+        report_type: ReportType,
+        loaded: usize,
+        skipped: usize,
+        failed: usize,
+    ) {
+        if let Some(status) = &self.status {
+            status.record_loaded(report_type, loaded, skipped, failed);
+        }
+    }
+
+    /// Record the comparisons or queries processed, as reported on the
+    /// command's `DONE.` line.
+    pub fn record_processed(&self, n: usize) {
+        if let Some(status) = &self.status {
+            status.record_processed(n);
+        }
+    }
+
+    /// Record result rows written to an output.
+    pub fn record_rows(&self, n: usize) {
+        if let Some(status) = &self.status {
+            status.record_rows(n);
+        }
+    }
+
+    /// Record a path that could not be loaded.
+    pub fn record_failed(&self, path: &str, reason: impl Display) {
+        if let Some(status) = &self.status {
+            status.record_failure();
+        }
+        if let Some(report) = &self.path_report {
+            report.record_failed(path, reason);
+        }
+    }
+
+    /// Record a path that loaded but held no usable sketches.
+    pub fn record_skipped(&self, path: &str, reason: impl Display) {
+        if let Some(report) = &self.path_report {
+            report.record_skipped(path, reason);
+        }
+    }
+
+    /// Finish the run that gave `result`, returning its exit status
+    /// (`status::SUCCESS` unless the outcome is collected) and writing the
+    /// summary, the path reports, and the profile. Reports are written
+    /// even if the run failed, since that is often when they are most
+    /// useful.
+    pub fn finish(&self, result: Result<()>) -> Result<u8> {
+        let mut result = match &self.status {
+            Some(status) => status.finish(result),
+            None => result.map(|()| status::SUCCESS),
+        };
+        if let Some(report) = &self.path_report {
+            if let Err(e) = report.write() {
+                eprintln!("Error writing failed/skipped path reports: {e}");
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        if let Some(profile) = &self.profile {
+            if let Err(e) = profile.write() {
+                eprintln!("Error writing profile: {e}");
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+}
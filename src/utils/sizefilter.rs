@@ -0,0 +1,83 @@
+//! Sketch size filters, for `--min-hashes` and `--max-hashes`.
+//!
+//! Sizes are the number of hashes recorded in each sketch's manifest
+//! record, i.e. at the sketch's own scaled, so sketches are filtered
+//! without being loaded. The filter is carried in the command's
+//! `RunContext` and applied by `load_collection` to everything it loads.
+
+use anyhow::Result;
+
+use super::{MultiCollection, RunContext};
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SizeFilter {
+    min_hashes: Option<usize>,
+    max_hashes: Option<usize>,
+}
+
+impl SizeFilter {
+    /// Build a filter, or None if neither bound is given.
+    pub fn new(min_hashes: Option<usize>, max_hashes: Option<usize>) -> Result<Option<Self>> {
+        if let (Some(min), Some(max)) = (min_hashes, max_hashes) {
+            if min > max {
+                bail!(
+                    "--min-hashes ({}) must not be greater than --max-hashes ({})",
+                    min,
+                    max
+                );
+            }
+        }
+        if min_hashes.is_none() && max_hashes.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(SizeFilter {
+            min_hashes,
+            max_hashes,
+        }))
+    }
+
+    fn keeps(&self, n_hashes: usize) -> bool {
+        self.min_hashes.is_none_or(|min| n_hashes >= min)
+            && self.max_hashes.is_none_or(|max| n_hashes <= max)
+    }
+
+    fn describe(&self) -> String {
+        match (self.min_hashes, self.max_hashes) {
+            (Some(min), Some(max)) => format!("between {} and {} hashes", min, max),
+            (Some(min), None) => format!("at least {} hashes", min),
+            (None, Some(max)) => format!("at most {} hashes", max),
+            (None, None) => "any number of hashes".to_string(),
+        }
+    }
+}
+
+/// Drop the sketches in `collection` that the size filter of `ctx`, if any,
+/// excludes, warning about (and reporting) what was dropped.
+pub fn apply(collection: MultiCollection, ctx: &RunContext) -> MultiCollection {
+    let Some(filter) = ctx.size_filter() else {
+        return collection;
+    };
+
+    let dropped: Vec<(String, usize)> = collection
+        .item_iter()
+        .filter(|(_, _, record)| !filter.keeps(*record.n_hashes()))
+        .map(|(_, _, record)| (record.internal_location().to_string(), *record.n_hashes()))
+        .collect();
+    if dropped.is_empty() {
+        return collection;
+    }
+
+    eprintln!(
+        "WARNING: skipped {} sketches without {}.",
+        dropped.len(),
+        filter.describe()
+    );
+    for (location, n_hashes) in dropped {
+        ctx.record_skipped(
+            &location,
+            format!("sketch has {} hashes, not {}", n_hashes, filter.describe()),
+        );
+    }
+
+    collection.filter_records(|record| filter.keeps(*record.n_hashes()))
+}
@@ -0,0 +1,33 @@
+//! sourmash-compatible output columns, for `--sourmash-compat`.
+//!
+//! Branchwater's prefetch CSVs have fewer columns than `sourmash
+//! prefetch` writes, which breaks tools that expect the standard schema.
+//! Compatibility is carried in the prefetch writer and in the
+//! `ColumnSelection` of result writers.
+
+/// The columns of `sourmash prefetch` CSV output, in order.
+pub const PREFETCH_COLUMNS: [&str; 23] = [
+    "intersect_bp",
+    "jaccard",
+    "max_containment",
+    "f_query_match",
+    "f_match_query",
+    "match_filename",
+    "match_name",
+    "match_md5",
+    "match_bp",
+    "query_filename",
+    "query_name",
+    "query_md5",
+    "query_bp",
+    "ksize",
+    "moltype",
+    "scaled",
+    "query_n_hashes",
+    "query_abundance",
+    "query_containment_ani",
+    "match_containment_ani",
+    "average_containment_ani",
+    "max_containment_ani",
+    "potential_false_negative",
+];
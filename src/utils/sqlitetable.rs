@@ -0,0 +1,261 @@
+//! Write results into a SQLite database, for `-o results.db` or
+//! `--output-format sqlite`.
+//!
+//! Each result type has its own table, e.g. `manysearch` or `gather`, with
+//! indexes on `query_name` and `match_name` so that the output can be
+//! queried as soon as it is written. Rows are inserted in transactions of
+//! many rows, committed at each flush, and the indexes are built once all
+//! rows are in.
+
+use anyhow::{Context, Result};
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params_from_iter, Connection};
+use serde_json::Value;
+use std::fs::{remove_file, rename};
+
+use super::columns::ColumnType;
+
+/// Commit after this many rows, even if not flushed.
+const BATCH_ROWS: usize = 100_000;
+
+/// The columns that are indexed, if present.
+const INDEXED_COLUMNS: [&str; 2] = ["query_name", "match_name"];
+
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn sql_type(ty: ColumnType) -> &'static str {
+    match ty {
+        ColumnType::Utf8 => "TEXT",
+        ColumnType::UInt64 | ColumnType::Boolean => "INTEGER",
+        ColumnType::Float64 => "REAL",
+    }
+}
+
+fn sql_value(ty: ColumnType, value: Value) -> SqlValue {
+    match (ty, value) {
+        (_, Value::Null) => SqlValue::Null,
+        (ColumnType::Utf8, Value::String(s)) => SqlValue::Text(s),
+        (ColumnType::Utf8, v) => SqlValue::Text(v.to_string()),
+        (ColumnType::UInt64, v) => v.as_i64().map(SqlValue::Integer).unwrap_or(SqlValue::Null),
+        (ColumnType::Float64, v) => v.as_f64().map(SqlValue::Real).unwrap_or(SqlValue::Null),
+        (ColumnType::Boolean, v) => v
+            .as_bool()
+            .map(|b| SqlValue::Integer(b as i64))
+            .unwrap_or(SqlValue::Null),
+    }
+}
+
+/// A table of result rows in a SQLite database. A new database is
+/// written to a temporary file and moved into place when finished; with
+/// `--append`, rows are added to the table in an existing database.
+pub struct SqliteTable {
+    conn: Connection,
+    path: String,
+    tmp_path: Option<String>,
+    table: String,
+    columns: Vec<String>,
+    types: Vec<ColumnType>,
+    insert: String,
+    pending: usize,
+}
+
+impl SqliteTable {
+    pub fn open(path: &str, append: bool) -> Result<Self> {
+        let tmp_path = (!append).then(|| format!("{}.tmp", path));
+        let db_path = tmp_path.as_deref().unwrap_or(path);
+        if tmp_path.is_some() {
+            // start afresh, rather than adding to a previous failed run.
+            let _ = remove_file(db_path);
+        }
+        let conn = Connection::open(db_path)
+            .with_context(|| format!("Failed to create output database '{}'", path))?;
+        Ok(SqliteTable {
+            conn,
+            path: path.to_string(),
+            tmp_path,
+            table: String::new(),
+            columns: vec![],
+            types: vec![],
+            insert: String::new(),
+            pending: 0,
+        })
+    }
+
+    /// Create `table` with the named and typed columns, if it doesn't
+    /// already exist, and start a transaction.
+    pub fn start<'a>(
+        &mut self,
+        table: &str,
+        columns: impl Iterator<Item = (&'a str, ColumnType)>,
+    ) -> Result<()> {
+        let (columns, types): (Vec<String>, Vec<ColumnType>) =
+            columns.map(|(name, ty)| (name.to_string(), ty)).unzip();
+        let definitions: Vec<String> = columns
+            .iter()
+            .zip(&types)
+            .map(|(name, ty)| format!("{} {}", quote(name), sql_type(*ty)))
+            .collect();
+        self.conn.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS {} ({})",
+            quote(table),
+            definitions.join(", ")
+        ))?;
+
+        let names: Vec<String> = columns.iter().map(|c| quote(c)).collect();
+        let placeholders = vec!["?"; columns.len()];
+        self.insert = format!(
+            "INSERT INTO {} ({}) VALUES ({})",
+            quote(table),
+            names.join(", "),
+            placeholders.join(", ")
+        );
+        self.table = table.to_string();
+        self.columns = columns;
+        self.types = types;
+        self.conn.execute_batch("BEGIN")?;
+        Ok(())
+    }
+
+    /// Add a row, with one value per column.
+    pub fn push(&mut self, row: Vec<Value>) -> Result<()> {
+        if row.len() != self.types.len() {
+            bail!(
+                "row has {} values, but table '{}' has {} columns",
+                row.len(),
+                self.table,
+                self.types.len()
+            );
+        }
+        let values = self.types.iter().zip(row).map(|(ty, v)| sql_value(*ty, v));
+        self.conn
+            .prepare_cached(&self.insert)?
+            .execute(params_from_iter(values))
+            .with_context(|| format!("Failed to add row to table '{}'", self.table))?;
+        self.pending += 1;
+        if self.pending >= BATCH_ROWS {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Commit the rows added so far.
+    pub fn flush(&mut self) -> Result<()> {
+        if self.conn.is_autocommit() {
+            return Ok(());
+        }
+        self.conn.execute_batch("COMMIT; BEGIN")?;
+        self.pending = 0;
+        Ok(())
+    }
+
+    /// Commit, index the table, and move a new database into place.
+    pub fn finish(&mut self) -> Result<()> {
+        if !self.conn.is_autocommit() {
+            self.conn.execute_batch("COMMIT")?;
+        }
+        for column in INDEXED_COLUMNS {
+            if !self.columns.iter().any(|c| c == column) {
+                continue;
+            }
+            self.conn.execute_batch(&format!(
+                "CREATE INDEX IF NOT EXISTS {} ON {} ({})",
+                quote(&format!("{}_{}", self.table, column)),
+                quote(&self.table),
+                quote(column)
+            ))?;
+        }
+        if let Some(tmp_path) = &self.tmp_path {
+            rename(tmp_path, &self.path)
+                .with_context(|| format!("Failed to move output database to '{}'", self.path))?;
+            self.tmp_path = None;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SqliteTable {
+    /// Remove a new database that was never finished, e.g. after an
+    /// error, rather than leaving a partial `.tmp` file behind.
+    fn drop(&mut self) {
+        if let Some(tmp_path) = self.tmp_path.take() {
+            let _ = remove_file(format!("{}-journal", tmp_path));
+            let _ = remove_file(tmp_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_write_and_query_table() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.db").to_str().unwrap().to_string();
+
+        let columns = [
+            ("query_name", ColumnType::Utf8),
+            ("intersect_bp", ColumnType::UInt64),
+            ("jaccard", ColumnType::Float64),
+        ];
+        for append in [false, true] {
+            let mut table = SqliteTable::open(&path, append).unwrap();
+            table.start("manysearch", columns.into_iter()).unwrap();
+            table
+                .push(vec![json!("a"), json!(1000), json!(0.5)])
+                .unwrap();
+            table
+                .push(vec![json!("b"), json!(2000), json!(null)])
+                .unwrap();
+            // rows must match the table.
+            assert!(table.push(vec![json!("c")]).is_err());
+            table.finish().unwrap();
+        }
+        assert!(!std::path::Path::new(&format!("{}.tmp", path)).exists());
+
+        let conn = Connection::open(&path).unwrap();
+        let (rows, total_bp): (i64, i64) = conn
+            .query_row(
+                "SELECT COUNT(*), SUM(intersect_bp) FROM manysearch",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((rows, total_bp), (4, 6000));
+        let nulls: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM manysearch WHERE jaccard IS NULL",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(nulls, 2);
+        let index: String = conn
+            .query_row(
+                "SELECT name FROM sqlite_master WHERE type = 'index'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(index, "manysearch_query_name");
+    }
+
+    #[test]
+    fn test_unfinished_table_is_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.db").to_str().unwrap().to_string();
+
+        let mut table = SqliteTable::open(&path, false).unwrap();
+        table
+            .start("manysearch", [("query_name", ColumnType::Utf8)].into_iter())
+            .unwrap();
+        table.push(vec![json!("a")]).unwrap();
+        assert!(std::path::Path::new(&format!("{}.tmp", path)).exists());
+        drop(table);
+
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,171 @@
+//! Exit statuses and run summaries for the search commands.
+//!
+//! The outcome of a command is collected in its `RunContext`: collection
+//! loading counts the sketches it loads, skips, and fails on, result
+//! writers count the rows they write, and failures are counted wherever
+//! paths are recorded as failed. At the end of the command, the outcome
+//! gives the exit status for `--detailed-exit-codes` (telling "no
+//! results" and "some inputs failed to load" apart from plain success)
+//! and a JSON summary, printed on one `SUMMARY:` line of stderr and
+//! written to `--summary-out`.
+
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use super::{open_stdout_or_file, ReportType};
+
+/// The command ran and wrote at least one result.
+pub const SUCCESS: u8 = 0;
+/// The command failed.
+pub const ERROR: u8 = 1;
+/// The command ran, but no results passed the threshold.
+pub const NO_RESULTS: u8 = 3;
+/// The command ran, but some input paths failed to load.
+pub const PARTIAL_FAILURE: u8 = 4;
+
+#[derive(Debug, Default, serde::Serialize)]
+struct Inputs {
+    loaded: usize,
+    skipped_paths: usize,
+    failed_paths: usize,
+}
+
+#[derive(Debug)]
+struct Outcome {
+    started: Instant,
+    inputs: BTreeMap<&'static str, Inputs>,
+    processed: usize,
+    rows: usize,
+    failures: usize,
+}
+
+impl Outcome {
+    fn new() -> Self {
+        Outcome {
+            started: Instant::now(),
+            inputs: BTreeMap::new(),
+            processed: 0,
+            rows: 0,
+            failures: 0,
+        }
+    }
+
+    fn status(&self) -> u8 {
+        if self.failures > 0 {
+            PARTIAL_FAILURE
+        } else if self.rows == 0 {
+            NO_RESULTS
+        } else {
+            SUCCESS
+        }
+    }
+
+    fn summary(&self, command: &str, status: u8, error: Option<String>) -> serde_json::Value {
+        serde_json::json!({
+            "command": command,
+            "status": status,
+            "error": error,
+            "inputs": self.inputs,
+            "processed": self.processed,
+            "results": self.rows,
+            "failed_paths": self.failures,
+            "seconds": self.started.elapsed().as_secs_f64(),
+        })
+    }
+}
+
+/// The outcome of one command, and how to report it.
+#[derive(Debug)]
+pub struct RunStatus {
+    command: String,
+    detailed: bool,
+    summary_out: Option<String>,
+    outcome: Mutex<Outcome>,
+}
+
+impl RunStatus {
+    pub fn new(command: &str, detailed: bool, summary_out: Option<String>) -> Self {
+        Self {
+            command: command.to_string(),
+            detailed,
+            summary_out,
+            outcome: Mutex::new(Outcome::new()),
+        }
+    }
+
+    /// Record the sketches loaded from a collection, and the paths skipped
+    /// and failed while loading it.
+    pub fn record_loaded(
+        &self,
+        report_type: ReportType,
+        loaded: usize,
+        skipped: usize,
+        failed: usize,
+    ) {
+        let key = match report_type {
+            ReportType::Query => "query",
+            ReportType::Against => "against",
+            ReportType::General => "analysis",
+        };
+        let mut outcome = self.outcome.lock().unwrap();
+        let inputs = outcome.inputs.entry(key).or_default();
+        inputs.loaded += loaded;
+        inputs.skipped_paths += skipped;
+        inputs.failed_paths += failed;
+    }
+
+    /// Record the comparisons or queries a command processed, as reported
+    /// on its `DONE.` line.
+    pub fn record_processed(&self, n: usize) {
+        self.outcome.lock().unwrap().processed += n;
+    }
+
+    /// Record result rows written to an output.
+    pub fn record_rows(&self, n: usize) {
+        self.outcome.lock().unwrap().rows += n;
+    }
+
+    /// Record an input path that failed to load.
+    pub fn record_failure(&self) {
+        self.outcome.lock().unwrap().failures += 1;
+    }
+
+    /// Print a summary of the run that gave `result`, write it to
+    /// `summary_out` if given, and return the exit status: `SUCCESS`, or
+    /// with `detailed`, `NO_RESULTS` if it wrote no result rows and
+    /// `PARTIAL_FAILURE` if any input failed to load. Errors are returned
+    /// as errors, for the caller to report with `ERROR`; the summary is
+    /// written either way.
+    pub fn finish(&self, result: Result<()>) -> Result<u8> {
+        let outcome = self.outcome.lock().unwrap();
+        let (status, error) = match &result {
+            Ok(()) if self.detailed => (outcome.status(), None),
+            Ok(()) => (SUCCESS, None),
+            Err(e) => (ERROR, Some(e.to_string())),
+        };
+
+        let summary = outcome.summary(&self.command, status, error);
+        eprintln!("SUMMARY: {}", summary);
+        if let Some(output) = &self.summary_out {
+            if let Err(e) = write_summary(output, &summary) {
+                eprintln!("Error writing run summary: {e}");
+                if result.is_ok() {
+                    return Err(e);
+                }
+            }
+        }
+
+        result?;
+        Ok(status)
+    }
+}
+
+fn write_summary(output: &str, summary: &serde_json::Value) -> Result<()> {
+    let mut out = open_stdout_or_file(Some(output.to_string()));
+    serde_json::to_writer_pretty(&mut out, summary)?;
+    writeln!(out)?;
+    out.commit()
+}
@@ -0,0 +1,164 @@
+//! Hashes to remove from queries before comparison, for `--stoplist`.
+//!
+//! A stoplist is a sketch file (e.g. of adapters, a host genome, or the
+//! ubiquitous hashes written by `check --output-stoplist`); the hashes in
+//! its sketches are removed from each query with the same ksize and
+//! moltype as it is loaded, so that searches don't need a separate
+//! `sig subtract` step. The stoplist is passed to the search and gather
+//! commands, which remove it from each query they load.
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use sourmash::encodings::HashFunctions;
+use sourmash::signature::SigsTrait;
+use sourmash::sketch::minhash::KmerMinHash;
+
+use super::SmallSignature;
+
+#[cfg(feature = "python")]
+use super::{load_collection, ReportType, RunContext};
+#[cfg(feature = "python")]
+use anyhow::Result;
+#[cfg(feature = "python")]
+use sourmash::selection::Selection;
+
+/// The hashes of one stoplist sketch.
+struct StopHashes {
+    ksize: usize,
+    hash_function: HashFunctions,
+    max_hash: u64,
+    hashes: HashSet<u64>,
+}
+
+pub struct Stoplist {
+    #[cfg(feature = "python")]
+    path: String,
+    sketches: Vec<StopHashes>,
+    n_queries: AtomicUsize,
+    n_affected: AtomicUsize,
+    n_removed: AtomicU64,
+    n_partial: AtomicUsize,
+}
+
+impl Stoplist {
+    /// Load the sketches in `path` with the ksize and moltype in
+    /// `selection`, if given, at any scaled.
+    #[cfg(feature = "python")]
+    pub fn load(path: &str, selection: &Selection) -> Result<Self> {
+        let mut stop_selection = Selection::default();
+        if let Some(ksize) = selection.ksize() {
+            stop_selection.set_ksize(ksize);
+        }
+        if let Some(moltype) = selection.moltype() {
+            stop_selection.set_moltype(moltype);
+        }
+
+        let collection = load_collection(
+            &path.to_string(),
+            &stop_selection,
+            ReportType::General,
+            false,
+            &RunContext::default(),
+        )?;
+        let sketches: Vec<StopHashes> = collection
+            .load_sketches()?
+            .into_iter()
+            .map(|sketch| StopHashes {
+                ksize: sketch.minhash.ksize(),
+                hash_function: sketch.minhash.hash_function(),
+                max_hash: sketch.minhash.max_hash(),
+                hashes: sketch.minhash.mins().into_iter().collect(),
+            })
+            .collect();
+        if sketches.is_empty() {
+            bail!("no compatible sketches in stoplist '{}'", path);
+        }
+        eprintln!(
+            "Loaded {} stoplist hashes from {} sketches in '{}'",
+            sketches.iter().map(|s| s.hashes.len()).sum::<usize>(),
+            sketches.len(),
+            path
+        );
+
+        Ok(Stoplist {
+            path: path.to_string(),
+            sketches,
+            n_queries: AtomicUsize::new(0),
+            n_affected: AtomicUsize::new(0),
+            n_removed: AtomicU64::new(0),
+            n_partial: AtomicUsize::new(0),
+        })
+    }
+
+    fn remove_from(&self, mh: &mut KmerMinHash) {
+        let mut removed = vec![];
+        let mut partial = false;
+        for stop in self.sketches.iter() {
+            if stop.ksize != mh.ksize() || stop.hash_function != mh.hash_function() {
+                continue;
+            }
+            // a stoplist at a higher scaled only covers some query hashes.
+            partial |= stop.max_hash < mh.max_hash();
+            removed.extend(mh.iter_mins().filter(|h| stop.hashes.contains(*h)).copied());
+        }
+
+        removed.sort_unstable();
+        removed.dedup();
+
+        self.n_queries.fetch_add(1, Ordering::SeqCst);
+        if partial {
+            self.n_partial.fetch_add(1, Ordering::SeqCst);
+        }
+        if removed.is_empty() {
+            return;
+        }
+        self.n_affected.fetch_add(1, Ordering::SeqCst);
+        self.n_removed
+            .fetch_add(removed.len() as u64, Ordering::SeqCst);
+        mh.remove_many(removed).expect("cannot remove hashes");
+    }
+
+    #[cfg(feature = "python")]
+    fn report(&self) {
+        eprintln!(
+            "Removed {} stoplist hashes from {} of {} queries, using '{}'",
+            self.n_removed.load(Ordering::SeqCst),
+            self.n_affected.load(Ordering::SeqCst),
+            self.n_queries.load(Ordering::SeqCst),
+            self.path
+        );
+        let n_partial = self.n_partial.load(Ordering::SeqCst);
+        if n_partial > 0 {
+            eprintln!(
+                "WARNING: {} queries have a lower scaled than the stoplist, so only some of their hashes could be stoplisted.",
+                n_partial
+            );
+        }
+    }
+}
+
+/// Report how many hashes `stoplist`, if given, removed from how many
+/// queries.
+#[cfg(feature = "python")]
+pub fn report(stoplist: Option<&Stoplist>) {
+    if let Some(stoplist) = stoplist {
+        stoplist.report();
+    }
+}
+
+/// Remove the hashes in `stoplist`, if given, from a query.
+pub fn apply(stoplist: Option<&Stoplist>, mh: &mut KmerMinHash) {
+    if let Some(stoplist) = stoplist {
+        stoplist.remove_from(mh);
+    }
+}
+
+/// Remove the hashes in `stoplist`, if given, from queries.
+pub fn apply_sketches(stoplist: Option<&Stoplist>, sketches: &mut [SmallSignature]) {
+    if let Some(stoplist) = stoplist {
+        for sketch in sketches.iter_mut() {
+            stoplist.remove_from(&mut sketch.minhash);
+        }
+    }
+}
@@ -0,0 +1,498 @@
+//! Taxonomic summaries of gather results, computed as results are written.
+use anyhow::{Context, Result};
+use csv::{Reader, Writer};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+use std::sync::mpsc::Receiver;
+use std::thread::JoinHandle;
+
+use crate::utils::columns::{ColumnSelection, ResultWriter};
+use crate::utils::{
+    batch_rows, finish_csv, open_stdout_or_file, Batched, BranchwaterGatherResult, RunContext,
+};
+
+/// Ranks we summarize at, in order; only those present in the lineages
+/// file are used.
+pub const RANKS: [&str; 8] = [
+    "superkingdom",
+    "phylum",
+    "class",
+    "order",
+    "family",
+    "genus",
+    "species",
+    "strain",
+];
+
+/// Columns that may hold the identifier in a lineages file.
+const IDENT_COLUMNS: [&str; 3] = ["ident", "identifiers", "accession"];
+
+/// One lineage: a name (possibly empty) per rank in `LineageDB::ranks`,
+/// plus per-rank taxids if the lineages file has a `taxpath` column.
+#[derive(Clone, Debug)]
+pub struct Lineage {
+    pub names: Vec<String>,
+    pub taxids: Vec<String>,
+}
+
+/// Identifier -> lineage mapping, loaded from a sourmash-style lineages CSV.
+pub struct LineageDB {
+    pub ranks: Vec<&'static str>,
+    lineages: HashMap<String, Lineage>,
+}
+
+/// Strip the version from an identifier, e.g. GCF_000005845.2 -> GCF_000005845.
+pub(crate) fn strip_version(ident: &str) -> &str {
+    ident.split('.').next().unwrap_or(ident)
+}
+
+impl LineageDB {
+    pub fn load(path: &str) -> Result<Self> {
+        let mut rdr = Reader::from_path(path)
+            .with_context(|| format!("Failed to open taxonomy file: '{}'", path))?;
+        let headers = rdr.headers()?.clone();
+        let column = |name: &str| headers.iter().position(|h| h == name);
+
+        let Some(ident_col) = IDENT_COLUMNS.iter().find_map(|c| column(c)) else {
+            bail!(
+                "taxonomy file '{}' has no identifier column (one of {:?})",
+                path,
+                IDENT_COLUMNS
+            );
+        };
+        let taxpath_col = column("taxpath");
+
+        let (ranks, rank_cols): (Vec<&'static str>, Vec<usize>) = RANKS
+            .iter()
+            .filter_map(|rank| column(rank).map(|col| (*rank, col)))
+            .unzip();
+        if ranks.is_empty() {
+            bail!("taxonomy file '{}' has no rank columns", path);
+        }
+
+        let mut lineages = HashMap::new();
+        for record in rdr.records() {
+            let record = record?;
+            let ident = record.get(ident_col).unwrap_or_default().to_string();
+            let lineage = Lineage {
+                names: rank_cols
+                    .iter()
+                    .map(|&col| record.get(col).unwrap_or_default().to_string())
+                    .collect(),
+                taxids: taxpath_col
+                    .and_then(|col| record.get(col))
+                    .map(|t| t.split('|').map(|t| t.to_string()).collect())
+                    .unwrap_or_default(),
+            };
+            let stripped = strip_version(&ident).to_string();
+            if stripped != ident {
+                lineages.entry(stripped).or_insert_with(|| lineage.clone());
+            }
+            lineages.insert(ident, lineage);
+        }
+        eprintln!("Loaded {} lineages from '{}'", lineages.len(), path);
+
+        Ok(LineageDB { ranks, lineages })
+    }
+
+    /// Find the lineage for a match name; like sourmash, we use the first
+    /// space-separated word as the identifier, ignoring versions.
+    pub fn get(&self, match_name: &str) -> Option<&Lineage> {
+        let ident = match_name.split(' ').next().unwrap_or_default();
+        self.lineages
+            .get(ident)
+            .or_else(|| self.lineages.get(strip_version(ident)))
+    }
+}
+
+/// Taxonomy-related outputs requested for a gather run.
+pub struct TaxonomyOptions {
+    pub lineages_path: String,
+    pub summary_output: Option<String>,
+    pub cami_output: Option<String>,
+    pub kraken_output: Option<String>,
+}
+
+/// Sums of gather results assigned to one lineage, at one rank.
+#[derive(Default, Clone)]
+pub struct RankSum {
+    pub fraction: f64,
+    pub f_weighted: f64,
+    pub bp: u64,
+    /// abundance-weighted bp, i.e. weighted hashes * scaled.
+    pub weighted_bp: u64,
+    /// '|'-separated taxids down to this rank; empty if unknown.
+    pub taxpath: String,
+}
+
+/// Gather results for one query, summed by rank and lineage.
+#[derive(Default)]
+pub struct QuerySummary {
+    pub query_md5: String,
+    pub query_filename: String,
+    pub total_weighted_hashes: u64,
+    pub scaled: u32,
+    /// per rank (index into LineageDB::ranks): lineage -> sums
+    pub by_rank: Vec<BTreeMap<String, RankSum>>,
+}
+
+#[derive(Serialize)]
+struct SummaryRow<'a> {
+    query_name: &'a str,
+    rank: &'a str,
+    fraction: f64,
+    lineage: &'a str,
+    query_md5: &'a str,
+    query_filename: &'a str,
+    f_weighted_at_rank: f64,
+    bp_match_at_rank: u64,
+    total_weighted_hashes: u64,
+}
+
+/// Rolls up gather results by rank, one query at a time.
+pub struct TaxSummarizer {
+    pub lineages: LineageDB,
+    pub options: TaxonomyOptions,
+    /// query_name -> summary; BTreeMap so output is sorted by query.
+    pub queries: BTreeMap<String, QuerySummary>,
+    missing: HashSet<String>,
+}
+
+impl TaxSummarizer {
+    /// Load the lineages file up front, so that errors are reported
+    /// before any searching is done.
+    pub fn load(options: TaxonomyOptions) -> Result<Self> {
+        let lineages = LineageDB::load(&options.lineages_path)?;
+        Ok(TaxSummarizer::new(lineages, options))
+    }
+
+    pub fn new(lineages: LineageDB, options: TaxonomyOptions) -> Self {
+        TaxSummarizer {
+            lineages,
+            options,
+            queries: BTreeMap::new(),
+            missing: HashSet::new(),
+        }
+    }
+
+    pub fn add(&mut self, result: &BranchwaterGatherResult) {
+        let n_ranks = self.lineages.ranks.len();
+        let summary = self
+            .queries
+            .entry(result.query_name.clone())
+            .or_insert_with(|| QuerySummary {
+                query_md5: result.query_md5.clone(),
+                query_filename: result.query_filename.clone(),
+                total_weighted_hashes: result.total_weighted_hashes,
+                scaled: result.scaled,
+                by_rank: vec![BTreeMap::new(); n_ranks],
+            });
+
+        let Some(lineage) = self.lineages.get(&result.match_name) else {
+            if self.missing.insert(result.match_name.clone()) {
+                eprintln!(
+                    "WARNING: no lineage found for '{}'; treating as unclassified",
+                    result.match_name
+                );
+            }
+            return;
+        };
+
+        for (i, name) in lineage.names.iter().enumerate() {
+            // stop at the first missing rank; this match is unclassified below it.
+            if name.is_empty() {
+                break;
+            }
+            let lineage_str = lineage.names[..=i].join(";");
+            let sum = summary.by_rank[i]
+                .entry(lineage_str)
+                .or_insert_with(|| RankSum {
+                    taxpath: lineage
+                        .taxids
+                        .get(..=i)
+                        .map(|t| t.join("|"))
+                        .unwrap_or_default(),
+                    ..Default::default()
+                });
+            sum.fraction += result.f_unique_to_query;
+            sum.f_weighted += result.f_unique_weighted;
+            sum.bp += result.unique_intersect_bp;
+            sum.weighted_bp += result.n_unique_weighted_found * result.scaled as u64;
+        }
+    }
+
+    pub fn n_missing(&self) -> usize {
+        self.missing.len()
+    }
+
+    /// Write a `sourmash tax metagenome`-style summary CSV, with an
+    /// 'unclassified' row per rank for the remainder of each query.
+    pub fn write_summary(&self, output: Option<String>) -> Result<()> {
+        let mut writer = Writer::from_writer(open_stdout_or_file(output));
+
+        for (query_name, summary) in &self.queries {
+            for (i, rank) in self.lineages.ranks.iter().enumerate() {
+                let mut sums: Vec<_> = summary.by_rank[i].iter().collect();
+                sums.sort_by(|a, b| b.1.fraction.total_cmp(&a.1.fraction));
+
+                let mut total = RankSum::default();
+                for (lineage, sum) in sums {
+                    total.fraction += sum.fraction;
+                    total.f_weighted += sum.f_weighted;
+                    writer.serialize(SummaryRow {
+                        query_name,
+                        rank,
+                        fraction: sum.fraction,
+                        lineage,
+                        query_md5: &summary.query_md5,
+                        query_filename: &summary.query_filename,
+                        f_weighted_at_rank: sum.f_weighted,
+                        bp_match_at_rank: sum.bp,
+                        total_weighted_hashes: summary.total_weighted_hashes,
+                    })?;
+                }
+
+                writer.serialize(SummaryRow {
+                    query_name,
+                    rank,
+                    fraction: (1.0 - total.fraction).max(0.0),
+                    lineage: "unclassified",
+                    query_md5: &summary.query_md5,
+                    query_filename: &summary.query_filename,
+                    f_weighted_at_rank: (1.0 - total.f_weighted).max(0.0),
+                    bp_match_at_rank: 0,
+                    total_weighted_hashes: summary.total_weighted_hashes,
+                })?;
+            }
+        }
+        finish_csv(writer)?.commit()
+    }
+
+    /// Write a CAMI Bioboxes profiling file, one sample per query. Taxids
+    /// come from the `taxpath` column of the lineages file; without one,
+    /// lineage names are used in their place.
+    pub fn write_cami(&self, output: Option<String>) -> Result<()> {
+        let mut out = open_stdout_or_file(output);
+
+        for (query_name, summary) in &self.queries {
+            writeln!(out, "@SampleID:{}", query_name)?;
+            writeln!(out, "@Version:0.10.0")?;
+            writeln!(out, "@Ranks:{}", self.lineages.ranks.join("|"))?;
+            writeln!(out, "@__program__:sourmash_plugin_branchwater")?;
+            writeln!(out, "@@TAXID\tRANK\tTAXPATH\tTAXPATHSN\tPERCENTAGE")?;
+
+            for (i, rank) in self.lineages.ranks.iter().enumerate() {
+                let mut sums: Vec<_> = summary.by_rank[i].iter().collect();
+                sums.sort_by(|a, b| b.1.f_weighted.total_cmp(&a.1.f_weighted));
+
+                for (lineage, sum) in sums {
+                    let taxpathsn = lineage.replace(';', "|");
+                    let taxpath = if sum.taxpath.is_empty() {
+                        &taxpathsn
+                    } else {
+                        &sum.taxpath
+                    };
+                    let taxid = taxpath.rsplit('|').next().unwrap_or_default();
+                    writeln!(
+                        out,
+                        "{}\t{}\t{}\t{}\t{:.5}",
+                        taxid,
+                        rank,
+                        taxpath,
+                        taxpathsn,
+                        sum.f_weighted * 100.0
+                    )?;
+                }
+            }
+            writeln!(out)?;
+        }
+        out.commit()
+    }
+
+    /// Write a kraken-style report for one query: percent, clade bp,
+    /// direct bp, rank code, taxid, and indented name, in tree order.
+    /// Counts are abundance-weighted bp rather than reads.
+    pub fn write_kraken(&self, summary: &QuerySummary, out: &mut dyn Write) -> Result<()> {
+        let total_bp = summary.total_weighted_hashes * summary.scaled as u64;
+        let classified_bp: u64 = summary
+            .by_rank
+            .first()
+            .map(|sums| sums.values().map(|s| s.weighted_bp).sum())
+            .unwrap_or(0);
+        let pct = |bp: u64| {
+            if total_bp > 0 {
+                bp as f64 * 100.0 / total_bp as f64
+            } else {
+                0.0
+            }
+        };
+
+        let unclassified_bp = total_bp.saturating_sub(classified_bp);
+        if unclassified_bp > 0 {
+            writeln!(
+                out,
+                "{:6.2}\t{}\t{}\tU\t0\tunclassified",
+                pct(unclassified_bp),
+                unclassified_bp,
+                unclassified_bp
+            )?;
+        }
+        writeln!(
+            out,
+            "{:6.2}\t{}\t0\tR\t1\troot",
+            pct(classified_bp),
+            classified_bp
+        )?;
+        self.write_kraken_children(summary, 0, "", out, &pct)
+    }
+
+    fn write_kraken_children(
+        &self,
+        summary: &QuerySummary,
+        rank_idx: usize,
+        parent: &str,
+        out: &mut dyn Write,
+        pct: &dyn Fn(u64) -> f64,
+    ) -> Result<()> {
+        let Some(sums) = summary.by_rank.get(rank_idx) else {
+            return Ok(());
+        };
+        let mut children: Vec<_> = sums
+            .iter()
+            .filter(|(lineage, _)| match lineage.rsplit_once(';') {
+                Some((p, _)) => p == parent,
+                None => parent.is_empty(),
+            })
+            .collect();
+        children.sort_by_key(|(_, s)| std::cmp::Reverse(s.weighted_bp));
+
+        let rank = self.lineages.ranks[rank_idx];
+        for (lineage, sum) in children {
+            let below: u64 = summary
+                .by_rank
+                .get(rank_idx + 1)
+                .map(|next| {
+                    next.iter()
+                        .filter(|(l, _)| l.rsplit_once(';').map(|(p, _)| p) == Some(lineage))
+                        .map(|(_, s)| s.weighted_bp)
+                        .sum()
+                })
+                .unwrap_or(0);
+            let name = lineage.rsplit(';').next().unwrap_or_default();
+            let taxid = sum.taxpath.rsplit('|').next().filter(|t| !t.is_empty());
+
+            writeln!(
+                out,
+                "{:6.2}\t{}\t{}\t{}\t{}\t{}{}",
+                pct(sum.weighted_bp),
+                sum.weighted_bp,
+                sum.weighted_bp.saturating_sub(below),
+                kraken_rank_code(rank),
+                taxid.unwrap_or("0"),
+                "  ".repeat(rank_idx + 1),
+                name
+            )?;
+            self.write_kraken_children(summary, rank_idx + 1, lineage, out, pct)?;
+        }
+        Ok(())
+    }
+
+    /// Write kraken reports to `path`; with more than one query, write one
+    /// report per query, with the query name inserted before the extension.
+    pub fn write_kraken_reports(&self, path: &str) -> Result<()> {
+        for (query_name, summary) in &self.queries {
+            let outpath = if self.queries.len() == 1 {
+                path.to_string()
+            } else {
+                kraken_path_for_query(path, query_name)
+            };
+            let mut out = open_stdout_or_file(Some(outpath.clone()));
+            self.write_kraken(summary, &mut out)?;
+            out.commit()?;
+            eprintln!("Wrote kraken-style report to '{}'", outpath);
+        }
+        Ok(())
+    }
+
+    /// Write all requested outputs.
+    pub fn write_outputs(&self) -> Result<()> {
+        if self.n_missing() > 0 {
+            eprintln!(
+                "WARNING: {} matches had no lineage in '{}'",
+                self.n_missing(),
+                self.options.lineages_path
+            );
+        }
+        if let Some(path) = &self.options.summary_output {
+            self.write_summary(Some(path.clone()))?;
+            eprintln!("Wrote taxonomic summary to '{}'", path);
+        }
+        if let Some(path) = &self.options.cami_output {
+            self.write_cami(Some(path.clone()))?;
+            eprintln!("Wrote CAMI profile to '{}'", path);
+        }
+        if let Some(path) = &self.options.kraken_output {
+            self.write_kraken_reports(path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Kraken rank codes; ranks below species are S1.
+fn kraken_rank_code(rank: &str) -> &'static str {
+    match rank {
+        "superkingdom" => "D",
+        "phylum" => "P",
+        "class" => "C",
+        "order" => "O",
+        "family" => "F",
+        "genus" => "G",
+        "species" => "S",
+        _ => "S1",
+    }
+}
+
+/// e.g. ("out.kreport", "SRR606249") -> "out.SRR606249.kreport"
+fn kraken_path_for_query(path: &str, query_name: &str) -> String {
+    let name = query_name.replace('/', "_");
+    match path.rsplit_once('.') {
+        Some((base, ext)) if !ext.contains('/') => format!("{}.{}.{}", base, name, ext),
+        _ => format!("{}.{}", path, name),
+    }
+}
+
+/// Like `csvwriter_thread`, but also rolls each gather result up by
+/// taxonomy as it is written, so that no second pass over the (possibly
+/// huge) output is needed. Returns the summarizer when the channel closes.
+pub fn gather_csvwriter_thread(
+    recv: Receiver<BranchwaterGatherResult>,
+    output: Option<String>,
+    columns: ColumnSelection,
+    mut summarizer: Option<TaxSummarizer>,
+    ctx: &RunContext,
+) -> Result<JoinHandle<Option<TaxSummarizer>>> {
+    let flush = ctx.flush_policy(output.as_deref());
+    let mut writer = ResultWriter::open(output, columns, ctx)?;
+    Ok(std::thread::spawn(move || {
+        for item in batch_rows(recv, flush) {
+            let res = match item {
+                Batched::Row(res) => res,
+                Batched::Flush => {
+                    writer.flush_or_warn();
+                    continue;
+                }
+            };
+            if let Some(s) = summarizer.as_mut() {
+                s.add(&res);
+            }
+            if let Err(e) = writer.write(&res) {
+                eprintln!("Error writing item: {:?}", e);
+            }
+        }
+        writer
+            .finish::<BranchwaterGatherResult>()
+            .expect("Failed to flush writer.");
+        summarizer
+    }))
+}
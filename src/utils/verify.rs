@@ -0,0 +1,98 @@
+//! md5 verification of loaded sketches, for `--verify`.
+//!
+//! Signature files store each sketch's md5, and sourmash trusts it on
+//! load, so a sketch whose hashes were damaged in copying still reports
+//! the md5 in its manifest. Verification recomputes the md5 from the
+//! hashes instead. Like the size filter, it is set in the command's
+//! `RunContext` and applied by `load_collection` to everything it loads; each sketch is read once more than usual, so it is opt-in.
+
+use anyhow::Result;
+use rayon::prelude::*;
+
+use sourmash::collection::Collection;
+use sourmash::manifest::Record;
+use sourmash::signature::{Signature, SigsTrait};
+use sourmash::sketch::minhash::KmerMinHash;
+use sourmash::sketch::Sketch;
+use sourmash::storage::Storage;
+
+use super::{MultiCollection, RunContext};
+
+/// The md5 of a sketch, computed from its hashes rather than taken from
+/// the signature file.
+fn recompute_md5(mh: &KmerMinHash) -> Result<String> {
+    // the md5 covers only ksize and hashes, so keep every hash.
+    let mut fresh = KmerMinHash::new(
+        1,
+        mh.ksize() as u32,
+        mh.hash_function(),
+        mh.seed(),
+        false,
+        0,
+    );
+    fresh.add_many(&mh.mins())?;
+    Ok(fresh.md5sum())
+}
+
+/// Check that the sketch stored for `record` has the md5 in the manifest,
+/// returning what's wrong if not.
+fn check_record(coll: &Collection, record: &Record) -> Option<String> {
+    let location = record.internal_location().as_str();
+    let sig = match coll.storage().load_sig(location) {
+        Ok(sig) => Signature::from(sig),
+        Err(e) => return Some(format!("could not load sketch: {}", e)),
+    };
+
+    let mut md5s = vec![];
+    for sketch in sig.sketches() {
+        let md5 = match sketch {
+            Sketch::MinHash(mh) => recompute_md5(&mh),
+            Sketch::LargeMinHash(mh) => recompute_md5(&KmerMinHash::from(mh)),
+            Sketch::HyperLogLog(_) => continue,
+        };
+        match md5 {
+            Ok(md5) if md5 == *record.md5() => return None,
+            Ok(md5) => md5s.push(md5),
+            Err(e) => return Some(format!("could not read hashes: {}", e)),
+        }
+    }
+    Some(format!(
+        "md5 mismatch: manifest has {}, sketch hashes give {}",
+        record.md5(),
+        md5s.join(";")
+    ))
+}
+
+/// If `ctx` verifies, load every sketch in `collection` and check
+/// its md5 against the manifest, failing if any differ.
+pub fn apply(collection: &MultiCollection, ctx: &RunContext) -> Result<()> {
+    if !ctx.verifies() {
+        return Ok(());
+    }
+
+    let mut problems: Vec<(String, String)> = collection
+        .par_iter()
+        .filter_map(|(coll, _idx, record)| {
+            check_record(coll, record)
+                .map(|problem| (record.internal_location().to_string(), problem))
+        })
+        .collect();
+    if problems.is_empty() {
+        eprintln!("Verified md5s of {} sketches.", collection.len());
+        return Ok(());
+    }
+
+    problems.sort();
+    for (location, problem) in problems.iter() {
+        eprintln!(
+            "ERROR: sketch '{}' failed verification: {}",
+            location, problem
+        );
+        ctx.record_failed(location, problem);
+    }
+    bail!(
+        "{} of {} sketches failed md5 verification; the collection may be corrupted.",
+        problems.len(),
+        collection.len()
+    );
+}
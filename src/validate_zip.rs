@@ -0,0 +1,198 @@
+/// validate_zip: check the manifest and members of a sourmash zip file.
+use anyhow::{anyhow, Result};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::io::{Cursor, Read};
+
+use sourmash::manifest::{Manifest, Record};
+use sourmash::signature::Signature;
+use sourmash::sketch::Sketch;
+use sourmash::storage::{Storage, ZipStorage};
+
+use crate::utils::{finish_csv, open_stdout_or_file};
+
+const MANIFEST_NAME: &str = "SOURMASH-MANIFEST.csv";
+
+#[derive(Serialize)]
+struct ZipProblem {
+    internal_location: String,
+    md5: String,
+    name: String,
+    problem: String,
+    detail: String,
+}
+
+impl ZipProblem {
+    fn new(internal_location: &str, problem: &str, detail: String) -> Self {
+        ZipProblem {
+            internal_location: internal_location.to_string(),
+            md5: "".to_string(),
+            name: "".to_string(),
+            problem: problem.to_string(),
+            detail,
+        }
+    }
+
+    fn for_record(record: &Record, problem: &str, detail: String) -> Self {
+        ZipProblem {
+            md5: record.md5().clone(),
+            name: record.name().clone(),
+            ..Self::new(record.internal_location().as_str(), problem, detail)
+        }
+    }
+}
+
+/// Check that the member for `record` can be read, decompressed, and
+/// parsed, and that it contains a sketch with the manifest md5.
+fn check_record(
+    storage: &ZipStorage,
+    members: &HashSet<String>,
+    record: &Record,
+) -> Option<ZipProblem> {
+    let location = record.internal_location().as_str();
+    if !members.contains(location) {
+        return Some(ZipProblem::for_record(
+            record,
+            "missing_member",
+            "manifest row has no member in the zip file".to_string(),
+        ));
+    }
+
+    let raw = match storage.load(location) {
+        Ok(raw) => raw,
+        Err(e) => {
+            return Some(ZipProblem::for_record(
+                record,
+                "unreadable_member",
+                e.to_string(),
+            ))
+        }
+    };
+
+    let mut data = vec![];
+    let decompressed = niffler::get_reader(Box::new(Cursor::new(raw)))
+        .map_err(|e| e.to_string())
+        .and_then(|(mut rdr, _)| rdr.read_to_end(&mut data).map_err(|e| e.to_string()));
+    if let Err(e) = decompressed {
+        return Some(ZipProblem::for_record(record, "bad_compression", e));
+    }
+
+    let sigs: Vec<Signature> = match serde_json::from_slice(&data) {
+        Ok(sigs) => sigs,
+        Err(e) => {
+            return Some(ZipProblem::for_record(
+                record,
+                "unreadable_signature",
+                e.to_string(),
+            ))
+        }
+    };
+
+    let md5s: Vec<String> = sigs
+        .iter()
+        .flat_map(|sig| sig.sketches())
+        .filter_map(|sketch| match sketch {
+            Sketch::MinHash(mh) => Some(mh.md5sum()),
+            Sketch::LargeMinHash(mh) => Some(mh.md5sum()),
+            _ => None,
+        })
+        .collect();
+    if !md5s.contains(record.md5()) {
+        return Some(ZipProblem::for_record(
+            record,
+            "md5_mismatch",
+            format!("found md5(s): {}", md5s.join(";")),
+        ));
+    }
+
+    None
+}
+
+/// Validate a zip file, writing any problems to `output` as CSV. Returns
+/// the number of problems found.
+pub fn validate_zip(zipfile: String, output: Option<String>) -> Result<usize> {
+    let storage = ZipStorage::from_file(&zipfile)
+        .map_err(|e| anyhow!("Failed to read zip file '{}': {}", zipfile, e))?;
+    let members: HashSet<String> = storage
+        .filenames()?
+        .into_iter()
+        .filter(|name| !name.ends_with('/'))
+        .collect();
+
+    let mut problems = vec![];
+    let manifest = if members.contains(MANIFEST_NAME) {
+        match storage
+            .load(MANIFEST_NAME)
+            .and_then(|buf| Manifest::from_reader(Cursor::new(buf)))
+        {
+            Ok(manifest) => Some(manifest),
+            Err(e) => {
+                problems.push(ZipProblem::new(
+                    MANIFEST_NAME,
+                    "bad_manifest",
+                    e.to_string(),
+                ));
+                None
+            }
+        }
+    } else {
+        problems.push(ZipProblem::new(
+            MANIFEST_NAME,
+            "missing_manifest",
+            "zip file has no manifest".to_string(),
+        ));
+        None
+    };
+
+    if let Some(manifest) = &manifest {
+        let records: Vec<&Record> = manifest.iter().collect();
+        eprintln!(
+            "Checking {} manifest rows and {} members in '{}'",
+            records.len(),
+            members.len(),
+            zipfile
+        );
+
+        let record_problems: Vec<ZipProblem> = records
+            .par_iter()
+            .filter_map(|record| check_record(&storage, &members, record))
+            .collect();
+        problems.extend(record_problems);
+
+        let referenced: HashSet<&str> = records
+            .iter()
+            .map(|r| r.internal_location().as_str())
+            .collect();
+        let mut orphans: Vec<&String> = members
+            .iter()
+            .filter(|m| *m != MANIFEST_NAME && !referenced.contains(m.as_str()))
+            .collect();
+        orphans.sort();
+        for member in orphans {
+            problems.push(ZipProblem::new(
+                member,
+                "orphan_member",
+                "member is not in the manifest".to_string(),
+            ));
+        }
+    }
+
+    let out = open_stdout_or_file(output);
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(out);
+    writer.write_record(["internal_location", "md5", "name", "problem", "detail"])?;
+    for problem in &problems {
+        writer.serialize(problem)?;
+    }
+    finish_csv(writer)?.commit()?;
+
+    if problems.is_empty() {
+        eprintln!("DONE. No problems found in '{}'", zipfile);
+    } else {
+        eprintln!("Found {} problems in '{}'", problems.len(), zipfile);
+    }
+
+    Ok(problems.len())
+}
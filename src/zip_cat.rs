@@ -0,0 +1,193 @@
+/// zip_cat: concatenate sourmash zip files into one, with a combined manifest.
+use anyhow::{anyhow, Result};
+use camino::Utf8Path as Path;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, Cursor, Write};
+
+use sourmash::storage::{Storage, ZipStorage};
+use zip::write::{FileOptions, ZipWriter};
+use zip::{CompressionMethod, ZipArchive};
+
+use crate::utils::atomicfile::AtomicFile;
+
+const MANIFEST_NAME: &str = "SOURMASH-MANIFEST.csv";
+
+/// Manifest columns, in the order sourmash writes them; any other columns
+/// (e.g. from `manysketch` params) follow, in the order first seen.
+const MANIFEST_COLUMNS: [&str; 11] = [
+    "internal_location",
+    "md5",
+    "md5short",
+    "ksize",
+    "moltype",
+    "num",
+    "scaled",
+    "n_hashes",
+    "with_abundance",
+    "name",
+    "filename",
+];
+
+/// A manifest row, keyed by column name.
+type ManifestRow = HashMap<String, String>;
+
+/// The manifest columns of one input zip, and its rows.
+fn read_manifest(zipfile: &str) -> Result<(Vec<String>, Vec<ManifestRow>)> {
+    let storage = ZipStorage::from_file(zipfile)
+        .map_err(|e| anyhow!("Failed to read zip file '{}': {}", zipfile, e))?;
+    let buf = storage.load(MANIFEST_NAME).map_err(|_| {
+        anyhow!(
+            "'{}' has no manifest; zip-cat needs the manifests of its inputs",
+            zipfile
+        )
+    })?;
+
+    let mut rdr = csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .from_reader(Cursor::new(buf));
+    let headers = rdr.headers()?.clone();
+    for column in ["internal_location", "md5"] {
+        if !headers.iter().any(|h| h == column) {
+            bail!("manifest in '{}' has no '{}' column", zipfile, column);
+        }
+    }
+
+    let mut rows = vec![];
+    for record in rdr.records() {
+        let record = record?;
+        rows.push(
+            headers
+                .iter()
+                .zip(record.iter())
+                .map(|(h, v)| (h.to_string(), v.to_string()))
+                .collect(),
+        );
+    }
+    Ok((headers.iter().map(String::from).collect(), rows))
+}
+
+/// `location`, or if that is already taken, `location` with `_2`, `_3`,
+/// etc. added before the extension, as `manysketch` names repeated md5s.
+fn unique_location(location: &str, used: &HashSet<String>) -> String {
+    if !used.contains(location) {
+        return location.to_string();
+    }
+    let basename_start = location.rfind('/').map_or(0, |i| i + 1);
+    let ext_start = location[basename_start..]
+        .find('.')
+        .map_or(location.len(), |i| basename_start + i);
+    let (stem, ext) = location.split_at(ext_start);
+    (2..)
+        .map(|n| format!("{}_{}{}", stem, n, ext))
+        .find(|candidate| !used.contains(candidate))
+        .unwrap()
+}
+
+/// Concatenate the sketches in `zipfiles` into a new zip file at `output`.
+/// Members are copied as-is, without decompressing or parsing the
+/// signatures, and a manifest is written for the result. Sketches whose
+/// md5 has already been copied from an earlier zip (or earlier in the same
+/// zip) are skipped; members are renamed if their internal location is
+/// already taken.
+pub fn zip_cat(zipfiles: Vec<String>, output: String) -> Result<()> {
+    if zipfiles.is_empty() {
+        bail!("No zip files given.");
+    }
+    if Path::new(&output).extension() != Some("zip") {
+        bail!("Output must be a zip file.");
+    }
+
+    let options = FileOptions::<()>::default()
+        .compression_method(CompressionMethod::Stored)
+        .unix_permissions(0o644)
+        .large_file(true);
+    let mut zip = ZipWriter::new(AtomicFile::create(&output)?);
+
+    let mut columns: Vec<String> = MANIFEST_COLUMNS.iter().map(|c| c.to_string()).collect();
+    let mut manifest_rows: Vec<ManifestRow> = vec![];
+    let mut seen_md5s: HashSet<String> = HashSet::new();
+    let mut used_locations: HashSet<String> = HashSet::new();
+    let mut n_duplicates = 0;
+
+    for zipfile in zipfiles.iter() {
+        let (headers, rows) = read_manifest(zipfile)?;
+        for column in headers {
+            if !columns.contains(&column) {
+                columns.push(column);
+            }
+        }
+
+        let mut archive = ZipArchive::new(BufReader::new(File::open(zipfile)?))
+            .map_err(|e| anyhow!("Failed to read zip file '{}': {}", zipfile, e))?;
+
+        // keep the rows for new sketches, grouped by the member they're in.
+        let mut members: Vec<(String, Vec<ManifestRow>)> = vec![];
+        let mut member_idx: HashMap<String, usize> = HashMap::new();
+        for row in rows {
+            if !seen_md5s.insert(row["md5"].clone()) {
+                n_duplicates += 1;
+                continue;
+            }
+            let location = row["internal_location"].clone();
+            let idx = *member_idx.entry(location.clone()).or_insert_with(|| {
+                members.push((location, vec![]));
+                members.len() - 1
+            });
+            members[idx].1.push(row);
+        }
+
+        let n_sketches: usize = members.iter().map(|(_, rows)| rows.len()).sum();
+        for (location, rows) in members {
+            let index = archive.index_for_name(&location).ok_or_else(|| {
+                anyhow!(
+                    "'{}' has no member '{}', but its manifest lists it",
+                    zipfile,
+                    location
+                )
+            })?;
+            let new_location = unique_location(&location, &used_locations);
+            zip.raw_copy_file_rename(archive.by_index_raw(index)?, new_location.as_str())?;
+
+            for mut row in rows {
+                row.insert("internal_location".to_string(), new_location.clone());
+                manifest_rows.push(row);
+            }
+            used_locations.insert(new_location);
+        }
+        eprintln!("Copied {} sketches from '{}'", n_sketches, zipfile);
+    }
+
+    let mut manifest = vec![];
+    manifest.write_all(b"# SOURMASH-MANIFEST-VERSION: 1.0\n")?;
+    {
+        let mut wtr = csv::Writer::from_writer(&mut manifest);
+        wtr.write_record(&columns)?;
+        for row in manifest_rows.iter() {
+            wtr.write_record(
+                columns
+                    .iter()
+                    .map(|c| row.get(c).map(String::as_str).unwrap_or("")),
+            )?;
+        }
+        wtr.flush()?;
+    }
+    zip.start_file(MANIFEST_NAME, options)?;
+    zip.write_all(&manifest)?;
+    zip.finish()?.commit()?;
+
+    eprintln!(
+        "DONE. Wrote {} sketches from {} zip files to '{}'",
+        manifest_rows.len(),
+        zipfiles.len(),
+        output
+    );
+    if n_duplicates > 0 {
+        eprintln!(
+            "Skipped {} sketches with the same md5 as a sketch already written.",
+            n_duplicates
+        );
+    }
+
+    Ok(())
+}